@@ -0,0 +1,56 @@
+//! Codec Benchmarks
+//!
+//! Throughput of encoding and decoding PUBLISH packets of a few payload
+//! sizes, for both protocol versions.
+
+use bytes::{Bytes, BytesMut};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use vibemq::codec::{Decoder, Encoder};
+use vibemq::protocol::{Packet, Properties, ProtocolVersion, Publish, QoS};
+
+fn publish(payload_size: usize) -> Packet {
+    Packet::Publish(Publish {
+        dup: false,
+        qos: QoS::AtLeastOnce,
+        retain: false,
+        topic: "sensors/building-1/floor-2/temperature".into(),
+        packet_id: Some(1),
+        payload: Bytes::from(vec![0u8; payload_size]),
+        properties: Properties::default(),
+    })
+}
+
+fn bench_codec(c: &mut Criterion) {
+    let mut group = c.benchmark_group("codec");
+
+    for version in [ProtocolVersion::V311, ProtocolVersion::V5] {
+        for payload_size in [16, 1024, 65536] {
+            let id = format!("{:?}/{}B", version, payload_size);
+            let packet = publish(payload_size);
+            let encoder = Encoder::new(version);
+            let mut encoded = BytesMut::new();
+            encoder.encode(&packet, &mut encoded).unwrap();
+            group.throughput(Throughput::Bytes(encoded.len() as u64));
+
+            group.bench_with_input(BenchmarkId::new("encode", &id), &packet, |b, packet| {
+                let mut buf = BytesMut::with_capacity(encoded.len());
+                b.iter(|| {
+                    buf.clear();
+                    encoder.encode(packet, &mut buf).unwrap();
+                    black_box(&buf);
+                });
+            });
+
+            group.bench_with_input(BenchmarkId::new("decode", &id), &encoded, |b, encoded| {
+                let mut decoder = Decoder::new();
+                decoder.set_protocol_version(version);
+                b.iter(|| black_box(decoder.decode(encoded).unwrap()));
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_codec);
+criterion_main!(benches);
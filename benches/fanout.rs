@@ -0,0 +1,82 @@
+//! Fan-out Benchmarks
+//!
+//! Compares encoding a PUBLISH for each subscriber with encoding it once
+//! and copying the shared bytes to each subscriber's write buffer, for a
+//! single subscriber up to ten thousand.
+
+use std::sync::Arc;
+
+use bytes::{Bytes, BytesMut};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use vibemq::codec::Encoder;
+use vibemq::protocol::{Packet, Properties, ProtocolVersion, Publish, QoS, SharedPublish};
+
+fn publish(qos: QoS, payload_size: usize) -> Publish {
+    Publish {
+        dup: false,
+        qos,
+        retain: false,
+        topic: "sensors/building-1/floor-2/temperature".into(),
+        packet_id: None,
+        payload: Bytes::from(vec![0u8; payload_size]),
+        properties: Properties::default(),
+    }
+}
+
+fn bench_fanout(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fanout");
+
+    for (subscribers, qos, payload_size) in [
+        (1, QoS::AtLeastOnce, 64),
+        (100, QoS::AtLeastOnce, 64),
+        (10_000, QoS::AtMostOnce, 64),
+        (10_000, QoS::AtLeastOnce, 64),
+        (10_000, QoS::AtLeastOnce, 4096),
+    ] {
+        let id = format!("{}/qos{}/{}B", subscribers, qos as u8, payload_size);
+        group.throughput(Throughput::Elements(subscribers as u64));
+        let message = publish(qos, payload_size);
+        let encoder = Encoder::new(ProtocolVersion::V5);
+
+        group.bench_with_input(
+            BenchmarkId::new("per_subscriber", &id),
+            &message,
+            |b, message| {
+                let mut buf = BytesMut::with_capacity(8192);
+                b.iter(|| {
+                    for i in 0..subscribers {
+                        let mut outgoing = message.clone();
+                        if qos != QoS::AtMostOnce {
+                            outgoing.packet_id = Some((i % 65535 + 1) as u16);
+                        }
+                        buf.clear();
+                        encoder
+                            .encode(&Packet::Publish(outgoing), &mut buf)
+                            .unwrap();
+                        black_box(&buf);
+                    }
+                });
+            },
+        );
+
+        group.bench_with_input(BenchmarkId::new("shared", &id), &message, |b, message| {
+            let mut buf = BytesMut::with_capacity(8192);
+            b.iter(|| {
+                let shared = Arc::new(SharedPublish::new(message.clone()));
+                for i in 0..subscribers {
+                    let packet_id = (qos != QoS::AtMostOnce).then_some((i % 65535 + 1) as u16);
+                    buf.clear();
+                    shared
+                        .encode(ProtocolVersion::V5, packet_id, &mut buf)
+                        .unwrap();
+                    black_box(&buf);
+                }
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_fanout);
+criterion_main!(benches);
@@ -0,0 +1,106 @@
+//! Session Store Benchmarks
+//!
+//! A connect storm: worker threads each connect and disconnect their own
+//! clients at once, all going through the store keyed by client ID.
+//! Compares the `DashMap` the store used to be built on with the client-ID
+//! sharded map, and measures the session store itself on top of it.
+
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use dashmap::DashMap;
+use vibemq::protocol::ProtocolVersion;
+use vibemq::session::{SessionLimits, SessionStore};
+use vibemq::sharded::ShardedMap;
+
+/// Clients each worker connects and disconnects per iteration
+const CLIENTS_PER_WORKER: usize = 10_000;
+
+/// Client IDs for each worker, created up front
+fn client_ids(workers: usize) -> Vec<Vec<Arc<str>>> {
+    (0..workers)
+        .map(|worker| {
+            (0..CLIENTS_PER_WORKER)
+                .map(|i| Arc::from(format!("client-{}-{}", worker, i)))
+                .collect()
+        })
+        .collect()
+}
+
+/// Run `connect` for each worker's clients on its own thread, all
+/// starting together
+fn storm<S, F>(store: &Arc<S>, ids: &[Vec<Arc<str>>], connect: F)
+where
+    S: Send + Sync + 'static,
+    F: Fn(&S, &Arc<str>) + Copy + Send + 'static,
+{
+    let barrier = Arc::new(Barrier::new(ids.len()));
+    let threads: Vec<_> = ids
+        .iter()
+        .cloned()
+        .map(|ids| {
+            let (store, barrier) = (store.clone(), barrier.clone());
+            thread::spawn(move || {
+                barrier.wait();
+                for client_id in &ids {
+                    connect(&store, client_id);
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
+
+fn bench_connect_storm(c: &mut Criterion) {
+    let mut group = c.benchmark_group("connect_storm");
+
+    for workers in [1, 4, 16] {
+        let ids = client_ids(workers);
+        group.throughput(Throughput::Elements((workers * CLIENTS_PER_WORKER) as u64));
+
+        group.bench_with_input(BenchmarkId::new("dashmap", workers), &ids, |b, ids| {
+            let map = Arc::new(DashMap::new());
+            b.iter(|| {
+                storm(&map, ids, |map: &DashMap<Arc<str>, u64>, client_id| {
+                    map.insert(client_id.clone(), 0);
+                    map.get(client_id.as_ref());
+                    map.remove(client_id.as_ref());
+                })
+            });
+        });
+
+        group.bench_with_input(BenchmarkId::new("sharded", workers), &ids, |b, ids| {
+            let map = Arc::new(ShardedMap::new(workers));
+            b.iter(|| {
+                storm(&map, ids, |map: &ShardedMap<u64>, client_id| {
+                    map.insert(client_id.clone(), 0);
+                    map.get(client_id);
+                    map.remove(client_id);
+                })
+            });
+        });
+
+        group.bench_with_input(BenchmarkId::new("sessions", workers), &ids, |b, ids| {
+            let sessions = Arc::new(SessionStore::with_workers(workers));
+            b.iter(|| {
+                storm(&sessions, ids, |sessions: &SessionStore, client_id| {
+                    sessions.get_or_create(
+                        client_id,
+                        ProtocolVersion::V5,
+                        true,
+                        SessionLimits::default(),
+                    );
+                    sessions.disconnect(client_id);
+                })
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_connect_storm);
+criterion_main!(benches);
@@ -0,0 +1,106 @@
+//! Topic Matching Benchmarks
+//!
+//! Matching topics against a subscription trie of exact and wildcard
+//! filters: a few hot topics the topic cache serves, and more distinct
+//! topics than it holds, which go through the trie.
+//!
+//! Also compares copying a published topic to each subscriber, event and
+//! retained message as a `String`, as `Publish` once held it, with sharing
+//! one `Topic`.
+
+use std::sync::Arc;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use vibemq::protocol::QoS;
+use vibemq::topic::{Subscription, SubscriptionStore, Topic};
+
+fn subscription(client: usize) -> Subscription {
+    Subscription {
+        client_id: Arc::from(format!("client-{}", client)),
+        qos: QoS::AtMostOnce,
+        no_local: false,
+        retain_as_published: false,
+        subscription_id: None,
+        share_group: None,
+    }
+}
+
+/// A store with one exact subscription per device and a few wildcard ones
+fn store(devices: usize) -> SubscriptionStore {
+    let store = SubscriptionStore::new();
+    for device in 0..devices {
+        store.subscribe(
+            &format!("site/{}/device/{}/telemetry", device % 100, device),
+            subscription(device),
+        );
+    }
+    store.subscribe("site/+/device/+/telemetry", subscription(devices));
+    store.subscribe("site/#", subscription(devices + 1));
+    store.subscribe("site/7/device/+/#", subscription(devices + 2));
+    store
+}
+
+fn bench_matches(c: &mut Criterion) {
+    const DEVICES: usize = 10_000;
+
+    let mut group = c.benchmark_group("topic_match");
+    let store = store(DEVICES);
+    let topics: Vec<String> = (0..DEVICES)
+        .map(|device| format!("site/{}/device/{}/telemetry", device % 100, device))
+        .collect();
+
+    for (name, distinct) in [("cached", 16), ("trie", DEVICES)] {
+        group.throughput(Throughput::Elements(distinct as u64));
+        group.bench_with_input(
+            BenchmarkId::new(name, distinct),
+            &topics[..distinct],
+            |b, topics| {
+                b.iter(|| {
+                    for topic in topics {
+                        black_box(store.matches(topic));
+                    }
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_topic_copies(c: &mut Criterion) {
+    let mut group = c.benchmark_group("topic_copies");
+    let topic = "sensors/building-1/floor-2/temperature";
+
+    // A published topic is copied into the queue or inflight state of each
+    // subscriber it fans out to, plus the publish event and retained store,
+    // and the copies live until the message is delivered
+    for subscribers in [1, 100, 10_000] {
+        let copies = subscribers + 2;
+        group.throughput(Throughput::Elements(copies as u64));
+
+        group.bench_with_input(BenchmarkId::new("string", copies), &copies, |b, &copies| {
+            let mut queued = Vec::with_capacity(copies);
+            b.iter(|| {
+                let decoded = topic.to_string();
+                queued.extend((0..copies).map(|_| decoded.clone()));
+                black_box(&queued);
+                queued.clear();
+            });
+        });
+
+        group.bench_with_input(BenchmarkId::new("shared", copies), &copies, |b, &copies| {
+            let mut queued = Vec::with_capacity(copies);
+            b.iter(|| {
+                let decoded = Topic::from(topic);
+                queued.extend((0..copies).map(|_| decoded.clone()));
+                black_box(&queued);
+                queued.clear();
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_matches, bench_topic_copies);
+criterion_main!(benches);
@@ -0,0 +1,28 @@
+-- Initial VibeMQ persistence schema.
+--
+-- Values are stored as bincode-encoded blobs (the same encoding used by the
+-- fjall backend) so both backends share the models in src/persistence/models.rs.
+
+CREATE TABLE IF NOT EXISTS vibemq_retained (
+    id         TEXT PRIMARY KEY,
+    data       BYTEA NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS vibemq_sessions (
+    id         TEXT PRIMARY KEY,
+    data       BYTEA NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS vibemq_users (
+    id         TEXT PRIMARY KEY,
+    data       BYTEA NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS vibemq_roles (
+    id         TEXT PRIMARY KEY,
+    data       BYTEA NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,10 @@
+-- Segments of offline session queues spilled out of broker memory.
+--
+-- Keys are opaque segment ids; rows are deleted once their messages are
+-- delivered, and the table is cleared at startup.
+
+CREATE TABLE IF NOT EXISTS vibemq_spills (
+    id         TEXT PRIMARY KEY,
+    data       BYTEA NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
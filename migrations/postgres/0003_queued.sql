@@ -0,0 +1,13 @@
+-- Messages appended to the queues of stored sessions.
+--
+-- Rows are folded into their session, in sequence order, when it is read and
+-- deleted when the session is next written, so queuing a message for an
+-- offline client does not rewrite its whole session.
+
+CREATE TABLE IF NOT EXISTS vibemq_queued (
+    seq        BIGSERIAL PRIMARY KEY,
+    client_id  TEXT NOT NULL,
+    data       BYTEA NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS vibemq_queued_client_id ON vibemq_queued (client_id, seq);
@@ -4,16 +4,27 @@
 //! - MQTT wildcards (# and +)
 //! - Variable substitution (%c = client_id, %u = username)
 //! - Role-based permissions
+//!
+//! Patterns starting with a wildcard do not grant `$SYS` topics; access to
+//! them has to be named explicitly, e.g. `$SYS/#`.
+//!
+//! Like authentication, the provider only ever denies: a permitted
+//! publish or subscription is left to later hooks. A `$SYS` subscription
+//! it permits is also reported through `grants_sys_subscribe`, as the broker
+//! refuses those unless a hook grants them.
 
 use std::collections::HashMap;
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use parking_lot::RwLock;
 
 use crate::auth::AuthProvider;
-use crate::config::AclConfig;
-use crate::hooks::{HookResult, Hooks};
-use crate::protocol::QoS;
+use crate::config::{AclConfig, QueueOverflowPolicy};
+use crate::hooks::{ClientContext, HookDecision, HookResult, Hooks, SubscribeDecision};
+use crate::persistence::StoredRole;
+use crate::protocol::{QoS, ReasonCode};
+use crate::topic::is_sys_topic;
 
 #[cfg(test)]
 mod tests;
@@ -22,8 +33,9 @@ mod tests;
 pub struct AclProvider {
     /// Whether ACL is enabled
     enabled: bool,
-    /// Role definitions (name -> role)
-    roles: HashMap<String, AclRoleEntry>,
+    /// Role definitions (name -> role), updated by replicated cluster
+    /// metadata
+    roles: RwLock<HashMap<String, Arc<AclRoleEntry>>>,
     /// Default permissions for users without explicit role (including anonymous)
     default_publish: Vec<String>,
     default_subscribe: Vec<String>,
@@ -37,6 +49,8 @@ struct AclRoleEntry {
     publish: Vec<String>,
     /// Subscribe patterns
     subscribe: Vec<String>,
+    /// Queue overflow policy override
+    queue_overflow: Option<QueueOverflowPolicy>,
 }
 
 impl AclProvider {
@@ -47,16 +61,17 @@ impl AclProvider {
         for role in &config.roles {
             roles.insert(
                 role.name.clone(),
-                AclRoleEntry {
+                Arc::new(AclRoleEntry {
                     publish: role.publish.clone(),
                     subscribe: role.subscribe.clone(),
-                },
+                    queue_overflow: role.queue_overflow,
+                }),
             );
         }
 
         Self {
             enabled: config.enabled,
-            roles,
+            roles: RwLock::new(roles),
             default_publish: config.default.publish.clone(),
             default_subscribe: config.default.subscribe.clone(),
             auth_provider,
@@ -68,6 +83,28 @@ impl AclProvider {
         self.enabled
     }
 
+    /// Add or replace a role's patterns, e.g. from replicated cluster
+    /// metadata; a configured queue overflow policy is kept
+    pub fn set_role(&self, role: &StoredRole) {
+        let mut roles = self.roles.write();
+        let queue_overflow = roles
+            .get(&role.name)
+            .and_then(|existing| existing.queue_overflow);
+        roles.insert(
+            role.name.clone(),
+            Arc::new(AclRoleEntry {
+                publish: role.publish.clone(),
+                subscribe: role.subscribe.clone(),
+                queue_overflow,
+            }),
+        );
+    }
+
+    /// Remove a role; its users fall back to the default permissions
+    pub fn remove_role(&self, name: &str) {
+        self.roles.write().remove(name);
+    }
+
     /// Check if topic matches pattern with variable substitution
     fn matches_pattern(
         pattern: &str,
@@ -85,6 +122,11 @@ impl AclProvider {
 
     /// MQTT pattern matching with wildcards
     fn mqtt_pattern_match(pattern: &str, topic: &str) -> bool {
+        // Broker internals are only granted by patterns naming them
+        if is_sys_topic(topic) && (pattern.starts_with('+') || pattern.starts_with('#')) {
+            return false;
+        }
+
         let pattern_parts: Vec<&str> = pattern.split('/').collect();
         let topic_parts: Vec<&str> = topic.split('/').collect();
 
@@ -129,10 +171,29 @@ impl AclProvider {
     }
 
     /// Get role permissions for a username
-    fn get_role_permissions(&self, username: Option<&str>) -> Option<&AclRoleEntry> {
+    fn get_role_permissions(&self, username: Option<&str>) -> Option<Arc<AclRoleEntry>> {
         let username = username?;
         let role_name = self.auth_provider.get_user_role(username)?;
-        self.roles.get(role_name)
+        self.roles.read().get(&role_name).cloned()
+    }
+
+    /// Check the client's role, or the defaults, for a subscribe pattern
+    /// matching the filter
+    fn permits_subscribe(&self, client: &ClientContext, filter: &str) -> bool {
+        // Try to get the actual username from auth provider
+        let client_id = &*client.client_id;
+        let actual_username = self.auth_provider.get_client_username(client_id);
+        let username_ref = actual_username.as_deref().or(client.username());
+
+        // Check role-based permissions first
+        if let Some(role) = self.get_role_permissions(username_ref) {
+            if Self::check_patterns(&role.subscribe, filter, client_id, username_ref) {
+                return true;
+            }
+        }
+
+        // Check default permissions (applies to all users without a role, including anonymous)
+        Self::check_patterns(&self.default_subscribe, filter, client_id, username_ref)
     }
 }
 
@@ -140,67 +201,69 @@ impl AclProvider {
 impl Hooks for AclProvider {
     async fn on_publish_check(
         &self,
-        client_id: &str,
-        username: Option<&str>,
+        client: &ClientContext,
         topic: &str,
         _qos: QoS,
         _retain: bool,
-    ) -> HookResult<bool> {
+    ) -> HookResult<HookDecision> {
         // If ACL is disabled, allow all
         if !self.enabled {
-            return Ok(true);
+            return Ok(HookDecision::Continue);
         }
 
         // Try to get the actual username from auth provider
+        let client_id = &*client.client_id;
         let actual_username = self.auth_provider.get_client_username(client_id);
-        let username_ref = actual_username.as_deref().or(username);
+        let username_ref = actual_username.as_deref().or(client.username());
 
         // Check role-based permissions first
         if let Some(role) = self.get_role_permissions(username_ref) {
             if Self::check_patterns(&role.publish, topic, client_id, username_ref) {
-                return Ok(true);
+                return Ok(HookDecision::Continue);
             }
         }
 
         // Check default permissions (applies to all users without a role, including anonymous)
         if Self::check_patterns(&self.default_publish, topic, client_id, username_ref) {
-            return Ok(true);
+            return Ok(HookDecision::Continue);
         }
 
         // Deny by default
-        Ok(false)
+        Ok(HookDecision::Deny)
     }
 
     async fn on_subscribe_check(
         &self,
-        client_id: &str,
-        username: Option<&str>,
+        client: &ClientContext,
         filter: &str,
         _qos: QoS,
-    ) -> HookResult<bool> {
+    ) -> HookResult<SubscribeDecision> {
         // If ACL is disabled, allow all
         if !self.enabled {
-            return Ok(true);
+            return Ok(SubscribeDecision::Continue);
         }
 
-        // Try to get the actual username from auth provider
-        let actual_username = self.auth_provider.get_client_username(client_id);
-        let username_ref = actual_username.as_deref().or(username);
-
-        // Check role-based permissions first
-        if let Some(role) = self.get_role_permissions(username_ref) {
-            if Self::check_patterns(&role.subscribe, filter, client_id, username_ref) {
-                return Ok(true);
-            }
+        if self.permits_subscribe(client, filter) {
+            Ok(SubscribeDecision::Continue)
+        } else {
+            // Deny by default
+            Ok(SubscribeDecision::Deny(ReasonCode::NotAuthorized))
         }
+    }
 
-        // Check default permissions (applies to all users without a role, including anonymous)
-        if Self::check_patterns(&self.default_subscribe, filter, client_id, username_ref) {
-            return Ok(true);
-        }
+    fn grants_sys_subscribe(&self, client: &ClientContext, filter: &str) -> bool {
+        // Only patterns naming $SYS match it, so any match is explicit
+        self.enabled && is_sys_topic(filter) && self.permits_subscribe(client, filter)
+    }
 
-        // Deny by default
-        Ok(false)
+    async fn queue_overflow_policy(&self, client: &ClientContext) -> Option<QueueOverflowPolicy> {
+        let actual_username = self.auth_provider.get_client_username(&client.client_id);
+        let username_ref = actual_username.as_deref().or(client.username());
+        self.get_role_permissions(username_ref)?.queue_overflow
+    }
+
+    fn observes_messages(&self) -> bool {
+        false
     }
 }
 
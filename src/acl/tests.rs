@@ -2,8 +2,13 @@
 
 use super::*;
 use crate::config::{AclConfig, AclPermissions, AclRole, AuthConfig, UserConfig};
+use crate::hooks::CompositeHooks;
 use std::sync::Arc;
 
+fn client(client_id: &str, username: Option<&str>) -> ClientContext {
+    ClientContext::new(client_id, "127.0.0.1:50000".parse().unwrap()).with_username(username)
+}
+
 fn make_test_auth_provider() -> Arc<AuthProvider> {
     let auth_config = AuthConfig {
         enabled: true,
@@ -40,16 +45,19 @@ fn make_test_acl_config() -> AclConfig {
                 name: "admin".to_string(),
                 publish: vec!["#".to_string()],
                 subscribe: vec!["#".to_string()],
+                queue_overflow: None,
             },
             AclRole {
                 name: "device".to_string(),
                 publish: vec!["sensors/%c/#".to_string()],
                 subscribe: vec!["commands/%c/#".to_string()],
+                queue_overflow: Some(QueueOverflowPolicy::DropNewest),
             },
             AclRole {
                 name: "reader".to_string(),
                 publish: vec![],
                 subscribe: vec!["sensors/#".to_string()],
+                queue_overflow: None,
             },
         ],
         default: AclPermissions {
@@ -71,15 +79,18 @@ async fn test_acl_disabled_allows_all() {
 
     let result = provider
         .on_publish_check(
-            "client1",
-            Some("anyone"),
+            &client("client1", Some("anyone")),
             "any/topic",
             QoS::AtMostOnce,
             false,
         )
         .await
         .unwrap();
-    assert!(result, "Should allow when ACL is disabled");
+    assert_eq!(
+        result,
+        HookDecision::Continue,
+        "Should allow when ACL is disabled"
+    );
 }
 
 #[tokio::test]
@@ -87,7 +98,7 @@ async fn test_admin_can_publish_anywhere() {
     let auth_provider = make_test_auth_provider();
     // Simulate authentication
     auth_provider
-        .on_authenticate("admin_client", Some("admin"), Some(b"admin_pass"))
+        .on_authenticate(&client("admin_client", Some("admin")), Some(b"admin_pass"))
         .await
         .unwrap();
 
@@ -96,15 +107,18 @@ async fn test_admin_can_publish_anywhere() {
 
     let result = provider
         .on_publish_check(
-            "admin_client",
-            Some("admin"),
+            &client("admin_client", Some("admin")),
             "any/topic/here",
             QoS::AtMostOnce,
             false,
         )
         .await
         .unwrap();
-    assert!(result, "Admin should be able to publish anywhere");
+    assert_eq!(
+        result,
+        HookDecision::Continue,
+        "Admin should be able to publish anywhere"
+    );
 }
 
 #[tokio::test]
@@ -112,7 +126,10 @@ async fn test_device_can_publish_to_own_topic() {
     let auth_provider = make_test_auth_provider();
     // Simulate authentication
     auth_provider
-        .on_authenticate("sensor_client", Some("sensor"), Some(b"sensor_pass"))
+        .on_authenticate(
+            &client("sensor_client", Some("sensor")),
+            Some(b"sensor_pass"),
+        )
         .await
         .unwrap();
 
@@ -122,28 +139,34 @@ async fn test_device_can_publish_to_own_topic() {
     // Device can publish to sensors/{client_id}/#
     let result = provider
         .on_publish_check(
-            "sensor_client",
-            Some("sensor"),
+            &client("sensor_client", Some("sensor")),
             "sensors/sensor_client/temperature",
             QoS::AtMostOnce,
             false,
         )
         .await
         .unwrap();
-    assert!(result, "Device should publish to its own topic");
+    assert_eq!(
+        result,
+        HookDecision::Continue,
+        "Device should publish to its own topic"
+    );
 
     // Device cannot publish to other topic
     let result = provider
         .on_publish_check(
-            "sensor_client",
-            Some("sensor"),
+            &client("sensor_client", Some("sensor")),
             "sensors/other_client/temperature",
             QoS::AtMostOnce,
             false,
         )
         .await
         .unwrap();
-    assert!(!result, "Device should NOT publish to other's topic");
+    assert_eq!(
+        result,
+        HookDecision::Deny,
+        "Device should NOT publish to other's topic"
+    );
 }
 
 #[tokio::test]
@@ -151,7 +174,10 @@ async fn test_readonly_cannot_publish() {
     let auth_provider = make_test_auth_provider();
     // Simulate authentication
     auth_provider
-        .on_authenticate("reader_client", Some("readonly"), Some(b"readonly_pass"))
+        .on_authenticate(
+            &client("reader_client", Some("readonly")),
+            Some(b"readonly_pass"),
+        )
         .await
         .unwrap();
 
@@ -160,15 +186,18 @@ async fn test_readonly_cannot_publish() {
 
     let result = provider
         .on_publish_check(
-            "reader_client",
-            Some("readonly"),
+            &client("reader_client", Some("readonly")),
             "sensors/temp",
             QoS::AtMostOnce,
             false,
         )
         .await
         .unwrap();
-    assert!(!result, "Readonly user should NOT be able to publish");
+    assert_eq!(
+        result,
+        HookDecision::Deny,
+        "Readonly user should NOT be able to publish"
+    );
 }
 
 #[tokio::test]
@@ -176,7 +205,10 @@ async fn test_readonly_can_subscribe_to_sensors() {
     let auth_provider = make_test_auth_provider();
     // Simulate authentication
     auth_provider
-        .on_authenticate("reader_client", Some("readonly"), Some(b"readonly_pass"))
+        .on_authenticate(
+            &client("reader_client", Some("readonly")),
+            Some(b"readonly_pass"),
+        )
         .await
         .unwrap();
 
@@ -185,26 +217,85 @@ async fn test_readonly_can_subscribe_to_sensors() {
 
     let result = provider
         .on_subscribe_check(
-            "reader_client",
-            Some("readonly"),
+            &client("reader_client", Some("readonly")),
             "sensors/temperature",
             QoS::AtMostOnce,
         )
         .await
         .unwrap();
-    assert!(result, "Readonly user should subscribe to sensors");
+    assert_eq!(
+        result,
+        SubscribeDecision::Continue,
+        "Readonly user should subscribe to sensors"
+    );
 
     // Cannot subscribe to commands
     let result = provider
         .on_subscribe_check(
-            "reader_client",
-            Some("readonly"),
+            &client("reader_client", Some("readonly")),
             "commands/device1",
             QoS::AtMostOnce,
         )
         .await
         .unwrap();
-    assert!(!result, "Readonly user should NOT subscribe to commands");
+    assert_eq!(
+        result,
+        SubscribeDecision::Deny(ReasonCode::NotAuthorized),
+        "Readonly user should NOT subscribe to commands"
+    );
+}
+
+#[tokio::test]
+async fn test_sys_subscribe_granted_explicitly() {
+    let auth_provider = make_test_auth_provider();
+    let acl_config = make_test_acl_config();
+    let provider = AclProvider::new(&acl_config, auth_provider);
+    let anon = client("anon", None);
+
+    // Permitted like any other filter, leaving the decision to later hooks
+    let result = provider
+        .on_subscribe_check(&anon, "$SYS/broker/uptime", QoS::AtMostOnce)
+        .await
+        .unwrap();
+    assert_eq!(result, SubscribeDecision::Continue);
+
+    // ...but reported as an explicit grant so the broker lets it through
+    assert!(provider.grants_sys_subscribe(&anon, "$SYS/broker/uptime"));
+    assert!(!provider.grants_sys_subscribe(&anon, "sensors/temp"));
+}
+
+/// Denies every subscription
+struct DenySubscribes;
+
+#[async_trait]
+impl Hooks for DenySubscribes {
+    async fn on_subscribe_check(
+        &self,
+        _client: &ClientContext,
+        _filter: &str,
+        _qos: QoS,
+    ) -> HookResult<SubscribeDecision> {
+        Ok(SubscribeDecision::Deny(ReasonCode::NotAuthorized))
+    }
+}
+
+#[tokio::test]
+async fn test_sys_subscribe_grant_keeps_later_hooks() {
+    let auth_provider = make_test_auth_provider();
+    let acl_config = make_test_acl_config();
+    let hooks = CompositeHooks::new()
+        .with_priority(AclProvider::new(&acl_config, auth_provider), 10)
+        .with(DenySubscribes);
+
+    let result = hooks
+        .on_subscribe_check(&client("anon", None), "$SYS/broker/uptime", QoS::AtMostOnce)
+        .await
+        .unwrap();
+    assert_eq!(
+        result,
+        SubscribeDecision::Deny(ReasonCode::NotAuthorized),
+        "A $SYS match in the ACL should not skip lower-priority hooks"
+    );
 }
 
 #[test]
@@ -220,6 +311,21 @@ fn test_pattern_matching() {
     // Multi level wildcard
     assert!(AclProvider::mqtt_pattern_match("foo/#", "foo/bar/baz"));
     assert!(AclProvider::mqtt_pattern_match("#", "any/topic/here"));
+
+    // $SYS is only granted by patterns naming it
+    assert!(!AclProvider::mqtt_pattern_match("#", "$SYS/broker/uptime"));
+    assert!(!AclProvider::mqtt_pattern_match(
+        "+/broker/+",
+        "$SYS/broker/uptime"
+    ));
+    assert!(AclProvider::mqtt_pattern_match(
+        "$SYS/#",
+        "$SYS/broker/uptime"
+    ));
+    assert!(AclProvider::mqtt_pattern_match(
+        "#",
+        "$share/group/sensors/#"
+    ));
 }
 
 #[test]
@@ -246,3 +352,63 @@ fn test_variable_substitution() {
         Some("admin")
     ));
 }
+
+#[tokio::test]
+async fn test_queue_overflow_policy_by_role() {
+    let provider = AclProvider::new(&make_test_acl_config(), make_test_auth_provider());
+
+    assert_eq!(
+        provider
+            .queue_overflow_policy(&client("dev1", Some("sensor")))
+            .await,
+        Some(QueueOverflowPolicy::DropNewest)
+    );
+    // Roles without an override and users without a role use the default
+    assert_eq!(
+        provider
+            .queue_overflow_policy(&client("a1", Some("admin")))
+            .await,
+        None
+    );
+    assert_eq!(
+        provider.queue_overflow_policy(&client("anon", None)).await,
+        None
+    );
+}
+
+#[tokio::test]
+async fn test_set_and_remove_role() {
+    let auth_provider = make_test_auth_provider();
+    auth_provider
+        .on_authenticate(
+            &client("sensor_client", Some("sensor")),
+            Some(b"sensor_pass"),
+        )
+        .await
+        .unwrap();
+    let provider = AclProvider::new(&make_test_acl_config(), auth_provider);
+    let sensor = client("sensor_client", Some("sensor"));
+
+    provider.set_role(&crate::persistence::StoredRole {
+        name: "device".to_string(),
+        publish: vec!["telemetry/%c".to_string()],
+        subscribe: vec![],
+    });
+    let result = provider
+        .on_publish_check(&sensor, "telemetry/sensor_client", QoS::AtMostOnce, false)
+        .await
+        .unwrap();
+    assert_eq!(result, HookDecision::Continue);
+    // The configured overflow policy survives the replacement
+    assert_eq!(
+        provider.queue_overflow_policy(&sensor).await,
+        Some(QueueOverflowPolicy::DropNewest)
+    );
+
+    provider.remove_role("device");
+    let result = provider
+        .on_publish_check(&sensor, "telemetry/sensor_client", QoS::AtMostOnce, false)
+        .await
+        .unwrap();
+    assert_eq!(result, HookDecision::Deny);
+}
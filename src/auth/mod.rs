@@ -3,6 +3,10 @@
 //! Provides username/password authentication with support for:
 //! - Plaintext passwords (for development/testing)
 //! - Argon2 password hashes (recommended for production)
+//!
+//! The provider only ever denies: a client that passes its check is left
+//! to later hooks, so a hook of higher priority can admit clients it
+//! does not know.
 
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -11,8 +15,10 @@ use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use async_trait::async_trait;
 use parking_lot::RwLock;
 
+use crate::broker::DisconnectReason;
 use crate::config::AuthConfig;
-use crate::hooks::{HookResult, Hooks};
+use crate::hooks::{ClientContext, HookDecision, HookResult, Hooks};
+use crate::persistence::StoredUser;
 
 #[cfg(test)]
 mod tests;
@@ -23,13 +29,15 @@ pub struct AuthProvider {
     enabled: bool,
     /// Allow anonymous connections
     allow_anonymous: bool,
-    /// User credentials map (username -> UserEntry)
-    users: HashMap<String, UserEntry>,
+    /// User credentials map (username -> UserEntry), updated by
+    /// replicated cluster metadata
+    users: RwLock<HashMap<String, UserEntry>>,
     /// Connected client usernames (for ACL lookups)
     client_usernames: Arc<RwLock<HashMap<String, Option<String>>>>,
 }
 
 /// Credential storage type
+#[derive(Clone)]
 enum Credential {
     /// Plaintext password (for development/testing)
     Plaintext(String),
@@ -72,7 +80,7 @@ impl AuthProvider {
         Self {
             enabled: config.enabled,
             allow_anonymous: config.allow_anonymous,
-            users,
+            users: RwLock::new(users),
             client_usernames: Arc::new(RwLock::new(HashMap::new())),
         }
     }
@@ -83,8 +91,24 @@ impl AuthProvider {
     }
 
     /// Get the ACL role for a username
-    pub fn get_user_role(&self, username: &str) -> Option<&str> {
-        self.users.get(username).and_then(|u| u.role.as_deref())
+    pub fn get_user_role(&self, username: &str) -> Option<String> {
+        self.users.read().get(username).and_then(|u| u.role.clone())
+    }
+
+    /// Add or replace a user, e.g. from replicated cluster metadata
+    pub fn set_user(&self, user: &StoredUser) {
+        self.users.write().insert(
+            user.username.clone(),
+            UserEntry {
+                credential: Credential::Argon2Hash(user.password_hash.clone()),
+                role: user.role.clone(),
+            },
+        );
+    }
+
+    /// Remove a user; connected clients keep their session
+    pub fn remove_user(&self, username: &str) {
+        self.users.write().remove(username);
     }
 
     /// Get the username for a connected client
@@ -134,23 +158,25 @@ impl AuthProvider {
 impl Hooks for AuthProvider {
     async fn on_authenticate(
         &self,
-        client_id: &str,
-        username: Option<&str>,
+        client: &ClientContext,
         password: Option<&[u8]>,
-    ) -> HookResult<bool> {
+    ) -> HookResult<HookDecision> {
+        let client_id = &client.client_id;
+        let username = client.username();
+
         // If auth is disabled, allow all
         if !self.enabled {
             self.store_client_username(client_id, username);
-            return Ok(true);
+            return Ok(HookDecision::Continue);
         }
 
         // Check for anonymous connection
         if username.is_none() {
             if self.allow_anonymous {
                 self.store_client_username(client_id, None);
-                return Ok(true);
+                return Ok(HookDecision::Continue);
             } else {
-                return Ok(false);
+                return Ok(HookDecision::Deny);
             }
         }
 
@@ -158,21 +184,28 @@ impl Hooks for AuthProvider {
         let password = password.unwrap_or(&[]);
 
         // Look up user
-        let user = match self.users.get(username) {
-            Some(u) => u,
-            None => return Ok(false),
+        let credential = match self.users.read().get(username) {
+            Some(u) => u.credential.clone(),
+            None => return Ok(HookDecision::Deny),
         };
 
         // Verify password
-        if self.verify_password(password, &user.credential) {
+        if self.verify_password(password, &credential) {
             self.store_client_username(client_id, Some(username));
-            Ok(true)
+            Ok(HookDecision::Continue)
         } else {
-            Ok(false)
+            Ok(HookDecision::Deny)
+        }
+    }
+
+    async fn on_client_disconnected(&self, client: &ClientContext, reason: DisconnectReason) {
+        // On takeover the username now belongs to the new connection
+        if reason != DisconnectReason::Takeover {
+            self.remove_client_username(&client.client_id);
         }
     }
 
-    async fn on_client_disconnected(&self, client_id: &str, _graceful: bool) {
-        self.remove_client_username(client_id);
+    fn observes_messages(&self) -> bool {
+        false
     }
 }
@@ -3,6 +3,10 @@
 use super::*;
 use crate::config::{AuthConfig, UserConfig};
 
+fn client(client_id: &str, username: Option<&str>) -> ClientContext {
+    ClientContext::new(client_id, "127.0.0.1:50000".parse().unwrap()).with_username(username)
+}
+
 fn make_auth_config(enabled: bool, allow_anonymous: bool, users: Vec<UserConfig>) -> AuthConfig {
     AuthConfig {
         enabled,
@@ -35,10 +39,14 @@ async fn test_auth_disabled_allows_all() {
     let provider = AuthProvider::new(&config);
 
     let result = provider
-        .on_authenticate("client1", Some("user"), Some(b"pass"))
+        .on_authenticate(&client("client1", Some("user")), Some(b"pass"))
         .await
         .unwrap();
-    assert!(result, "Should allow when auth is disabled");
+    assert_eq!(
+        result,
+        HookDecision::Continue,
+        "Should allow when auth is disabled"
+    );
 }
 
 #[tokio::test]
@@ -51,10 +59,10 @@ async fn test_auth_enabled_rejects_unknown_user() {
     let provider = AuthProvider::new(&config);
 
     let result = provider
-        .on_authenticate("client1", Some("unknown"), Some(b"pass"))
+        .on_authenticate(&client("client1", Some("unknown")), Some(b"pass"))
         .await
         .unwrap();
-    assert!(!result, "Should reject unknown user");
+    assert_eq!(result, HookDecision::Deny, "Should reject unknown user");
 }
 
 #[tokio::test]
@@ -67,10 +75,10 @@ async fn test_auth_enabled_rejects_wrong_password() {
     let provider = AuthProvider::new(&config);
 
     let result = provider
-        .on_authenticate("client1", Some("admin"), Some(b"wrong"))
+        .on_authenticate(&client("client1", Some("admin")), Some(b"wrong"))
         .await
         .unwrap();
-    assert!(!result, "Should reject wrong password");
+    assert_eq!(result, HookDecision::Deny, "Should reject wrong password");
 }
 
 #[tokio::test]
@@ -83,10 +91,14 @@ async fn test_auth_enabled_accepts_valid_credentials() {
     let provider = AuthProvider::new(&config);
 
     let result = provider
-        .on_authenticate("client1", Some("admin"), Some(b"secret"))
+        .on_authenticate(&client("client1", Some("admin")), Some(b"secret"))
         .await
         .unwrap();
-    assert!(result, "Should accept valid credentials");
+    assert_eq!(
+        result,
+        HookDecision::Continue,
+        "Should accept valid credentials"
+    );
 }
 
 #[tokio::test]
@@ -95,10 +107,14 @@ async fn test_anonymous_allowed() {
     let provider = AuthProvider::new(&config);
 
     let result = provider
-        .on_authenticate("client1", None, None)
+        .on_authenticate(&client("client1", None), None)
         .await
         .unwrap();
-    assert!(result, "Should allow anonymous when configured");
+    assert_eq!(
+        result,
+        HookDecision::Continue,
+        "Should allow anonymous when configured"
+    );
 }
 
 #[tokio::test]
@@ -107,10 +123,14 @@ async fn test_anonymous_rejected() {
     let provider = AuthProvider::new(&config);
 
     let result = provider
-        .on_authenticate("client1", None, None)
+        .on_authenticate(&client("client1", None), None)
         .await
         .unwrap();
-    assert!(!result, "Should reject anonymous when not allowed");
+    assert_eq!(
+        result,
+        HookDecision::Deny,
+        "Should reject anonymous when not allowed"
+    );
 }
 
 #[tokio::test]
@@ -124,7 +144,7 @@ async fn test_client_username_tracking() {
 
     // Authenticate
     let _ = provider
-        .on_authenticate("client1", Some("admin"), Some(b"secret"))
+        .on_authenticate(&client("client1", Some("admin")), Some(b"secret"))
         .await
         .unwrap();
 
@@ -134,8 +154,19 @@ async fn test_client_username_tracking() {
         Some("admin".to_string())
     );
 
+    // A takeover leaves the username to the new connection
+    provider
+        .on_client_disconnected(&client("client1", None), DisconnectReason::Takeover)
+        .await;
+    assert_eq!(
+        provider.get_client_username("client1"),
+        Some("admin".to_string())
+    );
+
     // Disconnect
-    provider.on_client_disconnected("client1", true).await;
+    provider
+        .on_client_disconnected(&client("client1", None), DisconnectReason::Normal)
+        .await;
 
     // Check username is removed
     assert_eq!(provider.get_client_username("client1"), None);
@@ -150,7 +181,10 @@ fn test_get_user_role() {
     );
     let provider = AuthProvider::new(&config);
 
-    assert_eq!(provider.get_user_role("admin"), Some("admin_role"));
+    assert_eq!(
+        provider.get_user_role("admin").as_deref(),
+        Some("admin_role")
+    );
     assert_eq!(provider.get_user_role("unknown"), None);
 }
 
@@ -167,10 +201,14 @@ async fn test_password_hash_accepts_valid() {
     let provider = AuthProvider::new(&config);
 
     let result = provider
-        .on_authenticate("client1", Some("admin"), Some(b"secret"))
+        .on_authenticate(&client("client1", Some("admin")), Some(b"secret"))
         .await
         .unwrap();
-    assert!(result, "Should accept valid password against hash");
+    assert_eq!(
+        result,
+        HookDecision::Continue,
+        "Should accept valid password against hash"
+    );
 }
 
 #[tokio::test]
@@ -183,10 +221,14 @@ async fn test_password_hash_rejects_wrong_password() {
     let provider = AuthProvider::new(&config);
 
     let result = provider
-        .on_authenticate("client1", Some("admin"), Some(b"wrong"))
+        .on_authenticate(&client("client1", Some("admin")), Some(b"wrong"))
         .await
         .unwrap();
-    assert!(!result, "Should reject wrong password against hash");
+    assert_eq!(
+        result,
+        HookDecision::Deny,
+        "Should reject wrong password against hash"
+    );
 }
 
 #[tokio::test]
@@ -203,15 +245,51 @@ async fn test_mixed_auth_methods() {
 
     // Plaintext user should work
     let result = provider
-        .on_authenticate("client1", Some("plain_user"), Some(b"plainpass"))
+        .on_authenticate(&client("client1", Some("plain_user")), Some(b"plainpass"))
         .await
         .unwrap();
-    assert!(result, "Plaintext user should authenticate");
+    assert_eq!(
+        result,
+        HookDecision::Continue,
+        "Plaintext user should authenticate"
+    );
 
     // Hashed user should work
     let result = provider
-        .on_authenticate("client2", Some("hash_user"), Some(b"secret"))
+        .on_authenticate(&client("client2", Some("hash_user")), Some(b"secret"))
+        .await
+        .unwrap();
+    assert_eq!(
+        result,
+        HookDecision::Continue,
+        "Hashed user should authenticate"
+    );
+}
+
+#[tokio::test]
+async fn test_set_and_remove_user() {
+    let provider = AuthProvider::new(&make_auth_config(true, false, vec![]));
+    let user = crate::persistence::StoredUser {
+        username: "replicated".to_string(),
+        password_hash: TEST_ARGON2_HASH.to_string(),
+        role: Some("device".to_string()),
+    };
+
+    provider.set_user(&user);
+    let result = provider
+        .on_authenticate(&client("client1", Some("replicated")), Some(b"secret"))
+        .await
+        .unwrap();
+    assert_eq!(result, HookDecision::Continue);
+    assert_eq!(
+        provider.get_user_role("replicated").as_deref(),
+        Some("device")
+    );
+
+    provider.remove_user("replicated");
+    let result = provider
+        .on_authenticate(&client("client2", Some("replicated")), Some(b"secret"))
         .await
         .unwrap();
-    assert!(result, "Hashed user should authenticate");
+    assert_eq!(result, HookDecision::Deny);
 }
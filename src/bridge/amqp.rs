@@ -0,0 +1,306 @@
+//! AMQP Bridge
+//!
+//! Runs a bridge with `protocol = "amqp"` against an AMQP 0-9-1 broker such
+//! as RabbitMQ. Queued outbound messages are published to the topic
+//! exchange `amqp.exchange` with the remote topic as routing key (`a/b/c`
+//! is `a.b.c`, `+` is `*`). Inbound rules bind one exclusive queue to the
+//! exchange with their remote filters. Publishes use publisher confirms, so
+//! a batch counts as forwarded once the broker has acknowledged it.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures_util::StreamExt;
+use lapin::options::{
+    BasicAckOptions, BasicConsumeOptions, BasicPublishOptions, ConfirmSelectOptions,
+    ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions,
+};
+use lapin::publisher_confirm::Confirmation;
+use lapin::tcp::OwnedTLSConfig;
+use lapin::types::FieldTable;
+use lapin::uri::{AMQPAuthority, AMQPScheme, AMQPUri, AMQPUserInfo};
+use lapin::{BasicProperties, Channel, Connection, ConnectionProperties, ExchangeKind};
+use parking_lot::RwLock;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tracing::{debug, info};
+
+use crate::config::BridgeConfig;
+use crate::protocol::{Properties, QoS};
+use crate::remote::{RemoteError, RemotePeerStatus};
+
+use super::client::{BridgeCommand, InboundCallback};
+use super::queue::BridgeQueue;
+use super::stats::BridgeStats;
+use super::topic_mapper::{from_dotted, to_dotted, TopicMapper};
+
+/// Maximum messages published before waiting for confirms
+const PUBLISH_BATCH: usize = 256;
+
+/// Persistent delivery mode for QoS 1/2 messages
+const DELIVERY_MODE_PERSISTENT: u8 = 2;
+
+/// Connect to the AMQP broker and run until shutdown or an error
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn connect_and_run(
+    config: &BridgeConfig,
+    address: &str,
+    topic_mapper: &TopicMapper,
+    status: &Arc<RwLock<RemotePeerStatus>>,
+    queue: &BridgeQueue,
+    stats: &Arc<BridgeStats>,
+    command_rx: &mut mpsc::Receiver<BridgeCommand>,
+    inbound_callback: &Option<InboundCallback>,
+) -> Result<(), RemoteError> {
+    let uri = amqp_uri(config, address);
+    let tls = OwnedTLSConfig {
+        cert_chain: match config.tls.as_ref().and_then(|t| t.ca_cert.as_ref()) {
+            Some(path) => Some(std::fs::read_to_string(path).map_err(|e| {
+                RemoteError::InvalidConfig(format!("Failed to read CA certificate: {}", e))
+            })?),
+            None => None,
+        },
+        ..Default::default()
+    };
+    let properties = ConnectionProperties::default()
+        .with_connection_name(config.client_id.clone().into())
+        .with_executor(tokio_executor_trait::Tokio::current())
+        .with_reactor(tokio_reactor_trait::Tokio);
+
+    let connection = timeout(
+        config.connect_timeout,
+        Connection::connect_uri_with_config(uri, properties, tls),
+    )
+    .await
+    .map_err(|_| RemoteError::Timeout)?
+    .map_err(amqp_error)?;
+
+    let channel = connection.create_channel().await.map_err(amqp_error)?;
+    channel
+        .confirm_select(ConfirmSelectOptions::default())
+        .await
+        .map_err(amqp_error)?;
+
+    let exchange = config.amqp.exchange.as_str();
+    // Predefined exchanges cannot be declared
+    if !exchange.starts_with("amq.") {
+        channel
+            .exchange_declare(
+                exchange,
+                ExchangeKind::Topic,
+                ExchangeDeclareOptions {
+                    durable: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .map_err(amqp_error)?;
+    }
+
+    // Without inbound rules the consumer stream stays empty
+    let mut consumer = futures_util::stream::select_all(Vec::new());
+    if inbound_callback.is_some() && !topic_mapper.inbound_filters().is_empty() {
+        let inbound = channel
+            .queue_declare(
+                "",
+                QueueDeclareOptions {
+                    exclusive: true,
+                    auto_delete: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .map_err(amqp_error)?;
+        for (filter, _) in topic_mapper.inbound_filters() {
+            let routing_key = to_dotted(filter, "#");
+            channel
+                .queue_bind(
+                    inbound.name().as_str(),
+                    exchange,
+                    &routing_key,
+                    QueueBindOptions::default(),
+                    FieldTable::default(),
+                )
+                .await
+                .map_err(amqp_error)?;
+            debug!(
+                "Bridge '{}': Bound {} on {}",
+                config.name, routing_key, exchange
+            );
+        }
+        consumer.push(
+            channel
+                .basic_consume(
+                    inbound.name().as_str(),
+                    &config.client_id,
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await
+                .map_err(amqp_error)?,
+        );
+    }
+
+    info!("Bridge '{}': Connected to AMQP at {}", config.name, address);
+    *status.write() = RemotePeerStatus::Connected;
+    stats.connected();
+
+    publish_queued(&channel, exchange, queue, stats).await?;
+
+    loop {
+        tokio::select! {
+            Some(cmd) = command_rx.recv() => {
+                if let BridgeCommand::Shutdown = cmd {
+                    let _ = connection.close(200, "bridge shutdown").await;
+                    return Ok(());
+                }
+            }
+
+            _ = queue.notified() => {
+                publish_queued(&channel, exchange, queue, stats).await?;
+            }
+
+            Some(delivery) = consumer.next() => {
+                let delivery = delivery.map_err(amqp_error)?;
+                let topic = from_dotted(delivery.routing_key.as_str());
+                // AMQP deliveries carry no QoS; the rule's QoS applies
+                let mapped = topic_mapper.map_inbound(&topic, QoS::ExactlyOnce, false);
+                if let (Some((local_topic, qos, retain)), Some(callback)) =
+                    (mapped, inbound_callback)
+                {
+                    callback(
+                        local_topic,
+                        Bytes::from(delivery.data.clone()),
+                        qos,
+                        retain,
+                        None,
+                        Properties::default(),
+                    );
+                    stats.forwarded_in();
+                }
+                delivery
+                    .ack(BasicAckOptions::default())
+                    .await
+                    .map_err(amqp_error)?;
+            }
+        }
+    }
+}
+
+/// Publish everything queued, waiting for the broker to confirm each batch
+///
+/// A batch that fails or is nacked is queued again as a whole.
+async fn publish_queued(
+    channel: &Channel,
+    exchange: &str,
+    queue: &BridgeQueue,
+    stats: &BridgeStats,
+) -> Result<(), RemoteError> {
+    loop {
+        let batch = queue.pop_batch(PUBLISH_BATCH);
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let mut result = Ok(());
+        let mut confirms = Vec::with_capacity(batch.len());
+        for publish in &batch {
+            let mut properties = BasicProperties::default();
+            if publish.qos != QoS::AtMostOnce {
+                properties = properties.with_delivery_mode(DELIVERY_MODE_PERSISTENT);
+            }
+            match channel
+                .basic_publish(
+                    exchange,
+                    &to_dotted(&publish.topic, "#"),
+                    BasicPublishOptions::default(),
+                    &publish.payload,
+                    properties,
+                )
+                .await
+            {
+                Ok(confirm) => confirms.push(confirm),
+                Err(e) => {
+                    result = Err(amqp_error(e));
+                    break;
+                }
+            }
+        }
+        if result.is_ok() {
+            for confirm in confirms {
+                match confirm.await {
+                    Ok(Confirmation::Nack(_)) => {
+                        result = Err(RemoteError::Other("AMQP: publish nacked".to_string()));
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        result = Err(amqp_error(e));
+                        break;
+                    }
+                }
+            }
+        }
+
+        if let Err(e) = result {
+            queue.requeue(batch);
+            return Err(e);
+        }
+        queue.ack(&batch);
+        stats.forwarded_out(batch.len());
+    }
+}
+
+/// Connection URI for one of the bridge's addresses
+fn amqp_uri(config: &BridgeConfig, address: &str) -> AMQPUri {
+    let (host, port) = config.split_address(address);
+    let mut uri = AMQPUri {
+        scheme: if config.tls.is_some() {
+            AMQPScheme::AMQPS
+        } else {
+            AMQPScheme::AMQP
+        },
+        authority: AMQPAuthority {
+            host,
+            port,
+            ..Default::default()
+        },
+        vhost: config.amqp.vhost.clone(),
+        ..Default::default()
+    };
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        uri.authority.userinfo = AMQPUserInfo {
+            username: username.clone(),
+            password: password.clone(),
+        };
+    }
+    uri
+}
+
+fn amqp_error(e: impl std::fmt::Display) -> RemoteError {
+    RemoteError::ConnectionLost(format!("AMQP: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_amqp_uri() {
+        let config = BridgeConfig {
+            protocol: crate::config::BridgeProtocol::Amqp,
+            username: Some("edge".to_string()),
+            password: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let uri = amqp_uri(&config, "rabbit.example.com:5673");
+        assert_eq!(uri.authority.host, "rabbit.example.com");
+        assert_eq!(uri.authority.port, 5673);
+        assert_eq!(uri.authority.userinfo.username, "edge");
+        assert_eq!(uri.vhost, "/");
+        assert_eq!(uri.scheme, AMQPScheme::AMQP);
+        assert_eq!(amqp_uri(&config, "rabbit.example.com").authority.port, 5672);
+    }
+}
@@ -0,0 +1,89 @@
+//! Bridge Reconnect Backoff
+//!
+//! Exponential backoff between reconnect attempts. Each delay is spread by
+//! a random jitter so bridges that lose the same remote broker do not all
+//! reconnect at the same instant.
+
+use std::time::Duration;
+
+use crate::broker::rand_id;
+use crate::config::BridgeConfig;
+
+/// Reconnect delay schedule of one bridge
+#[derive(Debug, Clone)]
+pub(crate) struct Backoff {
+    initial: Duration,
+    max: Duration,
+    multiplier: f64,
+    jitter: f64,
+    /// Delay before jitter for the next attempt
+    current: Duration,
+}
+
+impl Backoff {
+    pub(crate) fn new(config: &BridgeConfig) -> Self {
+        let initial = config.reconnect_interval.min(config.max_reconnect_interval);
+        Self {
+            initial,
+            max: config.max_reconnect_interval,
+            multiplier: config.backoff_multiplier,
+            jitter: config.reconnect_jitter,
+            current: initial,
+        }
+    }
+
+    /// Delay before the next attempt; later attempts wait longer
+    pub(crate) fn next_delay(&mut self) -> Duration {
+        let base = self.current;
+        self.current = Duration::try_from_secs_f64(base.as_secs_f64() * self.multiplier)
+            .map_or(self.max, |next| next.min(self.max));
+
+        if self.jitter <= 0.0 {
+            return base;
+        }
+        // Uniform in [-jitter, jitter]
+        let unit = rand_id() as f64 / u64::MAX as f64;
+        let spread = (unit * 2.0 - 1.0) * self.jitter;
+        Duration::try_from_secs_f64(base.as_secs_f64() * (1.0 + spread)).unwrap_or(base)
+    }
+
+    /// Start over from the initial interval after a successful connection
+    pub(crate) fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(multiplier: f64, jitter: f64) -> BridgeConfig {
+        BridgeConfig {
+            reconnect_interval: Duration::from_secs(1),
+            max_reconnect_interval: Duration::from_secs(10),
+            backoff_multiplier: multiplier,
+            reconnect_jitter: jitter,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_exponential_backoff_capped_and_reset() {
+        let mut backoff = Backoff::new(&config(3.0, 0.0));
+        let delays: Vec<u64> = (0..4).map(|_| backoff.next_delay().as_secs()).collect();
+        assert_eq!(delays, vec![1, 3, 9, 10]);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_jitter_stays_within_spread() {
+        let mut backoff = Backoff::new(&config(1.0, 0.5));
+        for _ in 0..100 {
+            let delay = backoff.next_delay();
+            assert!(delay >= Duration::from_millis(500), "{:?}", delay);
+            assert!(delay <= Duration::from_millis(1500), "{:?}", delay);
+        }
+    }
+}
@@ -3,39 +3,76 @@
 //! Implements a client that connects to a remote MQTT broker and forwards
 //! messages according to configured rules.
 
-use std::sync::atomic::{AtomicU16, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
 use parking_lot::RwLock;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
 use tokio::time::timeout;
 use tracing::{debug, error, info, warn};
 
 use crate::codec::{Decoder, Encoder};
+use crate::persistence::PersistenceManager;
 use crate::protocol::{
-    Connect, Disconnect, Packet, Properties, ProtocolVersion, Publish, QoS, ReasonCode, Subscribe,
-    Subscription, SubscriptionOptions,
+    Connect, Disconnect, Packet, Properties, ProtocolVersion, PubAck, PubComp, PubRec, PubRel,
+    Publish, QoS, ReasonCode, RetainHandling, Subscribe, Subscription, SubscriptionOptions,
 };
 use crate::remote::{RemoteError, RemotePeer, RemotePeerStatus};
+use crate::transport::WsStream;
 
+use super::backoff::Backoff;
+use super::origin::{is_origin_property, BridgeOrigin};
+use super::queue::{BridgeQueue, QueuedPublish};
+use super::session::{BridgeSession, Resend};
+use super::stats::{BridgeStats, BridgeStatus};
+use super::subscriptions::RemoteSubscriptions;
+use super::tls;
 use super::topic_mapper::TopicMapper;
-use crate::config::BridgeConfig;
+use super::transform::{BridgeMessage, BridgeTransform};
+use crate::config::{BridgeConfig, BridgeProtocol, BridgeTlsConfig};
+
+/// Byte stream to the remote broker: TCP, optionally wrapped in TLS and
+/// WebSocket framing
+pub(super) trait BridgeStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> BridgeStream for T {}
+
+/// Open a TCP connection to `host`, with TLS if `tls_config` is set
+pub(super) async fn connect_stream(
+    host: &str,
+    port: u16,
+    tls_config: Option<&BridgeTlsConfig>,
+    connect_timeout: Duration,
+) -> Result<Box<dyn BridgeStream>, RemoteError> {
+    let stream = timeout(
+        connect_timeout,
+        TcpStream::connect(format!("{}:{}", host, port)),
+    )
+    .await
+    .map_err(|_| RemoteError::Timeout)?
+    .map_err(|e| RemoteError::ConnectionLost(e.to_string()))?;
+
+    let Some(tls_config) = tls_config else {
+        return Ok(Box::new(stream));
+    };
+    let (connector, server_name) = tls::connector(tls_config, host)?;
+    let stream = timeout(connect_timeout, connector.connect(server_name, stream))
+        .await
+        .map_err(|_| RemoteError::Timeout)?
+        .map_err(|e| RemoteError::ConnectionLost(format!("TLS handshake failed: {}", e)))?;
+    Ok(Box::new(stream))
+}
+
+/// Maximum publishes written to the remote broker in one go
+const SEND_BATCH: usize = 64;
 
 /// Message to send to the bridge client task
 #[derive(Debug)]
-enum BridgeCommand {
-    /// Publish a message to the remote broker
-    Publish {
-        topic: String,
-        payload: Bytes,
-        qos: QoS,
-        retain: bool,
-    },
+pub(super) enum BridgeCommand {
     /// Subscribe to a topic on the remote broker
     Subscribe { filter: String, qos: QoS },
     /// Unsubscribe from a topic on the remote broker
@@ -44,8 +81,11 @@ enum BridgeCommand {
     Shutdown,
 }
 
-/// Callback for messages received from the remote broker
-pub type InboundCallback = Arc<dyn Fn(String, Bytes, QoS, bool) + Send + Sync>;
+/// Callback for messages received from the remote broker, with the bridges
+/// they crossed when user property loop prevention is on and the Response
+/// Topic and Correlation Data they carried
+pub type InboundCallback =
+    Arc<dyn Fn(String, Bytes, QoS, bool, Option<BridgeOrigin>, Properties) + Send + Sync>;
 
 /// MQTT Bridge Client
 ///
@@ -60,42 +100,158 @@ pub struct BridgeClient {
     status: Arc<RwLock<RemotePeerStatus>>,
     /// Command channel for sending operations to the connection task
     command_tx: Option<mpsc::Sender<BridgeCommand>>,
+    /// Publishes waiting to be sent, kept while the remote broker is unreachable
+    queue: Arc<BridgeQueue>,
+    /// Health counters updated by the connection task
+    stats: Arc<BridgeStats>,
     /// Callback for inbound messages
     inbound_callback: Option<InboundCallback>,
-    /// Next packet ID (for future QoS 1/2 tracking)
-    #[allow(dead_code)]
-    next_packet_id: AtomicU16,
+    /// Rewrites outbound messages before they are queued
+    transform: RwLock<Option<Arc<dyn BridgeTransform>>>,
 }
 
 impl BridgeClient {
     /// Create a new bridge client
-    pub fn new(config: BridgeConfig) -> Self {
+    ///
+    /// With its spool enabled, the bridge spools to `persistence`.
+    pub fn new(config: BridgeConfig, persistence: Option<Arc<PersistenceManager>>) -> Self {
         let topic_mapper = TopicMapper::new(&config.forwards);
 
+        let mut queue = BridgeQueue::new(config.queue_size);
+        if config.spool.enabled {
+            match persistence {
+                Some(persistence) => {
+                    queue = queue.with_spool(persistence, &config.name, config.spool.max_messages)
+                }
+                None => error!(
+                    "Bridge '{}': Spool needs persistence enabled, buffering in memory only",
+                    config.name
+                ),
+            }
+        }
+        let queue = queue.with_rate_limit(&config.rate_limit);
+
         Self {
             config,
             topic_mapper,
             status: Arc::new(RwLock::new(RemotePeerStatus::Disconnected)),
             command_tx: None,
+            queue: Arc::new(queue),
+            stats: Arc::new(BridgeStats::default()),
             inbound_callback: None,
-            next_packet_id: AtomicU16::new(1),
+            transform: RwLock::new(None),
         }
     }
 
-    /// Set the callback for inbound messages from the remote broker
-    pub fn set_inbound_callback(&mut self, callback: InboundCallback) {
-        self.inbound_callback = Some(callback);
+    /// Connection health, counters and queue depth
+    pub fn health(&self) -> BridgeStatus {
+        self.stats.snapshot(
+            &self.config.name,
+            self.status(),
+            self.queue.depth(),
+            self.queue.dropped(),
+        )
     }
 
-    /// Get the next packet ID (for future QoS 1/2 tracking)
-    #[allow(dead_code)]
-    fn next_packet_id(&self) -> u16 {
-        let id = self.next_packet_id.fetch_add(1, Ordering::SeqCst);
-        if id == 0 {
-            self.next_packet_id.fetch_add(1, Ordering::SeqCst)
+    /// Write messages spooled since the spool task last ran to the backend
+    pub async fn flush_spool(&self) {
+        self.queue.write_spool().await;
+    }
+
+    /// Forward a published message that may already have crossed bridges
+    ///
+    /// With user property loop prevention, messages that crossed this
+    /// bridge before or made `max_hops` hops are not forwarded. The user
+    /// properties, Response Topic and Correlation Data of `properties` go
+    /// with the message; the origin chain is rewritten for the next hop.
+    pub fn forward_publish_from(
+        &self,
+        topic: &str,
+        payload: Bytes,
+        qos: QoS,
+        retain: bool,
+        origin: Option<&BridgeOrigin>,
+        properties: &Properties,
+    ) -> Result<(), RemoteError> {
+        // Map the topic and check if we should forward
+        let (remote_topic, effective_qos, effective_retain) =
+            match self.topic_mapper.map_outbound(topic, qos, retain) {
+                Some(mapping) => mapping,
+                None => return Ok(()), // Topic doesn't match any rules
+            };
+
+        // Kafka records are keyed by MQTT topic; the Kafka topic is mapped when sending
+        let topic = if self.config.protocol == BridgeProtocol::Kafka {
+            topic.to_string()
+        } else {
+            remote_topic
+        };
+
+        // Messages that would loop or exceed max_hops stop here
+        let origin = if self.config.use_origin_property() {
+            match BridgeOrigin::next_hop(origin, self.config.get_origin_id(), self.config.max_hops)
+            {
+                Some(origin) => Some(origin),
+                None => return Ok(()),
+            }
         } else {
-            id
+            None
+        };
+
+        let mut message = BridgeMessage {
+            topic,
+            payload,
+            qos: effective_qos,
+            retain: effective_retain,
+            user_properties: properties
+                .user_properties
+                .iter()
+                .filter(|(key, _)| !is_origin_property(key))
+                .cloned()
+                .collect(),
+            response_topic: properties.response_topic.clone(),
+            correlation_data: properties.correlation_data.clone(),
+        };
+        let transform = self.transform.read().clone();
+        if let Some(transform) = transform {
+            match transform.transform(&self.config.name, message) {
+                Some(transformed) => message = transformed,
+                None => {
+                    debug!(
+                        "Bridge '{}': Message dropped by transform",
+                        self.config.name
+                    );
+                    return Ok(());
+                }
+            }
+        }
+
+        // Queue for the connection task; kept until the remote broker is reachable
+        if !self.queue.push(QueuedPublish {
+            topic: message.topic,
+            payload: message.payload,
+            qos: message.qos,
+            retain: message.retain,
+            origin,
+            user_properties: message.user_properties,
+            response_topic: message.response_topic,
+            correlation_data: message.correlation_data,
+            segment: None,
+        }) {
+            return Err(RemoteError::QueueFull);
         }
+
+        Ok(())
+    }
+
+    /// Set the hook that rewrites or drops outbound messages
+    pub fn set_transform(&self, transform: Arc<dyn BridgeTransform>) {
+        *self.transform.write() = Some(transform);
+    }
+
+    /// Set the callback for inbound messages from the remote broker
+    pub fn set_inbound_callback(&mut self, callback: InboundCallback) {
+        self.inbound_callback = Some(callback);
     }
 
     /// Run the connection loop
@@ -103,42 +259,131 @@ impl BridgeClient {
         config: BridgeConfig,
         topic_mapper: TopicMapper,
         status: Arc<RwLock<RemotePeerStatus>>,
+        queue: Arc<BridgeQueue>,
+        stats: Arc<BridgeStats>,
         mut command_rx: mpsc::Receiver<BridgeCommand>,
         inbound_callback: Option<InboundCallback>,
     ) {
-        let mut retry_interval = config.reconnect_interval;
-        let max_retry = config.max_reconnect_interval;
+        let mut backoff = Backoff::new(&config);
+        let addresses: Vec<String> = config.addresses().map(String::from).collect();
+        let mut attempt = 0usize;
+        // Outlives each connection so QoS 1/2 flows resume with the remote session
+        let mut session = BridgeSession::default();
+        // Kept across connections so rejected filters keep their backoff
+        let mut subscriptions = RemoteSubscriptions::new(&config, topic_mapper.inbound_filters());
+        stats.subscriptions(subscriptions.status());
 
         loop {
+            // Move on to the next address after each failure
+            let address = &addresses[attempt % addresses.len()];
             *status.write() = RemotePeerStatus::Connecting;
-            debug!("Bridge '{}': Connecting to {}", config.name, config.address);
-
-            match Self::connect_and_run(
-                &config,
-                &topic_mapper,
-                &status,
-                &mut command_rx,
-                &inbound_callback,
-            )
-            .await
-            {
+            stats.connecting(address);
+            debug!("Bridge '{}': Connecting to {}", config.name, address);
+
+            let result = match config.protocol {
+                #[cfg(feature = "kafka")]
+                BridgeProtocol::Kafka => {
+                    super::kafka::connect_and_run(
+                        &config,
+                        address,
+                        &topic_mapper,
+                        &status,
+                        &queue,
+                        &stats,
+                        &mut command_rx,
+                        &inbound_callback,
+                    )
+                    .await
+                }
+                #[cfg(feature = "nats")]
+                BridgeProtocol::Nats => {
+                    super::nats::connect_and_run(
+                        &config,
+                        address,
+                        &topic_mapper,
+                        &status,
+                        &queue,
+                        &stats,
+                        &mut command_rx,
+                        &inbound_callback,
+                    )
+                    .await
+                }
+                #[cfg(feature = "amqp")]
+                BridgeProtocol::Amqp => {
+                    super::amqp::connect_and_run(
+                        &config,
+                        address,
+                        &topic_mapper,
+                        &status,
+                        &queue,
+                        &stats,
+                        &mut command_rx,
+                        &inbound_callback,
+                    )
+                    .await
+                }
+                BridgeProtocol::Http | BridgeProtocol::Https => {
+                    super::http::connect_and_run(
+                        &config,
+                        address,
+                        &status,
+                        &queue,
+                        &stats,
+                        &mut command_rx,
+                    )
+                    .await
+                }
+                // Rejected by config validation when the feature is missing
+                #[allow(unreachable_patterns)]
+                BridgeProtocol::Kafka | BridgeProtocol::Nats | BridgeProtocol::Amqp => {
+                    Err(RemoteError::InvalidConfig(format!(
+                        "built without the {} feature",
+                        config.protocol
+                    )))
+                }
+                _ => {
+                    Self::connect_and_run(
+                        &config,
+                        address,
+                        &topic_mapper,
+                        &status,
+                        &queue,
+                        &stats,
+                        &mut session,
+                        &mut subscriptions,
+                        &mut command_rx,
+                        &inbound_callback,
+                    )
+                    .await
+                }
+            };
+
+            match result {
                 Ok(()) => {
                     info!("Bridge '{}': Disconnected gracefully", config.name);
                     *status.write() = RemotePeerStatus::Disconnected;
+                    stats.disconnected();
                     return; // Clean shutdown
                 }
                 Err(e) => {
-                    error!("Bridge '{}': Connection failed: {}", config.name, e);
-                    *status.write() = RemotePeerStatus::Backoff;
-
-                    debug!(
-                        "Bridge '{}': Reconnecting in {:?}",
-                        config.name, retry_interval
+                    error!(
+                        "Bridge '{}': Connection to {} failed: {}",
+                        config.name, address, e
                     );
+                    // A dropped session retries the preferred address first
+                    let was_connected = *status.read() == RemotePeerStatus::Connected;
+                    attempt = if was_connected { 0 } else { attempt + 1 };
+                    *status.write() = RemotePeerStatus::Backoff;
+                    stats.failed(&e.to_string());
 
-                    // Exponential backoff
-                    tokio::time::sleep(retry_interval).await;
-                    retry_interval = std::cmp::min(retry_interval * 2, max_retry);
+                    // A connection that was up starts the backoff over
+                    if was_connected {
+                        backoff.reset();
+                    }
+                    let delay = backoff.next_delay();
+                    debug!("Bridge '{}': Reconnecting in {:?}", config.name, delay);
+                    tokio::time::sleep(delay).await;
                 }
             }
 
@@ -154,33 +399,74 @@ impl BridgeClient {
         }
     }
 
+    /// Open a TCP connection to the remote host, with TLS if the protocol
+    /// uses it
+    pub(super) async fn open_stream(
+        config: &BridgeConfig,
+        host: &str,
+        port: u16,
+    ) -> Result<Box<dyn BridgeStream>, RemoteError> {
+        let tls_config = config
+            .protocol
+            .uses_tls()
+            .then(|| config.tls.clone().unwrap_or_default());
+        let stream =
+            connect_stream(host, port, tls_config.as_ref(), config.connect_timeout).await?;
+        debug!(
+            "Bridge '{}': {} connected",
+            config.name,
+            if tls_config.is_some() { "TLS" } else { "TCP" }
+        );
+        Ok(stream)
+    }
+
     /// Connect to the remote broker and run the message loop
+    #[allow(clippy::too_many_arguments)]
     async fn connect_and_run(
         config: &BridgeConfig,
+        address: &str,
         topic_mapper: &TopicMapper,
         status: &Arc<RwLock<RemotePeerStatus>>,
+        queue: &BridgeQueue,
+        stats: &BridgeStats,
+        session: &mut BridgeSession,
+        subscriptions: &mut RemoteSubscriptions,
         command_rx: &mut mpsc::Receiver<BridgeCommand>,
         inbound_callback: &Option<InboundCallback>,
     ) -> Result<(), RemoteError> {
-        let (host, port) = config.parse_address();
-
-        // Connect with timeout
-        let stream = timeout(
-            config.connect_timeout,
-            TcpStream::connect(format!("{}:{}", host, port)),
-        )
-        .await
-        .map_err(|_| RemoteError::Timeout)?
-        .map_err(|e| RemoteError::ConnectionLost(e.to_string()))?;
+        let (host, port) = config.split_address(address);
+        let stream = Self::open_stream(config, &host, port).await?;
 
-        debug!("Bridge '{}': TCP connected", config.name);
+        let stream: Box<dyn BridgeStream> = if config.protocol.uses_websocket() {
+            let url = format!(
+                "{}://{}:{}{}",
+                if config.protocol.uses_tls() {
+                    "wss"
+                } else {
+                    "ws"
+                },
+                host,
+                port,
+                config.ws_path
+            );
+            let stream = timeout(config.connect_timeout, WsStream::connect(stream, &url))
+                .await
+                .map_err(|_| RemoteError::Timeout)?
+                .map_err(|e| {
+                    RemoteError::ConnectionLost(format!("WebSocket handshake failed: {}", e))
+                })?;
+            debug!("Bridge '{}': WebSocket established ({})", config.name, url);
+            Box::new(stream)
+        } else {
+            stream
+        };
 
         // Set up encoder/decoder
         let encoder = Encoder::new(ProtocolVersion::V5);
         let mut decoder = Decoder::new();
         decoder.set_protocol_version(ProtocolVersion::V5);
 
-        let (mut read_half, mut write_half) = stream.into_split();
+        let (mut read_half, mut write_half) = tokio::io::split(stream);
 
         // Send CONNECT packet
         let connect = Packet::Connect(Box::new(Connect {
@@ -205,140 +491,120 @@ impl BridgeClient {
 
         debug!("Bridge '{}': CONNECT sent", config.name);
 
-        // Wait for CONNACK
+        // Wait for CONNACK; packets can span reads, and a read can hold several
         let mut read_buf = vec![0u8; 4096];
-        let n = timeout(config.connect_timeout, read_half.read(&mut read_buf))
-            .await
-            .map_err(|_| RemoteError::Timeout)?
-            .map_err(|e| RemoteError::ConnectionLost(e.to_string()))?;
-
-        if n == 0 {
-            return Err(RemoteError::ConnectionLost("Connection closed".to_string()));
-        }
+        let mut pending = BytesMut::new();
+        let connack = loop {
+            let n = timeout(config.connect_timeout, read_half.read(&mut read_buf))
+                .await
+                .map_err(|_| RemoteError::Timeout)?
+                .map_err(|e| RemoteError::ConnectionLost(e.to_string()))?;
 
-        let (packet, _) = decoder
-            .decode(&read_buf[..n])
-            .map_err(|e| RemoteError::Other(format!("Decode error: {}", e)))?
-            .ok_or_else(|| RemoteError::Other("Incomplete packet".to_string()))?;
+            if n == 0 {
+                return Err(RemoteError::ConnectionLost("Connection closed".to_string()));
+            }
+            pending.extend_from_slice(&read_buf[..n]);
 
-        match packet {
-            Packet::ConnAck(connack) => {
-                if connack.reason_code != ReasonCode::Success {
-                    return Err(RemoteError::Rejected(format!(
-                        "CONNACK failed: {:?}",
-                        connack.reason_code
-                    )));
+            if let Some((packet, len)) = decoder
+                .decode(&pending)
+                .map_err(|e| RemoteError::Other(format!("Decode error: {}", e)))?
+            {
+                let _ = pending.split_to(len);
+                match packet {
+                    Packet::ConnAck(connack) => break connack,
+                    _ => return Err(RemoteError::Other("Expected CONNACK".to_string())),
                 }
-                info!(
-                    "Bridge '{}': Connected (session_present={})",
-                    config.name, connack.session_present
-                );
-            }
-            _ => {
-                return Err(RemoteError::Other("Expected CONNACK".to_string()));
             }
+        };
+
+        if connack.reason_code != ReasonCode::Success {
+            return Err(RemoteError::Rejected(format!(
+                "CONNACK failed: {:?}",
+                connack.reason_code
+            )));
         }
+        info!(
+            "Bridge '{}': Connected (session_present={})",
+            config.name, connack.session_present
+        );
 
         *status.write() = RemotePeerStatus::Connected;
+        stats.connected();
 
-        // Subscribe to inbound topics with loop prevention
-        let use_no_local = config.use_no_local();
-        let inbound_filters = topic_mapper.inbound_filters();
-        if !inbound_filters.is_empty() {
-            let subscriptions: Vec<Subscription> = inbound_filters
-                .iter()
-                .map(|(filter, qos)| Subscription {
-                    filter: filter.to_string(),
-                    options: SubscriptionOptions {
-                        qos: *qos,
-                        no_local: use_no_local, // Prevents receiving our own messages
-                        ..Default::default()
-                    },
-                })
-                .collect();
-
-            let subscribe = Packet::Subscribe(Subscribe {
-                packet_id: 1,
-                subscriptions,
-                properties: Properties::default(),
-            });
-
-            buf.clear();
-            encoder
-                .encode(&subscribe, &mut buf)
-                .map_err(|e| RemoteError::Other(format!("Encode error: {}", e)))?;
-            write_half
-                .write_all(&buf)
-                .await
-                .map_err(|e| RemoteError::ConnectionLost(e.to_string()))?;
-
+        // Resume unfinished QoS 1/2 flows, or queue them again on a new session
+        let requeued =
+            session.connected(connack.session_present, connack.properties.receive_maximum);
+        if !requeued.is_empty() {
             debug!(
-                "Bridge '{}': Subscribed to {} inbound topics",
+                "Bridge '{}': Requeueing {} unacknowledged publishes",
                 config.name,
-                inbound_filters.len()
+                requeued.len()
             );
+            queue.requeue(requeued);
         }
+        Self::resend(session, &encoder, &mut write_half).await?;
+
+        // Subscribe to inbound topics; without a session the remote broker
+        // has none of them
+        let filters = subscriptions.connected();
+        Self::subscribe(
+            config,
+            session,
+            subscriptions,
+            &encoder,
+            &mut write_half,
+            filters,
+            RetainHandling::SendAtSubscribe,
+        )
+        .await?;
+        stats.subscriptions(subscriptions.status());
+
+        // Replay what was queued while disconnected
+        Self::send_queued(queue, session, stats, &encoder, &mut write_half).await?;
 
         // Message loop
         let keepalive_interval = Duration::from_secs(config.keepalive as u64);
         let mut keepalive_timer = tokio::time::interval(keepalive_interval);
         keepalive_timer.reset();
 
+        // Renew all subscriptions in case the remote broker lost them
+        let audit_enabled = !config.subscription_audit_interval.is_zero();
+        let mut audit_timer = tokio::time::interval(
+            config
+                .subscription_audit_interval
+                .max(Duration::from_secs(1)),
+        );
+        audit_timer.reset();
+
         loop {
+            let next_retry = subscriptions.next_retry();
             tokio::select! {
                 // Handle commands from the broker
                 Some(cmd) = command_rx.recv() => {
                     match cmd {
-                        BridgeCommand::Publish { topic, payload, qos, retain } => {
-                            let packet_id = if qos != QoS::AtMostOnce {
-                                Some(1) // Simplified - real impl would track packet IDs
-                            } else {
-                                None
-                            };
-
-                            let publish = Packet::Publish(Publish {
-                                dup: false,
-                                qos,
-                                retain,
-                                topic,
-                                packet_id,
-                                payload,
-                                properties: Properties::default(),
-                            });
-
-                            buf.clear();
-                            if encoder.encode(&publish, &mut buf).is_ok() {
-                                if let Err(e) = write_half.write_all(&buf).await {
-                                    return Err(RemoteError::ConnectionLost(e.to_string()));
-                                }
-                            }
-                        }
                         BridgeCommand::Subscribe { filter, qos } => {
-                            let subscribe = Packet::Subscribe(Subscribe {
-                                packet_id: 2,
-                                subscriptions: vec![Subscription {
-                                    filter,
-                                    options: SubscriptionOptions { qos, ..Default::default() },
-                                }],
-                                properties: Properties::default(),
-                            });
-
-                            buf.clear();
-                            if encoder.encode(&subscribe, &mut buf).is_ok() {
-                                let _ = write_half.write_all(&buf).await;
-                            }
+                            subscriptions.add(&filter, qos);
+                            Self::subscribe(
+                                config,
+                                session,
+                                subscriptions,
+                                &encoder,
+                                &mut write_half,
+                                vec![(filter, qos)],
+                                RetainHandling::SendAtSubscribe,
+                            )
+                            .await?;
+                            stats.subscriptions(subscriptions.status());
                         }
                         BridgeCommand::Unsubscribe { filter } => {
+                            subscriptions.remove(&filter);
+                            stats.subscriptions(subscriptions.status());
                             let unsubscribe = Packet::Unsubscribe(crate::protocol::Unsubscribe {
-                                packet_id: 3,
+                                packet_id: session.next_packet_id(),
                                 filters: vec![filter],
                                 properties: Properties::default(),
                             });
-
-                            buf.clear();
-                            if encoder.encode(&unsubscribe, &mut buf).is_ok() {
-                                let _ = write_half.write_all(&buf).await;
-                            }
+                            Self::write_packet(&encoder, &mut write_half, &unsubscribe).await?;
                         }
                         BridgeCommand::Shutdown => {
                             // Send DISCONNECT
@@ -346,88 +612,363 @@ impl BridgeClient {
                                 reason_code: ReasonCode::Success,
                                 properties: Properties::default(),
                             });
-
-                            buf.clear();
-                            if encoder.encode(&disconnect, &mut buf).is_ok() {
-                                let _ = write_half.write_all(&buf).await;
-                            }
+                            let _ = Self::write_packet(&encoder, &mut write_half, &disconnect).await;
                             return Ok(());
                         }
                     }
                 }
 
+                // Send queued publishes while the remote broker accepts more
+                _ = queue.notified(), if session.room() > 0 => {
+                    Self::send_queued(queue, session, stats, &encoder, &mut write_half).await?;
+                }
+
                 // Handle incoming packets from remote broker
                 result = read_half.read(&mut read_buf) => {
                     let n = result.map_err(|e| RemoteError::ConnectionLost(e.to_string()))?;
                     if n == 0 {
                         return Err(RemoteError::ConnectionLost("Connection closed".to_string()));
                     }
+                    pending.extend_from_slice(&read_buf[..n]);
 
-                    if let Ok(Some((packet, _))) = decoder.decode(&read_buf[..n]) {
-                        match packet {
-                            Packet::Publish(publish) => {
-                                // Forward to local broker via callback
-                                if let Some(ref callback) = inbound_callback {
-                                    if let Some((local_topic, qos, retain)) = topic_mapper.map_inbound(
-                                        &publish.topic,
-                                        publish.qos,
-                                        publish.retain,
-                                    ) {
-                                        debug!(
-                                            "Bridge '{}': Forwarding {} -> {}",
-                                            config.name, publish.topic, local_topic
-                                        );
-                                        callback(local_topic, publish.payload, qos, retain);
-                                    }
-                                }
-
-                                // Send PUBACK for QoS 1
-                                if publish.qos == QoS::AtLeastOnce {
-                                    if let Some(packet_id) = publish.packet_id {
-                                        let puback = Packet::PubAck(crate::protocol::PubAck {
-                                            packet_id,
-                                            reason_code: ReasonCode::Success,
-                                            properties: Properties::default(),
-                                        });
-                                        buf.clear();
-                                        if encoder.encode(&puback, &mut buf).is_ok() {
-                                            let _ = write_half.write_all(&buf).await;
-                                        }
-                                    }
-                                }
-                            }
-                            Packet::PingResp => {
-                                debug!("Bridge '{}': PINGRESP received", config.name);
-                            }
-                            Packet::SubAck(_) => {
-                                debug!("Bridge '{}': SUBACK received", config.name);
-                            }
-                            Packet::PubAck(_) => {
-                                debug!("Bridge '{}': PUBACK received", config.name);
-                            }
-                            Packet::Disconnect(disconnect) => {
-                                warn!(
-                                    "Bridge '{}': Received DISCONNECT: {:?}",
-                                    config.name, disconnect.reason_code
-                                );
-                                return Err(RemoteError::ConnectionLost("Remote disconnected".to_string()));
-                            }
-                            _ => {}
-                        }
+                    while let Some((packet, len)) = decoder
+                        .decode(&pending)
+                        .map_err(|e| RemoteError::Other(format!("Decode error: {}", e)))?
+                    {
+                        let _ = pending.split_to(len);
+                        Self::handle_packet(
+                            config,
+                            topic_mapper,
+                            queue,
+                            session,
+                            subscriptions,
+                            stats,
+                            inbound_callback,
+                            &encoder,
+                            &mut write_half,
+                            packet,
+                        )
+                        .await?;
                     }
+
+                    // Acknowledgements may have made room for queued publishes
+                    Self::send_queued(queue, session, stats, &encoder, &mut write_half).await?;
+                }
+
+                // Retry subscriptions the remote broker rejected
+                _ = tokio::time::sleep_until(next_retry.unwrap_or_else(Instant::now).into()),
+                    if next_retry.is_some() =>
+                {
+                    let due = subscriptions.due(Instant::now());
+                    Self::subscribe(
+                        config,
+                        session,
+                        subscriptions,
+                        &encoder,
+                        &mut write_half,
+                        due,
+                        RetainHandling::SendAtSubscribe,
+                    )
+                    .await?;
+                    stats.subscriptions(subscriptions.status());
+                }
+
+                // Renew subscriptions without replaying retained messages
+                _ = audit_timer.tick(), if audit_enabled => {
+                    let filters = subscriptions.all();
+                    Self::subscribe(
+                        config,
+                        session,
+                        subscriptions,
+                        &encoder,
+                        &mut write_half,
+                        filters,
+                        RetainHandling::SendAtSubscribeIfNew,
+                    )
+                    .await?;
                 }
 
                 // Send PINGREQ to keep connection alive
                 _ = keepalive_timer.tick() => {
-                    let pingreq = Packet::PingReq;
-                    buf.clear();
-                    if encoder.encode(&pingreq, &mut buf).is_ok() {
-                        if let Err(e) = write_half.write_all(&buf).await {
-                            return Err(RemoteError::ConnectionLost(e.to_string()));
-                        }
+                    Self::write_packet(&encoder, &mut write_half, &Packet::PingReq).await?;
+                    stats.ping_sent();
+                }
+            }
+        }
+    }
+
+    /// Handle a packet from the remote broker
+    #[allow(clippy::too_many_arguments)]
+    async fn handle_packet<W: AsyncWrite + Unpin>(
+        config: &BridgeConfig,
+        topic_mapper: &TopicMapper,
+        queue: &BridgeQueue,
+        session: &mut BridgeSession,
+        subscriptions: &mut RemoteSubscriptions,
+        stats: &BridgeStats,
+        inbound_callback: &Option<InboundCallback>,
+        encoder: &Encoder,
+        writer: &mut W,
+        packet: Packet,
+    ) -> Result<(), RemoteError> {
+        match packet {
+            Packet::Publish(publish) => {
+                // A redelivered QoS 2 publish was already forwarded
+                let first_delivery = match (publish.qos, publish.packet_id) {
+                    (QoS::ExactlyOnce, Some(packet_id)) => session.receive(packet_id),
+                    _ => true,
+                };
+
+                // Drop our own messages echoed back by the remote broker
+                let origin = if config.use_origin_property() {
+                    BridgeOrigin::from_user_properties(&publish.properties.user_properties)
+                } else {
+                    None
+                };
+                let echoed = origin
+                    .as_ref()
+                    .is_some_and(|o| o.contains(config.get_origin_id()));
+                if echoed {
+                    debug!(
+                        "Bridge '{}': Dropping {} that already crossed this bridge",
+                        config.name, publish.topic
+                    );
+                }
+
+                // Forward to local broker via callback
+                if let (true, false, Some(callback)) = (first_delivery, echoed, inbound_callback) {
+                    if let Some((local_topic, qos, retain)) =
+                        topic_mapper.map_inbound(&publish.topic, publish.qos, publish.retain)
+                    {
+                        debug!(
+                            "Bridge '{}': Forwarding {} -> {}",
+                            config.name, publish.topic, local_topic
+                        );
+                        let properties = Properties {
+                            response_topic: publish.properties.response_topic,
+                            correlation_data: publish.properties.correlation_data,
+                            ..Default::default()
+                        };
+                        callback(
+                            local_topic,
+                            publish.payload,
+                            qos,
+                            retain,
+                            origin,
+                            properties,
+                        );
+                        stats.forwarded_in();
+                    }
+                }
+
+                // Acknowledge QoS 1 with PUBACK, QoS 2 with PUBREC
+                let ack = match (publish.qos, publish.packet_id) {
+                    (QoS::AtLeastOnce, Some(packet_id)) => {
+                        Some(Packet::PubAck(PubAck::new(packet_id)))
+                    }
+                    (QoS::ExactlyOnce, Some(packet_id)) => {
+                        Some(Packet::PubRec(PubRec::new(packet_id)))
                     }
+                    _ => None,
+                };
+                if let Some(ack) = ack {
+                    Self::write_packet(encoder, writer, &ack).await?;
+                }
+            }
+            Packet::PubRel(pubrel) => {
+                session.received_release(pubrel.packet_id);
+                let pubcomp = Packet::PubComp(PubComp::new(pubrel.packet_id));
+                Self::write_packet(encoder, writer, &pubcomp).await?;
+            }
+            Packet::PubAck(puback) => {
+                queue.ack(&session.complete(puback.packet_id));
+            }
+            Packet::PubRec(pubrec) => {
+                if pubrec.reason_code.is_error() {
+                    // The remote broker refused the message; the flow ends here
+                    warn!(
+                        "Bridge '{}': Publish rejected: {:?}",
+                        config.name, pubrec.reason_code
+                    );
+                    queue.ack(&session.complete(pubrec.packet_id));
+                } else {
+                    session.release(pubrec.packet_id);
+                    let pubrel = Packet::PubRel(PubRel::new(pubrec.packet_id));
+                    Self::write_packet(encoder, writer, &pubrel).await?;
+                }
+            }
+            Packet::PubComp(pubcomp) => {
+                queue.ack(&session.complete(pubcomp.packet_id));
+            }
+            Packet::PingResp => {
+                stats.pong_received();
+                debug!("Bridge '{}': PINGRESP received", config.name);
+            }
+            Packet::SubAck(suback) => {
+                let rejected =
+                    subscriptions.suback(suback.packet_id, &suback.reason_codes, Instant::now());
+                for (filter, reason) in &rejected {
+                    warn!(
+                        "Bridge '{}': Subscription to {} rejected: {}, retrying",
+                        config.name, filter, reason
+                    );
+                }
+                stats.subscriptions(subscriptions.status());
+            }
+            Packet::Disconnect(disconnect) => {
+                warn!(
+                    "Bridge '{}': Received DISCONNECT: {:?}",
+                    config.name, disconnect.reason_code
+                );
+                return Err(RemoteError::ConnectionLost(
+                    "Remote disconnected".to_string(),
+                ));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Subscribe to inbound filters on the remote broker with loop prevention
+    async fn subscribe<W: AsyncWrite + Unpin>(
+        config: &BridgeConfig,
+        session: &mut BridgeSession,
+        subscriptions: &mut RemoteSubscriptions,
+        encoder: &Encoder,
+        writer: &mut W,
+        filters: Vec<(String, QoS)>,
+        retain_handling: RetainHandling,
+    ) -> Result<(), RemoteError> {
+        if filters.is_empty() {
+            return Ok(());
+        }
+        let packet_id = session.next_packet_id();
+        let subscribe = Packet::Subscribe(Subscribe {
+            packet_id,
+            subscriptions: filters
+                .iter()
+                .map(|(filter, qos)| Subscription {
+                    filter: filter.clone(),
+                    options: SubscriptionOptions {
+                        qos: *qos,
+                        no_local: config.use_no_local(), // Prevents receiving our own messages
+                        retain_handling,
+                        ..Default::default()
+                    },
+                })
+                .collect(),
+            properties: Properties::default(),
+        });
+        Self::write_packet(encoder, writer, &subscribe).await?;
+
+        debug!(
+            "Bridge '{}': Subscribing to {} inbound topics",
+            config.name,
+            filters.len()
+        );
+        subscriptions.sent(packet_id, filters.into_iter().map(|(f, _)| f).collect());
+        Ok(())
+    }
+
+    /// Encode and write a single packet
+    async fn write_packet<W: AsyncWrite + Unpin>(
+        encoder: &Encoder,
+        writer: &mut W,
+        packet: &Packet,
+    ) -> Result<(), RemoteError> {
+        let mut buf = BytesMut::new();
+        encoder
+            .encode(packet, &mut buf)
+            .map_err(|e| RemoteError::Other(format!("Encode error: {}", e)))?;
+        writer
+            .write_all(&buf)
+            .await
+            .map_err(|e| RemoteError::ConnectionLost(e.to_string()))
+    }
+
+    /// Resend publishes and releases left unacknowledged by the previous connection
+    async fn resend<W: AsyncWrite + Unpin>(
+        session: &BridgeSession,
+        encoder: &Encoder,
+        writer: &mut W,
+    ) -> Result<(), RemoteError> {
+        let mut buf = BytesMut::new();
+        for resend in session.resend() {
+            let packet = match resend {
+                Resend::Publish(packet_id, publish) => {
+                    let properties = publish.properties();
+                    Packet::Publish(Publish {
+                        dup: true,
+                        qos: publish.qos,
+                        retain: publish.retain,
+                        topic: publish.topic.into(),
+                        packet_id: Some(packet_id),
+                        properties,
+                        payload: publish.payload,
+                    })
                 }
+                Resend::Release(packet_id) => Packet::PubRel(PubRel::new(packet_id)),
+            };
+            let _ = encoder.encode(&packet, &mut buf);
+        }
+        if buf.is_empty() {
+            return Ok(());
+        }
+        writer
+            .write_all(&buf)
+            .await
+            .map_err(|e| RemoteError::ConnectionLost(e.to_string()))
+    }
+
+    /// Write queued publishes while the remote broker's Receive Maximum allows
+    ///
+    /// QoS 1/2 publishes are tracked in the session until acknowledged; if a
+    /// write fails they are resent or requeued on reconnect, and QoS 0
+    /// publishes of the failed batch are put back in the queue.
+    async fn send_queued<W: AsyncWrite + Unpin>(
+        queue: &BridgeQueue,
+        session: &mut BridgeSession,
+        stats: &BridgeStats,
+        encoder: &Encoder,
+        writer: &mut W,
+    ) -> Result<(), RemoteError> {
+        let mut buf = BytesMut::new();
+        loop {
+            let room = session.room();
+            if room == 0 {
+                return Ok(());
+            }
+            let batch = queue.pop_batch(SEND_BATCH.min(room));
+            if batch.is_empty() {
+                return Ok(());
+            }
+
+            buf.clear();
+            for publish in &batch {
+                let packet = Packet::Publish(Publish {
+                    dup: false,
+                    qos: publish.qos,
+                    retain: publish.retain,
+                    topic: publish.topic.as_str().into(),
+                    packet_id: session.send(publish),
+                    payload: publish.payload.clone(),
+                    properties: publish.properties(),
+                });
+                let _ = encoder.encode(&packet, &mut buf);
+            }
+
+            if let Err(e) = writer.write_all(&buf).await {
+                queue.requeue(
+                    batch
+                        .into_iter()
+                        .filter(|p| p.qos == QoS::AtMostOnce)
+                        .collect(),
+                );
+                return Err(RemoteError::ConnectionLost(e.to_string()));
             }
+            // QoS 1/2 publishes are acknowledged by the remote broker later
+            queue.ack(batch.iter().filter(|p| p.qos == QoS::AtMostOnce));
+            stats.forwarded_out(batch.len());
         }
     }
 }
@@ -449,26 +990,7 @@ impl RemotePeer for BridgeClient {
         qos: QoS,
         retain: bool,
     ) -> Result<(), RemoteError> {
-        // Map the topic and check if we should forward
-        let (remote_topic, effective_qos, effective_retain) =
-            match self.topic_mapper.map_outbound(topic, qos, retain) {
-                Some(mapping) => mapping,
-                None => return Ok(()), // Topic doesn't match any rules
-            };
-
-        // Send via command channel
-        if let Some(ref tx) = self.command_tx {
-            tx.send(BridgeCommand::Publish {
-                topic: remote_topic,
-                payload,
-                qos: effective_qos,
-                retain: effective_retain,
-            })
-            .await
-            .map_err(|_| RemoteError::ConnectionLost("Command channel closed".to_string()))?;
-        }
-
-        Ok(())
+        self.forward_publish_from(topic, payload, qos, retain, None, &Properties::default())
     }
 
     async fn notify_subscribe(&self, filter: &str, qos: QoS) -> Result<(), RemoteError> {
@@ -529,10 +1051,13 @@ impl BridgeClient {
         let config = self.config.clone();
         let topic_mapper = TopicMapper::new(&config.forwards);
         let status = self.status.clone();
+        let queue = self.queue.clone();
+        let stats = self.stats.clone();
         let callback = self.inbound_callback.clone();
 
+        queue.spawn_spool();
         tokio::spawn(async move {
-            Self::connection_loop(config, topic_mapper, status, rx, callback).await;
+            Self::connection_loop(config, topic_mapper, status, queue, stats, rx, callback).await;
         });
 
         Arc::new(self)
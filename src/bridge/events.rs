@@ -0,0 +1,174 @@
+//! Broker Event Export
+//!
+//! Turns broker events into JSON messages on `$export/<event>`, which the
+//! bridges forward like published messages. Nothing is published locally,
+//! so the events reach an HTTP endpoint or a Kafka topic without an MQTT
+//! consumer in between, with the bridge's queueing, batching and
+//! reconnects.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use serde_json::{json, Value};
+
+use crate::broker::BrokerEvent;
+use crate::config::{EventExportConfig, ExportedEvent};
+
+/// Broker events exported through the bridges
+#[derive(Debug, Clone, Default)]
+pub struct EventExport {
+    events: Vec<ExportedEvent>,
+}
+
+impl EventExport {
+    pub fn new(config: &EventExportConfig) -> Self {
+        Self {
+            events: config.events.clone(),
+        }
+    }
+
+    /// Whether any event is exported
+    pub fn is_enabled(&self) -> bool {
+        !self.events.is_empty()
+    }
+
+    /// Topic and JSON payload of the message exporting `event`, if its
+    /// kind is exported
+    pub fn message(&self, event: &BrokerEvent) -> Option<(String, Bytes)> {
+        let (kind, fields) = match event {
+            BrokerEvent::ClientConnected {
+                client_id,
+                protocol_version,
+                listener,
+            } => (
+                ExportedEvent::ClientConnected,
+                json!({
+                    "client_id": client_id.as_ref(),
+                    "protocol_version": *protocol_version as u8,
+                    "listener": listener.as_str(),
+                }),
+            ),
+            BrokerEvent::ClientDisconnected { client_id, reason } => (
+                ExportedEvent::ClientDisconnected,
+                json!({
+                    "client_id": client_id.as_ref(),
+                    "reason": reason.as_str(),
+                }),
+            ),
+            BrokerEvent::SubscriptionAdded { filter, client_id } => (
+                ExportedEvent::Subscribed,
+                json!({
+                    "client_id": client_id.as_ref(),
+                    "filter": filter,
+                }),
+            ),
+            BrokerEvent::SubscriptionRemoved { filter, client_id } => (
+                ExportedEvent::Unsubscribed,
+                json!({
+                    "client_id": client_id.as_ref(),
+                    "filter": filter,
+                }),
+            ),
+            BrokerEvent::MessageDropped {
+                client_id,
+                topic,
+                reason,
+            } => (
+                ExportedEvent::DeliveryFailed,
+                json!({
+                    "client_id": client_id.as_ref(),
+                    "topic": topic.as_ref(),
+                    "reason": reason.as_str(),
+                }),
+            ),
+            BrokerEvent::MessagePublished { .. } => return None,
+        };
+        if !self.events.contains(&kind) {
+            return None;
+        }
+
+        let mut payload = fields;
+        if let Value::Object(ref mut map) = payload {
+            map.insert("event".to_string(), kind.as_str().into());
+            map.insert("timestamp".to_string(), timestamp_ms().into());
+        }
+        Some((kind.topic(), Bytes::from(payload.to_string())))
+    }
+}
+
+/// Milliseconds since the Unix epoch
+fn timestamp_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::broker::{DisconnectReason, DropReason, Listener};
+    use crate::protocol::ProtocolVersion;
+
+    fn export(events: &[ExportedEvent]) -> EventExport {
+        EventExport::new(&EventExportConfig {
+            events: events.to_vec(),
+        })
+    }
+
+    fn payload(message: Option<(String, Bytes)>) -> (String, Value) {
+        let (topic, payload) = message.unwrap();
+        (topic, serde_json::from_slice(&payload).unwrap())
+    }
+
+    #[test]
+    fn test_exports_selected_events() {
+        let export = export(&[
+            ExportedEvent::ClientConnected,
+            ExportedEvent::DeliveryFailed,
+        ]);
+
+        let (topic, json) = payload(export.message(&BrokerEvent::ClientConnected {
+            client_id: Arc::from("dev42"),
+            protocol_version: ProtocolVersion::V5,
+            listener: Listener::Tls,
+        }));
+        assert_eq!(topic, "$export/client_connected");
+        assert_eq!(json["event"], "client_connected");
+        assert_eq!(json["client_id"], "dev42");
+        assert_eq!(json["protocol_version"], 5);
+        assert_eq!(json["listener"], "tls");
+        assert!(json["timestamp"].as_u64().unwrap() > 0);
+
+        let (topic, json) = payload(export.message(&BrokerEvent::MessageDropped {
+            client_id: Arc::from("dev42"),
+            topic: Arc::from("sensors/temp"),
+            reason: DropReason::QueueFull,
+        }));
+        assert_eq!(topic, "$export/delivery_failed");
+        assert_eq!(json["topic"], "sensors/temp");
+        assert_eq!(json["reason"], "queue_full");
+
+        // Events not selected are not exported
+        assert!(export
+            .message(&BrokerEvent::ClientDisconnected {
+                client_id: Arc::from("dev42"),
+                reason: DisconnectReason::Normal,
+            })
+            .is_none());
+    }
+
+    #[test]
+    fn test_subscription_events() {
+        let export = export(&[ExportedEvent::Subscribed, ExportedEvent::Unsubscribed]);
+        let (topic, json) = payload(export.message(&BrokerEvent::SubscriptionRemoved {
+            filter: "sensors/#".to_string(),
+            client_id: Arc::from("dev42"),
+        }));
+        assert_eq!(topic, "$export/unsubscribed");
+        assert_eq!(json["filter"], "sensors/#");
+        assert!(!EventExport::default().is_enabled());
+    }
+}
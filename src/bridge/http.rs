@@ -0,0 +1,505 @@
+//! HTTP Webhook Bridge
+//!
+//! Runs a bridge with `protocol = "http"` or `"https"`: queued outbound
+//! messages are sent as requests to the bridge address over a kept-alive
+//! HTTP/1.1 connection. The request path and header values are templated
+//! from each message (see `BridgeHttpConfig`). With `batch_size` above 1,
+//! consecutive messages for the same path and headers go in one request as
+//! a JSON array.
+//!
+//! A 2xx response counts as forwarded. Timeouts, 408, 429 and 5xx
+//! responses leave the messages queued and reconnect with the bridge's
+//! backoff; other responses are logged and the messages discarded, since
+//! retrying them would block the queue.
+//!
+//! [`HttpEndpoint`] opens the connections and sends the requests; the
+//! webhook actions of rules use it as well, so both verify TLS and read
+//! responses the same way.
+
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::client::conn::http1::{self, SendRequest};
+use hyper::header::{HeaderName, HeaderValue, CONTENT_TYPE, HOST};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::rt::TokioIo;
+use parking_lot::RwLock;
+use serde_json::json;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+
+use crate::config::{BridgeConfig, BridgeTlsConfig};
+use crate::remote::{RemoteError, RemotePeerStatus};
+
+use super::client::{connect_stream, BridgeCommand};
+use super::queue::{BridgeQueue, QueuedPublish};
+use super::stats::BridgeStats;
+
+/// Maximum messages taken from the queue in one go
+const SEND_BATCH: usize = 64;
+
+/// Connect to the webhook endpoint and run until shutdown or an error
+pub(super) async fn connect_and_run(
+    config: &BridgeConfig,
+    address: &str,
+    status: &Arc<RwLock<RemotePeerStatus>>,
+    queue: &BridgeQueue,
+    stats: &Arc<BridgeStats>,
+    command_rx: &mut mpsc::Receiver<BridgeCommand>,
+) -> Result<(), RemoteError> {
+    let (host, port) = config.split_address(address);
+    let authority = if port == config.protocol.default_port() {
+        host.clone()
+    } else {
+        format!("{}:{}", host, port)
+    };
+    let method = Method::from_bytes(config.http.method.as_bytes())
+        .map_err(|e| RemoteError::InvalidConfig(format!("Invalid HTTP method: {}", e)))?;
+
+    let endpoint = HttpEndpoint {
+        tls: config
+            .protocol
+            .uses_tls()
+            .then(|| config.tls.clone().unwrap_or_default()),
+        host,
+        port,
+        connect_timeout: config.connect_timeout,
+    };
+    let mut sender = endpoint.connect().await?;
+
+    info!(
+        "Bridge '{}': Connected to webhook at {}",
+        config.name, address
+    );
+    *status.write() = RemotePeerStatus::Connected;
+    stats.connected();
+
+    let webhook = Webhook {
+        config,
+        endpoint: &endpoint,
+        authority: &authority,
+        method: &method,
+    };
+    webhook.send_queued(&mut sender, queue, stats).await?;
+
+    loop {
+        tokio::select! {
+            Some(cmd) = command_rx.recv() => {
+                // Webhooks have no subscriptions to change
+                if let BridgeCommand::Shutdown = cmd {
+                    return Ok(());
+                }
+            }
+
+            _ = queue.notified() => {
+                webhook.send_queued(&mut sender, queue, stats).await?;
+            }
+        }
+    }
+}
+
+/// Sender half of an HTTP/1.1 connection
+pub(crate) type HttpSender = SendRequest<Full<Bytes>>;
+
+/// An HTTP server, reached over TLS when `tls` is set
+#[derive(Debug, Clone)]
+pub(crate) struct HttpEndpoint {
+    pub host: String,
+    pub port: u16,
+    pub tls: Option<BridgeTlsConfig>,
+    pub connect_timeout: Duration,
+}
+
+impl HttpEndpoint {
+    /// Open a connection and perform the HTTP/1.1 handshake
+    pub(crate) async fn connect(&self) -> Result<HttpSender, RemoteError> {
+        let stream = connect_stream(
+            &self.host,
+            self.port,
+            self.tls.as_ref(),
+            self.connect_timeout,
+        )
+        .await?;
+        let (sender, connection) = http1::handshake(TokioIo::new(stream))
+            .await
+            .map_err(http_error)?;
+
+        let address = format!("{}:{}", self.host, self.port);
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                debug!("HTTP connection to {} closed: {}", address, e);
+            }
+        });
+        Ok(sender)
+    }
+
+    /// Send a request and read the response, reconnecting first if the
+    /// server closed the connection
+    ///
+    /// The body is read so the connection can take the next request.
+    pub(crate) async fn send(
+        &self,
+        sender: &mut HttpSender,
+        request: Request<Full<Bytes>>,
+        request_timeout: Duration,
+    ) -> Result<StatusCode, RemoteError> {
+        if sender.is_closed() || sender.ready().await.is_err() {
+            *sender = self.connect().await?;
+        }
+
+        timeout(request_timeout, async {
+            let response = sender.send_request(request).await?;
+            let status = response.status();
+            response.into_body().collect().await?;
+            Ok::<_, hyper::Error>(status)
+        })
+        .await
+        .map_err(|_| RemoteError::Timeout)?
+        .map_err(http_error)
+    }
+}
+
+/// Request settings shared by every message of one connection
+struct Webhook<'a> {
+    config: &'a BridgeConfig,
+    endpoint: &'a HttpEndpoint,
+    authority: &'a str,
+    method: &'a Method,
+}
+
+/// Target of a request rendered from a message
+#[derive(PartialEq)]
+struct Target {
+    path: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Webhook<'_> {
+    /// Send everything queued
+    async fn send_queued(
+        &self,
+        sender: &mut HttpSender,
+        queue: &BridgeQueue,
+        stats: &BridgeStats,
+    ) -> Result<(), RemoteError> {
+        loop {
+            let batch = queue.pop_batch(SEND_BATCH);
+            if batch.is_empty() {
+                return Ok(());
+            }
+
+            let requests = self.group(batch);
+            let mut remaining = requests.into_iter();
+            while let Some((target, publishes)) = remaining.next() {
+                match self.send(sender, &target, &publishes).await {
+                    Ok(()) => {
+                        queue.ack(&publishes);
+                        stats.forwarded_out(publishes.len());
+                    }
+                    Err(Some(e)) => {
+                        let mut requeue = publishes;
+                        requeue.extend(remaining.flat_map(|(_, p)| p));
+                        queue.requeue(requeue);
+                        return Err(e);
+                    }
+                    Err(None) => queue.ack(&publishes),
+                }
+            }
+        }
+    }
+
+    /// Split messages into requests: runs of the same target, at most
+    /// `batch_size` messages each
+    fn group(&self, batch: Vec<QueuedPublish>) -> Vec<(Target, Vec<QueuedPublish>)> {
+        let mut requests: Vec<(Target, Vec<QueuedPublish>)> = Vec::new();
+        for publish in batch {
+            let target = Target {
+                path: encode_path(&render(&self.config.http.path, &publish, encode_value)),
+                headers: self
+                    .config
+                    .http
+                    .headers
+                    .iter()
+                    .map(|(name, value)| {
+                        (
+                            name.clone(),
+                            render(value, &publish, |value| Cow::Borrowed(value)),
+                        )
+                    })
+                    .collect(),
+            };
+            match requests.last_mut() {
+                Some((last, publishes))
+                    if *last == target && publishes.len() < self.config.http.batch_size =>
+                {
+                    publishes.push(publish)
+                }
+                _ => requests.push((target, vec![publish])),
+            }
+        }
+        requests
+    }
+
+    /// Send one request
+    ///
+    /// `Err(Some(_))` means the messages should be retried, `Err(None)`
+    /// that they were rejected and are discarded.
+    async fn send(
+        &self,
+        sender: &mut HttpSender,
+        target: &Target,
+        publishes: &[QueuedPublish],
+    ) -> Result<(), Option<RemoteError>> {
+        let request = match self.request(target, publishes) {
+            Ok(request) => request,
+            Err(e) => {
+                warn!(
+                    "Bridge '{}': Discarding {} message(s): {}",
+                    self.config.name,
+                    publishes.len(),
+                    e
+                );
+                return Err(None);
+            }
+        };
+
+        let response = self
+            .endpoint
+            .send(sender, request, self.config.http.timeout)
+            .await
+            .map_err(Some)?;
+
+        if response.is_success() {
+            return Ok(());
+        }
+        if is_retryable(response) {
+            return Err(Some(RemoteError::Rejected(format!(
+                "webhook returned {}",
+                response
+            ))));
+        }
+        warn!(
+            "Bridge '{}': Webhook rejected {} message(s) with {}",
+            self.config.name,
+            publishes.len(),
+            response
+        );
+        Err(None)
+    }
+
+    fn request(
+        &self,
+        target: &Target,
+        publishes: &[QueuedPublish],
+    ) -> Result<Request<Full<Bytes>>, String> {
+        let (content_type, body) = if self.config.http.batch_size > 1 {
+            ("application/json", json_body(publishes))
+        } else {
+            ("application/octet-stream", publishes[0].payload.clone())
+        };
+
+        let mut builder = Request::builder()
+            .method(self.method.clone())
+            .uri(target.path.as_str())
+            .header(HOST, self.authority);
+        let headers = builder.headers_mut().expect("request builder is valid");
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        for (name, value) in &target.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("invalid header name '{}'", name))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|_| format!("invalid value for header '{}'", name))?;
+            headers.insert(name, value);
+        }
+
+        builder
+            .body(Full::new(body))
+            .map_err(|e| format!("invalid request: {}", e))
+    }
+}
+
+/// Substitute `{topic}`, `{qos}`, `{retain}` and `{N}` (topic level N) in
+/// a template, passing topics and levels through `escape`; unknown
+/// placeholders are kept as they are
+fn render(template: &str, publish: &QueuedPublish, escape: fn(&str) -> Cow<'_, str>) -> String {
+    let mut out = String::with_capacity(template.len() + publish.topic.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let Some(len) = rest[start..].find('}') else {
+            rest = &rest[start..];
+            break;
+        };
+        let name = &rest[start + 1..start + len];
+        match name {
+            "topic" => out.push_str(&escape(&publish.topic)),
+            "qos" => out.push_str(&(publish.qos as u8).to_string()),
+            "retain" => out.push_str(if publish.retain { "true" } else { "false" }),
+            _ => match name.parse::<usize>() {
+                Ok(level) => {
+                    out.push_str(&escape(publish.topic.split('/').nth(level).unwrap_or("")))
+                }
+                Err(_) => out.push_str(&rest[start..=start + len]),
+            },
+        }
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Percent-encode a topic or topic level for a request path
+///
+/// Only unreserved characters are kept, so the value stays within one path
+/// segment or query value; a value of only dots is encoded as well, so it
+/// cannot become a `.` or `..` segment.
+fn encode_value(value: &str) -> Cow<'_, str> {
+    let unreserved = |b: u8| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~');
+    let dots = !value.is_empty() && value.bytes().all(|b| b == b'.');
+    if !dots && value.bytes().all(unreserved) {
+        return Cow::Borrowed(value);
+    }
+    let mut out = String::with_capacity(value.len() * 3);
+    for byte in value.bytes() {
+        if unreserved(byte) && !dots {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// Percent-encode characters that may not appear in a request path
+fn encode_path(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'.'
+            | b'_'
+            | b'~'
+            | b'/'
+            | b'?'
+            | b'&'
+            | b'='
+            | b'%'
+            | b':'
+            | b'@'
+            | b'+'
+            | b','
+            | b';' => out.push(byte as char),
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+/// JSON array body of a batch; payloads that are not UTF-8 are sent as
+/// arrays of bytes
+fn json_body(publishes: &[QueuedPublish]) -> Bytes {
+    let messages: Vec<_> = publishes
+        .iter()
+        .map(|p| {
+            let payload = match std::str::from_utf8(&p.payload) {
+                Ok(text) => json!(text),
+                Err(_) => json!(p.payload.as_ref()),
+            };
+            json!({
+                "topic": p.topic,
+                "payload": payload,
+                "qos": p.qos as u8,
+                "retain": p.retain,
+            })
+        })
+        .collect();
+    Bytes::from(serde_json::to_vec(&messages).unwrap_or_default())
+}
+
+/// Whether a failed response is worth retrying
+pub(crate) fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+fn http_error(e: impl std::fmt::Display) -> RemoteError {
+    RemoteError::ConnectionLost(format!("HTTP: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::QoS;
+
+    fn publish(topic: &str, payload: &'static [u8]) -> QueuedPublish {
+        QueuedPublish {
+            topic: topic.to_string(),
+            payload: Bytes::from_static(payload),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            origin: None,
+            user_properties: Vec::new(),
+            response_topic: None,
+            correlation_data: None,
+            segment: None,
+        }
+    }
+
+    #[test]
+    fn test_render_template() {
+        let p = publish("devices/dev42/telemetry", b"");
+        assert_eq!(
+            render("/ingest/{1}?topic={topic}&qos={qos}", &p, encode_value),
+            "/ingest/dev42?topic=devices%2Fdev42%2Ftelemetry&qos=1"
+        );
+        assert_eq!(
+            render("{topic}", &p, |value| Cow::Borrowed(value)),
+            "devices/dev42/telemetry"
+        );
+        assert_eq!(
+            render("{retain} {9} {other}", &p, encode_value),
+            "false  {other}"
+        );
+        assert_eq!(render("/open{", &p, encode_value), "/open{");
+        assert_eq!(encode_path("/a b/ü"), "/a%20b/%C3%BC");
+    }
+
+    #[test]
+    fn test_render_escapes_path_values() {
+        // Levels cannot leave their segment or start a query or fragment
+        let p = publish("../admin?x=1#/..", b"");
+        assert_eq!(
+            encode_path(&render("/ingest/{0}/{1}/{2}", &p, encode_value)),
+            "/ingest/%2E%2E/admin%3Fx%3D1%23/%2E%2E"
+        );
+        assert_eq!(
+            encode_path(&render("/ingest/{topic}", &p, encode_value)),
+            "/ingest/..%2Fadmin%3Fx%3D1%23%2F.."
+        );
+    }
+
+    #[test]
+    fn test_json_body() {
+        let body = json_body(&[publish("a/b", b"21.5"), publish("a/c", &[0xff])]);
+        let value: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(value[0]["topic"], "a/b");
+        assert_eq!(value[0]["payload"], "21.5");
+        assert_eq!(value[0]["qos"], 1);
+        assert_eq!(value[1]["payload"], json!([255]));
+    }
+
+    #[test]
+    fn test_retryable_status() {
+        assert!(is_retryable(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_retryable(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_retryable(StatusCode::BAD_REQUEST));
+    }
+}
@@ -0,0 +1,354 @@
+//! Kafka Bridge
+//!
+//! Runs a bridge with `protocol = "kafka"`. Queued outbound messages are
+//! produced to the Kafka topic their rule maps to, keyed by MQTT topic
+//! levels and partitioned by the murmur2 hash of the key, as by Kafka's
+//! default partitioner. Inbound rules consume Kafka topics
+//! and publish each record to their local MQTT topic. There are no consumer
+//! groups or committed offsets: consumers start at `kafka.start_offset` on
+//! every connection.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use chrono::Utc;
+use futures_util::StreamExt;
+use parking_lot::RwLock;
+use rskafka::client::consumer::{StartOffset, StreamConsumerBuilder};
+use rskafka::client::partition::{Compression, PartitionClient, UnknownTopicHandling};
+use rskafka::client::{Client, ClientBuilder};
+use rskafka::record::Record;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+use tracing::{debug, info};
+
+use crate::config::{BridgeConfig, KafkaStartOffset};
+use crate::protocol::{Properties, QoS};
+use crate::remote::{RemoteError, RemotePeerStatus};
+
+use super::client::{BridgeCommand, InboundCallback};
+use super::queue::{BridgeQueue, QueuedPublish};
+use super::stats::BridgeStats;
+use super::topic_mapper::TopicMapper;
+
+/// Maximum records produced in one go
+const PRODUCE_BATCH: usize = 500;
+
+/// Record header carrying the MQTT topic of a produced message
+const MQTT_TOPIC_HEADER: &str = "mqtt_topic";
+
+/// Connect to the Kafka cluster and run until shutdown or an error
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn connect_and_run(
+    config: &BridgeConfig,
+    address: &str,
+    topic_mapper: &TopicMapper,
+    status: &Arc<RwLock<RemotePeerStatus>>,
+    queue: &BridgeQueue,
+    stats: &Arc<BridgeStats>,
+    command_rx: &mut mpsc::Receiver<BridgeCommand>,
+    inbound_callback: &Option<InboundCallback>,
+) -> Result<(), RemoteError> {
+    let client = timeout(
+        config.connect_timeout,
+        ClientBuilder::new(vec![address.to_string()]).build(),
+    )
+    .await
+    .map_err(|_| RemoteError::Timeout)?
+    .map_err(kafka_error)?;
+    let client = Arc::new(client);
+
+    // Dropping the set when this returns stops the consumers
+    let mut consumers = JoinSet::new();
+    if let Some(callback) = inbound_callback {
+        for (kafka_topic, _) in topic_mapper.inbound_filters() {
+            // Kafka records carry no QoS; the rule's QoS applies
+            let Some((local_topic, qos, _)) =
+                topic_mapper.map_inbound(kafka_topic, QoS::ExactlyOnce, false)
+            else {
+                continue;
+            };
+            for partition in partition_clients(&client, kafka_topic).await? {
+                consumers.spawn(consume(
+                    partition,
+                    config.kafka.start_offset,
+                    local_topic.clone(),
+                    qos,
+                    callback.clone(),
+                    stats.clone(),
+                ));
+            }
+            debug!(
+                "Bridge '{}': Consuming Kafka topic {} -> {}",
+                config.name, kafka_topic, local_topic
+            );
+        }
+    }
+
+    info!(
+        "Bridge '{}': Connected to Kafka at {}",
+        config.name, address
+    );
+    *status.write() = RemotePeerStatus::Connected;
+    stats.connected();
+
+    let mut producer = Producer {
+        client,
+        partitions: HashMap::new(),
+    };
+    producer
+        .produce_queued(config, topic_mapper, queue, stats)
+        .await?;
+
+    loop {
+        tokio::select! {
+            Some(cmd) = command_rx.recv() => {
+                // Kafka has no subscriptions to change
+                if let BridgeCommand::Shutdown = cmd {
+                    return Ok(());
+                }
+            }
+
+            _ = queue.notified() => {
+                producer.produce_queued(config, topic_mapper, queue, stats).await?;
+            }
+
+            Some(result) = consumers.join_next() => {
+                return Err(match result {
+                    Ok(Err(e)) => e,
+                    Ok(Ok(())) => RemoteError::ConnectionLost("Kafka consumer stopped".to_string()),
+                    Err(e) => RemoteError::Other(format!("Kafka consumer failed: {}", e)),
+                });
+            }
+        }
+    }
+}
+
+/// Publish the records of one partition locally until the stream fails
+async fn consume(
+    partition: Arc<PartitionClient>,
+    start_offset: KafkaStartOffset,
+    local_topic: String,
+    qos: QoS,
+    callback: InboundCallback,
+    stats: Arc<BridgeStats>,
+) -> Result<(), RemoteError> {
+    let start_offset = match start_offset {
+        KafkaStartOffset::Latest => StartOffset::Latest,
+        KafkaStartOffset::Earliest => StartOffset::Earliest,
+    };
+    let mut stream = StreamConsumerBuilder::new(partition, start_offset)
+        .with_max_wait_ms(500)
+        .build();
+
+    while let Some(result) = stream.next().await {
+        let (record, _high_watermark) = result.map_err(kafka_error)?;
+        let payload = record.record.value.map(Bytes::from).unwrap_or_default();
+        callback(
+            local_topic.clone(),
+            payload,
+            qos,
+            false,
+            None,
+            Properties::default(),
+        );
+        stats.forwarded_in();
+    }
+    Ok(())
+}
+
+/// Produces queued messages, caching partition clients per Kafka topic
+struct Producer {
+    client: Arc<Client>,
+    partitions: HashMap<String, Vec<Arc<PartitionClient>>>,
+}
+
+impl Producer {
+    /// Produce everything queued
+    ///
+    /// Records are produced one partition at a time. If a partition fails,
+    /// the messages of that and the following partitions are queued again;
+    /// those already produced are not.
+    async fn produce_queued(
+        &mut self,
+        config: &BridgeConfig,
+        topic_mapper: &TopicMapper,
+        queue: &BridgeQueue,
+        stats: &BridgeStats,
+    ) -> Result<(), RemoteError> {
+        loop {
+            let batch = queue.pop_batch(PRODUCE_BATCH);
+            if batch.is_empty() {
+                return Ok(());
+            }
+
+            // Look the partitions up first, so a failure leaves nothing produced
+            let mut kafka_topics = Vec::with_capacity(batch.len());
+            for publish in &batch {
+                let kafka_topic = topic_mapper
+                    .map_outbound(&publish.topic, publish.qos, publish.retain)
+                    .map(|(kafka_topic, _, _)| kafka_topic);
+                if let Some(ref kafka_topic) = kafka_topic {
+                    if let Err(e) = self.partition_count(kafka_topic).await {
+                        queue.requeue(batch);
+                        return Err(e);
+                    }
+                }
+                kafka_topics.push(kafka_topic);
+            }
+
+            // Group by Kafka topic and partition, keeping order within each
+            let mut records: BTreeMap<(String, usize), Vec<(Record, QueuedPublish)>> =
+                BTreeMap::new();
+            let mut unmapped = Vec::new();
+            for (publish, kafka_topic) in batch.into_iter().zip(kafka_topics) {
+                let Some(kafka_topic) = kafka_topic else {
+                    unmapped.push(publish);
+                    continue;
+                };
+                let key = config.kafka.record_key(&publish.topic);
+                let partition = partition_for(key.as_bytes(), self.partitions[&kafka_topic].len());
+                let record = Record {
+                    key: Some(key.into_bytes()),
+                    value: Some(publish.payload.to_vec()),
+                    headers: BTreeMap::from([(
+                        MQTT_TOPIC_HEADER.to_string(),
+                        publish.topic.clone().into_bytes(),
+                    )]),
+                    timestamp: Utc::now(),
+                };
+                records
+                    .entry((kafka_topic, partition))
+                    .or_default()
+                    .push((record, publish));
+            }
+            queue.ack(&unmapped);
+
+            let mut remaining = records.into_iter();
+            while let Some(((kafka_topic, partition), produced)) = remaining.next() {
+                let (records, publishes): (Vec<_>, Vec<_>) = produced.into_iter().unzip();
+                let client = &self.partitions[&kafka_topic][partition];
+                if let Err(e) = client.produce(records, Compression::NoCompression).await {
+                    let mut requeue = publishes;
+                    requeue.extend(remaining.flat_map(|(_, p)| p).map(|(_, p)| p));
+                    queue.requeue(requeue);
+                    return Err(kafka_error(e));
+                }
+                queue.ack(&publishes);
+                stats.forwarded_out(publishes.len());
+            }
+        }
+    }
+
+    async fn partition_count(&mut self, kafka_topic: &str) -> Result<usize, RemoteError> {
+        if !self.partitions.contains_key(kafka_topic) {
+            let clients = partition_clients(&self.client, kafka_topic).await?;
+            self.partitions.insert(kafka_topic.to_string(), clients);
+        }
+        Ok(self.partitions[kafka_topic].len())
+    }
+}
+
+/// Clients for every partition of a Kafka topic
+async fn partition_clients(
+    client: &Client,
+    kafka_topic: &str,
+) -> Result<Vec<Arc<PartitionClient>>, RemoteError> {
+    let partitions = client
+        .list_topics()
+        .await
+        .map_err(kafka_error)?
+        .into_iter()
+        .find(|t| t.name == kafka_topic)
+        .map(|t| t.partitions)
+        .filter(|p| !p.is_empty())
+        .ok_or_else(|| {
+            RemoteError::Other(format!("Kafka topic '{}' does not exist", kafka_topic))
+        })?;
+
+    let mut clients = Vec::with_capacity(partitions.len());
+    for partition in partitions {
+        let client = client
+            .partition_client(kafka_topic, partition, UnknownTopicHandling::Error)
+            .await
+            .map_err(kafka_error)?;
+        clients.push(Arc::new(client));
+    }
+    Ok(clients)
+}
+
+/// Partition of a record key, as chosen by Kafka's default partitioner
+fn partition_for(key: &[u8], partitions: usize) -> usize {
+    (murmur2(key) & 0x7fff_ffff) as usize % partitions.max(1)
+}
+
+/// Kafka's murmur2 hash (`org.apache.kafka.common.utils.Utils.murmur2`)
+fn murmur2(data: &[u8]) -> u32 {
+    const SEED: u32 = 0x9747_b28c;
+    const M: u32 = 0x5bd1_e995;
+    const R: u32 = 24;
+
+    let mut h = SEED ^ data.len() as u32;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M);
+        h ^= k;
+    }
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        for (i, &byte) in rest.iter().enumerate().rev() {
+            h ^= (byte as u32) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^= h >> 15;
+    h
+}
+
+fn kafka_error(e: impl std::fmt::Display) -> RemoteError {
+    RemoteError::ConnectionLost(format!("Kafka: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partition_for_is_stable() {
+        let p = partition_for(b"dev42", 6);
+        assert!(p < 6);
+        assert_eq!(partition_for(b"dev42", 6), p);
+        assert_eq!(partition_for(b"anything", 1), 0);
+    }
+
+    #[test]
+    fn test_murmur2_matches_kafka() {
+        // Expected values from Kafka's own tests of `Utils.murmur2`
+        let cases: [(&[u8], i32); 6] = [
+            (b"21", -973932308),
+            (b"foobar", -790332482),
+            (b"a-little-bit-long-string", -985981536),
+            (b"a-little-bit-longer-string", -1486304829),
+            (
+                b"lkjh234lh9fiuh90y23oiuhsafujhadof229phr9h19h89h8",
+                -58897971,
+            ),
+            (b"abc", 479470107),
+        ];
+        for (key, expected) in cases {
+            assert_eq!(murmur2(key) as i32, expected);
+        }
+        assert_eq!(
+            partition_for(b"foobar", 7),
+            (-790332482i32 & 0x7fff_ffff) as usize % 7
+        );
+    }
+}
@@ -7,18 +7,31 @@ use std::sync::Arc;
 
 use bytes::Bytes;
 use parking_lot::RwLock;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, info_span};
 
-use crate::protocol::QoS;
+use crate::broker::BrokerEvent;
+use crate::metrics::Metrics;
+use crate::persistence::PersistenceManager;
+use crate::protocol::{Properties, QoS};
 use crate::remote::{RemotePeer, RemotePeerStatus};
 
 use super::client::{BridgeClient, InboundCallback};
+use super::events::EventExport;
+use super::origin::BridgeOrigin;
+use super::stats::BridgeStatus;
+use super::transform::BridgeTransform;
 use crate::config::BridgeConfig;
 
 /// Manages all bridge connections for a broker
 pub struct BridgeManager {
     /// All bridge connections
     bridges: RwLock<Vec<Arc<BridgeClient>>>,
+    /// Transform given to every bridge, including ones added later
+    transform: RwLock<Option<Arc<dyn BridgeTransform>>>,
+    /// Broker events forwarded to the bridges
+    export: RwLock<EventExport>,
+    /// Backend the bridges spool to
+    persistence: Option<Arc<PersistenceManager>>,
 }
 
 impl BridgeManager {
@@ -26,12 +39,24 @@ impl BridgeManager {
     pub fn new() -> Self {
         Self {
             bridges: RwLock::new(Vec::new()),
+            transform: RwLock::new(None),
+            export: RwLock::new(EventExport::default()),
+            persistence: None,
         }
     }
 
     /// Create a bridge manager from configuration
-    pub fn from_configs(configs: Vec<BridgeConfig>, inbound_callback: InboundCallback) -> Self {
-        let manager = Self::new();
+    ///
+    /// Bridges with a spool spool to `persistence`.
+    pub fn from_configs(
+        configs: Vec<BridgeConfig>,
+        inbound_callback: InboundCallback,
+        persistence: Option<Arc<PersistenceManager>>,
+    ) -> Self {
+        let manager = Self {
+            persistence,
+            ..Self::new()
+        };
 
         for config in configs {
             if config.enabled {
@@ -45,7 +70,10 @@ impl BridgeManager {
     /// Add a new bridge connection
     pub fn add_bridge(&self, config: BridgeConfig, inbound_callback: InboundCallback) {
         let name = config.name.clone();
-        let client = BridgeClient::new(config);
+        let client = BridgeClient::new(config, self.persistence.clone());
+        if let Some(transform) = self.transform.read().clone() {
+            client.set_transform(transform);
+        }
         let client = client.spawn(inbound_callback);
 
         info!("Bridge manager: Added bridge '{}'", name);
@@ -53,23 +81,97 @@ impl BridgeManager {
         self.bridges.write().push(client);
     }
 
+    /// Rewrite or drop outbound messages of all bridges before they are queued
+    pub fn set_transform(&self, transform: Arc<dyn BridgeTransform>) {
+        for bridge in self.bridges.read().iter() {
+            bridge.set_transform(transform.clone());
+        }
+        *self.transform.write() = Some(transform);
+    }
+
+    /// Set the broker events exported through the bridges
+    pub fn set_event_export(&self, export: EventExport) {
+        *self.export.write() = export;
+    }
+
+    /// Forward a broker event to the bridges if it is exported
+    pub fn export_event(&self, event: &BrokerEvent) {
+        let message = self.export.read().message(event);
+        if let Some((topic, payload)) = message {
+            self.forward_publish_from(
+                &topic,
+                payload,
+                QoS::AtLeastOnce,
+                false,
+                None,
+                &Properties::default(),
+            );
+        }
+    }
+
     /// Forward a published message to all matching bridges
     pub async fn forward_publish(&self, topic: &str, payload: Bytes, qos: QoS, retain: bool) {
-        // Collect bridges first to avoid holding lock across await
-        let bridges: Vec<_> = self.bridges.read().iter().cloned().collect();
+        self.forward_publish_from(topic, payload, qos, retain, None, &Properties::default());
+    }
 
-        for bridge in bridges {
-            if bridge.should_forward(topic) && bridge.status() == RemotePeerStatus::Connected {
-                if let Err(e) = bridge
-                    .forward_publish(topic, payload.clone(), qos, retain)
-                    .await
-                {
+    /// Forward a published message that may already have crossed bridges
+    /// to all matching bridges
+    pub fn forward_publish_from(
+        &self,
+        topic: &str,
+        payload: Bytes,
+        qos: QoS,
+        retain: bool,
+        origin: Option<&BridgeOrigin>,
+        properties: &Properties,
+    ) {
+        let span = info_span!(
+            target: crate::otel::SPAN_TARGET,
+            "bridge.forward",
+            topic = %topic,
+        );
+        crate::otel::continue_trace(&span, properties);
+        let _entered = span.enter();
+
+        for bridge in self.bridges.read().iter() {
+            // Bridges that are down queue the message until they reconnect
+            if bridge.should_forward(topic) {
+                if let Err(e) = bridge.forward_publish_from(
+                    topic,
+                    payload.clone(),
+                    qos,
+                    retain,
+                    origin,
+                    properties,
+                ) {
                     debug!("Bridge '{}': Forward failed: {}", bridge.name(), e);
                 }
             }
         }
     }
 
+    /// Forward a message to the bridge named `name`, if its outbound
+    /// forwards match the topic; returns false if there is no such bridge
+    pub fn forward_to(
+        &self,
+        name: &str,
+        topic: &str,
+        payload: Bytes,
+        qos: QoS,
+        retain: bool,
+    ) -> bool {
+        let bridges = self.bridges.read();
+        let Some(bridge) = bridges.iter().find(|b| b.name() == name) else {
+            return false;
+        };
+        if let Err(e) =
+            bridge.forward_publish_from(topic, payload, qos, retain, None, &Properties::default())
+        {
+            debug!("Bridge '{}': Forward failed: {}", bridge.name(), e);
+        }
+        true
+    }
+
     /// Check if any bridge wants to forward a topic
     pub fn should_forward(&self, topic: &str) -> bool {
         self.bridges.read().iter().any(|b| b.should_forward(topic))
@@ -98,6 +200,27 @@ impl BridgeManager {
             .collect()
     }
 
+    /// Get connection health and counters of all bridges
+    pub fn health(&self) -> Vec<BridgeStatus> {
+        self.bridges.read().iter().map(|b| b.health()).collect()
+    }
+
+    /// Update bridge health metrics
+    pub fn record_metrics(&self, metrics: &Metrics) {
+        for status in self.health() {
+            metrics.bridge_status(&status);
+        }
+    }
+
+    /// Write messages spooled since the bridges' spool tasks last ran to
+    /// the persistence backend, before it shuts down
+    pub async fn flush_spools(&self) {
+        let bridges: Vec<_> = self.bridges.read().iter().cloned().collect();
+        for bridge in bridges {
+            bridge.flush_spool().await;
+        }
+    }
+
     /// Start all bridges
     pub async fn start_all(&self) {
         // Collect bridges first to avoid holding lock across await
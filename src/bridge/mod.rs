@@ -7,7 +7,71 @@
 //!
 //! Bridges use multiple strategies to prevent message loops:
 //! - **no_local**: MQTT v5.0 subscription option that prevents receiving own messages
-//! - **User Property**: Tags messages with origin broker ID to detect loops
+//! - **User Property**: Tags messages with the origin ID of every bridge they
+//!   cross and a hop count, so loops and paths longer than `max_hops` are cut
+//!   in multi-level topologies
+//!
+//! # TLS
+//!
+//! `mqtts` and `wss` bridges verify the remote broker against `tls.ca_cert`
+//! (or the bundled Mozilla roots) and can present `tls.client_cert` /
+//! `tls.client_key` for mutual TLS, as AWS IoT Core and Azure IoT Hub require.
+//!
+//! # WebSocket
+//!
+//! `ws` and `wss` bridges tunnel MQTT through a WebSocket to `ws_path`, for
+//! brokers that are only exposed behind HTTP(S) load balancers.
+//!
+//! # Offline Buffering
+//!
+//! Outbound messages are queued while the remote broker is unreachable and
+//! replayed on reconnect: `queue_size` in memory, then up to
+//! `spool.max_messages` on disk with `spool.enabled`.
+//!
+//! # Kafka
+//!
+//! With the `kafka` feature, `protocol = "kafka"` produces outbound messages
+//! to the Kafka topics named in `remote_topic`, keyed by MQTT topic levels,
+//! and consumes the Kafka topics of inbound rules into MQTT.
+//!
+//! # NATS and AMQP
+//!
+//! With the `nats` or `amqp` feature, `protocol = "nats"` and
+//! `protocol = "amqp"` map remote topics to NATS subjects and to routing
+//! keys on the `amqp.exchange` topic exchange, with `/` as `.` and `+` as
+//! `*` (`#` is `>` on NATS).
+//!
+//! # Webhooks
+//!
+//! `protocol = "http"` or `"https"` sends outbound messages as requests to
+//! the bridge address, with the path and headers templated from each
+//! message and optional JSON batching. Failed requests are retried with
+//! the reconnect backoff.
+//!
+//! # Rate Limits and Transforms
+//!
+//! `rate_limit.messages_per_sec` and `rate_limit.bytes_per_sec` cap what a
+//! bridge sends; messages over the limit wait in the queue. A
+//! [`BridgeTransform`] set with [`BridgeManager::set_transform`] can rewrite
+//! or drop outbound messages before they are queued.
+//!
+//! # Event Export
+//!
+//! The broker events listed in `[export] events` are forwarded to the
+//! bridges as JSON messages on `$export/<event>`, for bridges with a
+//! forward rule for them to deliver; see [`EventExport`].
+//!
+//! # Health
+//!
+//! Each bridge reports its connection state, reconnects, forwarded and
+//! dropped messages and keep-alive round trip as `vibemq_bridge_*` metrics
+//! and at `/bridges` on the metrics server. Reconnects back off
+//! exponentially by `backoff_multiplier` with `reconnect_jitter`.
+//!
+//! Every SUBACK from the remote broker is checked: rejected subscriptions
+//! are retried with the same backoff and reported as unhealthy, and all
+//! subscriptions are renewed each `subscription_audit_interval` in case the
+//! remote broker lost them.
 //!
 //! # Example Configuration
 //!
@@ -19,6 +83,11 @@
 //! client_id = "edge-bridge-01"
 //! loop_prevention = "both"  # Uses no_local AND user property
 //!
+//! [bridge.tls]
+//! ca_cert = "/etc/vibemq/cloud-ca.pem"
+//! client_cert = "/etc/vibemq/edge01.crt"
+//! client_key = "/etc/vibemq/edge01.key"
+//!
 //! [[bridge.forwards]]
 //! local_topic = "sensors/#"
 //! remote_topic = "edge/device01/sensors/#"
@@ -26,20 +95,43 @@
 //! qos = 1
 //! ```
 
+#[cfg(feature = "amqp")]
+mod amqp;
+mod backoff;
 mod client;
+mod events;
+mod http;
+#[cfg(feature = "kafka")]
+mod kafka;
 mod manager;
+#[cfg(feature = "nats")]
+mod nats;
+mod origin;
+mod queue;
+mod rate_limit;
+mod session;
+mod stats;
+mod subscriptions;
+mod tls;
 mod topic_mapper;
+mod transform;
 
 #[cfg(test)]
 mod tests;
 
 pub use client::BridgeClient;
+pub use events::EventExport;
+pub(crate) use http::{HttpEndpoint, HttpSender};
 pub use manager::BridgeManager;
+pub use origin::{BridgeOrigin, BRIDGE_HOPS_PROPERTY};
+pub use stats::BridgeStatus;
+pub use subscriptions::{SubscriptionState, SubscriptionStatus};
 pub use topic_mapper::TopicMapper;
+pub use transform::{BridgeMessage, BridgeTransform};
 
 // Re-export config types from the config module for convenience
 pub use crate::config::{
-    BridgeConfig, BridgeProtocol, ForwardDirection, ForwardRule, LoopPrevention,
+    BridgeConfig, BridgeProtocol, ForwardDirection, ForwardRule, LoopPrevention, QosMode,
 };
 
 /// User property key for bridge origin tracking (loop prevention)
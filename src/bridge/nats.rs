@@ -0,0 +1,176 @@
+//! NATS Bridge
+//!
+//! Runs a bridge with `protocol = "nats"`. Remote topics map to NATS
+//! subjects level by level (`a/b/c` is `a.b.c`, `+` is `*`, `#` is `>`).
+//! Queued outbound messages are published to their subject, and inbound
+//! rules subscribe to the subjects of their remote filters. NATS core has
+//! no acknowledgements: QoS 1/2 publishes are flushed to the server before
+//! they count as forwarded.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_nats::{Client, ConnectOptions, Event};
+use futures_util::stream::SelectAll;
+use futures_util::StreamExt;
+use parking_lot::RwLock;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+
+use crate::config::BridgeConfig;
+use crate::protocol::{Properties, QoS};
+use crate::remote::{RemoteError, RemotePeerStatus};
+
+use super::client::{BridgeCommand, InboundCallback};
+use super::queue::BridgeQueue;
+use super::stats::BridgeStats;
+use super::topic_mapper::{from_dotted, to_dotted, TopicMapper};
+
+/// Maximum messages published before flushing
+const PUBLISH_BATCH: usize = 256;
+
+/// Connect to the NATS server and run until shutdown or an error
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn connect_and_run(
+    config: &BridgeConfig,
+    address: &str,
+    topic_mapper: &TopicMapper,
+    status: &Arc<RwLock<RemotePeerStatus>>,
+    queue: &BridgeQueue,
+    stats: &Arc<BridgeStats>,
+    command_rx: &mut mpsc::Receiver<BridgeCommand>,
+    inbound_callback: &Option<InboundCallback>,
+) -> Result<(), RemoteError> {
+    let mut options = ConnectOptions::new().name(&config.client_id);
+    if let (Some(user), Some(password)) = (&config.username, &config.password) {
+        options = options.user_and_password(user.clone(), password.clone());
+    }
+    if let Some(ref tls) = config.tls {
+        options = options.require_tls(true);
+        if let Some(ref ca_cert) = tls.ca_cert {
+            options = options.add_root_certificates(PathBuf::from(ca_cert));
+        }
+        if let (Some(cert), Some(key)) = (&tls.client_cert, &tls.client_key) {
+            options = options.add_client_certificate(PathBuf::from(cert), PathBuf::from(key));
+        }
+    }
+
+    // The client reconnects by itself; keep the bridge status in step
+    let name = config.name.clone();
+    let event_status = status.clone();
+    options = options.event_callback(move |event| {
+        let name = name.clone();
+        let status = event_status.clone();
+        async move {
+            match event {
+                Event::Connected => *status.write() = RemotePeerStatus::Connected,
+                Event::Disconnected => {
+                    warn!("Bridge '{}': NATS connection lost, reconnecting", name);
+                    *status.write() = RemotePeerStatus::Connecting;
+                }
+                _ => {}
+            }
+        }
+    });
+
+    let client = timeout(config.connect_timeout, options.connect(address))
+        .await
+        .map_err(|_| RemoteError::Timeout)?
+        .map_err(nats_error)?;
+
+    let mut subscriptions = SelectAll::new();
+    if inbound_callback.is_some() {
+        for (filter, _) in topic_mapper.inbound_filters() {
+            let subject = to_dotted(filter, ">");
+            subscriptions.push(
+                client
+                    .subscribe(subject.clone())
+                    .await
+                    .map_err(nats_error)?,
+            );
+            debug!("Bridge '{}': Subscribed to {}", config.name, subject);
+        }
+    }
+
+    info!("Bridge '{}': Connected to NATS at {}", config.name, address);
+    *status.write() = RemotePeerStatus::Connected;
+    stats.connected();
+
+    publish_queued(&client, queue, stats).await?;
+
+    loop {
+        tokio::select! {
+            Some(cmd) = command_rx.recv() => {
+                if let BridgeCommand::Shutdown = cmd {
+                    let _ = client.flush().await;
+                    return Ok(());
+                }
+            }
+
+            _ = queue.notified() => {
+                publish_queued(&client, queue, stats).await?;
+            }
+
+            Some(message) = subscriptions.next() => {
+                let topic = from_dotted(message.subject.as_str());
+                // NATS messages carry no QoS; the rule's QoS applies
+                let mapped = topic_mapper.map_inbound(&topic, QoS::ExactlyOnce, false);
+                if let (Some((local_topic, qos, retain)), Some(callback)) =
+                    (mapped, inbound_callback)
+                {
+                    callback(
+                        local_topic,
+                        message.payload,
+                        qos,
+                        retain,
+                        None,
+                        Properties::default(),
+                    );
+                    stats.forwarded_in();
+                }
+            }
+        }
+    }
+}
+
+/// Publish everything queued, flushing batches that contain QoS 1/2 messages
+async fn publish_queued(
+    client: &Client,
+    queue: &BridgeQueue,
+    stats: &BridgeStats,
+) -> Result<(), RemoteError> {
+    loop {
+        let batch = queue.pop_batch(PUBLISH_BATCH);
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        for (i, publish) in batch.iter().enumerate() {
+            let subject = to_dotted(&publish.topic, ">");
+            if let Err(e) = client.publish(subject, publish.payload.clone()).await {
+                queue.ack(batch[..i].iter().filter(|p| p.qos == QoS::AtMostOnce));
+                queue.requeue(batch[i..].to_vec());
+                return Err(nats_error(e));
+            }
+        }
+        if batch.iter().any(|p| p.qos != QoS::AtMostOnce) {
+            if let Err(e) = client.flush().await {
+                queue.ack(batch.iter().filter(|p| p.qos == QoS::AtMostOnce));
+                queue.requeue(
+                    batch
+                        .into_iter()
+                        .filter(|p| p.qos != QoS::AtMostOnce)
+                        .collect(),
+                );
+                return Err(nats_error(e));
+            }
+        }
+        queue.ack(&batch);
+        stats.forwarded_out(batch.len());
+    }
+}
+
+fn nats_error(e: impl std::fmt::Display) -> RemoteError {
+    RemoteError::ConnectionLost(format!("NATS: {}", e))
+}
@@ -0,0 +1,133 @@
+//! Bridge Origin Chain
+//!
+//! Loop prevention across multi-level bridge topologies (device -> site ->
+//! region -> cloud). With `loop_prevention = "user_property"` or `"both"`,
+//! a message sent over a bridge carries one `x-vibemq-origin` user property
+//! per bridge it has crossed, oldest first, and its hop count in
+//! `x-vibemq-hops`. A bridge does not forward a message whose chain already
+//! holds its origin id, nor one that has made `max_hops` hops, so loops are
+//! cut even when the rules of different levels do not mirror each other.
+
+use tracing::debug;
+
+use super::BRIDGE_ORIGIN_PROPERTY;
+
+/// User property key for the number of bridges a message has crossed
+pub const BRIDGE_HOPS_PROPERTY: &str = "x-vibemq-hops";
+
+/// Whether a user property belongs to the origin chain
+pub(crate) fn is_origin_property(key: &str) -> bool {
+    key == BRIDGE_ORIGIN_PROPERTY || key == BRIDGE_HOPS_PROPERTY
+}
+
+/// Bridges a message has crossed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BridgeOrigin {
+    /// Number of bridges crossed
+    pub hops: u32,
+    /// Origin ids of the bridges crossed, oldest first
+    pub chain: Vec<String>,
+}
+
+impl BridgeOrigin {
+    /// Read the origin chain from a publish's user properties; `None` if
+    /// the message has not crossed a bridge
+    pub fn from_user_properties(properties: &[(String, String)]) -> Option<Self> {
+        let mut origin = Self::default();
+        let mut hops = None;
+        for (key, value) in properties {
+            match key.as_str() {
+                BRIDGE_ORIGIN_PROPERTY => origin.chain.push(value.clone()),
+                BRIDGE_HOPS_PROPERTY => hops = value.parse().ok(),
+                _ => {}
+            }
+        }
+        if hops.is_none() && origin.chain.is_empty() {
+            return None;
+        }
+        // A broker that drops or rewrites the count cannot hide the chain
+        origin.hops = hops.unwrap_or(0).max(origin.chain.len() as u32);
+        Some(origin)
+    }
+
+    /// Check if the message has already crossed a bridge with this origin id
+    pub fn contains(&self, origin_id: &str) -> bool {
+        self.chain.iter().any(|id| id == origin_id)
+    }
+
+    /// The chain after crossing the bridge `origin_id`, or `None` if that
+    /// would close a loop or exceed `max_hops`
+    pub fn next_hop(origin: Option<&Self>, origin_id: &str, max_hops: u32) -> Option<Self> {
+        let mut next = origin.cloned().unwrap_or_default();
+        if next.contains(origin_id) {
+            debug!(
+                "Bridge origin '{}' already in chain {:?}, not forwarding",
+                origin_id, next.chain
+            );
+            return None;
+        }
+        if next.hops >= max_hops {
+            debug!(
+                "Message made {} bridge hops (max {}), not forwarding",
+                next.hops, max_hops
+            );
+            return None;
+        }
+        next.hops += 1;
+        next.chain.push(origin_id.to_string());
+        Some(next)
+    }
+
+    /// User properties carrying the chain
+    pub fn to_user_properties(&self) -> Vec<(String, String)> {
+        self.chain
+            .iter()
+            .map(|id| (BRIDGE_ORIGIN_PROPERTY.to_string(), id.clone()))
+            .chain(std::iter::once((
+                BRIDGE_HOPS_PROPERTY.to_string(),
+                self.hops.to_string(),
+            )))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_origin_chain_round_trip() {
+        assert_eq!(
+            BridgeOrigin::from_user_properties(&[("app".to_string(), "x".to_string())]),
+            None
+        );
+
+        let first = BridgeOrigin::next_hop(None, "device", 3).unwrap();
+        let second = BridgeOrigin::next_hop(Some(&first), "site", 3).unwrap();
+        assert_eq!(second.hops, 2);
+        assert_eq!(second.chain, vec!["device", "site"]);
+
+        let parsed = BridgeOrigin::from_user_properties(&second.to_user_properties()).unwrap();
+        assert_eq!(parsed, second);
+    }
+
+    #[test]
+    fn test_next_hop_stops_loops_and_long_paths() {
+        let origin = BridgeOrigin {
+            hops: 2,
+            chain: vec!["device".to_string(), "site".to_string()],
+        };
+        assert!(BridgeOrigin::next_hop(Some(&origin), "device", 8).is_none());
+        assert!(BridgeOrigin::next_hop(Some(&origin), "region", 2).is_none());
+        assert!(BridgeOrigin::next_hop(Some(&origin), "region", 3).is_some());
+
+        // The hop count never reads lower than the chain
+        let properties = vec![
+            (BRIDGE_ORIGIN_PROPERTY.to_string(), "a".to_string()),
+            (BRIDGE_ORIGIN_PROPERTY.to_string(), "b".to_string()),
+            (BRIDGE_HOPS_PROPERTY.to_string(), "0".to_string()),
+        ];
+        let parsed = BridgeOrigin::from_user_properties(&properties).unwrap();
+        assert_eq!(parsed.hops, 2);
+    }
+}
@@ -0,0 +1,739 @@
+//! Bridge Outbound Queue
+//!
+//! Buffers publishes for the remote broker while it is unreachable and
+//! replays them in order once the bridge reconnects. Up to `queue_size`
+//! messages are held in memory; with a spool, further messages collect in
+//! segments that a background task writes to the persistence backend and
+//! reads back as the memory queue drains. A segment stays in the backend
+//! until the remote broker has acknowledged all of its messages, so spooled
+//! messages survive a restart. When both are full, new messages are
+//! dropped. Messages over the bridge's rate limit stay queued until the
+//! limit lets them go.
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+use tracing::{debug, warn};
+
+use crate::config::BridgeRateLimitConfig;
+use crate::persistence::{
+    PersistenceError, PersistenceManager, PersistenceOp, StoredPendingMessage, StoredProperties,
+    StoredPublish, StoredSpillSegment,
+};
+use crate::protocol::{Properties, QoS};
+
+use super::origin::{is_origin_property, BridgeOrigin};
+use super::rate_limit::RateLimiter;
+
+/// Messages per spool segment
+const SEGMENT_SIZE: usize = 256;
+
+/// How often spooled messages are written to the backend
+const SPOOL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A publish waiting to be sent to the remote broker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct QueuedPublish {
+    pub(crate) topic: String,
+    pub(crate) payload: Bytes,
+    pub(crate) qos: QoS,
+    pub(crate) retain: bool,
+    /// Bridges crossed, including this one (user property loop prevention)
+    pub(crate) origin: Option<BridgeOrigin>,
+    /// User properties of the publish, as left by a transform hook (MQTT bridges)
+    pub(crate) user_properties: Vec<(String, String)>,
+    /// Response Topic of a request (MQTT bridges)
+    pub(crate) response_topic: Option<String>,
+    /// Correlation Data of a request or response (MQTT bridges)
+    pub(crate) correlation_data: Option<Bytes>,
+    /// Spool segment the message was read back from
+    pub(crate) segment: Option<u64>,
+}
+
+impl QueuedPublish {
+    /// Properties of the publish sent to the remote broker
+    pub(crate) fn properties(&self) -> Properties {
+        let mut user_properties = self
+            .origin
+            .as_ref()
+            .map(BridgeOrigin::to_user_properties)
+            .unwrap_or_default();
+        user_properties.extend(self.user_properties.iter().cloned());
+        Properties {
+            user_properties,
+            response_topic: self.response_topic.clone(),
+            correlation_data: self.correlation_data.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&QueuedPublish> for StoredPendingMessage {
+    fn from(p: &QueuedPublish) -> Self {
+        Self {
+            publish: StoredPublish {
+                topic: p.topic.clone(),
+                payload: p.payload.to_vec(),
+                qos: p.qos as u8,
+                retain: p.retain,
+                dup: false,
+                packet_id: None,
+                properties: StoredProperties::from(&p.properties()),
+            },
+            queued_at_secs: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+}
+
+impl From<StoredPendingMessage> for QueuedPublish {
+    fn from(m: StoredPendingMessage) -> Self {
+        let StoredPublish {
+            topic,
+            payload,
+            qos,
+            retain,
+            properties,
+            ..
+        } = m.publish;
+        let origin = BridgeOrigin::from_user_properties(&properties.user_properties);
+        Self {
+            topic,
+            payload: Bytes::from(payload),
+            qos: QoS::from_u8(qos).unwrap_or(QoS::AtMostOnce),
+            retain,
+            origin,
+            user_properties: properties
+                .user_properties
+                .into_iter()
+                .filter(|(key, _)| !is_origin_property(key))
+                .collect(),
+            response_topic: properties.response_topic,
+            correlation_data: properties.correlation_data.map(Bytes::from),
+            segment: None,
+        }
+    }
+}
+
+/// Where the messages of a spool segment currently are
+enum SegmentState {
+    /// In memory, taking newly spooled messages if it is the last segment
+    Open(Vec<QueuedPublish>),
+    /// In memory until the write to the backend is confirmed
+    Writing(Vec<QueuedPublish>),
+    /// In the backend only
+    Stored,
+    /// Being read back from the backend
+    Loading,
+}
+
+/// A run of spooled messages
+struct Segment {
+    seq: u64,
+    count: usize,
+    state: SegmentState,
+}
+
+/// Overflow of the queue into the persistence backend
+///
+/// Segments are keyed `bridge/<name>/<seq>-<count>`, so the spool can be
+/// rebuilt from the keys alone after a restart.
+struct Spool {
+    persistence: Arc<PersistenceManager>,
+    /// Key prefix of the bridge's segments
+    prefix: String,
+    capacity: usize,
+    /// Spooled segments, oldest first
+    segments: VecDeque<Segment>,
+    /// Messages in `segments`
+    len: usize,
+    next_seq: u64,
+    /// Segments read back into memory: key and messages not yet acknowledged
+    unacked: HashMap<u64, (String, usize)>,
+    /// Segments left by a previous run are not listed yet
+    recovering: bool,
+}
+
+impl Spool {
+    fn key(&self, seq: u64, count: usize) -> String {
+        format!("{}{:016x}-{:x}", self.prefix, seq, count)
+    }
+
+    /// Sequence number and message count of a segment key
+    fn parse_key(&self, key: &str) -> Option<(u64, usize)> {
+        let (seq, count) = key.strip_prefix(&self.prefix)?.split_once('-')?;
+        Some((
+            u64::from_str_radix(seq, 16).ok()?,
+            usize::from_str_radix(count, 16).ok()?,
+        ))
+    }
+
+    fn push(&mut self, publish: QueuedPublish) -> bool {
+        if self.len >= self.capacity {
+            return false;
+        }
+        match self.segments.back_mut() {
+            Some(Segment {
+                count,
+                state: SegmentState::Open(messages),
+                ..
+            }) if messages.len() < SEGMENT_SIZE => {
+                messages.push(publish);
+                *count += 1;
+            }
+            _ => {
+                self.segments.push_back(Segment {
+                    seq: self.next_seq,
+                    count: 1,
+                    state: SegmentState::Open(vec![publish]),
+                });
+                self.next_seq += 1;
+            }
+        }
+        self.len += 1;
+        true
+    }
+
+    /// Whether the last segment is full and waits to be written
+    fn tail_full(&self) -> bool {
+        matches!(
+            self.segments.back(),
+            Some(Segment { state: SegmentState::Open(messages), .. }) if messages.len() >= SEGMENT_SIZE
+        )
+    }
+
+    /// Put segments left by a previous run ahead of those spooled since
+    fn recovered(&mut self, keys: &[String]) {
+        let mut recovered: Vec<(u64, usize)> =
+            keys.iter().filter_map(|key| self.parse_key(key)).collect();
+        recovered.sort_unstable();
+
+        // Segments spooled meanwhile are all still in memory, so they can
+        // be numbered after the recovered ones
+        self.next_seq = recovered.last().map_or(0, |(seq, _)| seq + 1);
+        for segment in &mut self.segments {
+            segment.seq = self.next_seq;
+            self.next_seq += 1;
+        }
+        for &(seq, count) in recovered.iter().rev() {
+            self.segments.push_front(Segment {
+                seq,
+                count,
+                state: SegmentState::Stored,
+            });
+            self.len += count;
+        }
+        self.recovering = false;
+    }
+
+    /// Take the oldest segment if it was never written to the backend
+    fn take_unwritten(&mut self) -> Option<Vec<QueuedPublish>> {
+        if self.recovering {
+            return None;
+        }
+        let segment = self.segments.front_mut()?;
+        let SegmentState::Open(messages) = &mut segment.state else {
+            return None;
+        };
+        let messages = std::mem::take(messages);
+        self.len -= segment.count;
+        self.segments.pop_front();
+        Some(messages)
+    }
+
+    /// Whether the oldest segment waits to be read back
+    fn head_stored(&self) -> bool {
+        self.segments
+            .front()
+            .is_some_and(|s| matches!(s.state, SegmentState::Stored))
+    }
+
+    /// Messages of a segment were acknowledged; delete it once all were
+    fn ack(&mut self, seq: u64) {
+        if let Entry::Occupied(mut entry) = self.unacked.entry(seq) {
+            entry.get_mut().1 = entry.get().1.saturating_sub(1);
+            if entry.get().1 == 0 {
+                let (key, _) = entry.remove();
+                self.persistence.write(PersistenceOp::DeleteSpill { key });
+            }
+        }
+    }
+}
+
+struct Inner {
+    memory: VecDeque<QueuedPublish>,
+    spool: Option<Spool>,
+    limiter: Option<RateLimiter>,
+    /// When the rate limit lets the next held back message go
+    resume_at: Option<Instant>,
+}
+
+/// Outbound publish queue of one bridge
+pub(crate) struct BridgeQueue {
+    inner: Mutex<Inner>,
+    capacity: usize,
+    /// Wakes the connection task when messages are queued
+    notify: Notify,
+    /// Wakes the spool task when a segment is full or should be read back
+    spool_notify: Arc<Notify>,
+    /// Messages dropped because the queue was full
+    dropped: AtomicU64,
+}
+
+impl BridgeQueue {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                memory: VecDeque::new(),
+                spool: None,
+                limiter: None,
+                resume_at: None,
+            }),
+            capacity: capacity.max(1),
+            notify: Notify::new(),
+            spool_notify: Arc::new(Notify::new()),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Spill messages beyond the memory capacity to the persistence backend
+    ///
+    /// Nothing is written until the spool task runs, see `spawn_spool`.
+    pub(crate) fn with_spool(
+        self,
+        persistence: Arc<PersistenceManager>,
+        bridge: &str,
+        capacity: usize,
+    ) -> Self {
+        self.inner.lock().spool = Some(Spool {
+            persistence,
+            prefix: format!("bridge/{}/", bridge),
+            capacity: capacity.max(1),
+            segments: VecDeque::new(),
+            len: 0,
+            next_seq: 0,
+            unacked: HashMap::new(),
+            recovering: true,
+        });
+        self
+    }
+
+    /// Hold back messages over the configured rates
+    pub(crate) fn with_rate_limit(self, config: &BridgeRateLimitConfig) -> Self {
+        self.inner.lock().limiter = RateLimiter::new(config);
+        self
+    }
+
+    /// Start the task that writes spooled segments to the backend and reads
+    /// them back, after picking up those left by a previous run
+    pub(crate) fn spawn_spool(self: &Arc<Self>) {
+        if self.inner.lock().spool.is_none() {
+            return;
+        }
+        let queue = Arc::downgrade(self);
+        let notify = self.spool_notify.clone();
+        tokio::spawn(async move {
+            let Some(q) = queue.upgrade() else {
+                return;
+            };
+            q.recover().await;
+            drop(q);
+
+            let mut interval = tokio::time::interval(SPOOL_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = notify.notified() => {}
+                }
+                let Some(q) = queue.upgrade() else {
+                    return;
+                };
+                q.write_spool().await;
+                q.load_spool().await;
+            }
+        });
+    }
+
+    /// List the segments a previous run left in the backend
+    async fn recover(&self) {
+        let (persistence, prefix) = match self.inner.lock().spool {
+            Some(ref spool) => (spool.persistence.clone(), spool.prefix.clone()),
+            None => return,
+        };
+        let keys = persistence.list_spills(&prefix).await.unwrap_or_else(|e| {
+            warn!("Failed to list spooled bridge messages: {}", e);
+            Vec::new()
+        });
+        if let Some(spool) = self.inner.lock().spool.as_mut() {
+            spool.recovered(&keys);
+            if !keys.is_empty() {
+                debug!("Recovered {} spooled bridge segments", keys.len());
+            }
+        }
+        self.notify.notify_one();
+    }
+
+    /// Write the segments still held in memory to the backend
+    pub(crate) async fn write_spool(&self) {
+        let (persistence, ops, seqs) = {
+            let mut inner = self.inner.lock();
+            let Some(spool) = inner.spool.as_mut() else {
+                return;
+            };
+            let mut ops = Vec::new();
+            let mut seqs = Vec::new();
+            for i in 0..spool.segments.len() {
+                let segment = &mut spool.segments[i];
+                let SegmentState::Open(messages) = &mut segment.state else {
+                    continue;
+                };
+                let messages = std::mem::take(messages);
+                let (seq, count) = (segment.seq, segment.count);
+                ops.push(PersistenceOp::SetSpill {
+                    key: spool.key(seq, count),
+                    segment: StoredSpillSegment {
+                        messages: messages.iter().map(StoredPendingMessage::from).collect(),
+                    },
+                });
+                spool.segments[i].state = SegmentState::Writing(messages);
+                seqs.push(seq);
+            }
+            (spool.persistence.clone(), ops, seqs)
+        };
+        if ops.is_empty() {
+            return;
+        }
+
+        let result = persistence.write_sync(ops).await;
+        if let Err(ref e) = result {
+            warn!("Failed to spool bridge messages: {}", e);
+        }
+        let mut inner = self.inner.lock();
+        let Some(spool) = inner.spool.as_mut() else {
+            return;
+        };
+        for segment in spool.segments.iter_mut() {
+            if !seqs.contains(&segment.seq) {
+                continue;
+            }
+            // Kept in memory to retry on the next round if the write failed
+            segment.state = match std::mem::replace(&mut segment.state, SegmentState::Stored) {
+                SegmentState::Writing(messages) if result.is_err() => SegmentState::Open(messages),
+                _ => SegmentState::Stored,
+            };
+        }
+    }
+
+    /// Read stored segments back while the memory queue has room for them
+    async fn load_spool(&self) {
+        loop {
+            let (persistence, key, seq) = {
+                let mut inner = self.inner.lock();
+                let Inner { memory, spool, .. } = &mut *inner;
+                let Some(spool) = spool.as_mut() else {
+                    return;
+                };
+                if !spool.head_stored() {
+                    return;
+                }
+                let segment = &spool.segments[0];
+                if !memory.is_empty() && memory.len() + segment.count > self.capacity {
+                    return;
+                }
+                let (seq, count) = (segment.seq, segment.count);
+                spool.segments[0].state = SegmentState::Loading;
+                (spool.persistence.clone(), spool.key(seq, count), seq)
+            };
+
+            let messages = match persistence.get_spill(&key).await {
+                Ok(Some(segment)) => segment.messages,
+                Ok(None) => {
+                    warn!("Spooled bridge segment {} is missing", key);
+                    Vec::new()
+                }
+                Err(e @ (PersistenceError::Deserialize(_) | PersistenceError::Corruption(_))) => {
+                    warn!("Skipping corrupt spooled bridge segment {}: {}", key, e);
+                    persistence.write(PersistenceOp::DeleteSpill { key: key.clone() });
+                    Vec::new()
+                }
+                Err(e) => {
+                    // Retried on the next round
+                    warn!("Failed to read spooled bridge segment {}: {}", key, e);
+                    if let Some(spool) = self.inner.lock().spool.as_mut() {
+                        spool.segments[0].state = SegmentState::Stored;
+                    }
+                    return;
+                }
+            };
+
+            {
+                let mut inner = self.inner.lock();
+                let Inner { memory, spool, .. } = &mut *inner;
+                let Some(spool) = spool.as_mut() else {
+                    return;
+                };
+                if let Some(segment) = spool.segments.pop_front() {
+                    spool.len -= segment.count;
+                }
+                if !messages.is_empty() {
+                    spool.unacked.insert(seq, (key, messages.len()));
+                }
+                memory.extend(messages.into_iter().map(|message| QueuedPublish {
+                    segment: Some(seq),
+                    ..QueuedPublish::from(message)
+                }));
+            }
+            self.notify.notify_one();
+        }
+    }
+
+    /// Queue a message, returning false if it was dropped
+    pub(crate) fn push(&self, publish: QueuedPublish) -> bool {
+        let accepted = {
+            let mut inner = self.inner.lock();
+            let Inner { memory, spool, .. } = &mut *inner;
+            // Once messages are spooled, newer ones must queue behind them
+            let spooled = spool.as_ref().is_some_and(|s| s.len > 0 || s.recovering);
+            if !spooled && memory.len() < self.capacity {
+                memory.push_back(publish);
+                true
+            } else if let Some(spool) = spool {
+                let accepted = spool.push(publish);
+                if spool.tail_full() {
+                    self.spool_notify.notify_one();
+                }
+                accepted
+            } else {
+                false
+            }
+        };
+        if !accepted {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        self.notify.notify_one();
+        accepted
+    }
+
+    /// Put back messages whose write failed, so they are retried first
+    pub(crate) fn requeue(&self, publishes: Vec<QueuedPublish>) {
+        let mut inner = self.inner.lock();
+        for publish in publishes.into_iter().rev() {
+            inner.memory.push_front(publish);
+        }
+    }
+
+    /// Record that the remote broker has acknowledged messages, or that they
+    /// were given up on; spool segments are deleted once all of their
+    /// messages are
+    pub(crate) fn ack<'a>(&self, publishes: impl IntoIterator<Item = &'a QueuedPublish>) {
+        let mut inner = self.inner.lock();
+        let Some(spool) = inner.spool.as_mut() else {
+            return;
+        };
+        for seq in publishes.into_iter().filter_map(|p| p.segment) {
+            spool.ack(seq);
+        }
+    }
+
+    /// Take up to `max` messages to send, oldest first, within the rate
+    /// limit
+    pub(crate) fn pop_batch(&self, max: usize) -> Vec<QueuedPublish> {
+        let mut inner = self.inner.lock();
+        let Inner {
+            memory,
+            spool,
+            limiter,
+            resume_at,
+        } = &mut *inner;
+        if let Some(spool) = spool {
+            if memory.is_empty() {
+                if let Some(publishes) = spool.take_unwritten() {
+                    memory.extend(publishes);
+                }
+            }
+            if spool.head_stored() {
+                self.spool_notify.notify_one();
+            }
+        }
+        let mut n = memory.len().min(max);
+        if let Some(limiter) = limiter {
+            let now = Instant::now();
+            *resume_at = None;
+            for (i, publish) in memory.iter().take(n).enumerate() {
+                if let Err(wait) = limiter.acquire(publish.payload.len(), now) {
+                    *resume_at = Some(now + wait);
+                    n = i;
+                    break;
+                }
+            }
+        }
+        memory.drain(..n).collect()
+    }
+
+    /// Wait until a message is queued, or until the rate limit lets held
+    /// back messages go
+    pub(crate) async fn notified(&self) {
+        let resume_at = self.inner.lock().resume_at;
+        match resume_at {
+            Some(at) => tokio::time::sleep_until(at.into()).await,
+            None => self.notify.notified().await,
+        }
+    }
+
+    /// Messages held in memory and in the spool
+    pub(crate) fn depth(&self) -> (usize, usize) {
+        let inner = self.inner.lock();
+        (
+            inner.memory.len(),
+            inner.spool.as_ref().map_or(0, |s| s.len),
+        )
+    }
+
+    /// Total messages dropped because the queue was full
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SyncMode;
+    use crate::persistence::MemoryBackend;
+
+    fn publish(n: usize) -> QueuedPublish {
+        QueuedPublish {
+            topic: format!("sensors/{}", n),
+            payload: Bytes::from(n.to_string()),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            origin: None,
+            user_properties: Vec::new(),
+            response_topic: None,
+            correlation_data: None,
+            segment: None,
+        }
+    }
+
+    fn drain(queue: &BridgeQueue) -> Vec<String> {
+        let mut topics = Vec::new();
+        loop {
+            let batch = queue.pop_batch(10);
+            if batch.is_empty() {
+                return topics;
+            }
+            topics.extend(batch.into_iter().map(|p| p.topic));
+        }
+    }
+
+    #[test]
+    fn test_memory_queue_drops_when_full() {
+        let queue = BridgeQueue::new(2);
+        assert!(queue.push(publish(1)));
+        assert!(queue.push(publish(2)));
+        assert!(!queue.push(publish(3)));
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(drain(&queue), vec!["sensors/1", "sensors/2"]);
+    }
+
+    #[tokio::test]
+    async fn test_spool_deletes_acknowledged_segments_and_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = MemoryBackend::open(dir.path(), Duration::ZERO).unwrap();
+        let persistence = Arc::new(PersistenceManager::new(
+            Arc::new(backend),
+            Duration::from_millis(10),
+            100,
+            SyncMode::Never,
+        ));
+        let spooled = || async { persistence.list_spills("bridge/cloud/").await.unwrap() };
+
+        let queue = BridgeQueue::new(2).with_spool(persistence.clone(), "cloud", 3);
+        queue.recover().await;
+        for n in 1..=6 {
+            queue.push(publish(n));
+        }
+        assert_eq!(queue.depth(), (2, 3));
+        assert_eq!(queue.dropped(), 1);
+        queue.write_spool().await;
+        assert_eq!(spooled().await.len(), 1);
+
+        // Memory drains first, then the spool refills it
+        let first = queue.pop_batch(3);
+        assert_eq!(first.len(), 2);
+        assert_eq!(first[0], publish(1));
+        queue.requeue(first);
+        assert_eq!(drain(&queue), vec!["sensors/1", "sensors/2"]);
+        queue.load_spool().await;
+        assert_eq!(queue.depth(), (3, 0));
+
+        // Read back, but only partly acknowledged: still in the backend
+        let batch = queue.pop_batch(2);
+        assert_eq!(batch[0].topic, "sensors/3");
+        queue.ack(&batch);
+        persistence.write_sync(Vec::new()).await.unwrap();
+        assert_eq!(spooled().await.len(), 1);
+
+        // A restart replays the whole unacknowledged segment, ahead of
+        // messages spooled while the segments are listed
+        let queue = BridgeQueue::new(2).with_spool(persistence.clone(), "cloud", 3);
+        assert!(queue.push(publish(7)));
+        queue.recover().await;
+        assert_eq!(queue.depth(), (0, 4));
+        queue.write_spool().await;
+        queue.load_spool().await;
+        let batch = queue.pop_batch(10);
+        assert_eq!(
+            batch.iter().map(|p| p.topic.as_str()).collect::<Vec<_>>(),
+            vec!["sensors/3", "sensors/4", "sensors/5"]
+        );
+        queue.ack(&batch);
+        queue.load_spool().await;
+        let last = queue.pop_batch(10);
+        assert_eq!(last[0].topic, "sensors/7");
+        queue.ack(&last);
+        persistence.write_sync(Vec::new()).await.unwrap();
+        assert!(spooled().await.is_empty());
+    }
+
+    #[test]
+    fn test_rate_limit_holds_messages_back() {
+        let queue = BridgeQueue::new(10).with_rate_limit(&BridgeRateLimitConfig {
+            messages_per_sec: 2,
+            bytes_per_sec: 0,
+        });
+        for n in 1..=3 {
+            queue.push(publish(n));
+        }
+        assert_eq!(queue.pop_batch(10).len(), 2);
+        assert!(queue.pop_batch(10).is_empty());
+        assert_eq!(queue.depth(), (1, 0));
+        assert!(queue.inner.lock().resume_at.is_some());
+    }
+
+    #[test]
+    fn test_spooled_form_round_trip() {
+        let mut tagged = publish(1);
+        tagged.origin = Some(BridgeOrigin {
+            hops: 2,
+            chain: vec!["device".to_string(), "site".to_string()],
+        });
+        tagged.user_properties = vec![("site".to_string(), "12".to_string())];
+        tagged.response_topic = Some("replies/1".to_string());
+        tagged.correlation_data = Some(Bytes::from_static(b"req-1"));
+        let stored = StoredPendingMessage::from(&tagged);
+        assert_eq!(QueuedPublish::from(stored), tagged);
+        assert_eq!(
+            QueuedPublish::from(StoredPendingMessage::from(&publish(2))),
+            publish(2)
+        );
+    }
+}
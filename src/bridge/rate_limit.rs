@@ -0,0 +1,128 @@
+//! Bridge Rate Limiting
+//!
+//! Token buckets for the outbound message and byte rates of one bridge.
+//! Each bucket holds one second's worth of tokens, so short bursts go out
+//! at once and longer ones are spread to the configured rate.
+
+use std::time::{Duration, Instant};
+
+use crate::config::BridgeRateLimitConfig;
+
+#[derive(Debug)]
+struct Bucket {
+    /// Tokens added per second, also the bucket's capacity
+    rate: f64,
+    /// May drop below zero after a message larger than the capacity
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: f64, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: rate,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.updated = now;
+    }
+
+    /// Time until `cost` tokens are available; a cost above the capacity
+    /// only waits for a full bucket
+    fn wait(&self, cost: f64) -> Duration {
+        let needed = cost.min(self.rate);
+        if self.tokens >= needed {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((needed - self.tokens) / self.rate)
+        }
+    }
+}
+
+/// Outbound rate limits of one bridge
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    messages: Option<Bucket>,
+    bytes: Option<Bucket>,
+}
+
+impl RateLimiter {
+    /// A limiter for the configured rates, or `None` if unlimited
+    pub(crate) fn new(config: &BridgeRateLimitConfig) -> Option<Self> {
+        if !config.is_limited() {
+            return None;
+        }
+        let now = Instant::now();
+        let bucket = |rate: u64| (rate > 0).then(|| Bucket::new(rate as f64, now));
+        Some(Self {
+            messages: bucket(config.messages_per_sec.into()),
+            bytes: bucket(config.bytes_per_sec),
+        })
+    }
+
+    /// Take the tokens for a message with a payload of `size` bytes, or
+    /// return how long to wait until it may be sent
+    pub(crate) fn acquire(&mut self, size: usize, now: Instant) -> Result<(), Duration> {
+        let mut wait = Duration::ZERO;
+        for (bucket, cost) in self.buckets(size) {
+            bucket.refill(now);
+            wait = wait.max(bucket.wait(cost));
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        for (bucket, cost) in self.buckets(size) {
+            bucket.tokens -= cost;
+        }
+        Ok(())
+    }
+
+    fn buckets(&mut self, size: usize) -> impl Iterator<Item = (&mut Bucket, f64)> + '_ {
+        let messages = self.messages.as_mut().map(|b| (b, 1.0));
+        let bytes = self.bytes.as_mut().map(|b| (b, size as f64));
+        messages.into_iter().chain(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_rate() {
+        let config = BridgeRateLimitConfig {
+            messages_per_sec: 2,
+            bytes_per_sec: 0,
+        };
+        let mut limiter = RateLimiter::new(&config).unwrap();
+        let now = limiter.messages.as_ref().unwrap().updated;
+
+        assert!(limiter.acquire(100, now).is_ok());
+        assert!(limiter.acquire(100, now).is_ok());
+        assert_eq!(limiter.acquire(100, now), Err(Duration::from_millis(500)));
+        assert!(limiter
+            .acquire(100, now + Duration::from_millis(500))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_byte_rate_and_oversized_messages() {
+        let config = BridgeRateLimitConfig {
+            messages_per_sec: 0,
+            bytes_per_sec: 1024,
+        };
+        assert!(RateLimiter::new(&BridgeRateLimitConfig::default()).is_none());
+        let mut limiter = RateLimiter::new(&config).unwrap();
+        let now = limiter.bytes.as_ref().unwrap().updated;
+
+        // Larger than the burst: sent on a full bucket, then paid back
+        assert!(limiter.acquire(3072, now).is_ok());
+        assert_eq!(limiter.acquire(1024, now), Err(Duration::from_secs(3)));
+        assert!(limiter.acquire(1024, now + Duration::from_secs(3)).is_ok());
+    }
+}
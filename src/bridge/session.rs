@@ -0,0 +1,233 @@
+//! Bridge Session State
+//!
+//! Packet identifier state of the bridge's MQTT session with the remote
+//! broker, on both legs: QoS 1/2 publishes sent to the remote broker that
+//! await PUBACK or PUBREC/PUBCOMP, and QoS 2 publishes received from it that
+//! await PUBREL. The state outlives a single connection so QoS 2 flows
+//! resume when the remote broker still has the session.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::protocol::QoS;
+
+use super::queue::QueuedPublish;
+
+/// A publish sent to the remote broker and not yet acknowledged
+#[derive(Debug, Clone)]
+struct Inflight {
+    publish: QueuedPublish,
+    /// PUBREC received and PUBREL sent (QoS 2)
+    released: bool,
+    /// Send order, for resending in the original order
+    seq: u64,
+}
+
+/// What to resend for an unacknowledged publish after reconnecting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Resend {
+    /// PUBLISH again with the DUP flag
+    Publish(u16, QueuedPublish),
+    /// PUBREL again (the remote broker already has the message)
+    Release(u16),
+}
+
+/// Session state of a bridge connection
+#[derive(Debug)]
+pub(crate) struct BridgeSession {
+    outbound: HashMap<u16, Inflight>,
+    /// QoS 2 publishes received and delivered locally, awaiting PUBREL
+    incoming: HashSet<u16>,
+    next_packet_id: u16,
+    next_seq: u64,
+    /// Maximum unacknowledged outbound publishes (remote Receive Maximum)
+    limit: usize,
+}
+
+impl Default for BridgeSession {
+    fn default() -> Self {
+        Self {
+            outbound: HashMap::new(),
+            incoming: HashSet::new(),
+            next_packet_id: 1,
+            next_seq: 0,
+            limit: u16::MAX as usize,
+        }
+    }
+}
+
+impl BridgeSession {
+    /// Start a connection with the remote broker's Receive Maximum
+    ///
+    /// Without a resumed session, unacknowledged outbound publishes are
+    /// returned so they can be queued again, and received QoS 2 state is
+    /// dropped along with the remote session.
+    pub(crate) fn connected(
+        &mut self,
+        session_present: bool,
+        receive_maximum: Option<u16>,
+    ) -> Vec<QueuedPublish> {
+        self.limit = receive_maximum.unwrap_or(u16::MAX).max(1) as usize;
+        if session_present {
+            return Vec::new();
+        }
+        self.incoming.clear();
+        let mut unacked: Vec<Inflight> = self.outbound.drain().map(|(_, i)| i).collect();
+        unacked.sort_by_key(|i| i.seq);
+        unacked.into_iter().map(|i| i.publish).collect()
+    }
+
+    /// Unacknowledged publishes to resend on a resumed session, in send order
+    pub(crate) fn resend(&self) -> Vec<Resend> {
+        let mut inflight: Vec<(&u16, &Inflight)> = self.outbound.iter().collect();
+        inflight.sort_by_key(|(_, i)| i.seq);
+        inflight
+            .into_iter()
+            .map(|(&id, i)| {
+                if i.released {
+                    Resend::Release(id)
+                } else {
+                    Resend::Publish(id, i.publish.clone())
+                }
+            })
+            .collect()
+    }
+
+    /// How many more QoS 1/2 publishes may be sent before acknowledgements
+    pub(crate) fn room(&self) -> usize {
+        self.limit.saturating_sub(self.outbound.len())
+    }
+
+    /// Track an outbound publish, returning its packet identifier
+    ///
+    /// QoS 0 publishes are not tracked and get no identifier.
+    pub(crate) fn send(&mut self, publish: &QueuedPublish) -> Option<u16> {
+        if publish.qos == QoS::AtMostOnce {
+            return None;
+        }
+        let id = self.next_packet_id();
+        self.outbound.insert(
+            id,
+            Inflight {
+                publish: publish.clone(),
+                released: false,
+                seq: self.next_seq,
+            },
+        );
+        self.next_seq += 1;
+        Some(id)
+    }
+
+    /// Allocate a packet identifier not used by an unacknowledged publish
+    pub(crate) fn next_packet_id(&mut self) -> u16 {
+        loop {
+            let id = self.next_packet_id;
+            self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+            if !self.outbound.contains_key(&id) {
+                return id;
+            }
+        }
+    }
+
+    /// PUBACK (QoS 1) or PUBCOMP (QoS 2) received: the flow is complete
+    ///
+    /// Returns the acknowledged publish, if it was unacknowledged.
+    pub(crate) fn complete(&mut self, packet_id: u16) -> Option<QueuedPublish> {
+        self.outbound.remove(&packet_id).map(|i| i.publish)
+    }
+
+    /// PUBREC received: the remote broker has the message, PUBREL follows
+    pub(crate) fn release(&mut self, packet_id: u16) {
+        if let Some(inflight) = self.outbound.get_mut(&packet_id) {
+            inflight.released = true;
+        }
+    }
+
+    /// QoS 2 publish received; returns false if it is a redelivery that
+    /// was already delivered locally
+    pub(crate) fn receive(&mut self, packet_id: u16) -> bool {
+        self.incoming.insert(packet_id)
+    }
+
+    /// PUBREL received for a QoS 2 publish
+    pub(crate) fn received_release(&mut self, packet_id: u16) {
+        self.incoming.remove(&packet_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    fn publish(topic: &str, qos: QoS) -> QueuedPublish {
+        QueuedPublish {
+            topic: topic.to_string(),
+            payload: Bytes::from_static(b"x"),
+            qos,
+            retain: false,
+            origin: None,
+            user_properties: Vec::new(),
+            response_topic: None,
+            correlation_data: None,
+            segment: None,
+        }
+    }
+
+    #[test]
+    fn test_outbound_qos2_flow() {
+        let mut session = BridgeSession::default();
+        session.connected(false, Some(2));
+        assert_eq!(session.send(&publish("a", QoS::AtMostOnce)), None);
+
+        let id1 = session.send(&publish("a", QoS::ExactlyOnce)).unwrap();
+        let id2 = session.send(&publish("b", QoS::AtLeastOnce)).unwrap();
+        assert_ne!(id1, id2);
+        assert_eq!(session.room(), 0);
+
+        session.release(id1);
+        assert_eq!(
+            session.resend(),
+            vec![
+                Resend::Release(id1),
+                Resend::Publish(id2, publish("b", QoS::AtLeastOnce))
+            ]
+        );
+
+        assert!(session.complete(id1).is_some());
+        assert!(session.complete(id1).is_none());
+        assert_eq!(session.room(), 1);
+    }
+
+    #[test]
+    fn test_new_session_requeues_unacked() {
+        let mut session = BridgeSession::default();
+        session.send(&publish("a", QoS::ExactlyOnce));
+        session.send(&publish("b", QoS::AtLeastOnce));
+        assert!(session.receive(7));
+
+        // A resumed session keeps everything
+        assert!(session.connected(true, None).is_empty());
+        assert!(!session.receive(7));
+
+        let requeued = session.connected(false, None);
+        assert_eq!(
+            requeued
+                .iter()
+                .map(|p| p.topic.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+        assert!(session.resend().is_empty());
+        assert!(session.receive(7));
+    }
+
+    #[test]
+    fn test_incoming_qos2_dedup() {
+        let mut session = BridgeSession::default();
+        assert!(session.receive(1));
+        assert!(!session.receive(1));
+        session.received_release(1);
+        assert!(session.receive(1));
+    }
+}
@@ -0,0 +1,171 @@
+//! Bridge Health
+//!
+//! Counters kept by a bridge's connection task, and the status snapshot
+//! served at `/bridges` on the metrics server and exported as metrics.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::remote::RemotePeerStatus;
+
+use super::subscriptions::SubscriptionStatus;
+
+/// Health of one bridge at a point in time
+#[derive(Debug, Clone, Serialize)]
+pub struct BridgeStatus {
+    pub name: String,
+    pub status: RemotePeerStatus,
+    /// Address currently or last connected to
+    pub address: Option<String>,
+    /// When the current connection was established (Unix seconds)
+    pub connected_since: Option<u64>,
+    /// Reconnect attempts after a failed or dropped connection
+    pub reconnects: u64,
+    /// Messages sent to the remote broker
+    pub forwarded_out: u64,
+    /// Messages received from the remote broker and published locally
+    pub forwarded_in: u64,
+    /// Messages dropped because the queue and spool were full
+    pub dropped: u64,
+    /// Messages buffered in memory
+    pub queued: usize,
+    /// Messages buffered in the disk spool
+    pub spooled: usize,
+    /// Last PINGREQ/PINGRESP round trip in milliseconds
+    pub rtt_ms: Option<f64>,
+    /// Reason the last connection attempt failed or was lost
+    pub last_error: Option<String>,
+    /// Subscriptions on the remote broker
+    pub subscriptions: Vec<SubscriptionStatus>,
+}
+
+/// Counters updated by the connection task
+#[derive(Debug, Default)]
+pub(crate) struct BridgeStats {
+    reconnects: AtomicU64,
+    forwarded_out: AtomicU64,
+    forwarded_in: AtomicU64,
+    /// Last round trip in microseconds (0 = not measured)
+    rtt_micros: AtomicU64,
+    ping_sent: Mutex<Option<Instant>>,
+    address: Mutex<Option<String>>,
+    connected_since: Mutex<Option<SystemTime>>,
+    last_error: Mutex<Option<String>>,
+    subscriptions: Mutex<Vec<SubscriptionStatus>>,
+}
+
+impl BridgeStats {
+    pub(crate) fn connecting(&self, address: &str) {
+        *self.address.lock() = Some(address.to_string());
+    }
+
+    pub(crate) fn connected(&self) {
+        *self.connected_since.lock() = Some(SystemTime::now());
+        *self.ping_sent.lock() = None;
+    }
+
+    /// The connection failed or was lost and a reconnect is scheduled
+    pub(crate) fn failed(&self, error: &str) {
+        *self.connected_since.lock() = None;
+        *self.last_error.lock() = Some(error.to_string());
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn disconnected(&self) {
+        *self.connected_since.lock() = None;
+    }
+
+    pub(crate) fn forwarded_out(&self, count: usize) {
+        self.forwarded_out
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn forwarded_in(&self) {
+        self.forwarded_in.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn subscriptions(&self, subscriptions: Vec<SubscriptionStatus>) {
+        *self.subscriptions.lock() = subscriptions;
+    }
+
+    pub(crate) fn ping_sent(&self) {
+        let mut ping_sent = self.ping_sent.lock();
+        // Keep the first of several unanswered pings
+        ping_sent.get_or_insert_with(Instant::now);
+    }
+
+    pub(crate) fn pong_received(&self) {
+        if let Some(sent) = self.ping_sent.lock().take() {
+            let rtt = sent.elapsed().as_micros().max(1) as u64;
+            self.rtt_micros.store(rtt, Ordering::Relaxed);
+        }
+    }
+
+    /// Snapshot the counters; queue figures come from the bridge's queue
+    pub(crate) fn snapshot(
+        &self,
+        name: &str,
+        status: RemotePeerStatus,
+        (queued, spooled): (usize, usize),
+        dropped: u64,
+    ) -> BridgeStatus {
+        let rtt = self.rtt_micros.load(Ordering::Relaxed);
+        BridgeStatus {
+            name: name.to_string(),
+            status,
+            address: self.address.lock().clone(),
+            connected_since: self
+                .connected_since
+                .lock()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            forwarded_out: self.forwarded_out.load(Ordering::Relaxed),
+            forwarded_in: self.forwarded_in.load(Ordering::Relaxed),
+            dropped,
+            queued,
+            spooled,
+            rtt_ms: (rtt > 0).then(|| Duration::from_micros(rtt).as_secs_f64() * 1000.0),
+            last_error: self.last_error.lock().clone(),
+            subscriptions: self.subscriptions.lock().clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let stats = BridgeStats::default();
+        stats.connecting("broker.example.com:1883");
+        stats.failed("Connection refused");
+        stats.connected();
+        stats.forwarded_out(3);
+        stats.forwarded_in();
+        stats.ping_sent();
+        stats.ping_sent();
+        stats.pong_received();
+
+        let status = stats.snapshot("cloud", RemotePeerStatus::Connected, (2, 5), 1);
+        assert_eq!(status.address.as_deref(), Some("broker.example.com:1883"));
+        assert!(status.connected_since.is_some());
+        assert_eq!(status.reconnects, 1);
+        assert_eq!(status.forwarded_out, 3);
+        assert_eq!(status.forwarded_in, 1);
+        assert_eq!((status.queued, status.spooled, status.dropped), (2, 5, 1));
+        assert!(status.rtt_ms.is_some());
+        assert_eq!(status.last_error.as_deref(), Some("Connection refused"));
+
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["status"], "connected");
+
+        stats.disconnected();
+        let status = stats.snapshot("cloud", RemotePeerStatus::Backoff, (0, 0), 0);
+        assert!(status.connected_since.is_none());
+    }
+}
@@ -0,0 +1,254 @@
+//! Remote Subscriptions
+//!
+//! Tracks the bridge's subscriptions on the remote broker. Each SUBSCRIBE
+//! is matched to its SUBACK, and filters the remote broker rejects are
+//! retried with the reconnect backoff. Every `subscription_audit_interval`
+//! all filters are subscribed again, so subscriptions lost when the remote
+//! broker restarts without the bridge's session come back.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+
+use serde::Serialize;
+
+use crate::config::BridgeConfig;
+use crate::protocol::{QoS, ReasonCode};
+
+use super::backoff::Backoff;
+
+/// State of one remote subscription
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubscriptionState {
+    /// SUBSCRIBE sent, SUBACK not yet received (or not yet connected)
+    Pending,
+    /// Granted by the remote broker
+    Active,
+    /// Refused by the remote broker; retried with backoff
+    Rejected,
+}
+
+impl SubscriptionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionState::Pending => "pending",
+            SubscriptionState::Active => "active",
+            SubscriptionState::Rejected => "rejected",
+        }
+    }
+}
+
+/// Status of one remote subscription, as reported at `/bridges`
+#[derive(Debug, Clone, Serialize)]
+pub struct SubscriptionStatus {
+    pub filter: String,
+    pub state: SubscriptionState,
+    /// QoS granted by the remote broker
+    pub granted_qos: Option<u8>,
+    /// Reason code of the last rejection
+    pub reason: Option<String>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    qos: QoS,
+    state: SubscriptionState,
+    granted_qos: Option<QoS>,
+    reason: Option<ReasonCode>,
+    backoff: Backoff,
+    retry_at: Option<Instant>,
+}
+
+/// Subscriptions of one bridge on its remote broker
+#[derive(Debug)]
+pub(crate) struct RemoteSubscriptions {
+    entries: BTreeMap<String, Entry>,
+    /// Filters of each SUBSCRIBE awaiting its SUBACK
+    awaiting: HashMap<u16, Vec<String>>,
+    backoff: Backoff,
+}
+
+impl RemoteSubscriptions {
+    pub(crate) fn new<'a>(
+        config: &BridgeConfig,
+        filters: impl IntoIterator<Item = (&'a str, QoS)>,
+    ) -> Self {
+        let mut subscriptions = Self {
+            entries: BTreeMap::new(),
+            awaiting: HashMap::new(),
+            backoff: Backoff::new(config),
+        };
+        for (filter, qos) in filters {
+            subscriptions.add(filter, qos);
+        }
+        subscriptions
+    }
+
+    /// Track a filter; it is subscribed with the next `connected` or `due`
+    pub(crate) fn add(&mut self, filter: &str, qos: QoS) {
+        self.entries.insert(
+            filter.to_string(),
+            Entry {
+                qos,
+                state: SubscriptionState::Pending,
+                granted_qos: None,
+                reason: None,
+                backoff: self.backoff.clone(),
+                retry_at: None,
+            },
+        );
+    }
+
+    pub(crate) fn remove(&mut self, filter: &str) {
+        self.entries.remove(filter);
+    }
+
+    /// A new connection: every filter is pending until its SUBACK
+    pub(crate) fn connected(&mut self) -> Vec<(String, QoS)> {
+        self.awaiting.clear();
+        for entry in self.entries.values_mut() {
+            entry.state = SubscriptionState::Pending;
+            entry.retry_at = None;
+        }
+        self.all()
+    }
+
+    /// Every tracked filter, for an audit
+    pub(crate) fn all(&self) -> Vec<(String, QoS)> {
+        self.entries
+            .iter()
+            .map(|(filter, entry)| (filter.clone(), entry.qos))
+            .collect()
+    }
+
+    /// A SUBSCRIBE for these filters was sent
+    pub(crate) fn sent(&mut self, packet_id: u16, filters: Vec<String>) {
+        self.awaiting.insert(packet_id, filters);
+    }
+
+    /// Apply a SUBACK; returns the filters it rejected with their reasons
+    pub(crate) fn suback(
+        &mut self,
+        packet_id: u16,
+        reason_codes: &[ReasonCode],
+        now: Instant,
+    ) -> Vec<(String, ReasonCode)> {
+        let Some(filters) = self.awaiting.remove(&packet_id) else {
+            return Vec::new();
+        };
+        let mut rejected = Vec::new();
+        for (filter, &code) in filters.iter().zip(reason_codes) {
+            let Some(entry) = self.entries.get_mut(filter) else {
+                continue; // Unsubscribed meanwhile
+            };
+            if code.is_error() {
+                entry.state = SubscriptionState::Rejected;
+                entry.granted_qos = None;
+                entry.reason = Some(code);
+                entry.retry_at = Some(now + entry.backoff.next_delay());
+                rejected.push((filter.clone(), code));
+            } else {
+                entry.state = SubscriptionState::Active;
+                entry.granted_qos = QoS::from_u8(code as u8);
+                entry.reason = None;
+                entry.retry_at = None;
+                entry.backoff.reset();
+            }
+        }
+        rejected
+    }
+
+    /// When the next rejected filter is due for a retry
+    pub(crate) fn next_retry(&self) -> Option<Instant> {
+        self.entries.values().filter_map(|e| e.retry_at).min()
+    }
+
+    /// Rejected filters due for a retry, now pending again
+    pub(crate) fn due(&mut self, now: Instant) -> Vec<(String, QoS)> {
+        let mut due = Vec::new();
+        for (filter, entry) in &mut self.entries {
+            if entry.retry_at.is_some_and(|at| at <= now) {
+                entry.state = SubscriptionState::Pending;
+                entry.retry_at = None;
+                due.push((filter.clone(), entry.qos));
+            }
+        }
+        due
+    }
+
+    pub(crate) fn status(&self) -> Vec<SubscriptionStatus> {
+        self.entries
+            .iter()
+            .map(|(filter, entry)| SubscriptionStatus {
+                filter: filter.clone(),
+                state: entry.state,
+                granted_qos: entry.granted_qos.map(|q| q as u8),
+                reason: entry.reason.map(|r| r.to_string()),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn subscriptions() -> RemoteSubscriptions {
+        let config = BridgeConfig {
+            reconnect_interval: Duration::from_secs(1),
+            reconnect_jitter: 0.0,
+            ..Default::default()
+        };
+        RemoteSubscriptions::new(
+            &config,
+            [("a/#", QoS::AtLeastOnce), ("b/#", QoS::ExactlyOnce)],
+        )
+    }
+
+    #[test]
+    fn test_suback_marks_active_and_rejected() {
+        let mut subs = subscriptions();
+        let filters: Vec<String> = subs.connected().into_iter().map(|(f, _)| f).collect();
+        subs.sent(7, filters);
+
+        let now = Instant::now();
+        let rejected = subs.suback(
+            7,
+            &[ReasonCode::GrantedQoS1, ReasonCode::NotAuthorized],
+            now,
+        );
+        assert_eq!(
+            rejected,
+            vec![("b/#".to_string(), ReasonCode::NotAuthorized)]
+        );
+
+        let status = subs.status();
+        assert_eq!(status[0].state, SubscriptionState::Active);
+        assert_eq!(status[0].granted_qos, Some(1));
+        assert_eq!(status[1].state, SubscriptionState::Rejected);
+        assert_eq!(subs.next_retry(), Some(now + Duration::from_secs(1)));
+
+        // Not yet due, then retried
+        assert!(subs.due(now).is_empty());
+        let due = subs.due(now + Duration::from_secs(1));
+        assert_eq!(due, vec![("b/#".to_string(), QoS::ExactlyOnce)]);
+        assert_eq!(subs.status()[1].state, SubscriptionState::Pending);
+        assert_eq!(subs.next_retry(), None);
+    }
+
+    #[test]
+    fn test_unknown_suback_and_removed_filter() {
+        let mut subs = subscriptions();
+        subs.sent(1, vec!["a/#".to_string()]);
+        subs.remove("a/#");
+        assert!(subs
+            .suback(2, &[ReasonCode::Success], Instant::now())
+            .is_empty());
+        assert!(subs
+            .suback(1, &[ReasonCode::NotAuthorized], Instant::now())
+            .is_empty());
+        assert_eq!(subs.all(), vec![("b/#".to_string(), QoS::ExactlyOnce)]);
+    }
+}
@@ -1,9 +1,17 @@
 //! Bridge Module Tests
 
-use crate::config::{BridgeConfig, BridgeProtocol, ForwardDirection, ForwardRule, LoopPrevention};
-use crate::protocol::QoS;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use crate::config::{
+    BridgeConfig, BridgeProtocol, ForwardDirection, ForwardRule, LoopPrevention, QosMode,
+};
+use crate::protocol::{Properties, QoS};
 
 use super::topic_mapper::TopicMapper;
+use super::{BridgeMessage, BridgeOrigin, BRIDGE_ORIGIN_PROPERTY};
 
 // =============================================================================
 // Configuration Tests
@@ -28,6 +36,9 @@ fn test_bridge_protocol_defaults() {
     assert_eq!(BridgeProtocol::Mqtts.default_port(), 8883);
     assert_eq!(BridgeProtocol::Ws.default_port(), 80);
     assert_eq!(BridgeProtocol::Wss.default_port(), 443);
+    assert_eq!(BridgeProtocol::Kafka.default_port(), 9092);
+    assert_eq!(BridgeProtocol::Nats.default_port(), 4222);
+    assert_eq!(BridgeProtocol::Amqp.default_port(), 5672);
 
     assert!(!BridgeProtocol::Mqtt.uses_tls());
     assert!(BridgeProtocol::Mqtts.uses_tls());
@@ -38,6 +49,12 @@ fn test_bridge_protocol_defaults() {
     assert!(!BridgeProtocol::Mqtts.uses_websocket());
     assert!(BridgeProtocol::Ws.uses_websocket());
     assert!(BridgeProtocol::Wss.uses_websocket());
+
+    assert_eq!(BridgeProtocol::Https.default_port(), 443);
+    assert!(BridgeProtocol::Https.uses_tls());
+    assert!(BridgeProtocol::Http.uses_http());
+    assert!(!BridgeProtocol::Http.uses_websocket());
+    assert!(!BridgeProtocol::Mqtt.uses_http());
 }
 
 #[test]
@@ -70,7 +87,9 @@ fn test_forward_rule_directions() {
         remote_topic: "remote/#".to_string(),
         direction: ForwardDirection::Out,
         qos: 1,
+        qos_mode: QosMode::Cap,
         retain: true,
+        topic_regex: None,
     };
     assert!(out_rule.is_outbound());
     assert!(!out_rule.is_inbound());
@@ -138,6 +157,163 @@ fn test_origin_id() {
     assert_eq!(config.get_origin_id(), "custom-origin");
 }
 
+#[test]
+fn test_origin_chain_stops_outbound_loops() {
+    let client = super::BridgeClient::new(
+        BridgeConfig {
+            name: "site".to_string(),
+            loop_prevention: LoopPrevention::UserProperty,
+            max_hops: 2,
+            forwards: vec![make_rule("#", "#", ForwardDirection::Out, 1)],
+            ..Default::default()
+        },
+        None,
+    );
+    let queued = || client.health().queued;
+    let forward = |origin: Option<&BridgeOrigin>| {
+        client
+            .forward_publish_from(
+                "a/b",
+                Bytes::from_static(b"x"),
+                QoS::AtLeastOnce,
+                false,
+                origin,
+                &Properties::default(),
+            )
+            .unwrap()
+    };
+
+    forward(None);
+    assert_eq!(queued(), 1);
+
+    // Already crossed this bridge
+    let looped = BridgeOrigin {
+        hops: 1,
+        chain: vec!["site".to_string()],
+    };
+    forward(Some(&looped));
+    assert_eq!(queued(), 1);
+
+    // Too many hops
+    let far = BridgeOrigin {
+        hops: 2,
+        chain: vec!["device".to_string(), "edge".to_string()],
+    };
+    forward(Some(&far));
+    assert_eq!(queued(), 1);
+
+    let near = BridgeOrigin {
+        hops: 1,
+        chain: vec!["device".to_string()],
+    };
+    forward(Some(&near));
+    assert_eq!(queued(), 2);
+}
+
+#[test]
+fn test_transform_rewrites_and_drops_outbound() {
+    let client = super::BridgeClient::new(
+        BridgeConfig {
+            name: "cloud".to_string(),
+            forwards: vec![make_rule(
+                "sensors/#",
+                "edge/sensors/#",
+                ForwardDirection::Out,
+                1,
+            )],
+            ..Default::default()
+        },
+        None,
+    );
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let recorded = seen.clone();
+    client.set_transform(Arc::new(move |bridge: &str, mut message: BridgeMessage| {
+        recorded
+            .lock()
+            .push((bridge.to_string(), message.topic.clone()));
+        if message.payload.starts_with(b"ssn=") {
+            return None;
+        }
+        message
+            .user_properties
+            .push(("site".to_string(), "12".to_string()));
+        Some(message)
+    }));
+
+    for payload in [&b"21.5"[..], &b"ssn=123"[..]] {
+        client
+            .forward_publish_from(
+                "sensors/temp",
+                Bytes::from_static(payload),
+                QoS::AtLeastOnce,
+                false,
+                None,
+                &Properties::default(),
+            )
+            .unwrap();
+    }
+
+    // The transform sees the mapped topic; the dropped message is not queued
+    assert_eq!(client.health().queued, 1);
+    let seen = seen.lock();
+    assert_eq!(seen.len(), 2);
+    assert_eq!(
+        seen[0],
+        ("cloud".to_string(), "edge/sensors/temp".to_string())
+    );
+}
+
+#[test]
+fn test_outbound_keeps_request_properties() {
+    let client = super::BridgeClient::new(
+        BridgeConfig {
+            name: "cloud".to_string(),
+            forwards: vec![make_rule("req/#", "req/#", ForwardDirection::Out, 1)],
+            ..Default::default()
+        },
+        None,
+    );
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let recorded = seen.clone();
+    client.set_transform(Arc::new(move |_: &str, message: BridgeMessage| {
+        recorded.lock().push((
+            message.response_topic.clone(),
+            message.correlation_data.clone(),
+            message.user_properties.clone(),
+        ));
+        Some(message)
+    }));
+
+    let properties = Properties {
+        response_topic: Some("resp/a".to_string()),
+        correlation_data: Some(Bytes::from_static(b"req-1")),
+        user_properties: vec![
+            ("site".to_string(), "12".to_string()),
+            (BRIDGE_ORIGIN_PROPERTY.to_string(), "edge".to_string()),
+        ],
+        ..Default::default()
+    };
+    client
+        .forward_publish_from(
+            "req/a",
+            Bytes::from_static(b"ping"),
+            QoS::AtLeastOnce,
+            false,
+            None,
+            &properties,
+        )
+        .unwrap();
+
+    assert_eq!(
+        seen.lock()[0],
+        (
+            Some("resp/a".to_string()),
+            Some(Bytes::from_static(b"req-1")),
+            vec![("site".to_string(), "12".to_string())]
+        )
+    );
+}
+
 // =============================================================================
 // Topic Mapper Tests
 // =============================================================================
@@ -148,7 +324,9 @@ fn make_rule(local: &str, remote: &str, direction: ForwardDirection, qos: u8) ->
         remote_topic: remote.to_string(),
         direction,
         qos,
+        qos_mode: QosMode::Cap,
         retain: true,
+        topic_regex: None,
     }
 }
 
@@ -201,6 +379,34 @@ fn test_topic_mapper_qos_capping() {
     assert_eq!(qos, QoS::AtMostOnce);
 }
 
+#[test]
+fn test_topic_mapper_qos_modes() {
+    let mut fixed = make_rule("fixed/#", "fixed/#", ForwardDirection::Both, 2);
+    fixed.qos_mode = QosMode::Fixed;
+    let mut preserve = make_rule("preserve/#", "preserve/#", ForwardDirection::Both, 0);
+    preserve.qos_mode = QosMode::Preserve;
+    let mapper = TopicMapper::new(&[fixed, preserve]);
+
+    // Fixed upgrades
+    let (_, qos, _) = mapper
+        .map_outbound("fixed/a", QoS::AtMostOnce, false)
+        .unwrap();
+    assert_eq!(qos, QoS::ExactlyOnce);
+    let (_, qos, _) = mapper
+        .map_inbound("fixed/a", QoS::AtLeastOnce, false)
+        .unwrap();
+    assert_eq!(qos, QoS::ExactlyOnce);
+
+    // Preserve keeps the original QoS and subscribes at QoS 2 to receive it
+    let (_, qos, _) = mapper
+        .map_outbound("preserve/a", QoS::ExactlyOnce, false)
+        .unwrap();
+    assert_eq!(qos, QoS::ExactlyOnce);
+    assert!(mapper
+        .inbound_filters()
+        .contains(&("preserve/#", QoS::ExactlyOnce)));
+}
+
 #[test]
 fn test_topic_mapper_retain_filtering() {
     let rules = vec![ForwardRule {
@@ -208,7 +414,9 @@ fn test_topic_mapper_retain_filtering() {
         remote_topic: "test/#".to_string(),
         direction: ForwardDirection::Out,
         qos: 1,
+        qos_mode: QosMode::Cap,
         retain: false,
+        topic_regex: None,
     }];
     let mapper = TopicMapper::new(&rules);
 
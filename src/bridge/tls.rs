@@ -0,0 +1,182 @@
+//! Bridge TLS
+//!
+//! Builds the rustls client configuration for `mqtts` and `wss` bridges:
+//! a custom CA bundle (or the bundled Mozilla roots), an optional client
+//! certificate for mutual TLS, SNI override and ALPN protocols. Brokers
+//! such as AWS IoT Core and Azure IoT Hub require the client certificate.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{
+    verify_tls12_signature, verify_tls13_signature, CryptoProvider,
+};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use tokio_rustls::TlsConnector;
+
+use crate::broker::install_crypto_provider;
+use crate::config::BridgeTlsConfig;
+use crate::remote::RemoteError;
+
+/// Build a TLS connector and the server name to present for a bridge
+///
+/// `host` is the address being connected to; `server_name` overrides it.
+pub(crate) fn connector(
+    config: &BridgeTlsConfig,
+    host: &str,
+) -> Result<(TlsConnector, ServerName<'static>), RemoteError> {
+    install_crypto_provider();
+    let builder = ClientConfig::builder();
+    let builder = if config.insecure {
+        let provider = builder.crypto_provider().clone();
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerifier(provider)))
+    } else {
+        builder.with_root_certificates(root_store(config)?)
+    };
+
+    let mut client_config = match (&config.client_cert, &config.client_key) {
+        (Some(cert), Some(key)) => builder
+            .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+            .map_err(|e| invalid(format!("Invalid client certificate: {}", e)))?,
+        (None, None) => builder.with_no_client_auth(),
+        _ => {
+            return Err(invalid(
+                "client_cert and client_key must be set together".to_string(),
+            ))
+        }
+    };
+    client_config.alpn_protocols = config.alpn.iter().map(|p| p.as_bytes().to_vec()).collect();
+
+    let name = config.server_name.as_deref().unwrap_or(host);
+    let server_name = ServerName::try_from(name.to_string())
+        .map_err(|e| invalid(format!("Invalid server name '{}': {}", name, e)))?;
+
+    Ok((TlsConnector::from(Arc::new(client_config)), server_name))
+}
+
+/// Trust anchors: the configured CA bundle, or the Mozilla roots
+fn root_store(config: &BridgeTlsConfig) -> Result<RootCertStore, RemoteError> {
+    let mut roots = RootCertStore::empty();
+    match config.ca_cert {
+        Some(ref path) => {
+            for cert in load_certs(path)? {
+                roots
+                    .add(cert)
+                    .map_err(|e| invalid(format!("Invalid CA certificate in {}: {}", path, e)))?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    Ok(roots)
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, RemoteError> {
+    let file = File::open(path).map_err(|e| invalid(format!("{}: {}", path, e)))?;
+    let certs = CertificateDer::pem_reader_iter(BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| invalid(format!("Failed to parse certificates in {}: {}", path, e)))?;
+    if certs.is_empty() {
+        return Err(invalid(format!("No certificates found in {}", path)));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>, RemoteError> {
+    let file = File::open(path).map_err(|e| invalid(format!("{}: {}", path, e)))?;
+    PrivateKeyDer::from_pem_reader(BufReader::new(file))
+        .map_err(|e| invalid(format!("Failed to parse private key in {}: {}", path, e)))
+}
+
+fn invalid(msg: String) -> RemoteError {
+    RemoteError::InvalidConfig(msg)
+}
+
+/// Accepts any server certificate (`insecure = true`), still checking
+/// handshake signatures
+#[derive(Debug)]
+struct NoVerifier(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_cert_requires_key() {
+        let config = BridgeTlsConfig {
+            client_cert: Some("/nonexistent/cert.pem".to_string()),
+            insecure: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            connector(&config, "broker.example.com"),
+            Err(RemoteError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_server_name_override() {
+        let config = BridgeTlsConfig {
+            server_name: Some("iot.example.com".to_string()),
+            alpn: vec!["x-amzn-mqtt-ca".to_string()],
+            ..Default::default()
+        };
+        let (_, name) = connector(&config, "10.0.0.1").unwrap();
+        assert_eq!(name.to_str(), "iot.example.com");
+
+        let (_, name) = connector(&BridgeTlsConfig::default(), "10.0.0.1").unwrap();
+        assert_eq!(name.to_str(), "10.0.0.1");
+    }
+}
@@ -1,8 +1,13 @@
 //! Topic Mapping for Bridge Forwarding
 //!
 //! Handles topic pattern matching and transformation between local and remote brokers.
+//! Rules map topics by prefix, or with a `topic_regex` whose capture groups
+//! fill in a destination template.
 
-use crate::config::ForwardRule;
+use regex::Regex;
+use tracing::warn;
+
+use crate::config::{ForwardRule, QosMode};
 use crate::protocol::QoS;
 use crate::topic::validation::topic_matches_filter;
 
@@ -21,18 +26,23 @@ struct CompiledRule {
     local_pattern: String,
     /// Original remote topic pattern
     remote_pattern: String,
-    /// Maximum QoS
+    /// Rule QoS
     qos: QoS,
+    /// How the rule QoS applies to the original QoS
+    qos_mode: QosMode,
     /// Forward retained messages
     retain: bool,
     /// Prefix to strip from source topic
     strip_prefix: Option<String>,
     /// Prefix to add to destination topic
     add_prefix: Option<String>,
+    /// Regex over the whole source topic; the destination pattern is its
+    /// replacement template
+    remap: Option<Regex>,
 }
 
 impl CompiledRule {
-    fn from_forward_rule(rule: &ForwardRule, outbound: bool) -> Self {
+    fn from_forward_rule(rule: &ForwardRule, outbound: bool) -> Option<Self> {
         let qos = match rule.qos {
             0 => QoS::AtMostOnce,
             1 => QoS::AtLeastOnce,
@@ -52,14 +62,30 @@ impl CompiledRule {
         let (strip_prefix, add_prefix) =
             Self::compute_prefix_transform(source_pattern, dest_pattern);
 
-        Self {
+        let remap = match rule.topic_regex {
+            Some(ref pattern) => match Regex::new(&format!("^(?:{})$", pattern)) {
+                Ok(re) => Some(re),
+                Err(e) => {
+                    warn!(
+                        "Ignoring forward rule with invalid topic_regex '{}': {}",
+                        pattern, e
+                    );
+                    return None;
+                }
+            },
+            None => None,
+        };
+
+        Some(Self {
             local_pattern: rule.local_topic.clone(),
             remote_pattern: rule.remote_topic.clone(),
             qos,
+            qos_mode: rule.qos_mode,
             retain: rule.retain,
             strip_prefix,
             add_prefix,
-        }
+            remap,
+        })
     }
 
     /// Compute prefix transformation between two patterns
@@ -98,6 +124,20 @@ impl CompiledRule {
         (None, None)
     }
 
+    /// QoS of a forwarded message
+    fn effective_qos(&self, qos: QoS) -> QoS {
+        QoS::from_u8(self.qos_mode.apply(qos as u8, self.qos as u8)).unwrap_or(qos)
+    }
+
+    /// QoS to subscribe with on the remote broker
+    fn subscription_qos(&self) -> QoS {
+        match self.qos_mode {
+            // Receive messages with whatever QoS they were published
+            QosMode::Preserve => QoS::ExactlyOnce,
+            QosMode::Cap | QosMode::Fixed => self.qos,
+        }
+    }
+
     /// Check if a topic matches this rule's source pattern
     fn matches(&self, topic: &str, outbound: bool) -> bool {
         let filter = if outbound {
@@ -107,15 +147,35 @@ impl CompiledRule {
         };
         // topic_matches_filter takes (topic, filter)
         topic_matches_filter(topic, filter)
+            && self.remap.as_ref().is_none_or(|re| re.is_match(topic))
     }
 
     /// Transform a topic from source to destination
     fn transform(&self, topic: &str, outbound: bool) -> String {
+        if let Some(ref re) = self.remap {
+            let template = if outbound {
+                &self.remote_pattern
+            } else {
+                &self.local_pattern
+            };
+            return re.replace(topic, template.as_str()).into_owned();
+        }
+
         // Handle identical patterns
         if self.local_pattern == self.remote_pattern {
             return topic.to_string();
         }
 
+        // A destination without wildcards is a fixed topic (e.g. a Kafka topic)
+        let dest_pattern = if outbound {
+            &self.remote_pattern
+        } else {
+            &self.local_pattern
+        };
+        if !dest_pattern.contains(['#', '+']) {
+            return dest_pattern.clone();
+        }
+
         // Apply prefix transformation
         let mut result = topic.to_string();
 
@@ -153,13 +213,13 @@ impl TopicMapper {
         let outbound_rules: Vec<CompiledRule> = rules
             .iter()
             .filter(|r| r.is_outbound())
-            .map(|r| CompiledRule::from_forward_rule(r, true))
+            .filter_map(|r| CompiledRule::from_forward_rule(r, true))
             .collect();
 
         let inbound_rules: Vec<CompiledRule> = rules
             .iter()
             .filter(|r| r.is_inbound())
-            .map(|r| CompiledRule::from_forward_rule(r, false))
+            .filter_map(|r| CompiledRule::from_forward_rule(r, false))
             .collect();
 
         Self {
@@ -179,7 +239,7 @@ impl TopicMapper {
         for rule in &self.outbound_rules {
             if rule.matches(topic, true) {
                 let remote_topic = rule.transform(topic, true);
-                let effective_qos = qos.min(rule.qos);
+                let effective_qos = rule.effective_qos(qos);
                 let effective_retain = retain && rule.retain;
                 return Some((remote_topic, effective_qos, effective_retain));
             }
@@ -198,7 +258,7 @@ impl TopicMapper {
         for rule in &self.inbound_rules {
             if rule.matches(topic, false) {
                 let local_topic = rule.transform(topic, false);
-                let effective_qos = qos.min(rule.qos);
+                let effective_qos = rule.effective_qos(qos);
                 let effective_retain = retain && rule.retain;
                 return Some((local_topic, effective_qos, effective_retain));
             }
@@ -210,7 +270,7 @@ impl TopicMapper {
     pub fn inbound_filters(&self) -> Vec<(&str, QoS)> {
         self.inbound_rules
             .iter()
-            .map(|r| (r.remote_pattern.as_str(), r.qos))
+            .map(|r| (r.remote_pattern.as_str(), r.subscription_qos()))
             .collect()
     }
 
@@ -223,6 +283,28 @@ impl TopicMapper {
     }
 }
 
+/// Convert an MQTT topic or filter to a dot-separated NATS subject or AMQP
+/// routing key: levels are joined with `.`, `+` becomes `*` and `#` becomes
+/// `multi_level` (`>` for NATS, `#` for AMQP)
+#[cfg(any(feature = "nats", feature = "amqp"))]
+pub(crate) fn to_dotted(topic: &str, multi_level: &str) -> String {
+    topic
+        .split('/')
+        .map(|level| match level {
+            "+" => "*",
+            "#" => multi_level,
+            level => level,
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Convert a NATS subject or AMQP routing key back to an MQTT topic
+#[cfg(any(feature = "nats", feature = "amqp"))]
+pub(crate) fn from_dotted(subject: &str) -> String {
+    subject.replace('.', "/")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -234,7 +316,9 @@ mod tests {
             remote_topic: remote.to_string(),
             direction,
             qos: 1,
+            qos_mode: QosMode::Cap,
             retain: true,
+            topic_regex: None,
         }
     }
 
@@ -296,6 +380,56 @@ mod tests {
         assert!(!retain);
     }
 
+    #[test]
+    fn test_regex_remap() {
+        let mut out_rule = make_rule("sensors/#", "edge/site1/${dev}/$2", ForwardDirection::Out);
+        out_rule.topic_regex = Some("sensors/(?P<dev>[^/]+)/(.+)".to_string());
+        let mut in_rule = make_rule("devices/$1/commands", "site1/+/cmd", ForwardDirection::In);
+        in_rule.topic_regex = Some("site1/([^/]+)/cmd".to_string());
+        let mapper = TopicMapper::new(&[out_rule, in_rule]);
+
+        let (topic, _, _) = mapper
+            .map_outbound("sensors/dev42/temp/celsius", QoS::AtLeastOnce, false)
+            .unwrap();
+        assert_eq!(topic, "edge/site1/dev42/temp/celsius");
+
+        // The filter matches but the regex does not
+        assert!(!mapper.should_forward_outbound("sensors/dev42"));
+
+        let (topic, _, _) = mapper
+            .map_inbound("site1/dev7/cmd", QoS::AtLeastOnce, false)
+            .unwrap();
+        assert_eq!(topic, "devices/dev7/commands");
+        assert_eq!(mapper.inbound_filters()[0].0, "site1/+/cmd");
+    }
+
+    #[test]
+    fn test_fixed_destination() {
+        let rules = vec![
+            make_rule("sensors/#", "telemetry", ForwardDirection::Out),
+            make_rule("devices/commands", "commands", ForwardDirection::In),
+        ];
+        let mapper = TopicMapper::new(&rules);
+
+        let (topic, _, _) = mapper
+            .map_outbound("sensors/dev1/temp", QoS::AtLeastOnce, false)
+            .unwrap();
+        assert_eq!(topic, "telemetry");
+        let (topic, _, _) = mapper
+            .map_inbound("commands", QoS::AtLeastOnce, false)
+            .unwrap();
+        assert_eq!(topic, "devices/commands");
+    }
+
+    #[test]
+    #[cfg(any(feature = "nats", feature = "amqp"))]
+    fn test_dotted_conversion() {
+        assert_eq!(to_dotted("sensors/+/temp", ">"), "sensors.*.temp");
+        assert_eq!(to_dotted("sensors/#", ">"), "sensors.>");
+        assert_eq!(to_dotted("sensors/#", "#"), "sensors.#");
+        assert_eq!(from_dotted("sensors.dev1.temp"), "sensors/dev1/temp");
+    }
+
     #[test]
     fn test_inbound_filters() {
         let rules = vec![
@@ -0,0 +1,42 @@
+//! Bridge Transform Hook
+//!
+//! Lets an embedding application rewrite or drop outbound messages before a
+//! bridge queues them, e.g. to strip personal data from payloads before
+//! they leave the edge. The transform runs after topic mapping, on the
+//! publishing task, so it should be quick.
+
+use bytes::Bytes;
+
+use crate::protocol::QoS;
+
+/// An outbound message as it will be sent to the remote side
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BridgeMessage {
+    /// Remote topic (for Kafka, the local MQTT topic used as record key)
+    pub topic: String,
+    pub payload: Bytes,
+    pub qos: QoS,
+    pub retain: bool,
+    /// User properties sent with the message (MQTT bridges)
+    pub user_properties: Vec<(String, String)>,
+    /// Response Topic of a request (MQTT bridges)
+    pub response_topic: Option<String>,
+    /// Correlation Data of a request or response (MQTT bridges)
+    pub correlation_data: Option<Bytes>,
+}
+
+/// Rewrites outbound bridge messages
+pub trait BridgeTransform: Send + Sync {
+    /// Rewrite a message the bridge `bridge` is about to queue, or return
+    /// `None` to drop it
+    fn transform(&self, bridge: &str, message: BridgeMessage) -> Option<BridgeMessage>;
+}
+
+impl<F> BridgeTransform for F
+where
+    F: Fn(&str, BridgeMessage) -> Option<BridgeMessage> + Send + Sync,
+{
+    fn transform(&self, bridge: &str, message: BridgeMessage) -> Option<BridgeMessage> {
+        self(bridge, message)
+    }
+}
@@ -5,16 +5,22 @@ use std::time::Instant;
 
 use parking_lot::RwLock;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
-use tracing::{debug, error, trace};
+use tokio::sync::broadcast;
+use tracing::{debug, error, info_span, trace, Instrument};
 
-use super::{BytesMutExt, Connection, ConnectionError, State};
+use super::{BytesMutExt, Connection, ConnectionError, Presence, State};
 use crate::broker::BrokerEvent;
+use crate::hooks::HookDecision;
+use crate::metrics::{ConnectionGauge, ConnectionLabels};
+use crate::persistence::StoredSession;
 use crate::protocol::{
     ConnAck, Disconnect, Packet, Properties, ProtocolVersion, PubRel, QoS, ReasonCode,
 };
-use crate::session::{
-    InflightMessage, Qos2State, QueueResult, Session, SessionLimits, WillMessage,
-};
+use crate::session::{InflightMessage, Qos2State, Session, SessionLimits, WillMessage};
+use crate::topic::{parse_shared_subscription, Subscription, SubscriptionStore};
+
+/// Longest client ID accepted in strict mode
+const MAX_STRICT_CLIENT_ID_LEN: usize = 23;
 
 impl<S> Connection<S>
 where
@@ -30,7 +36,13 @@ where
 
                     match packet {
                         Packet::Connect(connect) => {
-                            return self.handle_connect(*connect).await;
+                            let span = info_span!(
+                                target: crate::otel::SPAN_TARGET,
+                                "mqtt.connect",
+                                client_id = %connect.client_id,
+                                protocol = connect.protocol_version as u8,
+                            );
+                            return self.handle_connect(*connect).instrument(span).await;
                         }
                         _ => {
                             // Protocol violation - first packet must be CONNECT
@@ -123,6 +135,28 @@ where
             ));
         }
 
+        // Servers need only accept client IDs of up to 23 bytes [MQTT-3.1.3-5]
+        if self.config.strict && connect.client_id.len() > MAX_STRICT_CLIENT_ID_LEN {
+            debug!(
+                "Rejecting client ID of {} bytes from {}",
+                connect.client_id.len(),
+                self.addr
+            );
+            let connack = ConnAck {
+                session_present: false,
+                reason_code: ReasonCode::ClientIdNotValid,
+                properties: Properties::default(),
+            };
+            self.write_buf.clear();
+            self.encoder
+                .encode(&Packet::ConnAck(connack), &mut self.write_buf)
+                .map_err(|e| ConnectionError::Protocol(e.into()))?;
+            self.stream.write_all(&self.write_buf).await?;
+            return Err(ConnectionError::Protocol(
+                crate::protocol::ProtocolError::ProtocolViolation("client ID too long"),
+            ));
+        }
+
         // Validate client ID
         let client_id: Arc<str> = if connect.client_id.is_empty() {
             // Generate client ID (only allowed when clean_start=true)
@@ -133,20 +167,48 @@ where
 
         debug!("CONNECT from {} (client_id: {})", self.addr, client_id);
 
+        // Point the client at another server during migrations or maintenance
+        if let Some(redirect) = self.redirect.get() {
+            debug!("Redirecting {} to {}", client_id, redirect.server_reference);
+            self.write_buf.clear();
+            self.encoder
+                .encode(
+                    &Packet::ConnAck(crate::broker::redirect::connack(&redirect)),
+                    &mut self.write_buf,
+                )
+                .map_err(|e| ConnectionError::Protocol(e.into()))?;
+            self.stream.write_all(&self.write_buf).await?;
+            return Err(ConnectionError::Protocol(
+                crate::protocol::ProtocolError::ProtocolViolation("client redirected"),
+            ));
+        }
+
+        // Hooks see the client as it connected
+        self.context.client_id = client_id.clone();
+        self.context.username = connect.username.clone();
+        self.context.protocol_version = protocol_version;
+        self.context.connect_properties = connect.properties.clone();
+
         // Authenticate the client
         let auth_result = self
             .hooks
-            .on_authenticate(
-                &client_id,
-                connect.username.as_deref(),
-                connect.password.as_deref(),
-            )
+            .on_authenticate(&self.context, connect.password.as_deref())
             .await;
 
-        match auth_result {
+        match auth_result.map(HookDecision::is_allowed) {
             Ok(true) => {
-                // Authentication successful, store username
-                self.username = connect.username.clone();
+                let listener = self.context.listener.as_str();
+                self.tenant = self
+                    .config
+                    .tenancy
+                    .tenant_for(listener, self.context.username())
+                    .map(Arc::from);
+                self.mount_point = self
+                    .config
+                    .mount_points
+                    .mount_point_for(listener, self.context.username())
+                    .map(Arc::from);
+                self.encoder.set_mount_point(self.mount_point.clone());
                 debug!("Authentication successful for {}", client_id);
             }
             Ok(false) => {
@@ -216,12 +278,28 @@ where
             let _ = existing.try_send(disconnect);
         }
 
+        // The client's ACL role may override the queue overflow policy
+        let queue_overflow = self
+            .hooks
+            .queue_overflow_policy(&self.context)
+            .await
+            .unwrap_or(self.config.queue_overflow);
+        let session_limits = self.config.session_limits(queue_overflow);
+
+        // If another cluster node owns the session, have it disconnect the
+        // client and hand the session over before we resume it
+        if let Some(cluster) = self.cluster.clone() {
+            if let Some(stored) = cluster
+                .take_over_session(&client_id, connect.clean_start)
+                .await
+            {
+                if !connect.clean_start {
+                    self.restore_taken_over_session(&client_id, stored, session_limits.clone());
+                }
+            }
+        }
+
         // Get or create session
-        let session_limits = SessionLimits {
-            max_pending_messages: self.config.max_queued_messages,
-            max_inflight: self.config.max_inflight,
-            max_awaiting_rel: self.config.max_awaiting_rel,
-        };
         let (session, session_present) = self.sessions.get_or_create(
             &client_id,
             protocol_version,
@@ -229,20 +307,39 @@ where
             session_limits,
         );
 
+        // A reconnect within the will delay cancels the pending will [MQTT-3.1.3-9]
+        if self.sessions.cancel_will(&client_id) {
+            debug!("Cancelled delayed will of {} on reconnect", client_id);
+        }
+
         // If clean_start=true, clear any previous subscriptions from the SubscriptionStore
         if connect.clean_start {
-            self.subscriptions.unsubscribe_all(&client_id);
+            remove_subscriptions(&self.subscriptions, &self.events, &client_id);
         }
 
+        // Keep alive the broker enforces; v5 clients are told when it differs
+        // from the requested value and MUST use it instead [MQTT-3.2.2-21]
+        let keep_alive = if connect.keep_alive == 0 {
+            self.config.default_keep_alive
+        } else if connect.keep_alive > self.config.max_keep_alive {
+            if protocol_version == ProtocolVersion::V5 {
+                self.config
+                    .server_keep_alive
+                    .unwrap_or(self.config.max_keep_alive)
+            } else {
+                self.config.max_keep_alive
+            }
+        } else {
+            connect.keep_alive
+        };
+
         // Update session with connection parameters
         {
             let mut s = session.write();
             s.clean_start = connect.clean_start;
-            s.keep_alive = if connect.keep_alive == 0 {
-                self.config.default_keep_alive
-            } else {
-                connect.keep_alive.min(self.config.max_keep_alive)
-            };
+            s.tenant = self.tenant.clone();
+            s.keep_alive = keep_alive;
+            s.queue_overflow = queue_overflow;
 
             // Handle session expiry based on protocol version
             if protocol_version == ProtocolVersion::V5 {
@@ -253,16 +350,6 @@ where
                     // If clean_start=false but no expiry specified, use a reasonable default
                     s.session_expiry_interval = 0xFFFFFFFF; // Never expires
                 }
-                if let Some(max) = connect.properties.receive_maximum {
-                    s.receive_maximum = max;
-                    s.send_quota = max;
-                }
-                if let Some(max) = connect.properties.maximum_packet_size {
-                    s.max_packet_size = max;
-                }
-                if let Some(max) = connect.properties.topic_alias_maximum {
-                    s.topic_alias_maximum = max;
-                }
             } else {
                 // v3.1.1: clean_session=false means session persists indefinitely
                 if !connect.clean_start {
@@ -271,20 +358,30 @@ where
                     s.session_expiry_interval = 0; // Delete on disconnect
                 }
             }
+            // Aliases and flow control start over with each connection,
+            // while inflight messages are re-sent below
+            s.reset_connection_state(
+                connect.properties.receive_maximum,
+                connect.properties.maximum_packet_size,
+                connect.properties.topic_alias_maximum.unwrap_or(0),
+            );
 
-            // Store will message
-            if let Some(will) = connect.will {
-                s.will = Some(WillMessage {
-                    topic: will.topic,
-                    payload: will.payload,
-                    qos: will.qos,
-                    retain: will.retain,
-                    properties: will.properties.clone(),
-                });
-                if let Some(delay) = will.properties.will_delay_interval {
-                    s.will_delay_interval = delay;
-                }
-            }
+            // Store will message, replacing the one of a previous connection
+            s.will_delay_interval = connect
+                .will
+                .as_ref()
+                .and_then(|will| will.properties.will_delay_interval)
+                .unwrap_or(0);
+            s.will = connect.will.map(|will| WillMessage {
+                topic: match self.mount_point {
+                    Some(ref mount_point) => format!("{}{}", mount_point, will.topic),
+                    None => will.topic,
+                },
+                payload: will.payload,
+                qos: will.qos,
+                retain: will.retain,
+                properties: will.properties,
+            });
 
             s.touch();
         }
@@ -303,6 +400,9 @@ where
         // Set v5.0 properties
         if protocol_version == ProtocolVersion::V5 {
             connack.properties.receive_maximum = Some(self.config.receive_maximum);
+            if keep_alive != connect.keep_alive {
+                connack.properties.server_keep_alive = Some(keep_alive);
+            }
             // Per MQTT 5.0 spec 3.2.2.3.4: Maximum QoS can only be 0 or 1.
             // If server supports QoS 2, don't include this property (default is 2).
             if self.config.max_qos != QoS::ExactlyOnce {
@@ -356,55 +456,136 @@ where
             session: session.clone(),
         };
 
+        if let Some(ref metrics) = self.metrics {
+            let labels = ConnectionLabels {
+                protocol: match protocol_version {
+                    ProtocolVersion::V311 => "v3.1.1",
+                    ProtocolVersion::V5 => "v5.0",
+                },
+                listener: self.context.listener.as_str(),
+            };
+            self.gauge = Some(ConnectionGauge::connected(metrics.clone(), labels));
+        }
+
         // Notify event subscribers
         let _ = self.events.send(BrokerEvent::ClientConnected {
             client_id: client_id.clone(),
             protocol_version,
+            listener: self.context.listener,
         });
+        if self.config.presence_topics {
+            self.publish_presence(&client_id, Presence::Connected(protocol_version))
+                .await;
+        }
+
+        // Re-send unacknowledged inflight messages on session resume
+        // [MQTT-4.4.0-1], ahead of the newer queued ones [MQTT-4.6.0-1]
+        if session_present {
+            self.resend_inflight_messages(&session).await?;
+        }
 
         // Send pending messages
         self.send_pending_messages(&session).await?;
 
-        // Re-send unacknowledged inflight messages on session resume [MQTT-4.4.0-1]
         if session_present {
-            self.resend_inflight_messages(&session).await?;
-
             // Send retained messages for existing subscriptions
-            self.send_retained_for_existing_subscriptions(&client_id, &session)
+            self.send_retained_for_existing_subscriptions(&session)
                 .await?;
         }
 
         Ok(())
     }
 
+    /// Install a session handed over by another cluster node
+    fn restore_taken_over_session(
+        &self,
+        client_id: &Arc<str>,
+        stored: StoredSession,
+        limits: SessionLimits,
+    ) {
+        let session = self.sessions.import_stored(stored, limits);
+        let s = session.read();
+        debug!(
+            "Took over session of {} from cluster peer ({} subscriptions, {} queued)",
+            client_id,
+            s.subscriptions.len(),
+            s.pending_messages.len()
+        );
+        restore_subscriptions(&self.subscriptions, &self.events, client_id, &s);
+    }
+
     /// Send pending messages from session queue
-    async fn send_pending_messages(
+    ///
+    /// Spilled messages are read back from persistence as the in-memory
+    /// queue empties.
+    pub(crate) async fn send_pending_messages(
         &mut self,
         session: &Arc<RwLock<Session>>,
     ) -> Result<(), ConnectionError> {
-        let (pending, max_packet_size) = {
+        let spill = self.sessions.spill().cloned();
+        loop {
+            if let Some(ref spill) = spill {
+                spill.load(session).await;
+            }
+            if session.read().pending_messages.is_empty() {
+                return Ok(());
+            }
+            self.send_queued_messages(session).await?;
+
+            // Go on with the next segment once everything in memory is out
+            let more = {
+                let s = session.read();
+                s.pending_messages.is_empty() && s.has_spilled()
+            };
+            if !more {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Send the messages queued in memory, putting back those that cannot
+    /// go yet
+    async fn send_queued_messages(
+        &mut self,
+        session: &Arc<RwLock<Session>>,
+    ) -> Result<(), ConnectionError> {
+        let (client_id, pending, max_packet_size) = {
             let mut s = session.write();
-            (s.drain_pending_messages(), s.max_packet_size)
+            (
+                s.client_id.clone(),
+                s.drain_pending_messages(),
+                s.max_packet_size,
+            )
         };
 
+        // If a message put back overflows the queue, the client is
+        // disconnected once all of them are back
+        let mut overflow = false;
+        let mut blocked = false;
         for mut publish in pending {
+            if blocked {
+                // Keep the rest queued behind the message that could not go
+                let result = session.write().requeue_message(publish);
+                overflow |= self.record_queue_result(&client_id, &result, "ordered delivery");
+                continue;
+            }
             if publish.qos != QoS::AtMostOnce {
                 let mut s = session.write();
                 // Check send quota (MQTT v5.0 flow control)
                 if !s.decrement_send_quota() {
                     // Quota exhausted - re-queue remaining messages
-                    if s.queue_message(publish) == QueueResult::DroppedOldest {
-                        let _ = self.events.send(BrokerEvent::MessageDropped);
-                    }
+                    let result = s.requeue_message(publish);
+                    overflow |= self.record_queue_result(&client_id, &result, "quota exhausted");
+                    blocked = self.config.ordered_delivery;
                     continue;
                 }
                 // Check max_inflight limit
                 if s.inflight_outgoing.len() >= s.max_inflight as usize {
                     // Inflight limit reached - re-queue and restore quota
                     s.increment_send_quota();
-                    if s.queue_message(publish) == QueueResult::DroppedOldest {
-                        let _ = self.events.send(BrokerEvent::MessageDropped);
-                    }
+                    let result = s.requeue_message(publish);
+                    overflow |= self.record_queue_result(&client_id, &result, "inflight limit");
+                    blocked = self.config.ordered_delivery;
                     continue;
                 }
                 publish.packet_id = Some(s.next_packet_id());
@@ -427,6 +608,9 @@ where
                 }
             }
 
+            let packet_id = publish.packet_id;
+            let delivered_topic = (self.observes_messages && publish.qos == QoS::AtMostOnce)
+                .then(|| publish.topic.clone());
             self.write_buf.clear();
             self.encoder
                 .encode(&Packet::Publish(publish), &mut self.write_buf)
@@ -440,16 +624,23 @@ where
                     self.write_buf.len(),
                     max_packet_size
                 );
+                self.discard_oversized_publish(session, packet_id);
                 continue;
             }
 
             let bytes_sent = self.write_buf.len();
             self.stream.write_all(&self.write_buf).await?;
-            if let Some(ref metrics) = self.metrics {
-                metrics.publish_sent(bytes_sent);
+            self.record_publish_sent(session, bytes_sent, None);
+            if let Some(topic) = delivered_topic {
+                self.hooks
+                    .on_message_delivered(&self.context, &topic, QoS::AtMostOnce)
+                    .await;
             }
         }
 
+        if overflow {
+            return Err(self.disconnect_queue_overflow(&client_id, session).await);
+        }
         Ok(())
     }
 
@@ -466,15 +657,17 @@ where
             let mut s = session.write();
             let now = Instant::now();
             let messages: Vec<_> = s
-                .inflight_outgoing
-                .iter_mut()
-                .map(|(packet_id, inflight)| {
+                .inflight_outgoing_in_order()
+                .into_iter()
+                .filter_map(|packet_id| {
+                    let inflight = s.inflight_outgoing.get_mut(&packet_id)?;
                     // Update sent_at for retry tracking
                     inflight.sent_at = now;
                     inflight.retry_count += 1;
-                    (*packet_id, inflight.publish.clone(), inflight.qos2_state)
+                    Some((packet_id, inflight.publish.clone(), inflight.qos2_state))
                 })
                 .collect();
+            s.retransmits += messages.len() as u64;
             (messages, s.max_packet_size)
         };
 
@@ -484,19 +677,25 @@ where
                     // QoS 1, or QoS 2 waiting for PUBREC: resend PUBLISH with DUP=1 [MQTT-3.3.1-1]
                     publish.dup = true;
                     publish.packet_id = Some(packet_id);
+                    // Topic aliases of the previous connection are gone
+                    publish.properties.topic_alias = None;
 
                     self.write_buf.clear();
                     self.encoder
                         .encode(&Packet::Publish(publish), &mut self.write_buf)
                         .map_err(|e| ConnectionError::Protocol(e.into()))?;
 
-                    if self.write_buf.len() <= max_packet_size as usize {
-                        trace!(
-                            "Resending inflight PUBLISH packet_id={} with DUP=1",
-                            packet_id
-                        );
-                        self.stream.write_all(&self.write_buf).await?;
+                    if self.write_buf.len() > max_packet_size as usize {
+                        // The new connection may allow smaller packets
+                        // [MQTT-3.1.2-25]
+                        self.discard_oversized_publish(session, Some(packet_id));
+                        continue;
                     }
+                    trace!(
+                        "Resending inflight PUBLISH packet_id={} with DUP=1",
+                        packet_id
+                    );
+                    self.stream.write_all(&self.write_buf).await?;
                 }
                 Some(Qos2State::WaitingPubComp) => {
                     // QoS 2 waiting for PUBCOMP: resend PUBREL
@@ -516,27 +715,78 @@ where
     }
 
     /// Send retained messages for existing subscriptions on session resume
+    ///
+    /// Subscriptions made with Retain Handling 1 or 2 are skipped: they are
+    /// not new, and 2 never receives retained messages.
     async fn send_retained_for_existing_subscriptions(
         &mut self,
-        client_id: &Arc<str>,
         session: &Arc<RwLock<Session>>,
     ) -> Result<(), ConnectionError> {
+        let queue_subscriptions = self.config.queue_subscriptions;
         let subs: Vec<_> = {
             let s = session.read();
-            s.subscriptions.values().cloned().collect()
+            s.subscriptions
+                .values()
+                .filter(|sub| {
+                    sub.options.retain_handling.sends_retained(true)
+                        && parse_shared_subscription(&sub.filter, queue_subscriptions).is_none()
+                })
+                .cloned()
+                .collect()
         };
 
         for sub in subs {
-            self.send_retained_messages(
-                client_id,
-                &sub.filter,
-                sub.options.qos,
-                session,
-                sub.subscription_id,
-            )
-            .await?;
+            self.send_retained_messages(&sub.filter, sub.options.qos, session, sub.subscription_id)
+                .await?;
         }
 
         Ok(())
     }
 }
+
+/// Remove all subscriptions of a client, announcing each one removed
+pub(crate) fn remove_subscriptions(
+    subscriptions: &SubscriptionStore,
+    events: &broadcast::Sender<BrokerEvent>,
+    client_id: &str,
+) {
+    let filters = subscriptions.unsubscribe_all(client_id);
+    if filters.is_empty() {
+        return;
+    }
+    let client_id: Arc<str> = client_id.into();
+    for filter in filters {
+        let _ = events.send(BrokerEvent::SubscriptionRemoved {
+            filter,
+            client_id: client_id.clone(),
+        });
+    }
+}
+
+/// Register the subscriptions of a session installed from its portable form
+pub(crate) fn restore_subscriptions(
+    subscriptions: &SubscriptionStore,
+    events: &broadcast::Sender<BrokerEvent>,
+    client_id: &Arc<str>,
+    session: &Session,
+) {
+    subscriptions.subscribe_many(session.subscriptions.values().map(|sub| {
+        (
+            sub.filter.as_str(),
+            Subscription {
+                client_id: client_id.clone(),
+                qos: sub.options.qos,
+                no_local: sub.options.no_local,
+                retain_as_published: sub.options.retain_as_published,
+                subscription_id: sub.subscription_id,
+                share_group: None,
+            },
+        )
+    }));
+    for sub in session.subscriptions.values() {
+        let _ = events.send(BrokerEvent::SubscriptionAdded {
+            filter: sub.filter.clone(),
+            client_id: client_id.clone(),
+        });
+    }
+}
@@ -10,31 +10,57 @@
 
 mod connect;
 mod disconnect;
+mod presence;
 mod publish;
 mod qos;
 mod subscribe;
 
+pub(crate) use connect::{remove_subscriptions, restore_subscriptions};
+pub(crate) use disconnect::publish_due_will;
+pub(crate) use presence::Presence;
+
+use std::collections::VecDeque;
+use std::io::IoSlice;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use bytes::BytesMut;
-use dashmap::DashMap;
+use bytes::{Bytes, BytesMut};
 use parking_lot::RwLock;
+use smallvec::SmallVec;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::sync::{broadcast, mpsc};
 use tokio::time::timeout;
 use tracing::{debug, error, info, warn};
 
-use crate::broker::{BrokerConfig, BrokerEvent, RetainedMessage};
+use crate::broker::{
+    report_dropped, BrokerConfig, BrokerEvent, ConnectionMap, DisconnectReason, DropReason,
+    DropReports, Listener, RetainedStore, ServerRedirect, SlowConsumers,
+};
 use crate::buffer_pool;
-use crate::codec::{Decoder, Encoder};
-use crate::hooks::Hooks;
-use crate::metrics::Metrics;
-use crate::protocol::Packet;
+use crate::cluster::ClusterManager;
+use crate::codec::{Decoder, Encoder, RawPublish};
+use crate::config::MessagePriority;
+use crate::hooks::{ClientContext, Hooks};
+use crate::metrics::{ConnectionGauge, Metrics};
+use crate::protocol::{Packet, Publish, QoS, SharedPublish};
 use crate::proxy::ProxyInfo;
-use crate::session::{Session, SessionStore};
-use crate::topic::SubscriptionStore;
+use crate::rules::RuleEngine;
+use crate::session::{topic_priority, InboundActivity, QueueResult, Session, SessionStore};
+use crate::topic::{SubscriptionStore, Topic};
+
+/// Most outbound packets reordered by topic priority at once
+const PRIORITY_BATCH_SIZE: usize = 64;
+
+/// Most queued packets handed to one vectored write
+const MAX_WRITE_SLICES: usize = 64;
+
+/// How long a slow consumer gets to read the DISCONNECT telling it why
+const SLOW_CONSUMER_DISCONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// One in this many fanned out messages written has its delivery latency
+/// observed
+const DELIVERY_LATENCY_SAMPLE_RATE: u32 = 16;
 
 /// Connection error types
 #[derive(Debug)]
@@ -90,20 +116,47 @@ pub struct Connection<S> {
     pub(crate) encoder: Encoder,
     pub(crate) read_buf: BytesMut,
     pub(crate) write_buf: BytesMut,
+    /// Outbound packets coalesced for one vectored write
+    pub(crate) pending_writes: VecDeque<Bytes>,
+    /// Bytes in `pending_writes`
+    pub(crate) pending_bytes: usize,
     pub(crate) sessions: Arc<SessionStore>,
     pub(crate) subscriptions: Arc<SubscriptionStore>,
-    pub(crate) retained: Arc<DashMap<String, RetainedMessage>>,
-    pub(crate) connections: Arc<DashMap<Arc<str>, mpsc::Sender<Packet>>>,
+    pub(crate) retained: Arc<RetainedStore>,
+    pub(crate) connections: Arc<ConnectionMap>,
     pub(crate) config: BrokerConfig,
     pub(crate) events: broadcast::Sender<BrokerEvent>,
     pub(crate) packet_tx: mpsc::Sender<Packet>,
     pub(crate) packet_rx: mpsc::Receiver<Packet>,
     pub(crate) hooks: Arc<dyn Hooks>,
+    /// Whether the hooks observe deliveries; the delivered hook is not
+    /// called otherwise
+    pub(crate) observes_messages: bool,
     pub(crate) metrics: Option<Arc<Metrics>>,
+    /// Counts the connection in the connection gauges while its client is
+    /// connected
+    pub(crate) gauge: Option<ConnectionGauge>,
     /// Persistence manager for durable storage
     pub(crate) persistence: Option<Arc<crate::persistence::PersistenceManager>>,
-    /// Username from CONNECT packet (for ACL checks)
-    pub(crate) username: Option<String>,
+    /// The client as hooks see it: its address, listener and TLS identity
+    /// from the start, the rest from its CONNECT packet
+    pub(crate) context: ClientContext,
+    /// Tenant of the client, resolved after authentication
+    pub(crate) tenant: Option<Arc<str>>,
+    /// Prefix of every topic the client uses, resolved after authentication
+    pub(crate) mount_point: Option<Arc<str>>,
+    /// Cluster manager, for taking sessions over from other nodes
+    pub(crate) cluster: Option<Arc<ClusterManager>>,
+    /// Redirect of new connections to another server
+    pub(crate) redirect: Arc<ServerRedirect>,
+    /// Slow consumer detection, for the policy applied to this client
+    pub(crate) slow_consumers: Arc<SlowConsumers>,
+    /// Reports of messages not delivered to clients
+    pub(crate) drops: Arc<DropReports>,
+    /// Rules evaluated on the client's publishes
+    pub(crate) rules: Option<Arc<RuleEngine>>,
+    /// Fanned out messages written, for sampling their delivery latency
+    pub(crate) fanned_out_sent: u32,
     /// PROXY protocol info (if connection came through a proxy)
     #[allow(dead_code)]
     pub(crate) proxy_info: Option<ProxyInfo>,
@@ -120,8 +173,8 @@ where
         proxy_info: Option<ProxyInfo>,
         sessions: Arc<SessionStore>,
         subscriptions: Arc<SubscriptionStore>,
-        retained: Arc<DashMap<String, RetainedMessage>>,
-        connections: Arc<DashMap<Arc<str>, mpsc::Sender<Packet>>>,
+        retained: Arc<RetainedStore>,
+        connections: Arc<ConnectionMap>,
         config: BrokerConfig,
         events: broadcast::Sender<BrokerEvent>,
         hooks: Arc<dyn Hooks>,
@@ -129,15 +182,29 @@ where
         persistence: Option<Arc<crate::persistence::PersistenceManager>>,
     ) -> Self {
         let (packet_tx, packet_rx) = mpsc::channel(config.outbound_channel_capacity);
+        let drops = Arc::new(DropReports::new(events.clone()));
+        // A TLS terminating proxy vouches for the certificate it verified
+        let context = ClientContext {
+            cert_cn: proxy_info
+                .as_ref()
+                .and_then(|info| info.tls_info.as_ref())
+                .filter(|tls| tls.client_cert_verified)
+                .and_then(|tls| tls.client_cert_cn.clone()),
+            ..ClientContext::new("", addr)
+        };
 
         Self {
             stream,
             addr,
             state: State::Connecting,
-            decoder: Decoder::new().with_max_packet_size(config.max_packet_size),
+            decoder: Decoder::new()
+                .with_max_packet_size(config.max_packet_size)
+                .with_strict(config.strict),
             encoder: Encoder::default(),
             read_buf: buffer_pool::get_buffer(),
             write_buf: buffer_pool::get_buffer(),
+            pending_writes: VecDeque::new(),
+            pending_bytes: 0,
             sessions,
             subscriptions,
             retained,
@@ -146,14 +213,69 @@ where
             events,
             packet_tx,
             packet_rx,
+            observes_messages: hooks.observes_messages(),
             hooks,
             metrics,
+            gauge: None,
             persistence,
-            username: None,
+            context,
+            tenant: None,
+            mount_point: None,
+            cluster: None,
+            redirect: Arc::new(ServerRedirect::default()),
+            slow_consumers: Arc::new(SlowConsumers::default()),
+            drops,
+            rules: None,
+            fanned_out_sent: 0,
             proxy_info,
         }
     }
 
+    /// Set the listener this connection was accepted on (defaults to TCP)
+    pub fn with_listener(mut self, listener: Listener) -> Self {
+        self.context.listener = listener;
+        self
+    }
+
+    /// Set the Common Name of the client certificate the TLS listener
+    /// verified
+    pub fn with_cert_cn(mut self, cert_cn: Option<String>) -> Self {
+        if cert_cn.is_some() {
+            self.context.cert_cn = cert_cn;
+        }
+        self
+    }
+
+    /// Set the cluster manager used to take sessions over from other nodes
+    pub fn with_cluster(mut self, cluster: Option<Arc<ClusterManager>>) -> Self {
+        self.cluster = cluster;
+        self
+    }
+
+    /// Set the redirect applied to this connection's CONNECT
+    pub fn with_redirect(mut self, redirect: Arc<ServerRedirect>) -> Self {
+        self.redirect = redirect;
+        self
+    }
+
+    /// Set the slow consumer detection this connection is subject to
+    pub fn with_slow_consumers(mut self, slow_consumers: Arc<SlowConsumers>) -> Self {
+        self.slow_consumers = slow_consumers;
+        self
+    }
+
+    /// Set the rules evaluated on this client's publishes
+    /// Set where messages not delivered to clients are reported
+    pub fn with_drops(mut self, drops: Arc<DropReports>) -> Self {
+        self.drops = drops;
+        self
+    }
+
+    pub fn with_rules(mut self, rules: Arc<RuleEngine>) -> Self {
+        self.rules = Some(rules);
+        self
+    }
+
     /// Run the connection handler
     pub async fn run(&mut self) -> Result<(), ConnectionError> {
         // Wait for CONNECT packet with timeout
@@ -205,6 +327,20 @@ where
         // Track keep-alive deadline (reset when packets received)
         let mut keep_alive_deadline = tokio::time::Instant::now() + keep_alive;
 
+        // Clients that neither subscribe nor publish in time are disconnected
+        let parked_timeout = self.config.parked_timeout;
+        let mut parked_deadline =
+            (!parked_timeout.is_zero()).then(|| tokio::time::Instant::now() + parked_timeout);
+
+        // Slow consumers are told apart by their outbound channel
+        let slow_consumers = self.slow_consumers.clone();
+        let mut flagged_rx = slow_consumers.subscribe();
+        let outbound = self.packet_tx.clone();
+
+        // Coalesced packets wait at most until then for more
+        let coalesce_delay = self.config.write_coalesce_delay;
+        let mut flush_deadline: Option<tokio::time::Instant> = None;
+
         loop {
             tokio::select! {
                 // Read from socket
@@ -213,22 +349,51 @@ where
                         Ok(0) => {
                             // Connection closed
                             debug!("Connection closed from {}", self.addr);
-                            self.handle_disconnect(&client_id, &session, true).await;
+                            self.handle_disconnect(&client_id, &session, DisconnectReason::ConnectionLost).await;
                             return Ok(());
                         }
                         Ok(_) => {
-                            // Process packets
-                            while let Some((packet, consumed)) = self.decoder.decode(&self.read_buf)? {
+                            // Packets coalesced so far go out before any reply
+                            if let Err(e) = self.flush_writes().await {
+                                debug!("Write error: {}", e);
+                                self.handle_disconnect(&client_id, &session, DisconnectReason::ConnectionLost).await;
+                                return Err(e.into());
+                            }
+                            flush_deadline = None;
+
+                            // Process every complete packet of the read, recording
+                            // them on the session once at the end
+                            let mut activity = InboundActivity::default();
+                            loop {
+                                let (packet, consumed, raw) = match self.decoder.decode_raw(&self.read_buf) {
+                                    Ok(Some(decoded)) => decoded,
+                                    Ok(None) => break,
+                                    Err(crate::protocol::DecodeError::PacketTooLarge) => {
+                                        // Over the Maximum Packet Size advertised in CONNACK
+                                        // [MQTT-3.2.2-15]
+                                        debug!("Packet from {} exceeds maximum packet size", client_id);
+                                        if let Some(ref metrics) = self.metrics {
+                                            metrics.packet_too_large("inbound");
+                                        }
+                                        activity.flush(&session);
+                                        self.send_disconnect(crate::protocol::ReasonCode::PacketTooLarge).await;
+                                        self.handle_disconnect(&client_id, &session, DisconnectReason::ProtocolError).await;
+                                        return Err(crate::protocol::DecodeError::PacketTooLarge.into());
+                                    }
+                                    Err(e) => return Err(e.into()),
+                                };
                                 self.read_buf.advance(consumed);
 
-                                // Update activity timestamp and reset keep-alive deadline
-                                {
-                                    let mut s = session.write();
-                                    s.touch();
+                                activity.add_packet();
+                                if let Packet::Publish(ref publish) = packet {
+                                    activity.add_publish(publish.payload.len());
+                                }
+                                if matches!(packet, Packet::Publish(_) | Packet::Subscribe(_)) {
+                                    parked_deadline = None;
                                 }
-                                keep_alive_deadline = tokio::time::Instant::now() + keep_alive;
 
-                                if let Err(e) = self.handle_packet(&client_id, &session, packet).await {
+                                if let Err(e) = self.handle_packet(&client_id, &session, packet, raw).await {
+                                    activity.flush(&session);
                                     match &e {
                                         ConnectionError::Shutdown => {
                                             // Normal disconnect, already handled in handle_packet
@@ -237,21 +402,27 @@ where
                                         ConnectionError::Io(_) => {
                                             // IO errors (broken pipe, etc.) are normal during disconnect
                                             debug!("Connection error: {}", e);
-                                            self.handle_disconnect(&client_id, &session, true).await;
+                                            self.handle_disconnect(&client_id, &session, DisconnectReason::ConnectionLost).await;
                                             return Err(e);
                                         }
                                         _ => {
                                             error!("Error handling packet: {}", e);
-                                            self.handle_disconnect(&client_id, &session, true).await;
+                                            self.handle_disconnect(&client_id, &session, DisconnectReason::ProtocolError).await;
                                             return Err(e);
                                         }
                                     }
                                 }
                             }
+
+                            // Update activity timestamp and reset keep-alive deadline
+                            if !activity.is_empty() {
+                                activity.flush(&session);
+                                keep_alive_deadline = tokio::time::Instant::now() + keep_alive;
+                            }
                         }
                         Err(e) => {
                             debug!("Read error: {}", e);
-                            self.handle_disconnect(&client_id, &session, true).await;
+                            self.handle_disconnect(&client_id, &session, DisconnectReason::ConnectionLost).await;
                             return Err(e.into());
                         }
                     }
@@ -259,89 +430,373 @@ where
 
                 // Receive packets to send
                 Some(packet) = self.packet_rx.recv() => {
-                    self.handle_outgoing_packet(&session, packet).await?;
+                    let outgoing = async {
+                        // Coalesce the packets already waiting, up to the
+                        // flush threshold
+                        let mut next = Some(packet);
+                        while let Some(packet) = next.take() {
+                            if self.config.topic_priorities.is_empty() {
+                                self.handle_outgoing_packet(&client_id, &session, packet).await?;
+                            } else {
+                                for packet in self.prioritized_outgoing(packet) {
+                                    self.handle_outgoing_packet(&client_id, &session, packet).await?;
+                                }
+                            }
+                            if self.pending_bytes < self.config.write_coalesce_bytes {
+                                next = self.packet_rx.try_recv().ok();
+                            }
+                        }
+                        if coalesce_delay.is_zero()
+                            || self.pending_bytes >= self.config.write_coalesce_bytes
+                        {
+                            self.flush_writes().await?;
+                        }
+                        Ok::<_, ConnectionError>(())
+                    };
+                    tokio::select! {
+                        result = outgoing => result?,
+                        // A write stuck on a slow consumer is abandoned
+                        _ = slow_consumers.disconnect_due(&client_id, &outbound, &mut flagged_rx) => {
+                            return Err(self.disconnect_slow_consumer(&client_id, &session, false).await);
+                        }
+                    }
+                    if self.pending_writes.is_empty() {
+                        flush_deadline = None;
+                    } else if flush_deadline.is_none() {
+                        flush_deadline = Some(tokio::time::Instant::now() + coalesce_delay);
+                    }
+                }
+
+                // Coalesced packets out of time to wait for more
+                _ = tokio::time::sleep_until(flush_deadline.unwrap_or(keep_alive_deadline)), if flush_deadline.is_some() => {
+                    flush_deadline = None;
+                    tokio::select! {
+                        result = self.flush_writes() => result?,
+                        _ = slow_consumers.disconnect_due(&client_id, &outbound, &mut flagged_rx) => {
+                            return Err(self.disconnect_slow_consumer(&client_id, &session, false).await);
+                        }
+                    }
+                }
+
+                // Slow consumer under the disconnect policy
+                _ = slow_consumers.disconnect_due(&client_id, &outbound, &mut flagged_rx) => {
+                    return Err(self.disconnect_slow_consumer(&client_id, &session, true).await);
                 }
 
                 // Retry unacked messages
                 _ = retry_ticker.tick() => {
-                    self.retry_unacked_messages(&session).await?;
+                    self.flush_writes().await?;
+                    flush_deadline = None;
+                    self.retry_unacked_messages(&client_id, &session).await?;
                 }
 
                 // Keep alive timeout
                 _ = tokio::time::sleep_until(keep_alive_deadline) => {
                     info!("Keep alive timeout for {} - disconnecting", client_id);
-                    // For MQTT v5, send DISCONNECT with KeepAliveTimeout reason before closing
-                    if self.decoder.protocol_version() == Some(crate::protocol::ProtocolVersion::V5) {
-                        let disconnect = crate::protocol::Disconnect {
-                            reason_code: crate::protocol::ReasonCode::KeepAliveTimeout,
-                            properties: crate::protocol::Properties::default(),
-                        };
-                        self.write_buf.clear();
-                        if self.encoder.encode(&Packet::Disconnect(disconnect), &mut self.write_buf).is_ok() {
-                            let _ = self.stream.write_all(&self.write_buf).await;
-                            let _ = self.stream.flush().await;
-                        }
-                    }
-                    self.handle_disconnect(&client_id, &session, true).await;
+                    self.send_disconnect(crate::protocol::ReasonCode::KeepAliveTimeout).await;
+                    self.handle_disconnect(&client_id, &session, DisconnectReason::KeepAliveTimeout).await;
+                    return Err(ConnectionError::Timeout);
+                }
+
+                // Parked connection timeout
+                _ = tokio::time::sleep_until(parked_deadline.unwrap_or(keep_alive_deadline)), if parked_deadline.is_some() => {
+                    info!("{} neither subscribed nor published within {:?} - disconnecting", client_id, parked_timeout);
+                    self.send_disconnect(crate::protocol::ReasonCode::AdministrativeAction).await;
+                    self.handle_disconnect(&client_id, &session, DisconnectReason::Parked).await;
                     return Err(ConnectionError::Timeout);
                 }
             }
         }
     }
 
+    /// Take the PUBLISH packets already waiting in the outbound channel along
+    /// with `first`, ordered by topic priority so high priority messages are
+    /// not stuck behind a backlog of bulk ones
+    ///
+    /// Other packets end the batch and keep their place after it.
+    fn prioritized_outgoing(&mut self, first: Packet) -> Vec<Packet> {
+        let mut batch = vec![first];
+        while matches!(
+            batch.last(),
+            Some(Packet::Publish(_) | Packet::SharedPublish(_))
+        ) && batch.len() < PRIORITY_BATCH_SIZE
+        {
+            match self.packet_rx.try_recv() {
+                Ok(packet) => batch.push(packet),
+                Err(_) => break,
+            }
+        }
+
+        let publishes = match batch.last() {
+            Some(Packet::Publish(_) | Packet::SharedPublish(_)) => batch.len(),
+            _ => batch.len() - 1,
+        };
+        let rules = &self.config.topic_priorities;
+        // Stable, so messages of the same priority keep their order
+        batch[..publishes].sort_by_key(|packet| match packet {
+            Packet::Publish(publish) => std::cmp::Reverse(topic_priority(rules, &publish.topic)),
+            Packet::SharedPublish(shared) => {
+                std::cmp::Reverse(topic_priority(rules, &shared.publish().topic))
+            }
+            _ => std::cmp::Reverse(MessagePriority::Normal),
+        });
+        batch
+    }
+
+    /// For MQTT v5, send a DISCONNECT with the reason the server closes the
+    /// connection; errors are ignored as the connection is closing anyway
+    pub(crate) async fn send_disconnect(&mut self, reason_code: crate::protocol::ReasonCode) {
+        let _ = self.flush_writes().await;
+        if self.decoder.protocol_version() != Some(crate::protocol::ProtocolVersion::V5) {
+            return;
+        }
+        let disconnect = crate::protocol::Disconnect {
+            reason_code,
+            properties: crate::protocol::Properties::default(),
+        };
+        self.write_buf.clear();
+        if self
+            .encoder
+            .encode(&Packet::Disconnect(disconnect), &mut self.write_buf)
+            .is_ok()
+        {
+            let _ = self.stream.write_all(&self.write_buf).await;
+            let _ = self.stream.flush().await;
+        }
+    }
+
+    /// Report a message lost to queue overflow; returns true when the
+    /// disconnect overflow policy requires ending the connection
+    pub(crate) fn record_queue_result(
+        &self,
+        client_id: &Arc<str>,
+        result: &QueueResult,
+        context: &str,
+    ) -> bool {
+        if let Some(topic) = result.dropped_topic() {
+            warn!(client_id = %client_id, "message dropped - queue full ({})", context);
+            report_dropped(&self.drops, client_id, topic, DropReason::QueueFull);
+        }
+        matches!(result, QueueResult::Overflow(_))
+    }
+
+    /// Disconnect a client whose queue overflowed with Quota Exceeded
+    pub(crate) async fn disconnect_queue_overflow(
+        &mut self,
+        client_id: &Arc<str>,
+        session: &Arc<RwLock<Session>>,
+    ) -> ConnectionError {
+        self.send_disconnect(crate::protocol::ReasonCode::QuotaExceeded)
+            .await;
+        self.handle_disconnect(client_id, session, DisconnectReason::QueueOverflow)
+            .await;
+        ConnectionError::Shutdown
+    }
+
+    /// Disconnect a slow consumer under the disconnect policy. With `notify`,
+    /// it is sent Quota Exceeded if it reads that in time; without, a write
+    /// to it was abandoned midway and nothing more can be sent.
+    async fn disconnect_slow_consumer(
+        &mut self,
+        client_id: &Arc<str>,
+        session: &Arc<RwLock<Session>>,
+        notify: bool,
+    ) -> ConnectionError {
+        warn!(client_id = %client_id, "disconnecting slow consumer");
+        if notify {
+            let _ = timeout(
+                SLOW_CONSUMER_DISCONNECT_TIMEOUT,
+                self.send_disconnect(crate::protocol::ReasonCode::QuotaExceeded),
+            )
+            .await;
+        }
+        self.handle_disconnect(client_id, session, DisconnectReason::SlowConsumer)
+            .await;
+        ConnectionError::Shutdown
+    }
+
+    /// Account for a message queued for this client, disconnecting it if
+    /// the queue overflowed under the disconnect policy
+    async fn handle_queue_result(
+        &mut self,
+        client_id: &Arc<str>,
+        session: &Arc<RwLock<Session>>,
+        result: QueueResult,
+        context: &str,
+    ) -> Result<(), ConnectionError> {
+        if self.record_queue_result(client_id, &result, context) {
+            return Err(self.disconnect_queue_overflow(client_id, session).await);
+        }
+        Ok(())
+    }
+
+    /// Discard a PUBLISH too large for the client, behaving as if it had been
+    /// delivered [MQTT-3.1.2-25] so it does not hold an inflight slot
+    pub(crate) fn discard_oversized_publish(
+        &self,
+        session: &Arc<RwLock<Session>>,
+        packet_id: Option<u16>,
+    ) {
+        if let Some(packet_id) = packet_id {
+            let mut s = session.write();
+            if s.inflight_outgoing.remove(&packet_id).is_some() {
+                s.increment_send_quota();
+            }
+        }
+        if let Some(ref metrics) = self.metrics {
+            metrics.packet_too_large("outbound");
+        }
+    }
+
     /// Handle outgoing packet from the channel
     async fn handle_outgoing_packet(
         &mut self,
+        client_id: &Arc<str>,
         session: &Arc<RwLock<Session>>,
         packet: Packet,
     ) -> Result<(), ConnectionError> {
-        use crate::protocol::QoS;
-        use crate::session::{InflightMessage, Qos2State, QueueResult};
-
         match packet {
-            Packet::Disconnect(_) => {
-                // We're being disconnected (session takeover)
-                // Per MQTT spec, after sending DISCONNECT, we must close the connection
-                self.write_buf.clear();
-                let _ = self.encoder.encode(&packet, &mut self.write_buf);
-                let _ = self.stream.write_all(&self.write_buf).await;
+            Packet::Disconnect(ref disconnect) => {
+                // We're being disconnected (session takeover or redirect)
+                // Per MQTT spec, after sending DISCONNECT, we must close the
+                // connection; MQTT v3.1.1 clients are closed without one
+                let taken_over =
+                    disconnect.reason_code == crate::protocol::ReasonCode::SessionTakenOver;
+                if self.decoder.protocol_version() == Some(crate::protocol::ProtocolVersion::V5) {
+                    self.write_buf.clear();
+                    if self.encoder.encode(&packet, &mut self.write_buf).is_ok() {
+                        self.queue_write_buf();
+                    }
+                }
+                let _ = self.flush_writes().await;
+                if taken_over {
+                    // The new connection owns the client's entry and session
+                    self.notify_disconnected(client_id, DisconnectReason::Takeover)
+                        .await;
+                } else {
+                    self.handle_disconnect(client_id, session, DisconnectReason::Redirected)
+                        .await;
+                }
                 // Return Shutdown to terminate the connection loop
                 Err(ConnectionError::Shutdown)
             }
-            Packet::Publish(mut publish) => {
-                // Get max packet size from session
-                let max_packet_size = {
-                    let s = session.read();
-                    s.max_packet_size
-                };
+            Packet::Publish(publish) => self.send_publish(client_id, session, publish, None).await,
+            Packet::SharedPublish(shared) => {
+                self.send_shared_publish(client_id, session, shared).await
+            }
+            _ => {
+                self.write_buf.clear();
+                self.encoder
+                    .encode(&packet, &mut self.write_buf)
+                    .map_err(|e| ConnectionError::Protocol(e.into()))?;
+                self.queue_write_buf();
+                Ok(())
+            }
+        }
+    }
 
-                // Per MQTT v5.0 spec [MQTT-4.9.0-2]: MUST NOT send QoS>0
-                // PUBLISH when send quota is 0
-                if publish.qos != QoS::AtMostOnce {
-                    let mut s = session.write();
-                    if !s.decrement_send_quota() {
-                        // Quota exhausted - queue message for later delivery
-                        debug!("Send quota exhausted for {}, queuing message", s.client_id);
-                        if s.queue_message(publish) == QueueResult::DroppedOldest {
-                            warn!(client_id = %s.client_id, "message dropped - queue full (quota exhausted)");
-                            let _ = self.events.send(BrokerEvent::MessageDropped);
-                        }
-                        return Ok(());
-                    }
-                    // Check max_inflight limit
-                    if s.inflight_outgoing.len() >= s.max_inflight as usize {
-                        // Inflight limit reached - queue and restore quota
-                        s.increment_send_quota();
-                        debug!(
-                            "Inflight limit ({}) reached for {}, queuing message",
-                            s.max_inflight, s.client_id
-                        );
-                        if s.queue_message(publish) == QueueResult::DroppedOldest {
-                            warn!(client_id = %s.client_id, "message dropped - queue full (inflight limit)");
-                            let _ = self.events.send(BrokerEvent::MessageDropped);
-                        }
-                        return Ok(());
-                    }
+    /// Queue the packet encoded in `write_buf` to be written along with the
+    /// packets coalesced before it
+    fn queue_write_buf(&mut self) {
+        let frame = self.write_buf.split().freeze();
+        self.queue_frame(frame);
+    }
+
+    fn queue_frame(&mut self, frame: Bytes) {
+        self.pending_bytes += frame.len();
+        self.pending_writes.push_back(frame);
+    }
+
+    /// Write the coalesced packets, as many at once as the stream takes
+    pub(crate) async fn flush_writes(&mut self) -> std::io::Result<()> {
+        while !self.pending_writes.is_empty() {
+            let mut written = {
+                let slices: SmallVec<[IoSlice<'_>; MAX_WRITE_SLICES]> = self
+                    .pending_writes
+                    .iter()
+                    .take(MAX_WRITE_SLICES)
+                    .map(|frame| IoSlice::new(frame))
+                    .collect();
+                self.stream.write_vectored(&slices).await?
+            };
+            if written == 0 {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+            self.pending_bytes -= written;
+            while written > 0 {
+                let frame = self
+                    .pending_writes
+                    .front_mut()
+                    .expect("written bytes were queued");
+                if written < frame.len() {
+                    bytes::Buf::advance(frame, written);
+                    break;
+                }
+                written -= frame.len();
+                self.pending_writes.pop_front();
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a PUBLISH from the channel, or queue it under flow control;
+    /// `shared` is the encoding it was fanned out with, if any
+    async fn send_publish(
+        &mut self,
+        client_id: &Arc<str>,
+        session: &Arc<RwLock<Session>>,
+        mut publish: Publish,
+        shared: Option<&SharedPublish>,
+    ) -> Result<(), ConnectionError> {
+        use crate::session::{InflightMessage, Qos2State};
+
+        // With ordered delivery nothing overtakes the messages queued
+        // for flow control
+        if self.config.ordered_delivery {
+            // The lock is released before awaiting
+            let queued = {
+                let mut s = session.write();
+                if s.has_queued() {
+                    Err(s.queue_message(publish))
+                } else {
+                    Ok(publish)
+                }
+            };
+            publish = match queued {
+                Ok(publish) => publish,
+                Err(result) => {
+                    return self
+                        .handle_queue_result(client_id, session, result, "ordered delivery")
+                        .await;
+                }
+            };
+        }
+
+        // Get max packet size from session
+        let max_packet_size = {
+            let s = session.read();
+            s.max_packet_size
+        };
+
+        // Per MQTT v5.0 spec [MQTT-4.9.0-2]: MUST NOT send QoS>0
+        // PUBLISH when send quota is 0
+        if publish.qos != QoS::AtMostOnce {
+            // The lock is released before awaiting
+            let queued = {
+                let mut s = session.write();
+                if !s.decrement_send_quota() {
+                    // Quota exhausted - queue message for later delivery
+                    debug!("Send quota exhausted for {}, queuing message", s.client_id);
+                    Err((s.queue_message(publish), "quota exhausted"))
+                } else if s.inflight_outgoing.len() >= s.max_inflight as usize {
+                    // Inflight limit reached - queue and restore quota
+                    s.increment_send_quota();
+                    debug!(
+                        "Inflight limit ({}) reached for {}, queuing message",
+                        s.max_inflight, s.client_id
+                    );
+                    Err((s.queue_message(publish), "inflight limit"))
+                } else {
                     // Assign packet ID
                     if publish.packet_id.is_none() {
                         publish.packet_id = Some(s.next_packet_id());
@@ -363,48 +818,160 @@ where
                             },
                         );
                     }
+                    Ok(publish)
                 }
+            };
+            publish = match queued {
+                Ok(publish) => publish,
+                Err((result, reason)) => {
+                    return self
+                        .handle_queue_result(client_id, session, result, reason)
+                        .await;
+                }
+            };
+        }
 
-                self.write_buf.clear();
-                self.encoder
-                    .encode(&Packet::Publish(publish), &mut self.write_buf)
-                    .map_err(|e| ConnectionError::Protocol(e.into()))?;
+        let packet_id = publish.packet_id;
+        // QoS 1 and 2 messages are delivered once acknowledged
+        let delivered_topic = (self.observes_messages && publish.qos == QoS::AtMostOnce)
+            .then(|| publish.topic.clone());
 
-                // Per MQTT v5.0 spec [MQTT-3.1.2-24]: MUST NOT send packets
-                // exceeding client's Maximum Packet Size
-                if self.write_buf.len() > max_packet_size as usize {
-                    warn!(
-                        "Dropping PUBLISH: encoded size {} exceeds client max {}",
-                        self.write_buf.len(),
-                        max_packet_size
-                    );
-                    return Ok(());
-                }
+        // Send repeated topics as Topic Aliases; the inflight copy
+        // keeps the topic, as aliases do not survive a reconnect
+        let alias = session.write().get_or_create_topic_alias(&publish.topic);
+        let mut new_alias = None;
+        if let Some((alias, created)) = alias {
+            publish.properties.topic_alias = Some(alias);
+            if created {
+                new_alias = Some(publish.topic.clone());
+            } else {
+                publish.topic = Topic::default();
+            }
+        }
 
-                let bytes_sent = self.write_buf.len();
-                self.stream.write_all(&self.write_buf).await?;
-                if let Some(ref metrics) = self.metrics {
-                    metrics.publish_sent(bytes_sent);
-                }
-                Ok(())
+        self.write_buf.clear();
+        match shared {
+            // The shared encoding has the full topic
+            Some(shared) if alias.is_none() && self.mount_point.is_none() => shared.encode(
+                self.encoder.protocol_version(),
+                packet_id,
+                &mut self.write_buf,
+            ),
+            _ => self
+                .encoder
+                .encode(&Packet::Publish(publish), &mut self.write_buf),
+        }
+        .map_err(|e| ConnectionError::Protocol(e.into()))?;
+
+        // Per MQTT v5.0 spec [MQTT-3.1.2-24]: MUST NOT send packets
+        // exceeding client's Maximum Packet Size
+        if self.write_buf.len() > max_packet_size as usize {
+            warn!(
+                "Dropping PUBLISH: encoded size {} exceeds client max {}",
+                self.write_buf.len(),
+                max_packet_size
+            );
+            // The client never learned the alias
+            if let Some(topic) = new_alias {
+                session.write().server_topic_aliases.remove(topic.as_str());
             }
-            _ => {
-                self.write_buf.clear();
-                self.encoder
-                    .encode(&packet, &mut self.write_buf)
-                    .map_err(|e| ConnectionError::Protocol(e.into()))?;
-                self.stream.write_all(&self.write_buf).await?;
-                Ok(())
+            self.discard_oversized_publish(session, packet_id);
+            return Ok(());
+        }
+
+        let bytes_sent = self.write_buf.len();
+        self.queue_write_buf();
+        self.record_publish_sent(session, bytes_sent, shared.map(|shared| shared.created()));
+        if let Some(topic) = delivered_topic {
+            self.hooks
+                .on_message_delivered(&self.context, &topic, QoS::AtMostOnce)
+                .await;
+        }
+        Ok(())
+    }
+
+    /// Send a fanned out PUBLISH; QoS 0 goes out as the shared encoding
+    /// without a copy of its own, unless this connection has to rewrite the
+    /// topic or hold the message back
+    async fn send_shared_publish(
+        &mut self,
+        client_id: &Arc<str>,
+        session: &Arc<RwLock<Session>>,
+        shared: Arc<SharedPublish>,
+    ) -> Result<(), ConnectionError> {
+        let max_packet_size = {
+            let s = session.read();
+            let direct = shared.publish().qos == QoS::AtMostOnce
+                && self.mount_point.is_none()
+                && s.topic_alias_maximum == 0
+                && !(self.config.ordered_delivery && s.has_queued());
+            direct.then_some(s.max_packet_size)
+        };
+        let Some(max_packet_size) = max_packet_size else {
+            let publish = shared.publish().clone();
+            return self
+                .send_publish(client_id, session, publish, Some(&shared))
+                .await;
+        };
+
+        // Written straight from the shared encoding
+        let [header, rest] = shared
+            .frames(self.encoder.protocol_version())
+            .map_err(|e| ConnectionError::Protocol(e.into()))?;
+        let bytes_sent = header.len() + rest.len();
+        if bytes_sent > max_packet_size as usize {
+            warn!(
+                "Dropping PUBLISH: encoded size {} exceeds client max {}",
+                bytes_sent, max_packet_size
+            );
+            self.discard_oversized_publish(session, None);
+            return Ok(());
+        }
+
+        self.queue_frame(header);
+        self.queue_frame(rest);
+        self.record_publish_sent(session, bytes_sent, Some(shared.created()));
+        if self.observes_messages {
+            self.hooks
+                .on_message_delivered(&self.context, &shared.publish().topic, QoS::AtMostOnce)
+                .await;
+        }
+        Ok(())
+    }
+
+    /// Count a PUBLISH written to the socket, sampling the delivery latency
+    /// of fanned out messages queued at `queued_at`
+    pub(crate) fn record_publish_sent(
+        &mut self,
+        session: &Arc<RwLock<Session>>,
+        bytes_sent: usize,
+        queued_at: Option<Instant>,
+    ) {
+        session.read().record_publish_sent(bytes_sent);
+        let Some(ref metrics) = self.metrics else {
+            return;
+        };
+        metrics.publish_sent(bytes_sent);
+        if let Some(queued_at) = queued_at {
+            self.fanned_out_sent = self.fanned_out_sent.wrapping_add(1);
+            if self
+                .fanned_out_sent
+                .is_multiple_of(DELIVERY_LATENCY_SAMPLE_RATE)
+            {
+                metrics
+                    .delivery_latency
+                    .observe(queued_at.elapsed().as_secs_f64());
             }
         }
     }
 
-    /// Handle an incoming packet
+    /// Handle an incoming packet, with its wire bytes if it is a PUBLISH
     async fn handle_packet(
         &mut self,
         client_id: &Arc<str>,
         session: &Arc<RwLock<Session>>,
         packet: Packet,
+        raw: Option<RawPublish>,
     ) -> Result<(), ConnectionError> {
         match packet {
             Packet::Connect(_) => {
@@ -413,7 +980,10 @@ where
                     crate::protocol::ProtocolError::ProtocolViolation("duplicate CONNECT"),
                 ))
             }
-            Packet::Publish(publish) => self.handle_publish(client_id, session, publish).await,
+            Packet::Publish(publish) => {
+                self.handle_publish(client_id, session, publish, raw.as_ref())
+                    .await
+            }
             Packet::PubAck(puback) => self.handle_puback(session, puback).await,
             Packet::PubRec(pubrec) => self.handle_pubrec(session, pubrec).await,
             Packet::PubRel(pubrel) => self.handle_pubrel(client_id, session, pubrel).await,
@@ -440,11 +1010,14 @@ where
                 );
                 // Per MQTT v5.0 spec [MQTT-3.1.2-10]:
                 // - Reason 0x00 (Normal): will message MUST be deleted, NOT published
-                // - Reason 0x04 (DisconnectWithWill): will message MUST still be published
-                let publish_will =
-                    disconnect.reason_code == crate::protocol::ReasonCode::DisconnectWithWill;
-                self.handle_disconnect(client_id, session, publish_will)
-                    .await;
+                // - Any other reason, such as 0x04 (DisconnectWithWill): will
+                //   message MUST still be published
+                let reason = if disconnect.reason_code == crate::protocol::ReasonCode::Success {
+                    DisconnectReason::Normal
+                } else {
+                    DisconnectReason::WithWill
+                };
+                self.handle_disconnect(client_id, session, reason).await;
                 Err(ConnectionError::Shutdown)
             }
             _ => {
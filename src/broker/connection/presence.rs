@@ -0,0 +1,113 @@
+//! Client presence topics
+//!
+//! With `presence_topics`, each client's state is kept as a retained
+//! message under `$events/clients/<client_id>/`: `connected` with the
+//! client's address and protocol version, or `disconnected` with the
+//! reason. Publishing one clears the other, so a subscriber to
+//! `$events/clients/+/+` sees exactly one message per client, for its
+//! latest state. Session takeovers publish nothing, as the client stays
+//! connected.
+
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use serde_json::json;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::debug;
+
+use super::Connection;
+use crate::broker::{DisconnectReason, RetainedPublish};
+use crate::protocol::{Properties, ProtocolVersion, Publish, QoS};
+use crate::topic::Topic;
+
+/// Topic prefix of the presence topics
+pub const PRESENCE_TOPIC_PREFIX: &str = "$events/clients";
+
+/// A state change of a client
+pub(crate) enum Presence {
+    Connected(ProtocolVersion),
+    Disconnected(DisconnectReason),
+}
+
+impl<S> Connection<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Publish the presence message of a client and clear that of its
+    /// previous state
+    pub(crate) async fn publish_presence(&self, client_id: &Arc<str>, presence: Presence) {
+        if client_id.contains(['+', '#']) {
+            debug!(
+                "No presence topics for {}: not a valid topic level",
+                client_id
+            );
+            return;
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let (state, cleared, payload) = match presence {
+            Presence::Connected(protocol_version) => (
+                "connected",
+                "disconnected",
+                json!({
+                    "client_id": client_id.as_ref(),
+                    "timestamp": timestamp,
+                    "ip": self.addr.ip().to_string(),
+                    "protocol_version": protocol_version as u8,
+                }),
+            ),
+            Presence::Disconnected(reason) => (
+                "disconnected",
+                "connected",
+                json!({
+                    "client_id": client_id.as_ref(),
+                    "timestamp": timestamp,
+                    "reason": reason.as_str(),
+                }),
+            ),
+        };
+
+        // An empty retained message removes the previous state
+        self.retained
+            .apply_publish_async(
+                RetainedPublish {
+                    topic: &Topic::from(format!(
+                        "{}/{}/{}",
+                        PRESENCE_TOPIC_PREFIX, client_id, cleared
+                    )),
+                    payload: Bytes::new(),
+                    qos: QoS::AtMostOnce,
+                    properties: Properties::default(),
+                    tenant: self.tenant.clone(),
+                },
+                self.persistence.as_ref(),
+            )
+            .await;
+
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            retain: true,
+            topic: format!("{}/{}/{}", PRESENCE_TOPIC_PREFIX, client_id, state).into(),
+            packet_id: None,
+            payload: Bytes::from(payload.to_string()),
+            properties: Properties::default(),
+        };
+        self.retained
+            .apply_publish_async(
+                RetainedPublish {
+                    topic: &publish.topic,
+                    payload: publish.payload.clone(),
+                    qos: publish.qos,
+                    properties: Properties::default(),
+                    tenant: self.tenant.clone(),
+                },
+                self.persistence.as_ref(),
+            )
+            .await;
+        let _ = self.route_message(client_id, &publish, None, None).await;
+    }
+}
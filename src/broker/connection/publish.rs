@@ -3,57 +3,98 @@
 use std::sync::Arc;
 use std::time::Instant;
 
-use ahash::AHashMap;
 use parking_lot::RwLock;
 use smallvec::SmallVec;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
-use tracing::{debug, error, trace, warn};
+use tracing::{debug, error, info_span, trace, warn, Instrument};
 
 use super::{Connection, ConnectionError};
-use crate::broker::{BrokerEvent, RetainedMessage};
-use crate::persistence::{PersistenceOp, StoredRetainedMessage};
-use crate::protocol::{Packet, Properties, PubAck, PubRec, Publish, QoS, ReasonCode};
+use crate::bridge::BridgeOrigin;
+use crate::broker::router::{group_by_client, FanOut, FANOUT_BATCH_SIZE};
+use crate::broker::{report_dropped, BrokerConfig, BrokerEvent, DropReason, RetainedPublish};
+use crate::codec::RawPublish;
+use crate::config::{InvalidPayloadAction, SharedDeliveryStrategy, SyncMode};
+use crate::hooks::{HookDecision, PublishTransform};
+use crate::persistence::{tenant_key, PersistenceOp, StoredPendingMessage, StoredSession};
+use crate::protocol::{
+    Packet, Properties, ProtocolVersion, PubAck, PubRec, Publish, QoS, ReasonCode,
+};
 use crate::session::{QueueResult, Session};
-use crate::topic::validate_topic_name_with_max_levels;
+use crate::topic::{is_reserved_topic, topic_matches_filter, validate_topic_name_with_max_levels};
+
+/// Why a publish's payload does not match the format it declares or the
+/// Content Type its topic requires; the first matching rule applies
+fn payload_format_error(config: &BrokerConfig, publish: &Publish) -> Option<&'static str> {
+    if config.validate_payload_format
+        && publish.properties.payload_format_indicator == Some(1)
+        && std::str::from_utf8(&publish.payload).is_err()
+    {
+        return Some("payload is not valid UTF-8");
+    }
+    let rule = config
+        .content_types
+        .iter()
+        .find(|rule| topic_matches_filter(&publish.topic, &rule.topic))?;
+    match publish.properties.content_type.as_deref() {
+        Some(content_type) if rule.content_types.iter().any(|c| c == content_type) => None,
+        _ => Some("content type not accepted"),
+    }
+}
+
+/// Payload size limit of the first rule matching a publish's topic, if
+/// it is exceeded
+fn payload_limit_exceeded(config: &BrokerConfig, publish: &Publish) -> Option<usize> {
+    let rule = config
+        .payload_limits
+        .iter()
+        .find(|rule| topic_matches_filter(&publish.topic, &rule.topic))?;
+    (rule.max_payload_size > 0 && publish.payload.len() > rule.max_payload_size)
+        .then_some(rule.max_payload_size)
+}
 
 impl<S> Connection<S>
 where
     S: AsyncRead + AsyncWrite + Unpin,
 {
-    /// Handle PUBLISH packet
+    /// Handle PUBLISH packet; `raw` holds the wire bytes it was received with
     pub(crate) async fn handle_publish(
         &mut self,
         client_id: &Arc<str>,
         session: &Arc<RwLock<Session>>,
         mut publish: Publish,
+        raw: Option<&RawPublish>,
     ) -> Result<(), ConnectionError> {
+        // The client may have at most Receive Maximum QoS 1/2 publishes
+        // unacknowledged; QoS 1 is acknowledged before the next packet is
+        // read, so only this publish and those awaiting PUBREL count. A
+        // resend of a publish awaiting PUBREL is not a new one
+        if let Some(packet_id) = publish.packet_id {
+            let exceeded = {
+                let s = session.read();
+                s.protocol_version == ProtocolVersion::V5
+                    && !s.inflight_incoming.contains_key(&packet_id)
+                    && s.inflight_incoming.len() >= self.config.receive_maximum as usize
+            };
+            if exceeded {
+                warn!(
+                    "Client {} exceeded Receive Maximum {} - disconnecting",
+                    client_id, self.config.receive_maximum
+                );
+                self.send_disconnect(ReasonCode::ReceiveMaxExceeded).await;
+                return Err(ConnectionError::Protocol(
+                    crate::protocol::ProtocolError::ProtocolViolation("receive maximum exceeded"),
+                ));
+            }
+        }
+
         // Validate topic name
         if let Err(e) =
             validate_topic_name_with_max_levels(&publish.topic, self.config.max_topic_levels)
         {
             warn!("Invalid topic name from {}: {}", client_id, e);
             // For v5.0, send PUBACK/PUBREC with error
-            if publish.qos != QoS::AtMostOnce {
-                let packet_id = publish.packet_id.unwrap();
-                let response = if publish.qos == QoS::AtLeastOnce {
-                    Packet::PubAck(PubAck {
-                        packet_id,
-                        reason_code: ReasonCode::TopicNameInvalid,
-                        properties: Properties::default(),
-                    })
-                } else {
-                    Packet::PubRec(PubRec {
-                        packet_id,
-                        reason_code: ReasonCode::TopicNameInvalid,
-                        properties: Properties::default(),
-                    })
-                };
-                self.write_buf.clear();
-                self.encoder
-                    .encode(&response, &mut self.write_buf)
-                    .map_err(|e| ConnectionError::Protocol(e.into()))?;
-                self.stream.write_all(&self.write_buf).await?;
-            }
+            self.reject_publish(&publish, ReasonCode::TopicNameInvalid)
+                .await?;
             return Ok(());
         }
 
@@ -75,6 +116,24 @@ where
                 let mut s = session.write();
                 s.register_topic_alias(alias, publish.topic.clone());
             }
+            // The alias is the publisher's; subscribers get their own
+            publish.properties.topic_alias = None;
+        }
+
+        // $-prefixed namespaces belong to the server, whatever the ACL allows
+        if is_reserved_topic(&publish.topic) {
+            debug!(
+                "PUBLISH denied for {} to reserved topic {}",
+                client_id, publish.topic
+            );
+            self.reject_publish(&publish, ReasonCode::NotAuthorized)
+                .await?;
+            return Ok(());
+        }
+
+        // A mounted client publishes below its mount point
+        if let Some(ref mount_point) = self.mount_point {
+            publish.topic = format!("{}{}", mount_point, publish.topic).into();
         }
 
         trace!(
@@ -85,84 +144,208 @@ where
         );
 
         // Check ACL for publish permission
-        let acl_result = self
+        if let Some(reason_code) = self
+            .publish_denied(client_id, &publish, &publish.topic)
+            .await
+        {
+            // For QoS > 0, send acknowledgment with error reason code
+            self.reject_publish(&publish, reason_code).await?;
+            return Ok(());
+        }
+
+        // Let plugins rewrite the message before it is validated and routed
+        let transform = self
             .hooks
-            .on_publish_check(
-                client_id,
-                self.username.as_deref(),
-                &publish.topic,
-                publish.qos,
-                publish.retain,
-            )
+            .on_publish_transform(&self.context, &publish)
             .await;
-
-        match acl_result {
-            Ok(true) => {
-                // Publish allowed
+        match transform {
+            Ok(PublishTransform::Unchanged) => {}
+            Ok(PublishTransform::Modified(modified)) => {
+                if let Err(e) = validate_topic_name_with_max_levels(
+                    &modified.topic,
+                    self.config.max_topic_levels,
+                ) {
+                    error!(
+                        "Publish transform for {} produced invalid topic {}: {}",
+                        client_id, modified.topic, e
+                    );
+                    self.reject_publish(&publish, ReasonCode::ImplementationError)
+                        .await?;
+                    return Ok(());
+                }
+                // A rewritten topic must pass the checks the client's did:
+                // below the mount point, not reserved, and allowed by the ACL
+                if modified.topic != publish.topic {
+                    let unmounted = match self.mount_point {
+                        Some(ref mount_point) => modified.topic.strip_prefix(&**mount_point),
+                        None => Some(modified.topic.as_str()),
+                    };
+                    if !unmounted
+                        .is_some_and(|topic| !topic.is_empty() && !is_reserved_topic(topic))
+                    {
+                        debug!(
+                            "Publish transform for {} produced topic {} outside its namespace",
+                            client_id, modified.topic
+                        );
+                        self.reject_publish(&publish, ReasonCode::NotAuthorized)
+                            .await?;
+                        return Ok(());
+                    }
+                    if let Some(reason_code) = self
+                        .publish_denied(client_id, &publish, &modified.topic)
+                        .await
+                    {
+                        self.reject_publish(&publish, reason_code).await?;
+                        return Ok(());
+                    }
+                }
+                trace!(
+                    "PUBLISH from {} to {} rewritten to {}",
+                    client_id,
+                    publish.topic,
+                    modified.topic
+                );
+                publish.topic = modified.topic;
+                publish.payload = modified.payload;
+                publish.properties = modified.properties;
+                publish.properties.topic_alias = None;
             }
-            Ok(false) => {
+            Ok(PublishTransform::Reject(reason_code)) => {
                 debug!(
-                    "PUBLISH denied for {} to topic {} (ACL)",
-                    client_id, publish.topic
+                    "PUBLISH from {} to {} rejected by transform ({:?})",
+                    client_id, publish.topic, reason_code
                 );
-                // For QoS > 0, send acknowledgment with error reason code
-                if publish.qos != QoS::AtMostOnce {
-                    let packet_id = publish.packet_id.unwrap();
-                    let response = if publish.qos == QoS::AtLeastOnce {
-                        Packet::PubAck(PubAck {
-                            packet_id,
-                            reason_code: ReasonCode::NotAuthorized,
-                            properties: Properties::default(),
-                        })
-                    } else {
-                        Packet::PubRec(PubRec {
-                            packet_id,
-                            reason_code: ReasonCode::NotAuthorized,
-                            properties: Properties::default(),
-                        })
-                    };
-                    self.write_buf.clear();
-                    self.encoder
-                        .encode(&response, &mut self.write_buf)
-                        .map_err(|e| ConnectionError::Protocol(e.into()))?;
-                    self.stream.write_all(&self.write_buf).await?;
-                }
+                self.reject_publish(&publish, reason_code).await?;
                 return Ok(());
             }
             Err(e) => {
-                error!("ACL check error for {}: {}", client_id, e);
-                // For QoS > 0, send error acknowledgment
-                if publish.qos != QoS::AtMostOnce {
-                    let packet_id = publish.packet_id.unwrap();
-                    let response = if publish.qos == QoS::AtLeastOnce {
-                        Packet::PubAck(PubAck {
-                            packet_id,
-                            reason_code: ReasonCode::UnspecifiedError,
-                            properties: Properties::default(),
-                        })
-                    } else {
-                        Packet::PubRec(PubRec {
-                            packet_id,
-                            reason_code: ReasonCode::UnspecifiedError,
-                            properties: Properties::default(),
-                        })
-                    };
-                    self.write_buf.clear();
-                    self.encoder
-                        .encode(&response, &mut self.write_buf)
-                        .map_err(|e| ConnectionError::Protocol(e.into()))?;
-                    self.stream.write_all(&self.write_buf).await?;
+                error!("Publish transform error for {}: {}", client_id, e);
+                self.reject_publish(&publish, ReasonCode::UnspecifiedError)
+                    .await?;
+                return Ok(());
+            }
+        }
+
+        // PUBACK and PUBREC cannot carry Packet Too Large, so a payload over
+        // its topic's limit is refused as implementation specific
+        if let Some(limit) = payload_limit_exceeded(&self.config, &publish) {
+            debug!(
+                "Rejecting PUBLISH from {} to {}: payload of {} bytes exceeds {}",
+                client_id,
+                publish.topic,
+                publish.payload.len(),
+                limit
+            );
+            self.reject_publish(&publish, ReasonCode::ImplementationError)
+                .await?;
+            return Ok(());
+        }
+
+        if let Some(reason) = payload_format_error(&self.config, &publish) {
+            debug!(
+                "Rejecting PUBLISH from {} to {}: {}",
+                client_id, publish.topic, reason
+            );
+            self.reject_publish(&publish, ReasonCode::PayloadFormatInvalid)
+                .await?;
+            return Ok(());
+        }
+
+        // Payloads must follow the schema of their topic; a quarantined one
+        // is routed below the quarantine prefix instead, unretained
+        if let Err(violation) = self
+            .config
+            .schemas
+            .validate(&publish.topic, &publish.payload)
+        {
+            let action = violation.rule.on_invalid;
+            debug!(
+                "PUBLISH from {} to {} violates schema for {} ({}): {}",
+                client_id,
+                publish.topic,
+                violation.rule.topic,
+                action.as_str(),
+                violation.error
+            );
+            if let Some(ref metrics) = self.metrics {
+                metrics.schema_validation_failed(&violation.rule.topic, action.as_str());
+            }
+            match action {
+                InvalidPayloadAction::Reject => {
+                    self.reject_publish(&publish, ReasonCode::PayloadFormatInvalid)
+                        .await?;
+                    return Ok(());
+                }
+                InvalidPayloadAction::Quarantine => {
+                    publish.topic =
+                        format!("{}{}", violation.rule.quarantine_prefix, publish.topic).into();
+                    publish.retain = false;
                 }
+            }
+        }
+
+        // A rule with a drop action consumes the message; the publisher is
+        // still acknowledged
+        if let Some(ref rules) = self.rules {
+            if rules.evaluate(client_id, self.context.username(), &publish) {
+                debug!(
+                    "PUBLISH from {} to {} dropped by a rule",
+                    client_id, publish.topic
+                );
+                self.reject_publish(&publish, ReasonCode::Success).await?;
                 return Ok(());
             }
         }
 
+        // Retained messages are cluster-wide state; refuse them on the
+        // minority side of a partition when configured to
+        if publish.retain
+            && self
+                .cluster
+                .as_ref()
+                .is_some_and(|cluster| cluster.rejects_shared_writes())
+        {
+            warn!(
+                "Rejecting retained PUBLISH from {} to {}: cluster partitioned",
+                client_id, publish.topic
+            );
+            self.reject_publish(&publish, ReasonCode::ImplementationError)
+                .await?;
+            return Ok(());
+        }
+
         // Handle QoS
         match publish.qos {
             QoS::AtMostOnce => {
                 // No acknowledgment needed
+                // Nothing tells the publisher, so the message is still
+                // delivered, just not retained
+                if !self.store_retained(&publish, None).await {
+                    debug!(
+                        "Not retaining QoS 0 publish from {} to {}: retained store full",
+                        client_id, publish.topic
+                    );
+                }
             }
             QoS::AtLeastOnce => {
+                let syncs = self.syncs_before_ack();
+                let mut durable = Vec::new();
+                let deferred = syncs.then_some(&mut durable);
+                if !self.store_retained(&publish, deferred).await {
+                    self.reject_publish(&publish, ReasonCode::QuotaExceeded)
+                        .await?;
+                    return Ok(());
+                }
+
+                // The message must be queued for subscribers before the
+                // acknowledgment can promise it survives a crash
+                if syncs {
+                    self.route_message(client_id, &publish, raw, Some(&mut durable))
+                        .await?;
+                }
+                self.sync_before_ack(client_id, session, publish.qos, durable)
+                    .await?;
+
                 // Send PUBACK
                 let puback = PubAck::new(publish.packet_id.unwrap());
                 self.write_buf.clear();
@@ -170,6 +353,9 @@ where
                     .encode(&Packet::PubAck(puback), &mut self.write_buf)
                     .map_err(|e| ConnectionError::Protocol(e.into()))?;
                 self.stream.write_all(&self.write_buf).await?;
+                if syncs {
+                    return Ok(());
+                }
             }
             QoS::ExactlyOnce => {
                 // Store message and send PUBREC - message will be routed on PUBREL
@@ -184,16 +370,18 @@ where
                 if limit_exceeded {
                     // Send PUBREC with QuotaExceeded - client should retry later
                     debug!("Max awaiting PUBREL limit reached, rejecting QoS 2 publish");
-                    let pubrec = PubRec {
-                        packet_id,
-                        reason_code: ReasonCode::QuotaExceeded,
-                        properties: Properties::default(),
-                    };
-                    self.write_buf.clear();
-                    self.encoder
-                        .encode(&Packet::PubRec(pubrec), &mut self.write_buf)
-                        .map_err(|e| ConnectionError::Protocol(e.into()))?;
-                    self.stream.write_all(&self.write_buf).await?;
+                    self.reject_publish(&publish, ReasonCode::QuotaExceeded)
+                        .await?;
+                    return Ok(());
+                }
+
+                // For QoS 2, we route after PUBREL (not now)
+                // Handle retained message now, but don't route to subscribers yet
+                let mut durable = Vec::new();
+                let deferred = self.syncs_before_ack().then_some(&mut durable);
+                if !self.store_retained(&publish, deferred).await {
+                    self.reject_publish(&publish, ReasonCode::QuotaExceeded)
+                        .await?;
                     return Ok(());
                 }
 
@@ -202,168 +390,339 @@ where
                     s.inflight_incoming.insert(packet_id, publish.clone());
                 }
 
+                self.sync_before_ack(client_id, session, publish.qos, durable)
+                    .await?;
+
                 let pubrec = PubRec::new(packet_id);
                 self.write_buf.clear();
                 self.encoder
                     .encode(&Packet::PubRec(pubrec), &mut self.write_buf)
                     .map_err(|e| ConnectionError::Protocol(e.into()))?;
                 self.stream.write_all(&self.write_buf).await?;
-
-                // For QoS 2, we route after PUBREL (not now)
-                // Handle retained message now, but don't route to subscribers yet
-                if publish.retain && self.config.retain_available {
-                    if publish.payload.is_empty() {
-                        self.retained.remove(&publish.topic);
-                        if let Some(ref persistence) = self.persistence {
-                            persistence.write(PersistenceOp::DeleteRetained {
-                                topic: publish.topic.clone(),
-                            });
-                        }
-                    } else {
-                        let retained_msg = RetainedMessage {
-                            topic: publish.topic.clone(),
-                            payload: publish.payload.clone(),
-                            qos: publish.qos,
-                            properties: publish.properties.clone(),
-                            timestamp: Instant::now(),
-                        };
-                        self.retained
-                            .insert(publish.topic.clone(), retained_msg.clone());
-                        if let Some(ref persistence) = self.persistence {
-                            persistence.write(PersistenceOp::SetRetained {
-                                topic: publish.topic.clone(),
-                                message: StoredRetainedMessage::from(&retained_msg),
-                            });
-                        }
-                    }
-                }
                 return Ok(());
             }
         }
 
-        // Handle retained message
-        if publish.retain && self.config.retain_available {
-            if publish.payload.is_empty() {
-                self.retained.remove(&publish.topic);
-                if let Some(ref persistence) = self.persistence {
-                    persistence.write(PersistenceOp::DeleteRetained {
-                        topic: publish.topic.clone(),
-                    });
+        // Route message to subscribers
+        self.route_message(client_id, &publish, raw, None).await?;
+
+        Ok(())
+    }
+
+    /// Store or clear the retained message for a publish with the RETAIN flag set
+    ///
+    /// With `deferred`, the persistence operations are added to it for the
+    /// caller to commit instead of being queued. Returns false if the
+    /// retained store limits rejected the message.
+    async fn store_retained(
+        &self,
+        publish: &Publish,
+        deferred: Option<&mut Vec<PersistenceOp>>,
+    ) -> bool {
+        if !publish.retain || !self.config.retain_available {
+            return true;
+        }
+
+        // With raft, the retained store is updated on every node once the
+        // write commits. Tenant-scoped retained messages stay local.
+        if let Some(ref cluster) = self.cluster {
+            if cluster.is_strongly_consistent() && self.tenant.is_none() {
+                return cluster
+                    .write_retained(&publish.topic, publish.payload.clone(), publish.qos)
+                    .await;
+            }
+        }
+
+        let retained = RetainedPublish {
+            topic: &publish.topic,
+            payload: publish.payload.clone(),
+            qos: publish.qos,
+            properties: publish.properties.clone(),
+            tenant: self.tenant.clone(),
+        };
+        match deferred {
+            Some(deferred) => match self.retained.apply_publish_deferred(retained) {
+                Some(ops) => {
+                    deferred.extend(ops);
+                    true
                 }
-            } else {
-                let retained_msg = RetainedMessage {
-                    topic: publish.topic.clone(),
-                    payload: publish.payload.clone(),
-                    qos: publish.qos,
-                    properties: publish.properties.clone(),
-                    timestamp: Instant::now(),
-                };
+                None => false,
+            },
+            None => {
                 self.retained
-                    .insert(publish.topic.clone(), retained_msg.clone());
-                if let Some(ref persistence) = self.persistence {
-                    persistence.write(PersistenceOp::SetRetained {
-                        topic: publish.topic.clone(),
-                        message: StoredRetainedMessage::from(&retained_msg),
-                    });
-                }
+                    .apply_publish_async(retained, self.persistence.as_ref())
+                    .await
             }
         }
+    }
 
-        // Route message to subscribers
-        self.route_message(client_id, &publish).await?;
+    /// Ask the hooks whether the client may publish `publish` to `topic`,
+    /// returning the reason code to refuse it with if not
+    async fn publish_denied(
+        &self,
+        client_id: &Arc<str>,
+        publish: &Publish,
+        topic: &str,
+    ) -> Option<ReasonCode> {
+        let acl_result = self
+            .hooks
+            .on_publish_check(&self.context, topic, publish.qos, publish.retain)
+            .await;
+        match acl_result.map(HookDecision::is_allowed) {
+            Ok(true) => None,
+            Ok(false) => {
+                debug!("PUBLISH denied for {} to topic {} (ACL)", client_id, topic);
+                Some(ReasonCode::NotAuthorized)
+            }
+            Err(e) => {
+                error!("ACL check error for {}: {}", client_id, e);
+                Some(ReasonCode::UnspecifiedError)
+            }
+        }
+    }
 
+    /// Send a PUBACK/PUBREC carrying an error reason code (no-op for QoS 0)
+    async fn reject_publish(
+        &mut self,
+        publish: &Publish,
+        reason_code: ReasonCode,
+    ) -> Result<(), ConnectionError> {
+        let Some(packet_id) = publish.packet_id else {
+            return Ok(());
+        };
+        let response = if publish.qos == QoS::AtLeastOnce {
+            Packet::PubAck(PubAck {
+                packet_id,
+                reason_code,
+                properties: Properties::default(),
+            })
+        } else {
+            Packet::PubRec(PubRec {
+                packet_id,
+                reason_code,
+                properties: Properties::default(),
+            })
+        };
+        self.write_buf.clear();
+        self.encoder
+            .encode(&response, &mut self.write_buf)
+            .map_err(|e| ConnectionError::Protocol(e.into()))?;
+        self.stream.write_all(&self.write_buf).await?;
         Ok(())
     }
 
-    /// Route a message to subscribers
-    /// Uses AHashMap for O(n) deduplication regardless of subscriber count
+    /// Whether PUBACK/PUBREC wait for the publisher's state to be durable
+    fn syncs_before_ack(&self) -> bool {
+        self.persistence
+            .as_ref()
+            .is_some_and(|persistence| persistence.sync_mode() == SyncMode::Always)
+    }
+
+    /// Make the publisher's state durable before PUBACK/PUBREC (`sync_mode = "always"`)
+    ///
+    /// Commits everything queued so far plus `ops` (the retained update for
+    /// this message and, for QoS 1, its copies queued for offline sessions)
+    /// and, for QoS 2 on a persistent session, a snapshot holding the message
+    /// awaiting PUBREL, then fsyncs the backend. On
+    /// failure no acknowledgment is sent and the connection is closed, so the
+    /// client retransmits after reconnecting.
+    async fn sync_before_ack(
+        &self,
+        client_id: &Arc<str>,
+        session: &Arc<RwLock<Session>>,
+        qos: QoS,
+        mut ops: Vec<PersistenceOp>,
+    ) -> Result<(), ConnectionError> {
+        if !self.syncs_before_ack() {
+            return Ok(());
+        }
+        let Some(ref persistence) = self.persistence else {
+            return Ok(());
+        };
+
+        if qos == QoS::ExactlyOnce {
+            let s = session.read();
+            if !s.clean_start && s.session_expiry_interval > 0 {
+                ops.push(PersistenceOp::SetSession {
+                    client_id: tenant_key(self.tenant.as_deref(), client_id),
+                    session: StoredSession::from_session(&s),
+                });
+            }
+        }
+
+        persistence.write_sync(ops).await.map_err(|e| {
+            error!(
+                "Failed to persist state for {} before ack: {}",
+                client_id, e
+            );
+            ConnectionError::Io(std::io::Error::other(e.to_string()))
+        })
+    }
+
+    /// Route a message to subscribers, fanning it out with the wire bytes
+    /// it was received with, if given, where they still encode it
+    ///
+    /// With `durable`, a snapshot of each persistent session the message is
+    /// queued in is added to it for the caller to commit.
     pub(crate) async fn route_message(
         &self,
         sender_id: &Arc<str>,
         publish: &Publish,
+        raw: Option<&RawPublish>,
+        durable: Option<&mut Vec<PersistenceOp>>,
     ) -> Result<(), ConnectionError> {
-        let matches = self.subscriptions.matches(&publish.topic);
-
-        // Deduplicate by client_id, keeping highest QoS and collecting ALL subscription IDs
-        struct ClientSub {
-            qos: QoS,
-            retain_as_published: bool,
-            subscription_ids: SmallVec<[u32; 4]>,
+        let span = info_span!(
+            target: crate::otel::SPAN_TARGET,
+            "mqtt.publish",
+            client_id = %sender_id,
+            topic = %publish.topic,
+            qos = publish.qos as u8,
+            subscribers = tracing::field::Empty,
+        );
+        crate::otel::continue_trace(&span, &publish.properties);
+        let start = Instant::now();
+        let result = self
+            .route_to_subscribers(sender_id, publish, raw, durable)
+            .instrument(span)
+            .await;
+        if let Some(ref metrics) = self.metrics {
+            metrics
+                .publish_latency
+                .observe(start.elapsed().as_secs_f64());
         }
+        result
+    }
 
-        let mut client_subs: AHashMap<Arc<str>, ClientSub> = AHashMap::with_capacity(matches.len());
+    async fn route_to_subscribers(
+        &self,
+        sender_id: &Arc<str>,
+        publish: &Publish,
+        raw: Option<&RawPublish>,
+        mut durable: Option<&mut Vec<PersistenceOp>>,
+    ) -> Result<(), ConnectionError> {
+        let matches = self.subscriptions.matches(&publish.topic);
 
-        for sub in matches {
-            // Skip sender if no_local is set
-            if sub.no_local && sub.client_id == *sender_id {
-                continue;
-            }
+        // In a cluster, each shared subscription group is served by one node;
+        // local_only groups are only ever served by this one
+        let shared_route = self.cluster.as_ref().map(|cluster| {
+            let is_balanced = |group: &str| {
+                self.subscriptions.shared_strategy(group) != SharedDeliveryStrategy::LocalOnly
+            };
+            let local_groups: SmallVec<[Arc<str>; 4]> = matches
+                .iter()
+                .filter_map(|sub| sub.share_group.clone())
+                .filter(|group| is_balanced(group))
+                .collect();
+            let mut route = cluster.route_shared(&publish.topic, &local_groups);
+            route.retain(is_balanced);
+            route
+        });
 
-            if let Some(entry) = client_subs.get_mut(&sub.client_id) {
-                // Update existing entry
-                if sub.qos > entry.qos {
-                    entry.qos = sub.qos;
-                }
-                if sub.retain_as_published {
-                    entry.retain_as_published = true;
-                }
-                if let Some(id) = sub.subscription_id {
-                    if !entry.subscription_ids.contains(&id) {
-                        entry.subscription_ids.push(id);
-                    }
-                }
-            } else {
-                // New client - add entry
-                let mut subscription_ids = SmallVec::new();
-                if let Some(id) = sub.subscription_id {
-                    subscription_ids.push(id);
-                }
-                client_subs.insert(
-                    sub.client_id.clone(),
-                    ClientSub {
-                        qos: sub.qos,
-                        retain_as_published: sub.retain_as_published,
-                        subscription_ids,
-                    },
-                );
+        // Deduplicate by client_id, keeping highest QoS and collecting ALL subscription IDs
+        let client_matches = group_by_client(matches, Some(sender_id.as_ref()), |sub| {
+            match (&sub.share_group, &shared_route) {
+                (Some(group), Some(route)) => !route.is_remote(group),
+                _ => true,
             }
-        }
+        });
 
-        // Send to each client
-        for (client_id, sub_info) in client_subs {
-            let effective_qos = publish.qos.min(sub_info.qos);
-
-            let mut outgoing = publish.clone();
-            outgoing.qos = effective_qos;
-            outgoing.dup = false;
-            // Clear incoming packet_id - broker assigns fresh IDs for each subscriber
-            outgoing.packet_id = None;
-
-            // Clear retain flag unless retain_as_published
-            if !sub_info.retain_as_published {
-                outgoing.retain = false;
-            }
+        tracing::Span::current().record("subscribers", client_matches.len());
 
-            // Add ALL subscription identifiers
-            for id in sub_info.subscription_ids {
-                outgoing.properties.subscription_identifiers.push(id);
-            }
+        // Popular topics are fanned out, sharing copies among clients
+        let threshold = self.config.fanout_threshold;
+        let mut fanout =
+            (threshold > 0 && client_matches.len() >= threshold).then(|| FanOut::new(publish, raw));
 
+        // Send to each client
+        for (i, (client_id, client_match)) in client_matches.into_iter().enumerate() {
             if let Some(sender) = self.connections.get(&client_id) {
-                if let Err(tokio::sync::mpsc::error::TrySendError::Full(_)) =
-                    sender.try_send(Packet::Publish(outgoing))
+                if self
+                    .slow_consumers
+                    .drops(&client_id, &sender, publish.qos.min(client_match.qos))
                 {
-                    warn!(client_id = %client_id, "channel full - dropping message");
+                    report_dropped(
+                        &self.drops,
+                        &client_id,
+                        &publish.topic,
+                        DropReason::SlowConsumer,
+                    );
+                } else {
+                    let packet = match fanout {
+                        Some(ref mut fanout) => fanout.packet(&client_match),
+                        None => Packet::Publish(client_match.outgoing(publish)),
+                    };
+                    if let Err(tokio::sync::mpsc::error::TrySendError::Full(_)) =
+                        sender.try_send(packet)
+                    {
+                        warn!(client_id = %client_id, "channel full - dropping message");
+                        report_dropped(
+                            &self.drops,
+                            &client_id,
+                            &publish.topic,
+                            DropReason::ChannelFull,
+                        );
+                    }
                 }
             } else {
                 // Client disconnected, queue message if persistent session
                 if let Some(session) = self.sessions.get(client_id.as_ref()) {
                     let mut s = session.write();
-                    if !s.clean_start && s.queue_message(outgoing) == QueueResult::DroppedOldest {
-                        let _ = self.events.send(BrokerEvent::MessageDropped);
+                    if !s.clean_start {
+                        let outgoing = client_match.outgoing(publish);
+                        let stored = durable
+                            .is_some()
+                            .then(|| StoredPendingMessage::queued_now(&outgoing));
+                        let result = s.queue_message(outgoing);
+                        if let Some(topic) = result.dropped_topic() {
+                            report_dropped(&self.drops, &client_id, topic, DropReason::QueueFull);
+                        }
+                        if let Some(ref mut durable) = durable {
+                            if s.session_expiry_interval > 0 {
+                                let client_key = tenant_key(s.tenant.as_deref(), &client_id);
+                                // Appending the message is enough unless the
+                                // queue dropped an older one to make room
+                                let op = match result {
+                                    QueueResult::Queued => {
+                                        stored.map(|message| PersistenceOp::AppendQueued {
+                                            client_id: client_key,
+                                            message,
+                                        })
+                                    }
+                                    QueueResult::DroppedOldest(_) => {
+                                        Some(PersistenceOp::SetSession {
+                                            client_id: client_key,
+                                            session: StoredSession::from_session(&s),
+                                        })
+                                    }
+                                    QueueResult::DroppedNewest(_) | QueueResult::Overflow(_) => {
+                                        None
+                                    }
+                                };
+                                durable.extend(op);
+                            }
+                        }
                     }
                 }
             }
+
+            // Let the subscribers' connections write while the rest are sent
+            if fanout.is_some() && (i + 1) % FANOUT_BATCH_SIZE == 0 {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        if let (Some(cluster), Some(route)) = (&self.cluster, shared_route) {
+            cluster
+                .forward_routed(
+                    route,
+                    &publish.topic,
+                    publish.payload.clone(),
+                    publish.qos,
+                    publish.retain,
+                    &publish.properties,
+                )
+                .instrument(info_span!(target: crate::otel::SPAN_TARGET, "cluster.forward"))
+                .await;
         }
 
         // Notify event subscribers (for bridge forwarding and monitoring)
@@ -372,6 +731,9 @@ where
             payload: publish.payload.clone(),
             qos: publish.qos,
             retain: publish.retain,
+            cluster_forwarded: self.cluster.is_some(),
+            origin: BridgeOrigin::from_user_properties(&publish.properties.user_properties),
+            properties: publish.properties.clone(),
         });
 
         Ok(())
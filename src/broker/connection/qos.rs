@@ -5,10 +5,12 @@ use std::time::Instant;
 
 use parking_lot::RwLock;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
-use tracing::trace;
+use tracing::{trace, warn};
 
 use super::{Connection, ConnectionError};
-use crate::protocol::{Packet, PubAck, PubComp, PubRec, PubRel};
+use crate::broker::{report_dropped, DisconnectReason, DropReason};
+use crate::config::RetryExhaustedPolicy;
+use crate::protocol::{Packet, PubAck, PubComp, PubRec, PubRel, ReasonCode};
 use crate::session::{Qos2State, Session};
 
 impl<S> Connection<S>
@@ -21,9 +23,21 @@ where
         session: &Arc<RwLock<Session>>,
         puback: PubAck,
     ) -> Result<(), ConnectionError> {
-        let mut s = session.write();
-        s.inflight_outgoing.remove(&puback.packet_id);
-        s.increment_send_quota();
+        let (acked, has_pending) = {
+            let mut s = session.write();
+            let acked = s.inflight_outgoing.remove(&puback.packet_id);
+            s.increment_send_quota();
+            (acked, s.has_queued())
+        };
+        if let Some(acked) = acked.filter(|_| self.observes_messages) {
+            self.hooks
+                .on_message_delivered(&self.context, &acked.publish.topic, acked.publish.qos)
+                .await;
+        }
+        // Messages queued for flow control can go now
+        if has_pending {
+            self.send_pending_messages(session).await?;
+        }
         Ok(())
     }
 
@@ -74,7 +88,7 @@ where
 
         // Now route the message to subscribers (QoS 2 delivery complete)
         if let Some(publish) = publish {
-            self.route_message(client_id, &publish).await?;
+            self.route_message(client_id, &publish, None, None).await?;
         }
 
         Ok(())
@@ -86,37 +100,68 @@ where
         session: &Arc<RwLock<Session>>,
         pubcomp: PubComp,
     ) -> Result<(), ConnectionError> {
-        let mut s = session.write();
-        s.inflight_outgoing.remove(&pubcomp.packet_id);
-        s.increment_send_quota();
+        let (completed, has_pending) = {
+            let mut s = session.write();
+            let completed = s.inflight_outgoing.remove(&pubcomp.packet_id);
+            s.increment_send_quota();
+            (completed, s.has_queued())
+        };
+        if let Some(completed) = completed.filter(|_| self.observes_messages) {
+            self.hooks
+                .on_message_delivered(
+                    &self.context,
+                    &completed.publish.topic,
+                    completed.publish.qos,
+                )
+                .await;
+        }
+        if has_pending {
+            self.send_pending_messages(session).await?;
+        }
         Ok(())
     }
 
     /// Retry unacked QoS 1/2 messages
+    ///
+    /// Each retransmission doubles the wait for the next one, up to
+    /// `retry_max_interval`. A message that was retransmitted `max_retries`
+    /// times is handled by the `retry_exhausted` policy instead.
     pub(crate) async fn retry_unacked_messages(
         &mut self,
+        client_id: &Arc<str>,
         session: &Arc<RwLock<Session>>,
     ) -> Result<(), ConnectionError> {
         let now = Instant::now();
-        let retry_interval = self.config.retry_interval;
+        let max_retries = self.config.max_retries;
 
-        // Collect messages that need retry (to avoid holding lock while sending)
+        // Collect messages that need retry (to avoid holding lock while sending),
+        // in their original order [MQTT-4.6.0-1]
+        let mut exhausted = Vec::new();
         let to_retry: Vec<_> = {
             let mut s = session.write();
-            s.inflight_outgoing
-                .iter_mut()
-                .filter_map(|(packet_id, inflight)| {
-                    if now.duration_since(inflight.sent_at) >= retry_interval {
-                        // Update retry metadata
-                        inflight.retry_count += 1;
-                        inflight.sent_at = now;
-
-                        Some((*packet_id, inflight.publish.clone(), inflight.qos2_state))
-                    } else {
-                        None
+            let to_retry: Vec<_> = s
+                .inflight_outgoing_in_order()
+                .into_iter()
+                .filter_map(|packet_id| {
+                    let inflight = s.inflight_outgoing.get_mut(&packet_id)?;
+                    let retry_delay = self.config.retry_delay(inflight.retry_count);
+                    if now.duration_since(inflight.sent_at) < retry_delay {
+                        return None;
+                    }
+                    if max_retries > 0 && inflight.retry_count >= max_retries {
+                        exhausted.push(packet_id);
+                        return None;
                     }
+
+                    // Update retry metadata
+                    inflight.retry_count += 1;
+                    inflight.sent_at = now;
+
+                    Some((packet_id, inflight.publish.clone(), inflight.qos2_state))
                 })
-                .collect()
+                .collect();
+            s.retransmits += to_retry.len() as u64;
+            to_retry
         };
 
         // Get max packet size
@@ -127,6 +172,9 @@ where
 
         // Send retries
         for (packet_id, mut publish, qos2_state) in to_retry {
+            if let Some(ref metrics) = self.metrics {
+                metrics.retransmit(publish.qos);
+            }
             match qos2_state {
                 None | Some(Qos2State::WaitingPubRec) => {
                     // QoS 1, or QoS 2 waiting for PUBREC: resend PUBLISH with DUP flag
@@ -157,6 +205,67 @@ where
             }
         }
 
+        if !exhausted.is_empty() {
+            self.handle_retries_exhausted(client_id, session, &exhausted)
+                .await?;
+        }
+
         Ok(())
     }
+
+    /// Apply the retry exhausted policy to unacked messages that ran out of
+    /// retransmissions
+    async fn handle_retries_exhausted(
+        &mut self,
+        client_id: &Arc<str>,
+        session: &Arc<RwLock<Session>>,
+        packet_ids: &[u16],
+    ) -> Result<(), ConnectionError> {
+        match self.config.retry_exhausted {
+            RetryExhaustedPolicy::Drop => {
+                let mut dropped = Vec::with_capacity(packet_ids.len());
+                let has_pending = {
+                    let mut s = session.write();
+                    for packet_id in packet_ids {
+                        if let Some(inflight) = s.inflight_outgoing.remove(packet_id) {
+                            warn!(
+                                client_id = %client_id,
+                                "dropping unacked message {} to {} after {} retries",
+                                packet_id,
+                                inflight.publish.topic,
+                                inflight.retry_count
+                            );
+                            s.increment_send_quota();
+                            dropped.push(inflight.publish.topic);
+                        }
+                    }
+                    s.has_queued()
+                };
+                for topic in dropped {
+                    if let Some(ref metrics) = self.metrics {
+                        metrics.retries_exhausted("drop");
+                    }
+                    report_dropped(&self.drops, client_id, &topic, DropReason::RetriesExhausted);
+                }
+                if has_pending {
+                    self.send_pending_messages(session).await?;
+                }
+                Ok(())
+            }
+            RetryExhaustedPolicy::Disconnect => {
+                warn!(
+                    client_id = %client_id,
+                    "disconnecting: {} unacked messages ran out of retries",
+                    packet_ids.len()
+                );
+                if let Some(ref metrics) = self.metrics {
+                    metrics.retries_exhausted("disconnect");
+                }
+                self.send_disconnect(ReasonCode::UnspecifiedError).await;
+                self.handle_disconnect(client_id, session, DisconnectReason::RetriesExhausted)
+                    .await;
+                Err(ConnectionError::Shutdown)
+            }
+        }
+    }
 }
@@ -8,12 +8,16 @@ use tracing::{debug, error};
 
 use super::{Connection, ConnectionError};
 use crate::broker::BrokerEvent;
+use crate::hooks::SubscribeDecision;
 use crate::protocol::{
     Packet, Properties, ProtocolVersion, Publish, QoS, ReasonCode, RetainHandling, SubAck,
     Subscribe, UnsubAck, Unsubscribe,
 };
 use crate::session::Session;
-use crate::topic::{validate_topic_filter_with_max_levels, Subscription};
+use crate::topic::{
+    is_sys_topic, mount_filter, parse_shared_subscription, validate_topic_filter_with_max_levels,
+    Subscription,
+};
 
 impl<S> Connection<S>
 where
@@ -24,7 +28,7 @@ where
         &mut self,
         client_id: &Arc<str>,
         session: &Arc<RwLock<Session>>,
-        subscribe: Subscribe,
+        mut subscribe: Subscribe,
     ) -> Result<(), ConnectionError> {
         let mut reason_codes = Vec::with_capacity(subscribe.subscriptions.len());
         let _protocol_version = self
@@ -42,10 +46,14 @@ where
         // Track subscription info for retained message handling
         let mut sub_info: Vec<(QoS, bool, RetainHandling, String)> = Vec::new();
 
-        for sub in &subscribe.subscriptions {
+        for sub in &mut subscribe.subscriptions {
             // Validate topic filter
-            if validate_topic_filter_with_max_levels(&sub.filter, self.config.max_topic_levels)
-                .is_err()
+            if validate_topic_filter_with_max_levels(
+                &sub.filter,
+                self.config.max_topic_levels,
+                self.config.queue_subscriptions,
+            )
+            .is_err()
             {
                 reason_codes.push(ReasonCode::TopicFilterInvalid);
                 sub_info.push((
@@ -71,27 +79,48 @@ where
                 continue;
             }
 
+            // A mounted client subscribes below its mount point
+            if let Some(ref mount_point) = self.mount_point {
+                sub.filter =
+                    mount_filter(mount_point, &sub.filter, self.config.queue_subscriptions);
+            }
+
             // Check ACL for subscribe permission
             let acl_result = self
                 .hooks
-                .on_subscribe_check(
-                    client_id,
-                    self.username.as_deref(),
-                    &sub.filter,
-                    sub.options.qos,
-                )
+                .on_subscribe_check(&self.context, &sub.filter, sub.options.qos)
                 .await;
 
-            match acl_result {
-                Ok(true) => {
-                    // Subscribe allowed
+            // $SYS is only readable when a hook allows or grants it
+            // explicitly, so it stays closed with the ACL disabled
+            let sys_filter = is_sys_topic(
+                parse_shared_subscription(&sub.filter, self.config.queue_subscriptions)
+                    .map_or(sub.filter.as_str(), |(_, f)| f),
+            );
+            let acl_result = match acl_result {
+                Ok(SubscribeDecision::Continue)
+                    if sys_filter
+                        && !self.hooks.grants_sys_subscribe(&self.context, &sub.filter) =>
+                {
+                    Ok(SubscribeDecision::Deny(ReasonCode::NotAuthorized))
                 }
-                Ok(false) => {
+                result => result,
+            };
+
+            let qos_limit = match acl_result {
+                Ok(SubscribeDecision::Allow | SubscribeDecision::Continue) => self.config.max_qos,
+                Ok(SubscribeDecision::Grant(qos)) => qos.min(self.config.max_qos),
+                Ok(SubscribeDecision::Deny(reason_code)) => {
                     debug!(
-                        "SUBSCRIBE denied for {} to filter {} (ACL)",
-                        client_id, sub.filter
+                        "SUBSCRIBE denied for {} to filter {} (ACL: {:?})",
+                        client_id, sub.filter, reason_code
                     );
-                    reason_codes.push(ReasonCode::NotAuthorized);
+                    // A SUBACK reason code must be a failure to deny
+                    reason_codes.push(if reason_code.is_success() {
+                        ReasonCode::NotAuthorized
+                    } else {
+                        reason_code
+                    });
                     sub_info.push((
                         QoS::AtMostOnce,
                         false,
@@ -111,17 +140,43 @@ where
                     ));
                     continue;
                 }
-            }
-
-            // Check QoS support
-            let granted_qos = sub.options.qos.min(self.config.max_qos);
+            };
 
             // Check if subscription already existed (for retain_handling=1)
-            let subscription_existed = {
+            let (subscription_existed, subscription_count) = {
                 let s = session.read();
-                s.subscriptions.contains_key(sub.filter.as_str())
+                (
+                    s.subscriptions.contains_key(sub.filter.as_str()),
+                    s.subscriptions.len(),
+                )
             };
 
+            // Replacing an existing subscription never exceeds the limit
+            let max_subscriptions = self.config.max_subscriptions_per_client;
+            if max_subscriptions > 0
+                && !subscription_existed
+                && subscription_count >= max_subscriptions
+            {
+                debug!(
+                    "SUBSCRIBE refused for {} to filter {}: {} subscriptions",
+                    client_id, sub.filter, subscription_count
+                );
+                if let Some(ref metrics) = self.metrics {
+                    metrics.subscription_quota_exceeded();
+                }
+                reason_codes.push(ReasonCode::QuotaExceeded);
+                sub_info.push((
+                    QoS::AtMostOnce,
+                    false,
+                    RetainHandling::DoNotSend,
+                    sub.filter.clone(),
+                ));
+                continue;
+            }
+
+            // Check QoS support, and the limit the hooks granted
+            let granted_qos = sub.options.qos.min(qos_limit);
+
             // Add subscription (SubscriptionStore handles $share parsing internally)
             self.subscriptions.subscribe(
                 &sub.filter,
@@ -135,10 +190,12 @@ where
                 },
             );
 
-            // Store in session
+            // Store in session, with the granted QoS for restoring it
             {
                 let mut s = session.write();
-                s.add_subscription(sub.filter.clone(), sub.options, sub_id);
+                let mut options = sub.options;
+                options.qos = granted_qos;
+                s.add_subscription(sub.filter.clone(), options, sub_id);
             }
 
             // Track info for retained message handling
@@ -189,15 +246,11 @@ where
                 continue;
             }
 
-            // Check retain_handling option
-            let should_send = match retain_handling {
-                RetainHandling::SendAtSubscribe => true,
-                RetainHandling::SendAtSubscribeIfNew => !existed,
-                RetainHandling::DoNotSend => false,
-            };
-
-            if should_send {
-                self.send_retained_messages(client_id, filter, *granted_qos, session, sub_id)
+            // Shared subscriptions never receive retained messages
+            if retain_handling.sends_retained(*existed)
+                && parse_shared_subscription(filter, self.config.queue_subscriptions).is_none()
+            {
+                self.send_retained_messages(filter, *granted_qos, session, sub_id)
                     .await?;
             }
         }
@@ -208,19 +261,13 @@ where
     /// Send retained messages for a subscription
     pub(crate) async fn send_retained_messages(
         &mut self,
-        _client_id: &Arc<str>,
         filter: &str,
         qos: QoS,
         session: &Arc<RwLock<Session>>,
         subscription_id: Option<u32>,
     ) -> Result<(), ConnectionError> {
         // Find matching retained messages
-        let mut matching_retained = Vec::new();
-        for entry in self.retained.iter() {
-            if crate::topic::validation::topic_matches_filter(entry.key(), filter) {
-                matching_retained.push(entry.clone());
-            }
-        }
+        let matching_retained = self.retained.matching(filter);
 
         for retained in matching_retained {
             // Calculate elapsed time for message expiry countdown
@@ -268,8 +315,11 @@ where
                 .map_err(|e| ConnectionError::Protocol(e.into()))?;
             let bytes_sent = self.write_buf.len();
             self.stream.write_all(&self.write_buf).await?;
-            if let Some(ref metrics) = self.metrics {
-                metrics.publish_sent(bytes_sent);
+            self.record_publish_sent(session, bytes_sent, None);
+            if self.observes_messages && effective_qos == QoS::AtMostOnce {
+                self.hooks
+                    .on_message_delivered(&self.context, &retained.topic, effective_qos)
+                    .await;
             }
         }
 
@@ -281,7 +331,7 @@ where
         &mut self,
         client_id: &Arc<str>,
         session: &Arc<RwLock<Session>>,
-        unsubscribe: Unsubscribe,
+        mut unsubscribe: Unsubscribe,
     ) -> Result<(), ConnectionError> {
         let mut reason_codes = Vec::with_capacity(unsubscribe.filters.len());
         let protocol_version = self
@@ -289,7 +339,10 @@ where
             .protocol_version()
             .unwrap_or(ProtocolVersion::V5);
 
-        for filter in &unsubscribe.filters {
+        for filter in &mut unsubscribe.filters {
+            if let Some(ref mount_point) = self.mount_point {
+                *filter = mount_filter(mount_point, filter, self.config.queue_subscriptions);
+            }
             let removed = self.subscriptions.unsubscribe(filter, client_id);
 
             // Remove from session
@@ -298,6 +351,10 @@ where
                 s.remove_subscription(filter);
             }
 
+            if removed {
+                self.hooks.on_unsubscribe(&self.context, filter).await;
+            }
+
             if protocol_version == ProtocolVersion::V5 {
                 reason_codes.push(if removed {
                     ReasonCode::Success
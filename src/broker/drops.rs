@@ -0,0 +1,98 @@
+//! Undelivered Message Reports
+//!
+//! Messages not delivered to a client are reported as
+//! [`BrokerEvent::MessageDropped`] to event subscribers and, when the hooks
+//! observe messages, to the `on_message_dropped` hook. The hook gets a
+//! channel of its own: drops happen under load, when the shared event
+//! channel is most likely to lag, and other events must not push them out.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::{broadcast, mpsc};
+
+use super::{BrokerEvent, DropReason};
+
+/// Drops waiting for the `on_message_dropped` hook; more are counted as
+/// missed
+pub(crate) const DROPPED_HOOK_QUEUE_SIZE: usize = 16384;
+
+/// A message not delivered to a client, for the dropped hook
+#[derive(Debug)]
+pub(crate) struct DroppedMessage {
+    pub client_id: Arc<str>,
+    pub topic: Arc<str>,
+    pub reason: DropReason,
+}
+
+/// Where undelivered messages are reported
+#[derive(Debug)]
+pub struct DropReports {
+    events: broadcast::Sender<BrokerEvent>,
+    /// Feeds the dropped hook, if the hooks observe messages
+    hook: Option<mpsc::Sender<DroppedMessage>>,
+    /// Drops the hook missed because its channel was full
+    missed: AtomicU64,
+}
+
+impl DropReports {
+    /// Report drops as events only
+    pub(crate) fn new(events: broadcast::Sender<BrokerEvent>) -> Self {
+        Self {
+            events,
+            hook: None,
+            missed: AtomicU64::new(0),
+        }
+    }
+
+    /// Report drops as events and to the dropped hook, which reads the
+    /// returned receiver
+    pub(crate) fn with_hook(
+        events: broadcast::Sender<BrokerEvent>,
+    ) -> (Self, mpsc::Receiver<DroppedMessage>) {
+        let (tx, rx) = mpsc::channel(DROPPED_HOOK_QUEUE_SIZE);
+        let reports = Self {
+            events,
+            hook: Some(tx),
+            missed: AtomicU64::new(0),
+        };
+        (reports, rx)
+    }
+
+    /// Drops the hook missed since the last call
+    pub(crate) fn take_missed(&self) -> u64 {
+        self.missed.swap(0, Ordering::Relaxed)
+    }
+}
+
+/// Report a message not delivered to a client
+pub(crate) fn report_dropped(
+    drops: &DropReports,
+    client_id: &Arc<str>,
+    topic: &str,
+    reason: DropReason,
+) {
+    let listening = drops.events.receiver_count() > 0;
+    if drops.hook.is_none() && !listening {
+        return;
+    }
+    let topic: Arc<str> = topic.into();
+    if let Some(ref hook) = drops.hook {
+        let dropped = DroppedMessage {
+            client_id: client_id.clone(),
+            topic: topic.clone(),
+            reason,
+        };
+        if hook.try_send(dropped).is_err() {
+            drops.missed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    if !listening {
+        return;
+    }
+    let _ = drops.events.send(BrokerEvent::MessageDropped {
+        client_id: client_id.clone(),
+        topic,
+        reason,
+    });
+}
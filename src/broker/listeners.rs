@@ -0,0 +1,50 @@
+//! Listener Status
+//!
+//! Records the listeners the broker bound and whether it still accepts
+//! connections on them, for the readiness endpoint. The broker is not
+//! accepting before `run` binds its listeners, nor after shutdown begins.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use parking_lot::RwLock;
+use serde::Serialize;
+
+use super::Listener;
+
+/// A bound listener
+#[derive(Debug, Clone, Serialize)]
+pub struct BoundListener {
+    pub listener: &'static str,
+    pub addr: SocketAddr,
+}
+
+/// Listeners of the broker
+#[derive(Debug, Default)]
+pub struct ListenerStatus {
+    bound: RwLock<Vec<BoundListener>>,
+    /// Bound all listeners and not shutting down
+    accepting: AtomicBool,
+}
+
+impl ListenerStatus {
+    pub(crate) fn bound(&self, listener: Listener, addr: SocketAddr) {
+        self.bound.write().push(BoundListener {
+            listener: listener.as_str(),
+            addr,
+        });
+    }
+
+    pub(crate) fn set_accepting(&self, accepting: bool) {
+        self.accepting.store(accepting, Ordering::Relaxed);
+    }
+
+    /// Whether the broker accepts connections on all its listeners
+    pub fn is_accepting(&self) -> bool {
+        self.accepting.load(Ordering::Relaxed)
+    }
+
+    pub fn listeners(&self) -> Vec<BoundListener> {
+        self.bound.read().clone()
+    }
+}
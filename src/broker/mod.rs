@@ -4,40 +4,92 @@
 //! message routing, and coordinates all components.
 
 mod connection;
+mod drops;
+mod listeners;
+mod redirect;
+mod resources;
+mod retained;
 mod router;
+mod slow_consumer;
 mod sys_topics;
 mod tls;
+mod workers;
 
+pub(crate) use connection::rand_id;
 pub use connection::Connection;
+use connection::{publish_due_will, remove_subscriptions, restore_subscriptions};
+pub(crate) use drops::report_dropped;
+pub use drops::DropReports;
+use drops::DroppedMessage;
+pub use listeners::{BoundListener, ListenerStatus};
+pub use redirect::{RedirectControl, ServerRedirect};
+use resources::RESOURCE_SAMPLE_INTERVAL;
+pub use resources::{ResourceMonitor, ResourceStats};
+pub use retained::{RetainOutcome, RetainedSnapshot, RetainedStore};
+use retained::{RetainedPublish, RETAINED_RESYNC_INTERVAL};
 pub use router::MessageRouter;
-pub use tls::load_tls_config;
-
+use router::{deliver_to_clients, group_by_client};
+pub use slow_consumer::SlowConsumers;
+use slow_consumer::SLOW_CONSUMER_CHECK_INTERVAL;
+pub use tls::{install_crypto_provider, load_tls_config, TlsError};
+#[cfg(feature = "raft")]
+pub(crate) use tls::{load_ca_certs, load_certs, load_private_key};
+use workers::spawn_connection;
+pub use workers::WorkerRuntimes;
+
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use ahash::AHashMap;
 use bytes::Bytes;
-use dashmap::DashMap;
+use futures_util::future::BoxFuture;
+use parking_lot::Mutex;
 use socket2::{Domain, Protocol, Socket, Type};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{broadcast, mpsc};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 /// TCP listen backlog size - high value for burst connection handling
 const TCP_BACKLOG: i32 = 4096;
 
-use crate::bridge::BridgeManager;
-use crate::cluster::ClusterManager;
-use crate::config::ProxyProtocolConfig;
+/// How often the depth of the outbound channels is sampled for metrics
+const OUTBOUND_DEPTH_SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Messages collected before they are appended to the journal between flushes
+const JOURNAL_BATCH_SIZE: usize = 256;
+
+/// How often clients without a session are removed from the cluster registry
+const CLIENT_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+use crate::acl::AclProvider;
+use crate::auth::AuthProvider;
+use crate::bridge::{BridgeManager, BridgeOrigin};
+use crate::buffer_pool;
+use crate::cluster::{ClusterManager, ClusterWill, MetadataCommand, SharedGroupState};
+use crate::config::{
+    ContentTypeRule, MountPointConfig, PayloadLimitRule, ProxyProtocolConfig, QueueOverflowPolicy,
+    RedirectConfig, RetainedPolicy, RetryExhaustedPolicy, SharedDeliveryStrategy, SharedGroupRule,
+    SlowConsumerConfig, TenancyConfig, TopicPriorityRule,
+};
 use crate::flapping::FlappingDetector;
 use crate::hooks::{DefaultHooks, Hooks};
+use crate::journal::Journal;
 use crate::metrics::Metrics;
-use crate::persistence::{PersistenceManager, PersistenceOp, StoredRetainedMessage};
-use crate::protocol::{Packet, Properties, ProtocolVersion, Publish, QoS};
+use crate::persistence::{
+    split_tenant_key, PersistenceError, PersistenceManager, PersistenceOp, StoredRetainedMessage,
+    StoredSession,
+};
+use crate::protocol::{Disconnect, Packet, Properties, ProtocolVersion, Publish, QoS, ReasonCode};
 use crate::proxy::{parse_proxy_header, ProxyInfo};
-use crate::session::SessionStore;
-use crate::topic::SubscriptionStore;
+use crate::rules::RuleEngine;
+use crate::schema::SchemaRegistry;
+use crate::session::{
+    QueueSpill, SessionLimits, SessionStore, EXPIRY_TIMER_RESOLUTION, SPILL_INTERVAL,
+    WILL_TIMER_RESOLUTION,
+};
+use crate::sharded::ShardedMap;
+use crate::topic::{parse_shared_subscription, SubscriptionStore, Topic};
 use crate::transport::WsStream;
 
 /// Broker configuration
@@ -61,8 +113,14 @@ pub struct BrokerConfig {
     pub default_keep_alive: u16,
     /// Maximum keep alive
     pub max_keep_alive: u16,
+    /// Keep alive assigned to v5 clients requesting more than `max_keep_alive`
+    /// (None = `max_keep_alive`)
+    pub server_keep_alive: Option<u16>,
     /// Session expiry check interval
     pub session_expiry_check_interval: Duration,
+    /// Disconnect clients that neither subscribe nor publish within this long
+    /// of connecting (zero = disabled)
+    pub parked_timeout: Duration,
     /// Receive maximum (flow control)
     pub receive_maximum: u16,
     /// Maximum QoS
@@ -83,28 +141,100 @@ pub struct BrokerConfig {
     pub sys_topics_enabled: bool,
     /// $SYS topic publish interval
     pub sys_topics_interval: Duration,
+    /// Publish filter statistics under $SYS/broker/subscriptions/
+    pub sys_filter_topics: bool,
+    /// Publish per-session state under $SYS/sessions/<client>/
+    pub sys_session_topics: bool,
+    /// Publish the traffic, queue and inflight counters of sessions under
+    /// $SYS/sessions/<client>/, every `sys_client_interval`
+    pub sys_client_topics: bool,
+    pub sys_client_interval: Duration,
+    /// Keep each client's presence as a retained message under
+    /// $events/clients/<client>/
+    pub presence_topics: bool,
     /// Maximum in-flight messages per client (QoS 1/2)
     pub max_inflight: u16,
     /// Maximum queued messages per offline client
     pub max_queued_messages: usize,
+    /// What to do when a client's message queue is full, unless its ACL
+    /// role overrides it
+    pub queue_overflow: QueueOverflowPolicy,
+    /// Queued messages per persistent session kept in memory before the rest
+    /// are spilled to persistence (0 = never spill)
+    pub spill_threshold: usize,
     /// Maximum pending PUBREL for QoS 2
     pub max_awaiting_rel: usize,
     /// Retry interval for unacked messages
     pub retry_interval: Duration,
+    /// Cap of the doubling retry interval (zero = flat retry interval)
+    pub retry_max_interval: Duration,
+    /// Retransmissions of an unacked message before giving up (0 = unlimited)
+    pub max_retries: u32,
+    /// What to do with a message that ran out of retransmissions
+    pub retry_exhausted: RetryExhaustedPolicy,
     /// Per-connection outbound message channel capacity.
     /// This buffer holds messages waiting to be written to the client socket.
     /// Higher values handle burst traffic better but use more memory per connection.
     pub outbound_channel_capacity: usize,
+    /// Bytes of outbound packets coalesced into one write (0 = no coalescing)
+    pub write_coalesce_bytes: usize,
+    /// Longest wait of coalesced packets for more (zero = until the
+    /// outbound channel is empty)
+    pub write_coalesce_delay: Duration,
+    /// Subscribers from which a message is fanned out with shared
+    /// encodings, in batches (0 = never)
+    pub fanout_threshold: usize,
+    /// Slow consumer detection and the policy applied to slow consumers
+    pub slow_consumer: SlowConsumerConfig,
     /// Maximum number of topic levels (depth) allowed.
     /// Topic levels are separated by '/'. For example, "a/b/c" has 3 levels.
     /// 0 = unlimited.
     pub max_topic_levels: usize,
+    /// Maximum subscriptions per client (0 = unlimited)
+    pub max_subscriptions_per_client: usize,
     /// PROXY protocol configuration for TCP listener
     pub proxy_protocol: ProxyProtocolConfig,
     /// PROXY protocol configuration for TLS listener
     pub tls_proxy_protocol: ProxyProtocolConfig,
     /// PROXY protocol configuration for WebSocket listener
     pub ws_proxy_protocol: ProxyProtocolConfig,
+    /// Maximum number of retained messages (0 = unlimited)
+    pub max_retained_messages: usize,
+    /// Maximum total size of retained messages in bytes (0 = unlimited)
+    pub max_retained_bytes: usize,
+    /// What to do when a retained message would exceed the limits
+    pub retained_policy: RetainedPolicy,
+    /// Tenant assignment for partitioning persisted data
+    pub tenancy: TenancyConfig,
+    /// Topic mount points of clients per listener and user
+    pub mount_points: MountPointConfig,
+    /// Redirect new connections to another server from startup
+    pub redirect: Option<RedirectConfig>,
+    /// Reject publishes marked as UTF-8 whose payload is not valid UTF-8
+    pub validate_payload_format: bool,
+    /// Content Types required of publishes to matching topics
+    pub content_types: Vec<ContentTypeRule>,
+    /// Maximum payload size of publishes to matching topics; the first
+    /// matching rule applies
+    pub payload_limits: Vec<PayloadLimitRule>,
+    /// Schemas payloads published to matching topics must follow
+    pub schemas: Arc<SchemaRegistry>,
+    /// Disconnect clients for marginal protocol violations instead of
+    /// accepting them
+    pub strict: bool,
+    /// Hold back all messages behind those queued for flow control, so each
+    /// subscriber gets them in publish order
+    pub ordered_delivery: bool,
+    /// Delivery priority of messages on matching topics; the first matching
+    /// rule applies
+    pub topic_priorities: Vec<TopicPriorityRule>,
+    /// How a member of a shared subscription group is picked for a message
+    pub shared_subscription_strategy: SharedDeliveryStrategy,
+    /// Strategies of individual share groups
+    pub shared_subscription_groups: Vec<SharedGroupRule>,
+    /// Whether `$queue/<filter>` subscriptions are shared among their
+    /// subscribers, rather than plain topic filters
+    pub queue_subscriptions: bool,
 }
 
 /// TLS configuration for the broker
@@ -120,6 +250,30 @@ pub struct TlsConfig {
     pub require_client_cert: bool,
 }
 
+impl BrokerConfig {
+    /// Limits of a new session under the given queue overflow policy
+    pub(crate) fn session_limits(&self, queue_overflow: QueueOverflowPolicy) -> SessionLimits {
+        SessionLimits {
+            max_pending_messages: self.max_queued_messages,
+            queue_overflow,
+            topic_priorities: self.topic_priorities.as_slice().into(),
+            max_inflight: self.max_inflight,
+            max_awaiting_rel: self.max_awaiting_rel,
+        }
+    }
+
+    /// How long after its last transmission a message that was already
+    /// retransmitted `retry_count` times is sent again
+    pub(crate) fn retry_delay(&self, retry_count: u32) -> Duration {
+        if self.retry_max_interval.is_zero() {
+            return self.retry_interval;
+        }
+        self.retry_interval
+            .saturating_mul(1 << retry_count.min(16))
+            .min(self.retry_max_interval)
+    }
+}
+
 impl Default for BrokerConfig {
     fn default() -> Self {
         Self {
@@ -132,7 +286,9 @@ impl Default for BrokerConfig {
             max_packet_size: 1024 * 1024, // 1 MB
             default_keep_alive: 60,
             max_keep_alive: 65535,
+            server_keep_alive: None,
             session_expiry_check_interval: Duration::from_secs(60),
+            parked_timeout: Duration::ZERO,
             receive_maximum: 65535,
             max_qos: QoS::ExactlyOnce,
             retain_available: true,
@@ -143,21 +299,52 @@ impl Default for BrokerConfig {
             num_workers: num_cpus::get(),
             sys_topics_enabled: true,
             sys_topics_interval: Duration::from_secs(10),
+            sys_filter_topics: false,
+            sys_session_topics: false,
+            sys_client_topics: false,
+            sys_client_interval: Duration::from_secs(60),
+            presence_topics: false,
             max_inflight: 32,
             max_queued_messages: 1000,
+            queue_overflow: QueueOverflowPolicy::default(),
+            spill_threshold: 0,
             max_awaiting_rel: 100,
             retry_interval: Duration::from_secs(30),
+            retry_max_interval: Duration::ZERO,
+            max_retries: 0,
+            retry_exhausted: RetryExhaustedPolicy::default(),
             outbound_channel_capacity: 1024,
-            max_topic_levels: 0, // 0 = unlimited
+            write_coalesce_bytes: 64 * 1024,
+            write_coalesce_delay: Duration::ZERO,
+            fanout_threshold: 128,
+            slow_consumer: SlowConsumerConfig::default(),
+            max_topic_levels: 0,             // 0 = unlimited
+            max_subscriptions_per_client: 0, // 0 = unlimited
             proxy_protocol: ProxyProtocolConfig::default(),
             tls_proxy_protocol: ProxyProtocolConfig::default(),
             ws_proxy_protocol: ProxyProtocolConfig::default(),
+            max_retained_messages: 0, // 0 = unlimited
+            max_retained_bytes: 0,    // 0 = unlimited
+            retained_policy: RetainedPolicy::default(),
+            tenancy: TenancyConfig::default(),
+            mount_points: MountPointConfig::default(),
+            redirect: None,
+            validate_payload_format: false,
+            content_types: Vec::new(),
+            payload_limits: Vec::new(),
+            schemas: Arc::new(SchemaRegistry::default()),
+            strict: true,
+            ordered_delivery: false,
+            topic_priorities: Vec::new(),
+            shared_subscription_strategy: SharedDeliveryStrategy::default(),
+            shared_subscription_groups: Vec::new(),
+            queue_subscriptions: false,
         }
     }
 }
 
 // Helper to get number of CPUs
-mod num_cpus {
+pub(crate) mod num_cpus {
     pub fn get() -> usize {
         std::thread::available_parallelism()
             .map(|n| n.get())
@@ -168,38 +355,164 @@ mod num_cpus {
 /// Retained message
 #[derive(Debug, Clone)]
 pub struct RetainedMessage {
-    pub topic: String,
+    pub topic: Topic,
     pub payload: Bytes,
     pub qos: QoS,
     pub properties: Properties,
     pub timestamp: Instant,
+    /// Tenant of the publisher (when tenancy is enabled)
+    pub tenant: Option<Arc<str>>,
+}
+
+/// Listener a client connected through
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Listener {
+    /// Plain MQTT over TCP
+    #[default]
+    Tcp,
+    /// MQTT over TLS
+    Tls,
+    /// MQTT over WebSocket
+    WebSocket,
+}
+
+impl Listener {
+    /// Name used in configuration
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Listener::Tcp => "tcp",
+            Listener::Tls => "tls",
+            Listener::WebSocket => "ws",
+        }
+    }
+}
+
+/// Why a client's connection ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The client sent DISCONNECT with Normal Disconnection
+    Normal,
+    /// The client sent DISCONNECT with another reason code, such as
+    /// Disconnect with Will Message, so its will is published
+    WithWill,
+    /// A new connection with the same client ID took the session over
+    Takeover,
+    /// Nothing was received from the client within 1.5x its keep alive
+    KeepAliveTimeout,
+    /// The client sent a malformed packet or broke the protocol
+    ProtocolError,
+    /// The network connection closed without a DISCONNECT
+    ConnectionLost,
+    /// The server disconnected the client, pointing it at another server
+    Redirected,
+    /// The client's message queue overflowed under the disconnect policy
+    QueueOverflow,
+    /// The client neither subscribed nor published within the parked timeout
+    Parked,
+    /// Unacked messages ran out of retransmissions under the disconnect policy
+    RetriesExhausted,
+    /// The client read too slowly under the slow consumer disconnect policy
+    SlowConsumer,
+}
+
+impl DisconnectReason {
+    /// Name used in logs and event consumers
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DisconnectReason::Normal => "normal",
+            DisconnectReason::WithWill => "with_will",
+            DisconnectReason::Takeover => "takeover",
+            DisconnectReason::KeepAliveTimeout => "keep_alive_timeout",
+            DisconnectReason::ProtocolError => "protocol_error",
+            DisconnectReason::ConnectionLost => "connection_lost",
+            DisconnectReason::Redirected => "redirected",
+            DisconnectReason::QueueOverflow => "queue_overflow",
+            DisconnectReason::Parked => "parked",
+            DisconnectReason::RetriesExhausted => "retries_exhausted",
+            DisconnectReason::SlowConsumer => "slow_consumer",
+        }
+    }
+
+    /// Whether the client asked to disconnect, rather than being dropped
+    pub fn is_graceful(&self) -> bool {
+        matches!(self, DisconnectReason::Normal | DisconnectReason::WithWill)
+    }
+
+    /// Whether the client's will message is published [MQTT-3.1.2-8]; on
+    /// takeover the session carries on with the new connection instead
+    pub fn publishes_will(&self) -> bool {
+        !matches!(self, DisconnectReason::Normal | DisconnectReason::Takeover)
+    }
+}
+
+/// Why a message was not delivered to a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// The client's message queue was full
+    QueueFull,
+    /// The message ran out of retransmissions under the drop policy
+    RetriesExhausted,
+    /// The client is a slow consumer under the drop_qos0 policy
+    SlowConsumer,
+    /// The client's outbound channel was full
+    ChannelFull,
+}
+
+impl DropReason {
+    /// Name used in logs and event consumers
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DropReason::QueueFull => "queue_full",
+            DropReason::RetriesExhausted => "retries_exhausted",
+            DropReason::SlowConsumer => "slow_consumer",
+            DropReason::ChannelFull => "channel_full",
+        }
+    }
 }
 
 /// Broker events
 #[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum BrokerEvent {
     /// Client connected
     ClientConnected {
         client_id: Arc<str>,
         protocol_version: ProtocolVersion,
+        listener: Listener,
     },
     /// Client disconnected
-    ClientDisconnected { client_id: Arc<str> },
+    ClientDisconnected {
+        client_id: Arc<str>,
+        reason: DisconnectReason,
+    },
     /// Message published (includes payload for bridge forwarding)
     MessagePublished {
-        topic: String,
+        topic: Topic,
         payload: Bytes,
         qos: QoS,
         retain: bool,
+        /// Already forwarded to cluster peers by the publishing connection
+        cluster_forwarded: bool,
+        /// Bridges the message crossed before reaching this broker
+        origin: Option<BridgeOrigin>,
+        /// Properties of the message (MQTT v5), such as Response Topic
+        properties: Properties,
+    },
+    /// Message not delivered to a client
+    MessageDropped {
+        client_id: Arc<str>,
+        topic: Arc<str>,
+        reason: DropReason,
     },
-    /// Message dropped due to queue overflow
-    MessageDropped,
     /// Subscription added (for cluster synchronization)
     SubscriptionAdded { filter: String, client_id: Arc<str> },
     /// Subscription removed (for cluster synchronization)
     SubscriptionRemoved { filter: String, client_id: Arc<str> },
 }
 
+/// Outbound channels of the connected clients, sharded by client ID
+pub type ConnectionMap = ShardedMap<mpsc::Sender<Packet>>;
+
 /// The MQTT Broker
 pub struct Broker {
     /// Configuration
@@ -209,13 +522,17 @@ pub struct Broker {
     /// Subscription store
     subscriptions: Arc<SubscriptionStore>,
     /// Retained messages
-    retained: Arc<DashMap<String, RetainedMessage>>,
+    retained: Arc<RetainedStore>,
     /// Active connections (client_id -> connection handle)
-    connections: Arc<DashMap<Arc<str>, mpsc::Sender<Packet>>>,
+    connections: Arc<ConnectionMap>,
     /// Shutdown signal
     shutdown: broadcast::Sender<()>,
     /// Event channel
     events: broadcast::Sender<BrokerEvent>,
+    /// Reports of messages not delivered to clients
+    drops: Arc<DropReports>,
+    /// Drops for the dropped hook, taken by the task calling it
+    dropped_rx: Mutex<Option<mpsc::Receiver<DroppedMessage>>>,
     /// Hooks for auth/ACL and events
     hooks: Arc<dyn Hooks>,
     /// Bridge manager for remote broker connections
@@ -228,6 +545,22 @@ pub struct Broker {
     persistence: Option<Arc<PersistenceManager>>,
     /// Flapping detector for DoS protection
     flapping_detector: Option<Arc<FlappingDetector>>,
+    /// Message journal
+    journal: Option<Arc<Journal>>,
+    /// Redirect of new connections to another server
+    redirect: Arc<ServerRedirect>,
+    /// Clients reading slower than messages arrive for them
+    slow_consumers: Arc<SlowConsumers>,
+    /// Bound listeners, for readiness
+    listeners: Arc<ListenerStatus>,
+    /// Process and runtime resource usage
+    resources: Arc<ResourceMonitor>,
+    /// Message routing rules
+    rules: Arc<RuleEngine>,
+    /// Worker runtimes connections are handed off to, if any
+    worker_runtimes: Option<Arc<WorkerRuntimes>>,
+    /// Auth and ACL providers that replicated users and roles are applied to
+    access_control: Option<(Arc<AuthProvider>, Arc<AclProvider>)>,
 }
 
 impl Broker {
@@ -240,21 +573,61 @@ impl Broker {
     pub fn with_hooks(config: BrokerConfig, hooks: Arc<dyn Hooks>) -> Self {
         let (shutdown, _) = broadcast::channel(1);
         let (events, _) = broadcast::channel(16384);
+        let (drops, dropped_rx) = if hooks.observes_messages() {
+            let (drops, rx) = DropReports::with_hook(events.clone());
+            (drops, Some(rx))
+        } else {
+            (DropReports::new(events.clone()), None)
+        };
+        let retained = Arc::new(RetainedStore::with_limits(
+            config.max_retained_messages,
+            config.max_retained_bytes,
+            config.retained_policy,
+        ));
+        let redirect = Arc::new(ServerRedirect::new(config.redirect.clone()));
+        let slow_consumers = Arc::new(SlowConsumers::new(config.slow_consumer.clone()));
+
+        let sessions = Arc::new(SessionStore::with_workers(config.num_workers));
+        let connections = Arc::new(ConnectionMap::new(config.num_workers));
+        let subscriptions = Arc::new(
+            SubscriptionStore::with_shared_delivery(
+                config.shared_subscription_strategy,
+                &config.shared_subscription_groups,
+            )
+            .with_queue_subscriptions(config.queue_subscriptions),
+        );
+        let inflight_sessions = Arc::downgrade(&sessions);
+        subscriptions.set_inflight_probe(Arc::new(move |client_id: &str| {
+            inflight_sessions
+                .upgrade()
+                .and_then(|sessions| sessions.get(client_id))
+                .map_or(0, |session| session.read().inflight_outgoing.len())
+        }));
 
         Self {
             config,
-            sessions: Arc::new(SessionStore::new()),
-            subscriptions: Arc::new(SubscriptionStore::new()),
-            retained: Arc::new(DashMap::new()),
-            connections: Arc::new(DashMap::new()),
+            sessions,
+            subscriptions,
+            retained,
+            connections,
             shutdown,
             events,
+            drops: Arc::new(drops),
+            dropped_rx: Mutex::new(dropped_rx),
             hooks,
             bridge_manager: None,
             cluster_manager: None,
             metrics: None,
             persistence: None,
             flapping_detector: None,
+            journal: None,
+            redirect,
+            slow_consumers,
+            listeners: Arc::new(ListenerStatus::default()),
+            resources: Arc::new(ResourceMonitor::default()),
+            rules: Arc::new(RuleEngine::new()),
+            worker_runtimes: None,
+            access_control: None,
         }
     }
 
@@ -263,6 +636,17 @@ impl Broker {
         self.flapping_detector = Some(Arc::new(detector));
     }
 
+    /// Run connections on worker runtimes rather than the broker's runtime
+    pub fn set_worker_runtimes(&mut self, workers: WorkerRuntimes) {
+        self.worker_runtimes = Some(Arc::new(workers));
+    }
+
+    /// Apply users and ACL roles committed through cluster metadata to
+    /// these providers
+    pub fn set_access_control(&mut self, auth: Arc<AuthProvider>, acl: Arc<AclProvider>) {
+        self.access_control = Some((auth, acl));
+    }
+
     /// Get flapping detector (if enabled)
     pub fn flapping_detector(&self) -> Option<&Arc<FlappingDetector>> {
         self.flapping_detector.as_ref()
@@ -270,6 +654,13 @@ impl Broker {
 
     /// Set persistence manager for this broker
     pub fn set_persistence(&mut self, persistence: Arc<PersistenceManager>) {
+        persistence.set_hooks(self.hooks.clone());
+        if self.config.spill_threshold > 0 {
+            self.sessions.enable_spill(QueueSpill::new(
+                persistence.clone(),
+                self.config.spill_threshold,
+            ));
+        }
         self.persistence = Some(persistence);
     }
 
@@ -278,6 +669,54 @@ impl Broker {
         self.persistence.as_ref()
     }
 
+    /// Serialize a client's session to migrate it to another broker
+    ///
+    /// See `SessionStore::export`; returns `None` if the client has no session.
+    pub async fn export_session(
+        &self,
+        client_id: &str,
+    ) -> Result<Option<Vec<u8>>, PersistenceError> {
+        self.sessions.export(client_id).await
+    }
+
+    /// Install a session exported by another broker, for its client to
+    /// resume on the next connect, and return the client ID
+    ///
+    /// A client connected here under the same ID is disconnected, and its
+    /// session and subscriptions are replaced.
+    pub fn import_session(&self, bytes: &[u8]) -> Result<Arc<str>, PersistenceError> {
+        let stored = StoredSession::import(bytes)?;
+        let client_id: Arc<str> = stored.client_id.as_str().into();
+
+        if let Some(sender) = self.connections.remove(&client_id) {
+            let _ = sender.try_send(Packet::Disconnect(Disconnect {
+                reason_code: ReasonCode::SessionTakenOver,
+                properties: Properties::default(),
+            }));
+        }
+        remove_subscriptions(&self.subscriptions, &self.events, &client_id);
+
+        let limits = self.config.session_limits(self.config.queue_overflow);
+        let session = self.sessions.import_stored(stored, limits);
+        restore_subscriptions(
+            &self.subscriptions,
+            &self.events,
+            &client_id,
+            &session.read(),
+        );
+        Ok(client_id)
+    }
+
+    /// Set the message journal for this broker
+    pub fn set_journal(&mut self, journal: Arc<Journal>) {
+        self.journal = Some(journal);
+    }
+
+    /// Get the message journal (if enabled)
+    pub fn journal(&self) -> Option<&Arc<Journal>> {
+        self.journal.as_ref()
+    }
+
     /// Set metrics for this broker
     pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
         self.metrics = Some(metrics);
@@ -288,7 +727,7 @@ impl Broker {
         self.metrics.as_ref()
     }
 
-    /// Clone broker for $SYS topics task (only needs publish capability)
+    /// Clone broker for $SYS topics task (only needs publish capability and cluster status)
     fn clone_for_sys_topics(&self) -> Self {
         Self {
             config: self.config.clone(),
@@ -298,15 +737,30 @@ impl Broker {
             connections: self.connections.clone(),
             shutdown: self.shutdown.clone(),
             events: self.events.clone(),
+            drops: self.drops.clone(),
+            dropped_rx: Mutex::new(None),
             hooks: self.hooks.clone(),
             bridge_manager: None,
-            cluster_manager: None,
+            cluster_manager: self.cluster_manager.clone(),
             metrics: None,
             persistence: self.persistence.clone(),
             flapping_detector: None,
+            journal: None,
+            redirect: self.redirect.clone(),
+            slow_consumers: self.slow_consumers.clone(),
+            listeners: self.listeners.clone(),
+            resources: self.resources.clone(),
+            rules: self.rules.clone(),
+            worker_runtimes: self.worker_runtimes.clone(),
+            access_control: None,
         }
     }
 
+    /// Rules evaluated on messages published by clients
+    pub fn rules(&self) -> &Arc<RuleEngine> {
+        &self.rules
+    }
+
     /// Set the bridge manager for this broker
     pub fn set_bridge_manager(&mut self, manager: BridgeManager) {
         self.bridge_manager = Some(Arc::new(manager));
@@ -317,6 +771,26 @@ impl Broker {
         self.cluster_manager = Some(Arc::new(manager));
     }
 
+    /// Get the cluster manager, if clustering is enabled
+    pub fn cluster_manager(&self) -> Option<Arc<ClusterManager>> {
+        self.cluster_manager.clone()
+    }
+
+    /// Get the bridge manager, if bridges are configured
+    pub fn bridge_manager(&self) -> Option<Arc<BridgeManager>> {
+        self.bridge_manager.clone()
+    }
+
+    /// Get the status of the listeners
+    pub fn listener_status(&self) -> Arc<ListenerStatus> {
+        self.listeners.clone()
+    }
+
+    /// Get the latest sample of process and runtime resource usage
+    pub fn resource_stats(&self) -> ResourceStats {
+        self.resources.latest()
+    }
+
     /// Create a cluster manager with inbound callback that publishes to this broker
     pub async fn create_cluster_manager(
         &self,
@@ -326,15 +800,25 @@ impl Broker {
         let sessions = self.sessions.clone();
         let subscriptions = self.subscriptions.clone();
         let connections = self.connections.clone();
+        let drops = self.drops.clone();
         let persistence = self.persistence.clone();
+        // With raft, retained messages arrive through the metadata callback
+        let raft_retained = config.consistency == crate::config::Consistency::Strong;
 
         // Callback for messages received from cluster peers
         let inbound_callback = Arc::new(
-            move |topic: String, payload: Bytes, qos: QoS, retain: bool, _origin_node: String| {
+            move |topic: String,
+                  payload: Bytes,
+                  qos: QoS,
+                  retain: bool,
+                  _origin_node: String,
+                  share_groups: Option<Vec<String>>,
+                  properties: Properties| {
                 debug!(
                     "Cluster inbound_callback: routing '{}' to local subscribers",
                     topic
                 );
+                let topic = Topic::from(topic);
 
                 // Create a publish packet
                 let publish = Publish {
@@ -344,89 +828,174 @@ impl Broker {
                     topic: topic.clone(),
                     packet_id: None,
                     payload: payload.clone(),
-                    properties: Properties::default(),
+                    properties,
                 };
 
-                // Handle retained message
-                if retain {
-                    if payload.is_empty() {
-                        retained.remove(&topic);
-                        if let Some(ref persistence) = persistence {
-                            persistence.write(PersistenceOp::DeleteRetained {
-                                topic: topic.clone(),
-                            });
-                        }
-                    } else {
-                        let retained_msg = RetainedMessage {
-                            topic: topic.clone(),
+                // Handle retained message (still routed if the store is full)
+                if retain && !raft_retained {
+                    retained.apply_publish(
+                        RetainedPublish {
+                            topic: &topic,
                             payload,
                             qos,
-                            properties: Properties::default(),
-                            timestamp: Instant::now(),
-                        };
-                        retained.insert(topic.clone(), retained_msg.clone());
-                        if let Some(ref persistence) = persistence {
-                            persistence.write(PersistenceOp::SetRetained {
-                                topic: topic.clone(),
-                                message: StoredRetainedMessage::from(&retained_msg),
-                            });
-                        }
-                    }
+                            properties: publish.properties.clone(),
+                            tenant: None,
+                        },
+                        persistence.as_ref(),
+                    );
                 }
 
-                // Route to local subscribers only
+                // Route to local subscribers only, deduplicated by client; the
+                // publisher is connected to another node, so No Local never applies
                 let matches = subscriptions.matches(&topic);
-
-                // Deduplicate by client_id (keep highest QoS) - use AHashMap for faster lookup
-                let mut client_qos: AHashMap<Arc<str>, QoS> =
-                    AHashMap::with_capacity(matches.len());
-                for sub in matches {
-                    let entry = client_qos
-                        .entry(sub.client_id.clone())
-                        .or_insert(QoS::AtMostOnce);
-                    if sub.qos > *entry {
-                        *entry = sub.qos;
+                let client_matches = group_by_client(matches, None, |sub| {
+                    // Shared publishes name the groups this node delivers to;
+                    // other nodes serve the remaining groups
+                    match (&sub.share_group, &share_groups) {
+                        (Some(group), Some(groups)) => {
+                            groups.iter().any(|g| g.as_str() == group.as_ref())
+                        }
+                        _ => true,
                     }
-                }
+                });
 
                 debug!(
                     "Cluster inbound_callback: found {} local subscribers for '{}'",
-                    client_qos.len(),
+                    client_matches.len(),
                     topic
                 );
 
-                // Send to each local client
-                for (client_id, sub_qos) in client_qos {
-                    let effective_qos = qos.min(sub_qos);
+                deliver_to_clients(&connections, &sessions, &drops, client_matches, &publish);
+            },
+        );
 
-                    if let Some(sender) = connections.get(&client_id) {
-                        let mut publish = publish.clone();
-                        publish.qos = effective_qos;
-                        match sender.try_send(Packet::Publish(publish)) {
-                            Ok(()) => {
-                                debug!("Cluster inbound_callback: sent to client {}", client_id)
-                            }
-                            Err(e) => debug!(
-                                "Cluster inbound_callback: failed to send to {}: {:?}",
-                                client_id, e
-                            ),
-                        }
+        // Callback for clients that reconnected to another cluster node
+        let sessions = self.sessions.clone();
+        let subscriptions = self.subscriptions.clone();
+        let events = self.events.clone();
+        let connections = self.connections.clone();
+        let takeover_callback = Arc::new(
+            move |client_id: String, transfer: bool| -> BoxFuture<'static, Option<StoredSession>> {
+                // Kick the local connection, if the client is still connected here
+                if let Some(sender) = connections.remove(client_id.as_str()) {
+                    let _ = sender.try_send(Packet::Disconnect(Disconnect {
+                        reason_code: ReasonCode::SessionTakenOver,
+                        properties: Properties::default(),
+                    }));
+                }
+                remove_subscriptions(&subscriptions, &events, &client_id);
+
+                // The session now lives on the other node
+                let sessions = sessions.clone();
+                Box::pin(async move {
+                    let session = sessions.get(&client_id)?;
+                    sessions.remove(&client_id);
+                    let clean_start = session.read().clean_start;
+                    if transfer && !clean_start {
+                        Some(sessions.export_stored(&session).await)
                     } else {
-                        // Client disconnected, queue message if persistent session
-                        if let Some(session) = sessions.get(client_id.as_ref()) {
-                            let mut s = session.write();
-                            if !s.clean_start {
-                                let mut publish = publish.clone();
-                                publish.qos = effective_qos;
-                                s.queue_message(publish);
-                            }
-                        }
+                        None
                     }
-                }
+                })
             },
         );
 
-        ClusterManager::new(config, inbound_callback).await
+        // Callback for metadata committed through raft (strong consistency)
+        let retained = self.retained.clone();
+        let persistence = self.persistence.clone();
+        let access_control = self.access_control.clone();
+        let metadata_callback = Arc::new(move |command: &MetadataCommand| match command {
+            MetadataCommand::SetRetained {
+                topic,
+                payload,
+                qos,
+            } => {
+                retained.apply_publish(
+                    RetainedPublish {
+                        topic: &Topic::from(topic.as_str()),
+                        payload: Bytes::copy_from_slice(payload),
+                        qos: QoS::from_u8(*qos).unwrap_or(QoS::AtMostOnce),
+                        properties: Properties::default(),
+                        tenant: None,
+                    },
+                    persistence.as_ref(),
+                );
+            }
+            MetadataCommand::SetUser(user) => {
+                if let Some((ref auth, _)) = access_control {
+                    auth.set_user(user);
+                }
+                if let Some(ref persistence) = persistence {
+                    persistence.write(PersistenceOp::SetUser {
+                        username: user.username.clone(),
+                        user: user.clone(),
+                    });
+                }
+            }
+            MetadataCommand::DeleteUser { username } => {
+                if let Some((ref auth, _)) = access_control {
+                    auth.remove_user(username);
+                }
+                if let Some(ref persistence) = persistence {
+                    persistence.write(PersistenceOp::DeleteUser {
+                        username: username.clone(),
+                    });
+                }
+            }
+            MetadataCommand::SetRole(role) => {
+                if let Some((_, ref acl)) = access_control {
+                    acl.set_role(role);
+                }
+                if let Some(ref persistence) = persistence {
+                    persistence.write(PersistenceOp::SetRole {
+                        name: role.name.clone(),
+                        role: role.clone(),
+                    });
+                }
+            }
+            MetadataCommand::DeleteRole { name } => {
+                if let Some((_, ref acl)) = access_control {
+                    acl.remove_role(name);
+                }
+                if let Some(ref persistence) = persistence {
+                    persistence.write(PersistenceOp::DeleteRole { name: name.clone() });
+                }
+            }
+            // Session ownership is read from the raft state directly
+            MetadataCommand::ClaimClient { .. } | MetadataCommand::ReleaseClient { .. } => {}
+        });
+
+        // Callback for the retained messages of peers, filling in the topics
+        // this node holds no retained message for
+        let retained = self.retained.clone();
+        let persistence = self.persistence.clone();
+        let retained_callback = Arc::new(move |messages: Vec<(String, StoredRetainedMessage)>| {
+            for (key, stored) in messages {
+                let (tenant, topic) = split_tenant_key(&key);
+                if retained.get(topic).is_some() {
+                    continue;
+                }
+                retained.apply_publish(
+                    RetainedPublish {
+                        topic: &Topic::from(topic),
+                        payload: Bytes::from(stored.payload),
+                        qos: QoS::from_u8(stored.qos).unwrap_or(QoS::AtMostOnce),
+                        properties: Properties::from(stored.properties),
+                        tenant: tenant.map(Arc::from),
+                    },
+                    persistence.as_ref(),
+                );
+            }
+        });
+
+        let manager = ClusterManager::new(
+            config,
+            inbound_callback,
+            takeover_callback,
+            metadata_callback,
+        )
+        .await?;
+        Ok(manager.with_retained(self.retained.clone(), retained_callback))
     }
 
     /// Create a bridge manager with inbound callback that publishes to this broker
@@ -438,10 +1007,21 @@ impl Broker {
         let sessions = self.sessions.clone();
         let subscriptions = self.subscriptions.clone();
         let connections = self.connections.clone();
+        let drops = self.drops.clone();
         let persistence = self.persistence.clone();
 
         let inbound_callback = Arc::new(
-            move |topic: String, payload: Bytes, qos: QoS, retain: bool| {
+            move |topic: String,
+                  payload: Bytes,
+                  qos: QoS,
+                  retain: bool,
+                  origin: Option<BridgeOrigin>,
+                  mut properties: Properties| {
+                // Keep the origin chain so bridges of other brokers see it
+                properties.user_properties =
+                    origin.map(|o| o.to_user_properties()).unwrap_or_default();
+                let topic = Topic::from(topic);
+
                 // Create a publish packet
                 let publish = Publish {
                     dup: false,
@@ -450,81 +1030,40 @@ impl Broker {
                     topic: topic.clone(),
                     packet_id: None,
                     payload: payload.clone(),
-                    properties: Properties::default(),
+                    properties: properties.clone(),
                 };
 
-                // Handle retained message
+                // Handle retained message (still routed if the store is full)
                 if retain {
-                    if payload.is_empty() {
-                        retained.remove(&topic);
-                        if let Some(ref persistence) = persistence {
-                            persistence.write(PersistenceOp::DeleteRetained {
-                                topic: topic.clone(),
-                            });
-                        }
-                    } else {
-                        let retained_msg = RetainedMessage {
-                            topic: topic.clone(),
+                    retained.apply_publish(
+                        RetainedPublish {
+                            topic: &topic,
                             payload,
                             qos,
-                            properties: Properties::default(),
-                            timestamp: Instant::now(),
-                        };
-                        retained.insert(topic.clone(), retained_msg.clone());
-                        if let Some(ref persistence) = persistence {
-                            persistence.write(PersistenceOp::SetRetained {
-                                topic: topic.clone(),
-                                message: StoredRetainedMessage::from(&retained_msg),
-                            });
-                        }
-                    }
+                            properties,
+                            tenant: None,
+                        },
+                        persistence.as_ref(),
+                    );
                 }
 
-                // Route to subscribers
+                // Route to subscribers, deduplicated by client; bridged messages
+                // have no local publisher for No Local to apply to
                 let matches = subscriptions.matches(&topic);
+                let client_matches = group_by_client(matches, None, |_| true);
 
-                // Deduplicate by client_id (keep highest QoS) - use AHashMap for faster lookup
-                let mut client_qos: AHashMap<Arc<str>, QoS> =
-                    AHashMap::with_capacity(matches.len());
-                for sub in matches {
-                    let entry = client_qos
-                        .entry(sub.client_id.clone())
-                        .or_insert(QoS::AtMostOnce);
-                    if sub.qos > *entry {
-                        *entry = sub.qos;
-                    }
-                }
-
-                // Send to each client
-                for (client_id, sub_qos) in client_qos {
-                    let effective_qos = qos.min(sub_qos);
-
-                    if let Some(sender) = connections.get(&client_id) {
-                        let mut publish = publish.clone();
-                        publish.qos = effective_qos;
-                        let _ = sender.try_send(Packet::Publish(publish));
-                    } else {
-                        // Client disconnected, queue message if persistent session
-                        if let Some(session) = sessions.get(client_id.as_ref()) {
-                            let mut s = session.write();
-                            if !s.clean_start {
-                                let mut publish = publish.clone();
-                                publish.qos = effective_qos;
-                                s.queue_message(publish);
-                            }
-                        }
-                    }
-                }
+                deliver_to_clients(&connections, &sessions, &drops, client_matches, &publish);
             },
         );
 
-        BridgeManager::from_configs(configs, inbound_callback)
+        BridgeManager::from_configs(configs, inbound_callback, self.persistence.clone())
     }
 
     /// Run the broker
     pub async fn run(&self) -> Result<(), std::io::Error> {
         let listener = create_tcp_listener(self.config.bind_addr)?;
         info!("MQTT/TCP listening on {}", self.config.bind_addr);
+        self.listeners.bound(Listener::Tcp, self.config.bind_addr);
 
         // Spawn TCP accept loop immediately to handle connection bursts
         self.spawn_tcp_accept_loop(listener);
@@ -532,6 +1071,7 @@ impl Broker {
         // Spawn WebSocket listener if configured
         if let Some(ws_addr) = self.config.ws_bind_addr {
             let ws_listener = create_tcp_listener(ws_addr)?;
+            self.listeners.bound(Listener::WebSocket, ws_addr);
             info!(
                 "MQTT/WebSocket listening on {} (path: {})",
                 ws_addr, self.config.ws_path
@@ -548,11 +1088,17 @@ impl Broker {
             let metrics = self.metrics.clone();
             let persistence = self.persistence.clone();
             let flapping_detector = self.flapping_detector.clone();
+            let cluster_manager = self.cluster_manager.clone();
+            let redirect = self.redirect.clone();
+            let slow_consumers = self.slow_consumers.clone();
+            let drops = self.drops.clone();
+            let rules = self.rules.clone();
+            let workers = self.worker_runtimes.clone();
 
             tokio::spawn(async move {
                 loop {
                     match ws_listener.accept().await {
-                        Ok((mut stream, addr)) => {
+                        Ok((stream, addr)) => {
                             debug!("New WebSocket connection from {}", addr);
                             let sessions = sessions.clone();
                             let subscriptions = subscriptions.clone();
@@ -564,12 +1110,23 @@ impl Broker {
                             let metrics = metrics.clone();
                             let persistence = persistence.clone();
                             let flapping_detector = flapping_detector.clone();
+                            let cluster_manager = cluster_manager.clone();
+                            let redirect = redirect.clone();
+                            let slow_consumers = slow_consumers.clone();
+                            let drops = drops.clone();
+                            let rules = rules.clone();
                             let mut shutdown_rx = shutdown.subscribe();
 
-                            tokio::spawn(async move {
-                                // Handle PROXY protocol before WebSocket handshake if enabled
-                                let (effective_addr, proxy_info) =
-                                    if config.ws_proxy_protocol.enabled {
+                            spawn_connection(
+                                workers.as_deref(),
+                                stream,
+                                addr,
+                                move |mut stream| async move {
+                                    // Handle PROXY protocol before WebSocket handshake if enabled
+                                    let (effective_addr, proxy_info) = if config
+                                        .ws_proxy_protocol
+                                        .enabled
+                                    {
                                         match parse_proxy_header(
                                             &mut stream,
                                             config.ws_proxy_protocol.timeout,
@@ -593,86 +1150,94 @@ impl Broker {
                                         (addr, None)
                                     };
 
-                                // Check flapping/rate limits before WebSocket handshake
-                                let client_ip = effective_addr.ip();
-                                if let Some(ref detector) = flapping_detector {
-                                    if let Err(reason) = detector.check_connection(client_ip) {
-                                        debug!(
-                                            "Rejecting WebSocket connection from {}: {:?}",
-                                            client_ip, reason
-                                        );
-                                        return;
+                                    // Check flapping/rate limits before WebSocket handshake
+                                    let client_ip = effective_addr.ip();
+                                    if let Some(ref detector) = flapping_detector {
+                                        if let Err(reason) = detector.check_connection(client_ip) {
+                                            debug!(
+                                                "Rejecting WebSocket connection from {}: {:?}",
+                                                client_ip, reason
+                                            );
+                                            return;
+                                        }
+                                        detector.record_connection(client_ip);
                                     }
-                                    detector.record_connection(client_ip);
-                                }
-
-                                // Perform WebSocket handshake with path validation
-                                match WsStream::accept_with_path(stream, &config.ws_path).await {
-                                    Ok(ws_stream) => {
-                                        debug!(
-                                            "WebSocket handshake complete for {}",
-                                            effective_addr
-                                        );
-                                        let mut conn = Connection::new(
-                                            ws_stream,
-                                            effective_addr,
-                                            proxy_info,
-                                            sessions,
-                                            subscriptions,
-                                            retained,
-                                            connections,
-                                            config,
-                                            events,
-                                            hooks,
-                                            metrics,
-                                            persistence,
-                                        );
-
-                                        {
-                                            let conn_fut = conn.run();
-                                            tokio::pin!(conn_fut);
-
-                                            loop {
-                                                tokio::select! {
-                                                    biased;
 
-                                                    result = &mut conn_fut => {
-                                                        if let Err(e) = result {
-                                                            debug!("WebSocket connection error from {}: {}", effective_addr, e);
+                                    // Perform WebSocket handshake with path validation
+                                    match WsStream::accept_with_path(stream, &config.ws_path).await
+                                    {
+                                        Ok(ws_stream) => {
+                                            debug!(
+                                                "WebSocket handshake complete for {}",
+                                                effective_addr
+                                            );
+                                            let mut conn = Connection::new(
+                                                ws_stream,
+                                                effective_addr,
+                                                proxy_info,
+                                                sessions,
+                                                subscriptions,
+                                                retained,
+                                                connections,
+                                                config,
+                                                events,
+                                                hooks,
+                                                metrics,
+                                                persistence,
+                                            )
+                                            .with_listener(Listener::WebSocket)
+                                            .with_cluster(cluster_manager)
+                                            .with_redirect(redirect)
+                                            .with_slow_consumers(slow_consumers)
+                                            .with_drops(drops)
+                                            .with_rules(rules);
+
+                                            {
+                                                let conn_fut = conn.run();
+                                                tokio::pin!(conn_fut);
+
+                                                loop {
+                                                    tokio::select! {
+                                                        biased;
+
+                                                        result = &mut conn_fut => {
+                                                            if let Err(e) = result {
+                                                                debug!("WebSocket connection error from {}: {}", effective_addr, e);
+                                                            }
+                                                            break;
                                                         }
-                                                        break;
-                                                    }
-                                                    result = shutdown_rx.recv() => {
-                                                        match result {
-                                                            Ok(()) => break,
-                                                            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
-                                                            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                                                        result = shutdown_rx.recv() => {
+                                                            match result {
+                                                                Ok(()) => break,
+                                                                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                                                                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                                                            }
                                                         }
                                                     }
                                                 }
                                             }
-                                        }
 
-                                        // Return buffers to the pool for reuse
-                                        conn.return_buffers();
+                                            // Return buffers to the pool for reuse
+                                            conn.return_buffers();
 
-                                        // Track disconnection for flapping detection
-                                        if let Some(ref detector) = flapping_detector {
-                                            detector.record_disconnection(effective_addr.ip());
+                                            // Track disconnection for flapping detection
+                                            if let Some(ref detector) = flapping_detector {
+                                                detector.record_disconnection(effective_addr.ip());
+                                            }
                                         }
-                                    }
-                                    Err(e) => {
-                                        debug!(
-                                            "WebSocket handshake failed for {}: {}",
-                                            effective_addr, e
-                                        );
-                                        // Track disconnection even on handshake failure
-                                        if let Some(ref detector) = flapping_detector {
-                                            detector.record_disconnection(effective_addr.ip());
+                                        Err(e) => {
+                                            debug!(
+                                                "WebSocket handshake failed for {}: {}",
+                                                effective_addr, e
+                                            );
+                                            // Track disconnection even on handshake failure
+                                            if let Some(ref detector) = flapping_detector {
+                                                detector.record_disconnection(effective_addr.ip());
+                                            }
                                         }
                                     }
-                                }
-                            });
+                                },
+                            );
                         }
                         Err(e) => {
                             error!("Failed to accept WebSocket connection: {}", e);
@@ -699,6 +1264,7 @@ impl Broker {
 
             let tls_listener = create_tcp_listener(tls_addr)?;
             info!("MQTT/TLS listening on {}", tls_addr);
+            self.listeners.bound(Listener::Tls, tls_addr);
 
             let sessions = self.sessions.clone();
             let subscriptions = self.subscriptions.clone();
@@ -711,11 +1277,17 @@ impl Broker {
             let metrics = self.metrics.clone();
             let persistence = self.persistence.clone();
             let flapping_detector = self.flapping_detector.clone();
+            let cluster_manager = self.cluster_manager.clone();
+            let redirect = self.redirect.clone();
+            let slow_consumers = self.slow_consumers.clone();
+            let drops = self.drops.clone();
+            let rules = self.rules.clone();
+            let workers = self.worker_runtimes.clone();
 
             tokio::spawn(async move {
                 loop {
                     match tls_listener.accept().await {
-                        Ok((mut stream, addr)) => {
+                        Ok((stream, addr)) => {
                             debug!("New TLS connection from {}", addr);
                             let sessions = sessions.clone();
                             let subscriptions = subscriptions.clone();
@@ -728,12 +1300,23 @@ impl Broker {
                             let tls_acceptor = tls_acceptor.clone();
                             let persistence = persistence.clone();
                             let flapping_detector = flapping_detector.clone();
+                            let cluster_manager = cluster_manager.clone();
+                            let redirect = redirect.clone();
+                            let slow_consumers = slow_consumers.clone();
+                            let drops = drops.clone();
+                            let rules = rules.clone();
                             let mut shutdown_rx = shutdown.subscribe();
 
-                            tokio::spawn(async move {
-                                // Handle PROXY protocol before TLS handshake if enabled
-                                let (effective_addr, proxy_info) =
-                                    if config.tls_proxy_protocol.enabled {
+                            spawn_connection(
+                                workers.as_deref(),
+                                stream,
+                                addr,
+                                move |mut stream| async move {
+                                    // Handle PROXY protocol before TLS handshake if enabled
+                                    let (effective_addr, proxy_info) = if config
+                                        .tls_proxy_protocol
+                                        .enabled
+                                    {
                                         match parse_proxy_header(
                                             &mut stream,
                                             config.tls_proxy_protocol.timeout,
@@ -757,83 +1340,94 @@ impl Broker {
                                         (addr, None)
                                     };
 
-                                // Check flapping/rate limits before TLS handshake
-                                let client_ip = effective_addr.ip();
-                                if let Some(ref detector) = flapping_detector {
-                                    if let Err(reason) = detector.check_connection(client_ip) {
-                                        debug!(
-                                            "Rejecting TLS connection from {}: {:?}",
-                                            client_ip, reason
-                                        );
-                                        return;
+                                    // Check flapping/rate limits before TLS handshake
+                                    let client_ip = effective_addr.ip();
+                                    if let Some(ref detector) = flapping_detector {
+                                        if let Err(reason) = detector.check_connection(client_ip) {
+                                            debug!(
+                                                "Rejecting TLS connection from {}: {:?}",
+                                                client_ip, reason
+                                            );
+                                            return;
+                                        }
+                                        detector.record_connection(client_ip);
                                     }
-                                    detector.record_connection(client_ip);
-                                }
 
-                                // Perform TLS handshake
-                                match tls_acceptor.accept(stream).await {
-                                    Ok(tls_stream) => {
-                                        debug!("TLS handshake complete for {}", effective_addr);
-                                        let mut conn = Connection::new(
-                                            tls_stream,
-                                            effective_addr,
-                                            proxy_info,
-                                            sessions,
-                                            subscriptions,
-                                            retained,
-                                            connections,
-                                            config,
-                                            events,
-                                            hooks,
-                                            metrics,
-                                            persistence,
-                                        );
-
-                                        {
-                                            let conn_fut = conn.run();
-                                            tokio::pin!(conn_fut);
-
-                                            loop {
-                                                tokio::select! {
-                                                    biased;
-
-                                                    result = &mut conn_fut => {
-                                                        if let Err(e) = result {
-                                                            debug!("TLS connection error from {}: {}", effective_addr, e);
+                                    // Perform TLS handshake
+                                    match tls_acceptor.accept(stream).await {
+                                        Ok(tls_stream) => {
+                                            debug!("TLS handshake complete for {}", effective_addr);
+                                            let cert_cn = tls::peer_common_name(
+                                                tls_stream.get_ref().1.peer_certificates(),
+                                            );
+                                            let mut conn = Connection::new(
+                                                tls_stream,
+                                                effective_addr,
+                                                proxy_info,
+                                                sessions,
+                                                subscriptions,
+                                                retained,
+                                                connections,
+                                                config,
+                                                events,
+                                                hooks,
+                                                metrics,
+                                                persistence,
+                                            )
+                                            .with_listener(Listener::Tls)
+                                            .with_cert_cn(cert_cn)
+                                            .with_cluster(cluster_manager)
+                                            .with_redirect(redirect)
+                                            .with_slow_consumers(slow_consumers)
+                                            .with_drops(drops)
+                                            .with_rules(rules);
+
+                                            {
+                                                let conn_fut = conn.run();
+                                                tokio::pin!(conn_fut);
+
+                                                loop {
+                                                    tokio::select! {
+                                                        biased;
+
+                                                        result = &mut conn_fut => {
+                                                            if let Err(e) = result {
+                                                                debug!("TLS connection error from {}: {}", effective_addr, e);
+                                                            }
+                                                            break;
                                                         }
-                                                        break;
-                                                    }
-                                                    result = shutdown_rx.recv() => {
-                                                        match result {
-                                                            Ok(()) => break,
-                                                            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
-                                                            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                                                        result = shutdown_rx.recv() => {
+                                                            match result {
+                                                                Ok(()) => break,
+                                                                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                                                                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                                                            }
                                                         }
                                                     }
                                                 }
                                             }
-                                        }
 
-                                        // Return buffers to the pool for reuse
-                                        conn.return_buffers();
+                                            // Return buffers to the pool for reuse
+                                            conn.return_buffers();
 
-                                        // Track disconnection for flapping detection
-                                        if let Some(ref detector) = flapping_detector {
-                                            detector.record_disconnection(effective_addr.ip());
+                                            // Track disconnection for flapping detection
+                                            if let Some(ref detector) = flapping_detector {
+                                                detector.record_disconnection(effective_addr.ip());
+                                            }
                                         }
-                                    }
-                                    Err(e) => {
-                                        debug!(
-                                            "TLS handshake failed for {}: {}",
-                                            effective_addr, e
-                                        );
-                                        // Track disconnection even on handshake failure
-                                        if let Some(ref detector) = flapping_detector {
-                                            detector.record_disconnection(effective_addr.ip());
+                                        Err(e) => {
+                                            debug!(
+                                                "TLS handshake failed for {}: {}",
+                                                effective_addr, e
+                                            );
+                                            // Track disconnection even on handshake failure
+                                            if let Some(ref detector) = flapping_detector {
+                                                detector.record_disconnection(effective_addr.ip());
+                                            }
                                         }
                                     }
-                                }
-                            });
+                                },
+                            );
                         }
                         Err(e) => {
                             error!("Failed to accept TLS connection: {}", e);
@@ -843,8 +1437,17 @@ impl Broker {
             });
         }
 
-        // Spawn session expiry cleanup task
+        // Spawn session expiry cleanup task; delayed wills of the sessions
+        // it ends are published right away
         let sessions = self.sessions.clone();
+        let subscriptions = self.subscriptions.clone();
+        let retained = self.retained.clone();
+        let connections = self.connections.clone();
+        let config = self.config.clone();
+        let events = self.events.clone();
+        let drops = self.drops.clone();
+        let hooks = self.hooks.clone();
+        let persistence = self.persistence.clone();
         let interval = self.config.session_expiry_check_interval;
         let mut shutdown_rx = self.shutdown.subscribe();
         tokio::spawn(async move {
@@ -854,7 +1457,179 @@ impl Broker {
                     biased;
 
                     _ = ticker.tick() => {
-                        sessions.cleanup_expired();
+                        let mut expired = Vec::new();
+                        let due_wills = sessions
+                            .cleanup_expired_with(|client_id| expired.push(client_id.clone()));
+                        for client_id in expired {
+                            remove_subscriptions(&subscriptions, &events, &client_id);
+                            hooks.on_session_expired(&client_id).await;
+                        }
+                        for due in due_wills {
+                            publish_due_will(
+                                due,
+                                &config,
+                                &retained,
+                                persistence.as_ref(),
+                                &subscriptions,
+                                &connections,
+                                &sessions,
+                                &events,
+                                &drops,
+                            )
+                            .await;
+                        }
+                    }
+                    result = shutdown_rx.recv() => {
+                        match result {
+                            Ok(()) => break,
+                            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                        }
+                    }
+                }
+            }
+        });
+
+        // Spawn message expiry task
+        let sessions = self.sessions.clone();
+        let mut shutdown_rx = self.shutdown.subscribe();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(EXPIRY_TIMER_RESOLUTION);
+            loop {
+                tokio::select! {
+                    biased;
+
+                    _ = ticker.tick() => {
+                        sessions.expire_messages();
+                    }
+                    result = shutdown_rx.recv() => {
+                        match result {
+                            Ok(()) => break,
+                            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                        }
+                    }
+                }
+            }
+        });
+
+        // Spawn queue spill task
+        if self.sessions.spill().is_some() {
+            let sessions = self.sessions.clone();
+            let mut shutdown_rx = self.shutdown.subscribe();
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(SPILL_INTERVAL);
+                loop {
+                    tokio::select! {
+                        biased;
+
+                        _ = ticker.tick() => {
+                            sessions.spill_queues().await;
+                        }
+                        result = shutdown_rx.recv() => {
+                            match result {
+                                Ok(()) => break,
+                                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                            }
+                        }
+                    }
+                }
+            });
+        }
+
+        // Spawn retained resync task, which rewrites the stored retained
+        // messages after persistence dropped updates to them
+        if let Some(persistence) = self.persistence.clone() {
+            let retained = self.retained.clone();
+            let mut shutdown_rx = self.shutdown.subscribe();
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(RETAINED_RESYNC_INTERVAL);
+                loop {
+                    tokio::select! {
+                        biased;
+
+                        _ = ticker.tick() => {
+                            if !persistence.take_retained_stale() {
+                                continue;
+                            }
+                            if let Err(e) = retained.resync_persistence(&persistence).await {
+                                warn!("Failed to rewrite stored retained messages: {}", e);
+                                persistence.mark_retained_stale();
+                            }
+                        }
+                        result = shutdown_rx.recv() => {
+                            match result {
+                                Ok(()) => break,
+                                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                            }
+                        }
+                    }
+                }
+            });
+        }
+
+        // Spawn buffer pool shrink task
+        {
+            let mut shutdown_rx = self.shutdown.subscribe();
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(buffer_pool::SHRINK_INTERVAL);
+                // Skip the first immediate tick
+                ticker.tick().await;
+                loop {
+                    tokio::select! {
+                        biased;
+
+                        _ = ticker.tick() => {
+                            let freed = buffer_pool::global_pool().shrink();
+                            if freed > 0 {
+                                debug!("Freed {} idle pooled buffers", freed);
+                            }
+                        }
+                        result = shutdown_rx.recv() => {
+                            match result {
+                                Ok(()) => break,
+                                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                            }
+                        }
+                    }
+                }
+            });
+        }
+
+        // Spawn delayed will task
+        let sessions = self.sessions.clone();
+        let subscriptions = self.subscriptions.clone();
+        let retained = self.retained.clone();
+        let connections = self.connections.clone();
+        let config = self.config.clone();
+        let events = self.events.clone();
+        let drops = self.drops.clone();
+        let persistence = self.persistence.clone();
+        let mut shutdown_rx = self.shutdown.subscribe();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(WILL_TIMER_RESOLUTION);
+            loop {
+                tokio::select! {
+                    biased;
+
+                    _ = ticker.tick() => {
+                        for due in sessions.take_due_wills() {
+                            publish_due_will(
+                                due,
+                                &config,
+                                &retained,
+                                persistence.as_ref(),
+                                &subscriptions,
+                                &connections,
+                                &sessions,
+                                &events,
+                                &drops,
+                            )
+                            .await;
+                        }
                     }
                     result = shutdown_rx.recv() => {
                         match result {
@@ -894,9 +1669,99 @@ impl Broker {
             });
         }
 
+        // Spawn slow consumer detection task if enabled
+        if self.slow_consumers.is_enabled() {
+            let slow_consumers = self.slow_consumers.clone();
+            let connections = self.connections.clone();
+            let metrics = self.metrics.clone();
+            let mut shutdown_rx = self.shutdown.subscribe();
+
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(SLOW_CONSUMER_CHECK_INTERVAL);
+                loop {
+                    tokio::select! {
+                        biased;
+
+                        _ = ticker.tick() => {
+                            let slow = slow_consumers.check(&connections);
+                            if let Some(ref metrics) = metrics {
+                                metrics.slow_consumers.set(slow as i64);
+                            }
+                        }
+                        result = shutdown_rx.recv() => {
+                            match result {
+                                Ok(()) => break,
+                                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                            }
+                        }
+                    }
+                }
+            });
+        }
+
+        // Spawn the task running rule actions; rules may be added later
+        if let Some(mut outputs) = self.rules.take_outputs() {
+            let rules = self.rules.clone();
+            let broker = self.clone_for_sys_topics();
+            let bridge_manager = self.bridge_manager.clone();
+            let mut shutdown_rx = self.shutdown.subscribe();
+
+            tokio::spawn(async move {
+                loop {
+                    tokio::select! {
+                        biased;
+
+                        Some(output) = outputs.recv() => {
+                            rules.run_actions(output, &broker, bridge_manager.as_deref());
+                        }
+                        result = shutdown_rx.recv() => {
+                            match result {
+                                Ok(()) => break,
+                                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                                Err(broadcast::error::RecvError::Closed) => break,
+                            }
+                        }
+                    }
+                }
+            });
+        }
+
+        // Spawn the task calling the message dropped hook if the hooks
+        // observe messages
+        if let Some(mut dropped_rx) = self.dropped_rx.lock().take() {
+            let hooks = self.hooks.clone();
+            let drops = self.drops.clone();
+            let mut shutdown_rx = self.shutdown.subscribe();
+
+            tokio::spawn(async move {
+                loop {
+                    tokio::select! {
+                        dropped = dropped_rx.recv() => {
+                            let Some(DroppedMessage { client_id, topic, reason }) = dropped else {
+                                break;
+                            };
+                            let missed = drops.take_missed();
+                            if missed > 0 {
+                                warn!("Message dropped hook fell behind, missed {} drops", missed);
+                            }
+                            hooks.on_message_dropped(&client_id, &topic, reason).await;
+                        }
+                        result = shutdown_rx.recv() => {
+                            match result {
+                                Ok(()) | Err(broadcast::error::RecvError::Closed) => break,
+                                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                            }
+                        }
+                    }
+                }
+            });
+        }
+
         // Spawn bridge forwarding task if bridges are configured
         if let Some(ref bridge_manager) = self.bridge_manager {
             let bridge_manager = bridge_manager.clone();
+            let metrics = self.metrics.clone();
             let mut events_rx = self.events.subscribe();
             let mut shutdown_rx = self.shutdown.subscribe();
 
@@ -909,17 +1774,26 @@ impl Broker {
                 // Start all bridges
                 bridge_manager.start_all().await;
 
+                let mut metrics_interval = tokio::time::interval(Duration::from_secs(1));
+                metrics_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
                 loop {
                     tokio::select! {
                         biased;
 
+                        _ = metrics_interval.tick() => {
+                            if let Some(ref metrics) = metrics {
+                                bridge_manager.record_metrics(metrics);
+                            }
+                        }
+
                         result = events_rx.recv() => {
                             match result {
-                                Ok(BrokerEvent::MessagePublished { topic, payload, qos, retain }) => {
+                                Ok(BrokerEvent::MessagePublished { topic, payload, qos, retain, origin, properties, .. }) => {
                                     // Forward to bridges
-                                    bridge_manager.forward_publish(&topic, payload, qos, retain).await;
+                                    bridge_manager.forward_publish_from(&topic, payload, qos, retain, origin.as_ref(), &properties);
                                 }
-                                Ok(_) => {} // Ignore other events
+                                Ok(event) => bridge_manager.export_event(&event),
                                 Err(broadcast::error::RecvError::Lagged(n)) => {
                                     debug!("Bridge event listener lagged, missed {} events", n);
                                 }
@@ -942,9 +1816,79 @@ impl Broker {
             });
         }
 
+        // Spawn journal task if the message journal is enabled
+        if let Some(ref journal) = self.journal {
+            let journal = journal.clone();
+            let mut events_rx = self.events.subscribe();
+            let mut shutdown_rx = self.shutdown.subscribe();
+
+            info!("Starting message journal in {:?}", journal.path());
+
+            tokio::spawn(async move {
+                // File I/O runs on the blocking pool, a batch of entries at a
+                // time; they become visible to readers on flush
+                let mut pending = Vec::new();
+                let mut unflushed = false;
+                let mut flush_interval = tokio::time::interval(Duration::from_secs(1));
+                let mut maintenance_interval = tokio::time::interval(Duration::from_secs(60));
+
+                loop {
+                    tokio::select! {
+                        biased;
+
+                        result = events_rx.recv() => {
+                            match result {
+                                Ok(BrokerEvent::MessagePublished { topic, payload, qos, retain, .. }) => {
+                                    if journal.matches(&topic) {
+                                        pending.push((topic, payload, qos, retain));
+                                        if pending.len() >= JOURNAL_BATCH_SIZE {
+                                            write_journal(&journal, std::mem::take(&mut pending), false).await;
+                                            unflushed = true;
+                                        }
+                                    }
+                                }
+                                Ok(_) => {} // Ignore other events
+                                Err(broadcast::error::RecvError::Lagged(n)) => {
+                                    warn!("Message journal lagged, {} events not journaled", n);
+                                }
+                                Err(broadcast::error::RecvError::Closed) => break,
+                            }
+                        }
+                        _ = flush_interval.tick() => {
+                            if unflushed || !pending.is_empty() {
+                                write_journal(&journal, std::mem::take(&mut pending), true).await;
+                                unflushed = false;
+                            }
+                        }
+                        _ = maintenance_interval.tick() => {
+                            let journal = journal.clone();
+                            match tokio::task::spawn_blocking(move || journal.maintain()).await {
+                                Ok(Ok(())) => {}
+                                Ok(Err(e)) => error!("Message journal maintenance failed: {}", e),
+                                Err(e) => error!("Message journal maintenance failed: {}", e),
+                            }
+                        }
+                        result = shutdown_rx.recv() => {
+                            match result {
+                                Ok(()) | Err(broadcast::error::RecvError::Closed) => break,
+                                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                            }
+                        }
+                    }
+                }
+
+                write_journal(&journal, pending, true).await;
+            });
+        }
+
         // Spawn cluster forwarding task if clustering is enabled
         if let Some(ref cluster_manager) = self.cluster_manager {
             let cluster_manager = cluster_manager.clone();
+            let sessions = self.sessions.clone();
+            let subscriptions = self.subscriptions.clone();
+            let connections = self.connections.clone();
+            let metrics = self.metrics.clone();
+            let queue_subscriptions = self.config.queue_subscriptions;
             let mut events_rx = self.events.subscribe();
             let mut shutdown_rx = self.shutdown.subscribe();
 
@@ -960,30 +1904,114 @@ impl Broker {
             }
 
             tokio::spawn(async move {
+                // Filters follow subscription events; the full set is only
+                // advertised at startup and after missed events
+                cluster_manager
+                    .sync_subscriptions(subscriptions.filters())
+                    .await;
+                let mut resync_interval = tokio::time::interval(Duration::from_secs(1));
+                resync_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+                // Persistent sessions keep their client registered until they expire
+                let mut prune_interval = tokio::time::interval(CLIENT_PRUNE_INTERVAL);
+                prune_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
                 loop {
                     tokio::select! {
                         biased;
 
+                        _ = resync_interval.tick() => {
+                            cluster_manager
+                                .sync_shared_groups(shared_group_states(&subscriptions, &sessions))
+                                .await;
+                            cluster_manager
+                                .publish_stats(connections.len(), subscriptions.subscription_count())
+                                .await;
+                            if let Some(ref metrics) = metrics {
+                                cluster_manager.record_metrics(metrics);
+                            }
+                        }
+                        _ = prune_interval.tick() => {
+                            cluster_manager
+                                .prune_clients(|client_id| {
+                                    connections.contains_key(client_id)
+                                        || sessions.get(client_id).is_some()
+                                })
+                                .await;
+                        }
                         result = events_rx.recv() => {
                             match result {
-                                Ok(BrokerEvent::MessagePublished { topic, payload, qos, retain }) => {
-                                    // Forward to cluster peers
+                                Ok(BrokerEvent::MessagePublished { cluster_forwarded: true, .. }) => {}
+                                Ok(BrokerEvent::MessagePublished { topic, payload, qos, retain, properties, .. }) => {
+                                    // Forward to cluster peers. It was delivered to every
+                                    // local shared group, so peers only serve the rest.
                                     debug!("Cluster: forwarding publish to topic '{}' (peers={})", topic, cluster_manager.peer_count());
-                                    cluster_manager.forward_publish(&topic, payload, qos, retain).await;
+                                    let mut route = cluster_manager.route_unserved_shared(&topic);
+                                    route.retain(|group| {
+                                        subscriptions.shared_strategy(group) != SharedDeliveryStrategy::LocalOnly
+                                    });
+                                    cluster_manager
+                                        .forward_routed(route, &topic, payload, qos, retain, &properties)
+                                        .await;
                                 }
                                 Ok(BrokerEvent::SubscriptionAdded { filter, client_id }) => {
                                     // Update cluster subscription state
                                     debug!("Cluster: subscription added '{}' by {}", filter, client_id);
-                                    cluster_manager.add_subscription(filter).await;
+                                    if parse_shared_subscription(&filter, queue_subscriptions).is_some() {
+                                        cluster_manager
+                                            .sync_shared_groups(shared_group_states(&subscriptions, &sessions))
+                                            .await;
+                                    } else {
+                                        cluster_manager.add_subscription(filter).await;
+                                    }
                                 }
                                 Ok(BrokerEvent::SubscriptionRemoved { filter, client_id }) => {
-                                    // Update cluster subscription state
+                                    // Withdraw the filter once no other client subscribes
                                     debug!("Cluster: subscription removed '{}' by {}", filter, client_id);
-                                    cluster_manager.remove_subscription(&filter).await;
+                                    if parse_shared_subscription(&filter, queue_subscriptions).is_some() {
+                                        cluster_manager
+                                            .sync_shared_groups(shared_group_states(&subscriptions, &sessions))
+                                            .await;
+                                    } else if !subscriptions.has_filter(&filter) {
+                                        cluster_manager.remove_subscription(&filter).await;
+                                    }
+                                }
+                                Ok(BrokerEvent::ClientConnected { client_id, .. }) => {
+                                    // Claim the client in the cluster-wide registry
+                                    cluster_manager.register_client(&client_id).await;
+                                    // Replicate its will for takeover should this node die
+                                    let will = sessions.get(&client_id).and_then(|session| {
+                                        let s = session.read();
+                                        s.will.as_ref().map(|will| {
+                                            ClusterWill::new(
+                                                will,
+                                                s.will_delay_interval,
+                                                s.session_expiry_interval,
+                                            )
+                                        })
+                                    });
+                                    if will.is_some() {
+                                        cluster_manager.register_will(&client_id, will).await;
+                                    }
+                                }
+                                Ok(BrokerEvent::ClientDisconnected {
+                                    reason: DisconnectReason::Takeover,
+                                    ..
+                                }) => {
+                                    // The client is connected again
+                                }
+                                Ok(BrokerEvent::ClientDisconnected { client_id, .. }) => {
+                                    // This node handles the will of a client that left
+                                    cluster_manager.register_will(&client_id, None).await;
+                                    // Persistent sessions stay owned by this node
+                                    if sessions.get(&client_id).is_none() {
+                                        cluster_manager.unregister_client(&client_id).await;
+                                    }
                                 }
                                 Ok(_) => {} // Ignore other events
                                 Err(broadcast::error::RecvError::Lagged(n)) => {
+                                    // Subscription changes may be among the missed events
                                     debug!("Cluster event listener lagged, missed {} events", n);
+                                    cluster_manager.sync_subscriptions(subscriptions.filters()).await;
                                 }
                                 Err(broadcast::error::RecvError::Closed) => break,
                             }
@@ -1007,34 +2035,37 @@ impl Broker {
         // Spawn metrics collection task if metrics are enabled
         if let Some(ref metrics) = self.metrics {
             let metrics = metrics.clone();
+            let senders = self.connections.clone();
             let mut events_rx = self.events.subscribe();
             let mut shutdown_rx = self.shutdown.subscribe();
 
             info!("Starting metrics collection");
 
             tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(OUTBOUND_DEPTH_SAMPLE_INTERVAL);
                 loop {
                     tokio::select! {
                         biased;
 
+                        _ = ticker.tick() => {
+                            senders.for_each(|_, sender| {
+                                let queued = sender.max_capacity() - sender.capacity();
+                                metrics.outbound_queue_depth.observe(queued as f64);
+                            });
+                        }
+
                         result = events_rx.recv() => {
                             match result {
-                                Ok(BrokerEvent::ClientConnected { protocol_version, .. }) => {
-                                    let protocol = match protocol_version {
-                                        ProtocolVersion::V311 => "v3.1.1",
-                                        ProtocolVersion::V5 => "v5.0",
-                                    };
-                                    metrics.client_connected(protocol);
-                                }
-                                Ok(BrokerEvent::ClientDisconnected { .. }) => {
-                                    // Note: We don't know the protocol here, so we just decrement total
-                                    // In a more complete impl, we'd track protocol per client
-                                    metrics.connections_current.dec();
-                                }
-                                Ok(BrokerEvent::MessagePublished { payload, .. }) => {
+                                // Connections update their gauges themselves
+                                Ok(
+                                    BrokerEvent::ClientConnected { .. }
+                                    | BrokerEvent::ClientDisconnected { .. },
+                                ) => {}
+                                Ok(BrokerEvent::MessagePublished { topic, payload, .. }) => {
                                     metrics.publish_received(payload.len());
+                                    metrics.topic_published(&topic, payload.len());
                                 }
-                                Ok(BrokerEvent::MessageDropped) => {
+                                Ok(BrokerEvent::MessageDropped { .. }) => {
                                     metrics.publish_dropped();
                                 }
                                 Ok(BrokerEvent::SubscriptionAdded { .. }) => {
@@ -1060,6 +2091,42 @@ impl Broker {
             });
         }
 
+        // Spawn resource sampling task if metrics or $SYS topics will report it
+        if self.metrics.is_some() || self.config.sys_topics_enabled {
+            let resources = self.resources.clone();
+            let metrics = self.metrics.clone();
+            let workers = self.worker_runtimes.clone();
+            let mut shutdown_rx = self.shutdown.subscribe();
+
+            tokio::spawn(async move {
+                // This runtime and the worker runtimes, sampled together
+                let mut runtimes = vec![tokio::runtime::Handle::current()];
+                if let Some(ref workers) = workers {
+                    runtimes.extend(workers.handles().cloned());
+                }
+                let mut ticker = tokio::time::interval(RESOURCE_SAMPLE_INTERVAL);
+                loop {
+                    tokio::select! {
+                        biased;
+
+                        _ = ticker.tick() => {
+                            let stats = resources.sample_runtimes(&runtimes);
+                            if let Some(ref metrics) = metrics {
+                                metrics.update_resources(&stats);
+                                metrics.update_buffer_pool(&buffer_pool::global_pool().stats());
+                            }
+                        }
+                        result = shutdown_rx.recv() => {
+                            match result {
+                                Ok(()) | Err(broadcast::error::RecvError::Closed) => break,
+                                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                            }
+                        }
+                    }
+                }
+            });
+        }
+
         // Spawn $SYS topics publishing task if enabled
         if self.config.sys_topics_enabled {
             let broker = Arc::new(self.clone_for_sys_topics());
@@ -1081,6 +2148,8 @@ impl Broker {
             );
         }
 
+        self.listeners.set_accepting(true);
+
         // Wait for Ctrl+C to trigger graceful shutdown
         tokio::signal::ctrl_c()
             .await
@@ -1103,6 +2172,12 @@ impl Broker {
         let persistence = self.persistence.clone();
         let shutdown = self.shutdown.clone();
         let flapping_detector = self.flapping_detector.clone();
+        let cluster_manager = self.cluster_manager.clone();
+        let redirect = self.redirect.clone();
+        let slow_consumers = self.slow_consumers.clone();
+        let drops = self.drops.clone();
+        let rules = self.rules.clone();
+        let workers = self.worker_runtimes.clone();
 
         tokio::spawn(async move {
             debug!("Starting TCP accept loop");
@@ -1148,7 +2223,9 @@ impl Broker {
                         }
 
                         spawn_connection_handler(
+                            workers.as_deref(),
                             stream,
+                            addr,
                             effective_addr,
                             proxy_info,
                             sessions.clone(),
@@ -1162,6 +2239,11 @@ impl Broker {
                             persistence.clone(),
                             shutdown.clone(),
                             flapping_detector.clone(),
+                            cluster_manager.clone(),
+                            redirect.clone(),
+                            slow_consumers.clone(),
+                            drops.clone(),
+                            rules.clone(),
                         );
                     }
                     Err(e) => {
@@ -1174,6 +2256,7 @@ impl Broker {
 
     /// Shutdown the broker
     pub fn shutdown(&self) {
+        self.listeners.set_accepting(false);
         let _ = self.shutdown.send(());
     }
 
@@ -1192,18 +2275,83 @@ impl Broker {
         self.connections.len()
     }
 
+    /// Messages dropped from a client's queue because it was full, or
+    /// `None` if the client has no session
+    pub fn dropped_messages(&self, client_id: &str) -> Option<u64> {
+        self.sessions
+            .get(client_id)
+            .map(|session| session.read().dropped_messages)
+    }
+
+    /// Get access to the session store, e.g. for inspecting sessions
+    pub fn sessions(&self) -> &Arc<SessionStore> {
+        &self.sessions
+    }
+
+    /// Get access to the subscription store, e.g. for filter statistics
+    pub fn subscriptions(&self) -> &Arc<SubscriptionStore> {
+        &self.subscriptions
+    }
+
     /// Get retained message count
     pub fn retained_count(&self) -> usize {
         self.retained.len()
     }
 
     /// Get access to retained messages for loading from persistence
-    pub fn retained(&self) -> &Arc<DashMap<String, RetainedMessage>> {
+    pub fn retained(&self) -> &Arc<RetainedStore> {
         &self.retained
     }
 
+    /// Remove all data belonging to a tenant
+    ///
+    /// Clears the tenant's retained messages from memory and deletes everything
+    /// persisted under the tenant. Returns the number of persisted entries removed.
+    pub async fn purge_tenant(&self, tenant: &str) -> Result<usize, PersistenceError> {
+        let retained = self.retained.remove_tenant(tenant);
+        debug!(
+            "Removed {} retained messages of tenant {}",
+            retained, tenant
+        );
+
+        match self.persistence {
+            Some(ref persistence) => persistence.delete_tenant(tenant).await,
+            None => Ok(0),
+        }
+    }
+
+    /// Redirect of new connections, changeable at runtime with
+    /// [`ServerRedirect::set`]
+    pub fn redirect(&self) -> &Arc<ServerRedirect> {
+        &self.redirect
+    }
+
+    /// Clients currently detected as slow consumers
+    pub fn slow_consumers(&self) -> &Arc<SlowConsumers> {
+        &self.slow_consumers
+    }
+
+    /// Redirect of connecting and connected clients, for the admin endpoint
+    pub fn redirect_control(&self) -> RedirectControl {
+        RedirectControl::new(self.redirect.clone(), self.connections.clone())
+    }
+
+    /// Disconnect a client, pointing it at another server; returns false if
+    /// the client is not connected to this broker
+    pub fn redirect_client(&self, client_id: &str, redirect: &RedirectConfig) -> bool {
+        self.redirect_control().redirect_client(client_id, redirect)
+    }
+
+    /// Disconnect every connected client, pointing it at another server;
+    /// returns the number of clients redirected
+    pub fn redirect_all(&self, redirect: &RedirectConfig) -> usize {
+        self.redirect_control().redirect_all(redirect)
+    }
+
     /// Publish a message from the server
     pub fn publish(&self, topic: String, payload: Bytes, qos: QoS, retain: bool) {
+        let topic = Topic::from(topic);
+
         // Create a publish packet
         let publish = Publish {
             dup: false,
@@ -1215,69 +2363,32 @@ impl Broker {
             properties: Properties::default(),
         };
 
-        // Handle retained message
+        // Handle retained message (still routed if the store is full)
         if retain {
-            if payload.is_empty() {
-                self.retained.remove(&topic);
-                if let Some(ref persistence) = self.persistence {
-                    persistence.write(PersistenceOp::DeleteRetained {
-                        topic: topic.clone(),
-                    });
-                }
-            } else {
-                let retained_msg = RetainedMessage {
-                    topic: topic.clone(),
+            self.retained.apply_publish(
+                RetainedPublish {
+                    topic: &topic,
                     payload,
                     qos,
                     properties: Properties::default(),
-                    timestamp: Instant::now(),
-                };
-                self.retained.insert(topic.clone(), retained_msg.clone());
-                if let Some(ref persistence) = self.persistence {
-                    persistence.write(PersistenceOp::SetRetained {
-                        topic: topic.clone(),
-                        message: StoredRetainedMessage::from(&retained_msg),
-                    });
-                }
-            }
+                    tenant: None,
+                },
+                self.persistence.as_ref(),
+            );
         }
 
-        // Route to subscribers
+        // Route to subscribers, deduplicated by client
         let matches = self.subscriptions.matches(&topic);
-
-        // Deduplicate by client_id (keep highest QoS) - use AHashMap for faster lookup
-        let mut client_qos: AHashMap<Arc<str>, QoS> = AHashMap::with_capacity(matches.len());
-        for sub in matches {
-            let entry = client_qos
-                .entry(sub.client_id.clone())
-                .or_insert(QoS::AtMostOnce);
-            if sub.qos > *entry {
-                *entry = sub.qos;
-            }
-        }
-
-        // Send to each client
-        for (client_id, sub_qos) in client_qos {
-            let effective_qos = qos.min(sub_qos);
-
-            if let Some(sender) = self.connections.get(&client_id) {
-                let mut publish = publish.clone();
-                publish.qos = effective_qos;
-
-                // For QoS > 0, packet_id will be assigned by the connection handler
-                let _ = sender.try_send(Packet::Publish(publish));
-            } else {
-                // Client disconnected, queue message if persistent session
-                if let Some(session) = self.sessions.get(client_id.as_ref()) {
-                    let mut s = session.write();
-                    if !s.clean_start {
-                        let mut publish = publish.clone();
-                        publish.qos = effective_qos;
-                        s.queue_message(publish);
-                    }
-                }
-            }
-        }
+        let client_matches = group_by_client(matches, None, |_| true);
+
+        // For QoS > 0, packet_id will be assigned by the connection handler
+        deliver_to_clients(
+            &self.connections,
+            &self.sessions,
+            &self.drops,
+            client_matches,
+            &publish,
+        );
     }
 }
 
@@ -1287,16 +2398,19 @@ impl Default for Broker {
     }
 }
 
-/// Spawn a connection handler task for a new TCP connection
+/// Spawn a connection handler task for a new TCP connection from
+/// `peer_addr`, on its worker runtime if there are any
 #[allow(clippy::too_many_arguments)]
 fn spawn_connection_handler(
+    workers: Option<&WorkerRuntimes>,
     stream: TcpStream,
+    peer_addr: SocketAddr,
     addr: SocketAddr,
     proxy_info: Option<ProxyInfo>,
     sessions: Arc<SessionStore>,
     subscriptions: Arc<SubscriptionStore>,
-    retained: Arc<DashMap<String, RetainedMessage>>,
-    connections: Arc<DashMap<Arc<str>, mpsc::Sender<Packet>>>,
+    retained: Arc<RetainedStore>,
+    connections: Arc<ConnectionMap>,
     config: BrokerConfig,
     events: broadcast::Sender<BrokerEvent>,
     hooks: Arc<dyn Hooks>,
@@ -1304,10 +2418,15 @@ fn spawn_connection_handler(
     persistence: Option<Arc<crate::persistence::PersistenceManager>>,
     shutdown: broadcast::Sender<()>,
     flapping_detector: Option<Arc<FlappingDetector>>,
+    cluster_manager: Option<Arc<ClusterManager>>,
+    redirect: Arc<ServerRedirect>,
+    slow_consumers: Arc<SlowConsumers>,
+    drops: Arc<DropReports>,
+    rules: Arc<RuleEngine>,
 ) {
     let mut shutdown_rx = shutdown.subscribe();
 
-    tokio::spawn(async move {
+    spawn_connection(workers, stream, peer_addr, move |stream| async move {
         let mut conn = Connection::new(
             stream,
             addr,
@@ -1321,7 +2440,12 @@ fn spawn_connection_handler(
             hooks,
             metrics,
             persistence,
-        );
+        )
+        .with_cluster(cluster_manager)
+        .with_redirect(redirect)
+        .with_slow_consumers(slow_consumers)
+        .with_drops(drops)
+        .with_rules(rules);
 
         // Pin the connection future so we can poll it repeatedly
         {
@@ -1368,6 +2492,31 @@ fn spawn_connection_handler(
     });
 }
 
+/// Append `entries` to the journal on the blocking pool, flushing it after
+/// them if `flush` is set
+async fn write_journal(
+    journal: &Arc<Journal>,
+    entries: Vec<(Topic, Bytes, QoS, bool)>,
+    flush: bool,
+) {
+    let journal = journal.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        for (topic, payload, qos, retain) in entries {
+            journal.append(&topic, &payload, qos, retain)?;
+        }
+        if flush {
+            journal.flush()?;
+        }
+        Ok::<_, std::io::Error>(())
+    })
+    .await;
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => error!("Failed to write message journal: {}", e),
+        Err(e) => error!("Failed to write message journal: {}", e),
+    }
+}
+
 /// Create a TCP listener with a large backlog for burst connection handling.
 ///
 /// Uses socket2 to configure the socket before calling listen() with a backlog
@@ -1394,3 +2543,34 @@ fn create_tcp_listener(addr: SocketAddr) -> Result<TcpListener, std::io::Error>
     // Convert to tokio TcpListener
     TcpListener::from_std(socket.into())
 }
+
+/// Local shared subscription groups as advertised to the cluster
+///
+/// The inflight count of a group is the number of unacknowledged outgoing
+/// messages across its members' sessions.
+fn shared_group_states(
+    subscriptions: &SubscriptionStore,
+    sessions: &SessionStore,
+) -> HashMap<String, SharedGroupState> {
+    subscriptions
+        .share_groups()
+        .into_iter()
+        .map(|(group, members)| {
+            let inflight: usize = members
+                .clients
+                .iter()
+                .filter_map(|client_id| sessions.get(client_id))
+                .map(|session| session.read().inflight_outgoing.len())
+                .sum();
+            let mut filters: Vec<String> = members.filters.into_iter().collect();
+            filters.sort();
+            (
+                group.to_string(),
+                SharedGroupState {
+                    filters,
+                    inflight: inflight as u32,
+                },
+            )
+        })
+        .collect()
+}
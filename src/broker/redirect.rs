@@ -0,0 +1,146 @@
+//! Server Redirect
+//!
+//! Steers clients to another server during migrations or maintenance. MQTT
+//! v5 clients are told with Use Another Server or Server Moved and a Server
+//! Reference [MQTT-4.11]: connecting clients in CONNACK, connected ones in
+//! DISCONNECT. MQTT v3.1.1 has no redirect, so those clients are refused as
+//! Server Unavailable when connecting and closed when connected.
+
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use super::ConnectionMap;
+use crate::config::RedirectConfig;
+use crate::protocol::{ConnAck, Disconnect, Packet, Properties, ReasonCode};
+
+/// Redirect applied to connecting clients, changeable at runtime
+#[derive(Debug, Default)]
+pub struct ServerRedirect {
+    current: RwLock<Option<RedirectConfig>>,
+}
+
+impl ServerRedirect {
+    pub fn new(redirect: Option<RedirectConfig>) -> Self {
+        Self {
+            current: RwLock::new(redirect),
+        }
+    }
+
+    /// Redirect of new connections, if any
+    pub fn get(&self) -> Option<RedirectConfig> {
+        self.current.read().clone()
+    }
+
+    /// Start redirecting new connections, or stop with `None`
+    pub fn set(&self, redirect: Option<RedirectConfig>) {
+        *self.current.write() = redirect;
+    }
+}
+
+/// Redirect of connecting and connected clients, as driven by the admin
+/// endpoint
+#[derive(Clone)]
+pub struct RedirectControl {
+    redirect: Arc<ServerRedirect>,
+    connections: Arc<ConnectionMap>,
+}
+
+impl RedirectControl {
+    pub(crate) fn new(redirect: Arc<ServerRedirect>, connections: Arc<ConnectionMap>) -> Self {
+        Self {
+            redirect,
+            connections,
+        }
+    }
+
+    /// Redirect of new connections
+    pub fn connecting(&self) -> &ServerRedirect {
+        &self.redirect
+    }
+
+    /// Disconnect a client, pointing it at another server; returns false if
+    /// the client is not connected to this broker
+    pub fn redirect_client(&self, client_id: &str, redirect: &RedirectConfig) -> bool {
+        match self.connections.get(client_id) {
+            Some(sender) => sender
+                .try_send(Packet::Disconnect(disconnect(redirect)))
+                .is_ok(),
+            None => false,
+        }
+    }
+
+    /// Disconnect every connected client, pointing it at another server;
+    /// returns the number of clients redirected
+    pub fn redirect_all(&self, redirect: &RedirectConfig) -> usize {
+        let mut redirected = 0;
+        self.connections.for_each(|_, sender| {
+            if sender
+                .try_send(Packet::Disconnect(disconnect(redirect)))
+                .is_ok()
+            {
+                redirected += 1;
+            }
+        });
+        redirected
+    }
+}
+
+fn reason_code(redirect: &RedirectConfig) -> ReasonCode {
+    if redirect.permanent {
+        ReasonCode::ServerMoved
+    } else {
+        ReasonCode::UseAnotherServer
+    }
+}
+
+fn properties(redirect: &RedirectConfig) -> Properties {
+    Properties {
+        server_reference: Some(redirect.server_reference.clone()),
+        ..Default::default()
+    }
+}
+
+/// CONNACK refusing a client and pointing it at the other server
+pub(crate) fn connack(redirect: &RedirectConfig) -> ConnAck {
+    ConnAck {
+        session_present: false,
+        reason_code: reason_code(redirect),
+        properties: properties(redirect),
+    }
+}
+
+/// DISCONNECT pointing a connected client at the other server
+pub(crate) fn disconnect(redirect: &RedirectConfig) -> Disconnect {
+    Disconnect {
+        reason_code: reason_code(redirect),
+        properties: properties(redirect),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redirect_reason_and_reference() {
+        let mut redirect = RedirectConfig {
+            server_reference: "mqtt2.example.com:1883".to_string(),
+            permanent: false,
+        };
+        let connack = connack(&redirect);
+        assert_eq!(connack.reason_code, ReasonCode::UseAnotherServer);
+        assert_eq!(
+            connack.properties.server_reference.as_deref(),
+            Some("mqtt2.example.com:1883")
+        );
+
+        redirect.permanent = true;
+        assert_eq!(disconnect(&redirect).reason_code, ReasonCode::ServerMoved);
+
+        let state = ServerRedirect::default();
+        assert!(state.get().is_none());
+        state.set(Some(redirect.clone()));
+        assert_eq!(state.get(), Some(redirect));
+    }
+}
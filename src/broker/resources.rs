@@ -0,0 +1,143 @@
+//! Process Resource Monitoring
+//!
+//! Samples the broker process's resident memory and open file descriptors,
+//! read from /proc on Linux, and the load of the tokio runtimes: the share
+//! of time their workers were busy since the previous sample, the alive
+//! tasks and the global queue depths, summed over the accept runtime and
+//! any worker runtimes. The depth of the blocking pools' queues is only
+//! known in builds with `--cfg tokio_unstable`.
+
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use tokio::runtime::Handle;
+
+/// How often resources are sampled
+pub const RESOURCE_SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Resource usage of the broker process
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ResourceStats {
+    /// Resident set size, where the platform reports it
+    pub rss_bytes: Option<u64>,
+    /// Open file descriptors, sockets included, where the platform reports them
+    pub open_fds: Option<u64>,
+    pub workers: usize,
+    pub alive_tasks: usize,
+    /// Tasks waiting in the runtime's global queue
+    pub global_queue_depth: usize,
+    /// Share of time the workers were busy since the previous sample, 0 to 1
+    pub worker_utilization: f64,
+    /// Tasks waiting for a blocking pool thread
+    pub blocking_queue_depth: Option<usize>,
+}
+
+/// Samples of the process resources
+#[derive(Default)]
+pub struct ResourceMonitor {
+    /// Total busy time of the workers at the previous sample, and when it
+    /// was taken
+    previous: Mutex<Option<(Duration, Instant)>>,
+    latest: Mutex<ResourceStats>,
+}
+
+impl ResourceMonitor {
+    /// The most recent sample
+    pub fn latest(&self) -> ResourceStats {
+        self.latest.lock().clone()
+    }
+
+    /// Take a sample of the current runtime; must be called on the broker's
+    /// runtime
+    pub fn sample(&self) -> ResourceStats {
+        self.sample_runtimes(&[Handle::current()])
+    }
+
+    /// Take a sample of `runtimes` together
+    pub fn sample_runtimes(&self, runtimes: &[Handle]) -> ResourceStats {
+        let metrics: Vec<_> = runtimes.iter().map(Handle::metrics).collect();
+        let workers: usize = metrics.iter().map(|runtime| runtime.num_workers()).sum();
+        let busy: Duration = metrics
+            .iter()
+            .flat_map(|runtime| {
+                (0..runtime.num_workers()).map(|worker| runtime.worker_total_busy_duration(worker))
+            })
+            .sum();
+        let now = Instant::now();
+        let worker_utilization = match self.previous.lock().replace((busy, now)) {
+            Some((previous_busy, previous_at)) => {
+                let capacity = now.duration_since(previous_at).as_secs_f64() * workers as f64;
+                if capacity > 0.0 {
+                    (busy.saturating_sub(previous_busy).as_secs_f64() / capacity).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                }
+            }
+            None => 0.0,
+        };
+
+        #[cfg(tokio_unstable)]
+        let blocking_queue_depth = Some(
+            metrics
+                .iter()
+                .map(|runtime| runtime.blocking_queue_depth())
+                .sum::<usize>(),
+        );
+        #[cfg(not(tokio_unstable))]
+        let blocking_queue_depth = None;
+
+        let stats = ResourceStats {
+            rss_bytes: rss_bytes(),
+            open_fds: open_fds(),
+            workers,
+            alive_tasks: metrics
+                .iter()
+                .map(|runtime| runtime.num_alive_tasks())
+                .sum(),
+            global_queue_depth: metrics
+                .iter()
+                .map(|runtime| runtime.global_queue_depth())
+                .sum(),
+            worker_utilization,
+            blocking_queue_depth,
+        };
+        *self.latest.lock() = stats.clone();
+        stats
+    }
+}
+
+/// Resident set size from /proc/self/status
+fn rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Entries of /proc/self/fd, less the one listing them
+fn open_fds() -> Option<u64> {
+    let entries = std::fs::read_dir("/proc/self/fd").ok()?.count() as u64;
+    Some(entries.saturating_sub(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_sample() {
+        let monitor = ResourceMonitor::default();
+        let first = monitor.sample();
+        assert_eq!(first.workers, 2);
+        assert_eq!(first.worker_utilization, 0.0);
+
+        let second = monitor.sample();
+        assert!((0.0..=1.0).contains(&second.worker_utilization));
+        assert_eq!(monitor.latest().workers, 2);
+        if cfg!(target_os = "linux") {
+            assert!(second.rss_bytes.unwrap() > 0);
+            assert!(second.open_fds.unwrap() > 0);
+        }
+    }
+}
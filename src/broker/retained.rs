@@ -0,0 +1,687 @@
+//! Retained Message Store
+//!
+//! Holds the last retained message for each topic. The store can be bounded
+//! by message count and total size; when a new retained message would exceed
+//! a limit, the configured `RetainedPolicy` either rejects it or evicts the
+//! oldest retained messages to make room.
+//!
+//! Messages are indexed in topic tries, so a new subscription finds the
+//! retained messages matching its filter without scanning every topic. The
+//! tries are sharded by the first level of their topics, so a filter such as
+//! `sensors/+/temp` is looked up in a single trie; only filters starting
+//! with a wildcard visit every shard. Tries are copy-on-write: a subscription
+//! walking a filter, or a [`RetainedSnapshot`] copying the store out, clones
+//! a shard's trie in O(1) under its read lock and walks the clone, so
+//! publishers retaining to that shard are not held up by the walk.
+//!
+//! Topics are [`Topic`]s, shared between a message, the messages
+//! replacing it, the eviction order and the events publishing them.
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ahash::RandomState;
+use bytes::Bytes;
+use parking_lot::{Mutex, RwLock};
+use tracing::debug;
+
+use super::RetainedMessage;
+use crate::config::RetainedPolicy;
+use crate::persistence::{
+    split_tenant_key, tenant_key, PersistenceError, PersistenceManager, PersistenceOp,
+    StoredRetainedMessage,
+};
+use crate::protocol::{Properties, QoS};
+use crate::topic::{Topic, TopicTrie};
+
+/// Result of storing a retained message
+#[derive(Debug)]
+pub enum RetainOutcome {
+    /// Stored; `evicted` holds the messages removed to make room
+    Stored { evicted: Vec<RetainedMessage> },
+    /// Rejected because the store is full (policy = reject) or the message
+    /// alone exceeds the byte limit
+    Rejected,
+}
+
+/// A retained PUBLISH to apply to the store
+pub(crate) struct RetainedPublish<'a> {
+    pub topic: &'a Topic,
+    pub payload: Bytes,
+    pub qos: QoS,
+    pub properties: Properties,
+    /// Tenant of the publisher
+    pub tenant: Option<Arc<str>>,
+}
+
+/// How often persistence is checked for dropped retained updates
+pub(crate) const RETAINED_RESYNC_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Shards per CPU, so concurrent publishers rarely share one
+const SHARDS_PER_CPU: usize = 4;
+
+/// A shard, aligned to keep neighbouring shard locks off its cache line
+#[repr(align(128))]
+struct Shard(RwLock<TopicTrie<RetainedMessage>>);
+
+/// Thread-safe retained message store with optional limits
+pub struct RetainedStore {
+    shards: Box<[Shard]>,
+    /// `shards.len() - 1`; the shard count is a power of two
+    mask: usize,
+    hasher: RandomState,
+    /// Number of stored messages
+    count: AtomicUsize,
+    /// Total size of stored messages (topic + payload bytes)
+    bytes: AtomicUsize,
+    /// Maximum number of messages (0 = unlimited)
+    max_messages: usize,
+    /// Maximum total size in bytes (0 = unlimited)
+    max_bytes: usize,
+    policy: RetainedPolicy,
+    /// Insertion order for eviction (only tracked when bounded).
+    /// Entries whose timestamp no longer matches the stored message are stale
+    /// and skipped. Holding this lock also serializes bounded inserts.
+    order: Mutex<VecDeque<(Topic, Instant)>>,
+}
+
+/// The retained messages of a store, copied out one shard at a time
+///
+/// Each shard's trie is cloned as the iteration reaches it and read outside
+/// the lock, so writers are never held up by the iteration. Changes to
+/// shards already reached are not seen.
+pub struct RetainedSnapshot<'a> {
+    store: &'a RetainedStore,
+    /// Next shard to copy
+    shard: usize,
+    batch: std::vec::IntoIter<RetainedMessage>,
+}
+
+impl Iterator for RetainedSnapshot<'_> {
+    type Item = RetainedMessage;
+
+    fn next(&mut self) -> Option<RetainedMessage> {
+        loop {
+            if let Some(message) = self.batch.next() {
+                return Some(message);
+            }
+            let shard = self.store.shards.get(self.shard)?;
+            self.shard += 1;
+            let trie = shard.0.read().clone();
+            let mut batch = Vec::new();
+            trie.for_each(|m| batch.push(m.clone()));
+            self.batch = batch.into_iter();
+        }
+    }
+}
+
+/// First level of a topic or filter, which picks its shard
+#[inline]
+fn first_level(topic: &str) -> &str {
+    topic.split('/').next().unwrap_or(topic)
+}
+
+/// Size accounted for a retained message
+#[inline]
+fn message_size(msg: &RetainedMessage) -> usize {
+    msg.topic.len() + msg.payload.len()
+}
+
+impl RetainedStore {
+    /// Create an unbounded store
+    pub fn new() -> Self {
+        Self::with_limits(0, 0, RetainedPolicy::default())
+    }
+
+    /// Create a store bounded by message count and total bytes (0 = unlimited)
+    pub fn with_limits(max_messages: usize, max_bytes: usize, policy: RetainedPolicy) -> Self {
+        let shards = (crate::broker::num_cpus::get() * SHARDS_PER_CPU).next_power_of_two();
+        Self {
+            shards: (0..shards)
+                .map(|_| Shard(RwLock::new(TopicTrie::new())))
+                .collect(),
+            mask: shards - 1,
+            hasher: RandomState::new(),
+            count: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
+            max_messages,
+            max_bytes,
+            policy,
+            order: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// The shard holding the topics whose first level is `level`
+    fn shard_of_level(&self, level: &str) -> &RwLock<TopicTrie<RetainedMessage>> {
+        &self.shards[self.hasher.hash_one(level) as usize & self.mask].0
+    }
+
+    /// The shard holding `topic`
+    fn shard(&self, topic: &str) -> &RwLock<TopicTrie<RetainedMessage>> {
+        self.shard_of_level(first_level(topic))
+    }
+
+    fn is_bounded(&self) -> bool {
+        self.max_messages > 0 || self.max_bytes > 0
+    }
+
+    /// Number of retained messages
+    pub fn len(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Check if there are no retained messages
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Total size of retained messages in bytes (topic + payload)
+    pub fn total_bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Get the retained message for a topic
+    pub fn get(&self, topic: &str) -> Option<RetainedMessage> {
+        self.shard(topic).read().get(topic).cloned()
+    }
+
+    /// Get the retained messages whose topics match a subscription filter
+    pub fn matching(&self, filter: &str) -> Vec<RetainedMessage> {
+        // A filter without wildcards names a single topic
+        if !filter.contains(['+', '#']) {
+            return self.get(filter).into_iter().collect();
+        }
+        let mut found = Vec::new();
+        let mut search = |shard: &RwLock<TopicTrie<RetainedMessage>>| {
+            let trie = shard.read().clone();
+            trie.matches_filter(filter, |m| found.push(m.clone()));
+        };
+        match first_level(filter) {
+            "+" | "#" => self.shards.iter().for_each(|shard| search(&shard.0)),
+            level => search(self.shard_of_level(level)),
+        }
+        found
+    }
+
+    /// Copy the retained messages out, one shard at a time
+    pub fn snapshot(&self) -> RetainedSnapshot<'_> {
+        RetainedSnapshot {
+            store: self,
+            shard: 0,
+            batch: Vec::new().into_iter(),
+        }
+    }
+
+    /// Check whether the retained message for a topic was stored at `timestamp`
+    fn is_current(&self, topic: &str, timestamp: Instant) -> bool {
+        self.shard(topic)
+            .read()
+            .get(topic)
+            .is_some_and(|m| m.timestamp == timestamp)
+    }
+
+    /// Store a retained message, replacing any existing one for its topic
+    pub fn insert(&self, mut msg: RetainedMessage) -> RetainOutcome {
+        if !self.is_bounded() {
+            self.put(msg);
+            return RetainOutcome::Stored {
+                evicted: Vec::new(),
+            };
+        }
+
+        let size = message_size(&msg);
+        if self.max_bytes > 0 && size > self.max_bytes {
+            return RetainOutcome::Rejected;
+        }
+
+        let mut order = self.order.lock();
+        let existing = self.get(&msg.topic);
+        let replaced = existing.as_ref().map(message_size);
+        if let Some(existing) = existing {
+            msg.topic = existing.topic;
+        }
+
+        let mut evicted = Vec::new();
+        loop {
+            let count = self.len() + usize::from(replaced.is_none());
+            let bytes = self.total_bytes() - replaced.unwrap_or(0) + size;
+            let over_count = self.max_messages > 0 && count > self.max_messages;
+            let over_bytes = self.max_bytes > 0 && bytes > self.max_bytes;
+            if !over_count && !over_bytes {
+                break;
+            }
+
+            if self.policy == RetainedPolicy::Reject {
+                return RetainOutcome::Rejected;
+            }
+
+            // Evict the oldest message other than the one being replaced
+            let Some((topic, timestamp)) = order.pop_front() else {
+                return RetainOutcome::Rejected;
+            };
+            if topic == msg.topic {
+                continue;
+            }
+            if self.is_current(&topic, timestamp) {
+                evicted.extend(self.take(&topic));
+            }
+        }
+
+        order.push_back((msg.topic.clone(), msg.timestamp));
+        self.put(msg);
+
+        // Drop stale order entries once they dominate the queue
+        if order.len() > self.len() * 2 + 64 {
+            order.retain(|(topic, timestamp)| self.is_current(topic, *timestamp));
+        }
+
+        RetainOutcome::Stored { evicted }
+    }
+
+    /// Remove the retained message for a topic
+    pub fn remove(&self, topic: &str) -> Option<RetainedMessage> {
+        // Stale order entries are skipped during eviction, no need to touch them here
+        self.take(topic)
+    }
+
+    /// Remove all retained messages published by a tenant, returning how many were removed
+    pub fn remove_tenant(&self, tenant: &str) -> usize {
+        let topics: Vec<Topic> = self
+            .snapshot()
+            .filter(|m| m.tenant.as_deref() == Some(tenant))
+            .map(|m| m.topic)
+            .collect();
+        topics
+            .iter()
+            .filter(|topic| self.take(topic).is_some())
+            .count()
+    }
+
+    /// Rewrite the stored retained messages from the store, after
+    /// persistence dropped updates to them
+    ///
+    /// The store is read through a [`RetainedSnapshot`], so publishers keep
+    /// retaining meanwhile. A message that changes while it is rewritten may
+    /// be stored out of date, so persistence is marked stale for another pass.
+    pub(crate) async fn resync_persistence(
+        &self,
+        persistence: &PersistenceManager,
+    ) -> Result<(), PersistenceError> {
+        let mut removed: HashSet<String> = persistence.retained_keys().await?.into_iter().collect();
+        let mut rewritten = 0;
+        for message in self.snapshot() {
+            let key = tenant_key(message.tenant.as_deref(), &message.topic);
+            removed.remove(&key);
+            let op = PersistenceOp::SetRetained {
+                topic: key,
+                message: StoredRetainedMessage::from(&message),
+            };
+            persistence.write_async(op).await;
+            if !self.is_current(&message.topic, message.timestamp) {
+                persistence.mark_retained_stale();
+            }
+            rewritten += 1;
+        }
+
+        // Stored messages the store no longer holds
+        let is_stored = |key: &str| {
+            let (tenant, topic) = split_tenant_key(key);
+            self.get(topic)
+                .is_some_and(|m| m.tenant.as_deref() == tenant)
+        };
+        let removed: Vec<String> = removed.into_iter().filter(|key| !is_stored(key)).collect();
+        for key in &removed {
+            let op = PersistenceOp::DeleteRetained { topic: key.clone() };
+            persistence.write_async(op).await;
+            if is_stored(key) {
+                persistence.mark_retained_stale();
+            }
+        }
+
+        debug!(
+            "Rewrote {} stored retained messages and deleted {}",
+            rewritten,
+            removed.len()
+        );
+        Ok(())
+    }
+
+    /// Apply a retained PUBLISH: an empty payload clears the topic, anything
+    /// else is stored. Changes, including evictions, are mirrored to persistence
+    /// under the owning tenant's key.
+    ///
+    /// Returns false if the message was rejected by the store limits.
+    pub(crate) fn apply_publish(
+        &self,
+        publish: RetainedPublish<'_>,
+        persistence: Option<&Arc<PersistenceManager>>,
+    ) -> bool {
+        let Some(ops) = self.update(publish, persistence.is_some()) else {
+            return false;
+        };
+        if let Some(persistence) = persistence {
+            for op in ops {
+                persistence.write(op);
+            }
+        }
+        true
+    }
+
+    /// Like `apply_publish`, but waits for persistence queue capacity
+    /// according to the configured overflow policy
+    pub(crate) async fn apply_publish_async(
+        &self,
+        publish: RetainedPublish<'_>,
+        persistence: Option<&Arc<PersistenceManager>>,
+    ) -> bool {
+        let Some(ops) = self.update(publish, persistence.is_some()) else {
+            return false;
+        };
+        if let Some(persistence) = persistence {
+            for op in ops {
+                persistence.write_async(op).await;
+            }
+        }
+        true
+    }
+
+    /// Like `apply_publish`, but returns the persistence operations for the
+    /// caller to commit, or `None` if the message was rejected
+    pub(crate) fn apply_publish_deferred(
+        &self,
+        publish: RetainedPublish<'_>,
+    ) -> Option<Vec<PersistenceOp>> {
+        self.update(publish, true)
+    }
+
+    /// Update the store for a retained PUBLISH
+    ///
+    /// Returns the persistence operations mirroring the change (empty unless
+    /// `persist` is set), or `None` if the message was rejected.
+    fn update(&self, publish: RetainedPublish<'_>, persist: bool) -> Option<Vec<PersistenceOp>> {
+        let RetainedPublish {
+            topic,
+            payload,
+            qos,
+            properties,
+            tenant,
+        } = publish;
+        let mut ops = Vec::new();
+
+        if payload.is_empty() {
+            let removed = self.remove(topic);
+            if persist {
+                // The stored copy lives under the tenant that published it
+                let owner = match &removed {
+                    Some(m) => m.tenant.as_deref(),
+                    None => tenant.as_deref(),
+                };
+                ops.push(PersistenceOp::DeleteRetained {
+                    topic: tenant_key(owner, topic),
+                });
+            }
+            return Some(ops);
+        }
+
+        let previous_owner = if persist {
+            self.shard(topic)
+                .read()
+                .get(topic)
+                .map(|m| m.tenant.clone())
+        } else {
+            None
+        };
+
+        let msg = RetainedMessage {
+            topic: topic.clone(),
+            payload,
+            qos,
+            properties,
+            timestamp: Instant::now(),
+            tenant,
+        };
+        let stored = persist.then(|| StoredRetainedMessage::from(&msg));
+        let key = tenant_key(msg.tenant.as_deref(), topic);
+        let owner = msg.tenant.clone();
+
+        match self.insert(msg) {
+            RetainOutcome::Stored { evicted } => {
+                for m in evicted {
+                    debug!("Evicted retained message for '{}'", m.topic);
+                    if persist {
+                        ops.push(PersistenceOp::DeleteRetained {
+                            topic: tenant_key(m.tenant.as_deref(), &m.topic),
+                        });
+                    }
+                }
+                // A different tenant owned the replaced message
+                if let Some(previous) = previous_owner.filter(|p| *p != owner) {
+                    ops.push(PersistenceOp::DeleteRetained {
+                        topic: tenant_key(previous.as_deref(), topic),
+                    });
+                }
+                if let Some(message) = stored {
+                    ops.push(PersistenceOp::SetRetained {
+                        topic: key,
+                        message,
+                    });
+                }
+                Some(ops)
+            }
+            RetainOutcome::Rejected => {
+                debug!("Retained message for '{}' rejected: store full", topic);
+                None
+            }
+        }
+    }
+
+    fn put(&self, mut msg: RetainedMessage) {
+        let size = message_size(&msg);
+        let mut messages = self.shard(&msg.topic).write();
+        self.bytes.fetch_add(size, Ordering::Relaxed);
+        if let Some(existing) = messages.get_mut(&msg.topic) {
+            // Keep the topic shared with the eviction order
+            msg.topic = existing.topic.clone();
+            let old = std::mem::replace(existing, msg);
+            self.bytes.fetch_sub(message_size(&old), Ordering::Relaxed);
+        } else {
+            let topic = msg.topic.clone();
+            messages.insert(&topic, msg);
+            self.count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn take(&self, topic: &str) -> Option<RetainedMessage> {
+        let old = self.shard(topic).write().remove(topic)?;
+        self.count.fetch_sub(1, Ordering::Relaxed);
+        self.bytes.fetch_sub(message_size(&old), Ordering::Relaxed);
+        Some(old)
+    }
+}
+
+impl Default for RetainedStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SyncMode;
+    use crate::persistence::FjallBackend;
+
+    fn msg(topic: &str, payload: &'static [u8]) -> RetainedMessage {
+        RetainedMessage {
+            topic: Topic::from(topic),
+            payload: Bytes::from_static(payload),
+            qos: QoS::AtMostOnce,
+            properties: Properties::default(),
+            timestamp: Instant::now(),
+            tenant: None,
+        }
+    }
+
+    #[test]
+    fn test_unbounded_tracks_bytes() {
+        let store = RetainedStore::new();
+        store.insert(msg("a", b"123"));
+        store.insert(msg("a", b"12345"));
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.total_bytes(), 6);
+
+        store.remove("a");
+        assert!(store.is_empty());
+        assert_eq!(store.total_bytes(), 0);
+    }
+
+    #[test]
+    fn test_reject_policy() {
+        let store = RetainedStore::with_limits(2, 0, RetainedPolicy::Reject);
+        assert!(matches!(
+            store.insert(msg("a", b"1")),
+            RetainOutcome::Stored { .. }
+        ));
+        assert!(matches!(
+            store.insert(msg("b", b"1")),
+            RetainOutcome::Stored { .. }
+        ));
+        assert!(matches!(
+            store.insert(msg("c", b"1")),
+            RetainOutcome::Rejected
+        ));
+
+        // Replacing an existing topic does not grow the store
+        assert!(matches!(
+            store.insert(msg("a", b"2")),
+            RetainOutcome::Stored { .. }
+        ));
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn test_evict_oldest_policy() {
+        let store = RetainedStore::with_limits(2, 0, RetainedPolicy::EvictOldest);
+        store.insert(msg("a", b"1"));
+        store.insert(msg("b", b"1"));
+        // Re-publishing "a" makes "b" the oldest
+        store.insert(msg("a", b"2"));
+
+        let RetainOutcome::Stored { evicted } = store.insert(msg("c", b"1")) else {
+            panic!("expected the message to be stored");
+        };
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(&*evicted[0].topic, "b");
+        assert!(store.get("a").is_some());
+        assert!(store.get("b").is_none());
+        assert!(store.get("c").is_some());
+    }
+
+    #[test]
+    fn test_matching_filter() {
+        let store = RetainedStore::new();
+        store.insert(msg("sensors/1/temp", b"1"));
+        store.insert(msg("sensors/2/temp", b"2"));
+        store.insert(msg("sensors/2/hum", b"3"));
+        store.insert(msg("$SYS/uptime", b"4"));
+
+        let topics = |filter: &str| {
+            let mut topics: Vec<String> = store
+                .matching(filter)
+                .into_iter()
+                .map(|m| m.topic.to_string())
+                .collect();
+            topics.sort();
+            topics
+        };
+        assert_eq!(
+            topics("sensors/+/temp"),
+            ["sensors/1/temp", "sensors/2/temp"]
+        );
+        assert_eq!(topics("sensors/#").len(), 3);
+        assert_eq!(topics("#").len(), 3);
+        assert_eq!(topics("sensors/2/hum"), ["sensors/2/hum"]);
+
+        store.remove("sensors/2/hum");
+        assert_eq!(topics("sensors/2/#"), ["sensors/2/temp"]);
+        assert_eq!(store.len(), 3);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let store = RetainedStore::new();
+        for i in 0..1000 {
+            store.insert(msg(&format!("sensors/{}", i), b"1"));
+        }
+
+        let mut snapshot = store.snapshot();
+        let first = snapshot.next().unwrap();
+        // Writers are not blocked by the iteration
+        store.remove(&first.topic);
+        store.insert(msg("sensors/new", b"1"));
+        let rest = snapshot.count();
+        assert!((999..=1000).contains(&rest));
+        assert_eq!(store.len(), 1000);
+
+        let mut tenant = msg("tenant/a", b"1");
+        tenant.tenant = Some(Arc::from("acme"));
+        store.insert(tenant);
+        assert_eq!(store.remove_tenant("acme"), 1);
+        assert_eq!(store.snapshot().count(), 1000);
+    }
+
+    #[tokio::test]
+    async fn test_resync_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = Arc::new(FjallBackend::open(dir.path()).unwrap());
+        let persistence =
+            PersistenceManager::new(backend, Duration::from_secs(3600), 100, SyncMode::Never);
+
+        // Stored, but its delete was dropped
+        let gone = StoredRetainedMessage::from(&msg("gone", b"1"));
+        persistence
+            .write_sync(vec![PersistenceOp::SetRetained {
+                topic: "gone".to_string(),
+                message: gone,
+            }])
+            .await
+            .unwrap();
+
+        // Retained, but their sets were dropped
+        let store = RetainedStore::new();
+        store.insert(msg("kept", b"1"));
+        let mut owned = msg("owned", b"2");
+        owned.tenant = Some(Arc::from("acme"));
+        store.insert(owned);
+
+        store.resync_persistence(&persistence).await.unwrap();
+        let mut keys = persistence.retained_keys().await.unwrap();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [tenant_key(Some("acme"), "owned"), "kept".to_string()]
+        );
+        assert!(!persistence.take_retained_stale());
+    }
+
+    #[test]
+    fn test_byte_limit() {
+        let store = RetainedStore::with_limits(0, 10, RetainedPolicy::EvictOldest);
+        store.insert(msg("a", b"1234"));
+        store.insert(msg("b", b"1234"));
+        assert_eq!(store.total_bytes(), 10);
+
+        // Needs room for 5 bytes, evicting "a"
+        store.insert(msg("c", b"1234"));
+        assert!(store.get("a").is_none());
+        assert_eq!(store.total_bytes(), 10);
+
+        // Larger than the whole store
+        assert!(matches!(
+            store.insert(msg("d", b"1234567890")),
+            RetainOutcome::Rejected
+        ));
+    }
+}
@@ -4,20 +4,178 @@
 
 use std::sync::Arc;
 
-use dashmap::DashMap;
+use ahash::AHashMap;
+use smallvec::SmallVec;
 use tokio::sync::mpsc;
 
-use crate::protocol::Packet;
+use super::{report_dropped, ConnectionMap, DropReason, DropReports};
+use crate::codec::RawPublish;
+use crate::protocol::{Packet, Publish, QoS, SharedPublish};
+use crate::session::SessionStore;
+use crate::topic::Subscription;
+
+/// Clients sent a fanned out message between yields to other tasks
+pub(crate) const FANOUT_BATCH_SIZE: usize = 256;
+
+/// The matching subscriptions of one client, merged
+pub(crate) struct ClientMatch {
+    /// Highest QoS of the subscriptions
+    pub(crate) qos: QoS,
+    /// Any of the subscriptions has Retain As Published set
+    pub(crate) retain_as_published: bool,
+    /// Identifiers of all subscriptions, each once
+    pub(crate) subscription_ids: SmallVec<[u32; 4]>,
+}
+
+impl ClientMatch {
+    /// Copy of `publish` for this client, with the effective QoS and the
+    /// subscription identifiers [MQTT-3.3.4-3]. The RETAIN flag is kept only
+    /// for Retain As Published subscriptions [MQTT-3.3.1-12] [MQTT-3.3.1-13]
+    pub(crate) fn outgoing(&self, publish: &Publish) -> Publish {
+        let mut outgoing = publish.clone();
+        outgoing.qos = publish.qos.min(self.qos);
+        outgoing.dup = false;
+        // The connection assigns a fresh packet id
+        outgoing.packet_id = None;
+        if !self.retain_as_published {
+            outgoing.retain = false;
+        }
+        outgoing
+            .properties
+            .subscription_identifiers
+            .extend(self.subscription_ids.iter().copied());
+        outgoing
+    }
+}
+
+/// QoS and RETAIN flag a shared copy of a message is sent with
+type Variant = (QoS, bool);
+
+/// Outgoing packets of a message sent to many clients
+///
+/// Clients whose subscriptions merge to the same QoS and RETAIN flag, and
+/// that have no Subscription Identifiers, share one copy of the message and
+/// its encodings rather than each getting a copy of their own.
+///
+/// Copies with the QoS the message was received with start from the wire
+/// bytes it was received with, if given, for their protocol version.
+pub(crate) struct FanOut<'a> {
+    publish: &'a Publish,
+    raw: Option<&'a RawPublish>,
+    shared: SmallVec<[(Variant, Arc<SharedPublish>); 4]>,
+}
+
+impl<'a> FanOut<'a> {
+    pub(crate) fn new(publish: &'a Publish, raw: Option<&'a RawPublish>) -> Self {
+        Self {
+            publish,
+            raw,
+            shared: SmallVec::new(),
+        }
+    }
+
+    /// Packet for a connected client
+    pub(crate) fn packet(&mut self, client_match: &ClientMatch) -> Packet {
+        if !client_match.subscription_ids.is_empty() {
+            return Packet::Publish(client_match.outgoing(self.publish));
+        }
+        let variant: Variant = (
+            self.publish.qos.min(client_match.qos),
+            self.publish.retain && client_match.retain_as_published,
+        );
+        if let Some((_, shared)) = self.shared.iter().find(|(v, _)| *v == variant) {
+            return Packet::SharedPublish(shared.clone());
+        }
+        let shared = Arc::new(SharedPublish::with_raw(
+            client_match.outgoing(self.publish),
+            self.raw,
+        ));
+        self.shared.push((variant, shared.clone()));
+        Packet::SharedPublish(shared)
+    }
+}
+
+/// Merge matching subscriptions by client, skipping those `include` rejects
+/// and the No Local subscriptions of the publishing client [MQTT-3.8.3-3]
+pub(crate) fn group_by_client(
+    matches: impl IntoIterator<Item = Subscription>,
+    sender: Option<&str>,
+    mut include: impl FnMut(&Subscription) -> bool,
+) -> AHashMap<Arc<str>, ClientMatch> {
+    let matches = matches.into_iter();
+    let mut clients: AHashMap<Arc<str>, ClientMatch> =
+        AHashMap::with_capacity(matches.size_hint().0);
+    for sub in matches {
+        if sub.no_local && sender == Some(sub.client_id.as_ref()) {
+            continue;
+        }
+        if !include(&sub) {
+            continue;
+        }
+        let entry = clients.entry(sub.client_id.clone()).or_insert(ClientMatch {
+            qos: QoS::AtMostOnce,
+            retain_as_published: false,
+            subscription_ids: SmallVec::new(),
+        });
+        if sub.qos > entry.qos {
+            entry.qos = sub.qos;
+        }
+        entry.retain_as_published |= sub.retain_as_published;
+        if let Some(id) = sub.subscription_id {
+            if !entry.subscription_ids.contains(&id) {
+                entry.subscription_ids.push(id);
+            }
+        }
+    }
+    clients
+}
+
+/// Deliver a message to the clients it matched: on the outbound channel of
+/// connected clients, else to the queue of their persistent session
+///
+/// Messages that find a full channel or are lost to queue overflow are
+/// reported as dropped. Sessions without a connection never disconnect on
+/// overflow; a connection applies that policy to the messages it queues.
+pub(crate) fn deliver_to_clients(
+    connections: &ConnectionMap,
+    sessions: &SessionStore,
+    drops: &DropReports,
+    client_matches: AHashMap<Arc<str>, ClientMatch>,
+    publish: &Publish,
+) {
+    for (client_id, client_match) in client_matches {
+        let outgoing = client_match.outgoing(publish);
+
+        if let Some(sender) = connections.get(&client_id) {
+            if let Err(mpsc::error::TrySendError::Full(_)) =
+                sender.try_send(Packet::Publish(outgoing))
+            {
+                tracing::warn!(client_id = %client_id, "channel full - dropping message");
+                report_dropped(drops, &client_id, &publish.topic, DropReason::ChannelFull);
+            }
+        } else if let Some(session) = sessions.get(client_id.as_ref()) {
+            // Client disconnected, queue message if persistent session
+            let mut s = session.write();
+            if !s.clean_start {
+                if let Some(topic) = s.queue_message(outgoing).dropped_topic() {
+                    report_dropped(drops, &client_id, topic, DropReason::QueueFull);
+                }
+            }
+        }
+    }
+}
 
 /// Message router for distributing messages to subscribers
 pub struct MessageRouter {
     /// Client send channels
-    clients: Arc<DashMap<Arc<str>, mpsc::Sender<Packet>>>,
+    clients: Arc<ConnectionMap>,
+    /// Where messages for full channels are reported
+    drops: Arc<DropReports>,
 }
 
 impl MessageRouter {
-    pub fn new(clients: Arc<DashMap<Arc<str>, mpsc::Sender<Packet>>>) -> Self {
-        Self { clients }
+    pub fn new(clients: Arc<ConnectionMap>, drops: Arc<DropReports>) -> Self {
+        Self { clients, drops }
     }
 
     /// Send a message to a specific client
@@ -34,8 +192,16 @@ impl MessageRouter {
         if let Some(sender) = self.clients.get(client_id) {
             match sender.try_send(packet) {
                 Ok(()) => true,
-                Err(mpsc::error::TrySendError::Full(_)) => {
+                Err(mpsc::error::TrySendError::Full(packet)) => {
                     tracing::warn!(client_id = %client_id, "channel full - backpressure");
+                    if let Packet::Publish(publish) = packet {
+                        report_dropped(
+                            &self.drops,
+                            client_id,
+                            &publish.topic,
+                            DropReason::ChannelFull,
+                        );
+                    }
                     false
                 }
                 Err(mpsc::error::TrySendError::Closed(_)) => false,
@@ -45,3 +211,122 @@ impl MessageRouter {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::protocol::Properties;
+
+    fn sub(client_id: &str, qos: QoS, subscription_id: Option<u32>) -> Subscription {
+        Subscription {
+            client_id: client_id.into(),
+            qos,
+            no_local: false,
+            retain_as_published: false,
+            subscription_id,
+            share_group: None,
+        }
+    }
+
+    #[test]
+    fn test_group_by_client_collects_subscription_ids() {
+        let matches = vec![
+            sub("a", QoS::AtMostOnce, Some(1)),
+            sub("a", QoS::ExactlyOnce, Some(2)),
+            sub("a", QoS::AtLeastOnce, Some(1)),
+            sub("b", QoS::AtLeastOnce, None),
+            sub("c", QoS::AtLeastOnce, Some(3)),
+        ];
+        let clients = group_by_client(matches, None, |s| s.client_id.as_ref() != "c");
+        assert_eq!(clients.len(), 2);
+
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            topic: "t".into(),
+            packet_id: None,
+            payload: Bytes::from_static(b"x"),
+            properties: Properties::default(),
+        };
+        let a = clients["a"].outgoing(&publish);
+        assert_eq!(a.qos, QoS::AtLeastOnce);
+        assert_eq!(a.properties.subscription_identifiers, vec![1, 2]);
+        let b = clients["b"].outgoing(&publish);
+        assert!(b.properties.subscription_identifiers.is_empty());
+    }
+
+    #[test]
+    fn test_fan_out_shares_variants() {
+        let matches = vec![
+            sub("a", QoS::AtLeastOnce, None),
+            sub("b", QoS::ExactlyOnce, None),
+            sub("c", QoS::AtMostOnce, None),
+            sub("d", QoS::AtLeastOnce, Some(4)),
+        ];
+        let clients = group_by_client(matches, None, |_| true);
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            topic: "t".into(),
+            packet_id: None,
+            payload: Bytes::from_static(b"x"),
+            properties: Properties::default(),
+        };
+
+        let mut fanout = FanOut::new(&publish, None);
+        let shared = |packet: Packet| match packet {
+            Packet::SharedPublish(shared) => shared,
+            other => panic!("expected a shared publish, got {:?}", other),
+        };
+        let a = shared(fanout.packet(&clients["a"]));
+        // QoS 2 subscriptions get QoS 1 messages like QoS 1 ones
+        let b = shared(fanout.packet(&clients["b"]));
+        assert!(Arc::ptr_eq(&a, &b));
+        let c = shared(fanout.packet(&clients["c"]));
+        assert_eq!(c.publish().qos, QoS::AtMostOnce);
+        assert!(!Arc::ptr_eq(&a, &c));
+        // Subscription Identifiers make a copy of its own
+        match fanout.packet(&clients["d"]) {
+            Packet::Publish(d) => assert_eq!(d.properties.subscription_identifiers, vec![4]),
+            other => panic!("expected a publish, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_no_local_and_retain_as_published() {
+        let mut own = sub("a", QoS::AtLeastOnce, None);
+        own.no_local = true;
+        let mut rap = sub("b", QoS::AtLeastOnce, None);
+        rap.retain_as_published = true;
+        let matches = vec![
+            own,
+            rap,
+            sub("b", QoS::AtMostOnce, None),
+            sub("c", QoS::AtMostOnce, None),
+        ];
+
+        let clients = group_by_client(matches.clone(), Some("a"), |_| true);
+        assert!(!clients.contains_key("a"));
+        // Published by another client, the No Local subscription matches
+        assert!(group_by_client(matches, Some("b"), |_| true).contains_key("a"));
+
+        let publish = Publish {
+            dup: true,
+            qos: QoS::AtLeastOnce,
+            retain: true,
+            topic: "t".into(),
+            packet_id: Some(7),
+            payload: Bytes::from_static(b"x"),
+            properties: Properties::default(),
+        };
+        let b = clients["b"].outgoing(&publish);
+        assert!(b.retain);
+        assert!(!b.dup);
+        assert_eq!(b.packet_id, None);
+        assert!(!clients["c"].outgoing(&publish).retain);
+    }
+}
@@ -0,0 +1,284 @@
+//! Slow Consumer Detection
+//!
+//! A client that reads slower than messages arrive for it fills its
+//! outbound channel. Once the channel has held at least the threshold of
+//! messages for longer than the grace period, the client is a slow consumer
+//! until the channel drains below the threshold again, and the configured
+//! policy applies:
+//! - `log` only reports it
+//! - `drop_qos0` stops routing QoS 0 messages to it
+//! - `disconnect` closes its connection, even while a write to it is stuck
+//!
+//! Clients are told apart by their outbound channel, so a client that
+//! reconnects starts over rather than inheriting the verdict on its old
+//! connection.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use tokio::sync::{mpsc, watch};
+use tracing::{info, warn};
+
+use super::ConnectionMap;
+use crate::config::{SlowConsumerConfig, SlowConsumerPolicy};
+use crate::protocol::{Packet, QoS};
+
+/// How often the outbound channels are checked
+pub const SLOW_CONSUMER_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A client whose outbound channel is over the threshold
+struct Tracked {
+    sender: mpsc::Sender<Packet>,
+    /// When the channel went over the threshold
+    since: Instant,
+    /// Over the threshold for longer than the grace period
+    slow: bool,
+}
+
+/// Slow consumers among the connected clients
+pub struct SlowConsumers {
+    config: SlowConsumerConfig,
+    tracked: DashMap<Arc<str>, Tracked>,
+    /// Number of tracked clients that are slow consumers
+    slow: AtomicUsize,
+    /// Signalled when clients become slow consumers under the disconnect
+    /// policy
+    flagged_tx: watch::Sender<()>,
+}
+
+impl Default for SlowConsumers {
+    fn default() -> Self {
+        Self::new(SlowConsumerConfig::default())
+    }
+}
+
+impl SlowConsumers {
+    pub fn new(config: SlowConsumerConfig) -> Self {
+        let (flagged_tx, _) = watch::channel(());
+        Self {
+            config,
+            tracked: DashMap::new(),
+            slow: AtomicUsize::new(0),
+            flagged_tx,
+        }
+    }
+
+    /// Whether detection is enabled
+    pub fn is_enabled(&self) -> bool {
+        self.config.threshold > 0
+    }
+
+    /// Number of slow consumers
+    pub fn count(&self) -> usize {
+        self.slow.load(Ordering::Relaxed)
+    }
+
+    /// Client IDs of the slow consumers
+    pub fn clients(&self) -> Vec<Arc<str>> {
+        self.tracked
+            .iter()
+            .filter(|tracked| tracked.slow)
+            .map(|tracked| tracked.key().clone())
+            .collect()
+    }
+
+    /// Whether the client on the other end of `sender` is a slow consumer
+    pub fn is_slow(&self, client_id: &str, sender: &mpsc::Sender<Packet>) -> bool {
+        self.count() > 0
+            && self
+                .tracked
+                .get(client_id)
+                .is_some_and(|tracked| tracked.slow && tracked.sender.same_channel(sender))
+    }
+
+    /// Whether a message of `qos` is dropped rather than sent through
+    /// `sender`, under the drop_qos0 policy
+    pub(crate) fn drops(&self, client_id: &str, sender: &mpsc::Sender<Packet>, qos: QoS) -> bool {
+        self.config.policy == SlowConsumerPolicy::DropQos0
+            && qos == QoS::AtMostOnce
+            && self.is_slow(client_id, sender)
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<()> {
+        self.flagged_tx.subscribe()
+    }
+
+    /// Resolve once the client on the other end of `sender` is to be
+    /// disconnected as a slow consumer; never resolves under other policies
+    pub(crate) async fn disconnect_due(
+        &self,
+        client_id: &str,
+        sender: &mpsc::Sender<Packet>,
+        flagged_rx: &mut watch::Receiver<()>,
+    ) {
+        if self.config.policy != SlowConsumerPolicy::Disconnect {
+            return std::future::pending().await;
+        }
+        loop {
+            if self.is_slow(client_id, sender) {
+                return;
+            }
+            if flagged_rx.changed().await.is_err() {
+                return std::future::pending().await;
+            }
+        }
+    }
+
+    /// Check the outbound channels of the connected clients; returns the
+    /// number of slow consumers
+    pub fn check(&self, connections: &ConnectionMap) -> usize {
+        let now = Instant::now();
+        let mut flagged = false;
+
+        connections.for_each(|client_id, sender| {
+            let queued = sender.max_capacity() - sender.capacity();
+            if queued < self.config.threshold {
+                if let Some((_, tracked)) = self.tracked.remove(client_id) {
+                    if tracked.slow && tracked.sender.same_channel(sender) {
+                        info!(client_id = %client_id, "no longer a slow consumer");
+                    }
+                }
+                return;
+            }
+
+            let mut tracked = self
+                .tracked
+                .entry(client_id.clone())
+                .or_insert_with(|| Tracked {
+                    sender: sender.clone(),
+                    since: now,
+                    slow: false,
+                });
+            if !tracked.sender.same_channel(sender) {
+                // The client reconnected
+                *tracked = Tracked {
+                    sender: sender.clone(),
+                    since: now,
+                    slow: false,
+                };
+            }
+            if !tracked.slow && now.duration_since(tracked.since) >= self.config.grace_period {
+                tracked.slow = true;
+                flagged = true;
+                warn!(
+                    client_id = %client_id,
+                    queued,
+                    "slow consumer - outbound channel over {} messages for {:?} ({:?} policy)",
+                    self.config.threshold,
+                    self.config.grace_period,
+                    self.config.policy
+                );
+            }
+        });
+
+        // Forget the connections that closed
+        self.tracked.retain(|client_id, tracked| {
+            connections
+                .get(client_id)
+                .is_some_and(|sender| sender.same_channel(&tracked.sender))
+        });
+
+        let slow = self.tracked.iter().filter(|tracked| tracked.slow).count();
+        self.slow.store(slow, Ordering::Relaxed);
+        if flagged && self.config.policy == SlowConsumerPolicy::Disconnect {
+            self.flagged_tx.send_replace(());
+        }
+        slow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slow_consumers(policy: SlowConsumerPolicy) -> SlowConsumers {
+        SlowConsumers::new(SlowConsumerConfig {
+            threshold: 2,
+            grace_period: Duration::ZERO,
+            policy,
+        })
+    }
+
+    fn fill(sender: &mpsc::Sender<Packet>, n: usize) {
+        for _ in 0..n {
+            sender.try_send(Packet::PingResp).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_flag_and_recover() {
+        let slow = slow_consumers(SlowConsumerPolicy::Log);
+        let connections = ConnectionMap::with_shards(2);
+        let (fast_tx, _fast_rx) = mpsc::channel(4);
+        let (slow_tx, mut slow_rx) = mpsc::channel(4);
+        connections.insert(Arc::<str>::from("fast"), fast_tx.clone());
+        connections.insert(Arc::<str>::from("slow"), slow_tx.clone());
+
+        fill(&fast_tx, 1);
+        fill(&slow_tx, 3);
+        assert_eq!(slow.check(&connections), 1);
+        assert!(slow.is_slow("slow", &slow_tx));
+        assert!(!slow.is_slow("fast", &fast_tx));
+        assert_eq!(slow.clients(), vec![Arc::<str>::from("slow")]);
+
+        // Draining below the threshold clears the flag
+        slow_rx.try_recv().unwrap();
+        slow_rx.try_recv().unwrap();
+        assert_eq!(slow.check(&connections), 0);
+        assert!(!slow.is_slow("slow", &slow_tx));
+    }
+
+    #[test]
+    fn test_grace_period() {
+        let slow = SlowConsumers::new(SlowConsumerConfig {
+            threshold: 1,
+            grace_period: Duration::from_secs(60),
+            policy: SlowConsumerPolicy::Log,
+        });
+        let connections = ConnectionMap::with_shards(2);
+        let (tx, _rx) = mpsc::channel(4);
+        connections.insert(Arc::<str>::from("c"), tx.clone());
+
+        fill(&tx, 2);
+        assert_eq!(slow.check(&connections), 0);
+        assert!(!slow.is_slow("c", &tx));
+    }
+
+    #[test]
+    fn test_reconnect_starts_over() {
+        let slow = slow_consumers(SlowConsumerPolicy::DropQos0);
+        let connections = ConnectionMap::with_shards(2);
+        let (old_tx, _old_rx) = mpsc::channel(4);
+        connections.insert(Arc::<str>::from("c"), old_tx.clone());
+        fill(&old_tx, 3);
+        slow.check(&connections);
+        assert!(slow.drops("c", &old_tx, QoS::AtMostOnce));
+        assert!(!slow.drops("c", &old_tx, QoS::AtLeastOnce));
+
+        // The new connection is not held to the old one's verdict
+        let (new_tx, _new_rx) = mpsc::channel(4);
+        connections.insert(Arc::<str>::from("c"), new_tx.clone());
+        assert!(!slow.drops("c", &new_tx, QoS::AtMostOnce));
+        assert_eq!(slow.check(&connections), 0);
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_due() {
+        let slow = slow_consumers(SlowConsumerPolicy::Disconnect);
+        let connections = ConnectionMap::with_shards(2);
+        let (tx, _rx) = mpsc::channel(4);
+        connections.insert(Arc::<str>::from("c"), tx.clone());
+        let mut flagged_rx = slow.subscribe();
+
+        fill(&tx, 3);
+        slow.check(&connections);
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            slow.disconnect_due("c", &tx, &mut flagged_rx),
+        )
+        .await
+        .unwrap();
+    }
+}
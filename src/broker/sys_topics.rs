@@ -1,8 +1,11 @@
 //! $SYS Topics Publisher
 //!
 //! Publishes broker statistics as retained messages to standard $SYS/# topics.
-//! Topics are updated periodically based on configuration.
+//! Topics are updated periodically based on configuration. Per-session state
+//! is published under $SYS/sessions/<client>/ when enabled, and so are the
+//! sessions' traffic counters, at their own longer interval.
 
+use std::collections::HashSet;
 use std::sync::Arc;
 use std::time::Instant;
 
@@ -15,6 +18,9 @@ use crate::protocol::QoS;
 /// Version string for $SYS/broker/version
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Most subscribed filters listed in $SYS/broker/subscriptions/filters/top
+const TOP_FILTERS: usize = 10;
+
 /// Publish all $SYS topics as retained messages
 pub fn publish_sys_topics(broker: &Broker, metrics: Option<&Metrics>, start_time: Instant) {
     let uptime = start_time.elapsed().as_secs();
@@ -28,6 +34,75 @@ pub fn publish_sys_topics(broker: &Broker, metrics: Option<&Metrics>, start_time
     let messages_stored = broker.sessions.total_queued_messages();
     let shared_subs_count = broker.subscriptions.shared_subscription_count();
 
+    // Subscription trie stats (when enabled, as they walk the whole trie)
+    if broker.config.sys_filter_topics {
+        let mut filter_stats = broker.subscriptions.filter_stats();
+        publish(
+            broker,
+            "$SYS/broker/subscriptions/filters/count",
+            &filter_stats.filters.len().to_string(),
+        );
+        publish(
+            broker,
+            "$SYS/broker/subscriptions/trie_nodes",
+            &filter_stats.trie_nodes.to_string(),
+        );
+        filter_stats.filters.truncate(TOP_FILTERS);
+        if let Ok(json) = serde_json::to_string(&filter_stats.filters) {
+            publish(broker, "$SYS/broker/subscriptions/filters/top", &json);
+        }
+    }
+
+    // Slow consumers (when detection is enabled)
+    if broker.slow_consumers.is_enabled() {
+        publish(
+            broker,
+            "$SYS/broker/clients/slow",
+            &broker.slow_consumers.count().to_string(),
+        );
+        let clients = broker.slow_consumers.clients();
+        let clients: Vec<&str> = clients.iter().map(|client_id| client_id.as_ref()).collect();
+        if let Ok(json) = serde_json::to_string(&clients) {
+            publish(broker, "$SYS/broker/clients/slow/list", &json);
+        }
+    }
+
+    // Process and runtime resources (always available)
+    let resources = broker.resources.latest();
+    if let Some(rss) = resources.rss_bytes {
+        publish(broker, "$SYS/broker/process/rss_bytes", &rss.to_string());
+    }
+    if let Some(fds) = resources.open_fds {
+        publish(broker, "$SYS/broker/process/open_fds", &fds.to_string());
+    }
+    publish(
+        broker,
+        "$SYS/broker/runtime/workers",
+        &resources.workers.to_string(),
+    );
+    publish(
+        broker,
+        "$SYS/broker/runtime/alive_tasks",
+        &resources.alive_tasks.to_string(),
+    );
+    publish(
+        broker,
+        "$SYS/broker/runtime/global_queue_depth",
+        &resources.global_queue_depth.to_string(),
+    );
+    publish(
+        broker,
+        "$SYS/broker/runtime/worker_utilization",
+        &format!("{:.3}", resources.worker_utilization),
+    );
+    if let Some(depth) = resources.blocking_queue_depth {
+        publish(
+            broker,
+            "$SYS/broker/runtime/blocking_queue_depth",
+            &depth.to_string(),
+        );
+    }
+
     // Metrics-dependent stats
     if let Some(metrics) = metrics {
         // Client metrics - existing
@@ -156,6 +231,208 @@ pub fn publish_sys_topics(broker: &Broker, metrics: Option<&Metrics>, start_time
             &metrics.retained_bytes_current.get().to_string(),
         );
     }
+
+    // Cluster status
+    if let Some(ref cluster) = broker.cluster_manager {
+        let status = cluster.status();
+        publish(
+            broker,
+            "$SYS/broker/cluster/status",
+            if status.partitioned {
+                "partitioned"
+            } else {
+                "ok"
+            },
+        );
+        publish(broker, "$SYS/broker/cluster/node_id", cluster.node_id());
+        publish(
+            broker,
+            "$SYS/broker/cluster/nodes/reachable",
+            &status.reachable_nodes.to_string(),
+        );
+        publish(
+            broker,
+            "$SYS/broker/cluster/nodes/expected",
+            &status.expected_nodes.to_string(),
+        );
+        publish(
+            broker,
+            "$SYS/broker/cluster/partition_events",
+            &status.partition_events.to_string(),
+        );
+
+        // Cluster-wide view, from the stats each node gossips
+        let view = cluster.cluster_view();
+        publish(
+            broker,
+            "$SYS/broker/cluster/clients/connected",
+            &view.total_connections().to_string(),
+        );
+        for node in &view.nodes {
+            let prefix = format!("$SYS/broker/cluster/nodes/{}", node.node_id);
+            let health = if node.alive { "alive" } else { "dead" };
+            publish(broker, &format!("{}/status", prefix), health);
+            publish(
+                broker,
+                &format!("{}/clients/connected", prefix),
+                &node.stats.connections.to_string(),
+            );
+            publish(
+                broker,
+                &format!("{}/subscriptions/count", prefix),
+                &node.stats.subscriptions.to_string(),
+            );
+            publish(
+                broker,
+                &format!("{}/messages/sent", prefix),
+                &node.stats.messages_sent.to_string(),
+            );
+            publish(
+                broker,
+                &format!("{}/messages/received", prefix),
+                &node.stats.messages_received.to_string(),
+            );
+            publish(
+                broker,
+                &format!("{}/bytes/sent", prefix),
+                &node.stats.bytes_sent.to_string(),
+            );
+            publish(
+                broker,
+                &format!("{}/bytes/received", prefix),
+                &node.stats.bytes_received.to_string(),
+            );
+        }
+        if let Ok(json) = serde_json::to_string(&view.subscriptions) {
+            publish(broker, "$SYS/broker/cluster/subscriptions", &json);
+        }
+    }
+}
+
+/// Per-session topics under $SYS/sessions/<client>/
+const SESSION_TOPICS: &[&str] = &[
+    "connected",
+    "queued/messages",
+    "queued/spilled",
+    "queued/bytes",
+    "queued/dropped",
+    "inflight/awaiting_ack",
+    "inflight/awaiting_comp",
+    "inflight/awaiting_rel",
+    "inflight/retransmits",
+    "send_quota",
+    "expires_in",
+    "subscriptions",
+];
+
+/// Traffic counters of each session, also under $SYS/sessions/<client>/
+///
+/// The queue and inflight topics are shared with [`SESSION_TOPICS`], so the
+/// counters are complete without `sys_session_topics`; with it, they are
+/// left to the more frequent state updates.
+const COUNTER_TOPICS: &[&str] = &[
+    "messages/received",
+    "messages/sent",
+    "bytes/received",
+    "bytes/sent",
+    "queued/messages",
+    "queued/dropped",
+    "inflight/awaiting_ack",
+    "inflight/awaiting_comp",
+    "inflight/awaiting_rel",
+    "connected_at",
+];
+
+/// Publish the state of each session under $SYS/sessions/<client>/, with
+/// its traffic counters if `counters`, and clear the topics of sessions
+/// that are gone
+///
+/// The state is published if `sys_session_topics` is set; the counters go
+/// out if `sys_client_topics` is, less often. `published` tracks the
+/// clients whose topics are currently retained.
+pub fn publish_session_topics(broker: &Broker, published: &mut HashSet<String>, counters: bool) {
+    let state = broker.config.sys_session_topics;
+    let counters = counters && broker.config.sys_client_topics;
+    let mut current = HashSet::new();
+    for stats in broker.sessions.all_stats() {
+        // Wildcards cannot appear in a topic name
+        if stats.client_id.is_empty() || stats.client_id.contains(['+', '#']) {
+            continue;
+        }
+
+        let prefix = format!("$SYS/sessions/{}", stats.client_id);
+        if state {
+            let subscriptions = serde_json::to_string(&stats.subscriptions).unwrap_or_default();
+            let expires_in = stats
+                .expires_in_secs
+                .map(|secs| secs.to_string())
+                .unwrap_or_default();
+            let values = [
+                stats.connected.to_string(),
+                stats.queued_messages.to_string(),
+                stats.spilled_messages.to_string(),
+                stats.queued_bytes.to_string(),
+                stats.dropped_messages.to_string(),
+                stats.inflight_awaiting_ack.to_string(),
+                stats.inflight_awaiting_comp.to_string(),
+                stats.incoming_awaiting_rel.to_string(),
+                stats.retransmits.to_string(),
+                stats.send_quota.to_string(),
+                expires_in,
+                subscriptions,
+            ];
+            for (topic, value) in SESSION_TOPICS.iter().zip(values) {
+                // An empty value clears the retained topic
+                publish(broker, &format!("{}/{}", prefix, topic), &value);
+            }
+        }
+        if counters {
+            let values = [
+                stats.messages_received.to_string(),
+                stats.messages_sent.to_string(),
+                stats.bytes_received.to_string(),
+                stats.bytes_sent.to_string(),
+                stats.queued_messages.to_string(),
+                stats.dropped_messages.to_string(),
+                stats.inflight_awaiting_ack.to_string(),
+                stats.inflight_awaiting_comp.to_string(),
+                stats.incoming_awaiting_rel.to_string(),
+                stats
+                    .connected_at
+                    .map(|at| at.to_string())
+                    .unwrap_or_default(),
+            ];
+            for (topic, value) in COUNTER_TOPICS.iter().zip(values) {
+                if state && SESSION_TOPICS.contains(topic) {
+                    continue;
+                }
+                publish(broker, &format!("{}/{}", prefix, topic), &value);
+            }
+        }
+        current.insert(stats.client_id);
+    }
+
+    let mut enabled: Vec<&str> = Vec::new();
+    if broker.config.sys_session_topics {
+        enabled.extend(SESSION_TOPICS);
+    }
+    if broker.config.sys_client_topics {
+        enabled.extend(
+            COUNTER_TOPICS
+                .iter()
+                .filter(|t| !SESSION_TOPICS.contains(t)),
+        );
+    }
+    for client_id in published.difference(&current) {
+        for topic in &enabled {
+            publish(
+                broker,
+                &format!("$SYS/sessions/{}/{}", client_id, topic),
+                "",
+            );
+        }
+    }
+    *published = current;
 }
 
 /// Helper to publish a single $SYS topic as QoS 0 retained
@@ -178,6 +455,11 @@ pub fn spawn_sys_topics_task(
 ) {
     tokio::spawn(async move {
         let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+        let mut session_topics = HashSet::new();
+        // Session counters go out every this many ticks
+        let client_ticks =
+            (broker.config.sys_client_interval.as_secs() / interval_secs.max(1)).max(1);
+        let mut ticks: u64 = 0;
 
         // Publish immediately on startup
         publish_sys_topics(&broker, metrics.as_deref(), start_time);
@@ -186,6 +468,12 @@ pub fn spawn_sys_topics_task(
             tokio::select! {
                 _ = ticker.tick() => {
                     publish_sys_topics(&broker, metrics.as_deref(), start_time);
+                    let counters =
+                        broker.config.sys_client_topics && ticks.is_multiple_of(client_ticks);
+                    if broker.config.sys_session_topics || counters {
+                        publish_session_topics(&broker, &mut session_topics, counters);
+                    }
+                    ticks += 1;
                 }
                 _ = shutdown_rx.recv() => {
                     tracing::debug!("$SYS topics task shutting down");
@@ -48,7 +48,7 @@ impl From<std::io::Error> for TlsError {
 }
 
 /// Load certificates from a PEM file
-fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+pub(crate) fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, TlsError> {
     let file = File::open(path)?;
     let reader = BufReader::new(file);
     let certs: Vec<CertificateDer<'static>> = CertificateDer::pem_reader_iter(reader)
@@ -66,7 +66,7 @@ fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, TlsError> {
 }
 
 /// Load private key from a PEM file
-fn load_private_key(path: &str) -> Result<PrivateKeyDer<'static>, TlsError> {
+pub(crate) fn load_private_key(path: &str) -> Result<PrivateKeyDer<'static>, TlsError> {
     let file = File::open(path)?;
     let reader = BufReader::new(file);
 
@@ -75,7 +75,7 @@ fn load_private_key(path: &str) -> Result<PrivateKeyDer<'static>, TlsError> {
 }
 
 /// Load CA certificates into a root store
-fn load_ca_certs(path: &str) -> Result<RootCertStore, TlsError> {
+pub(crate) fn load_ca_certs(path: &str) -> Result<RootCertStore, TlsError> {
     let mut root_store = RootCertStore::empty();
     let certs = load_certs(path)?;
 
@@ -88,8 +88,19 @@ fn load_ca_certs(path: &str) -> Result<RootCertStore, TlsError> {
     Ok(root_store)
 }
 
+/// Make aws-lc-rs the process-wide rustls crypto provider, unless one is
+/// already installed
+///
+/// With the `kubernetes` feature the Kubernetes client also enables ring,
+/// and with two providers built in rustls no longer picks one on its own.
+pub fn install_crypto_provider() {
+    let _ = tokio_rustls::rustls::crypto::aws_lc_rs::default_provider().install_default();
+}
+
 /// Load TLS configuration and create a TlsAcceptor
 pub fn load_tls_config(config: &TlsConfig) -> Result<TlsAcceptor, TlsError> {
+    install_crypto_provider();
+
     // Load server certificate chain
     let certs = load_certs(&config.cert_path)?;
 
@@ -141,6 +152,13 @@ pub fn load_tls_config(config: &TlsConfig) -> Result<TlsAcceptor, TlsError> {
     Ok(TlsAcceptor::from(Arc::new(server_config)))
 }
 
+/// Common Name of the certificate a TLS client presented, if any
+pub(crate) fn peer_common_name(certs: Option<&[CertificateDer<'_>]>) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(certs?.first()?.as_ref()).ok()?;
+    let cn = cert.subject().iter_common_name().next()?.as_str().ok()?;
+    Some(cn.to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,217 @@
+//! Worker Runtimes
+//!
+//! Connections run on a set of single-threaded worker runtimes, one per
+//! worker thread and optionally pinned to a core of its own, rather than on
+//! the runtime accepting them. Accept loops and broker-wide tasks stay on
+//! the accept runtime; each accepted socket is handed off to the worker
+//! picked by the hash of its peer address, where it is registered with that
+//! worker's I/O driver. A connection's reads, writes, timers and the tasks
+//! it spawns then all stay on one thread and its caches, and one busy
+//! connection cannot steal time from connections on other workers.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::mpsc as std_mpsc;
+use std::thread::JoinHandle;
+
+use ahash::RandomState;
+use tokio::net::TcpStream;
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+use tracing::{debug, warn};
+
+/// A worker runtime, running on its own thread
+struct Worker {
+    handle: Handle,
+    /// Stops the worker's runtime when sent or dropped
+    stop: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Single-threaded runtimes connections are handed off to
+pub struct WorkerRuntimes {
+    workers: Box<[Worker]>,
+    hasher: RandomState,
+}
+
+impl WorkerRuntimes {
+    /// Start `workers` worker runtimes, pinning each to a core if `pin` is
+    /// set and the platform allows it
+    pub fn start(workers: usize, pin: bool) -> io::Result<Self> {
+        let cores = if pin {
+            core_affinity::get_core_ids().unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        if pin && cores.is_empty() {
+            warn!("Cannot pin worker threads to cores on this platform");
+        }
+
+        let workers = (0..workers.max(1))
+            .map(|index| {
+                let core = (!cores.is_empty()).then(|| cores[index % cores.len()]);
+                Worker::start(index, core)
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Self {
+            workers,
+            hasher: RandomState::new(),
+        })
+    }
+
+    /// Number of worker runtimes
+    pub fn len(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_empty()
+    }
+
+    /// Handles of the worker runtimes
+    pub fn handles(&self) -> impl Iterator<Item = &Handle> {
+        self.workers.iter().map(|worker| &worker.handle)
+    }
+
+    /// Index of the worker a connection from `addr` runs on
+    pub fn worker_for(&self, addr: &SocketAddr) -> usize {
+        self.hasher.hash_one(addr) as usize % self.workers.len()
+    }
+
+    /// Run `run` with `stream` on the worker of `addr`
+    pub fn hand_off<F, Fut>(&self, stream: TcpStream, addr: SocketAddr, run: F)
+    where
+        F: FnOnce(TcpStream) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        // Deregistered from the accept runtime, to register with the worker's
+        let stream = match stream.into_std() {
+            Ok(stream) => stream,
+            Err(e) => {
+                debug!("Failed to hand off connection from {}: {}", addr, e);
+                return;
+            }
+        };
+        let worker = &self.workers[self.worker_for(&addr)];
+        worker.handle.spawn(async move {
+            match TcpStream::from_std(stream) {
+                Ok(stream) => run(stream).await,
+                Err(e) => debug!("Failed to hand off connection from {}: {}", addr, e),
+            }
+        });
+    }
+}
+
+/// Run `run` with `stream` on the worker of `addr`, or as a task of the
+/// current runtime if there are no worker runtimes
+pub(crate) fn spawn_connection<F, Fut>(
+    workers: Option<&WorkerRuntimes>,
+    stream: TcpStream,
+    addr: SocketAddr,
+    run: F,
+) where
+    F: FnOnce(TcpStream) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    match workers {
+        Some(workers) => workers.hand_off(stream, addr, run),
+        None => {
+            tokio::spawn(run(stream));
+        }
+    }
+}
+
+impl Worker {
+    fn start(index: usize, core: Option<core_affinity::CoreId>) -> io::Result<Self> {
+        let (handle_tx, handle_rx) = std_mpsc::channel();
+        let (stop, stopped) = oneshot::channel::<()>();
+        // The runtime is built and dropped on its own thread, never in the
+        // async context starting the workers
+        let thread = std::thread::Builder::new()
+            .name(format!("vibemq-worker-{}", index))
+            .spawn(move || {
+                if let Some(core) = core {
+                    if !core_affinity::set_for_current(core) {
+                        warn!("Failed to pin worker {} to core {}", index, core.id);
+                    }
+                }
+                let runtime = match tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                {
+                    Ok(runtime) => runtime,
+                    Err(e) => {
+                        let _ = handle_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = handle_tx.send(Ok(runtime.handle().clone()));
+                runtime.block_on(async {
+                    let _ = stopped.await;
+                });
+            })?;
+        let handle = handle_rx
+            .recv()
+            .map_err(|_| io::Error::other("worker thread exited on start"))??;
+        Ok(Self {
+            handle,
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for WorkerRuntimes {
+    fn drop(&mut self) {
+        for worker in self.workers.iter_mut() {
+            if let Some(stop) = worker.stop.take() {
+                let _ = stop.send(());
+            }
+        }
+        for worker in self.workers.iter_mut() {
+            if let Some(thread) = worker.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_hand_off() {
+        let workers = WorkerRuntimes::start(2, false).unwrap();
+        assert_eq!(workers.len(), 2);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, addr) = listener.accept().await.unwrap();
+
+        // Echo on the worker, which runs on a thread of its own
+        let (done_tx, done_rx) = oneshot::channel();
+        workers.hand_off(stream, addr, move |mut stream| async move {
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+            let name = std::thread::current().name().map(str::to_string);
+            let _ = done_tx.send(name);
+        });
+
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        let thread = done_rx.await.unwrap().unwrap();
+        assert_eq!(
+            thread,
+            format!("vibemq-worker-{}", workers.worker_for(&addr))
+        );
+    }
+}
@@ -2,49 +2,153 @@
 //!
 //! Provides reusable BytesMut buffers to avoid repeated allocation/deallocation
 //! in hot paths like packet encoding.
+//!
+//! Buffers are pooled in size classes (4K, 16K and 64K), so a pool of small
+//! buffers neither wastes memory on large ones nor has large packets
+//! allocate afresh every time. Each worker thread gets pools of its own to
+//! avoid contention between threads, and buffers left unused between two
+//! calls of [`BufferPool::shrink`] are freed, so the pools shrink back once
+//! a burst is over.
 
 use bytes::BytesMut;
 use crossbeam_queue::ArrayQueue;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 /// Default buffer size for pooled buffers
 const DEFAULT_BUFFER_SIZE: usize = 4096;
 
-/// Maximum number of buffers to keep in the pool
-const MAX_POOLED_BUFFERS: usize = 256;
+/// Buffer sizes pooled, smallest first
+pub const SIZE_CLASSES: [usize; 3] = [DEFAULT_BUFFER_SIZE, 16384, 65536];
+
+/// Maximum number of buffers of each size class to keep per worker, so
+/// each class holds at most 256K per worker
+const MAX_POOLED_BUFFERS: [usize; 3] = [64, 16, 4];
 
 /// Maximum buffer size to return to pool (don't pool oversized buffers)
-const MAX_POOLED_BUFFER_SIZE: usize = 16384;
+const MAX_POOLED_BUFFER_SIZE: usize = 65536;
+
+/// How often idle buffers are freed
+pub const SHRINK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Pooled buffers of one size class for one worker
+struct ClassPool {
+    buffers: ArrayQueue<BytesMut>,
+    /// Fewest buffers pooled since the last shrink; that many sat idle
+    low_water: AtomicUsize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ClassPool {
+    fn new(capacity: usize) -> Self {
+        Self {
+            buffers: ArrayQueue::new(capacity),
+            low_water: AtomicUsize::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn pop(&self) -> Option<BytesMut> {
+        let buf = self.buffers.pop();
+        match buf {
+            Some(_) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                self.low_water
+                    .fetch_min(self.buffers.len(), Ordering::Relaxed);
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        buf
+    }
+
+    /// Free the buffers left idle since the last shrink
+    fn shrink(&self) -> usize {
+        let idle = self.low_water.load(Ordering::Relaxed);
+        let mut freed = 0;
+        while freed < idle && self.buffers.pop().is_some() {
+            freed += 1;
+        }
+        self.low_water.store(self.buffers.len(), Ordering::Relaxed);
+        freed
+    }
+}
+
+/// Pools of one worker, one per size class
+#[repr(align(128))]
+struct WorkerPools([ClassPool; 3]);
+
+/// Occupancy of one size class, across workers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SizeClassStats {
+    /// Buffer size of the class
+    pub size: usize,
+    /// Buffers pooled
+    pub buffers: usize,
+    /// Bytes held by the buffers pooled, counted at the class size
+    pub bytes: usize,
+    /// Buffers handed out from the pool
+    pub hits: u64,
+    /// Buffers allocated as the pool was empty
+    pub misses: u64,
+}
 
 /// A pool of reusable BytesMut buffers
 pub struct BufferPool {
-    pool: ArrayQueue<BytesMut>,
+    workers: Box<[WorkerPools]>,
     buffer_size: usize,
 }
 
 impl BufferPool {
     /// Create a new buffer pool with default settings
     pub fn new() -> Self {
-        Self {
-            pool: ArrayQueue::new(MAX_POOLED_BUFFERS),
-            buffer_size: DEFAULT_BUFFER_SIZE,
-        }
+        Self::with_workers(crate::broker::num_cpus::get())
     }
 
     /// Create a new buffer pool with custom buffer size
     pub fn with_buffer_size(buffer_size: usize) -> Self {
         Self {
-            pool: ArrayQueue::new(MAX_POOLED_BUFFERS),
             buffer_size,
+            ..Self::new()
+        }
+    }
+
+    /// Create a new buffer pool with pools for `workers` worker threads
+    pub fn with_workers(workers: usize) -> Self {
+        Self {
+            workers: (0..workers.max(1))
+                .map(|_| WorkerPools(MAX_POOLED_BUFFERS.map(ClassPool::new)))
+                .collect(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
         }
     }
 
+    /// The pools of the calling thread
+    fn local(&self) -> &WorkerPools {
+        &self.workers[worker_index() % self.workers.len()]
+    }
+
     /// Get a buffer from the pool, or allocate a new one if pool is empty
     #[inline]
     pub fn get(&self) -> BytesMut {
-        self.pool
+        self.get_with_capacity(self.buffer_size)
+    }
+
+    /// Get a buffer of at least `capacity` bytes from the pool of the
+    /// smallest size class that fits, or allocate a new one if that pool is
+    /// empty or no class fits
+    pub fn get_with_capacity(&self, capacity: usize) -> BytesMut {
+        let Some(class) = SIZE_CLASSES.iter().position(|&size| size >= capacity) else {
+            return BytesMut::with_capacity(capacity);
+        };
+        self.local().0[class]
             .pop()
-            .unwrap_or_else(|| BytesMut::with_capacity(self.buffer_size))
+            .unwrap_or_else(|| BytesMut::with_capacity(SIZE_CLASSES[class].max(capacity)))
     }
 
     /// Return a buffer to the pool for reuse
@@ -53,21 +157,61 @@ impl BufferPool {
     #[inline]
     pub fn put(&self, mut buf: BytesMut) {
         // Don't pool oversized buffers - let them be deallocated
-        if buf.capacity() <= MAX_POOLED_BUFFER_SIZE {
-            buf.clear();
-            // If pool is full, buffer is simply dropped
-            let _ = self.pool.push(buf);
+        if buf.capacity() > MAX_POOLED_BUFFER_SIZE {
+            return;
+        }
+        // The largest class the buffer can serve; undersized ones are dropped
+        let Some(class) = SIZE_CLASSES
+            .iter()
+            .rposition(|&size| size <= buf.capacity())
+        else {
+            return;
+        };
+        buf.clear();
+        // If pool is full, buffer is simply dropped
+        let _ = self.local().0[class].buffers.push(buf);
+    }
+
+    /// Free the buffers no worker used since the previous call, returning
+    /// how many were freed
+    pub fn shrink(&self) -> usize {
+        self.workers
+            .iter()
+            .flat_map(|worker| worker.0.iter())
+            .map(ClassPool::shrink)
+            .sum()
+    }
+
+    /// Occupancy of each size class
+    pub fn stats(&self) -> [SizeClassStats; 3] {
+        let mut stats = SIZE_CLASSES.map(|size| SizeClassStats {
+            size,
+            ..Default::default()
+        });
+        for worker in self.workers.iter() {
+            for (stats, pool) in stats.iter_mut().zip(&worker.0) {
+                let buffers = pool.buffers.len();
+                stats.buffers += buffers;
+                stats.bytes += buffers * stats.size;
+                stats.hits += pool.hits.load(Ordering::Relaxed);
+                stats.misses += pool.misses.load(Ordering::Relaxed);
+            }
         }
+        stats
     }
 
     /// Get the number of buffers currently in the pool
     pub fn len(&self) -> usize {
-        self.pool.len()
+        self.workers
+            .iter()
+            .flat_map(|worker| worker.0.iter())
+            .map(|pool| pool.buffers.len())
+            .sum()
     }
 
     /// Check if the pool is empty
     pub fn is_empty(&self) -> bool {
-        self.pool.is_empty()
+        self.len() == 0
     }
 }
 
@@ -77,6 +221,21 @@ impl Default for BufferPool {
     }
 }
 
+/// Index of the calling thread among the threads using buffer pools
+fn worker_index() -> usize {
+    static NEXT_WORKER: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static WORKER: Cell<Option<usize>> = const { Cell::new(None) };
+    }
+    WORKER.with(|worker| {
+        worker.get().unwrap_or_else(|| {
+            let index = NEXT_WORKER.fetch_add(1, Ordering::Relaxed);
+            worker.set(Some(index));
+            index
+        })
+    })
+}
+
 /// Global buffer pool instance
 static GLOBAL_POOL: std::sync::OnceLock<Arc<BufferPool>> = std::sync::OnceLock::new();
 
@@ -132,6 +291,50 @@ mod tests {
         assert_eq!(pool.len(), 0);
     }
 
+    #[test]
+    fn test_size_classes() {
+        let pool = BufferPool::with_workers(1);
+
+        let buf = pool.get_with_capacity(10_000);
+        assert!(buf.capacity() >= 16384);
+        pool.put(buf);
+        pool.put(BytesMut::with_capacity(65536));
+        // Too small for any class
+        pool.put(BytesMut::with_capacity(100));
+
+        let stats = pool.stats();
+        assert_eq!(stats[0].buffers, 0);
+        assert_eq!(stats[1].buffers, 1);
+        assert_eq!(stats[2].buffers, 1);
+        assert_eq!(stats[1].misses, 1);
+
+        // Served from the 16K class, not allocated
+        let buf = pool.get_with_capacity(16384);
+        assert!(buf.capacity() >= 16384);
+        assert_eq!(pool.stats()[1].hits, 1);
+        assert!(pool.get_with_capacity(1 << 20).capacity() >= 1 << 20);
+    }
+
+    #[test]
+    fn test_shrink_on_idle() {
+        let pool = BufferPool::with_workers(1);
+        for _ in 0..4 {
+            pool.put(BytesMut::with_capacity(DEFAULT_BUFFER_SIZE));
+        }
+        // Freshly pooled buffers are not idle yet
+        assert_eq!(pool.shrink(), 0);
+        assert_eq!(pool.len(), 4);
+
+        // One buffer stays in use over the next interval
+        let buf = pool.get();
+        assert_eq!(pool.shrink(), 3);
+        assert_eq!(pool.len(), 0);
+        pool.put(buf);
+        assert_eq!(pool.shrink(), 0);
+        assert_eq!(pool.shrink(), 1);
+        assert!(pool.is_empty());
+    }
+
     #[test]
     fn test_global_pool() {
         let buf = get_buffer();
@@ -0,0 +1,213 @@
+//! Cluster Discovery
+//!
+//! Finds the gossip addresses of other nodes through DNS (A/AAAA or SRV
+//! records) or the Kubernetes endpoints API, so nodes can join without a
+//! static seed list. Discovery is re-run periodically to pick up nodes that
+//! were started later.
+
+#[cfg(feature = "kubernetes")]
+use std::net::IpAddr;
+use std::net::SocketAddr;
+#[cfg(any(feature = "dns-srv", feature = "kubernetes"))]
+use std::time::Duration;
+
+#[cfg(feature = "dns-srv")]
+use hickory_resolver::error::ResolveErrorKind;
+#[cfg(feature = "dns-srv")]
+use hickory_resolver::TokioAsyncResolver;
+#[cfg(feature = "kubernetes")]
+use k8s_openapi::api::core::v1::Endpoints;
+#[cfg(feature = "kubernetes")]
+use kube::api::Api;
+#[cfg(feature = "dns-srv")]
+use tracing::debug;
+
+use crate::config::{DiscoveryConfig, DiscoveryMethod};
+
+/// Timeout for a single DNS lookup or API request
+#[cfg(any(feature = "dns-srv", feature = "kubernetes"))]
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Error type for cluster discovery
+#[derive(Debug)]
+pub enum DiscoveryError {
+    /// IO error (network, service account files)
+    Io(std::io::Error),
+    /// DNS query failed or returned an invalid response
+    Dns(String),
+    /// Kubernetes API request failed
+    Kubernetes(String),
+}
+
+impl std::fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiscoveryError::Io(e) => write!(f, "IO error: {}", e),
+            DiscoveryError::Dns(msg) => write!(f, "DNS error: {}", msg),
+            DiscoveryError::Kubernetes(msg) => write!(f, "Kubernetes error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for DiscoveryError {}
+
+impl From<std::io::Error> for DiscoveryError {
+    fn from(e: std::io::Error) -> Self {
+        DiscoveryError::Io(e)
+    }
+}
+
+/// Discover the gossip addresses of cluster nodes
+///
+/// `default_port` is used when neither the configuration nor the records
+/// provide a port. The result may include this node's own address.
+pub(crate) async fn discover(
+    config: &DiscoveryConfig,
+    default_port: u16,
+) -> Result<Vec<SocketAddr>, DiscoveryError> {
+    let port = config.port.unwrap_or(default_port);
+    let name = config.name.as_deref().unwrap_or_default();
+
+    let mut addrs: Vec<SocketAddr> = match config.method {
+        DiscoveryMethod::Static => Vec::new(),
+        DiscoveryMethod::Dns => tokio::net::lookup_host((name, port)).await?.collect(),
+        #[cfg(feature = "dns-srv")]
+        DiscoveryMethod::DnsSrv => resolve_srv(name).await?,
+        #[cfg(not(feature = "dns-srv"))]
+        DiscoveryMethod::DnsSrv => {
+            return Err(DiscoveryError::Dns(
+                "discovery method \"dns_srv\" requires building with --features dns-srv"
+                    .to_string(),
+            ))
+        }
+        #[cfg(feature = "kubernetes")]
+        DiscoveryMethod::Kubernetes => kubernetes_endpoints(config, port).await?,
+        #[cfg(not(feature = "kubernetes"))]
+        DiscoveryMethod::Kubernetes => {
+            return Err(DiscoveryError::Kubernetes(
+                "discovery method \"kubernetes\" requires building with --features kubernetes"
+                    .to_string(),
+            ))
+        }
+    };
+    addrs.sort();
+    addrs.dedup();
+    Ok(addrs)
+}
+
+/// Resolve SRV records to socket addresses
+///
+/// Uses the system resolver configuration, so every listed nameserver,
+/// search domains and TCP fallback for truncated responses apply.
+#[cfg(feature = "dns-srv")]
+async fn resolve_srv(name: &str) -> Result<Vec<SocketAddr>, DiscoveryError> {
+    let resolver = TokioAsyncResolver::tokio_from_system_conf()
+        .map_err(|e| DiscoveryError::Dns(e.to_string()))?;
+
+    let lookup = match tokio::time::timeout(QUERY_TIMEOUT, resolver.srv_lookup(name)).await {
+        Ok(Ok(lookup)) => lookup,
+        // A name without records is a service without endpoints, not an error
+        Ok(Err(e)) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+            return Ok(Vec::new())
+        }
+        Ok(Err(e)) => return Err(DiscoveryError::Dns(e.to_string())),
+        Err(_) => return Err(DiscoveryError::Dns(format!("timed out resolving {}", name))),
+    };
+
+    let mut addrs = Vec::new();
+    for srv in lookup.iter() {
+        let target = srv.target().to_utf8();
+        match resolver.lookup_ip(srv.target().clone()).await {
+            Ok(ips) => addrs.extend(ips.iter().map(|ip| SocketAddr::new(ip, srv.port()))),
+            Err(e) => debug!(
+                "Cluster discovery: cannot resolve SRV target '{}': {}",
+                target, e
+            ),
+        }
+    }
+    Ok(addrs)
+}
+
+/// Read the endpoints of the configured service from the Kubernetes API
+#[cfg(feature = "kubernetes")]
+async fn kubernetes_endpoints(
+    config: &DiscoveryConfig,
+    port: u16,
+) -> Result<Vec<SocketAddr>, DiscoveryError> {
+    let service = config.service.as_deref().unwrap_or_default();
+
+    // In-cluster credentials from the pod's service account
+    let mut client_config =
+        kube::Config::incluster().map_err(|e| DiscoveryError::Kubernetes(e.to_string()))?;
+    client_config.cluster_url = config.api_server.parse().map_err(|_| {
+        DiscoveryError::Kubernetes(format!("invalid api_server URL '{}'", config.api_server))
+    })?;
+    client_config.connect_timeout = Some(QUERY_TIMEOUT);
+    client_config.read_timeout = Some(QUERY_TIMEOUT);
+    let namespace = config
+        .namespace
+        .clone()
+        .unwrap_or_else(|| client_config.default_namespace.clone());
+
+    // The client's TLS needs a process-wide rustls provider, which embedders
+    // that never set up broker or bridge TLS have not installed
+    crate::broker::install_crypto_provider();
+    let client = kube::Client::try_from(client_config)
+        .map_err(|e| DiscoveryError::Kubernetes(e.to_string()))?;
+    let endpoints = Api::<Endpoints>::namespaced(client, &namespace)
+        .get_opt(service)
+        .await
+        .map_err(|e| DiscoveryError::Kubernetes(e.to_string()))?;
+
+    Ok(endpoints
+        .map(|endpoints| endpoint_ips(&endpoints))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect())
+}
+
+/// Addresses of an Endpoints object, including not-ready ones
+///
+/// Nodes gossip before they report ready, so not-ready pods are members too.
+#[cfg(feature = "kubernetes")]
+fn endpoint_ips(endpoints: &Endpoints) -> Vec<IpAddr> {
+    endpoints
+        .subsets
+        .iter()
+        .flatten()
+        .flat_map(|subset| {
+            let ready = subset.addresses.iter().flatten();
+            let not_ready = subset.not_ready_addresses.iter().flatten();
+            ready.chain(not_ready)
+        })
+        .filter_map(|address| address.ip.parse().ok())
+        .collect()
+}
+
+#[cfg(all(test, feature = "kubernetes"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint_ips() {
+        let endpoints: Endpoints = serde_json::from_str(
+            r#"{
+            "kind": "Endpoints",
+            "subsets": [{
+                "addresses": [{"ip": "10.0.0.5"}, {"ip": "10.0.0.6"}],
+                "notReadyAddresses": [{"ip": "10.0.0.7"}],
+                "ports": [{"name": "gossip", "port": 7946, "protocol": "UDP"}]
+            }]
+        }"#,
+        )
+        .unwrap();
+        let ips = endpoint_ips(&endpoints);
+        assert_eq!(ips.len(), 3);
+        assert_eq!(ips[2], "10.0.0.7".parse::<IpAddr>().unwrap());
+
+        // A service without endpoints has no subsets
+        let empty: Endpoints = serde_json::from_str(r#"{"kind": "Endpoints"}"#).unwrap();
+        assert!(endpoint_ips(&empty).is_empty());
+    }
+}
@@ -3,8 +3,9 @@
 //! Coordinates gossip-based cluster membership and message forwarding
 //! between VibeMQ nodes.
 
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::net::SocketAddr;
+use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -12,22 +13,54 @@ use bytes::Bytes;
 use chitchat::transport::UdpTransport;
 use chitchat::{spawn_chitchat, ChitchatConfig, ChitchatHandle, ChitchatId, FailureDetectorConfig};
 use dashmap::DashMap;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use smallvec::SmallVec;
 use tokio::net::TcpListener;
 use tracing::{debug, error, info, warn};
 
-use crate::config::{ClusterConfig, ProxyProtocolConfig};
-use crate::protocol::QoS;
+use crate::broker::RetainedStore;
+use crate::config::{
+    ClusterConfig, Consistency, DiscoveryConfig, DiscoveryMethod, PartitionMode,
+    ProxyProtocolConfig, SharedSubscriptionStrategy,
+};
+use crate::metrics::Metrics;
+use crate::persistence::StoredSession;
+use crate::protocol::{Properties, QoS};
 use crate::proxy::parse_proxy_header;
 use crate::remote::RemotePeer;
 use crate::remote::RemotePeerStatus;
 
-use super::peer::{ClusterInboundCallback, ClusterPeer};
-use super::protocol::{frame_message, read_frame_length, ClusterMessage, CLUSTER_PROTOCOL_VERSION};
+use super::discovery::discover;
+use super::partition::{quorum, witness_reachable, ClusterStatus, PartitionState};
+use super::peer::{ClusterInboundCallback, ClusterPeer, ClusterRetainedCallback};
+use super::protocol::{
+    decode_hello, fit_read_buf, frame_message, hello_ack_frame, read_frame_length, ClusterMessage,
+    FrameOptions, VersionRange, CLUSTER_PROTOCOL_VERSION, READ_BUF_SIZE,
+};
+use super::raft::{MetadataApplyCallback, MetadataCommand, MetadataStore};
+use super::routes::route_filter;
+use super::shared::{choose, Candidate, SharedGroupState, SharedGroupTable, SharedRoute};
+use super::takeover::{ClientRegistry, ClusterTakeoverCallback, PendingTakeovers};
+use super::topology::{retain_nearest, Locality};
+use super::view::{
+    add_subscriptions, ClusterView, NodeHealth, NodeStats, NodeView, TrafficSnapshot,
+};
+use super::wills::{successor, ClusterWill};
 
 /// Chitchat state keys
 const KEY_PEER_ADDR: &str = "peer_addr";
 const KEY_SUBSCRIPTIONS: &str = "subscriptions";
+/// Shared subscription groups (JSON map of group -> SharedGroupState)
+const KEY_SHARED_GROUPS: &str = "shared_groups";
+/// Zone and region of the node (JSON `Locality`)
+const KEY_LOCALITY: &str = "locality";
+/// Node statistics for the cluster view (JSON `NodeStats`)
+const KEY_STATS: &str = "stats";
+/// Gossip key prefix of replicated client wills
+const KEY_WILL_PREFIX: &str = "will:";
+
+/// How long the partition monitor waits for the witness to accept a connection
+const WITNESS_TIMEOUT: Duration = Duration::from_secs(1);
 
 /// Cluster manager for gossip-based horizontal scaling
 pub struct ClusterManager {
@@ -35,21 +68,207 @@ pub struct ClusterManager {
     node_id: String,
     /// Cluster configuration
     config: ClusterConfig,
+    /// Our zone and region
+    locality: Locality,
     /// Chitchat handle for gossip communication
-    chitchat: ChitchatHandle,
+    chitchat: Arc<ChitchatHandle>,
     /// Connected peer nodes
     peers: Arc<DashMap<String, Arc<ClusterPeer>>>,
     /// Local subscriptions (topic filters we have subscribers for)
     local_subscriptions: Arc<RwLock<HashSet<String>>>,
     /// Callback for inbound messages from cluster peers
     inbound_callback: ClusterInboundCallback,
+    /// Callback for handing a local client's session to another node
+    takeover_callback: ClusterTakeoverCallback,
+    /// Retained messages sent to peers when their links come up
+    retained: Option<Arc<RetainedStore>>,
+    /// Callback for the retained messages peers send
+    retained_callback: Option<ClusterRetainedCallback>,
+    /// Owning node of each client in the cluster-wide registry
+    clients: Arc<ClientRegistry>,
+    /// Takeover requests awaiting the previous owner's reply
+    pending_takeovers: Arc<PendingTakeovers>,
+    /// Shared subscription groups last advertised via gossip
+    advertised_shared: RwLock<HashMap<String, SharedGroupState>>,
+    /// Picks the node serving each shared subscription group
+    shared: Arc<SharedRouter>,
+    /// Quorum state maintained by the partition monitor
+    partition: Arc<PartitionState>,
+    /// Raft metadata store (with `consistency = "strong"`)
+    metadata: Option<MetadataStore>,
+    /// Liveness and advertised statistics of remote nodes, from gossip
+    node_health: Arc<DashMap<String, NodeHealth>>,
+    /// Statistics last advertised via gossip
+    local_stats: RwLock<NodeStats>,
+    /// Traffic of peers that have left the cluster, so totals never go back
+    retired_traffic: Arc<Mutex<TrafficSnapshot>>,
+}
+
+/// A peer with the shared groups it advertises for a topic
+type PeerGroups = (Arc<ClusterPeer>, SmallVec<[(String, u32); 4]>);
+
+/// A shared group candidate with the peer to send to (`None` for the local
+/// node), and its zone distance
+type NearbyCandidate<'a> = ((Candidate<'a>, Option<&'a Arc<ClusterPeer>>), u8);
+
+/// Picks the node that delivers a message to each shared subscription group
+struct SharedRouter {
+    strategy: SharedSubscriptionStrategy,
+    /// Our zone and region
+    locality: Locality,
+    /// Connected peer nodes
+    peers: Arc<DashMap<String, Arc<ClusterPeer>>>,
+    /// Local shared subscription groups, with inflight estimates
+    local: RwLock<SharedGroupTable>,
+    /// Per-group round-robin counters
+    counters: DashMap<String, AtomicUsize>,
+}
+
+impl SharedRouter {
+    /// Assign matching shared groups to nodes
+    ///
+    /// With `delivered_locally`, the local groups were already served and
+    /// only groups without a local member are assigned.
+    fn route(
+        &self,
+        topic: &str,
+        local_groups: &[Arc<str>],
+        delivered_locally: bool,
+    ) -> SharedRoute {
+        let mut route = SharedRoute::default();
+
+        // Remote groups matching the topic, in node ID order so round-robin
+        // rotates evenly
+        let mut remote: Vec<PeerGroups> = self
+            .peers
+            .iter()
+            .filter(|p| p.value().status() == RemotePeerStatus::Connected)
+            .map(|p| (p.value().clone(), p.value().shared_groups_matching(topic)))
+            .filter(|(_, groups)| !groups.is_empty())
+            .collect();
+        if remote.is_empty() {
+            return route;
+        }
+        remote.sort_by(|a, b| a.0.node_id().cmp(b.0.node_id()));
+
+        let is_local = |group: &str| local_groups.iter().any(|g| g.as_ref() == group);
+        let mut groups: Vec<&str> = if delivered_locally {
+            Vec::new()
+        } else {
+            local_groups.iter().map(|g| g.as_ref()).collect()
+        };
+        for (_, matching) in &remote {
+            for (group, _) in matching {
+                let served = delivered_locally && is_local(group);
+                if !served && !groups.contains(&group.as_str()) {
+                    groups.push(group);
+                }
+            }
+        }
+
+        // Distance of each remote node, so delivery stays in the nearest zone
+        let distances: Vec<u8> = remote
+            .iter()
+            .map(|(peer, _)| self.locality.distance(&peer.locality()))
+            .collect();
+
+        let local = self.local.read();
+        for group in groups {
+            let mut nearby: SmallVec<[NearbyCandidate<'_>; 4]> = SmallVec::new();
+            if !delivered_locally && is_local(group) {
+                let candidate = Candidate {
+                    node: None,
+                    inflight: local.inflight(group),
+                };
+                nearby.push(((candidate, None), 0));
+            }
+            for ((peer, matching), distance) in remote.iter().zip(&distances) {
+                if let Some((_, inflight)) = matching.iter().find(|(g, _)| g == group) {
+                    let candidate = Candidate {
+                        node: Some(peer.node_id()),
+                        inflight: *inflight,
+                    };
+                    nearby.push(((candidate, Some(peer)), *distance));
+                }
+            }
+            if self.locality.is_labeled() {
+                retain_nearest(&mut nearby);
+            }
+            let (candidates, owners): (SmallVec<[Candidate<'_>; 4]>, SmallVec<[_; 4]>) =
+                nearby.into_iter().map(|(pair, _)| pair).unzip();
+
+            let chosen = {
+                let counter = self.counters.entry(group.to_string()).or_default();
+                choose(self.strategy, &counter, &candidates)
+            };
+            match owners[chosen] {
+                Some(peer) => {
+                    peer.bump_shared_inflight(group);
+                    route.assign(peer.node_id(), group.to_string());
+                }
+                None => local.bump(group),
+            }
+        }
+
+        drop(local);
+
+        route
+    }
+
+    /// Assign the matching shared groups without a local member to nodes
+    fn route_unserved(&self, topic: &str) -> SharedRoute {
+        let local_groups: SmallVec<[Arc<str>; 4]> = self
+            .local
+            .read()
+            .matching(topic)
+            .into_iter()
+            .map(|(group, _)| Arc::from(group))
+            .collect();
+        self.route(topic, &local_groups, true)
+    }
+
+    /// Forward a message to the peers assigned groups in the route or
+    /// with non-shared subscribers for the topic
+    async fn forward(
+        &self,
+        mut route: SharedRoute,
+        topic: &str,
+        payload: Bytes,
+        qos: QoS,
+        retain: bool,
+        properties: &Properties,
+    ) {
+        let peers: Vec<Arc<ClusterPeer>> = self.peers.iter().map(|p| p.value().clone()).collect();
+
+        for peer in peers {
+            let groups = route.take(peer.node_id());
+            if groups.is_empty() && !peer.should_forward(topic) {
+                continue;
+            }
+            if let Err(e) = peer
+                .forward_shared_publish(topic, payload.clone(), qos, retain, groups, properties)
+                .await
+            {
+                warn!(
+                    "Failed to forward message to peer '{}': {}",
+                    peer.node_id(),
+                    e
+                );
+            }
+        }
+    }
 }
 
 impl ClusterManager {
     /// Create a new cluster manager
+    ///
+    /// `metadata_callback` receives committed raft metadata changes and is
+    /// only used with strong consistency.
     pub async fn new(
         config: ClusterConfig,
         inbound_callback: ClusterInboundCallback,
+        takeover_callback: ClusterTakeoverCallback,
+        metadata_callback: MetadataApplyCallback,
     ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
         let node_id = config.get_node_id();
         let gossip_advertise_addr = config.get_gossip_advertise_addr();
@@ -64,7 +283,23 @@ impl ClusterManager {
         let chitchat_id = ChitchatId::new(node_id.clone(), 0, gossip_advertise_addr);
 
         // Parse seed nodes as strings (chitchat expects Vec<String>)
-        let seed_nodes: Vec<String> = config.seeds.clone();
+        let mut seed_nodes: Vec<String> = config.seeds.clone();
+
+        // Add dynamically discovered nodes
+        if config.discovery.method != DiscoveryMethod::Static {
+            match discover(&config.discovery, config.gossip_addr.port()).await {
+                Ok(addrs) => {
+                    info!("Cluster discovery found {} node(s)", addrs.len());
+                    seed_nodes.extend(
+                        addrs
+                            .into_iter()
+                            .filter(|addr| *addr != gossip_advertise_addr)
+                            .map(|addr| addr.to_string()),
+                    );
+                }
+                Err(e) => warn!("Cluster discovery failed: {}", e),
+            }
+        }
 
         // Configure failure detector
         let failure_detector_config = FailureDetectorConfig {
@@ -90,24 +325,76 @@ impl ClusterManager {
         let transport = UdpTransport;
 
         // Initial key-value pairs for our node - use advertise address for peer_addr
+        let locality = Locality::from_config(&config);
         let initial_kvs = vec![
+            (
+                KEY_LOCALITY.to_string(),
+                serde_json::to_string(&locality).unwrap_or_else(|_| "{}".to_string()),
+            ),
             (KEY_PEER_ADDR.to_string(), peer_advertise_addr.to_string()),
             (KEY_SUBSCRIPTIONS.to_string(), "[]".to_string()),
+            (KEY_SHARED_GROUPS.to_string(), "{}".to_string()),
+            (KEY_STATS.to_string(), "{}".to_string()),
         ];
 
         // Spawn chitchat
         let chitchat = spawn_chitchat(chitchat_config, initial_kvs, &transport).await?;
 
+        // Start the raft metadata store
+        let metadata = match config.consistency {
+            Consistency::Eventual => None,
+            Consistency::Strong => {
+                Some(MetadataStore::start(&node_id, &config.raft, metadata_callback).await?)
+            }
+        };
+
+        let peers = Arc::new(DashMap::new());
         Ok(Self {
             node_id,
+            shared: Arc::new(SharedRouter {
+                strategy: config.shared_subscription_strategy,
+                locality: locality.clone(),
+                peers: Arc::clone(&peers),
+                local: RwLock::new(SharedGroupTable::default()),
+                counters: DashMap::new(),
+            }),
             config,
-            chitchat,
-            peers: Arc::new(DashMap::new()),
+            locality,
+            chitchat: Arc::new(chitchat),
+            peers,
             local_subscriptions: Arc::new(RwLock::new(HashSet::new())),
             inbound_callback,
+            takeover_callback,
+            retained: None,
+            retained_callback: None,
+            clients: Arc::new(ClientRegistry::default()),
+            pending_takeovers: Arc::new(PendingTakeovers::default()),
+            advertised_shared: RwLock::new(HashMap::new()),
+            partition: Arc::new(PartitionState::default()),
+            metadata,
+            node_health: Arc::new(DashMap::new()),
+            local_stats: RwLock::new(NodeStats::default()),
+            retired_traffic: Arc::new(Mutex::new(TrafficSnapshot::default())),
         })
     }
 
+    /// Exchange retained messages with peers when links come up
+    ///
+    /// The messages in `store` are sent to each peer this node connects to,
+    /// and `callback` receives those peers send. Ignored with strong
+    /// consistency, where raft replicates retained messages.
+    pub fn with_retained(
+        mut self,
+        store: Arc<RetainedStore>,
+        callback: ClusterRetainedCallback,
+    ) -> Self {
+        if self.metadata.is_none() {
+            self.retained = Some(store);
+            self.retained_callback = Some(callback);
+        }
+        self
+    }
+
     /// Get our node ID
     pub fn node_id(&self) -> &str {
         &self.node_id
@@ -154,55 +441,475 @@ impl ClusterManager {
     }
 
     /// Add a subscription filter
+    ///
+    /// Besides the gossip state, new filters are pushed straight to connected
+    /// peers so they start forwarding matching messages right away.
     pub async fn add_subscription(&self, filter: String) {
-        debug!("Cluster: adding subscription filter '{}'", filter);
+        let filter = route_filter(filter);
         let filters = {
             let mut subs = self.local_subscriptions.write();
-            subs.insert(filter);
+            if !subs.insert(filter.clone()) {
+                return;
+            }
             subs.clone()
         };
+        debug!("Cluster: adding subscription filter '{}'", filter);
         self.update_subscriptions(filters).await;
+        self.push_subscription_update(vec![filter], Vec::new())
+            .await;
     }
 
     /// Remove a subscription filter
+    ///
+    /// Only call this once no local client subscribes to the filter anymore,
+    /// as the broker checks on each removed subscription.
     pub async fn remove_subscription(&self, filter: &str) {
+        let filter = route_filter(filter.to_string());
         let filters = {
             let mut subs = self.local_subscriptions.write();
-            subs.remove(filter);
+            if !subs.remove(&filter) {
+                return;
+            }
             subs.clone()
         };
         self.update_subscriptions(filters).await;
+        self.push_subscription_update(Vec::new(), vec![filter])
+            .await;
     }
 
-    /// Forward a published message to peers that have matching subscriptions
-    pub async fn forward_publish(&self, topic: &str, payload: Bytes, qos: QoS, retain: bool) {
-        for peer in self.peers.iter() {
-            let peer_ref = peer.value();
-            let status = peer_ref.status();
-            let should_fwd = peer_ref.should_forward(topic);
-            debug!(
-                "Cluster forward check: peer='{}' status={:?} should_forward={} topic='{}'",
-                peer_ref.node_id(),
-                status,
-                should_fwd,
-                topic
-            );
-            if status == RemotePeerStatus::Connected && should_fwd {
-                debug!("Cluster: forwarding to peer '{}'", peer_ref.node_id());
-                if let Err(e) = peer_ref
-                    .forward_publish(topic, payload.clone(), qos, retain)
-                    .await
-                {
-                    warn!(
-                        "Failed to forward message to peer '{}': {}",
-                        peer_ref.node_id(),
-                        e
-                    );
+    /// Bring the advertised filters in line with the local subscription store
+    ///
+    /// `filters` is the full set of filters with local subscribers. Peers are
+    /// only updated if it differs from what was last advertised.
+    pub async fn sync_subscriptions(&self, filters: HashSet<String>) {
+        let filters: HashSet<String> = filters.into_iter().map(route_filter).collect();
+        let (added, removed) = {
+            let subs = self.local_subscriptions.read();
+            let added: Vec<String> = filters.difference(&subs).cloned().collect();
+            let removed: Vec<String> = subs.difference(&filters).cloned().collect();
+            (added, removed)
+        };
+        if added.is_empty() && removed.is_empty() {
+            return;
+        }
+
+        debug!(
+            "Cluster: subscription filters changed (+{}, -{})",
+            added.len(),
+            removed.len()
+        );
+        self.update_subscriptions(filters).await;
+        self.push_subscription_update(added, removed).await;
+    }
+
+    /// Advertise the local shared subscription groups
+    ///
+    /// `groups` is the full set of local groups with their unacknowledged
+    /// message counts. Gossip is only updated if it differs from what was last
+    /// advertised.
+    pub async fn sync_shared_groups(&self, groups: HashMap<String, SharedGroupState>) {
+        self.shared.local.write().replace(groups.clone());
+        {
+            let mut advertised = self.advertised_shared.write();
+            if *advertised == groups {
+                return;
+            }
+            *advertised = groups.clone();
+        }
+
+        let json = serde_json::to_string(&groups).unwrap_or_else(|_| "{}".to_string());
+        self.chitchat
+            .with_chitchat(|cc| {
+                cc.self_node_state()
+                    .set(KEY_SHARED_GROUPS.to_string(), json.clone());
+            })
+            .await;
+    }
+
+    /// Decide which node delivers a message to each matching shared group
+    ///
+    /// `local_groups` are the groups with a local member matching the topic.
+    /// Groups assigned to this node are delivered locally; the rest must be
+    /// forwarded with [`ClusterManager::forward_routed`].
+    pub fn route_shared(&self, topic: &str, local_groups: &[Arc<str>]) -> SharedRoute {
+        self.shared.route(topic, local_groups, false)
+    }
+
+    /// Route the shared groups of a message already delivered to every
+    /// local group
+    ///
+    /// For messages not published by a client connection, such as wills.
+    /// Only groups without a local member are assigned to other nodes.
+    pub fn route_unserved_shared(&self, topic: &str) -> SharedRoute {
+        self.shared.route_unserved(topic)
+    }
+
+    /// Forward a published message according to a shared subscription route
+    ///
+    /// Peers receive the message if shared groups were assigned to them or
+    /// they have non-shared subscribers for the topic. Messages for peers
+    /// whose link is down are queued until it reconnects.
+    pub async fn forward_routed(
+        &self,
+        route: SharedRoute,
+        topic: &str,
+        payload: Bytes,
+        qos: QoS,
+        retain: bool,
+        properties: &Properties,
+    ) {
+        self.shared
+            .forward(route, topic, payload, qos, retain, properties)
+            .await;
+    }
+
+    /// Send an incremental subscription update to all connected peers
+    async fn push_subscription_update(&self, added: Vec<String>, removed: Vec<String>) {
+        let peers: Vec<Arc<ClusterPeer>> = self
+            .peers
+            .iter()
+            .filter(|p| p.value().status() == RemotePeerStatus::Connected)
+            .map(|p| p.value().clone())
+            .collect();
+
+        for peer in peers {
+            if let Err(e) = peer
+                .send_subscription_update(added.clone(), removed.clone())
+                .await
+            {
+                debug!(
+                    "Cluster: failed to send subscription update to '{}': {}",
+                    peer.node_id(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// Send the clients that connected to or left this node to connected peers
+    async fn push_client_update(&self, claimed: Vec<String>, released: Vec<String>) {
+        let peers: Vec<Arc<ClusterPeer>> = self
+            .peers
+            .iter()
+            .filter(|p| p.value().status() == RemotePeerStatus::Connected)
+            .map(|p| p.value().clone())
+            .collect();
+
+        for peer in peers {
+            if let Err(e) = peer
+                .send_client_update(claimed.clone(), released.clone())
+                .await
+            {
+                debug!(
+                    "Cluster: failed to send client update to '{}': {}",
+                    peer.node_id(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// Whether retained messages and session ownership go through raft
+    pub fn is_strongly_consistent(&self) -> bool {
+        self.metadata.is_some()
+    }
+
+    /// Commit a retained message through raft
+    ///
+    /// Every node, including this one, stores it once committed. Returns
+    /// false if raft is not in use or the write failed.
+    pub async fn write_retained(&self, topic: &str, payload: Bytes, qos: QoS) -> bool {
+        let Some(ref metadata) = self.metadata else {
+            return false;
+        };
+        let command = MetadataCommand::SetRetained {
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+            qos: qos as u8,
+        };
+        match metadata.write(command).await {
+            Ok(_) => true,
+            Err(e) => {
+                warn!(
+                    "Cluster: failed to commit retained message on '{}': {}",
+                    topic, e
+                );
+                false
+            }
+        }
+    }
+
+    /// Commit a metadata change (users, ACL roles) through raft
+    pub async fn write_metadata(
+        &self,
+        command: MetadataCommand,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match self.metadata {
+            Some(ref metadata) => {
+                metadata.write(command).await?;
+                Ok(())
+            }
+            None => Err("cluster metadata requires consistency = \"strong\"".into()),
+        }
+    }
+
+    /// Record in the cluster-wide registry that a client is connected here
+    pub async fn register_client(&self, client_id: &str) {
+        if self.clients.claim(client_id) {
+            self.push_client_update(vec![client_id.to_string()], Vec::new())
+                .await;
+        }
+
+        if let Some(ref metadata) = self.metadata {
+            // Queued rather than awaited, so the caller's event loop does
+            // not wait for a raft commit
+            metadata.submit(MetadataCommand::ClaimClient {
+                client_id: client_id.to_string(),
+                node_id: self.node_id.clone(),
+            });
+        }
+    }
+
+    /// Replicate a local client's will so a surviving node can publish it
+    /// if this node dies; `None` clears it
+    pub async fn register_will(&self, client_id: &str, will: Option<ClusterWill>) {
+        let key = will_key(client_id);
+        let value = will.map(|w| w.to_gossip());
+        self.chitchat
+            .with_chitchat(|cc| match value {
+                Some(ref value) => {
+                    cc.self_node_state().set(key.clone(), value.clone());
+                }
+                None => {
+                    cc.self_node_state().delete(&key);
                 }
+            })
+            .await;
+    }
+
+    /// Remove a client from the cluster-wide registry
+    pub async fn unregister_client(&self, client_id: &str) {
+        if self.clients.release(client_id) {
+            self.push_client_update(Vec::new(), vec![client_id.to_string()])
+                .await;
+        }
+
+        if let Some(ref metadata) = self.metadata {
+            metadata.submit(MetadataCommand::ReleaseClient {
+                client_id: client_id.to_string(),
+                node_id: self.node_id.clone(),
+            });
+        }
+    }
+
+    /// Remove clients from the registry that are neither connected here
+    /// nor have a session left, such as expired persistent sessions
+    pub async fn prune_clients(&self, keep: impl Fn(&str) -> bool) {
+        for client_id in self.clients.local_clients() {
+            if !keep(&client_id) {
+                debug!(
+                    "Cluster: releasing client '{}' without a session",
+                    client_id
+                );
+                self.unregister_client(&client_id).await;
             }
         }
     }
 
+    /// Node owning a client's session, from raft or the client registry
+    fn client_owner(&self, client_id: &str) -> Option<String> {
+        match self.metadata {
+            Some(ref metadata) => metadata.client_owner(client_id),
+            None => self.clients.owner(client_id),
+        }
+    }
+
+    /// Take a client's session over from the node that currently owns it
+    ///
+    /// If the registry shows another node owning `client_id`, that node is
+    /// asked to disconnect the client with SessionTakenOver and, unless
+    /// `clean_start` is set, to send its persistent session back. Returns the
+    /// session if one was received within `session_takeover_timeout`.
+    pub async fn take_over_session(
+        &self,
+        client_id: &str,
+        clean_start: bool,
+    ) -> Option<StoredSession> {
+        let owner = self.client_owner(client_id)?;
+        if owner == self.node_id {
+            return None;
+        }
+        let peer = self.peers.get(&owner).map(|p| p.value().clone())?;
+        if peer.status() != RemotePeerStatus::Connected {
+            warn!(
+                "Cluster: cannot take over session of '{}', owner '{}' is not connected",
+                client_id, owner
+            );
+            return None;
+        }
+
+        debug!(
+            "Cluster: taking over session of '{}' from '{}'",
+            client_id, owner
+        );
+        let reply = self.pending_takeovers.register(client_id);
+        if let Err(e) = peer.request_takeover(client_id, !clean_start).await {
+            warn!(
+                "Cluster: failed to request takeover of '{}' from '{}': {}",
+                client_id, owner, e
+            );
+            self.pending_takeovers.cancel(client_id);
+            return None;
+        }
+
+        match tokio::time::timeout(self.config.session_takeover_timeout, reply).await {
+            Ok(Ok(session)) => {
+                self.clients.forget(client_id);
+                session
+            }
+            _ => {
+                warn!(
+                    "Cluster: timed out waiting for '{}' to hand over session of '{}'",
+                    owner, client_id
+                );
+                self.pending_takeovers.cancel(client_id);
+                None
+            }
+        }
+    }
+
+    /// Whether this node has lost quorum and is cut off from the cluster majority
+    pub fn is_partitioned(&self) -> bool {
+        self.partition.is_partitioned()
+    }
+
+    /// Whether writes to cluster-wide state (retained messages) must be refused
+    pub fn rejects_shared_writes(&self) -> bool {
+        self.config.partition_mode == PartitionMode::ReadOnlyShared && self.is_partitioned()
+    }
+
+    /// Current partition status of this node
+    pub fn status(&self) -> ClusterStatus {
+        self.partition
+            .status(1 + self.connected_peer_count(), self.config.expected_nodes)
+    }
+
+    /// Advertise this node's statistics to the cluster
+    ///
+    /// Inter-node traffic is taken from the peer links. Gossip is only
+    /// updated if the statistics changed since the previous call.
+    pub async fn publish_stats(&self, connections: usize, subscriptions: usize) {
+        let traffic = self.traffic();
+        let stats = NodeStats {
+            connections,
+            subscriptions,
+            messages_sent: traffic.messages_sent,
+            bytes_sent: traffic.bytes_sent,
+            messages_received: traffic.messages_received,
+            bytes_received: traffic.bytes_received,
+        };
+        {
+            let mut local = self.local_stats.write();
+            if *local == stats {
+                return;
+            }
+            *local = stats;
+        }
+
+        let json = serde_json::to_string(&stats).unwrap_or_else(|_| "{}".to_string());
+        self.chitchat
+            .with_chitchat(|cc| {
+                cc.self_node_state()
+                    .set(KEY_STATS.to_string(), json.clone());
+            })
+            .await;
+    }
+
+    /// Inter-node traffic of this node, summed over all peer links
+    fn traffic(&self) -> TrafficSnapshot {
+        let mut total = *self.retired_traffic.lock();
+        for peer in self.peers.iter() {
+            total += peer.value().traffic();
+        }
+        total
+    }
+
+    /// Aggregated view of the cluster: nodes with their health, connection
+    /// counts and traffic, and which nodes subscribe to which filters
+    pub fn cluster_view(&self) -> ClusterView {
+        let mut subscriptions = BTreeMap::new();
+        add_subscriptions(
+            &mut subscriptions,
+            &self.node_id,
+            self.local_subscriptions.read().iter().cloned(),
+        );
+
+        let mut nodes = vec![NodeView {
+            node_id: self.node_id.clone(),
+            alive: true,
+            locality: self.locality.clone(),
+            link: None,
+            protocol_version: Some(CLUSTER_PROTOCOL_VERSION),
+            queue_depth: 0,
+            stats: *self.local_stats.read(),
+            traffic: self.traffic(),
+        }];
+
+        let mut remote: Vec<NodeView> = self
+            .node_health
+            .iter()
+            .map(|entry| {
+                let node_id = entry.key();
+                let health = entry.value();
+                let peer = self.peers.get(node_id);
+                if let Some(ref peer) = peer {
+                    add_subscriptions(&mut subscriptions, node_id, peer.remote_subscriptions());
+                }
+                NodeView {
+                    node_id: node_id.clone(),
+                    alive: health.alive,
+                    locality: peer.as_ref().map(|p| p.locality()).unwrap_or_default(),
+                    link: peer.as_ref().map(|p| p.status()),
+                    protocol_version: peer.as_ref().and_then(|p| p.protocol_version()),
+                    queue_depth: peer.as_ref().map_or(0, |p| p.queue_depth()),
+                    stats: health.stats,
+                    traffic: peer.as_ref().map(|p| p.traffic()).unwrap_or_default(),
+                }
+            })
+            .collect();
+        remote.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+        nodes.extend(remote);
+
+        for nodes in subscriptions.values_mut() {
+            nodes.sort();
+        }
+
+        ClusterView {
+            node_id: self.node_id.clone(),
+            partitioned: self.is_partitioned(),
+            nodes,
+            subscriptions,
+        }
+    }
+
+    /// Update cluster gauges and counters from the current peer state
+    pub fn record_metrics(&self, metrics: &Metrics) {
+        metrics
+            .cluster_peers_current
+            .set(self.connected_peer_count() as i64);
+        metrics.cluster_partition_status(&self.status());
+        let traffic = self.traffic();
+        metrics.cluster_traffic(traffic.messages_sent, traffic.messages_received);
+        for peer in self.peers.iter() {
+            let peer = peer.value();
+            metrics.cluster_peer_queued(
+                peer.node_id(),
+                peer.queue_depth(),
+                peer.take_queue_drops(),
+            );
+        }
+    }
+
     /// Start the cluster manager background tasks
     pub async fn start(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         info!(
@@ -213,31 +920,84 @@ impl ClusterManager {
         // Spawn peer listener (accepts incoming TCP connections from other nodes)
         let listener = TcpListener::bind(self.config.peer_addr).await?;
         let inbound_callback = self.inbound_callback.clone();
+        let takeover_callback = self.takeover_callback.clone();
+        let retained_callback = self.retained_callback.clone();
+        let clients = self.clients.clone();
+        let peers = self.peers.clone();
         let local_node_id = self.node_id.clone();
         let local_subs = self.local_subscriptions.clone();
         let proxy_config = self.config.proxy_protocol.clone();
+        let batch_size = self.config.batch_size;
 
         tokio::spawn(async move {
             Self::peer_listener_loop(
                 listener,
                 inbound_callback,
+                takeover_callback,
+                retained_callback,
+                clients,
+                peers,
                 local_node_id,
                 local_subs,
                 proxy_config,
+                batch_size,
             )
             .await;
         });
 
+        // Spawn discovery refresh (finds nodes that joined after we started)
+        if self.config.discovery.method != DiscoveryMethod::Static {
+            let chitchat = self.chitchat.clone();
+            let discovery = self.config.discovery.clone();
+            let default_port = self.config.gossip_addr.port();
+            let self_addr = self.config.get_gossip_advertise_addr();
+
+            tokio::spawn(async move {
+                Self::discovery_loop(chitchat, discovery, default_port, self_addr).await;
+            });
+        }
+
+        // Spawn partition monitor (tracks whether we still hold quorum)
+        if self.config.expected_nodes > 0 {
+            let peers = self.peers.clone();
+            let partition = self.partition.clone();
+            let config = self.config.clone();
+
+            tokio::spawn(async move {
+                Self::partition_monitor_loop(peers, partition, config).await;
+            });
+        }
+
         // Spawn gossip watcher (discovers new peers, connects to them)
         let chitchat = self.chitchat.chitchat();
         let peers = self.peers.clone();
         let config = self.config.clone();
         let inbound_callback = self.inbound_callback.clone();
         let local_node_id = self.node_id.clone();
+        let clients = self.clients.clone();
+        let pending_takeovers = self.pending_takeovers.clone();
+        let node_health = self.node_health.clone();
+        let retired_traffic = self.retired_traffic.clone();
+        let partition = self.partition.clone();
+        let shared = self.shared.clone();
+        let retained = self.retained.clone();
 
         tokio::spawn(async move {
-            Self::gossip_watcher_loop(chitchat, peers, config, inbound_callback, local_node_id)
-                .await;
+            Self::gossip_watcher_loop(
+                chitchat,
+                peers,
+                config,
+                inbound_callback,
+                local_node_id,
+                clients,
+                pending_takeovers,
+                node_health,
+                retired_traffic,
+                partition,
+                shared,
+                retained,
+            )
+            .await;
         });
 
         Ok(())
@@ -252,16 +1012,26 @@ impl ClusterManager {
             let _ = peer.value().stop().await;
         }
 
+        if let Some(ref metadata) = self.metadata {
+            metadata.shutdown().await;
+        }
+
         // Shutdown chitchat - it will stop when handle is dropped
     }
 
     /// Listen for incoming peer connections
+    #[allow(clippy::too_many_arguments)]
     async fn peer_listener_loop(
         listener: TcpListener,
         inbound_callback: ClusterInboundCallback,
+        takeover_callback: ClusterTakeoverCallback,
+        retained_callback: Option<ClusterRetainedCallback>,
+        clients: Arc<ClientRegistry>,
+        peers: Arc<DashMap<String, Arc<ClusterPeer>>>,
         local_node_id: String,
         local_subs: Arc<RwLock<HashSet<String>>>,
         proxy_config: ProxyProtocolConfig,
+        batch_size: usize,
     ) {
         loop {
             match listener.accept().await {
@@ -269,6 +1039,10 @@ impl ClusterManager {
                     debug!("Incoming cluster peer connection from {}", addr);
 
                     let callback = inbound_callback.clone();
+                    let takeover_callback = takeover_callback.clone();
+                    let retained_callback = retained_callback.clone();
+                    let clients = clients.clone();
+                    let peers = peers.clone();
                     let node_id = local_node_id.clone();
                     let subs = local_subs.clone();
                     let proxy_config = proxy_config.clone();
@@ -302,8 +1076,18 @@ impl ClusterManager {
                             addr
                         };
 
-                        if let Err(e) =
-                            Self::handle_incoming_peer(stream, callback, node_id, subs).await
+                        if let Err(e) = Self::handle_incoming_peer(
+                            stream,
+                            callback,
+                            takeover_callback,
+                            retained_callback,
+                            clients,
+                            peers,
+                            node_id,
+                            subs,
+                            batch_size,
+                        )
+                        .await
                         {
                             debug!(
                                 "Incoming peer connection error from {}: {}",
@@ -320,16 +1104,22 @@ impl ClusterManager {
     }
 
     /// Handle an incoming peer connection
+    #[allow(clippy::too_many_arguments)]
     async fn handle_incoming_peer(
         stream: tokio::net::TcpStream,
         inbound_callback: ClusterInboundCallback,
+        takeover_callback: ClusterTakeoverCallback,
+        retained_callback: Option<ClusterRetainedCallback>,
+        clients: Arc<ClientRegistry>,
+        peers: Arc<DashMap<String, Arc<ClusterPeer>>>,
         local_node_id: String,
         local_subs: Arc<RwLock<HashSet<String>>>,
+        batch_size: usize,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
         let (mut read_half, mut write_half) = stream.into_split();
-        let mut read_buf = vec![0u8; 65536];
+        let mut read_buf = vec![0u8; READ_BUF_SIZE];
 
         // Wait for Hello
         let n =
@@ -344,30 +1134,37 @@ impl ClusterManager {
             return Err("Incomplete frame".into());
         }
 
-        let msg = ClusterMessage::decode(&read_buf[4..4 + len as usize])?;
+        let (peer_node_id, peer_versions) =
+            decode_hello(&read_buf[4..4 + len as usize])?.ok_or("Expected Hello")?;
 
-        let peer_node_id = match msg {
-            ClusterMessage::Hello { node_id, version } => {
-                if version != CLUSTER_PROTOCOL_VERSION {
-                    return Err(format!(
-                        "Protocol version mismatch: {} vs {}",
-                        version, CLUSTER_PROTOCOL_VERSION
-                    )
-                    .into());
-                }
-                node_id
-            }
-            _ => return Err("Expected Hello".into()),
+        let Some(version) = VersionRange::LOCAL.negotiate(&peer_versions) else {
+            warn!(
+                "Refusing cluster peer '{}': it speaks protocol {}, this node speaks {}; \
+                 upgrade nodes one release at a time",
+                peer_node_id,
+                peer_versions,
+                VersionRange::LOCAL
+            );
+            let reject = ClusterMessage::HelloReject {
+                node_id: local_node_id.clone(),
+                min_version: VersionRange::LOCAL.min,
+                max_version: VersionRange::LOCAL.max,
+            };
+            write_half.write_all(&frame_message(&reject)?).await?;
+            return Err(format!(
+                "Incompatible protocol {} from '{}'",
+                peer_versions, peer_node_id
+            )
+            .into());
         };
 
-        info!("Incoming cluster peer: {}", peer_node_id);
+        info!(
+            "Incoming cluster peer: {} (protocol=v{})",
+            peer_node_id, version
+        );
 
         // Send HelloAck
-        let ack = ClusterMessage::HelloAck {
-            node_id: local_node_id.clone(),
-            version: CLUSTER_PROTOCOL_VERSION,
-        };
-        let frame = frame_message(&ack)?;
+        let frame = hello_ack_frame(&local_node_id, version, batch_size)?;
         write_half.write_all(&frame).await?;
 
         // Send our subscriptions
@@ -394,7 +1191,27 @@ impl ClusterManager {
                     break;
                 }
 
-                if let Ok(msg) = ClusterMessage::decode(&read_buf[4..4 + len]) {
+                let messages = ClusterMessage::decode(&read_buf[4..4 + len])
+                    .and_then(ClusterMessage::unpack)
+                    .unwrap_or_default();
+                let publishes = messages
+                    .iter()
+                    .filter(|m| {
+                        matches!(
+                            m,
+                            ClusterMessage::Publish { .. }
+                                | ClusterMessage::SharedPublish { .. }
+                                | ClusterMessage::WithProperties { .. }
+                        )
+                    })
+                    .count();
+                if publishes > 0 {
+                    if let Some(peer) = peers.get(&peer_node_id) {
+                        peer.record_received(publishes, 4 + len);
+                    }
+                }
+                for msg in messages {
+                    let (msg, properties) = msg.take_properties();
                     match msg {
                         ClusterMessage::Publish {
                             topic,
@@ -423,8 +1240,91 @@ impl ClusterManager {
                                 qos_level,
                                 retain,
                                 origin_node,
+                                None,
+                                properties,
+                            );
+                        }
+                        ClusterMessage::SharedPublish {
+                            topic,
+                            payload,
+                            qos,
+                            retain,
+                            origin_node,
+                            groups,
+                        } => {
+                            debug!(
+                                "Cluster inbound: received shared publish '{}' from peer {} (groups={:?})",
+                                topic, peer_node_id, groups
+                            );
+                            inbound_callback(
+                                topic,
+                                Bytes::from(payload),
+                                QoS::from_u8(qos).unwrap_or(QoS::ExactlyOnce),
+                                retain,
+                                origin_node,
+                                Some(groups),
+                                properties,
                             );
                         }
+                        ClusterMessage::SubscriptionSync { filters } => {
+                            // Peers we have not discovered yet are synced once
+                            // gossip reports them
+                            if let Some(peer) = peers.get(&peer_node_id) {
+                                peer.update_remote_subscriptions(filters);
+                            }
+                        }
+                        ClusterMessage::SubscriptionUpdate { added, removed } => {
+                            debug!(
+                                "Cluster: subscription update from '{}' (+{}, -{})",
+                                peer_node_id,
+                                added.len(),
+                                removed.len()
+                            );
+                            if let Some(peer) = peers.get(&peer_node_id) {
+                                peer.apply_subscription_update(added, removed);
+                            }
+                        }
+                        ClusterMessage::SessionTakeover {
+                            client_id,
+                            node_id,
+                            transfer,
+                        } => {
+                            info!(
+                                "Cluster: client '{}' reconnected to '{}', handing over its session",
+                                client_id, node_id
+                            );
+                            let session = takeover_callback(client_id.clone(), transfer).await;
+                            // The new owner announces the client itself
+                            clients.release(&client_id);
+
+                            let reply = ClusterMessage::SessionTransfer {
+                                client_id,
+                                session: session.map(Box::new),
+                            };
+                            let frame = frame_message(&reply)?;
+                            write_half.write_all(&frame).await?;
+                        }
+                        ClusterMessage::ClientSync { clients: synced } => {
+                            debug!(
+                                "Cluster: client sync from '{}' ({} clients)",
+                                peer_node_id,
+                                synced.len()
+                            );
+                            clients.apply_sync(&peer_node_id, synced);
+                        }
+                        ClusterMessage::ClientUpdate { claimed, released } => {
+                            clients.apply_update(&peer_node_id, claimed, released);
+                        }
+                        ClusterMessage::RetainedSync { messages } => {
+                            debug!(
+                                "Cluster: retained sync from '{}' ({} messages)",
+                                peer_node_id,
+                                messages.len()
+                            );
+                            if let Some(ref callback) = retained_callback {
+                                callback(messages);
+                            }
+                        }
                         ClusterMessage::Ping => {
                             let pong = ClusterMessage::Pong;
                             if let Ok(frame) = frame_message(&pong) {
@@ -442,26 +1342,188 @@ impl ClusterManager {
                 read_buf.copy_within(4 + len..buf_offset, 0);
                 buf_offset -= 4 + len;
             }
+
+            // Make room for frames larger than the buffer (e.g. batches)
+            if let Err(len) = fit_read_buf(&mut read_buf, buf_offset) {
+                return Err(format!("Frame too large: {} bytes", len).into());
+            }
+        }
+    }
+
+    /// Periodically re-run discovery and gossip with nodes not yet in the cluster
+    async fn discovery_loop(
+        chitchat: Arc<ChitchatHandle>,
+        discovery: DiscoveryConfig,
+        default_port: u16,
+        self_addr: SocketAddr,
+    ) {
+        let mut interval = tokio::time::interval(discovery.refresh_interval);
+        // The initial discovery already provided the seed nodes
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            let addrs = match discover(&discovery, default_port).await {
+                Ok(addrs) => addrs,
+                Err(e) => {
+                    warn!("Cluster discovery failed: {}", e);
+                    continue;
+                }
+            };
+
+            let known: HashSet<SocketAddr> = {
+                let cc = chitchat.chitchat();
+                let cc = cc.lock().await;
+                cc.state_snapshot()
+                    .node_states
+                    .iter()
+                    .map(|ns| ns.chitchat_id().gossip_advertise_addr)
+                    .collect()
+            };
+
+            for addr in addrs {
+                if addr == self_addr || known.contains(&addr) {
+                    continue;
+                }
+                debug!("Cluster discovery: gossiping with new node at {}", addr);
+                if let Err(e) = chitchat.gossip(addr) {
+                    debug!("Cluster discovery: failed to gossip with {}: {}", addr, e);
+                }
+            }
+        }
+    }
+
+    /// Periodically count reachable nodes and detect loss of quorum
+    async fn partition_monitor_loop(
+        peers: Arc<DashMap<String, Arc<ClusterPeer>>>,
+        partition: Arc<PartitionState>,
+        config: ClusterConfig,
+    ) {
+        let needed = quorum(config.expected_nodes, config.witness.is_some());
+        // Give peers time to connect before the first verdict
+        tokio::time::sleep(config.failure_timeout).await;
+
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+
+            let reachable = 1 + peers
+                .iter()
+                .filter(|p| p.value().status() == RemotePeerStatus::Connected)
+                .count();
+            let mut votes = reachable;
+            // The witness only matters when the peers alone fall short
+            if votes < needed {
+                if let Some(ref witness) = config.witness {
+                    if witness_reachable(witness, WITNESS_TIMEOUT).await {
+                        votes += 1;
+                    }
+                }
+            }
+
+            let partitioned = votes < needed;
+            if partition.update(partitioned) {
+                if partitioned {
+                    warn!(
+                        "Cluster partition detected: {} of {} nodes reachable (quorum {}), mode={:?}",
+                        reachable, config.expected_nodes, needed, config.partition_mode
+                    );
+                } else {
+                    info!(
+                        "Cluster quorum restored: {} of {} nodes reachable",
+                        reachable, config.expected_nodes
+                    );
+                }
+            }
+        }
+    }
+
+    /// Publish the will of a client whose node died, once its delay has
+    /// passed, unless the client reconnected to another node meanwhile
+    async fn publish_orphaned_will(
+        client_id: String,
+        will: ClusterWill,
+        dead_node: String,
+        shared: Arc<SharedRouter>,
+        clients: Arc<ClientRegistry>,
+        inbound_callback: ClusterInboundCallback,
+    ) {
+        if will.delay > 0 {
+            tokio::time::sleep(Duration::from_secs(will.delay as u64)).await;
         }
+        if clients.owned_elsewhere(&client_id, &dead_node) {
+            debug!(
+                "Cluster: client '{}' reconnected, dropping will taken over from '{}'",
+                client_id, dead_node
+            );
+            return;
+        }
+
+        let Some(publish) = will.to_publish() else {
+            debug!(
+                "Cluster: will of '{}' taken over from '{}' expired",
+                client_id, dead_node
+            );
+            return;
+        };
+        inbound_callback(
+            publish.topic.to_string(),
+            publish.payload.clone(),
+            publish.qos,
+            publish.retain,
+            dead_node,
+            None,
+            publish.properties.clone(),
+        );
+        // Delivered to every local shared group, so peers only serve the rest
+        let route = shared.route_unserved(&publish.topic);
+        shared
+            .forward(
+                route,
+                &publish.topic,
+                publish.payload,
+                publish.qos,
+                publish.retain,
+                &publish.properties,
+            )
+            .await;
     }
 
     /// Watch gossip state for new peers and connect to them
+    #[allow(clippy::too_many_arguments)]
     async fn gossip_watcher_loop(
         chitchat: Arc<tokio::sync::Mutex<chitchat::Chitchat>>,
         peers: Arc<DashMap<String, Arc<ClusterPeer>>>,
         config: ClusterConfig,
         inbound_callback: ClusterInboundCallback,
         local_node_id: String,
+        clients: Arc<ClientRegistry>,
+        pending_takeovers: Arc<PendingTakeovers>,
+        node_health: Arc<DashMap<String, NodeHealth>>,
+        retired_traffic: Arc<Mutex<TrafficSnapshot>>,
+        partition: Arc<PartitionState>,
+        shared: Arc<SharedRouter>,
+        retained: Option<Arc<RetainedStore>>,
     ) {
         let mut known_nodes: HashSet<String> = HashSet::new();
+        // Dead nodes whose wills were already taken over
+        let mut orphaned_nodes: HashSet<String> = HashSet::new();
+        // Last subscription state seen in gossip per node; incremental updates
+        // received over TCP are newer, so only apply gossip when it changes
+        let mut gossiped_subs: HashMap<String, String> = HashMap::new();
+        let mut gossiped_shared: HashMap<String, String> = HashMap::new();
+        let mut gossiped_locality: HashMap<String, String> = HashMap::new();
 
         loop {
             tokio::time::sleep(config.gossip_interval).await;
 
             // Get current cluster state
-            let cluster_state = {
+            let (cluster_state, live_nodes) = {
                 let cc = chitchat.lock().await;
-                cc.state_snapshot()
+                let live_nodes: HashSet<String> =
+                    cc.live_nodes().map(|id| id.node_id.clone()).collect();
+                (cc.state_snapshot(), live_nodes)
             };
 
             // Find new nodes
@@ -492,24 +1554,120 @@ impl ClusterManager {
                                 node_id_str.clone(),
                                 peer_addr,
                                 local_node_id.clone(),
+                            )
+                            .with_queue(config.peer_queue_size, config.peer_queue_policy)
+                            .with_framing(FrameOptions::from(&config))
+                            .with_retained(retained.clone());
+                            let peer = peer.spawn(
+                                inbound_callback.clone(),
+                                pending_takeovers.clone(),
+                                clients.clone(),
                             );
-                            let peer = peer.spawn(inbound_callback.clone());
                             peers.insert(node_id_str.clone(), peer);
                         }
                     }
                 }
 
+                // Record liveness and advertised statistics for the cluster view
+                let stats = node_state
+                    .get(KEY_STATS)
+                    .and_then(|json| serde_json::from_str::<NodeStats>(json).ok())
+                    .unwrap_or_default();
+                node_health.insert(
+                    node_id_str.clone(),
+                    NodeHealth {
+                        alive: live_nodes.contains(&node_id_str),
+                        stats,
+                    },
+                );
+
                 // Update peer subscriptions from gossip state
                 if let Some(peer) = peers.get(&node_id_str) {
                     if let Some(subs_json) = node_state.get(KEY_SUBSCRIPTIONS) {
-                        if let Ok(filters) = serde_json::from_str::<Vec<String>>(subs_json) {
-                            debug!(
-                                "Cluster: updating peer '{}' subscriptions from gossip: {:?}",
-                                node_id_str, filters
-                            );
-                            peer.update_remote_subscriptions(filters);
+                        if gossiped_subs.get(&node_id_str).map(String::as_str) != Some(subs_json) {
+                            if let Ok(filters) = serde_json::from_str::<Vec<String>>(subs_json) {
+                                debug!(
+                                    "Cluster: updating peer '{}' subscriptions from gossip: {:?}",
+                                    node_id_str, filters
+                                );
+                                peer.update_remote_subscriptions(filters);
+                            }
+                            gossiped_subs.insert(node_id_str.clone(), subs_json.to_string());
                         }
                     }
+                    if let Some(shared_json) = node_state.get(KEY_SHARED_GROUPS) {
+                        if gossiped_shared.get(&node_id_str).map(String::as_str)
+                            != Some(shared_json)
+                        {
+                            if let Ok(groups) = serde_json::from_str::<
+                                HashMap<String, SharedGroupState>,
+                            >(shared_json)
+                            {
+                                peer.update_shared_groups(groups);
+                            }
+                            gossiped_shared.insert(node_id_str.clone(), shared_json.to_string());
+                        }
+                    }
+                    if let Some(locality_json) = node_state.get(KEY_LOCALITY) {
+                        if gossiped_locality.get(&node_id_str).map(String::as_str)
+                            != Some(locality_json)
+                        {
+                            if let Ok(locality) = serde_json::from_str::<Locality>(locality_json) {
+                                peer.set_locality(locality);
+                            }
+                            gossiped_locality
+                                .insert(node_id_str.clone(), locality_json.to_string());
+                        }
+                    }
+                }
+            }
+
+            // Take over the wills of nodes gossip declared dead. Only the
+            // successor publishes them, and not while partitioned, since the
+            // node may still be serving its clients on the other side.
+            orphaned_nodes.retain(|n| {
+                !live_nodes.contains(n)
+                    && cluster_state
+                        .node_states
+                        .iter()
+                        .any(|ns| &ns.chitchat_id().node_id == n)
+            });
+            let is_successor = successor(
+                live_nodes
+                    .iter()
+                    .map(String::as_str)
+                    .chain(std::iter::once(local_node_id.as_str())),
+            ) == Some(local_node_id.as_str());
+            for node_state in &cluster_state.node_states {
+                let node_id = &node_state.chitchat_id().node_id;
+                if *node_id == local_node_id
+                    || live_nodes.contains(node_id)
+                    || orphaned_nodes.contains(node_id)
+                {
+                    continue;
+                }
+                orphaned_nodes.insert(node_id.clone());
+                if !is_successor || partition.is_partitioned() {
+                    continue;
+                }
+                for (key, value) in node_state.iter_prefix(KEY_WILL_PREFIX) {
+                    // Cleared wills have no value
+                    let Some(will) = ClusterWill::from_gossip(&value.value) else {
+                        continue;
+                    };
+                    let client_id = key[KEY_WILL_PREFIX.len()..].to_string();
+                    info!(
+                        "Cluster: node '{}' is dead, taking over will of client '{}'",
+                        node_id, client_id
+                    );
+                    tokio::spawn(Self::publish_orphaned_will(
+                        client_id,
+                        will,
+                        node_id.clone(),
+                        shared.clone(),
+                        clients.clone(),
+                        inbound_callback.clone(),
+                    ));
                 }
             }
 
@@ -529,7 +1687,13 @@ impl ClusterManager {
             for node_id in dead_nodes {
                 info!("Cluster peer '{}' left the cluster", node_id);
                 known_nodes.remove(&node_id);
+                gossiped_subs.remove(&node_id);
+                gossiped_shared.remove(&node_id);
+                gossiped_locality.remove(&node_id);
+                node_health.remove(&node_id);
+                clients.remove_node(&node_id);
                 if let Some((_, peer)) = peers.remove(&node_id) {
+                    *retired_traffic.lock() += peer.traffic();
                     let _ = peer.stop().await;
                 }
             }
@@ -537,6 +1701,11 @@ impl ClusterManager {
     }
 }
 
+/// Gossip key of a client's replicated will
+fn will_key(client_id: &str) -> String {
+    format!("{}{}", KEY_WILL_PREFIX, client_id)
+}
+
 // ClusterManager is Send + Sync because all its fields are thread-safe
 unsafe impl Send for ClusterManager {}
 unsafe impl Sync for ClusterManager {}
@@ -4,9 +4,14 @@
 //!
 //! # Architecture
 //!
-//! The cluster uses two communication channels:
-//! - **Gossip (UDP via chitchat)**: Node discovery, membership, subscription state
+//! The cluster uses these communication channels:
+//! - **Gossip (UDP via chitchat)**: Node discovery, membership, subscription state,
+//!   client registry (which node each client is connected to), shared
+//!   subscription groups, zone/region labels, per-node statistics for the
+//!   cluster view, replicated client wills for takeover when a node dies
 //! - **Peer TCP**: Direct message forwarding between nodes
+//! - **Raft (optional)**: Strongly consistent metadata (retained messages,
+//!   session ownership, users, ACL roles) with `consistency = "strong"`
 //!
 //! # Usage
 //!
@@ -17,15 +22,44 @@
 //! gossip_addr = "0.0.0.0:7946"
 //! peer_addr = "0.0.0.0:7947"
 //! seeds = ["node1:7946", "node2:7946"]
+//!
+//! # Detect network splits and stop retained writes on the minority side
+//! expected_nodes = 3
+//! partition_mode = "read_only_shared"
+//!
+//! # Or discover nodes dynamically (dns, dns_srv, kubernetes)
+//! [cluster.discovery]
+//! method = "dns"
+//! name = "vibemq-headless.default.svc.cluster.local"
 //! ```
 
+mod discovery;
 mod manager;
+mod partition;
 mod peer;
 mod protocol;
+mod queue;
+mod raft;
+mod routes;
+mod shared;
+mod takeover;
+mod topology;
+mod view;
+mod wills;
 
+pub use discovery::DiscoveryError;
 pub use manager::ClusterManager;
-pub use peer::{ClusterInboundCallback, ClusterPeer};
-pub use protocol::{ClusterMessage, CLUSTER_PROTOCOL_VERSION};
+pub use partition::ClusterStatus;
+pub use peer::{ClusterInboundCallback, ClusterPeer, ClusterRetainedCallback};
+pub use protocol::{
+    ClusterMessage, Codec, VersionRange, CLUSTER_PROTOCOL_VERSION, MIN_CLUSTER_PROTOCOL_VERSION,
+};
+pub use raft::{MetadataApplyCallback, MetadataCommand, MetadataError, MetadataStore};
+pub use shared::{SharedGroupState, SharedRoute};
+pub use takeover::ClusterTakeoverCallback;
+pub use topology::Locality;
+pub use view::{ClusterView, NodeStats, NodeView, TrafficSnapshot};
+pub use wills::ClusterWill;
 
 // Re-export cluster config
 pub use crate::config::ClusterConfig;
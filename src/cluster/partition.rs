@@ -0,0 +1,110 @@
+//! Cluster Partition Detection
+//!
+//! Decides whether this node is on the majority side of a network split.
+//! A node votes for itself, for every connected peer, and for the witness
+//! when it can reach it; it is partitioned while those votes fall short of
+//! a quorum of `expected_nodes` (plus the witness).
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+
+/// Snapshot of this node's view of the cluster
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClusterStatus {
+    /// Whether this node has lost quorum
+    pub partitioned: bool,
+    /// Nodes reachable from here, including this one
+    pub reachable_nodes: usize,
+    /// Configured cluster size (0 = detection disabled)
+    pub expected_nodes: usize,
+    /// Number of times this node has entered a partition
+    pub partition_events: u64,
+}
+
+/// Partition state shared between the monitor task and readers
+#[derive(Default)]
+pub(crate) struct PartitionState {
+    partitioned: AtomicBool,
+    events: AtomicU64,
+}
+
+impl PartitionState {
+    /// Record the result of an evaluation, returning true if the state changed
+    pub(crate) fn update(&self, partitioned: bool) -> bool {
+        let was = self.partitioned.swap(partitioned, Ordering::Relaxed);
+        if partitioned && !was {
+            self.events.fetch_add(1, Ordering::Relaxed);
+        }
+        was != partitioned
+    }
+
+    pub(crate) fn is_partitioned(&self) -> bool {
+        self.partitioned.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn status(&self, reachable_nodes: usize, expected_nodes: usize) -> ClusterStatus {
+        ClusterStatus {
+            partitioned: self.is_partitioned(),
+            reachable_nodes,
+            expected_nodes,
+            partition_events: self.events.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Votes needed to hold quorum
+///
+/// The witness adds one voter, which breaks ties in even-sized clusters.
+pub(crate) fn quorum(expected_nodes: usize, witness: bool) -> usize {
+    (expected_nodes + usize::from(witness)) / 2 + 1
+}
+
+/// Whether the witness accepts a TCP connection within `timeout`
+pub(crate) async fn witness_reachable(addr: &str, timeout: Duration) -> bool {
+    matches!(
+        tokio::time::timeout(timeout, TcpStream::connect(addr)).await,
+        Ok(Ok(_))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quorum() {
+        assert_eq!(quorum(3, false), 2);
+        assert_eq!(quorum(4, false), 3);
+        assert_eq!(quorum(5, false), 3);
+        // A two-node cluster needs the witness to survive losing its peer
+        assert_eq!(quorum(2, false), 2);
+        assert_eq!(quorum(2, true), 2);
+        assert_eq!(quorum(4, true), 3);
+    }
+
+    #[test]
+    fn test_partition_events_counted_on_entry() {
+        let state = PartitionState::default();
+        assert!(!state.update(false));
+        assert!(state.update(true));
+        assert!(!state.update(true));
+        assert!(state.update(false));
+        assert!(state.update(true));
+
+        let status = state.status(1, 3);
+        assert!(status.partitioned);
+        assert_eq!(status.partition_events, 2);
+    }
+
+    #[tokio::test]
+    async fn test_witness_unreachable() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        assert!(witness_reachable(&addr, Duration::from_secs(1)).await);
+        // Once the listener is gone, connections are refused
+        drop(listener);
+        assert!(!witness_reachable(&addr, Duration::from_secs(1)).await);
+    }
+}
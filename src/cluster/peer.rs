@@ -3,36 +3,46 @@
 //! Represents a connection to another node in the cluster.
 //! Implements RemotePeer for unified message forwarding.
 
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU8, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
 use bytes::Bytes;
 use parking_lot::RwLock;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use smallvec::SmallVec;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
 use tracing::{debug, error, info};
 
-use crate::protocol::QoS;
+use crate::broker::RetainedStore;
+use crate::config::PeerQueuePolicy;
+use crate::persistence::{tenant_key, StoredRetainedMessage};
+use crate::protocol::{Properties, QoS};
 use crate::remote::{RemoteError, RemotePeer, RemotePeerStatus};
-use crate::topic::topic_matches_filter;
 
-use super::protocol::{frame_message, read_frame_length, ClusterMessage, CLUSTER_PROTOCOL_VERSION};
+use super::protocol::{
+    decode_hello_reply, fit_read_buf, frame_message, frame_message_with, hello_frame,
+    read_frame_length, ClusterMessage, FrameOptions, VersionRange,
+    CLIENT_REGISTRY_PROTOCOL_VERSION, READ_BUF_SIZE, RETAINED_SYNC_PROTOCOL_VERSION,
+    TAKEOVER_PROTOCOL_VERSION,
+};
+use super::queue::PeerQueue;
+use super::routes::SubscriptionTable;
+use super::shared::{SharedGroupState, SharedGroupTable};
+use super::takeover::{ClientRegistry, PendingTakeovers};
+use super::topology::Locality;
+use super::view::{TrafficSnapshot, TrafficStats};
 
 /// Commands sent to the peer connection task
+///
+/// Publishes go through the peer's `PeerQueue` instead, so they are kept
+/// while the link is down.
 #[derive(Debug)]
 pub enum ClusterCommand {
-    /// Forward a publish message
-    Publish {
-        topic: String,
-        payload: Bytes,
-        qos: QoS,
-        retain: bool,
-        origin_node: String,
-    },
     /// Send subscription sync
     SyncSubscriptions { filters: Vec<String> },
     /// Send subscription update
@@ -40,12 +50,33 @@ pub enum ClusterCommand {
         added: Vec<String>,
         removed: Vec<String>,
     },
+    /// Send the clients that connected to or left this node
+    UpdateClients {
+        claimed: Vec<String>,
+        released: Vec<String>,
+    },
+    /// Ask the peer to give up a client's session
+    TakeOverSession { client_id: String, transfer: bool },
     /// Shutdown the connection
     Shutdown,
 }
 
 /// Callback for messages received from a cluster peer
-pub type ClusterInboundCallback = Arc<dyn Fn(String, Bytes, QoS, bool, String) + Send + Sync>;
+///
+/// Receives topic, payload, QoS, retain flag, origin node, for shared
+/// publishes the `$share` groups this node delivers to, and the message's
+/// MQTT v5 properties. Without groups every local subscriber, shared or not,
+/// receives the message.
+pub type ClusterInboundCallback =
+    Arc<dyn Fn(String, Bytes, QoS, bool, String, Option<Vec<String>>, Properties) + Send + Sync>;
+
+/// Callback for the retained messages a peer sends when its link comes up
+///
+/// Receives the messages keyed by their tenant storage key.
+pub type ClusterRetainedCallback = Arc<dyn Fn(Vec<(String, StoredRetainedMessage)>) + Send + Sync>;
+
+/// Payload bytes at which a `RetainedSync` frame is cut
+const RETAINED_SYNC_BATCH_BYTES: usize = 256 * 1024;
 
 /// A connection to another cluster node
 pub struct ClusterPeer {
@@ -58,7 +89,21 @@ pub struct ClusterPeer {
     /// Command channel for sending operations to the connection task
     command_tx: Option<mpsc::Sender<ClusterCommand>>,
     /// Remote node's subscriptions (updated via gossip)
-    remote_subscriptions: Arc<RwLock<HashSet<String>>>,
+    remote_subscriptions: Arc<RwLock<SubscriptionTable>>,
+    /// Remote node's shared subscription groups (updated via gossip)
+    shared_groups: Arc<RwLock<SharedGroupTable>>,
+    /// Publishes waiting to be written to the peer
+    queue: Arc<PeerQueue>,
+    /// Batching and compression of queued publishes
+    framing: FrameOptions,
+    /// Publishes exchanged with the peer
+    traffic: Arc<TrafficStats>,
+    /// Protocol version negotiated on the last connect (0 = never connected)
+    protocol_version: Arc<AtomicU8>,
+    /// Zone and region of the remote node (updated via gossip)
+    locality: RwLock<Locality>,
+    /// Retained messages sent to the peer when the link comes up
+    retained: Option<Arc<RetainedStore>>,
     /// Our local node ID (for origin tracking)
     local_node_id: String,
 }
@@ -71,11 +116,37 @@ impl ClusterPeer {
             peer_addr,
             status: Arc::new(RwLock::new(RemotePeerStatus::Disconnected)),
             command_tx: None,
-            remote_subscriptions: Arc::new(RwLock::new(HashSet::new())),
+            remote_subscriptions: Arc::new(RwLock::new(SubscriptionTable::default())),
+            shared_groups: Arc::new(RwLock::new(SharedGroupTable::default())),
+            queue: Arc::new(PeerQueue::new(10000, PeerQueuePolicy::default())),
+            framing: FrameOptions::default(),
+            traffic: Arc::new(TrafficStats::default()),
+            protocol_version: Arc::new(AtomicU8::new(0)),
+            locality: RwLock::new(Locality::default()),
+            retained: None,
             local_node_id,
         }
     }
 
+    /// Set the size and overflow policy of the outbound publish queue
+    pub fn with_queue(mut self, capacity: usize, policy: PeerQueuePolicy) -> Self {
+        self.queue = Arc::new(PeerQueue::new(capacity, policy));
+        self
+    }
+
+    /// Batch and compress queued publishes sent to the peer
+    pub fn with_framing(mut self, framing: FrameOptions) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Send the retained messages of `store` to the peer whenever the link
+    /// comes up
+    pub fn with_retained(mut self, store: Option<Arc<RetainedStore>>) -> Self {
+        self.retained = store;
+        self
+    }
+
     /// Get the remote node ID
     pub fn node_id(&self) -> &str {
         &self.node_id
@@ -88,9 +159,146 @@ impl ClusterPeer {
 
     /// Update remote subscriptions (called when gossip state changes)
     pub fn update_remote_subscriptions(&self, filters: Vec<String>) {
-        let mut subs = self.remote_subscriptions.write();
-        subs.clear();
-        subs.extend(filters);
+        if self.remote_subscriptions.write().replace(filters) {
+            debug!(
+                "ClusterPeer '{}': {} remote subscription filters",
+                self.node_id,
+                self.remote_subscription_count()
+            );
+        }
+    }
+
+    /// Apply an incremental subscription update received from the peer
+    pub fn apply_subscription_update(&self, added: Vec<String>, removed: Vec<String>) {
+        self.remote_subscriptions.write().apply(added, removed);
+    }
+
+    /// Number of topic filters the remote node has subscribers for
+    pub fn remote_subscription_count(&self) -> usize {
+        self.remote_subscriptions.read().len()
+    }
+
+    /// Zone and region of the remote node
+    pub fn locality(&self) -> Locality {
+        self.locality.read().clone()
+    }
+
+    /// Update the remote node's zone and region (called when gossip state changes)
+    pub fn set_locality(&self, locality: Locality) {
+        *self.locality.write() = locality;
+    }
+
+    /// Topic filters the remote node has subscribers for
+    pub fn remote_subscriptions(&self) -> Vec<String> {
+        self.remote_subscriptions
+            .read()
+            .filters()
+            .cloned()
+            .collect()
+    }
+
+    /// Protocol version negotiated with the peer, if it ever connected
+    pub fn protocol_version(&self) -> Option<u8> {
+        match self.protocol_version.load(Ordering::Relaxed) {
+            0 => None,
+            version => Some(version),
+        }
+    }
+
+    /// Publishes exchanged with the peer so far
+    pub fn traffic(&self) -> TrafficSnapshot {
+        self.traffic.snapshot()
+    }
+
+    /// Count publishes received from the peer
+    pub(crate) fn record_received(&self, messages: usize, bytes: usize) {
+        self.traffic.record_received(messages, bytes);
+    }
+
+    /// Number of publishes waiting to be sent to the peer
+    pub fn queue_depth(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Publishes dropped from the queue since the previous call
+    pub fn take_queue_drops(&self) -> u64 {
+        self.queue.take_dropped()
+    }
+
+    /// Queue a publish for the peer, counting it if the queue overflows
+    fn enqueue(&self, message: ClusterMessage) {
+        if !self.queue.push(message) {
+            debug!(
+                "ClusterPeer '{}': outbound queue full, dropped a message",
+                self.node_id
+            );
+        }
+    }
+
+    /// Update the remote node's shared subscription groups (from gossip)
+    pub fn update_shared_groups(&self, groups: HashMap<String, SharedGroupState>) {
+        debug!(
+            "ClusterPeer '{}': {} remote shared subscription groups",
+            self.node_id,
+            groups.len()
+        );
+        self.shared_groups.write().replace(groups);
+    }
+
+    /// Shared subscription groups with a filter matching the topic
+    pub(crate) fn shared_groups_matching(&self, topic: &str) -> SmallVec<[(String, u32); 4]> {
+        self.shared_groups.read().matching(topic)
+    }
+
+    /// Account for a message assigned to the remote node's group members
+    pub(crate) fn bump_shared_inflight(&self, group: &str) {
+        self.shared_groups.read().bump(group);
+    }
+
+    /// Forward a publish, assigning it to the peer's members of `groups`
+    pub async fn forward_shared_publish(
+        &self,
+        topic: &str,
+        payload: Bytes,
+        qos: QoS,
+        retain: bool,
+        groups: Vec<String>,
+        properties: &Properties,
+    ) -> Result<(), RemoteError> {
+        self.enqueue(
+            ClusterMessage::SharedPublish {
+                topic: topic.to_string(),
+                payload: payload.to_vec(),
+                qos: qos as u8,
+                retain,
+                origin_node: self.local_node_id.clone(),
+                groups,
+            }
+            .with_properties(properties),
+        );
+        Ok(())
+    }
+
+    /// Forward a publish along with its MQTT v5 properties
+    pub async fn forward_publish_with_properties(
+        &self,
+        topic: &str,
+        payload: Bytes,
+        qos: QoS,
+        retain: bool,
+        properties: &Properties,
+    ) -> Result<(), RemoteError> {
+        self.enqueue(
+            ClusterMessage::Publish {
+                topic: topic.to_string(),
+                payload: payload.to_vec(),
+                qos: qos as u8,
+                retain,
+                origin_node: self.local_node_id.clone(),
+            }
+            .with_properties(properties),
+        );
+        Ok(())
     }
 
     /// Send a subscription sync to this peer
@@ -117,8 +325,47 @@ impl ClusterPeer {
         Ok(())
     }
 
+    /// Send the clients that connected to or left this node
+    pub async fn send_client_update(
+        &self,
+        claimed: Vec<String>,
+        released: Vec<String>,
+    ) -> Result<(), RemoteError> {
+        if let Some(ref tx) = self.command_tx {
+            tx.send(ClusterCommand::UpdateClients { claimed, released })
+                .await
+                .map_err(|_| RemoteError::ConnectionLost("Command channel closed".to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Ask the peer to disconnect a client and give up its session
+    ///
+    /// The reply arrives as a SessionTransfer and completes the request
+    /// registered in `PendingTakeovers`.
+    pub async fn request_takeover(
+        &self,
+        client_id: &str,
+        transfer: bool,
+    ) -> Result<(), RemoteError> {
+        if let Some(ref tx) = self.command_tx {
+            tx.send(ClusterCommand::TakeOverSession {
+                client_id: client_id.to_string(),
+                transfer,
+            })
+            .await
+            .map_err(|_| RemoteError::ConnectionLost("Command channel closed".to_string()))?;
+        }
+        Ok(())
+    }
+
     /// Spawn the connection task and return the peer ready to use
-    pub fn spawn(mut self, inbound_callback: ClusterInboundCallback) -> Arc<Self> {
+    pub(crate) fn spawn(
+        mut self,
+        inbound_callback: ClusterInboundCallback,
+        pending_takeovers: Arc<PendingTakeovers>,
+        clients: Arc<ClientRegistry>,
+    ) -> Arc<Self> {
         let (tx, rx) = mpsc::channel(1000);
         self.command_tx = Some(tx);
 
@@ -127,6 +374,11 @@ impl ClusterPeer {
         let peer_addr = self.peer_addr;
         let status = self.status.clone();
         let remote_subs = self.remote_subscriptions.clone();
+        let queue = self.queue.clone();
+        let framing = self.framing;
+        let traffic = self.traffic.clone();
+        let protocol_version = self.protocol_version.clone();
+        let retained = self.retained.clone();
 
         tokio::spawn(async move {
             Self::connection_loop(
@@ -135,8 +387,15 @@ impl ClusterPeer {
                 peer_addr,
                 status,
                 rx,
+                queue,
+                framing,
+                traffic,
+                protocol_version,
                 inbound_callback,
                 remote_subs,
+                pending_takeovers,
+                clients,
+                retained,
             )
             .await;
         });
@@ -145,14 +404,22 @@ impl ClusterPeer {
     }
 
     /// Run the connection loop with reconnection
+    #[allow(clippy::too_many_arguments)]
     async fn connection_loop(
         node_id: String,
         local_node_id: String,
         peer_addr: SocketAddr,
         status: Arc<RwLock<RemotePeerStatus>>,
         mut command_rx: mpsc::Receiver<ClusterCommand>,
+        queue: Arc<PeerQueue>,
+        framing: FrameOptions,
+        traffic: Arc<TrafficStats>,
+        protocol_version: Arc<AtomicU8>,
         inbound_callback: ClusterInboundCallback,
-        remote_subs: Arc<RwLock<HashSet<String>>>,
+        remote_subs: Arc<RwLock<SubscriptionTable>>,
+        pending_takeovers: Arc<PendingTakeovers>,
+        clients: Arc<ClientRegistry>,
+        retained: Option<Arc<RetainedStore>>,
     ) {
         let mut retry_interval = Duration::from_secs(1);
         let max_retry = Duration::from_secs(30);
@@ -167,8 +434,15 @@ impl ClusterPeer {
                 peer_addr,
                 &status,
                 &mut command_rx,
+                &queue,
+                &framing,
+                &traffic,
+                &protocol_version,
                 &inbound_callback,
                 &remote_subs,
+                &pending_takeovers,
+                &clients,
+                retained.as_deref(),
             )
             .await
             {
@@ -204,14 +478,22 @@ impl ClusterPeer {
     }
 
     /// Connect to the peer and run the message loop
+    #[allow(clippy::too_many_arguments)]
     async fn connect_and_run(
         node_id: &str,
         local_node_id: &str,
         peer_addr: SocketAddr,
         status: &Arc<RwLock<RemotePeerStatus>>,
         command_rx: &mut mpsc::Receiver<ClusterCommand>,
+        queue: &PeerQueue,
+        framing: &FrameOptions,
+        traffic: &TrafficStats,
+        protocol_version: &AtomicU8,
         inbound_callback: &ClusterInboundCallback,
-        remote_subs: &Arc<RwLock<HashSet<String>>>,
+        remote_subs: &Arc<RwLock<SubscriptionTable>>,
+        pending_takeovers: &PendingTakeovers,
+        clients: &ClientRegistry,
+        retained: Option<&RetainedStore>,
     ) -> Result<(), RemoteError> {
         // Connect with timeout
         let stream = tokio::time::timeout(Duration::from_secs(10), TcpStream::connect(peer_addr))
@@ -224,11 +506,7 @@ impl ClusterPeer {
         let (mut read_half, mut write_half) = stream.into_split();
 
         // Send Hello
-        let hello = ClusterMessage::Hello {
-            node_id: local_node_id.to_string(),
-            version: CLUSTER_PROTOCOL_VERSION,
-        };
-        let frame = frame_message(&hello)
+        let frame = hello_frame(local_node_id)
             .map_err(|e| RemoteError::Other(format!("Encode error: {}", e)))?;
         write_half
             .write_all(&frame)
@@ -238,7 +516,7 @@ impl ClusterPeer {
         debug!("ClusterPeer '{}': Hello sent", node_id);
 
         // Wait for HelloAck
-        let mut read_buf = vec![0u8; 65536];
+        let mut read_buf = vec![0u8; READ_BUF_SIZE];
         let n = tokio::time::timeout(Duration::from_secs(10), read_half.read(&mut read_buf))
             .await
             .map_err(|_| RemoteError::Timeout)?
@@ -255,29 +533,75 @@ impl ClusterPeer {
             return Err(RemoteError::Other("Incomplete frame".to_string()));
         }
 
-        let msg = ClusterMessage::decode(&read_buf[4..4 + len as usize])
+        let (msg, batch_limit) = decode_hello_reply(&read_buf[4..4 + len as usize])
             .map_err(|e| RemoteError::Other(format!("Decode error: {}", e)))?;
 
-        match msg {
+        let version = match msg {
             ClusterMessage::HelloAck {
                 node_id: peer_id,
                 version,
             } => {
-                if version != CLUSTER_PROTOCOL_VERSION {
+                if !VersionRange::LOCAL.contains(version) {
                     return Err(RemoteError::Rejected(format!(
-                        "Protocol version mismatch: {} vs {}",
-                        version, CLUSTER_PROTOCOL_VERSION
+                        "peer chose protocol v{}, this node speaks {}",
+                        version,
+                        VersionRange::LOCAL
                     )));
                 }
-                info!("ClusterPeer '{}': Connected (peer_id={})", node_id, peer_id);
+                info!(
+                    "ClusterPeer '{}': Connected (peer_id={}, protocol=v{})",
+                    node_id, peer_id, version
+                );
+                version
+            }
+            ClusterMessage::HelloReject {
+                node_id: peer_id,
+                min_version,
+                max_version,
+            } => {
+                let theirs = VersionRange {
+                    min: min_version,
+                    max: max_version,
+                };
+                return Err(RemoteError::Rejected(format!(
+                    "incompatible cluster protocol: '{}' speaks {}, this node speaks {}; \
+                     upgrade nodes one release at a time",
+                    peer_id,
+                    theirs,
+                    VersionRange::LOCAL
+                )));
             }
             _ => {
                 return Err(RemoteError::Other("Expected HelloAck".to_string()));
             }
-        }
+        };
+        protocol_version.store(version, Ordering::Relaxed);
+        let framing = &framing.for_version(version).with_batch_limit(batch_limit);
 
         *status.write() = RemotePeerStatus::Connected;
 
+        // Flush publishes queued while the link was down
+        Self::send_queued(node_id, queue, framing, traffic, &mut write_half).await?;
+
+        // Send our clients; updates queued meanwhile follow in order
+        let registry = version >= CLIENT_REGISTRY_PROTOCOL_VERSION;
+        if registry {
+            let msg = ClusterMessage::ClientSync {
+                clients: clients.local_clients(),
+            };
+            if let Ok(frame) = frame_message(&msg) {
+                write_half
+                    .write_all(&frame)
+                    .await
+                    .map_err(|e| RemoteError::ConnectionLost(e.to_string()))?;
+            }
+        }
+
+        // Send our retained messages for the peer to fill in the topics it lacks
+        if let Some(retained) = retained.filter(|_| version >= RETAINED_SYNC_PROTOCOL_VERSION) {
+            Self::send_retained(node_id, retained, framing, &mut write_half).await?;
+        }
+
         // Message loop
         let ping_interval = Duration::from_secs(15);
         let mut ping_timer = tokio::time::interval(ping_interval);
@@ -290,23 +614,6 @@ impl ClusterPeer {
                 // Handle commands from the cluster manager
                 Some(cmd) = command_rx.recv() => {
                     match cmd {
-                        ClusterCommand::Publish { topic, payload, qos, retain, origin_node } => {
-                            debug!("ClusterPeer '{}': sending publish '{}' over TCP", node_id, topic);
-                            let msg = ClusterMessage::Publish {
-                                topic: topic.clone(),
-                                payload: payload.to_vec(),
-                                qos: qos as u8,
-                                retain,
-                                origin_node,
-                            };
-                            if let Ok(frame) = frame_message(&msg) {
-                                if let Err(e) = write_half.write_all(&frame).await {
-                                    error!("ClusterPeer '{}': TCP write error: {}", node_id, e);
-                                    return Err(RemoteError::ConnectionLost(e.to_string()));
-                                }
-                                debug!("ClusterPeer '{}': sent {} bytes for '{}'", node_id, frame.len(), topic);
-                            }
-                        }
                         ClusterCommand::SyncSubscriptions { filters } => {
                             let msg = ClusterMessage::SubscriptionSync { filters };
                             if let Ok(frame) = frame_message(&msg) {
@@ -319,6 +626,32 @@ impl ClusterPeer {
                                 let _ = write_half.write_all(&frame).await;
                             }
                         }
+                        ClusterCommand::UpdateClients { claimed, released } => {
+                            if registry {
+                                let msg = ClusterMessage::ClientUpdate { claimed, released };
+                                if let Ok(frame) = frame_message(&msg) {
+                                    let _ = write_half.write_all(&frame).await;
+                                }
+                            }
+                        }
+                        ClusterCommand::TakeOverSession { client_id, transfer } => {
+                            if version < TAKEOVER_PROTOCOL_VERSION {
+                                // The peer can't give the session up, so
+                                // don't keep the connecting client waiting
+                                pending_takeovers.complete(&client_id, None);
+                            } else {
+                                let msg = ClusterMessage::SessionTakeover {
+                                    client_id,
+                                    node_id: local_node_id.to_string(),
+                                    transfer,
+                                };
+                                if let Ok(frame) = frame_message(&msg) {
+                                    if let Err(e) = write_half.write_all(&frame).await {
+                                        return Err(RemoteError::ConnectionLost(e.to_string()));
+                                    }
+                                }
+                            }
+                        }
                         ClusterCommand::Shutdown => {
                             // Send Goodbye
                             let msg = ClusterMessage::Goodbye;
@@ -330,6 +663,11 @@ impl ClusterPeer {
                     }
                 }
 
+                // Send queued publishes
+                _ = queue.notified() => {
+                    Self::send_queued(node_id, queue, framing, traffic, &mut write_half).await?;
+                }
+
                 // Handle incoming messages from peer
                 result = read_half.read(&mut read_buf[buf_offset..]) => {
                     let n = result.map_err(|e| RemoteError::ConnectionLost(e.to_string()))?;
@@ -346,7 +684,11 @@ impl ClusterPeer {
                             break; // Need more data
                         }
 
-                        if let Ok(msg) = ClusterMessage::decode(&read_buf[4..4 + len]) {
+                        let messages = ClusterMessage::decode(&read_buf[4..4 + len])
+                            .and_then(ClusterMessage::unpack)
+                            .unwrap_or_default();
+                        for msg in messages {
+                            let (msg, properties) = msg.take_properties();
                             match msg {
                                 ClusterMessage::Publish { topic, payload, qos, retain, origin_node } => {
                                     // Always process messages from cluster peers
@@ -365,6 +707,23 @@ impl ClusterPeer {
                                         qos_level,
                                         retain,
                                         origin_node,
+                                        None,
+                                        properties,
+                                    );
+                                }
+                                ClusterMessage::SharedPublish { topic, payload, qos, retain, origin_node, groups } => {
+                                    debug!(
+                                        "ClusterPeer '{}': Received shared publish on '{}' (groups={:?})",
+                                        node_id, topic, groups
+                                    );
+                                    inbound_callback(
+                                        topic,
+                                        Bytes::from(payload),
+                                        QoS::from_u8(qos).unwrap_or(QoS::ExactlyOnce),
+                                        retain,
+                                        origin_node,
+                                        Some(groups),
+                                        properties,
                                     );
                                 }
                                 ClusterMessage::SubscriptionSync { filters } => {
@@ -372,22 +731,21 @@ impl ClusterPeer {
                                         "ClusterPeer '{}': Received subscription sync ({} filters)",
                                         node_id, filters.len()
                                     );
-                                    let mut subs = remote_subs.write();
-                                    subs.clear();
-                                    subs.extend(filters);
+                                    remote_subs.write().replace(filters);
                                 }
                                 ClusterMessage::SubscriptionUpdate { added, removed } => {
                                     debug!(
                                         "ClusterPeer '{}': Subscription update (+{}, -{})",
                                         node_id, added.len(), removed.len()
                                     );
-                                    let mut subs = remote_subs.write();
-                                    for f in removed {
-                                        subs.remove(&f);
-                                    }
-                                    for f in added {
-                                        subs.insert(f);
-                                    }
+                                    remote_subs.write().apply(added, removed);
+                                }
+                                ClusterMessage::SessionTransfer { client_id, session } => {
+                                    debug!(
+                                        "ClusterPeer '{}': Received session of '{}' (present={})",
+                                        node_id, client_id, session.is_some()
+                                    );
+                                    pending_takeovers.complete(&client_id, session.map(|s| *s));
                                 }
                                 ClusterMessage::Ping => {
                                     let pong = ClusterMessage::Pong;
@@ -410,6 +768,11 @@ impl ClusterPeer {
                         read_buf.copy_within(4 + len..buf_offset, 0);
                         buf_offset -= 4 + len;
                     }
+
+                    // Make room for frames larger than the buffer (e.g. batches)
+                    if let Err(len) = fit_read_buf(&mut read_buf, buf_offset) {
+                        return Err(RemoteError::Other(format!("Frame too large: {} bytes", len)));
+                    }
                 }
 
                 // Send periodic ping
@@ -424,6 +787,87 @@ impl ClusterPeer {
             }
         }
     }
+
+    /// Write the retained messages of a store in `RetainedSync` frames
+    ///
+    /// The store is read through a snapshot, so publishers keep retaining
+    /// while the frames are written.
+    async fn send_retained<W: AsyncWrite + Unpin>(
+        node_id: &str,
+        retained: &RetainedStore,
+        framing: &FrameOptions,
+        write_half: &mut W,
+    ) -> Result<(), RemoteError> {
+        let mut snapshot = retained.snapshot().peekable();
+        let mut sent = 0;
+        while snapshot.peek().is_some() {
+            let mut messages = Vec::new();
+            let mut bytes = 0;
+            while bytes < RETAINED_SYNC_BATCH_BYTES {
+                let Some(message) = snapshot.next() else {
+                    break;
+                };
+                bytes += message.topic.len() + message.payload.len();
+                let key = tenant_key(message.tenant.as_deref(), &message.topic);
+                messages.push((key, StoredRetainedMessage::from(&message)));
+            }
+            sent += messages.len();
+            let frame = frame_message_with(&ClusterMessage::RetainedSync { messages }, framing)
+                .map_err(|e| RemoteError::Other(format!("Encode error: {}", e)))?;
+            write_half
+                .write_all(&frame)
+                .await
+                .map_err(|e| RemoteError::ConnectionLost(e.to_string()))?;
+        }
+        debug!("ClusterPeer '{}': Sent {} retained messages", node_id, sent);
+        Ok(())
+    }
+
+    /// Write all queued publishes, putting back the ones whose write failed
+    ///
+    /// Up to `batch_size` publishes share a frame, which is compressed when
+    /// it reaches the compression threshold.
+    async fn send_queued<W: AsyncWrite + Unpin>(
+        node_id: &str,
+        queue: &PeerQueue,
+        framing: &FrameOptions,
+        traffic: &TrafficStats,
+        write_half: &mut W,
+    ) -> Result<(), RemoteError> {
+        loop {
+            let mut messages: Vec<_> = queue
+                .pop_batch(framing.batch_size)
+                .into_iter()
+                .map(|msg| msg.for_peer(framing))
+                .collect();
+            let count = messages.len();
+            let msg = match count {
+                0 => return Ok(()),
+                1 => messages.pop().unwrap(),
+                _ => ClusterMessage::Batch { messages },
+            };
+            let frame = match frame_message_with(&msg, framing) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    error!("ClusterPeer '{}': failed to encode publish: {}", node_id, e);
+                    continue;
+                }
+            };
+            if let Err(e) = write_half.write_all(&frame).await {
+                error!("ClusterPeer '{}': TCP write error: {}", node_id, e);
+                match msg {
+                    ClusterMessage::Batch { messages } => {
+                        for msg in messages.into_iter().rev() {
+                            queue.requeue(msg);
+                        }
+                    }
+                    msg => queue.requeue(msg),
+                }
+                return Err(RemoteError::ConnectionLost(e.to_string()));
+            }
+            traffic.record_sent(count, frame.len());
+        }
+    }
 }
 
 #[async_trait]
@@ -443,18 +887,8 @@ impl RemotePeer for ClusterPeer {
         qos: QoS,
         retain: bool,
     ) -> Result<(), RemoteError> {
-        if let Some(ref tx) = self.command_tx {
-            tx.send(ClusterCommand::Publish {
-                topic: topic.to_string(),
-                payload,
-                qos,
-                retain,
-                origin_node: self.local_node_id.clone(),
-            })
+        self.forward_publish_with_properties(topic, payload, qos, retain, &Properties::default())
             .await
-            .map_err(|_| RemoteError::ConnectionLost("Command channel closed".to_string()))?;
-        }
-        Ok(())
     }
 
     async fn notify_subscribe(&self, filter: &str, _qos: QoS) -> Result<(), RemoteError> {
@@ -484,20 +918,8 @@ impl RemotePeer for ClusterPeer {
     }
 
     fn should_forward(&self, topic: &str) -> bool {
-        // Check if the peer has any subscription that matches this topic
-        let subs = self.remote_subscriptions.read();
-        let subs_list: Vec<_> = subs.iter().cloned().collect();
-        let matches = subs
-            .iter()
-            .any(|filter| topic_matches_filter(topic, filter));
-        tracing::debug!(
-            "ClusterPeer '{}': should_forward('{}')={} remote_subs={:?}",
-            self.node_id,
-            topic,
-            matches,
-            subs_list
-        );
-        matches
+        // Only forward if the peer has a subscriber for this topic
+        self.remote_subscriptions.read().matches(topic)
     }
 
     async fn start(&self) -> Result<(), RemoteError> {
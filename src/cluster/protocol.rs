@@ -2,20 +2,199 @@
 //!
 //! Defines the binary protocol used for inter-node communication.
 //! Messages are serialized using bincode for efficiency.
+//!
+//! # Versioning
+//!
+//! Nodes agree on a protocol version when a peer link is opened. The `Hello`
+//! handshake carries the lowest version the sender speaks in its `version`
+//! field, followed by one trailing byte with the highest; nodes that predate
+//! negotiation ignore the trailing byte and require `version` to match their
+//! own. The responder answers with the highest version both sides speak in
+//! `HelloAck`, or refuses with `HelloReject` if the ranges do not overlap.
+//! `HelloAck` carries a trailing big-endian `u16` with the most publishes
+//! the responder accepts in one `Batch`; the initiator batches no more than
+//! that, and not at all if the trailer is missing.
+//!
+//! - 1: base protocol
+//! - 2: `Batch` and `Compressed` frames
+//! - 3: `WithProperties` frames
+//! - 4: `ClientSync` and `ClientUpdate` messages
+//! - 5: `SessionTakeover`, `SessionTransfer` and `SharedPublish` messages
+//! - 6: `RetainedSync` messages
+//!
+//! Messages a peer's version lacks are never sent to it: publishes are
+//! rewritten with [`ClusterMessage::for_peer`], the rest are skipped.
 
+use bincode::error::{DecodeError, EncodeError};
 use bincode::{Decode, Encode};
 
-/// Protocol version for compatibility checking
-pub const CLUSTER_PROTOCOL_VERSION: u8 = 1;
+use crate::config::{ClusterCompression, ClusterConfig};
+use crate::persistence::{StoredProperties, StoredRetainedMessage, StoredSession};
+use crate::protocol::Properties;
+
+/// Highest protocol version this node speaks
+pub const CLUSTER_PROTOCOL_VERSION: u8 = 6;
+
+/// Lowest protocol version this node speaks
+///
+/// Every version back to the base protocol, whose messages keep their
+/// encoding, so any older release can be upgraded from.
+pub const MIN_CLUSTER_PROTOCOL_VERSION: u8 = 1;
+
+/// First protocol version with `Batch` and `Compressed` frames
+pub const FRAMING_PROTOCOL_VERSION: u8 = 2;
+
+/// First protocol version with `WithProperties` frames
+pub const PROPERTIES_PROTOCOL_VERSION: u8 = 3;
+
+/// First protocol version with `ClientSync` and `ClientUpdate` messages
+pub const CLIENT_REGISTRY_PROTOCOL_VERSION: u8 = 4;
+
+/// First protocol version with `SessionTakeover` and `SessionTransfer`
+pub const TAKEOVER_PROTOCOL_VERSION: u8 = 5;
+
+/// First protocol version with `SharedPublish` messages
+pub const SHARED_PUBLISH_PROTOCOL_VERSION: u8 = 5;
+
+/// First protocol version with `RetainedSync` messages
+pub const RETAINED_SYNC_PROTOCOL_VERSION: u8 = 6;
+
+/// Largest frame (and decompressed message) accepted from a peer
+pub const MAX_FRAME_SIZE: u32 = 256 * 1024 * 1024;
+
+/// Size a peer link's read buffer starts at and shrinks back to
+pub const READ_BUF_SIZE: usize = 64 * 1024;
+
+/// zstd compression level for peer traffic
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+/// Compression codec of a `ClusterMessage::Compressed` frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum Codec {
+    /// LZ4 block with the decompressed size prepended
+    Lz4,
+    /// zstd frame
+    Zstd,
+}
+
+/// Range of protocol versions a node speaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionRange {
+    pub min: u8,
+    pub max: u8,
+}
+
+impl VersionRange {
+    /// Versions this node speaks
+    pub const LOCAL: Self = Self {
+        min: MIN_CLUSTER_PROTOCOL_VERSION,
+        max: CLUSTER_PROTOCOL_VERSION,
+    };
+
+    /// Whether the version is in the range
+    pub fn contains(&self, version: u8) -> bool {
+        (self.min..=self.max).contains(&version)
+    }
+
+    /// Highest version both ranges contain
+    pub fn negotiate(&self, other: &Self) -> Option<u8> {
+        let version = self.max.min(other.max);
+        (version >= self.min.max(other.min)).then_some(version)
+    }
+}
+
+impl std::fmt::Display for VersionRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.min == self.max {
+            write!(f, "v{}", self.min)
+        } else {
+            write!(f, "v{}-v{}", self.min, self.max)
+        }
+    }
+}
+
+/// How queued publishes are packed into frames for a peer
+#[derive(Debug, Clone, Copy)]
+pub struct FrameOptions {
+    /// Maximum publishes per frame (1 = no batching)
+    pub batch_size: usize,
+    /// Codec for frames at or above `compression_threshold`
+    pub compression: ClusterCompression,
+    /// Smallest encoded frame (in bytes) that is compressed
+    pub compression_threshold: usize,
+    /// Whether publishes may carry their MQTT v5 properties
+    pub message_properties: bool,
+    /// Whether publishes may name the shared subscription groups the peer
+    /// delivers to
+    pub shared_groups: bool,
+}
+
+impl Default for FrameOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 1,
+            compression: ClusterCompression::None,
+            compression_threshold: 0,
+            message_properties: false,
+            shared_groups: false,
+        }
+    }
+}
+
+impl From<&ClusterConfig> for FrameOptions {
+    fn from(config: &ClusterConfig) -> Self {
+        Self {
+            batch_size: config.batch_size,
+            compression: config.compression,
+            compression_threshold: config.compression_threshold,
+            message_properties: true,
+            shared_groups: true,
+        }
+    }
+}
+
+impl FrameOptions {
+    /// Batch no more publishes per frame than the peer accepts
+    pub fn with_batch_limit(self, limit: usize) -> Self {
+        Self {
+            batch_size: self.batch_size.min(limit).max(1),
+            ..self
+        }
+    }
+
+    /// Options usable with a peer speaking the given protocol version
+    pub fn for_version(self, version: u8) -> Self {
+        let shared_groups = self.shared_groups && version >= SHARED_PUBLISH_PROTOCOL_VERSION;
+        if version >= FRAMING_PROTOCOL_VERSION {
+            Self {
+                message_properties: self.message_properties
+                    && version >= PROPERTIES_PROTOCOL_VERSION,
+                shared_groups,
+                ..self
+            }
+        } else {
+            Self {
+                shared_groups,
+                ..Self::default()
+            }
+        }
+    }
+}
 
 /// Messages exchanged between cluster nodes over TCP
+///
+/// bincode encodes the variant index, so variants are only ever appended;
+/// the first eight are the base protocol.
 #[derive(Debug, Clone, Encode, Decode)]
 pub enum ClusterMessage {
     /// Handshake sent when connecting to a peer
+    ///
+    /// Framed with [`hello_frame`], which appends the highest version.
     Hello {
         /// Node ID of the sender
         node_id: String,
-        /// Protocol version
+        /// Lowest protocol version the sender speaks
         version: u8,
     },
 
@@ -23,7 +202,7 @@ pub enum ClusterMessage {
     HelloAck {
         /// Node ID of the responder
         node_id: String,
-        /// Protocol version
+        /// Negotiated protocol version
         version: u8,
     },
 
@@ -63,6 +242,102 @@ pub enum ClusterMessage {
 
     /// Graceful disconnect notification
     Goodbye,
+
+    /// Handshake refusal: the nodes have no protocol version in common
+    HelloReject {
+        /// Node ID of the responder
+        node_id: String,
+        /// Lowest protocol version the responder speaks
+        min_version: u8,
+        /// Highest protocol version the responder speaks
+        max_version: u8,
+    },
+
+    /// A Publish or SharedPublish with the MQTT v5 properties of the message
+    ///
+    /// Publishes without properties are sent bare, so they stay readable by
+    /// peers that predate this frame.
+    WithProperties {
+        /// The wrapped Publish or SharedPublish
+        message: Box<ClusterMessage>,
+        /// Response Topic, Correlation Data, User Properties and the like
+        properties: StoredProperties,
+    },
+
+    /// Every client connected to the sending node, sent when a link comes up
+    ClientSync {
+        /// Client IDs
+        clients: Vec<String>,
+    },
+
+    /// Clients that connected to or left the sending node
+    ClientUpdate {
+        /// Clients now owned by the sender
+        claimed: Vec<String>,
+        /// Clients the sender no longer owns
+        released: Vec<String>,
+    },
+
+    /// Ask the node owning a client's session to give it up
+    SessionTakeover {
+        /// Client taking its session over
+        client_id: String,
+        /// Node the client reconnected to
+        node_id: String,
+        /// Whether the session state should be sent back (false on clean start)
+        transfer: bool,
+    },
+
+    /// Reply to SessionTakeover with the client's persistent session
+    SessionTransfer {
+        /// Client whose session was taken over
+        client_id: String,
+        /// Session state, if one was requested and the node still had it
+        session: Option<Box<StoredSession>>,
+    },
+
+    /// Forward a published message with shared subscription groups assigned
+    ///
+    /// The receiving node delivers to its non-shared subscribers and to its
+    /// members of exactly the listed `$share` groups.
+    SharedPublish {
+        /// Topic of the message
+        topic: String,
+        /// Message payload
+        payload: Vec<u8>,
+        /// QoS level (0, 1, or 2)
+        qos: u8,
+        /// Retain flag
+        retain: bool,
+        /// Origin node ID (to prevent loops)
+        origin_node: String,
+        /// Shared subscription groups the receiving node delivers to
+        groups: Vec<String>,
+    },
+
+    /// Several forwarded publishes sent as one frame
+    Batch {
+        /// Publish or SharedPublish messages, in send order
+        messages: Vec<ClusterMessage>,
+    },
+
+    /// Another message, encoded and then compressed
+    Compressed {
+        /// Compression codec
+        codec: Codec,
+        /// Compressed encoding of the wrapped message
+        data: Vec<u8>,
+    },
+
+    /// Retained messages of the sending node, sent in several of these when
+    /// a link comes up
+    ///
+    /// The receiving node stores those whose topics it holds no retained
+    /// message for, so it does not route them or replace newer messages.
+    RetainedSync {
+        /// Messages keyed by their tenant storage key
+        messages: Vec<(String, StoredRetainedMessage)>,
+    },
 }
 
 impl ClusterMessage {
@@ -76,6 +351,80 @@ impl ClusterMessage {
         bincode::decode_from_slice(data, bincode::config::standard()).map(|(msg, _)| msg)
     }
 
+    /// Decompress and split the message into the messages it carries
+    pub fn unpack(self) -> Result<Vec<Self>, DecodeError> {
+        match self {
+            ClusterMessage::Compressed { codec, data } => {
+                Self::decode(&decompress(codec, &data)?)?.unpack()
+            }
+            ClusterMessage::Batch { messages } => Ok(messages),
+            msg => Ok(vec![msg]),
+        }
+    }
+
+    /// Attach the MQTT v5 properties of a publish, if it has any
+    pub fn with_properties(self, properties: &Properties) -> Self {
+        let properties = StoredProperties::from(properties);
+        if properties.is_empty() {
+            self
+        } else {
+            ClusterMessage::WithProperties {
+                message: Box::new(self),
+                properties,
+            }
+        }
+    }
+
+    /// Rewrite a queued publish into messages the peer understands
+    ///
+    /// Properties are dropped for peers without `WithProperties`, and a
+    /// `SharedPublish` becomes a plain `Publish` for peers that pick their
+    /// own shared group members.
+    pub fn for_peer(self, framing: &FrameOptions) -> Self {
+        let (message, properties) = match self {
+            ClusterMessage::WithProperties {
+                message,
+                properties,
+            } => (*message, framing.message_properties.then_some(properties)),
+            msg => (msg, None),
+        };
+        let message = match message {
+            ClusterMessage::SharedPublish {
+                topic,
+                payload,
+                qos,
+                retain,
+                origin_node,
+                ..
+            } if !framing.shared_groups => ClusterMessage::Publish {
+                topic,
+                payload,
+                qos,
+                retain,
+                origin_node,
+            },
+            msg => msg,
+        };
+        match properties {
+            Some(properties) => ClusterMessage::WithProperties {
+                message: Box::new(message),
+                properties,
+            },
+            None => message,
+        }
+    }
+
+    /// Split a message into the message itself and its MQTT v5 properties
+    pub fn take_properties(self) -> (Self, Properties) {
+        match self {
+            ClusterMessage::WithProperties {
+                message,
+                properties,
+            } => (*message, properties.into()),
+            msg => (msg, Properties::default()),
+        }
+    }
+
     /// Get the message type name for logging
     pub fn type_name(&self) -> &'static str {
         match self {
@@ -87,6 +436,16 @@ impl ClusterMessage {
             ClusterMessage::Ping => "Ping",
             ClusterMessage::Pong => "Pong",
             ClusterMessage::Goodbye => "Goodbye",
+            ClusterMessage::HelloReject { .. } => "HelloReject",
+            ClusterMessage::WithProperties { .. } => "WithProperties",
+            ClusterMessage::ClientSync { .. } => "ClientSync",
+            ClusterMessage::ClientUpdate { .. } => "ClientUpdate",
+            ClusterMessage::SessionTakeover { .. } => "SessionTakeover",
+            ClusterMessage::SessionTransfer { .. } => "SessionTransfer",
+            ClusterMessage::SharedPublish { .. } => "SharedPublish",
+            ClusterMessage::Batch { .. } => "Batch",
+            ClusterMessage::Compressed { .. } => "Compressed",
+            ClusterMessage::RetainedSync { .. } => "RetainedSync",
         }
     }
 }
@@ -103,6 +462,140 @@ pub fn frame_message(msg: &ClusterMessage) -> Result<Vec<u8>, bincode::error::En
     Ok(frame)
 }
 
+/// Frame the `Hello` handshake for this node
+pub fn hello_frame(node_id: &str) -> Result<Vec<u8>, EncodeError> {
+    let hello = ClusterMessage::Hello {
+        node_id: node_id.to_string(),
+        version: VersionRange::LOCAL.min,
+    };
+    let mut payload = hello.encode()?;
+    payload.push(VersionRange::LOCAL.max);
+
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// Decode a `Hello` handshake into the sender's node ID and version range
+///
+/// Returns `None` if the message is not a `Hello`. Nodes without version
+/// negotiation send no trailing byte and speak exactly `version`.
+pub fn decode_hello(data: &[u8]) -> Result<Option<(String, VersionRange)>, DecodeError> {
+    let (msg, used): (ClusterMessage, usize) =
+        bincode::decode_from_slice(data, bincode::config::standard())?;
+    match msg {
+        ClusterMessage::Hello { node_id, version } => {
+            let max = data.get(used).copied().unwrap_or(version).max(version);
+            Ok(Some((node_id, VersionRange { min: version, max })))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Frame the `HelloAck` handshake reply, advertising the accepted batch size
+pub fn hello_ack_frame(
+    node_id: &str,
+    version: u8,
+    batch_size: usize,
+) -> Result<Vec<u8>, EncodeError> {
+    let ack = ClusterMessage::HelloAck {
+        node_id: node_id.to_string(),
+        version,
+    };
+    let mut payload = ack.encode()?;
+    payload.extend_from_slice(&(batch_size.min(u16::MAX as usize) as u16).to_be_bytes());
+
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// Decode a handshake reply and the batch size a `HelloAck` advertises
+///
+/// Replies without the trailer accept no batches, so the batch size is 1.
+pub fn decode_hello_reply(data: &[u8]) -> Result<(ClusterMessage, usize), DecodeError> {
+    let (msg, used): (ClusterMessage, usize) =
+        bincode::decode_from_slice(data, bincode::config::standard())?;
+    let batch_size = match (&msg, data.get(used..used + 2)) {
+        (ClusterMessage::HelloAck { .. }, Some(b)) => u16::from_be_bytes([b[0], b[1]]).max(1),
+        _ => 1,
+    };
+    Ok((msg, batch_size as usize))
+}
+
+/// Frame a message, compressing it if it is large enough
+///
+/// The compressed form is only used when it is actually smaller.
+pub fn frame_message_with(
+    msg: &ClusterMessage,
+    options: &FrameOptions,
+) -> Result<Vec<u8>, EncodeError> {
+    let codec = match options.compression {
+        ClusterCompression::None => return frame_message(msg),
+        ClusterCompression::Lz4 => Codec::Lz4,
+        ClusterCompression::Zstd => Codec::Zstd,
+    };
+
+    let encoded = msg.encode()?;
+    if encoded.len() < options.compression_threshold {
+        return frame_message(msg);
+    }
+
+    let data = match codec {
+        Codec::Lz4 => lz4_flex::compress_prepend_size(&encoded),
+        Codec::Zstd => zstd_compress(&encoded)?,
+    };
+    if data.len() >= encoded.len() {
+        return frame_message(msg);
+    }
+    frame_message(&ClusterMessage::Compressed { codec, data })
+}
+
+/// Decompress the payload of a `Compressed` message
+fn decompress(codec: Codec, data: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let max = MAX_FRAME_SIZE as usize;
+    match codec {
+        Codec::Lz4 => {
+            let (size, rest) = lz4_flex::block::uncompressed_size(data)
+                .map_err(|e| DecodeError::OtherString(e.to_string()))?;
+            if size > max {
+                return Err(DecodeError::OtherString(format!(
+                    "decompressed size {} exceeds limit",
+                    size
+                )));
+            }
+            lz4_flex::decompress(rest, size).map_err(|e| DecodeError::OtherString(e.to_string()))
+        }
+        Codec::Zstd => zstd_decompress(data, max),
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_compress(data: &[u8]) -> Result<Vec<u8>, EncodeError> {
+    zstd::bulk::compress(data, ZSTD_LEVEL).map_err(|e| EncodeError::OtherString(e.to_string()))
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_decompress(data: &[u8], max: usize) -> Result<Vec<u8>, DecodeError> {
+    zstd::bulk::decompress(data, max).map_err(|e| DecodeError::OtherString(e.to_string()))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_compress(_data: &[u8]) -> Result<Vec<u8>, EncodeError> {
+    Err(EncodeError::Other(
+        "zstd requires building with the zstd feature",
+    ))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_decompress(_data: &[u8], _max: usize) -> Result<Vec<u8>, DecodeError> {
+    Err(DecodeError::Other(
+        "zstd requires building with the zstd feature",
+    ))
+}
+
 /// Read frame length from bytes (returns None if not enough data)
 pub fn read_frame_length(data: &[u8]) -> Option<u32> {
     if data.len() < 4 {
@@ -111,6 +604,31 @@ pub fn read_frame_length(data: &[u8]) -> Option<u32> {
     Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
 }
 
+/// Size a peer link's read buffer for the frame at its start
+///
+/// `filled` bytes of the buffer hold unprocessed data. The buffer only
+/// doubles once it is full, so a peer announcing a large frame it never
+/// sends cannot make this node allocate it up front, and it shrinks back
+/// to `READ_BUF_SIZE` once the large frame has been processed. Returns the
+/// announced length of a frame over `MAX_FRAME_SIZE` as the error.
+pub fn fit_read_buf(read_buf: &mut Vec<u8>, filled: usize) -> Result<(), u32> {
+    let needed = match read_frame_length(&read_buf[..filled]) {
+        Some(len) if len > MAX_FRAME_SIZE => return Err(len),
+        Some(len) => 4 + len as usize,
+        None => 0,
+    };
+    if needed > read_buf.len() {
+        if filled == read_buf.len() {
+            let size = (read_buf.len() * 2).min(needed);
+            read_buf.resize(size, 0);
+        }
+    } else if read_buf.len() > READ_BUF_SIZE && needed.max(filled) <= READ_BUF_SIZE {
+        read_buf.truncate(READ_BUF_SIZE);
+        read_buf.shrink_to_fit();
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -134,6 +652,170 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_hello_frame_version_range() {
+        let frame = hello_frame("node1").unwrap();
+        let len = read_frame_length(&frame).unwrap() as usize;
+        let (node_id, range) = decode_hello(&frame[4..4 + len]).unwrap().unwrap();
+        assert_eq!(node_id, "node1");
+        assert_eq!(range, VersionRange::LOCAL);
+
+        // Nodes without negotiation ignore the trailing byte and see the
+        // lowest version
+        match ClusterMessage::decode(&frame[4..4 + len]).unwrap() {
+            ClusterMessage::Hello { version, .. } => {
+                assert_eq!(version, MIN_CLUSTER_PROTOCOL_VERSION)
+            }
+            _ => panic!("Wrong message type"),
+        }
+
+        // A legacy Hello speaks exactly its version
+        let legacy = ClusterMessage::Hello {
+            node_id: "old".to_string(),
+            version: 1,
+        }
+        .encode()
+        .unwrap();
+        let (_, range) = decode_hello(&legacy).unwrap().unwrap();
+        assert_eq!(range, VersionRange { min: 1, max: 1 });
+
+        assert!(decode_hello(&ClusterMessage::Ping.encode().unwrap())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_hello_ack_batch_size() {
+        let frame = hello_ack_frame("node2", CLUSTER_PROTOCOL_VERSION, 32).unwrap();
+        let len = read_frame_length(&frame).unwrap() as usize;
+        let (msg, batch_size) = decode_hello_reply(&frame[4..4 + len]).unwrap();
+        assert!(
+            matches!(msg, ClusterMessage::HelloAck { version, .. } if version == CLUSTER_PROTOCOL_VERSION)
+        );
+        assert_eq!(batch_size, 32);
+
+        // A HelloAck without the trailer accepts no batches
+        let bare = ClusterMessage::HelloAck {
+            node_id: "old".to_string(),
+            version: 1,
+        }
+        .encode()
+        .unwrap();
+        assert_eq!(decode_hello_reply(&bare).unwrap().1, 1);
+
+        let framing = FrameOptions {
+            batch_size: 64,
+            ..FrameOptions::default()
+        };
+        assert_eq!(framing.with_batch_limit(32).batch_size, 32);
+        assert_eq!(framing.with_batch_limit(128).batch_size, 64);
+    }
+
+    #[test]
+    fn test_read_buf_grows_and_shrinks() {
+        let mut read_buf = vec![0u8; READ_BUF_SIZE];
+        let len = (READ_BUF_SIZE * 3) as u32;
+        read_buf[..4].copy_from_slice(&len.to_be_bytes());
+
+        // Not grown before the frame fills the buffer
+        fit_read_buf(&mut read_buf, 4).unwrap();
+        assert_eq!(read_buf.len(), READ_BUF_SIZE);
+
+        // Doubles as it fills, up to the frame size
+        fit_read_buf(&mut read_buf, READ_BUF_SIZE).unwrap();
+        assert_eq!(read_buf.len(), READ_BUF_SIZE * 2);
+        let filled = read_buf.len();
+        fit_read_buf(&mut read_buf, filled).unwrap();
+        assert_eq!(read_buf.len(), 4 + len as usize);
+
+        // Shrinks once the large frame is consumed
+        fit_read_buf(&mut read_buf, 0).unwrap();
+        assert_eq!(read_buf.len(), READ_BUF_SIZE);
+
+        read_buf[..4].copy_from_slice(&(MAX_FRAME_SIZE + 1).to_be_bytes());
+        assert_eq!(fit_read_buf(&mut read_buf, 4), Err(MAX_FRAME_SIZE + 1));
+    }
+
+    #[test]
+    fn test_version_negotiation() {
+        let local = VersionRange { min: 1, max: 2 };
+        assert_eq!(local.negotiate(&VersionRange { min: 1, max: 1 }), Some(1));
+        assert_eq!(local.negotiate(&VersionRange { min: 2, max: 3 }), Some(2));
+        assert_eq!(local.negotiate(&VersionRange { min: 3, max: 4 }), None);
+        assert!(local.contains(2));
+        assert!(!local.contains(3));
+        assert_eq!(VersionRange { min: 3, max: 4 }.to_string(), "v3-v4");
+
+        let framing = FrameOptions {
+            batch_size: 64,
+            compression: ClusterCompression::Lz4,
+            compression_threshold: 1024,
+            message_properties: true,
+            shared_groups: true,
+        };
+        assert_eq!(framing.for_version(1).batch_size, 1);
+        assert_eq!(framing.for_version(1).compression, ClusterCompression::None);
+        assert_eq!(framing.for_version(2).batch_size, 64);
+        assert!(!framing.for_version(2).message_properties);
+        assert!(framing.for_version(3).message_properties);
+        assert!(!framing.for_version(4).shared_groups);
+        assert!(framing.for_version(5).shared_groups);
+    }
+
+    #[test]
+    fn test_base_protocol_encoding() {
+        // Variant indexes of the base protocol never change
+        let index = |msg: ClusterMessage| msg.encode().unwrap()[0];
+        assert_eq!(index(ClusterMessage::Ping), 5);
+        assert_eq!(index(ClusterMessage::Pong), 6);
+        assert_eq!(index(ClusterMessage::Goodbye), 7);
+        assert_eq!(
+            index(ClusterMessage::SubscriptionSync {
+                filters: Vec::new()
+            }),
+            3
+        );
+    }
+
+    #[test]
+    fn test_for_peer_downgrades_publishes() {
+        let shared = ClusterMessage::SharedPublish {
+            topic: "t".to_string(),
+            payload: vec![1],
+            qos: 1,
+            retain: false,
+            origin_node: "node1".to_string(),
+            groups: vec!["g1".to_string()],
+        };
+        let properties = Properties {
+            content_type: Some("text/plain".to_string()),
+            ..Properties::default()
+        };
+        let msg = shared.with_properties(&properties);
+        let framing = FrameOptions {
+            message_properties: true,
+            shared_groups: true,
+            ..FrameOptions::default()
+        };
+
+        // Peers speaking the latest version get the message as is
+        let (kept, kept_properties) = msg.clone().for_peer(&framing).take_properties();
+        assert!(matches!(kept, ClusterMessage::SharedPublish { .. }));
+        assert_eq!(kept_properties.content_type.as_deref(), Some("text/plain"));
+
+        // A v3 peer keeps the properties but picks its own group members
+        let v3 = framing.for_version(3);
+        let (plain, plain_properties) = msg.clone().for_peer(&v3).take_properties();
+        assert!(matches!(plain, ClusterMessage::Publish { .. }));
+        assert_eq!(plain_properties.content_type.as_deref(), Some("text/plain"));
+
+        // A v1 peer gets a bare Publish
+        assert!(matches!(
+            msg.for_peer(&framing.for_version(1)),
+            ClusterMessage::Publish { .. }
+        ));
+    }
+
     #[test]
     fn test_encode_decode_publish() {
         let msg = ClusterMessage::Publish {
@@ -165,6 +847,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_encode_decode_shared_publish() {
+        let msg = ClusterMessage::SharedPublish {
+            topic: "jobs/build".to_string(),
+            payload: b"42".to_vec(),
+            qos: 1,
+            retain: false,
+            origin_node: "node1".to_string(),
+            groups: vec!["workers".to_string()],
+        };
+
+        let encoded = msg.encode().unwrap();
+        let decoded = ClusterMessage::decode(&encoded).unwrap();
+
+        match decoded {
+            ClusterMessage::SharedPublish { topic, groups, .. } => {
+                assert_eq!(topic, "jobs/build");
+                assert_eq!(groups, vec!["workers"]);
+            }
+            _ => panic!("Wrong message type"),
+        }
+    }
+
     #[test]
     fn test_encode_decode_subscription_sync() {
         let msg = ClusterMessage::SubscriptionSync {
@@ -184,6 +889,40 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_encode_decode_session_transfer() {
+        let msg = ClusterMessage::SessionTransfer {
+            client_id: "client1".to_string(),
+            session: Some(Box::new(StoredSession {
+                client_id: "client1".to_string(),
+                protocol_version: 5,
+                session_expiry_interval: 3600,
+                keep_alive: 60,
+                subscriptions: Vec::new(),
+                pending_messages: Vec::new(),
+                spilled: Vec::new(),
+                inflight_outgoing: Vec::new(),
+                inflight_incoming: Vec::new(),
+                will: None,
+                disconnected_at_secs: None,
+                next_packet_id: 1,
+            })),
+        };
+
+        let encoded = msg.encode().unwrap();
+        let decoded = ClusterMessage::decode(&encoded).unwrap();
+
+        match decoded {
+            ClusterMessage::SessionTransfer { client_id, session } => {
+                assert_eq!(client_id, "client1");
+                let session = session.unwrap();
+                assert_eq!(session.client_id, "client1");
+                assert_eq!(session.session_expiry_interval, 3600);
+            }
+            _ => panic!("Wrong message type"),
+        }
+    }
+
     #[test]
     fn test_frame_message() {
         let msg = ClusterMessage::Ping;
@@ -198,6 +937,108 @@ mod tests {
         assert!(matches!(decoded, ClusterMessage::Ping));
     }
 
+    fn publish(n: u8) -> ClusterMessage {
+        ClusterMessage::Publish {
+            topic: "sensors/temp".to_string(),
+            payload: vec![n; 256],
+            qos: 0,
+            retain: false,
+            origin_node: "node1".to_string(),
+        }
+    }
+
+    fn payloads(messages: Vec<ClusterMessage>) -> Vec<u8> {
+        messages
+            .into_iter()
+            .map(|msg| match msg {
+                ClusterMessage::Publish { payload, .. } => payload[0],
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_batch_unpack() {
+        let batch = ClusterMessage::Batch {
+            messages: vec![publish(1), publish(2), publish(3)],
+        };
+        let decoded = ClusterMessage::decode(&batch.encode().unwrap()).unwrap();
+        assert_eq!(payloads(decoded.unpack().unwrap()), vec![1, 2, 3]);
+
+        // Plain messages unpack to themselves
+        assert_eq!(payloads(publish(7).unpack().unwrap()), vec![7]);
+    }
+
+    #[test]
+    fn test_compressed_frames() {
+        let batch = ClusterMessage::Batch {
+            messages: vec![publish(1), publish(2)],
+        };
+
+        let compressions = [ClusterCompression::Lz4, ClusterCompression::Zstd];
+        for compression in compressions.into_iter().filter(|c| c.is_available()) {
+            let options = FrameOptions {
+                batch_size: 2,
+                compression,
+                compression_threshold: 64,
+                message_properties: false,
+                shared_groups: false,
+            };
+            let frame = frame_message_with(&batch, &options).unwrap();
+            let len = read_frame_length(&frame).unwrap() as usize;
+            assert_eq!(len, frame.len() - 4);
+            assert!(len < batch.encode().unwrap().len());
+
+            let decoded = ClusterMessage::decode(&frame[4..]).unwrap();
+            assert!(matches!(decoded, ClusterMessage::Compressed { .. }));
+            assert_eq!(payloads(decoded.unpack().unwrap()), vec![1, 2]);
+        }
+    }
+
+    #[test]
+    fn test_small_frames_not_compressed() {
+        let options = FrameOptions {
+            batch_size: 1,
+            compression: ClusterCompression::Lz4,
+            compression_threshold: 4096,
+            message_properties: false,
+            shared_groups: false,
+        };
+        let frame = frame_message_with(&publish(1), &options).unwrap();
+        assert_eq!(frame, frame_message(&publish(1)).unwrap());
+    }
+
+    #[test]
+    fn test_publish_properties_round_trip() {
+        let properties = Properties {
+            response_topic: Some("replies/1".to_string()),
+            correlation_data: Some(bytes::Bytes::from_static(b"req-1")),
+            ..Default::default()
+        };
+        let msg = publish(1).with_properties(&properties);
+        assert!(matches!(msg, ClusterMessage::WithProperties { .. }));
+
+        let batch = ClusterMessage::Batch {
+            messages: vec![msg, publish(2)],
+        };
+        let mut messages = ClusterMessage::decode(&batch.encode().unwrap())
+            .unwrap()
+            .unpack()
+            .unwrap()
+            .into_iter()
+            .map(ClusterMessage::take_properties);
+        let (first, properties) = messages.next().unwrap();
+        assert_eq!(payloads(vec![first]), vec![1]);
+        assert_eq!(properties.response_topic.as_deref(), Some("replies/1"));
+        assert_eq!(properties.correlation_data.as_deref(), Some(&b"req-1"[..]));
+        let (_, properties) = messages.next().unwrap();
+        assert!(properties.response_topic.is_none());
+
+        // Publishes without properties are not wrapped
+        let bare = publish(3).with_properties(&Properties::default());
+        assert!(matches!(bare, ClusterMessage::Publish { .. }));
+    }
+
     #[test]
     fn test_type_name() {
         assert_eq!(ClusterMessage::Ping.type_name(), "Ping");
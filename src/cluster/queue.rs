@@ -0,0 +1,185 @@
+//! Cluster Peer Queue
+//!
+//! Bounded buffer of publishes waiting to be written to a peer. Messages
+//! survive a broken link and are sent once the peer reconnects; when the
+//! queue is full, the configured policy decides which message is dropped.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+
+use crate::config::PeerQueuePolicy;
+
+use super::protocol::ClusterMessage;
+
+/// Outbound publish queue of one cluster peer
+pub(crate) struct PeerQueue {
+    messages: Mutex<VecDeque<ClusterMessage>>,
+    capacity: usize,
+    policy: PeerQueuePolicy,
+    /// Wakes the connection task when messages are queued
+    notify: Notify,
+    /// Messages dropped since the last `take_dropped`
+    dropped: AtomicU64,
+}
+
+impl PeerQueue {
+    pub(crate) fn new(capacity: usize, policy: PeerQueuePolicy) -> Self {
+        Self {
+            messages: Mutex::new(VecDeque::new()),
+            capacity: capacity.max(1),
+            policy,
+            notify: Notify::new(),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Queue a message, returning false if a message was dropped to make room
+    pub(crate) fn push(&self, message: ClusterMessage) -> bool {
+        let accepted = {
+            let mut messages = self.messages.lock();
+            if messages.len() < self.capacity {
+                messages.push_back(message);
+                true
+            } else {
+                if self.policy == PeerQueuePolicy::DropOldest {
+                    messages.pop_front();
+                    messages.push_back(message);
+                }
+                false
+            }
+        };
+        if !accepted {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        self.notify.notify_one();
+        accepted
+    }
+
+    /// Put back a message whose write failed, so it is retried first
+    pub(crate) fn requeue(&self, message: ClusterMessage) {
+        let mut messages = self.messages.lock();
+        messages.push_front(message);
+        if messages.len() > self.capacity {
+            messages.pop_back();
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Take up to `max` messages to send together, oldest first
+    pub(crate) fn pop_batch(&self, max: usize) -> Vec<ClusterMessage> {
+        let mut messages = self.messages.lock();
+        let n = messages.len().min(max);
+        messages.drain(..n).collect()
+    }
+
+    /// Wait until a message is queued
+    pub(crate) async fn notified(&self) {
+        self.notify.notified().await
+    }
+
+    /// Number of queued messages
+    pub(crate) fn len(&self) -> usize {
+        self.messages.lock().len()
+    }
+
+    /// Messages dropped since the previous call
+    pub(crate) fn take_dropped(&self) -> u64 {
+        self.dropped.swap(0, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn publish(n: u8) -> ClusterMessage {
+        ClusterMessage::Publish {
+            topic: "t".to_string(),
+            payload: vec![n],
+            qos: 0,
+            retain: false,
+            origin_node: "node1".to_string(),
+        }
+    }
+
+    fn pop(queue: &PeerQueue) -> Option<ClusterMessage> {
+        queue.pop_batch(1).pop()
+    }
+
+    fn payload(message: Option<ClusterMessage>) -> u8 {
+        match message {
+            Some(ClusterMessage::Publish { payload, .. }) => payload[0],
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let queue = PeerQueue::new(2, PeerQueuePolicy::DropOldest);
+        assert!(queue.push(publish(1)));
+        assert!(queue.push(publish(2)));
+        assert!(!queue.push(publish(3)));
+
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.take_dropped(), 1);
+        assert_eq!(queue.take_dropped(), 0);
+        assert_eq!(payload(pop(&queue)), 2);
+        assert_eq!(payload(pop(&queue)), 3);
+        assert!(pop(&queue).is_none());
+    }
+
+    #[test]
+    fn test_drop_newest() {
+        let queue = PeerQueue::new(2, PeerQueuePolicy::DropNewest);
+        queue.push(publish(1));
+        queue.push(publish(2));
+        assert!(!queue.push(publish(3)));
+
+        assert_eq!(payload(pop(&queue)), 1);
+        assert_eq!(payload(pop(&queue)), 2);
+    }
+
+    #[test]
+    fn test_requeue_goes_first() {
+        let queue = PeerQueue::new(2, PeerQueuePolicy::DropOldest);
+        queue.push(publish(1));
+        queue.push(publish(2));
+
+        let failed = pop(&queue).unwrap();
+        queue.push(publish(3));
+        queue.requeue(failed);
+
+        // The newest message makes room for the retried one
+        assert_eq!(queue.take_dropped(), 1);
+        assert_eq!(payload(pop(&queue)), 1);
+        assert_eq!(payload(pop(&queue)), 2);
+        assert!(pop(&queue).is_none());
+    }
+
+    #[test]
+    fn test_pop_batch() {
+        let queue = PeerQueue::new(8, PeerQueuePolicy::DropOldest);
+        for n in 1..=5 {
+            queue.push(publish(n));
+        }
+
+        let batch = queue.pop_batch(3);
+        assert_eq!(batch.len(), 3);
+        assert_eq!(payload(batch.into_iter().next()), 1);
+        assert_eq!(queue.pop_batch(3).len(), 2);
+        assert!(queue.pop_batch(3).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_notified_after_push() {
+        let queue = PeerQueue::new(4, PeerQueuePolicy::DropOldest);
+        queue.push(publish(1));
+        // The permit stored by push completes the wait immediately
+        tokio::time::timeout(std::time::Duration::from_secs(1), queue.notified())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,350 @@
+//! Raft Metadata Store
+//!
+//! Optional strongly consistent store for cluster metadata, enabled with
+//! `consistency = "strong"` in builds with the `raft` feature. Gossip keeps
+//! handling membership and message routing; retained messages, session
+//! ownership, users and ACL roles are written through raft (openraft) and
+//! applied on every node once committed.
+//!
+//! The raft log, vote and snapshots are kept in `data_dir`, so a restarted
+//! node resumes from its own state before catching up with the leader.
+//! Raft RPCs require mutual TLS with certificates signed by the raft CA.
+
+#[cfg(feature = "raft")]
+mod network;
+#[cfg(feature = "raft")]
+mod store;
+
+#[cfg(feature = "raft")]
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(feature = "raft")]
+use std::hash::Hasher;
+use std::io;
+#[cfg(feature = "raft")]
+use std::io::Cursor;
+use std::sync::Arc;
+
+#[cfg(feature = "raft")]
+use openraft::error::{ClientWriteError, RaftError};
+#[cfg(feature = "raft")]
+use openraft::{BasicNode, Raft};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "raft")]
+use tokio::net::TcpListener;
+#[cfg(feature = "raft")]
+use tokio::sync::mpsc;
+#[cfg(feature = "raft")]
+use tracing::{debug, info, warn};
+
+use crate::config::RaftConfig;
+use crate::persistence::{StoredRole, StoredUser};
+
+#[cfg(feature = "raft")]
+use network::{serve, RaftConnection, RaftReply, RaftRequest, RaftTls, RaftTransport};
+#[cfg(feature = "raft")]
+use store::{LogStore, RaftDisk, StateMachineStore};
+
+/// Raft node ID, derived from the cluster node ID
+pub type NodeId = u64;
+
+// Raft type configuration of the metadata store
+#[cfg(feature = "raft")]
+openraft::declare_raft_types!(
+    pub TypeConfig:
+        D = MetadataCommand,
+        R = MetadataResponse,
+        NodeId = NodeId,
+        Node = BasicNode,
+        Entry = openraft::Entry<TypeConfig>,
+        SnapshotData = Cursor<Vec<u8>>,
+        AsyncRuntime = openraft::TokioRuntime,
+);
+
+/// A change to the replicated metadata
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MetadataCommand {
+    /// Store a retained message (an empty payload clears the topic)
+    SetRetained {
+        topic: String,
+        payload: Vec<u8>,
+        qos: u8,
+    },
+    /// Record that a client is connected to a node
+    ClaimClient { client_id: String, node_id: String },
+    /// Remove a client's owner, if it is still the given node
+    ReleaseClient { client_id: String, node_id: String },
+    /// Create or replace a user
+    SetUser(StoredUser),
+    /// Delete a user
+    DeleteUser { username: String },
+    /// Create or replace an ACL role
+    SetRole(StoredRole),
+    /// Delete an ACL role
+    DeleteRole { name: String },
+}
+
+/// Result of applying a `MetadataCommand`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MetadataResponse {
+    /// Whether the command changed the metadata
+    pub changed: bool,
+}
+
+/// Callback for committed metadata changes, invoked on every node
+///
+/// The broker mirrors retained messages, users and roles into its local
+/// stores from here.
+pub type MetadataApplyCallback = Arc<dyn Fn(&MetadataCommand) + Send + Sync>;
+
+/// Errors from the raft metadata store
+#[derive(Debug)]
+pub enum MetadataError {
+    /// Network or listener error
+    Io(io::Error),
+    /// Raft TLS certificates could not be loaded
+    Tls(String),
+    /// The raft data directory could not be opened or read
+    Storage(String),
+    /// Raft rejected or failed the operation
+    Raft(String),
+    /// No leader is known to accept the write
+    NoLeader,
+}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetadataError::Io(e) => write!(f, "I/O error: {}", e),
+            MetadataError::Tls(e) => write!(f, "TLS error: {}", e),
+            MetadataError::Storage(e) => write!(f, "storage error: {}", e),
+            MetadataError::Raft(e) => write!(f, "raft error: {}", e),
+            MetadataError::NoLeader => write!(f, "no raft leader"),
+        }
+    }
+}
+
+impl std::error::Error for MetadataError {}
+
+impl From<io::Error> for MetadataError {
+    fn from(e: io::Error) -> Self {
+        MetadataError::Io(e)
+    }
+}
+
+/// Raft node ID of a cluster node (FNV-1a of its node ID)
+#[cfg(feature = "raft")]
+pub fn raft_node_id(node_id: &str) -> NodeId {
+    let mut hasher = fnv::FnvHasher::default();
+    hasher.write(node_id.as_bytes());
+    hasher.finish()
+}
+
+/// Raft-replicated cluster metadata
+#[cfg(feature = "raft")]
+pub struct MetadataStore {
+    raft: Raft<TypeConfig>,
+    state_machine: StateMachineStore,
+    tls: RaftTls,
+    /// Writes committed in order by a background task
+    queue: mpsc::UnboundedSender<MetadataCommand>,
+}
+
+#[cfg(feature = "raft")]
+impl MetadataStore {
+    /// Start the raft node and its RPC listener
+    ///
+    /// If this node is one of the configured members it proposes the
+    /// initial membership; every member doing so with the same list is safe.
+    pub async fn start(
+        node_id: &str,
+        config: &RaftConfig,
+        on_apply: MetadataApplyCallback,
+    ) -> Result<Self, MetadataError> {
+        let id = raft_node_id(node_id);
+        let members: BTreeMap<NodeId, BasicNode> = config
+            .members
+            .iter()
+            .map(|(name, addr)| (raft_node_id(name), BasicNode::new(addr)))
+            .collect();
+
+        let raft_config = openraft::Config {
+            cluster_name: "vibemq".to_string(),
+            heartbeat_interval: config.heartbeat_interval.as_millis() as u64,
+            election_timeout_min: config.election_timeout.as_millis() as u64,
+            election_timeout_max: config.election_timeout.as_millis() as u64 * 2,
+            ..Default::default()
+        }
+        .validate()
+        .map_err(|e| MetadataError::Raft(e.to_string()))?;
+
+        let tls = match config.tls {
+            Some(ref tls) => RaftTls::load(tls).map_err(|e| MetadataError::Tls(e.to_string()))?,
+            None => {
+                return Err(MetadataError::Tls(
+                    "cluster.raft.tls is required".to_string(),
+                ))
+            }
+        };
+
+        let disk = RaftDisk::open(&config.data_dir)
+            .map_err(|e| MetadataError::Storage(format!("{}: {}", config.data_dir.display(), e)))?;
+        let log_store =
+            LogStore::load(disk.clone()).map_err(|e| MetadataError::Storage(e.to_string()))?;
+        let state_machine = StateMachineStore::load(on_apply, disk)
+            .map_err(|e| MetadataError::Storage(e.to_string()))?;
+
+        let listener = TcpListener::bind(config.bind).await?;
+        let raft = Raft::new(
+            id,
+            Arc::new(raft_config),
+            RaftTransport { tls: tls.clone() },
+            log_store,
+            state_machine.clone(),
+        )
+        .await
+        .map_err(|e| MetadataError::Raft(e.to_string()))?;
+
+        info!(
+            "Raft metadata store listening on {} (node {}, {} members)",
+            config.bind,
+            id,
+            members.len()
+        );
+        tokio::spawn(serve(listener, raft.clone(), tls.clone()));
+
+        if members.contains_key(&id) {
+            if let Err(e) = raft.initialize(members).await {
+                // Already initialized by an earlier start or another member
+                debug!("Raft: initialize skipped: {}", e);
+            }
+        }
+
+        let (queue, mut queued) = mpsc::unbounded_channel();
+        {
+            let raft = raft.clone();
+            let tls = tls.clone();
+            tokio::spawn(async move {
+                while let Some(command) = queued.recv().await {
+                    if let Err(e) = write(&raft, &tls, command).await {
+                        warn!("Raft: queued write failed: {}", e);
+                    }
+                }
+            });
+        }
+
+        Ok(Self {
+            raft,
+            state_machine,
+            tls,
+            queue,
+        })
+    }
+
+    /// Commit a change, forwarding it to the leader if needed
+    pub async fn write(&self, command: MetadataCommand) -> Result<MetadataResponse, MetadataError> {
+        write(&self.raft, &self.tls, command).await
+    }
+
+    /// Queue a change to be committed in the background, in submission
+    /// order, without waiting for it
+    pub fn submit(&self, command: MetadataCommand) {
+        // The writer task only stops when the store is dropped
+        let _ = self.queue.send(command);
+    }
+
+    /// Node owning a client's session, from the local replica
+    pub fn client_owner(&self, client_id: &str) -> Option<String> {
+        self.state_machine
+            .read(|state| state.owners.get(client_id).cloned())
+    }
+
+    /// Current raft leader, if one is known
+    pub fn leader(&self) -> Option<NodeId> {
+        self.raft.metrics().borrow().current_leader
+    }
+
+    /// Stop the raft node
+    pub async fn shutdown(&self) {
+        if let Err(e) = self.raft.shutdown().await {
+            debug!("Raft: shutdown error: {}", e);
+        }
+    }
+}
+
+/// Commit a change, forwarding it to the leader if this node is not it
+#[cfg(feature = "raft")]
+async fn write(
+    raft: &Raft<TypeConfig>,
+    tls: &RaftTls,
+    command: MetadataCommand,
+) -> Result<MetadataResponse, MetadataError> {
+    match raft.client_write(command.clone()).await {
+        Ok(response) => Ok(response.data),
+        Err(RaftError::APIError(ClientWriteError::ForwardToLeader(forward))) => {
+            let (Some(leader), Some(node)) = (forward.leader_id, forward.leader_node) else {
+                return Err(MetadataError::NoLeader);
+            };
+            let mut connection = RaftConnection::new(leader, node.addr, tls.clone());
+            match connection.call(&RaftRequest::Write(command)).await? {
+                RaftReply::Write(result) => result.map_err(MetadataError::Raft),
+                _ => Err(MetadataError::Raft("unexpected reply".to_string())),
+            }
+        }
+        Err(e) => Err(MetadataError::Raft(e.to_string())),
+    }
+}
+
+/// Raft-replicated cluster metadata, unavailable in builds without the
+/// `raft` feature
+#[cfg(not(feature = "raft"))]
+pub enum MetadataStore {}
+
+#[cfg(not(feature = "raft"))]
+impl MetadataStore {
+    pub async fn start(
+        _node_id: &str,
+        _config: &RaftConfig,
+        _on_apply: MetadataApplyCallback,
+    ) -> Result<Self, MetadataError> {
+        Err(MetadataError::Raft(
+            "consistency \"strong\" requires building with --features raft".to_string(),
+        ))
+    }
+
+    pub async fn write(
+        &self,
+        _command: MetadataCommand,
+    ) -> Result<MetadataResponse, MetadataError> {
+        match *self {}
+    }
+
+    pub fn submit(&self, _command: MetadataCommand) {
+        match *self {}
+    }
+
+    pub fn client_owner(&self, _client_id: &str) -> Option<String> {
+        match *self {}
+    }
+
+    pub fn leader(&self) -> Option<NodeId> {
+        match *self {}
+    }
+
+    pub async fn shutdown(&self) {
+        match *self {}
+    }
+}
+
+#[cfg(all(test, feature = "raft"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raft_node_id_is_stable() {
+        assert_eq!(raft_node_id("node1"), raft_node_id("node1"));
+        assert_ne!(raft_node_id("node1"), raft_node_id("node2"));
+        // FNV-1a offset basis for the empty string
+        assert_eq!(raft_node_id(""), 0xcbf29ce484222325);
+    }
+}
@@ -0,0 +1,334 @@
+//! Raft Network
+//!
+//! Raft RPCs between metadata nodes, sent as length-prefixed JSON frames
+//! over mutually authenticated TLS: a node only accepts connections from,
+//! and only connects to, holders of a certificate signed by the raft CA.
+//! Writes submitted on a follower are forwarded to the leader over the
+//! same channel.
+
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use openraft::error::{
+    InstallSnapshotError, NetworkError, RPCError, RaftError, RemoteError, Unreachable,
+};
+use openraft::network::{RPCOption, RaftNetwork, RaftNetworkFactory};
+use openraft::raft::{
+    AppendEntriesRequest, AppendEntriesResponse, InstallSnapshotRequest, InstallSnapshotResponse,
+    VoteRequest, VoteResponse,
+};
+use openraft::{BasicNode, Raft};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::ClientConfig;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tracing::{debug, error};
+
+use crate::broker::{
+    load_ca_certs, load_certs, load_private_key, load_tls_config, TlsConfig, TlsError,
+};
+use crate::cluster::protocol::MAX_FRAME_SIZE;
+use crate::config::RaftTlsConfig;
+
+use super::{MetadataCommand, MetadataResponse, NodeId, TypeConfig};
+
+/// How long to wait for a connection to another raft node, including the
+/// TLS handshake
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Server and client TLS setup of a raft node
+#[derive(Clone)]
+pub(crate) struct RaftTls {
+    acceptor: TlsAcceptor,
+    connector: TlsConnector,
+}
+
+impl RaftTls {
+    /// Load the node certificate and the CA that signs every member's
+    pub(crate) fn load(config: &RaftTlsConfig) -> Result<Self, TlsError> {
+        let acceptor = load_tls_config(&TlsConfig {
+            cert_path: config.cert.clone(),
+            key_path: config.key.clone(),
+            ca_cert_path: Some(config.ca_cert.clone()),
+            require_client_cert: true,
+        })?;
+        let client_config = ClientConfig::builder()
+            .with_root_certificates(load_ca_certs(&config.ca_cert)?)
+            .with_client_auth_cert(load_certs(&config.cert)?, load_private_key(&config.key)?)
+            .map_err(|e| TlsError::ConfigError(format!("Invalid client certificate: {}", e)))?;
+
+        Ok(Self {
+            acceptor,
+            connector: TlsConnector::from(Arc::new(client_config)),
+        })
+    }
+}
+
+/// Request sent to a raft node
+#[derive(Serialize, Deserialize)]
+pub(crate) enum RaftRequest {
+    AppendEntries(AppendEntriesRequest<TypeConfig>),
+    Vote(VoteRequest<NodeId>),
+    InstallSnapshot(InstallSnapshotRequest<TypeConfig>),
+    /// Client write forwarded to the leader
+    Write(MetadataCommand),
+}
+
+/// Reply to a `RaftRequest`
+#[derive(Serialize, Deserialize)]
+pub(crate) enum RaftReply {
+    AppendEntries(Result<AppendEntriesResponse<NodeId>, RaftError<NodeId>>),
+    Vote(Result<VoteResponse<NodeId>, RaftError<NodeId>>),
+    InstallSnapshot(
+        Result<InstallSnapshotResponse<NodeId>, RaftError<NodeId, InstallSnapshotError>>,
+    ),
+    Write(Result<MetadataResponse, String>),
+}
+
+type RpcResult<T, E = RaftError<NodeId>> = Result<T, RPCError<NodeId, BasicNode, E>>;
+
+/// Creates connections to other raft nodes
+pub(crate) struct RaftTransport {
+    pub(crate) tls: RaftTls,
+}
+
+impl RaftNetworkFactory<TypeConfig> for RaftTransport {
+    type Network = RaftConnection;
+
+    async fn new_client(&mut self, target: NodeId, node: &BasicNode) -> Self::Network {
+        RaftConnection::new(target, node.addr.clone(), self.tls.clone())
+    }
+}
+
+/// Connection to one raft node, reopened after errors
+pub(crate) struct RaftConnection {
+    target: NodeId,
+    addr: String,
+    tls: RaftTls,
+    stream: Option<TlsStream<TcpStream>>,
+}
+
+impl RaftConnection {
+    pub(crate) fn new(target: NodeId, addr: String, tls: RaftTls) -> Self {
+        Self {
+            target,
+            addr,
+            tls,
+            stream: None,
+        }
+    }
+
+    async fn connect(&self) -> io::Result<TlsStream<TcpStream>> {
+        let name = ServerName::try_from(server_name(&self.addr).to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let stream = TcpStream::connect(&self.addr).await?;
+        self.tls.connector.connect(name, stream).await
+    }
+
+    /// Send a request and wait for its reply
+    pub(crate) async fn call(&mut self, request: &RaftRequest) -> io::Result<RaftReply> {
+        if self.stream.is_none() {
+            let stream = tokio::time::timeout(CONNECT_TIMEOUT, self.connect())
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))??;
+            self.stream = Some(stream);
+        }
+        let stream = self.stream.as_mut().unwrap();
+
+        let result = match write_frame(stream, request).await {
+            Ok(()) => read_frame(stream).await,
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+
+    /// Map a failed call or mismatched reply to an RPC error
+    fn failed<E: std::error::Error>(
+        &self,
+        reply: io::Result<RaftReply>,
+    ) -> RPCError<NodeId, BasicNode, E> {
+        match reply {
+            Err(e) => RPCError::Unreachable(Unreachable::new(&e)),
+            Ok(_) => RPCError::Network(NetworkError::new(&io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected raft reply",
+            ))),
+        }
+    }
+}
+
+impl RaftNetwork<TypeConfig> for RaftConnection {
+    async fn append_entries(
+        &mut self,
+        rpc: AppendEntriesRequest<TypeConfig>,
+        _option: RPCOption,
+    ) -> RpcResult<AppendEntriesResponse<NodeId>> {
+        match self.call(&RaftRequest::AppendEntries(rpc)).await {
+            Ok(RaftReply::AppendEntries(result)) => {
+                result.map_err(|e| RPCError::RemoteError(RemoteError::new(self.target, e)))
+            }
+            reply => Err(self.failed(reply)),
+        }
+    }
+
+    async fn install_snapshot(
+        &mut self,
+        rpc: InstallSnapshotRequest<TypeConfig>,
+        _option: RPCOption,
+    ) -> RpcResult<InstallSnapshotResponse<NodeId>, RaftError<NodeId, InstallSnapshotError>> {
+        match self.call(&RaftRequest::InstallSnapshot(rpc)).await {
+            Ok(RaftReply::InstallSnapshot(result)) => {
+                result.map_err(|e| RPCError::RemoteError(RemoteError::new(self.target, e)))
+            }
+            reply => Err(self.failed(reply)),
+        }
+    }
+
+    async fn vote(
+        &mut self,
+        rpc: VoteRequest<NodeId>,
+        _option: RPCOption,
+    ) -> RpcResult<VoteResponse<NodeId>> {
+        match self.call(&RaftRequest::Vote(rpc)).await {
+            Ok(RaftReply::Vote(result)) => {
+                result.map_err(|e| RPCError::RemoteError(RemoteError::new(self.target, e)))
+            }
+            reply => Err(self.failed(reply)),
+        }
+    }
+}
+
+/// Host part of a "host:port" member address, checked against the
+/// certificate the member presents
+fn server_name(addr: &str) -> &str {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// Accept raft RPC connections and hand requests to the local raft node
+pub(crate) async fn serve(listener: TcpListener, raft: Raft<TypeConfig>, tls: RaftTls) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let raft = raft.clone();
+                let acceptor = tls.acceptor.clone();
+                tokio::spawn(async move {
+                    let stream = match tokio::time::timeout(
+                        CONNECT_TIMEOUT,
+                        acceptor.accept(stream),
+                    )
+                    .await
+                    {
+                        Ok(Ok(stream)) => stream,
+                        Ok(Err(e)) => {
+                            debug!("Raft: TLS handshake with {} failed: {}", addr, e);
+                            return;
+                        }
+                        Err(_) => {
+                            debug!("Raft: TLS handshake with {} timed out", addr);
+                            return;
+                        }
+                    };
+                    if let Err(e) = handle_connection(stream, raft).await {
+                        debug!("Raft: connection from {} closed: {}", addr, e);
+                    }
+                });
+            }
+            Err(e) => {
+                error!("Failed to accept raft connection: {}", e);
+            }
+        }
+    }
+}
+
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    raft: Raft<TypeConfig>,
+) -> io::Result<()> {
+    loop {
+        let request: RaftRequest = match read_frame(&mut stream).await {
+            Ok(request) => request,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let reply = match request {
+            RaftRequest::AppendEntries(rpc) => {
+                RaftReply::AppendEntries(raft.append_entries(rpc).await)
+            }
+            RaftRequest::Vote(rpc) => RaftReply::Vote(raft.vote(rpc).await),
+            RaftRequest::InstallSnapshot(rpc) => {
+                RaftReply::InstallSnapshot(raft.install_snapshot(rpc).await)
+            }
+            RaftRequest::Write(command) => RaftReply::Write(
+                raft.client_write(command)
+                    .await
+                    .map(|response| response.data)
+                    .map_err(|e| e.to_string()),
+            ),
+        };
+        write_frame(&mut stream, &reply).await?;
+    }
+}
+
+async fn write_frame<W: AsyncWrite + Unpin, T: Serialize>(
+    writer: &mut W,
+    value: &T,
+) -> io::Result<()> {
+    let data =
+        serde_json::to_vec(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    writer.write_all(&(data.len() as u32).to_be_bytes()).await?;
+    writer.write_all(&data).await
+}
+
+async fn read_frame<R: AsyncRead + Unpin, T: DeserializeOwned>(reader: &mut R) -> io::Result<T> {
+    let len = reader.read_u32().await?;
+    if len > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("raft frame too large: {} bytes", len),
+        ));
+    }
+    let mut data = vec![0u8; len as usize];
+    reader.read_exact(&mut data).await?;
+    serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_frame_roundtrip() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let command = MetadataCommand::ClaimClient {
+            client_id: "c1".to_string(),
+            node_id: "node1".to_string(),
+        };
+
+        write_frame(&mut client, &RaftRequest::Write(command.clone()))
+            .await
+            .unwrap();
+        let request: RaftRequest = read_frame(&mut server).await.unwrap();
+        match request {
+            RaftRequest::Write(received) => assert_eq!(received, command),
+            _ => panic!("Wrong request type"),
+        }
+    }
+
+    #[test]
+    fn test_server_name() {
+        assert_eq!(server_name("vibemq-0:7948"), "vibemq-0");
+        assert_eq!(server_name("10.0.0.1:7948"), "10.0.0.1");
+        assert_eq!(server_name("[::1]:7948"), "::1");
+        assert_eq!(server_name("vibemq-0"), "vibemq-0");
+    }
+}
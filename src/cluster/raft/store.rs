@@ -0,0 +1,685 @@
+//! Raft Log and State Machine
+//!
+//! The raft log, vote and latest snapshot are written to a fjall keyspace
+//! before raft is told they are durable, and cached in memory for reads.
+//! Committed commands are applied to `MetadataState` and reported through
+//! the apply callback so the broker can mirror them into its local stores.
+//! On restart the state is rebuilt from the snapshot, and raft re-applies
+//! the committed entries after it.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::io::Cursor;
+use std::ops::RangeBounds;
+use std::path::Path;
+use std::sync::Arc;
+
+use fjall::{Config, Keyspace, PartitionCreateOptions, PartitionHandle, PersistMode};
+use openraft::storage::{LogFlushed, LogState, RaftLogStorage, RaftStateMachine, Snapshot};
+use openraft::{
+    AnyError, BasicNode, Entry, EntryPayload, ErrorSubject, ErrorVerb, LogId, OptionalSend,
+    RaftLogReader, RaftSnapshotBuilder, SnapshotMeta, StorageError, StorageIOError,
+    StoredMembership, Vote,
+};
+use parking_lot::RwLock;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::persistence::{StoredRole, StoredUser};
+
+use super::{MetadataApplyCallback, MetadataCommand, MetadataResponse, NodeId, TypeConfig};
+
+/// A retained message in the replicated metadata
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetainedEntry {
+    pub payload: Vec<u8>,
+    pub qos: u8,
+}
+
+/// Replicated cluster metadata
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MetadataState {
+    /// Retained messages by topic
+    pub retained: BTreeMap<String, RetainedEntry>,
+    /// Owning node of each connected client
+    pub owners: BTreeMap<String, String>,
+    /// Users by username
+    pub users: BTreeMap<String, StoredUser>,
+    /// ACL roles by name
+    pub roles: BTreeMap<String, StoredRole>,
+}
+
+impl MetadataState {
+    /// Apply a command, returning whether anything changed
+    fn apply(&mut self, command: &MetadataCommand) -> bool {
+        match command {
+            MetadataCommand::SetRetained {
+                topic,
+                payload,
+                qos,
+            } => {
+                if payload.is_empty() {
+                    self.retained.remove(topic).is_some()
+                } else {
+                    let entry = RetainedEntry {
+                        payload: payload.clone(),
+                        qos: *qos,
+                    };
+                    self.retained.insert(topic.clone(), entry);
+                    true
+                }
+            }
+            MetadataCommand::ClaimClient { client_id, node_id } => {
+                self.owners
+                    .insert(client_id.clone(), node_id.clone())
+                    .as_ref()
+                    != Some(node_id)
+            }
+            MetadataCommand::ReleaseClient { client_id, node_id } => {
+                if self.owners.get(client_id) == Some(node_id) {
+                    self.owners.remove(client_id);
+                    true
+                } else {
+                    false
+                }
+            }
+            MetadataCommand::SetUser(user) => {
+                self.users.insert(user.username.clone(), user.clone());
+                true
+            }
+            MetadataCommand::DeleteUser { username } => self.users.remove(username).is_some(),
+            MetadataCommand::SetRole(role) => {
+                self.roles.insert(role.name.clone(), role.clone());
+                true
+            }
+            MetadataCommand::DeleteRole { name } => self.roles.remove(name).is_some(),
+        }
+    }
+
+    /// Commands that turn `self` into `other`, for mirroring a snapshot
+    fn diff(&self, other: &MetadataState) -> Vec<MetadataCommand> {
+        let mut commands = Vec::new();
+        for topic in self.retained.keys() {
+            if !other.retained.contains_key(topic) {
+                commands.push(MetadataCommand::SetRetained {
+                    topic: topic.clone(),
+                    payload: Vec::new(),
+                    qos: 0,
+                });
+            }
+        }
+        for (topic, entry) in &other.retained {
+            if self.retained.get(topic) != Some(entry) {
+                commands.push(MetadataCommand::SetRetained {
+                    topic: topic.clone(),
+                    payload: entry.payload.clone(),
+                    qos: entry.qos,
+                });
+            }
+        }
+        for username in self.users.keys() {
+            if !other.users.contains_key(username) {
+                commands.push(MetadataCommand::DeleteUser {
+                    username: username.clone(),
+                });
+            }
+        }
+        commands.extend(other.users.values().cloned().map(MetadataCommand::SetUser));
+        for name in self.roles.keys() {
+            if !other.roles.contains_key(name) {
+                commands.push(MetadataCommand::DeleteRole { name: name.clone() });
+            }
+        }
+        commands.extend(other.roles.values().cloned().map(MetadataCommand::SetRole));
+        commands
+    }
+}
+
+const VOTE_KEY: &str = "vote";
+const COMMITTED_KEY: &str = "committed";
+const LAST_PURGED_KEY: &str = "last_purged";
+const SNAPSHOT_KEY: &str = "snapshot";
+
+/// On-disk raft state: log entries by index, plus vote, commit and
+/// snapshot records
+#[derive(Clone)]
+pub(crate) struct RaftDisk {
+    keyspace: Keyspace,
+    log: PartitionHandle,
+    meta: PartitionHandle,
+}
+
+impl RaftDisk {
+    /// Open (or create) the raft keyspace in `path`
+    pub(crate) fn open(path: &Path) -> Result<Self, fjall::Error> {
+        let keyspace = Config::new(path).open()?;
+        let log = keyspace.open_partition("log", PartitionCreateOptions::default())?;
+        let meta = keyspace.open_partition("meta", PartitionCreateOptions::default())?;
+        Ok(Self {
+            keyspace,
+            log,
+            meta,
+        })
+    }
+
+    fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, AnyError> {
+        match self.meta.get(key).map_err(|e| AnyError::new(&e))? {
+            Some(bytes) => serde_json::from_slice(&bytes)
+                .map(Some)
+                .map_err(|e| AnyError::new(&e)),
+            None => Ok(None),
+        }
+    }
+
+    fn entries(&self) -> Result<BTreeMap<u64, Entry<TypeConfig>>, AnyError> {
+        let mut log = BTreeMap::new();
+        for item in self.log.iter() {
+            let (_, value) = item.map_err(|e| AnyError::new(&e))?;
+            let entry: Entry<TypeConfig> =
+                serde_json::from_slice(&value).map_err(|e| AnyError::new(&e))?;
+            log.insert(entry.log_id.index, entry);
+        }
+        Ok(log)
+    }
+
+    /// Run a write off the async runtime, syncing it to disk if `sync`
+    async fn write(
+        &self,
+        subject: ErrorSubject<NodeId>,
+        verb: ErrorVerb,
+        sync: bool,
+        f: impl FnOnce(&Self) -> Result<(), fjall::Error> + Send + 'static,
+    ) -> Result<(), StorageError<NodeId>> {
+        let disk = self.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            f(&disk)?;
+            if sync {
+                disk.keyspace.persist(PersistMode::SyncAll)?;
+            }
+            Ok::<_, fjall::Error>(())
+        })
+        .await;
+        match result {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(StorageIOError::new(subject, verb, AnyError::new(&e)).into()),
+            Err(e) => Err(StorageIOError::new(subject, verb, AnyError::new(&e)).into()),
+        }
+    }
+}
+
+fn index_key(index: u64) -> [u8; 8] {
+    index.to_be_bytes()
+}
+
+#[derive(Default)]
+struct LogData {
+    vote: Option<Vote<NodeId>>,
+    committed: Option<LogId<NodeId>>,
+    last_purged: Option<LogId<NodeId>>,
+    log: BTreeMap<u64, Entry<TypeConfig>>,
+}
+
+/// Raft log, written through to disk and cached in memory
+#[derive(Clone)]
+pub(crate) struct LogStore {
+    inner: Arc<RwLock<LogData>>,
+    disk: RaftDisk,
+}
+
+impl LogStore {
+    /// Load the log, vote and commit index kept on disk
+    pub(crate) fn load(disk: RaftDisk) -> Result<Self, AnyError> {
+        let data = LogData {
+            vote: disk.get(VOTE_KEY)?,
+            committed: disk.get(COMMITTED_KEY)?,
+            last_purged: disk.get(LAST_PURGED_KEY)?,
+            log: disk.entries()?,
+        };
+        Ok(Self {
+            inner: Arc::new(RwLock::new(data)),
+            disk,
+        })
+    }
+}
+
+impl RaftLogReader<TypeConfig> for LogStore {
+    async fn try_get_log_entries<RB: RangeBounds<u64> + Clone + Debug + OptionalSend>(
+        &mut self,
+        range: RB,
+    ) -> Result<Vec<Entry<TypeConfig>>, StorageError<NodeId>> {
+        Ok(self
+            .inner
+            .read()
+            .log
+            .range(range)
+            .map(|(_, entry)| entry.clone())
+            .collect())
+    }
+}
+
+impl RaftLogStorage<TypeConfig> for LogStore {
+    type LogReader = Self;
+
+    async fn get_log_state(&mut self) -> Result<LogState<TypeConfig>, StorageError<NodeId>> {
+        let data = self.inner.read();
+        let last_log_id = data
+            .log
+            .values()
+            .next_back()
+            .map(|entry| entry.log_id)
+            .or(data.last_purged);
+        Ok(LogState {
+            last_purged_log_id: data.last_purged,
+            last_log_id,
+        })
+    }
+
+    async fn get_log_reader(&mut self) -> Self::LogReader {
+        self.clone()
+    }
+
+    async fn save_vote(&mut self, vote: &Vote<NodeId>) -> Result<(), StorageError<NodeId>> {
+        let bytes = serde_json::to_vec(vote).map_err(|e| StorageIOError::write_vote(&e))?;
+        self.disk
+            .write(ErrorSubject::Vote, ErrorVerb::Write, true, move |disk| {
+                disk.meta.insert(VOTE_KEY, bytes)
+            })
+            .await?;
+        self.inner.write().vote = Some(*vote);
+        Ok(())
+    }
+
+    async fn read_vote(&mut self) -> Result<Option<Vote<NodeId>>, StorageError<NodeId>> {
+        Ok(self.inner.read().vote)
+    }
+
+    async fn save_committed(
+        &mut self,
+        committed: Option<LogId<NodeId>>,
+    ) -> Result<(), StorageError<NodeId>> {
+        // Losing the latest commit index only means re-learning it from
+        // the leader, so it rides along with the next sync
+        let bytes = serde_json::to_vec(&committed).map_err(|e| StorageIOError::write(&e))?;
+        self.disk
+            .write(ErrorSubject::Store, ErrorVerb::Write, false, move |disk| {
+                disk.meta.insert(COMMITTED_KEY, bytes)
+            })
+            .await?;
+        self.inner.write().committed = committed;
+        Ok(())
+    }
+
+    async fn read_committed(&mut self) -> Result<Option<LogId<NodeId>>, StorageError<NodeId>> {
+        Ok(self.inner.read().committed)
+    }
+
+    async fn append<I>(
+        &mut self,
+        entries: I,
+        callback: LogFlushed<TypeConfig>,
+    ) -> Result<(), StorageError<NodeId>>
+    where
+        I: IntoIterator<Item = Entry<TypeConfig>> + OptionalSend,
+        I::IntoIter: OptionalSend,
+    {
+        let entries: Vec<Entry<TypeConfig>> = entries.into_iter().collect();
+        let mut encoded = Vec::with_capacity(entries.len());
+        for entry in &entries {
+            encoded.push((
+                index_key(entry.log_id.index),
+                serde_json::to_vec(entry).map_err(|e| StorageIOError::write_logs(&e))?,
+            ));
+        }
+
+        let written = self
+            .disk
+            .write(ErrorSubject::Logs, ErrorVerb::Write, true, move |disk| {
+                for (key, bytes) in encoded {
+                    disk.log.insert(key, bytes)?;
+                }
+                Ok(())
+            })
+            .await;
+        if written.is_ok() {
+            let mut data = self.inner.write();
+            for entry in entries {
+                data.log.insert(entry.log_id.index, entry);
+            }
+        }
+        callback.log_io_completed(
+            written
+                .clone()
+                .map_err(|e| std::io::Error::other(e.to_string())),
+        );
+        written
+    }
+
+    async fn truncate(&mut self, log_id: LogId<NodeId>) -> Result<(), StorageError<NodeId>> {
+        let removed: Vec<u64> = {
+            let mut data = self.inner.write();
+            data.log.split_off(&log_id.index).into_keys().collect()
+        };
+        self.disk
+            .write(ErrorSubject::Logs, ErrorVerb::Delete, true, move |disk| {
+                for index in removed {
+                    disk.log.remove(index_key(index))?;
+                }
+                Ok(())
+            })
+            .await
+    }
+
+    async fn purge(&mut self, log_id: LogId<NodeId>) -> Result<(), StorageError<NodeId>> {
+        let marker = serde_json::to_vec(&log_id).map_err(|e| StorageIOError::write_logs(&e))?;
+        let removed: Vec<u64> = {
+            let mut data = self.inner.write();
+            data.last_purged = Some(log_id);
+            let kept = data.log.split_off(&(log_id.index + 1));
+            std::mem::replace(&mut data.log, kept).into_keys().collect()
+        };
+        // The purge marker goes first, so a crash never leaves a gap
+        // before the first remaining entry
+        self.disk
+            .write(ErrorSubject::Logs, ErrorVerb::Delete, true, move |disk| {
+                disk.meta.insert(LAST_PURGED_KEY, marker)?;
+                for index in removed {
+                    disk.log.remove(index_key(index))?;
+                }
+                Ok(())
+            })
+            .await
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredSnapshot {
+    meta: SnapshotMeta<NodeId, BasicNode>,
+    data: Vec<u8>,
+}
+
+#[derive(Default)]
+struct StateMachineData {
+    last_applied: Option<LogId<NodeId>>,
+    last_membership: StoredMembership<NodeId, BasicNode>,
+    state: MetadataState,
+    snapshot: Option<StoredSnapshot>,
+    snapshot_count: u64,
+}
+
+/// Raft state machine holding the replicated metadata
+#[derive(Clone)]
+pub(crate) struct StateMachineStore {
+    inner: Arc<RwLock<StateMachineData>>,
+    on_apply: MetadataApplyCallback,
+    disk: RaftDisk,
+}
+
+impl StateMachineStore {
+    /// Restore the state from the snapshot kept on disk, mirroring it
+    /// through `on_apply`
+    pub(crate) fn load(on_apply: MetadataApplyCallback, disk: RaftDisk) -> Result<Self, AnyError> {
+        let snapshot: Option<StoredSnapshot> = disk.get(SNAPSHOT_KEY)?;
+
+        let mut data = StateMachineData::default();
+        if let Some(snapshot) = snapshot {
+            data.state = serde_json::from_slice(&snapshot.data).map_err(|e| AnyError::new(&e))?;
+            data.last_applied = snapshot.meta.last_log_id;
+            data.last_membership = snapshot.meta.last_membership.clone();
+            data.snapshot = Some(snapshot);
+        }
+        for command in MetadataState::default().diff(&data.state) {
+            on_apply(&command);
+        }
+
+        Ok(Self {
+            inner: Arc::new(RwLock::new(data)),
+            on_apply,
+            disk,
+        })
+    }
+
+    /// Read the local replica of the metadata
+    pub(crate) fn read<T>(&self, f: impl FnOnce(&MetadataState) -> T) -> T {
+        f(&self.inner.read().state)
+    }
+
+    /// Keep a snapshot on disk, so a restart does not need the purged log
+    async fn save_snapshot(&self, snapshot: &StoredSnapshot) -> Result<(), StorageError<NodeId>> {
+        let subject = ErrorSubject::Snapshot(Some(snapshot.meta.signature()));
+        let bytes = serde_json::to_vec(snapshot)
+            .map_err(|e| StorageIOError::write_snapshot(Some(snapshot.meta.signature()), &e))?;
+        self.disk
+            .write(subject, ErrorVerb::Write, true, move |disk| {
+                disk.meta.insert(SNAPSHOT_KEY, bytes)
+            })
+            .await
+    }
+}
+
+impl RaftSnapshotBuilder<TypeConfig> for StateMachineStore {
+    async fn build_snapshot(&mut self) -> Result<Snapshot<TypeConfig>, StorageError<NodeId>> {
+        let snapshot = {
+            let mut sm = self.inner.write();
+            let data = serde_json::to_vec(&sm.state)
+                .map_err(|e| StorageIOError::read_state_machine(&e))?;
+
+            sm.snapshot_count += 1;
+            let snapshot_id = match sm.last_applied {
+                Some(log_id) => format!("{}-{}", log_id.index, sm.snapshot_count),
+                None => format!("none-{}", sm.snapshot_count),
+            };
+            StoredSnapshot {
+                meta: SnapshotMeta {
+                    last_log_id: sm.last_applied,
+                    last_membership: sm.last_membership.clone(),
+                    snapshot_id,
+                },
+                data,
+            }
+        };
+        self.save_snapshot(&snapshot).await?;
+
+        let result = Snapshot {
+            meta: snapshot.meta.clone(),
+            snapshot: Box::new(Cursor::new(snapshot.data.clone())),
+        };
+        self.inner.write().snapshot = Some(snapshot);
+        Ok(result)
+    }
+}
+
+impl RaftStateMachine<TypeConfig> for StateMachineStore {
+    type SnapshotBuilder = Self;
+
+    async fn applied_state(
+        &mut self,
+    ) -> Result<(Option<LogId<NodeId>>, StoredMembership<NodeId, BasicNode>), StorageError<NodeId>>
+    {
+        let sm = self.inner.read();
+        Ok((sm.last_applied, sm.last_membership.clone()))
+    }
+
+    async fn apply<I>(&mut self, entries: I) -> Result<Vec<MetadataResponse>, StorageError<NodeId>>
+    where
+        I: IntoIterator<Item = Entry<TypeConfig>> + OptionalSend,
+        I::IntoIter: OptionalSend,
+    {
+        let mut responses = Vec::new();
+        let mut changes = Vec::new();
+        {
+            let mut sm = self.inner.write();
+            for entry in entries {
+                sm.last_applied = Some(entry.log_id);
+                let changed = match entry.payload {
+                    EntryPayload::Blank => false,
+                    EntryPayload::Normal(command) => {
+                        let changed = sm.state.apply(&command);
+                        if changed {
+                            changes.push(command);
+                        }
+                        changed
+                    }
+                    EntryPayload::Membership(membership) => {
+                        sm.last_membership = StoredMembership::new(Some(entry.log_id), membership);
+                        false
+                    }
+                };
+                responses.push(MetadataResponse { changed });
+            }
+        }
+
+        for command in &changes {
+            (self.on_apply)(command);
+        }
+        Ok(responses)
+    }
+
+    async fn get_snapshot_builder(&mut self) -> Self::SnapshotBuilder {
+        self.clone()
+    }
+
+    async fn begin_receiving_snapshot(
+        &mut self,
+    ) -> Result<Box<Cursor<Vec<u8>>>, StorageError<NodeId>> {
+        Ok(Box::new(Cursor::new(Vec::new())))
+    }
+
+    async fn install_snapshot(
+        &mut self,
+        meta: &SnapshotMeta<NodeId, BasicNode>,
+        snapshot: Box<Cursor<Vec<u8>>>,
+    ) -> Result<(), StorageError<NodeId>> {
+        let data = snapshot.into_inner();
+        let state: MetadataState = serde_json::from_slice(&data)
+            .map_err(|e| StorageIOError::read_snapshot(Some(meta.signature()), &e))?;
+        let stored = StoredSnapshot {
+            meta: meta.clone(),
+            data,
+        };
+        self.save_snapshot(&stored).await?;
+
+        let changes = {
+            let mut sm = self.inner.write();
+            let changes = sm.state.diff(&state);
+            sm.state = state;
+            sm.last_applied = meta.last_log_id;
+            sm.last_membership = meta.last_membership.clone();
+            sm.snapshot = Some(stored);
+            changes
+        };
+
+        for command in &changes {
+            (self.on_apply)(command);
+        }
+        Ok(())
+    }
+
+    async fn get_current_snapshot(
+        &mut self,
+    ) -> Result<Option<Snapshot<TypeConfig>>, StorageError<NodeId>> {
+        Ok(self.inner.read().snapshot.as_ref().map(|stored| Snapshot {
+            meta: stored.meta.clone(),
+            snapshot: Box::new(Cursor::new(stored.data.clone())),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retained(topic: &str, payload: &[u8]) -> MetadataCommand {
+        MetadataCommand::SetRetained {
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+            qos: 1,
+        }
+    }
+
+    #[test]
+    fn test_apply_retained() {
+        let mut state = MetadataState::default();
+        assert!(state.apply(&retained("a/b", b"1")));
+        assert_eq!(state.retained["a/b"].payload, b"1");
+
+        // An empty payload clears the topic
+        assert!(state.apply(&retained("a/b", b"")));
+        assert!(state.retained.is_empty());
+        assert!(!state.apply(&retained("a/b", b"")));
+    }
+
+    #[test]
+    fn test_release_only_by_owner() {
+        let mut state = MetadataState::default();
+        let claim = |node: &str| MetadataCommand::ClaimClient {
+            client_id: "c1".to_string(),
+            node_id: node.to_string(),
+        };
+        let release = |node: &str| MetadataCommand::ReleaseClient {
+            client_id: "c1".to_string(),
+            node_id: node.to_string(),
+        };
+
+        assert!(state.apply(&claim("node1")));
+        assert!(!state.apply(&claim("node1")));
+        // The client moved to node2 before node1 released it
+        assert!(state.apply(&claim("node2")));
+        assert!(!state.apply(&release("node1")));
+        assert_eq!(state.owners["c1"], "node2");
+        assert!(state.apply(&release("node2")));
+        assert!(state.owners.is_empty());
+    }
+
+    #[test]
+    fn test_diff_mirrors_snapshot() {
+        let mut old = MetadataState::default();
+        old.apply(&retained("stale", b"x"));
+        old.apply(&retained("kept", b"y"));
+
+        let mut new = MetadataState::default();
+        new.apply(&retained("kept", b"y"));
+        new.apply(&retained("fresh", b"z"));
+
+        let commands = old.diff(&new);
+        let clear = MetadataCommand::SetRetained {
+            topic: "stale".to_string(),
+            payload: Vec::new(),
+            qos: 0,
+        };
+        assert_eq!(commands, vec![clear, retained("fresh", b"z")]);
+
+        // Replaying the diff on the old state yields the new one
+        for command in &commands {
+            old.apply(command);
+        }
+        assert_eq!(old.retained, new.retained);
+    }
+
+    #[tokio::test]
+    async fn test_state_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let applied = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let on_apply: MetadataApplyCallback = {
+            let applied = applied.clone();
+            Arc::new(move |command: &MetadataCommand| applied.lock().push(command.clone()))
+        };
+
+        {
+            let disk = RaftDisk::open(dir.path()).unwrap();
+            let mut log = LogStore::load(disk.clone()).unwrap();
+            log.save_vote(&Vote::new(3, 1)).await.unwrap();
+            let mut sm = StateMachineStore::load(on_apply.clone(), disk).unwrap();
+            sm.inner.write().state.apply(&retained("a/b", b"1"));
+            sm.build_snapshot().await.unwrap();
+        }
+        assert!(applied.lock().is_empty());
+
+        let disk = RaftDisk::open(dir.path()).unwrap();
+        let mut log = LogStore::load(disk.clone()).unwrap();
+        assert_eq!(log.read_vote().await.unwrap(), Some(Vote::new(3, 1)));
+        // The restored snapshot is mirrored to the broker again
+        let sm = StateMachineStore::load(on_apply, disk).unwrap();
+        assert_eq!(sm.read(|state| state.retained.len()), 1);
+        assert_eq!(*applied.lock(), vec![retained("a/b", b"1")]);
+    }
+}
@@ -0,0 +1,114 @@
+//! Cluster Subscription Routes
+//!
+//! Tracks the topic filters a remote node has subscribers for, so a publish
+//! is only forwarded to nodes that can deliver it.
+
+use std::collections::HashSet;
+
+use crate::topic::{parse_shared_subscription, TopicTrie};
+
+/// Topic filters a remote node has subscribers for
+#[derive(Default)]
+pub(crate) struct SubscriptionTable {
+    filters: HashSet<String>,
+    trie: TopicTrie<()>,
+}
+
+impl SubscriptionTable {
+    /// Replace the whole table, returning false if nothing changed
+    pub(crate) fn replace(&mut self, filters: impl IntoIterator<Item = String>) -> bool {
+        let filters: HashSet<String> = filters.into_iter().map(route_filter).collect();
+        if filters == self.filters {
+            return false;
+        }
+
+        let mut trie = TopicTrie::new();
+        for filter in &filters {
+            trie.insert(filter, ());
+        }
+        self.filters = filters;
+        self.trie = trie;
+        true
+    }
+
+    /// Apply an incremental update
+    pub(crate) fn apply(&mut self, added: Vec<String>, removed: Vec<String>) {
+        for filter in removed.into_iter().map(route_filter) {
+            if self.filters.remove(&filter) {
+                self.trie.remove(&filter);
+            }
+        }
+        for filter in added.into_iter().map(route_filter) {
+            if !self.filters.contains(&filter) {
+                self.trie.insert(&filter, ());
+                self.filters.insert(filter);
+            }
+        }
+    }
+
+    /// Whether any filter in the table matches the topic
+    pub(crate) fn matches(&self, topic: &str) -> bool {
+        let mut found = false;
+        self.trie.matches(topic, |_| found = true);
+        found
+    }
+
+    /// Filters in the table
+    pub(crate) fn filters(&self) -> impl Iterator<Item = &String> {
+        self.filters.iter()
+    }
+
+    /// Number of filters in the table
+    pub(crate) fn len(&self) -> usize {
+        self.filters.len()
+    }
+}
+
+/// The filter used for routing: shared subscriptions route on their topic filter
+///
+/// Shared `$queue/` subscriptions are synced as share groups and never get
+/// here, so a `$queue/` filter is a plain one.
+pub(crate) fn route_filter(filter: String) -> String {
+    match parse_shared_subscription(&filter, false) {
+        Some((_, actual)) => actual.to_string(),
+        None => filter,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_and_match() {
+        let mut table = SubscriptionTable::default();
+        assert!(!table.matches("sensors/temp"));
+
+        assert!(table.replace(vec![
+            "sensors/+".to_string(),
+            "$share/g/alerts/#".to_string()
+        ]));
+        assert!(table.matches("sensors/temp"));
+        assert!(table.matches("alerts/fire"));
+        assert!(!table.matches("sensors/temp/raw"));
+        assert!(!table.matches("$share/g/alerts/fire"));
+
+        // Same set is not a change
+        assert!(!table.replace(vec!["alerts/#".to_string(), "sensors/+".to_string()]));
+        assert_eq!(table.len(), 2);
+    }
+
+    #[test]
+    fn test_incremental_update() {
+        let mut table = SubscriptionTable::default();
+        table.apply(vec!["a/#".to_string(), "b".to_string()], vec![]);
+        assert!(table.matches("a/x"));
+        assert!(table.matches("b"));
+
+        table.apply(vec![], vec!["a/#".to_string()]);
+        assert!(!table.matches("a/x"));
+        assert!(table.matches("b"));
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.filters().collect::<Vec<_>>(), vec!["b"]);
+    }
+}
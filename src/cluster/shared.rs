@@ -0,0 +1,254 @@
+//! Cluster Shared Subscriptions
+//!
+//! Balances `$share/<group>/<filter>` delivery across nodes so that exactly
+//! one member of a group receives each message cluster-wide. Every node
+//! advertises its groups (filters and unacknowledged message count) via
+//! gossip; the node a message is published on picks the node that delivers
+//! it to each group.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
+
+use crate::config::SharedSubscriptionStrategy;
+use crate::topic::topic_matches_filter;
+
+/// A shared subscription group as advertised by one node
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SharedGroupState {
+    /// Topic filters subscribed to in the group
+    pub filters: Vec<String>,
+    /// Unacknowledged outgoing messages across the node's group members
+    pub inflight: u32,
+}
+
+/// Shared subscription groups of one node, keyed by group name
+///
+/// Between gossip rounds the inflight counts are bumped for every message
+/// assigned to the node, so bursts spread out under `least_inflight`. The
+/// counts are atomic, so routing a message only takes a read lock.
+#[derive(Debug, Default)]
+pub(crate) struct SharedGroupTable {
+    groups: HashMap<String, GroupEntry>,
+}
+
+#[derive(Debug)]
+struct GroupEntry {
+    filters: Vec<String>,
+    inflight: AtomicU32,
+}
+
+impl GroupEntry {
+    fn matches(&self, topic: &str) -> bool {
+        self.filters
+            .iter()
+            .any(|filter| topic_matches_filter(topic, filter))
+    }
+}
+
+impl SharedGroupTable {
+    /// Replace the table with freshly advertised state
+    pub(crate) fn replace(&mut self, groups: HashMap<String, SharedGroupState>) {
+        self.groups = groups
+            .into_iter()
+            .map(|(group, state)| {
+                let entry = GroupEntry {
+                    filters: state.filters,
+                    inflight: AtomicU32::new(state.inflight),
+                };
+                (group, entry)
+            })
+            .collect();
+    }
+
+    /// Groups with a filter matching the topic, with their inflight count
+    pub(crate) fn matching(&self, topic: &str) -> SmallVec<[(String, u32); 4]> {
+        self.groups
+            .iter()
+            .filter(|(_, entry)| entry.matches(topic))
+            .map(|(group, entry)| (group.clone(), entry.inflight.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// Inflight count of a group, 0 if unknown
+    pub(crate) fn inflight(&self, group: &str) -> u32 {
+        self.groups
+            .get(group)
+            .map(|entry| entry.inflight.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+
+    /// Account for a message assigned to this node's members of a group
+    pub(crate) fn bump(&self, group: &str) {
+        if let Some(entry) = self.groups.get(group) {
+            let _ = entry
+                .inflight
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_add(1));
+        }
+    }
+}
+
+/// Which node delivers a message to each shared subscription group
+#[derive(Debug, Default)]
+pub struct SharedRoute {
+    /// Groups assigned to other nodes, by node ID
+    remote: HashMap<String, Vec<String>>,
+}
+
+impl SharedRoute {
+    /// Whether the group is served by another node for this message
+    pub fn is_remote(&self, group: &str) -> bool {
+        self.remote
+            .values()
+            .any(|groups| groups.iter().any(|g| g == group))
+    }
+
+    pub(crate) fn assign(&mut self, node_id: &str, group: String) {
+        self.remote
+            .entry(node_id.to_string())
+            .or_default()
+            .push(group);
+    }
+
+    /// Keep only the groups for which `f` returns true
+    pub fn retain(&mut self, mut f: impl FnMut(&str) -> bool) {
+        self.remote.retain(|_, groups| {
+            groups.retain(|group| f(group));
+            !groups.is_empty()
+        });
+    }
+
+    /// Take the groups assigned to a node
+    pub(crate) fn take(&mut self, node_id: &str) -> Vec<String> {
+        self.remote.remove(node_id).unwrap_or_default()
+    }
+}
+
+/// A node with members in a shared subscription group
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Candidate<'a> {
+    /// Node ID, `None` for the local node
+    pub node: Option<&'a str>,
+    pub inflight: u32,
+}
+
+/// Pick the candidate that delivers the next message to a group
+///
+/// `candidates` must be in a stable order (local node first, then by node ID)
+/// so round-robin rotates evenly. Returns an index into `candidates`.
+pub(crate) fn choose(
+    strategy: SharedSubscriptionStrategy,
+    counter: &AtomicUsize,
+    candidates: &[Candidate<'_>],
+) -> usize {
+    debug_assert!(!candidates.is_empty());
+    match strategy {
+        SharedSubscriptionStrategy::LocalPreferred if candidates[0].node.is_none() => 0,
+        SharedSubscriptionStrategy::LocalPreferred => {
+            counter.fetch_add(1, Ordering::Relaxed) % candidates.len()
+        }
+        SharedSubscriptionStrategy::RoundRobin => {
+            counter.fetch_add(1, Ordering::Relaxed) % candidates.len()
+        }
+        SharedSubscriptionStrategy::LeastInflight => {
+            let least = candidates.iter().map(|c| c.inflight).min().unwrap_or(0);
+            let tied: SmallVec<[usize; 4]> = candidates
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| c.inflight == least)
+                .map(|(i, _)| i)
+                .collect();
+            tied[counter.fetch_add(1, Ordering::Relaxed) % tied.len()]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates() -> Vec<Candidate<'static>> {
+        vec![
+            Candidate {
+                node: None,
+                inflight: 5,
+            },
+            Candidate {
+                node: Some("node-a"),
+                inflight: 1,
+            },
+            Candidate {
+                node: Some("node-b"),
+                inflight: 1,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_round_robin_rotates_over_all_nodes() {
+        let counter = AtomicUsize::new(0);
+        let picks: Vec<usize> = (0..6)
+            .map(|_| {
+                choose(
+                    SharedSubscriptionStrategy::RoundRobin,
+                    &counter,
+                    &candidates(),
+                )
+            })
+            .collect();
+        assert_eq!(picks, vec![0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn test_local_preferred() {
+        let counter = AtomicUsize::new(0);
+        let strategy = SharedSubscriptionStrategy::LocalPreferred;
+        assert_eq!(choose(strategy, &counter, &candidates()), 0);
+
+        // Without a local member, rotate over the remote nodes
+        let remote = &candidates()[1..];
+        assert_eq!(choose(strategy, &counter, remote), 0);
+        assert_eq!(choose(strategy, &counter, remote), 1);
+    }
+
+    #[test]
+    fn test_least_inflight_rotates_among_ties() {
+        let counter = AtomicUsize::new(0);
+        let strategy = SharedSubscriptionStrategy::LeastInflight;
+        assert_eq!(choose(strategy, &counter, &candidates()), 1);
+        assert_eq!(choose(strategy, &counter, &candidates()), 2);
+    }
+
+    #[test]
+    fn test_table_matching_and_bump() {
+        let mut table = SharedGroupTable::default();
+        table.replace(HashMap::from([(
+            "workers".to_string(),
+            SharedGroupState {
+                filters: vec!["jobs/+".to_string()],
+                inflight: 0,
+            },
+        )]));
+
+        assert!(table.matching("events/build").is_empty());
+
+        table.bump("workers");
+        assert_eq!(
+            table.matching("jobs/build").to_vec(),
+            vec![("workers".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_route_assignment() {
+        let mut route = SharedRoute::default();
+        route.assign("node-a", "workers".to_string());
+
+        assert!(route.is_remote("workers"));
+        assert!(!route.is_remote("other"));
+        assert_eq!(route.take("node-a"), vec!["workers"]);
+        assert!(route.take("node-a").is_empty());
+    }
+}
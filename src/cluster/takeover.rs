@@ -0,0 +1,174 @@
+//! Cluster Session Takeover
+//!
+//! Tracks which node owns each client, and the takeover requests sent to
+//! the node that previously owned a client's session, until that node
+//! replies with the session state.
+
+use std::sync::Arc;
+
+use dashmap::{DashMap, DashSet};
+use futures_util::future::BoxFuture;
+use tokio::sync::oneshot;
+
+use crate::persistence::StoredSession;
+
+/// Callback invoked when another node takes over one of our clients
+///
+/// Receives the client ID and whether the session state is wanted, and
+/// resolves to the client's persistent session (if any) after disconnecting
+/// it. Reading back spilled queue segments makes this asynchronous.
+pub type ClusterTakeoverCallback =
+    Arc<dyn Fn(String, bool) -> BoxFuture<'static, Option<StoredSession>> + Send + Sync>;
+
+/// Cluster-wide client registry
+///
+/// Each node sends its peers the clients it owns: the full list when a peer
+/// link comes up, then the clients that connect and leave. Entries of clients
+/// that left are dropped, so the registry only holds current clients.
+#[derive(Default)]
+pub(crate) struct ClientRegistry {
+    /// Clients owned by this node
+    local: DashSet<String>,
+    /// Owning node of each client owned by a peer
+    owners: DashMap<String, String>,
+}
+
+impl ClientRegistry {
+    /// Record that this node owns a client, returning false if it already did
+    pub(crate) fn claim(&self, client_id: &str) -> bool {
+        self.local.insert(client_id.to_string())
+    }
+
+    /// Record that this node no longer owns a client, returning false if it
+    /// did not
+    pub(crate) fn release(&self, client_id: &str) -> bool {
+        self.local.remove(client_id).is_some()
+    }
+
+    /// Clients owned by this node
+    pub(crate) fn local_clients(&self) -> Vec<String> {
+        self.local.iter().map(|c| c.key().clone()).collect()
+    }
+
+    /// Peer owning a client, if any
+    pub(crate) fn owner(&self, client_id: &str) -> Option<String> {
+        self.owners.get(client_id).map(|o| o.value().clone())
+    }
+
+    /// Whether a client is owned by this node or a peer other than `node_id`
+    pub(crate) fn owned_elsewhere(&self, client_id: &str, node_id: &str) -> bool {
+        self.local.contains(client_id)
+            || self
+                .owners
+                .get(client_id)
+                .is_some_and(|owner| *owner != node_id)
+    }
+
+    /// Forget a peer's client after taking it over
+    pub(crate) fn forget(&self, client_id: &str) {
+        self.owners.remove(client_id);
+    }
+
+    /// Replace the clients a peer owns with the full list it sent
+    pub(crate) fn apply_sync(&self, node_id: &str, clients: Vec<String>) {
+        self.remove_node(node_id);
+        for client_id in clients {
+            self.owners.insert(client_id, node_id.to_string());
+        }
+    }
+
+    /// Apply the clients that connected to or left a peer; a client that
+    /// reconnected elsewhere meanwhile keeps its newer owner
+    pub(crate) fn apply_update(&self, node_id: &str, claimed: Vec<String>, released: Vec<String>) {
+        for client_id in claimed {
+            self.owners.insert(client_id, node_id.to_string());
+        }
+        for client_id in released {
+            self.owners
+                .remove_if(&client_id, |_, owner| owner == node_id);
+        }
+    }
+
+    /// Drop every client of a peer that left the cluster
+    pub(crate) fn remove_node(&self, node_id: &str) {
+        self.owners.retain(|_, owner| owner != node_id);
+    }
+}
+
+/// Takeover requests awaiting a SessionTransfer reply, keyed by client ID
+#[derive(Default)]
+pub(crate) struct PendingTakeovers {
+    waiters: DashMap<String, oneshot::Sender<Option<StoredSession>>>,
+}
+
+impl PendingTakeovers {
+    /// Register a takeover request, replacing any earlier one for the client
+    pub(crate) fn register(&self, client_id: &str) -> oneshot::Receiver<Option<StoredSession>> {
+        let (tx, rx) = oneshot::channel();
+        self.waiters.insert(client_id.to_string(), tx);
+        rx
+    }
+
+    /// Complete a takeover request with the session received from the peer
+    pub(crate) fn complete(&self, client_id: &str, session: Option<StoredSession>) {
+        if let Some((_, tx)) = self.waiters.remove(client_id) {
+            let _ = tx.send(session);
+        }
+    }
+
+    /// Drop a takeover request that timed out
+    pub(crate) fn cancel(&self, client_id: &str) {
+        self.waiters.remove(client_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_complete_and_cancel() {
+        let pending = PendingTakeovers::default();
+
+        let rx = pending.register("client1");
+        pending.complete("client1", None);
+        assert!(rx.await.unwrap().is_none());
+
+        let rx = pending.register("client2");
+        pending.cancel("client2");
+        assert!(rx.await.is_err());
+
+        // Replies for unknown clients are ignored
+        pending.complete("client3", None);
+    }
+
+    #[test]
+    fn test_client_registry() {
+        let registry = ClientRegistry::default();
+        assert!(registry.claim("local"));
+        assert!(!registry.claim("local"));
+
+        registry.apply_sync("node2", vec!["a".into(), "b".into()]);
+        registry.apply_update("node3", vec!["b".into(), "c".into()], vec![]);
+        assert_eq!(registry.owner("a").as_deref(), Some("node2"));
+        assert_eq!(registry.owner("b").as_deref(), Some("node3"));
+
+        // A late release from the previous owner keeps the newer claim
+        registry.apply_update("node2", vec![], vec!["a".into(), "b".into()]);
+        assert_eq!(registry.owner("a"), None);
+        assert_eq!(registry.owner("b").as_deref(), Some("node3"));
+
+        assert!(registry.owned_elsewhere("local", "node3"));
+        assert!(registry.owned_elsewhere("c", "node2"));
+        assert!(!registry.owned_elsewhere("c", "node3"));
+
+        // A full sync replaces everything the peer sent before
+        registry.apply_sync("node3", vec!["d".into()]);
+        assert_eq!(registry.owner("b"), None);
+        registry.remove_node("node3");
+        assert_eq!(registry.owner("d"), None);
+
+        assert!(registry.release("local"));
+        assert!(registry.local_clients().is_empty());
+    }
+}
@@ -0,0 +1,95 @@
+//! Cluster Topology
+//!
+//! Zone and region labels of cluster nodes. Nodes gossip their labels so
+//! shared subscription delivery can prefer nodes in the same zone, then the
+//! same region, keeping traffic off cross-zone links where possible.
+
+use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
+
+use crate::config::ClusterConfig;
+
+/// Where a node runs
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Locality {
+    pub zone: Option<String>,
+    pub region: Option<String>,
+}
+
+impl Locality {
+    pub fn from_config(config: &ClusterConfig) -> Self {
+        Self {
+            zone: config.zone.clone(),
+            region: config.region.clone(),
+        }
+    }
+
+    /// Whether any label is set
+    pub fn is_labeled(&self) -> bool {
+        self.zone.is_some() || self.region.is_some()
+    }
+
+    /// How far away another node is: 0 in the same zone, 1 in the same
+    /// region, 2 elsewhere or unknown
+    pub fn distance(&self, other: &Locality) -> u8 {
+        if self.zone.is_some() && self.zone == other.zone {
+            0
+        } else if self.region.is_some() && self.region == other.region {
+            1
+        } else {
+            2
+        }
+    }
+}
+
+/// Keep only the nearest items
+pub(crate) fn retain_nearest<T>(items: &mut SmallVec<[(T, u8); 4]>) {
+    if let Some(nearest) = items.iter().map(|(_, d)| *d).min() {
+        items.retain(|(_, d)| *d == nearest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locality(zone: Option<&str>, region: Option<&str>) -> Locality {
+        Locality {
+            zone: zone.map(String::from),
+            region: region.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_distance() {
+        let here = locality(Some("us-east-1a"), Some("us-east-1"));
+        assert_eq!(here.distance(&here.clone()), 0);
+        assert_eq!(
+            here.distance(&locality(Some("us-east-1b"), Some("us-east-1"))),
+            1
+        );
+        assert_eq!(
+            here.distance(&locality(Some("eu-west-1a"), Some("eu-west-1"))),
+            2
+        );
+        assert_eq!(here.distance(&Locality::default()), 2);
+
+        // Unlabeled nodes never count as near
+        let unlabeled = Locality::default();
+        assert!(!unlabeled.is_labeled());
+        assert_eq!(unlabeled.distance(&Locality::default()), 2);
+    }
+
+    #[test]
+    fn test_retain_nearest() {
+        let mut items: SmallVec<[(&str, u8); 4]> =
+            smallvec::smallvec![("a", 2), ("b", 1), ("c", 1)];
+        retain_nearest(&mut items);
+        assert_eq!(items.as_slice(), &[("b", 1), ("c", 1)]);
+
+        let mut empty: SmallVec<[(&str, u8); 4]> = SmallVec::new();
+        retain_nearest(&mut empty);
+        assert!(empty.is_empty());
+    }
+}
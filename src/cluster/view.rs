@@ -0,0 +1,196 @@
+//! Cluster View
+//!
+//! Aggregated, cluster-wide state for the admin API and $SYS topics. Each
+//! node piggybacks its connection count and inter-node traffic on gossip
+//! (`NodeStats`); the view combines that with gossip liveness, the link to
+//! each peer and the subscription filters each node advertises.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use crate::remote::RemotePeerStatus;
+
+use super::topology::Locality;
+
+/// Statistics a node advertises to the cluster via gossip
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeStats {
+    /// Connected clients
+    pub connections: usize,
+    /// Client subscriptions
+    pub subscriptions: usize,
+    /// Publishes forwarded to other nodes
+    pub messages_sent: u64,
+    /// Bytes forwarded to other nodes
+    pub bytes_sent: u64,
+    /// Publishes received from other nodes
+    pub messages_received: u64,
+    /// Bytes received from other nodes
+    pub bytes_received: u64,
+}
+
+/// One node as seen from this node
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeView {
+    pub node_id: String,
+    /// Whether gossip considers the node alive
+    pub alive: bool,
+    /// Zone and region of the node
+    pub locality: Locality,
+    /// State of our link to the node (`None` for this node)
+    pub link: Option<RemotePeerStatus>,
+    /// Cluster protocol version spoken on the link
+    pub protocol_version: Option<u8>,
+    /// Publishes queued for the node
+    pub queue_depth: usize,
+    /// Last statistics the node advertised
+    pub stats: NodeStats,
+    /// Traffic between this node and the node
+    pub traffic: TrafficSnapshot,
+}
+
+/// Aggregated cluster state
+#[derive(Debug, Clone, Serialize)]
+pub struct ClusterView {
+    /// ID of the node that built the view
+    pub node_id: String,
+    /// Whether this node has lost quorum
+    pub partitioned: bool,
+    /// All known nodes, this one first
+    pub nodes: Vec<NodeView>,
+    /// Subscription filter -> nodes with subscribers for it
+    pub subscriptions: BTreeMap<String, Vec<String>>,
+}
+
+impl ClusterView {
+    /// Total connected clients across live nodes
+    pub fn total_connections(&self) -> usize {
+        self.nodes
+            .iter()
+            .filter(|n| n.alive)
+            .map(|n| n.stats.connections)
+            .sum()
+    }
+}
+
+/// Inter-node traffic counters of one peer link
+#[derive(Default)]
+pub(crate) struct TrafficStats {
+    messages_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    messages_received: AtomicU64,
+    bytes_received: AtomicU64,
+}
+
+/// Point-in-time copy of `TrafficStats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TrafficSnapshot {
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    pub messages_received: u64,
+    pub bytes_received: u64,
+}
+
+impl TrafficStats {
+    pub(crate) fn record_sent(&self, messages: usize, bytes: usize) {
+        self.messages_sent
+            .fetch_add(messages as u64, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_received(&self, messages: usize, bytes: usize) {
+        self.messages_received
+            .fetch_add(messages as u64, Ordering::Relaxed);
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> TrafficSnapshot {
+        TrafficSnapshot {
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl std::ops::AddAssign for TrafficSnapshot {
+    fn add_assign(&mut self, other: Self) {
+        self.messages_sent += other.messages_sent;
+        self.bytes_sent += other.bytes_sent;
+        self.messages_received += other.messages_received;
+        self.bytes_received += other.bytes_received;
+    }
+}
+
+/// Gossip-derived state of a remote node, cached by the gossip watcher
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct NodeHealth {
+    pub(crate) alive: bool,
+    pub(crate) stats: NodeStats,
+}
+
+/// Add a node's filters to the cluster subscription table
+pub(crate) fn add_subscriptions(
+    table: &mut BTreeMap<String, Vec<String>>,
+    node_id: &str,
+    filters: impl IntoIterator<Item = String>,
+) {
+    for filter in filters {
+        table.entry(filter).or_default().push(node_id.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traffic_stats() {
+        let traffic = TrafficStats::default();
+        traffic.record_sent(3, 300);
+        traffic.record_sent(1, 50);
+        traffic.record_received(2, 20);
+
+        let mut total = traffic.snapshot();
+        assert_eq!(total.messages_sent, 4);
+        assert_eq!(total.bytes_sent, 350);
+        assert_eq!(total.messages_received, 2);
+        assert_eq!(total.bytes_received, 20);
+
+        total += traffic.snapshot();
+        assert_eq!(total.messages_sent, 8);
+    }
+
+    #[test]
+    fn test_node_stats_roundtrip() {
+        let stats = NodeStats {
+            connections: 10,
+            subscriptions: 25,
+            messages_sent: 100,
+            bytes_sent: 4096,
+            messages_received: 7,
+            bytes_received: 512,
+        };
+        let json = serde_json::to_string(&stats).unwrap();
+        assert_eq!(serde_json::from_str::<NodeStats>(&json).unwrap(), stats);
+    }
+
+    #[test]
+    fn test_subscription_table() {
+        let mut table = BTreeMap::new();
+        add_subscriptions(
+            &mut table,
+            "node1",
+            vec!["a/#".to_string(), "b".to_string()],
+        );
+        add_subscriptions(&mut table, "node2", vec!["a/#".to_string()]);
+
+        assert_eq!(table["a/#"], vec!["node1", "node2"]);
+        assert_eq!(table["b"], vec!["node1"]);
+    }
+}
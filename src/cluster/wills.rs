@@ -0,0 +1,129 @@
+//! Cluster Will Coordination
+//!
+//! Each node replicates the will messages of its connected clients via
+//! gossip. While the owner is alive it publishes its clients' wills itself;
+//! when gossip declares it dead, one surviving node (the live node with the
+//! lowest ID) publishes them after their delay, unless the client has
+//! reconnected elsewhere in the meantime.
+//!
+//! Wills are gossiped as base64-encoded bincode, keeping binary payloads
+//! compact in the gossip state.
+
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bincode::{Decode, Encode};
+
+use crate::persistence::StoredProperties;
+use crate::protocol::{Properties, Publish, QoS};
+use crate::session::WillMessage;
+
+/// A client's will as replicated to the cluster
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct ClusterWill {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: u8,
+    pub retain: bool,
+    /// Seconds to wait before publishing: the will delay, capped by the
+    /// session expiry (the will is due when either ends)
+    pub delay: u32,
+    /// Will properties, re-emitted with the will
+    pub properties: StoredProperties,
+}
+
+impl ClusterWill {
+    pub fn new(will: &WillMessage, will_delay_interval: u32, session_expiry_interval: u32) -> Self {
+        Self {
+            topic: will.topic.clone(),
+            payload: will.payload.to_vec(),
+            qos: will.qos as u8,
+            retain: will.retain,
+            delay: will_delay_interval.min(session_expiry_interval),
+            properties: StoredProperties::from(&will.properties),
+        }
+    }
+
+    /// The PUBLISH sent for this will once its delay has passed, or `None`
+    /// if it expired meanwhile
+    pub fn to_publish(&self) -> Option<Publish> {
+        WillMessage {
+            topic: self.topic.clone(),
+            payload: self.payload.clone().into(),
+            qos: QoS::from_u8(self.qos).unwrap_or(QoS::AtMostOnce),
+            retain: self.retain,
+            properties: Properties::from(self.properties.clone()),
+        }
+        .to_publish(Duration::from_secs(self.delay as u64))
+    }
+
+    /// Encode for the gossip state
+    pub fn to_gossip(&self) -> String {
+        let encoded = bincode::encode_to_vec(self, bincode::config::standard())
+            .expect("encoding a will cannot fail");
+        BASE64.encode(encoded)
+    }
+
+    /// Decode from the gossip state
+    pub fn from_gossip(value: &str) -> Option<Self> {
+        let encoded = BASE64.decode(value).ok()?;
+        bincode::decode_from_slice(&encoded, bincode::config::standard())
+            .ok()
+            .map(|(will, _)| will)
+    }
+}
+
+/// The node responsible for the wills of dead nodes
+pub(crate) fn successor<'a>(live_nodes: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    live_nodes.into_iter().min()
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn test_will_delay_capped_by_session_expiry() {
+        let will = WillMessage {
+            topic: "clients/c1/status".to_string(),
+            payload: Bytes::from_static(b"offline"),
+            qos: QoS::AtLeastOnce,
+            retain: true,
+            properties: Properties {
+                content_type: Some("text/plain".to_string()),
+                message_expiry_interval: Some(30),
+                will_delay_interval: Some(60),
+                ..Default::default()
+            },
+        };
+
+        let replicated = ClusterWill::new(&will, 60, 10);
+        assert_eq!(replicated.delay, 10);
+        assert_eq!(replicated.payload, b"offline");
+        assert_eq!(replicated.qos, 1);
+        assert_eq!(ClusterWill::new(&will, 5, u32::MAX).delay, 5);
+
+        // Properties survive, with the expiry counting down over the delay
+        let publish = replicated.to_publish().unwrap();
+        assert_eq!(
+            publish.properties.content_type.as_deref(),
+            Some("text/plain")
+        );
+        assert_eq!(publish.properties.message_expiry_interval, Some(20));
+        assert_eq!(publish.properties.will_delay_interval, None);
+        assert!(ClusterWill::new(&will, 45, u32::MAX).to_publish().is_none());
+
+        let gossip = replicated.to_gossip();
+        assert_eq!(ClusterWill::from_gossip(&gossip), Some(replicated));
+        assert_eq!(ClusterWill::from_gossip("not a will"), None);
+    }
+
+    #[test]
+    fn test_successor_is_lowest_live_node() {
+        assert_eq!(successor(["node3", "node1", "node2"]), Some("node1"));
+        assert_eq!(successor(Vec::<&str>::new()), None);
+    }
+}
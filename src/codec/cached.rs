@@ -0,0 +1,76 @@
+//! Cached PUBLISH encodings
+//!
+//! The decoder keeps the wire bytes of each PUBLISH it reads as a
+//! [`RawPublish`]. A message sent on to subscribers with the topic,
+//! properties, payload and QoS it arrived with encodes to those same bytes
+//! but for the fixed header flags and the packet identifier, so a
+//! [`SharedPublish`](crate::protocol::SharedPublish) fanned out to
+//! subscribers on the publisher's protocol version starts from them rather
+//! than encoding the message again.
+
+use bytes::Bytes;
+
+use super::read_variable_int;
+use crate::protocol::{Properties, ProtocolVersion, Publish, QoS};
+
+/// A PUBLISH as it was received, with its wire bytes
+#[derive(Debug, Clone)]
+pub struct RawPublish {
+    /// The whole packet, fixed header included
+    bytes: Bytes,
+    /// Protocol version the packet was encoded with
+    version: ProtocolVersion,
+    /// Offset of the topic, past its length prefix
+    topic_offset: usize,
+    /// Offset of the packet identifier, or where it would be at QoS 0
+    packet_id_offset: usize,
+    /// The properties the packet was decoded with
+    properties: Properties,
+}
+
+impl RawPublish {
+    /// Keep `bytes`, the encoding of `publish` with `version`; the payload
+    /// of `publish` must share them
+    pub(crate) fn new(bytes: Bytes, version: ProtocolVersion, publish: &Publish) -> Self {
+        let (_, length_bytes) =
+            read_variable_int(&bytes[1..]).expect("decoded packets have a valid length");
+        let topic_offset = 1 + length_bytes + 2;
+        Self {
+            packet_id_offset: topic_offset + publish.topic.len(),
+            topic_offset,
+            bytes,
+            version,
+            properties: publish.properties.clone(),
+        }
+    }
+
+    /// Protocol version the packet was encoded with
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    /// The wire bytes
+    pub fn bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    /// Offset of the packet identifier, or where it would be at QoS 0
+    pub fn packet_id_offset(&self) -> usize {
+        self.packet_id_offset
+    }
+
+    /// Whether the wire bytes encode `publish`, but for the fixed header
+    /// flags and the packet identifier
+    ///
+    /// The payload has to be the one decoded from the bytes rather than an
+    /// equal one, which keeps the check from comparing payloads.
+    pub fn encodes(&self, publish: &Publish) -> bool {
+        let Some(payload_offset) = self.bytes.len().checked_sub(publish.payload.len()) else {
+            return false;
+        };
+        QoS::from_u8((self.bytes[0] >> 1) & 0x03) == Some(publish.qos)
+            && self.bytes[payload_offset..].as_ptr() == publish.payload.as_ptr()
+            && self.bytes[self.topic_offset..self.packet_id_offset] == *publish.topic.as_bytes()
+            && self.properties == publish.properties
+    }
+}
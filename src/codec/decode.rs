@@ -4,31 +4,45 @@
 
 use bytes::Bytes;
 
-use super::{read_binary, read_string, read_variable_int, MAX_REMAINING_LENGTH};
+use super::{read_binary, read_string, read_variable_int, RawPublish, MAX_REMAINING_LENGTH};
 use crate::protocol::{
     Auth, ConnAck, Connect, DecodeError, Disconnect, Packet, Properties, ProtocolVersion, PubAck,
     PubComp, PubRec, PubRel, Publish, QoS, ReasonCode, SubAck, Subscribe, Subscription,
     SubscriptionOptions, UnsubAck, Unsubscribe, Will,
 };
+use crate::topic::Topic;
+
+/// Largest possible packet: fixed header byte, 4 length bytes and payload
+const MAX_PACKET_SIZE: usize = 1 + 4 + MAX_REMAINING_LENGTH;
 
 /// MQTT Packet Decoder
 pub struct Decoder {
-    /// Maximum packet size
+    /// Maximum packet size, fixed header included
     max_packet_size: usize,
     /// Current protocol version (set after CONNECT)
     protocol_version: Option<ProtocolVersion>,
+    /// Reject reserved flag bits and invalid UTF-8 in User Properties
+    strict: bool,
 }
 
 impl Decoder {
     pub fn new() -> Self {
         Self {
-            max_packet_size: MAX_REMAINING_LENGTH,
+            max_packet_size: MAX_PACKET_SIZE,
             protocol_version: None,
+            strict: true,
         }
     }
 
+    /// Accept packets with reserved flag bits set or invalid UTF-8 in User
+    /// Properties when not `strict`, as sloppy client firmware often sends
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
     pub fn with_max_packet_size(mut self, size: usize) -> Self {
-        self.max_packet_size = size.min(MAX_REMAINING_LENGTH);
+        self.max_packet_size = size.min(MAX_PACKET_SIZE);
         self
     }
 
@@ -40,9 +54,27 @@ impl Decoder {
         self.protocol_version
     }
 
+    fn decode_properties(&self, buf: &[u8]) -> Result<(Properties, usize), DecodeError> {
+        Properties::decode_with(buf, self.strict)
+    }
+
     /// Decode a packet from the buffer
     /// Returns (packet, bytes_consumed) or error
     pub fn decode(&mut self, buf: &[u8]) -> Result<Option<(Packet, usize)>, DecodeError> {
+        Ok(self
+            .decode_raw(buf)?
+            .map(|(packet, consumed, _)| (packet, consumed)))
+    }
+
+    /// Decode a packet from the buffer like [`Self::decode`], keeping the
+    /// wire bytes of a PUBLISH, which its payload shares, to send it on
+    /// without encoding it again
+    /// Returns (packet, bytes_consumed, raw PUBLISH) or error
+    #[allow(clippy::type_complexity)]
+    pub fn decode_raw(
+        &mut self,
+        buf: &[u8],
+    ) -> Result<Option<(Packet, usize, Option<RawPublish>)>, DecodeError> {
         if buf.len() < 2 {
             return Ok(None);
         }
@@ -61,8 +93,8 @@ impl Decoder {
 
         let total_len = 1 + len_bytes + remaining_length as usize;
 
-        // Check packet size limit
-        if remaining_length as usize > self.max_packet_size {
+        // Check packet size limit, which covers the whole packet [MQTT-3.2.2-15]
+        if total_len > self.max_packet_size {
             return Err(DecodeError::PacketTooLarge);
         }
 
@@ -74,10 +106,16 @@ impl Decoder {
         let payload_start = 1 + len_bytes;
         let payload = &buf[payload_start..total_len];
 
+        if packet_type == 3 {
+            // The packet is copied once, and the payload shares the copy
+            let bytes = Bytes::copy_from_slice(&buf[..total_len]);
+            let (publish, raw) = self.decode_publish(flags, &bytes, payload_start)?;
+            return Ok(Some((Packet::Publish(publish), total_len, raw)));
+        }
+
         let packet = match packet_type {
             1 => self.decode_connect(payload)?,
             2 => self.decode_connack(flags, payload)?,
-            3 => self.decode_publish(flags, payload)?,
             4 => self.decode_puback(flags, payload)?,
             5 => self.decode_pubrec(flags, payload)?,
             6 => self.decode_pubrel(flags, payload)?,
@@ -87,13 +125,13 @@ impl Decoder {
             10 => self.decode_unsubscribe(flags, payload)?,
             11 => self.decode_unsuback(flags, payload)?,
             12 => {
-                if flags != 0 {
+                if self.strict && flags != 0 {
                     return Err(DecodeError::InvalidFlags);
                 }
                 Packet::PingReq
             }
             13 => {
-                if flags != 0 {
+                if self.strict && flags != 0 {
                     return Err(DecodeError::InvalidFlags);
                 }
                 Packet::PingResp
@@ -103,7 +141,7 @@ impl Decoder {
             _ => return Err(DecodeError::InvalidPacketType(packet_type)),
         };
 
-        Ok(Some((packet, total_len)))
+        Ok(Some((packet, total_len, None)))
     }
 
     fn decode_connect(&mut self, payload: &[u8]) -> Result<Packet, DecodeError> {
@@ -140,7 +178,7 @@ impl Decoder {
         pos += 1;
 
         // Reserved bit must be 0
-        if (connect_flags & 0x01) != 0 {
+        if self.strict && (connect_flags & 0x01) != 0 {
             return Err(DecodeError::InvalidFlags);
         }
 
@@ -175,7 +213,7 @@ impl Decoder {
 
         // Properties (v5.0 only)
         let properties = if protocol_version == ProtocolVersion::V5 {
-            let (props, len) = Properties::decode(&payload[pos..])?;
+            let (props, len) = self.decode_properties(&payload[pos..])?;
             pos += len;
             props
         } else {
@@ -190,7 +228,7 @@ impl Decoder {
         let will = if will_flag {
             // Will properties (v5.0 only)
             let will_properties = if protocol_version == ProtocolVersion::V5 {
-                let (props, len) = Properties::decode(&payload[pos..])?;
+                let (props, len) = self.decode_properties(&payload[pos..])?;
                 pos += len;
                 props
             } else {
@@ -246,7 +284,7 @@ impl Decoder {
     }
 
     fn decode_connack(&self, flags: u8, payload: &[u8]) -> Result<Packet, DecodeError> {
-        if flags != 0 {
+        if self.strict && flags != 0 {
             return Err(DecodeError::InvalidFlags);
         }
 
@@ -256,7 +294,7 @@ impl Decoder {
 
         let acknowledge_flags = payload[0];
         // Only bit 0 is valid (session present), rest must be 0
-        if (acknowledge_flags & 0xFE) != 0 {
+        if self.strict && (acknowledge_flags & 0xFE) != 0 {
             return Err(DecodeError::InvalidFlags);
         }
 
@@ -268,7 +306,7 @@ impl Decoder {
                 let reason_code = ReasonCode::from_u8(reason_byte)
                     .ok_or(DecodeError::InvalidReasonCode(reason_byte))?;
                 let properties = if payload.len() > 2 {
-                    let (props, _) = Properties::decode(&payload[2..])?;
+                    let (props, _) = self.decode_properties(&payload[2..])?;
                     props
                 } else {
                     Properties::default()
@@ -288,7 +326,15 @@ impl Decoder {
         }))
     }
 
-    fn decode_publish(&self, flags: u8, payload: &[u8]) -> Result<Packet, DecodeError> {
+    /// Decode the PUBLISH in `packet`, whose variable header starts at
+    /// `start`; the wire bytes are kept unless lossy decoding changed them
+    fn decode_publish(
+        &self,
+        flags: u8,
+        packet: &Bytes,
+        start: usize,
+    ) -> Result<(Publish, Option<RawPublish>), DecodeError> {
+        let payload = &packet[start..];
         let dup = (flags & 0x08) != 0;
         let qos_bits = (flags >> 1) & 0x03;
         let retain = (flags & 0x01) != 0;
@@ -329,7 +375,7 @@ impl Decoder {
         // Properties (v5.0 only)
         let properties = match self.protocol_version {
             Some(ProtocolVersion::V5) => {
-                let (props, len) = Properties::decode(&payload[pos..])?;
+                let (props, len) = self.decode_properties(&payload[pos..])?;
                 pos += len;
                 props
             }
@@ -337,21 +383,40 @@ impl Decoder {
         };
 
         // Payload (remainder)
-        let message_payload = Bytes::copy_from_slice(&payload[pos..]);
+        let message_payload = packet.slice(start + pos..);
 
-        Ok(Packet::Publish(Publish {
+        let publish = Publish {
             dup,
             qos,
             retain,
-            topic: topic.to_string(),
+            topic: Topic::from(topic),
             packet_id,
             payload: message_payload,
             properties,
-        }))
+        };
+        // Lenient decoding replaces invalid UTF-8 in User Properties with
+        // U+FFFD; the wire bytes are reused unless something may have been
+        let lossless = self.strict
+            || !publish
+                .properties
+                .user_properties
+                .iter()
+                .any(|(key, value)| {
+                    key.contains(char::REPLACEMENT_CHARACTER)
+                        || value.contains(char::REPLACEMENT_CHARACTER)
+                });
+        let raw = lossless.then(|| {
+            RawPublish::new(
+                packet.clone(),
+                self.protocol_version.unwrap_or(ProtocolVersion::V311),
+                &publish,
+            )
+        });
+        Ok((publish, raw))
     }
 
     fn decode_puback(&self, flags: u8, payload: &[u8]) -> Result<Packet, DecodeError> {
-        if flags != 0 {
+        if self.strict && flags != 0 {
             return Err(DecodeError::InvalidFlags);
         }
 
@@ -367,7 +432,7 @@ impl Decoder {
                     let reason_code = ReasonCode::from_u8(payload[2])
                         .ok_or(DecodeError::InvalidReasonCode(payload[2]))?;
                     let properties = if payload.len() > 3 {
-                        let (props, _) = Properties::decode(&payload[3..])?;
+                        let (props, _) = self.decode_properties(&payload[3..])?;
                         props
                     } else {
                         Properties::default()
@@ -388,7 +453,7 @@ impl Decoder {
     }
 
     fn decode_pubrec(&self, flags: u8, payload: &[u8]) -> Result<Packet, DecodeError> {
-        if flags != 0 {
+        if self.strict && flags != 0 {
             return Err(DecodeError::InvalidFlags);
         }
 
@@ -404,7 +469,7 @@ impl Decoder {
                     let reason_code = ReasonCode::from_u8(payload[2])
                         .ok_or(DecodeError::InvalidReasonCode(payload[2]))?;
                     let properties = if payload.len() > 3 {
-                        let (props, _) = Properties::decode(&payload[3..])?;
+                        let (props, _) = self.decode_properties(&payload[3..])?;
                         props
                     } else {
                         Properties::default()
@@ -426,7 +491,7 @@ impl Decoder {
 
     fn decode_pubrel(&self, flags: u8, payload: &[u8]) -> Result<Packet, DecodeError> {
         // PUBREL must have flags 0010
-        if flags != 0x02 {
+        if self.strict && flags != 0x02 {
             return Err(DecodeError::InvalidFlags);
         }
 
@@ -442,7 +507,7 @@ impl Decoder {
                     let reason_code = ReasonCode::from_u8(payload[2])
                         .ok_or(DecodeError::InvalidReasonCode(payload[2]))?;
                     let properties = if payload.len() > 3 {
-                        let (props, _) = Properties::decode(&payload[3..])?;
+                        let (props, _) = self.decode_properties(&payload[3..])?;
                         props
                     } else {
                         Properties::default()
@@ -463,7 +528,7 @@ impl Decoder {
     }
 
     fn decode_pubcomp(&self, flags: u8, payload: &[u8]) -> Result<Packet, DecodeError> {
-        if flags != 0 {
+        if self.strict && flags != 0 {
             return Err(DecodeError::InvalidFlags);
         }
 
@@ -479,7 +544,7 @@ impl Decoder {
                     let reason_code = ReasonCode::from_u8(payload[2])
                         .ok_or(DecodeError::InvalidReasonCode(payload[2]))?;
                     let properties = if payload.len() > 3 {
-                        let (props, _) = Properties::decode(&payload[3..])?;
+                        let (props, _) = self.decode_properties(&payload[3..])?;
                         props
                     } else {
                         Properties::default()
@@ -501,7 +566,7 @@ impl Decoder {
 
     fn decode_subscribe(&self, flags: u8, payload: &[u8]) -> Result<Packet, DecodeError> {
         // SUBSCRIBE must have flags 0010
-        if flags != 0x02 {
+        if self.strict && flags != 0x02 {
             return Err(DecodeError::InvalidFlags);
         }
 
@@ -519,7 +584,7 @@ impl Decoder {
         // Properties (v5.0 only)
         let properties = match self.protocol_version {
             Some(ProtocolVersion::V5) => {
-                let (props, len) = Properties::decode(&payload[pos..])?;
+                let (props, len) = self.decode_properties(&payload[pos..])?;
                 pos += len;
                 props
             }
@@ -541,8 +606,12 @@ impl Decoder {
                 return Err(DecodeError::InsufficientData);
             }
 
-            let options_byte = payload[pos];
+            let mut options_byte = payload[pos];
             pos += 1;
+            if !self.strict {
+                // Ignore the reserved bits
+                options_byte &= 0x3F;
+            }
 
             let options = match self.protocol_version {
                 Some(ProtocolVersion::V5) => SubscriptionOptions::from_byte(options_byte)
@@ -579,7 +648,7 @@ impl Decoder {
     }
 
     fn decode_suback(&self, flags: u8, payload: &[u8]) -> Result<Packet, DecodeError> {
-        if flags != 0 {
+        if self.strict && flags != 0 {
             return Err(DecodeError::InvalidFlags);
         }
 
@@ -593,7 +662,7 @@ impl Decoder {
         // Properties (v5.0 only)
         let properties = match self.protocol_version {
             Some(ProtocolVersion::V5) => {
-                let (props, len) = Properties::decode(&payload[pos..])?;
+                let (props, len) = self.decode_properties(&payload[pos..])?;
                 pos += len;
                 props
             }
@@ -633,7 +702,7 @@ impl Decoder {
 
     fn decode_unsubscribe(&self, flags: u8, payload: &[u8]) -> Result<Packet, DecodeError> {
         // UNSUBSCRIBE must have flags 0010
-        if flags != 0x02 {
+        if self.strict && flags != 0x02 {
             return Err(DecodeError::InvalidFlags);
         }
 
@@ -651,7 +720,7 @@ impl Decoder {
         // Properties (v5.0 only)
         let properties = match self.protocol_version {
             Some(ProtocolVersion::V5) => {
-                let (props, len) = Properties::decode(&payload[pos..])?;
+                let (props, len) = self.decode_properties(&payload[pos..])?;
                 pos += len;
                 props
             }
@@ -687,7 +756,7 @@ impl Decoder {
     }
 
     fn decode_unsuback(&self, flags: u8, payload: &[u8]) -> Result<Packet, DecodeError> {
-        if flags != 0 {
+        if self.strict && flags != 0 {
             return Err(DecodeError::InvalidFlags);
         }
 
@@ -700,7 +769,7 @@ impl Decoder {
         let (properties, reason_codes) = match self.protocol_version {
             Some(ProtocolVersion::V5) => {
                 let mut pos = 2;
-                let (props, len) = Properties::decode(&payload[pos..])?;
+                let (props, len) = self.decode_properties(&payload[pos..])?;
                 pos += len;
 
                 let mut codes = Vec::new();
@@ -723,7 +792,7 @@ impl Decoder {
     }
 
     fn decode_disconnect(&self, flags: u8, payload: &[u8]) -> Result<Packet, DecodeError> {
-        if flags != 0 {
+        if self.strict && flags != 0 {
             return Err(DecodeError::InvalidFlags);
         }
 
@@ -737,7 +806,7 @@ impl Decoder {
                     .ok_or(DecodeError::InvalidReasonCode(payload[0]))?;
 
                 let properties = if payload.len() > 1 {
-                    let (props, _) = Properties::decode(&payload[1..])?;
+                    let (props, _) = self.decode_properties(&payload[1..])?;
                     props
                 } else {
                     Properties::default()
@@ -761,7 +830,7 @@ impl Decoder {
     }
 
     fn decode_auth(&self, flags: u8, payload: &[u8]) -> Result<Packet, DecodeError> {
-        if flags != 0 {
+        if self.strict && flags != 0 {
             return Err(DecodeError::InvalidFlags);
         }
 
@@ -778,7 +847,7 @@ impl Decoder {
             ReasonCode::from_u8(payload[0]).ok_or(DecodeError::InvalidReasonCode(payload[0]))?;
 
         let properties = if payload.len() > 1 {
-            let (props, _) = Properties::decode(&payload[1..])?;
+            let (props, _) = self.decode_properties(&payload[1..])?;
             props
         } else {
             Properties::default()
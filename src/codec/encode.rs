@@ -2,6 +2,8 @@
 //!
 //! Encodes MQTT packets for both v3.1.1 and v5.0
 
+use std::sync::Arc;
+
 use bytes::{BufMut, BytesMut};
 
 use super::{variable_int_len, write_binary, write_string, write_variable_int};
@@ -13,12 +15,15 @@ use crate::protocol::{
 /// MQTT Packet Encoder
 pub struct Encoder {
     protocol_version: ProtocolVersion,
+    /// Prefix stripped from the topics of outgoing PUBLISH packets
+    mount_point: Option<Arc<str>>,
 }
 
 impl Encoder {
     pub fn new(version: ProtocolVersion) -> Self {
         Self {
             protocol_version: version,
+            mount_point: None,
         }
     }
 
@@ -26,12 +31,28 @@ impl Encoder {
         self.protocol_version = version;
     }
 
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.protocol_version
+    }
+
+    /// Send PUBLISH topics below `mount_point` without it
+    pub fn set_mount_point(&mut self, mount_point: Option<Arc<str>>) {
+        self.mount_point = mount_point;
+    }
+
     /// Encode a packet to the buffer
     pub fn encode(&self, packet: &Packet, buf: &mut BytesMut) -> Result<(), EncodeError> {
         match packet {
             Packet::Connect(p) => self.encode_connect(p, buf),
             Packet::ConnAck(p) => self.encode_connack(p, buf),
             Packet::Publish(p) => self.encode_publish(p, buf),
+            Packet::SharedPublish(p) => {
+                if self.mount_point.is_some() {
+                    // The shared encoding keeps the topic as it is
+                    return self.encode_publish(p.publish(), buf);
+                }
+                p.encode(self.protocol_version, None, buf)
+            }
             Packet::PubAck(p) => self.encode_puback(p, buf),
             Packet::PubRec(p) => self.encode_pubrec(p, buf),
             Packet::PubRel(p) => self.encode_pubrel(p, buf),
@@ -201,8 +222,17 @@ impl Encoder {
     fn encode_publish(&self, packet: &Publish, buf: &mut BytesMut) -> Result<(), EncodeError> {
         let is_v5 = self.protocol_version == ProtocolVersion::V5;
 
+        let topic = match self.mount_point {
+            Some(ref mount_point) => packet
+                .topic
+                .strip_prefix(mount_point.as_ref())
+                .filter(|topic| !topic.is_empty())
+                .unwrap_or(&packet.topic),
+            None => &packet.topic,
+        };
+
         // Calculate remaining length
-        let mut remaining_length = 2 + packet.topic.len(); // topic length prefix + topic
+        let mut remaining_length = 2 + topic.len(); // topic length prefix + topic
 
         if packet.qos != QoS::AtMostOnce {
             remaining_length += 2; // packet identifier
@@ -216,19 +246,11 @@ impl Encoder {
         remaining_length += packet.payload.len();
 
         // Fixed header
-        let mut first_byte: u8 = 0x30; // PUBLISH type (0011)
-        if packet.dup {
-            first_byte |= 0x08;
-        }
-        first_byte |= (packet.qos as u8) << 1;
-        if packet.retain {
-            first_byte |= 0x01;
-        }
-        buf.put_u8(first_byte);
+        buf.put_u8(packet.fixed_header());
         write_variable_int(buf, remaining_length as u32)?;
 
         // Topic name
-        write_string(buf, &packet.topic)?;
+        write_string(buf, topic)?;
 
         // Packet identifier (only for QoS > 0)
         if let Some(packet_id) = packet.packet_id {
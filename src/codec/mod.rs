@@ -3,15 +3,19 @@
 //! Provides encoding and decoding for MQTT v3.1.1 and v5.0 packets
 //! in a unified manner.
 
+mod cached;
 mod decode;
 mod encode;
 
 #[cfg(test)]
 mod tests;
 
+pub use cached::RawPublish;
 pub use decode::Decoder;
 pub use encode::Encoder;
 
+use std::borrow::Cow;
+
 use crate::protocol::{DecodeError, EncodeError};
 use bytes::{BufMut, BytesMut};
 
@@ -137,6 +141,14 @@ pub fn read_string(buf: &[u8]) -> Result<(&str, usize), DecodeError> {
     Ok((s, total_len))
 }
 
+/// Read a string, replacing invalid UTF-8 rather than failing
+/// Returns (string, bytes_consumed) or error
+#[inline]
+pub fn read_string_lossy(buf: &[u8]) -> Result<(Cow<'_, str>, usize), DecodeError> {
+    let (data, len) = read_binary(buf)?;
+    Ok((String::from_utf8_lossy(data), len))
+}
+
 /// Read binary data
 /// Returns (data, bytes_consumed) or error
 #[inline]
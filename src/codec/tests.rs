@@ -11,8 +11,8 @@ use pretty_assertions::assert_eq;
 use crate::codec::{Decoder, Encoder};
 use crate::protocol::{
     Auth, ConnAck, Connect, DecodeError, Disconnect, Packet, Properties, ProtocolVersion, PubAck,
-    PubComp, PubRec, PubRel, Publish, QoS, ReasonCode, RetainHandling, SubAck, Subscribe,
-    Subscription, SubscriptionOptions, UnsubAck, Unsubscribe, Will,
+    PubComp, PubRec, PubRel, Publish, QoS, ReasonCode, RetainHandling, SharedPublish, SubAck,
+    Subscribe, Subscription, SubscriptionOptions, UnsubAck, Unsubscribe, Will,
 };
 
 // ============================================================================
@@ -335,7 +335,7 @@ fn test_publish_qos0() {
         dup: false,
         qos: QoS::AtMostOnce,
         retain: false,
-        topic: "test/topic".to_string(),
+        topic: "test/topic".into(),
         packet_id: None,
         payload: Bytes::from("hello world"),
         properties: Properties::default(),
@@ -352,7 +352,7 @@ fn test_publish_qos1() {
         dup: false,
         qos: QoS::AtLeastOnce,
         retain: false,
-        topic: "test/topic".to_string(),
+        topic: "test/topic".into(),
         packet_id: Some(1234),
         payload: Bytes::from("hello world"),
         properties: Properties::default(),
@@ -369,7 +369,7 @@ fn test_publish_qos2() {
         dup: true,
         qos: QoS::ExactlyOnce,
         retain: true,
-        topic: "sensors/temp".to_string(),
+        topic: "sensors/temp".into(),
         packet_id: Some(65535),
         payload: Bytes::from(r#"{"temp": 25.5}"#),
         properties: Properties::default(),
@@ -394,7 +394,7 @@ fn test_publish_v5_with_properties() {
         dup: false,
         qos: QoS::AtLeastOnce,
         retain: false,
-        topic: "data/stream".to_string(),
+        topic: "data/stream".into(),
         packet_id: Some(100),
         payload: Bytes::from(r#"{"value": 42}"#),
         properties: props,
@@ -405,13 +405,113 @@ fn test_publish_v5_with_properties() {
     assert_eq!(packet, decoded);
 }
 
+#[test]
+fn test_shared_publish_matches_publish() {
+    let mut props = Properties::default();
+    props.content_type = Some("application/json".to_string());
+    // Long enough for a two byte remaining length
+    let publish = Publish {
+        dup: false,
+        qos: QoS::ExactlyOnce,
+        retain: true,
+        topic: "data/stream".into(),
+        packet_id: None,
+        payload: Bytes::from(vec![7u8; 300]),
+        properties: props,
+    };
+    let shared = SharedPublish::new(publish.clone());
+
+    for version in [ProtocolVersion::V311, ProtocolVersion::V5] {
+        for packet_id in [1, 0x1234, 0xFFFF] {
+            let mut expected = publish.clone();
+            expected.packet_id = Some(packet_id);
+            let mut buf = BytesMut::from(&b"prefix"[..]);
+            shared.encode(version, Some(packet_id), &mut buf).unwrap();
+            assert_eq!(
+                &buf[6..],
+                &encode_packet(&Packet::Publish(expected), version)[..]
+            );
+        }
+    }
+
+    let mut qos0 = publish;
+    qos0.qos = QoS::AtMostOnce;
+    let shared = SharedPublish::new(qos0.clone());
+    let mut buf = BytesMut::new();
+    shared.encode(ProtocolVersion::V5, None, &mut buf).unwrap();
+    assert_eq!(
+        buf,
+        encode_packet(&Packet::Publish(qos0.clone()), ProtocolVersion::V5)
+    );
+    let [header, rest] = shared.frames(ProtocolVersion::V5).unwrap();
+    assert_eq!([&header[..], &rest[..]].concat(), buf);
+}
+
+#[test]
+fn test_shared_publish_reuses_raw_bytes() {
+    let mut props = Properties::default();
+    props.content_type = Some("text/plain".to_string());
+    let received = Publish {
+        dup: true,
+        qos: QoS::AtLeastOnce,
+        retain: true,
+        topic: "sensors/1".into(),
+        packet_id: Some(42),
+        payload: Bytes::from_static(b"21.5"),
+        properties: props,
+    };
+    let wire = encode_packet(&Packet::Publish(received), ProtocolVersion::V5);
+    let mut decoder = Decoder::new();
+    decoder.set_protocol_version(ProtocolVersion::V5);
+    let (publish, raw) = match decoder.decode_raw(&wire).unwrap() {
+        Some((Packet::Publish(publish), _, Some(raw))) => (publish, raw),
+        other => panic!("expected a raw publish, got {:?}", other),
+    };
+
+    // Sent on with the flags of its own and a packet id of the connection
+    let mut outgoing = publish.clone();
+    outgoing.dup = false;
+    outgoing.retain = false;
+    outgoing.packet_id = None;
+    assert!(raw.encodes(&outgoing));
+    let shared = SharedPublish::with_raw(outgoing.clone(), Some(&raw));
+    let mut buf = BytesMut::new();
+    shared
+        .encode(ProtocolVersion::V5, Some(7), &mut buf)
+        .unwrap();
+    outgoing.packet_id = Some(7);
+    assert_eq!(
+        buf,
+        encode_packet(&Packet::Publish(outgoing), ProtocolVersion::V5)
+    );
+
+    // A changed message or an equal payload of its own is encoded anew
+    let mut downgraded = publish.clone();
+    downgraded.qos = QoS::AtMostOnce;
+    assert!(!raw.encodes(&downgraded));
+    let mut renamed = publish.clone();
+    renamed.topic = "sensors/2".into();
+    assert!(!raw.encodes(&renamed));
+    let mut copied = publish;
+    copied.payload = Bytes::copy_from_slice(b"21.5");
+    assert!(!raw.encodes(&copied));
+
+    // Lenient decoding keeps the bytes of a packet it did not change
+    let mut lenient = Decoder::new().with_strict(false);
+    lenient.set_protocol_version(ProtocolVersion::V5);
+    assert!(matches!(
+        lenient.decode_raw(&wire).unwrap(),
+        Some((Packet::Publish(_), _, Some(_)))
+    ));
+}
+
 #[test]
 fn test_publish_empty_payload() {
     let packet = Packet::Publish(Publish {
         dup: false,
         qos: QoS::AtMostOnce,
         retain: true,
-        topic: "clear/retained".to_string(),
+        topic: "clear/retained".into(),
         packet_id: None,
         payload: Bytes::new(), // Empty payload clears retained message
         properties: Properties::default(),
@@ -1062,6 +1162,23 @@ fn test_packet_too_large() {
     assert!(matches!(result, Err(DecodeError::PacketTooLarge)));
 }
 
+#[test]
+fn test_packet_size_includes_fixed_header() {
+    let mut decoder = Decoder::new().with_max_packet_size(9);
+    decoder.set_protocol_version(ProtocolVersion::V311);
+
+    // 9 bytes in total: accepted
+    let publish = [0x30, 0x07, 0x00, 0x01, b't', b'a', b'b', b'c', b'd'];
+    assert!(decoder.decode(&publish).unwrap().is_some());
+
+    // 10 bytes in total: rejected, though the remaining length is only 8
+    let publish = [0x30, 0x08, 0x00, 0x01, b't', b'a', b'b', b'c', b'd', b'e'];
+    assert!(matches!(
+        decoder.decode(&publish),
+        Err(DecodeError::PacketTooLarge)
+    ));
+}
+
 #[test]
 fn test_incomplete_packet() {
     let mut decoder = Decoder::new();
@@ -1108,6 +1225,55 @@ fn test_string_invalid_utf8() {
     assert!(matches!(result, Err(DecodeError::InvalidUtf8)));
 }
 
+#[test]
+fn test_lenient_decoding() {
+    let mut decoder = Decoder::new().with_strict(false);
+    decoder.set_protocol_version(ProtocolVersion::V5);
+
+    // PUBACK with reserved flag bits set
+    let puback = [0x42, 0x02, 0x00, 0x01];
+    assert!(matches!(
+        decode_packet(&puback, Some(ProtocolVersion::V5)),
+        Err(DecodeError::InvalidFlags)
+    ));
+    let (packet, _) = decoder.decode(&puback).unwrap().unwrap();
+    assert!(matches!(
+        packet,
+        Packet::PubAck(PubAck { packet_id: 1, .. })
+    ));
+
+    // SUBSCRIBE with reserved subscription option bits set
+    let subscribe = [0x82, 0x07, 0x00, 0x01, 0x00, 0x00, 0x01, b'a', 0xC1];
+    assert!(matches!(
+        decode_packet(&subscribe, Some(ProtocolVersion::V5)),
+        Err(DecodeError::InvalidSubscriptionOptions)
+    ));
+    let (packet, _) = decoder.decode(&subscribe).unwrap().unwrap();
+    match packet {
+        Packet::Subscribe(sub) => assert_eq!(sub.subscriptions[0].options.qos, QoS::AtLeastOnce),
+        other => panic!("expected SUBSCRIBE, got {:?}", other),
+    }
+
+    // PUBLISH with invalid UTF-8 in a User Property value
+    let publish = [
+        0x30, 0x0C, 0x00, 0x01, b't', 0x07, 0x26, 0x00, 0x01, b'k', 0x00, 0x01, 0xFF, b'x',
+    ];
+    assert!(matches!(
+        decode_packet(&publish, Some(ProtocolVersion::V5)),
+        Err(DecodeError::InvalidUtf8)
+    ));
+    let (packet, _, raw) = decoder.decode_raw(&publish).unwrap().unwrap();
+    match packet {
+        Packet::Publish(publish) => assert_eq!(
+            publish.properties.user_properties,
+            vec![("k".to_string(), "\u{FFFD}".to_string())]
+        ),
+        other => panic!("expected PUBLISH, got {:?}", other),
+    }
+    // The replaced bytes are not forwarded as received
+    assert!(raw.is_none());
+}
+
 // ============================================================================
 // Round-trip Tests
 // ============================================================================
@@ -1129,7 +1295,7 @@ fn test_roundtrip_all_packet_types_v311() {
             dup: false,
             qos: QoS::AtLeastOnce,
             retain: false,
-            topic: "test".to_string(),
+            topic: "test".into(),
             packet_id: Some(1),
             payload: Bytes::from("data"),
             properties: Properties::default(),
@@ -1185,7 +1351,7 @@ fn test_roundtrip_all_packet_types_v5() {
             dup: false,
             qos: QoS::ExactlyOnce,
             retain: true,
-            topic: "test".to_string(),
+            topic: "test".into(),
             packet_id: Some(1),
             payload: Bytes::from("data"),
             properties: Properties::default(),
@@ -1340,7 +1506,7 @@ mod proptest_tests {
                 dup: false,
                 qos: QoS::AtMostOnce,
                 retain,
-                topic,
+                topic: topic.into(),
                 packet_id: None,
                 payload: Bytes::from(payload),
                 properties: Properties::default(),
@@ -1363,7 +1529,7 @@ mod proptest_tests {
                 dup,
                 qos: QoS::AtLeastOnce,
                 retain,
-                topic,
+                topic: topic.into(),
                 packet_id: Some(packet_id),
                 payload: Bytes::from(payload),
                 properties: Properties::default(),
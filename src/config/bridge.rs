@@ -1,7 +1,10 @@
 //! Bridge Configuration
 //!
-//! Configuration structures for MQTT bridge connections.
+//! Configuration structures for MQTT bridge connections, HTTP webhook
+//! bridges, and Kafka, NATS and AMQP bridges (each behind the cargo
+//! feature of the same name).
 
+use std::collections::HashMap;
 use std::time::Duration;
 
 use serde::Deserialize;
@@ -19,6 +22,16 @@ pub enum BridgeProtocol {
     Ws,
     /// MQTT over WebSocket with TLS
     Wss,
+    /// Apache Kafka (requires the `kafka` feature)
+    Kafka,
+    /// NATS core (requires the `nats` feature)
+    Nats,
+    /// AMQP 0-9-1, e.g. RabbitMQ (requires the `amqp` feature)
+    Amqp,
+    /// HTTP webhook (outbound only)
+    Http,
+    /// HTTP webhook over TLS (outbound only)
+    Https,
 }
 
 impl std::fmt::Display for BridgeProtocol {
@@ -28,6 +41,11 @@ impl std::fmt::Display for BridgeProtocol {
             BridgeProtocol::Mqtts => write!(f, "mqtts"),
             BridgeProtocol::Ws => write!(f, "ws"),
             BridgeProtocol::Wss => write!(f, "wss"),
+            BridgeProtocol::Kafka => write!(f, "kafka"),
+            BridgeProtocol::Nats => write!(f, "nats"),
+            BridgeProtocol::Amqp => write!(f, "amqp"),
+            BridgeProtocol::Http => write!(f, "http"),
+            BridgeProtocol::Https => write!(f, "https"),
         }
     }
 }
@@ -40,18 +58,39 @@ impl BridgeProtocol {
             BridgeProtocol::Mqtts => 8883,
             BridgeProtocol::Ws => 80,
             BridgeProtocol::Wss => 443,
+            BridgeProtocol::Kafka => 9092,
+            BridgeProtocol::Nats => 4222,
+            BridgeProtocol::Amqp => 5672,
+            BridgeProtocol::Http => 80,
+            BridgeProtocol::Https => 443,
         }
     }
 
+    /// Check if this build includes the protocol (non-MQTT protocols are
+    /// behind the cargo feature of the same name)
+    pub fn is_available(&self) -> bool {
+        (*self != BridgeProtocol::Kafka || cfg!(feature = "kafka"))
+            && (*self != BridgeProtocol::Nats || cfg!(feature = "nats"))
+            && (*self != BridgeProtocol::Amqp || cfg!(feature = "amqp"))
+    }
+
     /// Check if this protocol uses TLS
     pub fn uses_tls(&self) -> bool {
-        matches!(self, BridgeProtocol::Mqtts | BridgeProtocol::Wss)
+        matches!(
+            self,
+            BridgeProtocol::Mqtts | BridgeProtocol::Wss | BridgeProtocol::Https
+        )
     }
 
     /// Check if this protocol uses WebSocket
     pub fn uses_websocket(&self) -> bool {
         matches!(self, BridgeProtocol::Ws | BridgeProtocol::Wss)
     }
+
+    /// Check if this protocol posts messages to an HTTP endpoint
+    pub fn uses_http(&self) -> bool {
+        matches!(self, BridgeProtocol::Http | BridgeProtocol::Https)
+    }
 }
 
 /// Direction of message forwarding
@@ -82,13 +121,47 @@ pub struct ForwardRule {
     #[serde(default)]
     pub direction: ForwardDirection,
 
-    /// QoS level for forwarded messages (caps the original QoS)
+    /// QoS level for forwarded messages (applied according to `qos_mode`)
     #[serde(default = "default_qos")]
     pub qos: u8,
 
+    /// How `qos` applies to the original QoS of a message
+    #[serde(default)]
+    pub qos_mode: QosMode,
+
     /// Whether to forward retained messages
     #[serde(default = "default_true")]
     pub retain: bool,
+
+    /// Regex matched against the whole source topic (local for `out`,
+    /// remote for `in`). The destination topic is then a template that
+    /// refers to its capture groups as `$1` or `${name}`.
+    #[serde(default)]
+    pub topic_regex: Option<String>,
+}
+
+/// How a forward rule's QoS applies to forwarded messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QosMode {
+    /// Downgrade to the rule's QoS, never upgrade
+    #[default]
+    Cap,
+    /// Always forward with the rule's QoS, upgrading or downgrading
+    Fixed,
+    /// Keep the original QoS, ignoring the rule's QoS
+    Preserve,
+}
+
+impl QosMode {
+    /// QoS of a forwarded message, given its original QoS and the rule's QoS
+    pub fn apply(self, original: u8, rule: u8) -> u8 {
+        match self {
+            QosMode::Cap => original.min(rule),
+            QosMode::Fixed => rule,
+            QosMode::Preserve => original,
+        }
+    }
 }
 
 /// Loop prevention strategy
@@ -141,6 +214,16 @@ pub struct BridgeConfig {
     /// Remote broker address (host:port or just host)
     pub address: String,
 
+    /// Further addresses of the remote broker, tried in turn when the
+    /// previous one cannot be reached
+    #[serde(default)]
+    pub fallback_addresses: Vec<String>,
+
+    /// Zone-local addresses of the remote broker (zone -> address)
+    /// A cluster node in one of these zones connects there first
+    #[serde(default)]
+    pub zone_addresses: HashMap<String, String>,
+
     /// Connection protocol
     #[serde(default)]
     pub protocol: BridgeProtocol,
@@ -171,10 +254,26 @@ pub struct BridgeConfig {
     #[serde(default = "default_max_reconnect_interval", with = "humantime_serde")]
     pub max_reconnect_interval: Duration,
 
+    /// Factor the reconnect interval grows by after each failed attempt
+    #[serde(default = "default_backoff_multiplier")]
+    pub backoff_multiplier: f64,
+
+    /// Random spread of each reconnect delay, as a fraction of it (0.0-1.0)
+    #[serde(default = "default_reconnect_jitter")]
+    pub reconnect_jitter: f64,
+
     /// Connection timeout (e.g., "30s", "1m")
     #[serde(default = "default_connect_timeout", with = "humantime_serde")]
     pub connect_timeout: Duration,
 
+    /// How often remote subscriptions are renewed, so ones lost when the
+    /// remote broker restarts come back (e.g., "5m"; "0s" disables)
+    #[serde(
+        default = "default_subscription_audit_interval",
+        with = "humantime_serde"
+    )]
+    pub subscription_audit_interval: Duration,
+
     /// Topic forwarding rules
     #[serde(default, alias = "forward")]
     pub forwards: Vec<ForwardRule>,
@@ -199,6 +298,39 @@ pub struct BridgeConfig {
     /// Defaults to the bridge name if not specified
     #[serde(default)]
     pub origin_id: Option<String>,
+
+    /// Bridges a message may cross before it is no longer forwarded
+    /// (user property loop prevention)
+    #[serde(default = "default_max_hops")]
+    pub max_hops: u32,
+
+    /// Outbound messages buffered in memory while the remote broker is unreachable
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
+
+    /// Disk spool for outbound messages beyond `queue_size`
+    #[serde(default)]
+    pub spool: BridgeSpoolConfig,
+
+    /// Outbound rate limits
+    #[serde(default)]
+    pub rate_limit: BridgeRateLimitConfig,
+
+    /// Kafka settings (when using kafka)
+    #[serde(default)]
+    pub kafka: BridgeKafkaConfig,
+
+    /// AMQP settings (when using amqp)
+    #[serde(default)]
+    pub amqp: BridgeAmqpConfig,
+
+    /// Webhook settings (when using http or https)
+    #[serde(default)]
+    pub http: BridgeHttpConfig,
+}
+
+fn default_max_hops() -> u32 {
+    8
 }
 
 fn default_client_id() -> String {
@@ -217,19 +349,37 @@ fn default_max_reconnect_interval() -> Duration {
     Duration::from_secs(60)
 }
 
+fn default_backoff_multiplier() -> f64 {
+    2.0
+}
+
+fn default_reconnect_jitter() -> f64 {
+    0.1
+}
+
 fn default_connect_timeout() -> Duration {
     Duration::from_secs(30)
 }
 
+fn default_subscription_audit_interval() -> Duration {
+    Duration::from_secs(300)
+}
+
 fn default_ws_path() -> String {
     "/mqtt".to_string()
 }
 
+fn default_queue_size() -> usize {
+    10_000
+}
+
 impl Default for BridgeConfig {
     fn default() -> Self {
         Self {
             name: "default".to_string(),
             address: "localhost:1883".to_string(),
+            fallback_addresses: Vec::new(),
+            zone_addresses: HashMap::new(),
             protocol: BridgeProtocol::default(),
             client_id: default_client_id(),
             username: None,
@@ -238,13 +388,23 @@ impl Default for BridgeConfig {
             clean_start: true,
             reconnect_interval: Duration::from_secs(5),
             max_reconnect_interval: Duration::from_secs(60),
+            backoff_multiplier: default_backoff_multiplier(),
+            reconnect_jitter: default_reconnect_jitter(),
             connect_timeout: Duration::from_secs(30),
+            subscription_audit_interval: default_subscription_audit_interval(),
             forwards: Vec::new(),
             tls: None,
             ws_path: default_ws_path(),
             enabled: true,
             loop_prevention: LoopPrevention::default(),
             origin_id: None,
+            max_hops: default_max_hops(),
+            queue_size: default_queue_size(),
+            spool: BridgeSpoolConfig::default(),
+            rate_limit: BridgeRateLimitConfig::default(),
+            kafka: BridgeKafkaConfig::default(),
+            amqp: BridgeAmqpConfig::default(),
+            http: BridgeHttpConfig::default(),
         }
     }
 }
@@ -252,12 +412,36 @@ impl Default for BridgeConfig {
 impl BridgeConfig {
     /// Parse address into host and port
     pub fn parse_address(&self) -> (String, u16) {
-        if let Some((host, port_str)) = self.address.rsplit_once(':') {
+        self.split_address(&self.address)
+    }
+
+    /// Parse one of the bridge's addresses into host and port
+    pub fn split_address(&self, address: &str) -> (String, u16) {
+        if let Some((host, port_str)) = address.rsplit_once(':') {
             if let Ok(port) = port_str.parse::<u16>() {
                 return (host.to_string(), port);
             }
         }
-        (self.address.clone(), self.protocol.default_port())
+        (address.to_string(), self.protocol.default_port())
+    }
+
+    /// All addresses of the remote broker, in the order they are tried
+    pub fn addresses(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.address.as_str())
+            .chain(self.fallback_addresses.iter().map(String::as_str))
+    }
+
+    /// Prefer the address for this node's zone, keeping the others as fallbacks
+    pub fn localize(&mut self, zone: &str) {
+        let Some(local) = self.zone_addresses.get(zone) else {
+            return;
+        };
+        if *local == self.address {
+            return;
+        }
+        let previous = std::mem::replace(&mut self.address, local.clone());
+        self.fallback_addresses.retain(|a| a != local);
+        self.fallback_addresses.insert(0, previous);
     }
 
     /// Get outbound forwarding rules (local → remote)
@@ -310,6 +494,211 @@ pub struct BridgeTlsConfig {
 
     /// Server name for SNI (defaults to address hostname)
     pub server_name: Option<String>,
+
+    /// ALPN protocols to offer, e.g. `["x-amzn-mqtt-ca"]` for AWS IoT Core on port 443
+    #[serde(default)]
+    pub alpn: Vec<String>,
+}
+
+/// Disk spool for bridge messages that do not fit the memory queue
+///
+/// Spooled messages are kept in the persistence backend, so the spool needs
+/// persistence to be enabled.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BridgeSpoolConfig {
+    /// Spill to disk when the memory queue is full
+    pub enabled: bool,
+
+    /// Maximum messages held on disk
+    pub max_messages: usize,
+}
+
+impl Default for BridgeSpoolConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_messages: 1_000_000,
+        }
+    }
+}
+
+/// Outbound rate limits of a bridge
+///
+/// Messages over the limit wait in the queue. Each limit allows bursts of
+/// one second's worth.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct BridgeRateLimitConfig {
+    /// Messages sent per second (0 = unlimited)
+    pub messages_per_sec: u32,
+
+    /// Payload bytes sent per second (0 = unlimited)
+    pub bytes_per_sec: u64,
+}
+
+impl BridgeRateLimitConfig {
+    /// Check if any limit is set
+    pub fn is_limited(&self) -> bool {
+        self.messages_per_sec > 0 || self.bytes_per_sec > 0
+    }
+}
+
+/// Kafka bridge settings
+///
+/// Forward rules name Kafka topics in `remote_topic`. Outbound messages are
+/// keyed by their MQTT topic, so messages of one MQTT topic stay in order
+/// on one partition.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct BridgeKafkaConfig {
+    /// MQTT topic levels (0-based) that form the record key, joined by `/`;
+    /// empty uses the whole topic. E.g. `[1]` keys `sensors/dev42/temp` by `dev42`.
+    pub key_levels: Vec<usize>,
+
+    /// Where inbound rules start consuming on each connection
+    pub start_offset: KafkaStartOffset,
+}
+
+impl BridgeKafkaConfig {
+    /// Record key for an MQTT topic
+    pub fn record_key(&self, topic: &str) -> String {
+        if self.key_levels.is_empty() {
+            return topic.to_string();
+        }
+        let levels: Vec<&str> = topic.split('/').collect();
+        self.key_levels
+            .iter()
+            .filter_map(|&i| levels.get(i).copied())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+/// Starting offset of a Kafka bridge's consumers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KafkaStartOffset {
+    /// Only records produced after connecting
+    #[default]
+    Latest,
+    /// Everything retained in the partition
+    Earliest,
+}
+
+/// AMQP bridge settings
+///
+/// Messages are published to a topic exchange with the remote topic as
+/// routing key (`/` becomes `.`, `+` becomes `*`); inbound rules bind an
+/// exclusive queue to the exchange with their remote filter.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BridgeAmqpConfig {
+    /// Topic exchange to publish to and consume from (declared unless it
+    /// is a predefined `amq.` exchange)
+    pub exchange: String,
+
+    /// Virtual host
+    pub vhost: String,
+}
+
+impl Default for BridgeAmqpConfig {
+    fn default() -> Self {
+        Self {
+            exchange: "amq.topic".to_string(),
+            vhost: "/".to_string(),
+        }
+    }
+}
+
+/// HTTP webhook bridge settings
+///
+/// Each forwarded message is sent as a request to `path` on the bridge
+/// address. `path` and header values are templates: `{topic}` is the remote
+/// topic, `{0}`, `{1}`, ... its levels, and `{qos}` / `{retain}` the
+/// message's QoS and retain flag. Topics and levels are percent-encoded in
+/// the path, `/` included.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BridgeHttpConfig {
+    /// Request path template
+    pub path: String,
+
+    /// Request method
+    pub method: String,
+
+    /// Request headers; values are templates
+    pub headers: HashMap<String, String>,
+
+    /// Messages per request. Above 1, consecutive messages for the same
+    /// path and headers are sent together as a JSON array.
+    pub batch_size: usize,
+
+    /// Timeout for each request
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+impl Default for BridgeHttpConfig {
+    fn default() -> Self {
+        Self {
+            path: "/".to_string(),
+            method: "POST".to_string(),
+            headers: HashMap::new(),
+            batch_size: 1,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Topic prefix of exported broker events
+pub const EXPORT_TOPIC_PREFIX: &str = "$export";
+
+/// Broker events exported through the bridges
+///
+/// Each event becomes a JSON message on `$export/<event>`, which bridges
+/// with a forward rule for it (such as `$export/#`) carry like a published
+/// message: an `http` bridge posts it, batched with `http.batch_size`, and
+/// a `kafka` bridge produces it to the rule's Kafka topic.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct EventExportConfig {
+    /// Events to export (none by default)
+    pub events: Vec<ExportedEvent>,
+}
+
+/// A kind of broker event that can be exported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportedEvent {
+    /// A client connected
+    ClientConnected,
+    /// A client disconnected, with the reason
+    ClientDisconnected,
+    /// A client subscribed to a topic filter
+    Subscribed,
+    /// A client unsubscribed from a topic filter
+    Unsubscribed,
+    /// A message could not be delivered to a client
+    DeliveryFailed,
+}
+
+impl ExportedEvent {
+    /// Name of the event, the last level of its topic
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExportedEvent::ClientConnected => "client_connected",
+            ExportedEvent::ClientDisconnected => "client_disconnected",
+            ExportedEvent::Subscribed => "subscribed",
+            ExportedEvent::Unsubscribed => "unsubscribed",
+            ExportedEvent::DeliveryFailed => "delivery_failed",
+        }
+    }
+
+    /// Topic the event is exported on
+    pub fn topic(&self) -> String {
+        format!("{}/{}", EXPORT_TOPIC_PREFIX, self.as_str())
+    }
 }
 
 #[cfg(test)]
@@ -339,6 +728,29 @@ mod tests {
         assert_eq!(port, 8883); // Default for mqtts
     }
 
+    #[test]
+    fn test_localize_prefers_zone_address() {
+        let mut config = BridgeConfig {
+            address: "broker.example.com:1883".to_string(),
+            fallback_addresses: vec!["broker-b.example.com:1883".to_string()],
+            zone_addresses: HashMap::from([(
+                "us-east-1b".to_string(),
+                "broker-b.example.com:1883".to_string(),
+            )]),
+            ..Default::default()
+        };
+
+        // Unknown zone keeps the configured order
+        config.localize("us-east-1a");
+        assert_eq!(config.address, "broker.example.com:1883");
+
+        config.localize("us-east-1b");
+        assert_eq!(
+            config.addresses().collect::<Vec<_>>(),
+            vec!["broker-b.example.com:1883", "broker.example.com:1883"]
+        );
+    }
+
     #[test]
     fn test_forward_direction() {
         let out_rule = ForwardRule {
@@ -346,7 +758,9 @@ mod tests {
             remote_topic: "remote/#".to_string(),
             direction: ForwardDirection::Out,
             qos: 1,
+            qos_mode: QosMode::Cap,
             retain: true,
+            topic_regex: None,
         };
         assert!(out_rule.is_outbound());
         assert!(!out_rule.is_inbound());
@@ -359,6 +773,27 @@ mod tests {
         assert!(both_rule.is_inbound());
     }
 
+    #[test]
+    fn test_kafka_record_key() {
+        let kafka = BridgeKafkaConfig::default();
+        assert_eq!(kafka.record_key("sensors/dev42/temp"), "sensors/dev42/temp");
+
+        let kafka = BridgeKafkaConfig {
+            key_levels: vec![1, 2, 7],
+            ..Default::default()
+        };
+        assert_eq!(kafka.record_key("sensors/dev42/temp"), "dev42/temp");
+    }
+
+    #[test]
+    fn test_qos_mode() {
+        assert_eq!(QosMode::Cap.apply(2, 1), 1);
+        assert_eq!(QosMode::Cap.apply(0, 1), 0);
+        assert_eq!(QosMode::Fixed.apply(0, 2), 2);
+        assert_eq!(QosMode::Fixed.apply(2, 0), 0);
+        assert_eq!(QosMode::Preserve.apply(2, 0), 2);
+    }
+
     #[test]
     fn test_protocol_defaults() {
         assert_eq!(BridgeProtocol::Mqtt.default_port(), 1883);
@@ -2,13 +2,212 @@
 //!
 //! Configuration types for gossip-based horizontal clustering.
 
+use std::collections::BTreeMap;
 use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
 use std::time::Duration;
 
 use serde::Deserialize;
 
 use super::ProxyProtocolConfig;
 
+/// How a message is assigned to one member node of a shared subscription group
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SharedSubscriptionStrategy {
+    /// Rotate through the nodes that have members in the group
+    #[default]
+    RoundRobin,
+    /// Deliver on the publishing node if it has a member, else rotate
+    LocalPreferred,
+    /// Pick the node whose group members have the fewest unacknowledged messages
+    LeastInflight,
+}
+
+/// Which message is dropped when a peer's outbound queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerQueuePolicy {
+    /// Drop the oldest queued message to make room
+    #[default]
+    DropOldest,
+    /// Drop the message being queued
+    DropNewest,
+}
+
+/// Compression of frames sent to cluster peers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClusterCompression {
+    /// Send frames uncompressed
+    #[default]
+    None,
+    /// LZ4: fast, moderate ratio
+    Lz4,
+    /// zstd: slower, better ratio for links between datacenters
+    Zstd,
+}
+
+impl ClusterCompression {
+    /// Check if this build includes the codec (zstd is behind the cargo
+    /// feature of the same name)
+    pub fn is_available(&self) -> bool {
+        *self != ClusterCompression::Zstd || cfg!(feature = "zstd")
+    }
+}
+
+/// Consistency model of cluster metadata
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Consistency {
+    /// Gossip only: nodes converge on retained messages and session owners
+    #[default]
+    Eventual,
+    /// Retained messages, session ownership, users and ACL roles are
+    /// replicated through raft
+    Strong,
+}
+
+/// Raft metadata store configuration (used with `consistency = "strong"`)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RaftConfig {
+    /// Address for raft RPCs to bind to
+    /// Default: 0.0.0.0:7948
+    pub bind: SocketAddr,
+
+    /// Raft members: node ID -> raft address ("host:port")
+    /// Every node should list the same members
+    pub members: BTreeMap<String, String>,
+
+    /// Leader heartbeat interval
+    /// Default: 250ms
+    #[serde(with = "humantime_serde")]
+    pub heartbeat_interval: Duration,
+
+    /// Minimum follower election timeout (randomized up to twice this)
+    /// Default: 1s
+    #[serde(with = "humantime_serde")]
+    pub election_timeout: Duration,
+
+    /// Directory holding the raft log, vote and snapshot
+    /// Default: ./data/raft
+    pub data_dir: PathBuf,
+
+    /// Mutual TLS between raft members (required with strong consistency)
+    pub tls: Option<RaftTlsConfig>,
+}
+
+impl Default for RaftConfig {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0:7948".parse().unwrap(),
+            members: BTreeMap::new(),
+            heartbeat_interval: Duration::from_millis(250),
+            election_timeout: Duration::from_secs(1),
+            data_dir: PathBuf::from("./data/raft"),
+            tls: None,
+        }
+    }
+}
+
+/// Certificates for raft RPCs; every member both presents `cert` and
+/// requires a peer certificate signed by `ca_cert`
+#[derive(Debug, Clone, Deserialize)]
+pub struct RaftTlsConfig {
+    /// Path to this node's certificate file (PEM format), valid for the
+    /// host of its address in `members`
+    pub cert: String,
+    /// Path to this node's private key file (PEM format)
+    pub key: String,
+    /// Path to the CA certificate file that signs member certificates (PEM format)
+    pub ca_cert: String,
+}
+
+/// What a node does while it is cut off from the majority of the cluster
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PartitionMode {
+    /// Keep serving local clients as usual
+    #[default]
+    Continue,
+    /// Keep serving local clients, but refuse writes to cluster-wide state
+    /// such as retained messages
+    ReadOnlyShared,
+}
+
+/// How cluster members are discovered in addition to static `seeds`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscoveryMethod {
+    /// Only the configured `seeds`
+    #[default]
+    Static,
+    /// A/AAAA records of `name` (e.g. a headless service)
+    Dns,
+    /// SRV records of `name`, which provide the gossip port (requires the
+    /// `dns-srv` feature)
+    DnsSrv,
+    /// Endpoints of a Kubernetes service, read from the API server
+    /// (requires the `kubernetes` feature)
+    Kubernetes,
+}
+
+impl DiscoveryMethod {
+    /// Cargo feature this method needs, if this build lacks it
+    pub fn missing_feature(&self) -> Option<&'static str> {
+        match self {
+            DiscoveryMethod::DnsSrv if !cfg!(feature = "dns-srv") => Some("dns-srv"),
+            DiscoveryMethod::Kubernetes if !cfg!(feature = "kubernetes") => Some("kubernetes"),
+            _ => None,
+        }
+    }
+}
+
+/// Dynamic cluster discovery configuration
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DiscoveryConfig {
+    /// Discovery method: "static" (default), "dns", "dns_srv", or "kubernetes"
+    pub method: DiscoveryMethod,
+
+    /// DNS name to resolve (required for "dns" and "dns_srv")
+    pub name: Option<String>,
+
+    /// Gossip port of discovered nodes ("dns" and "kubernetes")
+    /// Default: the port of gossip_addr
+    pub port: Option<u16>,
+
+    /// Kubernetes service whose endpoints are cluster members
+    pub service: Option<String>,
+
+    /// Kubernetes namespace
+    /// Default: the namespace of the pod's service account
+    pub namespace: Option<String>,
+
+    /// Kubernetes API server URL
+    pub api_server: String,
+
+    /// How often discovery is re-run to find nodes that joined later
+    /// Default: 30s
+    #[serde(with = "humantime_serde")]
+    pub refresh_interval: Duration,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            method: DiscoveryMethod::Static,
+            name: None,
+            port: None,
+            service: None,
+            namespace: None,
+            api_server: "https://kubernetes.default.svc".to_string(),
+            refresh_interval: Duration::from_secs(30),
+        }
+    }
+}
+
 /// Cluster configuration for gossip-based horizontal scaling
 #[derive(Debug, Clone, Deserialize)]
 #[serde(default)]
@@ -42,6 +241,16 @@ pub struct ClusterConfig {
     #[serde(default)]
     pub seeds: Vec<String>,
 
+    /// Dynamic discovery of further seed nodes
+    pub discovery: DiscoveryConfig,
+
+    /// Availability zone of this node (e.g., "us-east-1a")
+    /// Shared subscription delivery prefers nodes in the same zone
+    pub zone: Option<String>,
+
+    /// Region of this node (e.g., "us-east-1"), preferred after the zone
+    pub region: Option<String>,
+
     /// Gossip interval (e.g., "1s", "500ms")
     /// Default: 1s
     #[serde(default = "default_gossip_interval", with = "humantime_serde")]
@@ -57,6 +266,53 @@ pub struct ClusterConfig {
     #[serde(default = "default_dead_node_grace_period", with = "humantime_serde")]
     pub dead_node_grace_period: Duration,
 
+    /// How long a node waits for the previous owner of a client's session
+    /// to hand it over when the client reconnects here (e.g., "2s")
+    /// Default: 2s
+    #[serde(default = "default_session_takeover_timeout", with = "humantime_serde")]
+    pub session_takeover_timeout: Duration,
+
+    /// How `$share/<group>/...` messages are balanced across nodes:
+    /// "round_robin" (default), "local_preferred", or "least_inflight"
+    pub shared_subscription_strategy: SharedSubscriptionStrategy,
+
+    /// Maximum publishes buffered per peer while its link is down or slow
+    /// Default: 10000
+    pub peer_queue_size: usize,
+
+    /// What to drop when a peer queue is full: "drop_oldest" (default) or "drop_newest"
+    pub peer_queue_policy: PeerQueuePolicy,
+
+    /// Maximum queued publishes sent to a peer in one frame, and accepted
+    /// from one; a link uses the smaller of both nodes' values
+    /// Default: 64 (1 disables batching)
+    pub batch_size: usize,
+
+    /// Compression of peer frames: "none" (default), "lz4", or "zstd"
+    /// (zstd requires the zstd feature)
+    pub compression: ClusterCompression,
+
+    /// Frames smaller than this many bytes are sent uncompressed
+    /// Default: 1024
+    pub compression_threshold: usize,
+
+    /// Metadata consistency: "eventual" (default, gossip) or "strong" (raft)
+    pub consistency: Consistency,
+
+    /// Raft metadata store, used when consistency is "strong"
+    pub raft: RaftConfig,
+
+    /// Number of nodes in the full cluster, used to detect network partitions
+    /// A node without a quorum of these is partitioned. Default: 0 (disabled)
+    pub expected_nodes: usize,
+
+    /// Witness address ("host:port") that counts as one extra vote when a
+    /// TCP connection to it succeeds; breaks ties in even-sized clusters
+    pub witness: Option<String>,
+
+    /// Behavior while partitioned: "continue" (default) or "read_only_shared"
+    pub partition_mode: PartitionMode,
+
     /// PROXY protocol configuration for peer listener
     #[serde(default)]
     pub proxy_protocol: ProxyProtocolConfig,
@@ -82,6 +338,10 @@ fn default_dead_node_grace_period() -> Duration {
     Duration::from_secs(30)
 }
 
+fn default_session_takeover_timeout() -> Duration {
+    Duration::from_secs(2)
+}
+
 impl Default for ClusterConfig {
     fn default() -> Self {
         Self {
@@ -92,9 +352,24 @@ impl Default for ClusterConfig {
             peer_addr: default_peer_addr(),
             peer_advertise_addr: None,
             seeds: Vec::new(),
+            discovery: DiscoveryConfig::default(),
+            zone: None,
+            region: None,
             gossip_interval: Duration::from_secs(1),
             failure_timeout: Duration::from_secs(5),
             dead_node_grace_period: Duration::from_secs(30),
+            session_takeover_timeout: default_session_takeover_timeout(),
+            shared_subscription_strategy: SharedSubscriptionStrategy::default(),
+            peer_queue_size: 10000,
+            peer_queue_policy: PeerQueuePolicy::default(),
+            batch_size: 64,
+            compression: ClusterCompression::default(),
+            compression_threshold: 1024,
+            consistency: Consistency::default(),
+            raft: RaftConfig::default(),
+            expected_nodes: 0,
+            witness: None,
+            partition_mode: PartitionMode::default(),
             proxy_protocol: ProxyProtocolConfig::default(),
         }
     }
@@ -202,5 +477,37 @@ mod tests {
         assert_eq!(config.gossip_interval, Duration::from_secs(1));
         assert_eq!(config.failure_timeout, Duration::from_secs(5));
         assert_eq!(config.dead_node_grace_period, Duration::from_secs(30));
+        assert_eq!(config.session_takeover_timeout, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_peer_queue_defaults() {
+        let config = ClusterConfig::default();
+        assert_eq!(config.peer_queue_size, 10000);
+        assert_eq!(config.peer_queue_policy, PeerQueuePolicy::DropOldest);
+    }
+
+    #[test]
+    fn test_batching_defaults() {
+        let config = ClusterConfig::default();
+        assert_eq!(config.batch_size, 64);
+        assert_eq!(config.compression, ClusterCompression::None);
+        assert_eq!(config.compression_threshold, 1024);
+    }
+
+    #[test]
+    fn test_eventual_consistency_by_default() {
+        let config = ClusterConfig::default();
+        assert_eq!(config.consistency, Consistency::Eventual);
+        assert_eq!(config.raft.bind, "0.0.0.0:7948".parse().unwrap());
+        assert!(config.raft.members.is_empty());
+    }
+
+    #[test]
+    fn test_partition_detection_disabled_by_default() {
+        let config = ClusterConfig::default();
+        assert_eq!(config.expected_nodes, 0);
+        assert!(config.witness.is_none());
+        assert_eq!(config.partition_mode, PartitionMode::Continue);
     }
 }
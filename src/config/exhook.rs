@@ -0,0 +1,83 @@
+//! External gRPC hook provider configuration.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// Hooks an external provider can register, by their ExHook names
+pub const EXHOOK_NAMES: &[&str] = &[
+    "client.authenticate",
+    "client.authorize",
+    "client.connected",
+    "client.disconnected",
+    "session.unsubscribed",
+    "session.terminated",
+    "message.publish",
+    "message.delivered",
+    "message.dropped",
+];
+
+fn default_request_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+/// External gRPC hook provider configuration (requires the `exhook` feature)
+///
+/// The provider implements the `emqx.exhook.v2.HookProvider` service, so
+/// services written for EMQX's ExHook work unchanged.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ExHookConfig {
+    /// Forward hook callbacks to the provider
+    pub enabled: bool,
+    /// gRPC endpoint of the provider (http:// or https://)
+    pub url: String,
+    /// Connections to the provider, used in turn
+    pub pool_size: usize,
+    /// Deadline of each call (e.g., "5s")
+    #[serde(default = "default_request_timeout", with = "humantime_serde")]
+    pub request_timeout: Duration,
+    /// What a failed or timed out call decides
+    pub failed_action: FailedAction,
+    /// `failed_action` of individual hooks, by name (e.g. "message.publish")
+    pub failed_actions: HashMap<String, FailedAction>,
+    /// Order among the broker's hooks, highest first; the built-in
+    /// authentication and ACL have priority 0
+    pub priority: i32,
+}
+
+impl Default for ExHookConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: "http://127.0.0.1:9000".to_string(),
+            pool_size: 4,
+            request_timeout: default_request_timeout(),
+            failed_action: FailedAction::Deny,
+            failed_actions: HashMap::new(),
+            priority: 0,
+        }
+    }
+}
+
+impl ExHookConfig {
+    /// What a failed call of `hook` decides
+    pub fn failed_action(&self, hook: &str) -> FailedAction {
+        self.failed_actions
+            .get(hook)
+            .copied()
+            .unwrap_or(self.failed_action)
+    }
+}
+
+/// What a hook decides when it fails to answer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailedAction {
+    /// Fail closed: refuse the authentication, authorization or publish
+    #[default]
+    Deny,
+    /// Fail open: carry on as if the hook had no opinion
+    Ignore,
+}
@@ -0,0 +1,36 @@
+//! Hook isolation configuration.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use super::FailedAction;
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+/// Isolation of the broker's hooks (authentication, ACL and the external
+/// provider) from the connections calling them
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HooksConfig {
+    /// Run each hook call on a task of its own under `timeout`, so a hook
+    /// that hangs or panics fails that call instead of the connection
+    pub isolate: bool,
+    /// Deadline of each hook call (e.g., "5s")
+    #[serde(default = "default_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+    /// What a hook call that timed out or panicked decides
+    pub failed_action: FailedAction,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            isolate: false,
+            timeout: default_timeout(),
+            failed_action: FailedAction::Deny,
+        }
+    }
+}
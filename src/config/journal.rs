@@ -0,0 +1,47 @@
+//! Message journal configuration.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+fn default_max_age() -> Duration {
+    Duration::from_secs(24 * 60 * 60)
+}
+
+/// Message journal configuration
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JournalConfig {
+    /// Enable the message journal
+    pub enabled: bool,
+
+    /// Journal directory
+    pub path: PathBuf,
+
+    /// Topic filters whose messages are journaled
+    pub topics: Vec<String>,
+
+    /// Maximum total size of the journal in bytes (0 = unlimited)
+    pub max_bytes: u64,
+
+    /// Maximum age of journaled messages (e.g., "24h", 0 = unlimited)
+    #[serde(default = "default_max_age", with = "humantime_serde")]
+    pub max_age: Duration,
+
+    /// Size at which the active segment file is rolled over
+    pub segment_size: u64,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from("./data/journal"),
+            topics: vec!["#".to_string()],
+            max_bytes: 256 * 1024 * 1024,
+            max_age: default_max_age(),
+            segment_size: 16 * 1024 * 1024,
+        }
+    }
+}
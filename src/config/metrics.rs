@@ -2,6 +2,7 @@
 
 use serde::Deserialize;
 use std::net::SocketAddr;
+use std::time::Duration;
 
 /// Metrics configuration
 #[derive(Debug, Clone, Deserialize)]
@@ -10,14 +11,116 @@ pub struct MetricsConfig {
     /// Whether metrics are enabled
     pub enabled: bool,
     /// HTTP bind address for metrics endpoint
+    ///
+    /// Loopback by default: the server has no authentication and serves
+    /// session and client state.
     pub bind: SocketAddr,
+    /// Topic filters to export message, byte and subscriber metrics for,
+    /// labeled by filter. Each topic counts toward the first matching
+    /// filter only; other topics are not exported.
+    pub topic_patterns: Vec<String>,
+    /// What `/readyz` requires of the broker
+    pub health: HealthConfig,
+    /// Push the metrics to a statsd or Graphite server
+    pub push: Option<MetricsPushConfig>,
+    /// Accept rule changes at `POST /rules` and `DELETE /rules/<id>`; the
+    /// endpoint has no authentication, so bind it to a private address
+    pub rules_api: bool,
+    /// Accept user and ACL role changes at `/users/<username>` and
+    /// `/roles/<name>`, replicated through a cluster with
+    /// `consistency = "strong"`; unauthenticated like `rules_api`
+    pub users_api: bool,
+    /// Accept redirects of connecting and connected clients at `/redirect`;
+    /// unauthenticated like `rules_api`
+    pub redirect_api: bool,
+    /// Serve the message journal, payloads included, at `/journal`;
+    /// unauthenticated like `rules_api`
+    pub journal_api: bool,
 }
 
 impl Default for MetricsConfig {
     fn default() -> Self {
         Self {
             enabled: false,
-            bind: "0.0.0.0:9090".parse().unwrap(),
+            bind: "127.0.0.1:9090".parse().unwrap(),
+            topic_patterns: Vec::new(),
+            health: HealthConfig::default(),
+            push: None,
+            rules_api: false,
+            users_api: false,
+            redirect_api: false,
+            journal_api: false,
         }
     }
 }
+
+/// Readiness criteria
+///
+/// The broker is ready once it accepts connections on all its listeners
+/// and, as configured, the checks below pass. `/healthz` only checks that
+/// the process answers, so a dependency outage never restarts the broker.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    /// Not ready while the persistence backend fails to commit writes
+    pub persistence: bool,
+    /// Not ready while this node has lost cluster quorum
+    pub cluster_quorum: bool,
+    /// Bridges that must be connected
+    pub bridges: BridgeReadiness,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            persistence: true,
+            cluster_quorum: true,
+            bridges: BridgeReadiness::None,
+        }
+    }
+}
+
+/// Bridges that must be connected for the broker to be ready
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BridgeReadiness {
+    /// Bridge connectivity does not affect readiness
+    #[default]
+    None,
+    /// At least one bridge
+    Any,
+    /// Every bridge
+    All,
+}
+
+/// Push export of the metrics
+#[derive(Debug, Clone, Deserialize)]
+pub struct MetricsPushConfig {
+    pub protocol: PushProtocol,
+    /// Server address as host:port, resolved at each push
+    pub address: String,
+    /// Interval between pushes
+    #[serde(default = "default_push_interval", with = "humantime_serde")]
+    pub interval: Duration,
+    /// First component of every metric path
+    #[serde(default = "default_push_prefix")]
+    pub prefix: String,
+}
+
+fn default_push_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_push_prefix() -> String {
+    "vibemq".to_string()
+}
+
+/// Protocol metrics are pushed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PushProtocol {
+    /// statsd over UDP: gauges, and counters as deltas
+    Statsd,
+    /// Graphite plaintext over TCP
+    Graphite,
+}
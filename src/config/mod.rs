@@ -16,39 +16,83 @@ use std::time::Duration;
 
 use config::{Environment, File, FileFormat};
 use regex::Regex;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use tracing::level_filters::LevelFilter;
 
 use crate::flapping::{ConnectionLimitConfig, FlappingConfig};
 
 // Re-export bridge config types
 pub use bridge::{
-    BridgeConfig, BridgeProtocol, BridgeTlsConfig, ForwardDirection, ForwardRule, LoopPrevention,
+    BridgeAmqpConfig, BridgeConfig, BridgeHttpConfig, BridgeKafkaConfig, BridgeProtocol,
+    BridgeRateLimitConfig, BridgeSpoolConfig, BridgeTlsConfig, EventExportConfig, ExportedEvent,
+    ForwardDirection, ForwardRule, KafkaStartOffset, LoopPrevention, QosMode, EXPORT_TOPIC_PREFIX,
 };
 
 // Re-export cluster config types
-pub use cluster::ClusterConfig;
+pub use cluster::{
+    ClusterCompression, ClusterConfig, Consistency, DiscoveryConfig, DiscoveryMethod,
+    PartitionMode, PeerQueuePolicy, RaftConfig, RaftTlsConfig, SharedSubscriptionStrategy,
+};
 
 // Re-export metrics config types
-pub use metrics::MetricsConfig;
+pub use metrics::{BridgeReadiness, HealthConfig, MetricsConfig, MetricsPushConfig, PushProtocol};
 
 // Re-export proxy protocol config types
 pub use proxy::ProxyProtocolConfig;
 
 // Re-export persistence config types
-pub use persistence::{BackendType, PersistenceConfig};
+pub use persistence::{BackendType, CorruptionPolicy, OverflowPolicy, PersistenceConfig, SyncMode};
+
+// Re-export tenancy config types
+pub use tenancy::{TenancyConfig, TenantSource};
+
+// Re-export mount point config types
+pub use mount::MountPointConfig;
+
+// Re-export journal config types
+pub use journal::JournalConfig;
+
+// Re-export OpenTelemetry config types
+pub use otel::OtelConfig;
+
+// Re-export external hook provider config types
+pub use exhook::{ExHookConfig, FailedAction, EXHOOK_NAMES};
+
+// Re-export hook isolation config types
+pub use hooks::HooksConfig;
+
+// Re-export scripting hook config types
+pub use script::ScriptConfig;
+
+// Re-export rule engine config types
+pub use rules::{RuleActionConfig, RuleConfig};
+
+// Re-export payload schema config types
+pub use schema::{InvalidPayloadAction, SchemaFormat, SchemaRule};
 
 mod bridge;
 mod cluster;
+mod exhook;
+mod hooks;
+mod journal;
 mod metrics;
+mod mount;
+mod otel;
 mod persistence;
 mod proxy;
+mod rules;
+mod schema;
+mod script;
+mod tenancy;
 
 /// Substitute environment variables in a string.
-/// Supports `${VAR}` and `${VAR:-default}` syntax.
+/// Supports `${VAR}` and `${VAR:-default}` syntax; `$$` is a literal `$`.
 fn substitute_env_vars(content: &str) -> String {
-    let re = Regex::new(r"\$\{([^}:]+)(?::-([^}]*))?\}").unwrap();
+    let re = Regex::new(r"\$\$|\$\{([^}:]+)(?::-([^}]*))?\}").unwrap();
     re.replace_all(content, |caps: &regex::Captures| {
-        let var_name = &caps[1];
+        let Some(var_name) = caps.get(1).map(|m| m.as_str()) else {
+            return "$".to_string();
+        };
         let default = caps.get(2).map(|m| m.as_str()).unwrap_or("");
         std::env::var(var_name).unwrap_or_else(|_| default.to_string())
     })
@@ -124,6 +168,9 @@ pub struct Config {
     /// Bridge configurations
     #[serde(default)]
     pub bridge: Vec<BridgeConfig>,
+    /// Broker events exported through the bridges
+    #[serde(default)]
+    pub export: EventExportConfig,
     /// Cluster configuration (only first entry is used if multiple)
     #[serde(default)]
     pub cluster: Vec<ClusterConfig>,
@@ -133,6 +180,30 @@ pub struct Config {
     /// Persistence configuration
     #[serde(default)]
     pub persistence: PersistenceConfig,
+    /// Tenancy configuration
+    #[serde(default)]
+    pub tenancy: TenancyConfig,
+    /// Topic mount points per listener and user
+    #[serde(default)]
+    pub mount_points: MountPointConfig,
+    /// Message journal configuration
+    #[serde(default)]
+    pub journal: JournalConfig,
+    /// OpenTelemetry trace export configuration
+    #[serde(default)]
+    pub otel: OtelConfig,
+    /// External gRPC hook provider
+    #[serde(default)]
+    pub exhook: ExHookConfig,
+    /// Hook isolation
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    /// Scripting hook
+    #[serde(default)]
+    pub script: ScriptConfig,
+    /// Message routing rules
+    #[serde(default)]
+    pub rules: Vec<RuleConfig>,
 }
 
 /// Logging configuration
@@ -142,6 +213,13 @@ pub struct LogConfig {
     /// Log level: error, warn, info, debug, trace
     #[serde(default = "default_log_level")]
     pub level: String,
+    /// Output format of log lines
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Per-module levels overriding `level`, as `target=level`
+    /// (e.g., "vibemq::cluster=debug")
+    #[serde(default)]
+    pub filters: Vec<String>,
 }
 
 fn default_log_level() -> String {
@@ -152,10 +230,46 @@ impl Default for LogConfig {
     fn default() -> Self {
         Self {
             level: default_log_level(),
+            format: LogFormat::default(),
+            filters: Vec::new(),
         }
     }
 }
 
+impl LogConfig {
+    /// Targets and levels of the per-module filters
+    pub fn targets(&self) -> Result<Vec<(String, LevelFilter)>, ConfigError> {
+        self.filters
+            .iter()
+            .map(|filter| {
+                filter
+                    .split_once('=')
+                    .filter(|(target, _)| !target.is_empty())
+                    .and_then(|(target, level)| {
+                        Some((target.to_string(), level.parse::<LevelFilter>().ok()?))
+                    })
+                    .ok_or_else(|| {
+                        ConfigError::Validation(format!(
+                            "log.filters entry '{}' must be target=level, with level one of off, error, warn, info, debug, trace",
+                            filter
+                        ))
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Output format of log lines
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, for log collectors
+    Json,
+}
+
 /// Server configuration
 #[derive(Debug, Clone, Deserialize)]
 #[serde(default)]
@@ -170,9 +284,13 @@ pub struct ServerConfig {
     /// WebSocket path (default: "/mqtt")
     #[serde(default = "default_ws_path")]
     pub ws_path: String,
-    /// Number of worker threads (0 = auto)
+    /// Number of worker runtimes connections run on, each on a thread of
+    /// its own (0 = auto, one per CPU)
     #[serde(default)]
     pub workers: usize,
+    /// Pin each worker thread to a core of its own
+    #[serde(default = "default_true")]
+    pub pin_workers: bool,
     /// TLS configuration (required when tls_bind is set)
     #[serde(default)]
     pub tls: Option<ServerTlsConfig>,
@@ -217,6 +335,7 @@ impl Default for ServerConfig {
             ws_bind: None,
             ws_path: default_ws_path(),
             workers: 0,
+            pin_workers: true,
             tls: None,
             proxy_protocol: ProxyProtocolConfig::default(),
             tls_proxy_protocol: ProxyProtocolConfig::default(),
@@ -241,23 +360,64 @@ pub struct LimitsConfig {
     /// Maximum queued messages per offline client
     #[serde(default = "default_max_queued_messages")]
     pub max_queued_messages: usize,
+    /// What to do when a client's message queue is full
+    #[serde(default)]
+    pub queue_overflow: QueueOverflowPolicy,
+    /// Queued messages per persistent session kept in memory; the rest are
+    /// spilled to the persistence backend (0 = never spill)
+    #[serde(default)]
+    pub spill_threshold: usize,
     /// Maximum pending PUBREL for QoS 2
     #[serde(default = "default_max_awaiting_rel")]
     pub max_awaiting_rel: usize,
     /// Retry interval for unacked messages (e.g., "30s", "1m")
     #[serde(default = "default_retry_interval", with = "humantime_serde")]
     pub retry_interval: Duration,
+    /// Upper bound of the retry interval, which doubles after each
+    /// retransmission of a message. Set to 0 for a flat retry interval
+    /// (default).
+    #[serde(default, with = "humantime_serde")]
+    pub retry_max_interval: Duration,
+    /// Retransmissions of an unacked message before `retry_exhausted`
+    /// applies. Set to 0 for unlimited (default).
+    #[serde(default)]
+    pub max_retries: u32,
+    /// What to do with a message that ran out of retransmissions
+    #[serde(default)]
+    pub retry_exhausted: RetryExhaustedPolicy,
     /// Per-connection outbound message channel capacity.
     /// This buffer holds messages waiting to be written to the client socket.
     /// Higher values handle burst traffic better but use more memory per connection.
     /// Set to 0 for unbounded (not recommended for production).
     #[serde(default = "default_outbound_channel_capacity")]
     pub outbound_channel_capacity: usize,
+    /// Bytes of outbound packets coalesced before they are written to the
+    /// client socket at once. Set to 0 to write every packet on its own.
+    #[serde(default = "default_write_coalesce_bytes")]
+    pub write_coalesce_bytes: usize,
+    /// How long coalesced packets may wait for more to arrive before they
+    /// are written. Set to 0 to write them as soon as the outbound channel
+    /// is empty (default).
+    #[serde(default, with = "humantime_serde")]
+    pub write_coalesce_delay: Duration,
+    /// Subscribers from which a message is fanned out: subscribers that
+    /// receive it alike share one encoding per protocol version, and the
+    /// publisher yields to other tasks between batches of them.
+    /// Set to 0 to never fan out.
+    #[serde(default = "default_fanout_threshold")]
+    pub fanout_threshold: usize,
+    /// Detection of clients that read slower than messages arrive for them
+    #[serde(default)]
+    pub slow_consumer: SlowConsumerConfig,
     /// Maximum number of topic levels (depth) allowed.
     /// Topic levels are separated by '/'. For example, "a/b/c" has 3 levels.
     /// Set to 0 for unlimited (default).
     #[serde(default)]
     pub max_topic_levels: usize,
+    /// Maximum subscriptions per client; further SUBSCRIBEs are refused
+    /// with Quota Exceeded. Set to 0 for unlimited (default).
+    #[serde(default)]
+    pub max_subscriptions_per_client: usize,
     /// Flapping detection configuration (DoS protection)
     #[serde(default)]
     pub flapping_detect: FlappingConfig,
@@ -287,6 +447,12 @@ fn default_retry_interval() -> Duration {
 fn default_outbound_channel_capacity() -> usize {
     1024
 }
+fn default_write_coalesce_bytes() -> usize {
+    64 * 1024
+}
+fn default_fanout_threshold() -> usize {
+    128
+}
 
 impl Default for LimitsConfig {
     fn default() -> Self {
@@ -295,10 +461,20 @@ impl Default for LimitsConfig {
             max_packet_size: default_max_packet_size(),
             max_inflight: default_max_inflight(),
             max_queued_messages: default_max_queued_messages(),
+            queue_overflow: QueueOverflowPolicy::default(),
+            spill_threshold: 0,
             max_awaiting_rel: default_max_awaiting_rel(),
             retry_interval: Duration::from_secs(30),
+            retry_max_interval: Duration::ZERO,
+            max_retries: 0,
+            retry_exhausted: RetryExhaustedPolicy::default(),
             outbound_channel_capacity: default_outbound_channel_capacity(),
-            max_topic_levels: 0, // 0 = unlimited
+            write_coalesce_bytes: default_write_coalesce_bytes(),
+            write_coalesce_delay: Duration::ZERO,
+            fanout_threshold: default_fanout_threshold(),
+            slow_consumer: SlowConsumerConfig::default(),
+            max_topic_levels: 0,             // 0 = unlimited
+            max_subscriptions_per_client: 0, // 0 = unlimited
             flapping_detect: FlappingConfig::default(),
             connection_limit: ConnectionLimitConfig::default(),
         }
@@ -321,6 +497,10 @@ pub struct SessionConfig {
     /// Maximum topic aliases
     #[serde(default = "default_max_topic_aliases")]
     pub max_topic_aliases: u16,
+    /// Disconnect clients that neither subscribe nor publish within this
+    /// long of connecting (e.g., "5m"; 0 = disabled)
+    #[serde(default, with = "humantime_serde")]
+    pub parked_timeout: Duration,
 }
 
 fn default_keep_alive() -> u16 {
@@ -343,6 +523,7 @@ impl Default for SessionConfig {
             max_keep_alive: default_max_keep_alive(),
             expiry_check_interval: Duration::from_secs(60),
             max_topic_aliases: default_max_topic_aliases(),
+            parked_timeout: Duration::ZERO,
         }
     }
 }
@@ -366,12 +547,226 @@ pub struct MqttConfig {
     /// Whether shared subscriptions are available
     #[serde(default = "default_true")]
     pub shared_subscriptions: bool,
+    /// Keep alive assigned to v5 clients that request more than
+    /// `session.max_keep_alive`, returned as Server Keep Alive in CONNACK;
+    /// from 1 up to `session.max_keep_alive` (default:
+    /// `session.max_keep_alive`)
+    pub server_keep_alive: Option<u16>,
     /// Whether $SYS topics are published
     #[serde(default = "default_true")]
     pub sys_topics: bool,
     /// $SYS topic publish interval (e.g., "10s", "1m")
     #[serde(default = "default_sys_interval", with = "humantime_serde")]
     pub sys_interval: Duration,
+    /// Whether the most subscribed filters and the size of the subscription
+    /// trie are published under $SYS/broker/subscriptions/, which walks the
+    /// whole trie every `sys_interval`
+    pub sys_filter_topics: bool,
+    /// Whether per-session state is published under $SYS/sessions/<client>/
+    pub sys_session_topics: bool,
+    /// Whether the traffic, queue and inflight counters of each session are
+    /// published under $SYS/sessions/<client>/ (not $SYS/broker/clients/),
+    /// with or without `sys_session_topics`
+    pub sys_client_topics: bool,
+    /// Interval between updates of the traffic counters, no shorter than
+    /// `sys_interval`
+    #[serde(with = "humantime_serde")]
+    pub sys_client_interval: Duration,
+    /// Whether each client's connected or disconnected state is kept as a
+    /// retained message under $events/clients/<client>/
+    pub presence_topics: bool,
+    /// Maximum number of retained messages (0 = unlimited)
+    pub max_retained_messages: usize,
+    /// Maximum total size of retained messages in bytes, topic plus payload (0 = unlimited)
+    pub max_retained_bytes: usize,
+    /// What to do when a new retained message would exceed the limits
+    pub retained_policy: RetainedPolicy,
+    /// Refuse new connections, pointing clients at another server
+    pub redirect: Option<RedirectConfig>,
+    /// Reject publishes marked as UTF-8 (Payload Format Indicator 1) whose
+    /// payload is not valid UTF-8
+    pub validate_payload_format: bool,
+    /// Content Types required of publishes to matching topics
+    pub content_types: Vec<ContentTypeRule>,
+    /// Maximum payload size of publishes to matching topics, within
+    /// max_packet_size; the first matching rule applies
+    pub payload_limits: Vec<PayloadLimitRule>,
+    /// Schemas payloads published to matching topics must follow; the
+    /// first matching rule applies
+    pub schemas: Vec<SchemaRule>,
+    /// Disconnect clients for marginal protocol violations (reserved flag
+    /// bits, client IDs over 23 bytes, invalid UTF-8 in User Properties)
+    /// rather than accepting them
+    pub strict: bool,
+    /// Keep each subscriber's messages in publish order while flow control
+    /// holds some back, at the cost of also holding back QoS 0 messages
+    pub ordered_delivery: bool,
+    /// Delivery priority of messages on matching topics
+    pub topic_priorities: Vec<TopicPriorityRule>,
+    /// How a member of a `$share/<group>/...` group is picked for a message
+    pub shared_subscription_strategy: SharedDeliveryStrategy,
+    /// Strategies of individual share groups
+    pub shared_subscription_groups: Vec<SharedGroupRule>,
+    /// Share `$queue/<filter>` subscriptions among all their subscribers,
+    /// as the implicit `$queue` group; when off, `$queue/...` is a plain
+    /// topic filter
+    pub queue_subscriptions: bool,
+}
+
+/// How the member of a shared subscription group receiving a message is picked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SharedDeliveryStrategy {
+    /// Rotate through the members
+    #[default]
+    RoundRobin,
+    /// Keep sending a topic to the same member until it disconnects
+    Sticky,
+    /// Pick a random member
+    Random,
+    /// Pick the member with the fewest unacknowledged messages
+    LeastInflight,
+    /// Rotate through the members on this node, never balancing the group
+    /// across cluster nodes
+    LocalOnly,
+}
+
+/// Delivery strategy of one shared subscription group
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SharedGroupRule {
+    /// Share group name, as in `$share/<group>/...`
+    pub group: String,
+    pub strategy: SharedDeliveryStrategy,
+}
+
+/// Delivery priority of messages published to matching topics
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TopicPriorityRule {
+    /// Topic filter the rule applies to (wildcards allowed)
+    pub topic: String,
+    /// Priority of matching messages
+    pub priority: MessagePriority,
+}
+
+/// Priority class of a message in queues and the outbound channel
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessagePriority {
+    /// Shed first when a queue overflows
+    Low,
+    #[default]
+    Normal,
+    /// Delivered ahead of normal and low priority messages
+    High,
+}
+
+/// Content Types accepted for publishes to matching topics
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ContentTypeRule {
+    /// Topic filter the rule applies to (wildcards allowed)
+    pub topic: String,
+    /// Accepted Content Type values (e.g., "application/json")
+    pub content_types: Vec<String>,
+}
+
+/// Maximum payload size of publishes to matching topics
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PayloadLimitRule {
+    /// Topic filter the rule applies to (wildcards allowed)
+    pub topic: String,
+    /// Maximum payload size in bytes (0 = no limit beyond max_packet_size)
+    pub max_payload_size: usize,
+}
+
+/// Redirection of clients to another server
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RedirectConfig {
+    /// Server Reference sent to MQTT v5 clients (e.g., "mqtt2.example.com:1883")
+    pub server_reference: String,
+    /// Permanent move (Server Moved) rather than temporary (Use Another Server)
+    #[serde(default)]
+    pub permanent: bool,
+}
+
+/// Policy applied when the retained message store is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetainedPolicy {
+    /// Reject the new retained message (QoS 1/2 publishers get Quota Exceeded)
+    #[default]
+    Reject,
+    /// Evict the oldest retained messages to make room
+    EvictOldest,
+}
+
+/// Policy applied when a client's message queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueOverflowPolicy {
+    /// Drop the oldest queued message to make room
+    #[default]
+    DropOldest,
+    /// Drop the new message
+    DropNewest,
+    /// Disconnect a connected client with Quota Exceeded when its
+    /// connection queues the message, whether it was published locally or
+    /// came from a cluster peer or bridge; the new message is dropped for
+    /// clients without a connection
+    Disconnect,
+    /// Drop the oldest queued QoS 0 message, then the new message if it is
+    /// QoS 0, and only then the oldest queued message
+    DropQos0First,
+}
+
+/// Policy applied when an unacked message ran out of retransmissions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryExhaustedPolicy {
+    /// Drop the message from the inflight window and log it
+    #[default]
+    Drop,
+    /// Disconnect the client; the message stays in its session and is sent
+    /// again when it reconnects
+    Disconnect,
+}
+
+/// Slow consumer detection: a client whose outbound channel holds at least
+/// `threshold` messages for longer than `grace_period` is a slow consumer
+/// until the channel drains below the threshold again
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SlowConsumerConfig {
+    /// Queued outbound messages that make a client a slow consumer candidate.
+    /// Set to 0 to disable detection (default).
+    pub threshold: usize,
+    /// How long the channel must stay over the threshold (e.g., "10s")
+    #[serde(with = "humantime_serde")]
+    pub grace_period: Duration,
+    /// What happens to slow consumers
+    pub policy: SlowConsumerPolicy,
+}
+
+impl Default for SlowConsumerConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0,
+            grace_period: Duration::from_secs(10),
+            policy: SlowConsumerPolicy::default(),
+        }
+    }
+}
+
+/// Policy applied to slow consumers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlowConsumerPolicy {
+    /// Only log them and count them in metrics and $SYS
+    #[default]
+    Log,
+    /// Stop routing QoS 0 messages to them
+    DropQos0,
+    /// Disconnect them; persistent sessions queue their messages meanwhile
+    Disconnect,
 }
 
 fn default_max_qos() -> u8 {
@@ -392,8 +787,28 @@ impl Default for MqttConfig {
             wildcard_subscriptions: true,
             subscription_identifiers: true,
             shared_subscriptions: true,
+            server_keep_alive: None,
             sys_topics: true,
             sys_interval: Duration::from_secs(10),
+            sys_filter_topics: false,
+            sys_session_topics: false,
+            sys_client_topics: false,
+            sys_client_interval: Duration::from_secs(60),
+            presence_topics: false,
+            max_retained_messages: 0,
+            max_retained_bytes: 0,
+            retained_policy: RetainedPolicy::default(),
+            redirect: None,
+            validate_payload_format: false,
+            content_types: Vec::new(),
+            payload_limits: Vec::new(),
+            schemas: Vec::new(),
+            strict: true,
+            ordered_delivery: false,
+            topic_priorities: Vec::new(),
+            shared_subscription_strategy: SharedDeliveryStrategy::default(),
+            shared_subscription_groups: Vec::new(),
+            queue_subscriptions: false,
         }
     }
 }
@@ -453,6 +868,10 @@ pub struct AclRole {
     /// Topic patterns this role can subscribe to
     #[serde(default)]
     pub subscribe: Vec<String>,
+    /// Queue overflow policy of the role's clients, overriding
+    /// `limits.queue_overflow`
+    #[serde(default)]
+    pub queue_overflow: Option<QueueOverflowPolicy>,
 }
 
 /// ACL permissions
@@ -478,9 +897,11 @@ impl Config {
         let mut builder = config::Config::builder()
             // Start with defaults
             .set_default("log.level", "info")?
+            .set_default("log.format", "text")?
             .set_default("server.bind", "0.0.0.0:1883")?
             .set_default("server.ws_path", "/mqtt")?
             .set_default("server.workers", 0)?
+            .set_default("server.pin_workers", true)?
             .set_default("limits.max_connections", 100_000)?
             .set_default("limits.max_packet_size", 1024 * 1024)?
             .set_default("limits.max_inflight", 32)?
@@ -488,7 +909,10 @@ impl Config {
             .set_default("limits.max_awaiting_rel", 100)?
             .set_default("limits.retry_interval", "30s")?
             .set_default("limits.outbound_channel_capacity", 1024)?
+            .set_default("limits.write_coalesce_bytes", 64 * 1024)?
+            .set_default("limits.fanout_threshold", 128)?
             .set_default("limits.max_topic_levels", 0)?
+            .set_default("limits.max_subscriptions_per_client", 0)?
             .set_default("session.default_keep_alive", 60)?
             .set_default("session.max_keep_alive", 65535)?
             .set_default("session.expiry_check_interval", "60s")?
@@ -499,6 +923,7 @@ impl Config {
             .set_default("mqtt.subscription_identifiers", true)?
             .set_default("mqtt.shared_subscriptions", true)?
             .set_default("mqtt.sys_interval", "10s")?
+            .set_default("mqtt.sys_client_interval", "60s")?
             .set_default("auth.enabled", false)?
             .set_default("auth.allow_anonymous", true)?
             .set_default("acl.enabled", false)?;
@@ -554,6 +979,150 @@ impl Config {
             ));
         }
 
+        // Validate per-module log levels
+        self.log.targets()?;
+
+        // Server Keep Alive 0 would turn keep-alive off for clients that
+        // asked for it, and one above the maximum would let them exceed it
+        if let Some(keep_alive) = self.mqtt.server_keep_alive {
+            if keep_alive == 0 || keep_alive > self.session.max_keep_alive {
+                return Err(ConfigError::Validation(format!(
+                    "mqtt.server_keep_alive must be between 1 and session.max_keep_alive ({})",
+                    self.session.max_keep_alive
+                )));
+            }
+        }
+
+        if let Some(ref push) = self.metrics.push {
+            if push.address.rsplit_once(':').is_none() {
+                return Err(ConfigError::Validation(format!(
+                    "metrics.push.address '{}' must be host:port",
+                    push.address
+                )));
+            }
+            if push.interval.is_zero() {
+                return Err(ConfigError::Validation(
+                    "metrics.push.interval must be greater than 0".to_string(),
+                ));
+            }
+            if push.prefix.is_empty() || push.prefix.contains(char::is_whitespace) {
+                return Err(ConfigError::Validation(format!(
+                    "metrics.push.prefix '{}' must be non-empty without whitespace",
+                    push.prefix
+                )));
+            }
+        }
+
+        if self.mqtt.sys_client_topics && self.mqtt.sys_client_interval < self.mqtt.sys_interval {
+            return Err(ConfigError::Validation(format!(
+                "mqtt.sys_client_interval ({:?}) must not be shorter than mqtt.sys_interval ({:?})",
+                self.mqtt.sys_client_interval, self.mqtt.sys_interval
+            )));
+        }
+
+        // Validate required content types
+        for rule in &self.mqtt.content_types {
+            if let Err(e) = crate::topic::validate_topic_filter(&rule.topic) {
+                return Err(ConfigError::Validation(format!(
+                    "mqtt.content_types topic '{}' is invalid: {}",
+                    rule.topic, e
+                )));
+            }
+            if rule.content_types.is_empty() {
+                return Err(ConfigError::Validation(format!(
+                    "mqtt.content_types rule for '{}' must list at least one content type",
+                    rule.topic
+                )));
+            }
+        }
+
+        // User and role changes are only committed through raft
+        if self.metrics.users_api
+            && !self
+                .cluster
+                .iter()
+                .any(|c| c.enabled && c.consistency == Consistency::Strong)
+        {
+            return Err(ConfigError::Validation(
+                "metrics.users_api requires a cluster with consistency = \"strong\"".to_string(),
+            ));
+        }
+
+        // Validate metric topic patterns
+        for pattern in &self.metrics.topic_patterns {
+            if let Err(e) = crate::topic::validate_topic_filter(pattern) {
+                return Err(ConfigError::Validation(format!(
+                    "metrics.topic_patterns entry '{}' is invalid: {}",
+                    pattern, e
+                )));
+            }
+        }
+
+        // Validate payload limits
+        for rule in &self.mqtt.payload_limits {
+            if let Err(e) = crate::topic::validate_topic_filter(&rule.topic) {
+                return Err(ConfigError::Validation(format!(
+                    "mqtt.payload_limits topic '{}' is invalid: {}",
+                    rule.topic, e
+                )));
+            }
+        }
+
+        // Validate payload schemas
+        for rule in &self.mqtt.schemas {
+            if let Err(e) = crate::topic::validate_topic_filter(&rule.topic) {
+                return Err(ConfigError::Validation(format!(
+                    "mqtt.schemas topic '{}' is invalid: {}",
+                    rule.topic, e
+                )));
+            }
+            if rule.format == SchemaFormat::Protobuf && rule.message.is_none() {
+                return Err(ConfigError::Validation(format!(
+                    "mqtt.schemas rule for '{}' needs the protobuf message type",
+                    rule.topic
+                )));
+            }
+            if rule.on_invalid == InvalidPayloadAction::Quarantine {
+                let topic = format!("{}{}", rule.quarantine_prefix, rule.topic);
+                if rule.quarantine_prefix.is_empty()
+                    || rule.quarantine_prefix.contains(['+', '#'])
+                    || crate::topic::validate_topic_filter(&topic).is_err()
+                {
+                    return Err(ConfigError::Validation(format!(
+                        "mqtt.schemas quarantine_prefix '{}' is invalid",
+                        rule.quarantine_prefix
+                    )));
+                }
+            }
+        }
+
+        // Validate topic priorities
+        for rule in &self.mqtt.topic_priorities {
+            if let Err(e) = crate::topic::validate_topic_filter(&rule.topic) {
+                return Err(ConfigError::Validation(format!(
+                    "mqtt.topic_priorities topic '{}' is invalid: {}",
+                    rule.topic, e
+                )));
+            }
+        }
+
+        // Validate shared subscription group strategies
+        let mut groups = std::collections::HashSet::new();
+        for rule in &self.mqtt.shared_subscription_groups {
+            if rule.group.is_empty() || rule.group.contains(['/', '+', '#']) {
+                return Err(ConfigError::Validation(format!(
+                    "mqtt.shared_subscription_groups group '{}' is not a valid share name",
+                    rule.group
+                )));
+            }
+            if !groups.insert(rule.group.as_str()) {
+                return Err(ConfigError::Validation(format!(
+                    "mqtt.shared_subscription_groups lists group '{}' more than once",
+                    rule.group
+                )));
+            }
+        }
+
         // Note: 0 means unbounded for all limits
 
         // Validate user password configuration
@@ -606,6 +1175,278 @@ impl Config {
             }
         }
 
+        // Validate persistence backend configuration
+        if self.persistence.enabled && self.persistence.backend == BackendType::Postgres {
+            match &self.persistence.url {
+                Some(url) if !url.is_empty() => {}
+                _ => {
+                    return Err(ConfigError::Validation(
+                        "persistence.url is required when backend is \"postgres\"".to_string(),
+                    ));
+                }
+            }
+            if self.persistence.max_connections == 0 {
+                return Err(ConfigError::Validation(
+                    "persistence.max_connections must be greater than 0".to_string(),
+                ));
+            }
+        }
+
+        if !self.limits.retry_max_interval.is_zero()
+            && self.limits.retry_max_interval < self.limits.retry_interval
+        {
+            return Err(ConfigError::Validation(
+                "limits.retry_max_interval must not be less than limits.retry_interval".to_string(),
+            ));
+        }
+
+        if self.limits.spill_threshold > 0 && !self.persistence.enabled {
+            return Err(ConfigError::Validation(
+                "limits.spill_threshold requires persistence to be enabled".to_string(),
+            ));
+        }
+        // The memory backend keeps spilled messages in memory too
+        if self.limits.spill_threshold > 0 && self.persistence.backend == BackendType::Memory {
+            return Err(ConfigError::Validation(
+                "limits.spill_threshold requires a disk-backed persistence backend, not memory"
+                    .to_string(),
+            ));
+        }
+
+        let slow_consumer = &self.limits.slow_consumer;
+        if slow_consumer.threshold > 0
+            && self.limits.outbound_channel_capacity > 0
+            && slow_consumer.threshold > self.limits.outbound_channel_capacity
+        {
+            return Err(ConfigError::Validation(format!(
+                "limits.slow_consumer.threshold ({}) exceeds limits.outbound_channel_capacity ({})",
+                slow_consumer.threshold, self.limits.outbound_channel_capacity
+            )));
+        }
+
+        if self.persistence.enabled
+            && self.persistence.backend == BackendType::Memory
+            && self.persistence.sync_mode == SyncMode::Always
+        {
+            return Err(ConfigError::Validation(
+                "persistence.sync_mode \"always\" is not supported by the \"memory\" backend"
+                    .to_string(),
+            ));
+        }
+
+        // Validate tenancy configuration
+        if self.tenancy.enabled {
+            if self.tenancy.source == TenantSource::UsernamePrefix
+                && self.tenancy.separator.is_empty()
+            {
+                return Err(ConfigError::Validation(
+                    "tenancy.separator must not be empty".to_string(),
+                ));
+            }
+            for listener in self.tenancy.listeners.keys() {
+                if !TenancyConfig::LISTENERS.contains(&listener.as_str()) {
+                    return Err(ConfigError::Validation(format!(
+                        "tenancy.listeners: unknown listener '{}' (expected tcp, tls or ws)",
+                        listener
+                    )));
+                }
+            }
+        }
+
+        // Validate mount points
+        for listener in self.mount_points.listeners.keys() {
+            if !TenancyConfig::LISTENERS.contains(&listener.as_str()) {
+                return Err(ConfigError::Validation(format!(
+                    "mount_points.listeners: unknown listener '{}' (expected tcp, tls or ws)",
+                    listener
+                )));
+            }
+        }
+        for mount_point in self
+            .mount_points
+            .listeners
+            .values()
+            .chain(self.mount_points.users.values())
+        {
+            if !mount_point.ends_with('/')
+                || mount_point.starts_with('$')
+                || mount_point.contains(['+', '#'])
+            {
+                return Err(ConfigError::Validation(format!(
+                    "invalid mount point '{}': must end with '/' and contain no wildcards or leading '$'",
+                    mount_point
+                )));
+            }
+        }
+
+        // Validate journal configuration
+        if self.journal.enabled {
+            if self.journal.topics.is_empty() {
+                return Err(ConfigError::Validation(
+                    "journal.topics must not be empty".to_string(),
+                ));
+            }
+            for filter in &self.journal.topics {
+                if let Err(e) = crate::topic::validate_topic_filter(filter) {
+                    return Err(ConfigError::Validation(format!(
+                        "journal.topics: invalid filter '{}': {}",
+                        filter, e
+                    )));
+                }
+            }
+            if self.journal.segment_size == 0 {
+                return Err(ConfigError::Validation(
+                    "journal.segment_size must be greater than 0".to_string(),
+                ));
+            }
+        }
+
+        // Validate OpenTelemetry configuration
+        if !(0.0..=1.0).contains(&self.otel.sample_ratio) {
+            return Err(ConfigError::Validation(
+                "otel.sample_ratio must be between 0.0 and 1.0".to_string(),
+            ));
+        }
+
+        // Validate rules
+        for (i, rule) in self.rules.iter().enumerate() {
+            crate::rules::check(rule).map_err(|e| ConfigError::Validation(e.to_string()))?;
+            if self.rules[..i].iter().any(|r| r.id == rule.id) {
+                return Err(ConfigError::Validation(format!(
+                    "duplicate rule id '{}'",
+                    rule.id
+                )));
+            }
+            for action in &rule.actions {
+                if let RuleActionConfig::Bridge { bridge, .. } = action {
+                    if !self.bridge.iter().any(|b| b.enabled && &b.name == bridge) {
+                        return Err(ConfigError::Validation(format!(
+                            "rule '{}': no enabled bridge named '{}'",
+                            rule.id, bridge
+                        )));
+                    }
+                }
+            }
+        }
+
+        // Validate external hook provider configuration
+        if self.exhook.enabled {
+            if !self.exhook.url.starts_with("http://") && !self.exhook.url.starts_with("https://") {
+                return Err(ConfigError::Validation(format!(
+                    "exhook.url '{}' must start with http:// or https://",
+                    self.exhook.url
+                )));
+            }
+            if self.exhook.pool_size == 0 {
+                return Err(ConfigError::Validation(
+                    "exhook.pool_size must be at least 1".to_string(),
+                ));
+            }
+            if self.exhook.request_timeout.is_zero() {
+                return Err(ConfigError::Validation(
+                    "exhook.request_timeout must be greater than 0".to_string(),
+                ));
+            }
+            if let Some(hook) = self
+                .exhook
+                .failed_actions
+                .keys()
+                .find(|hook| !EXHOOK_NAMES.contains(&hook.as_str()))
+            {
+                return Err(ConfigError::Validation(format!(
+                    "exhook.failed_actions: unknown hook '{}'",
+                    hook
+                )));
+            }
+        }
+
+        // Validate hook isolation configuration
+        if self.hooks.isolate && self.hooks.timeout.is_zero() {
+            return Err(ConfigError::Validation(
+                "hooks.timeout must be greater than 0".to_string(),
+            ));
+        }
+
+        // Validate scripting hook configuration
+        if self.script.enabled {
+            if self.script.path.is_some() == self.script.source.is_some() {
+                return Err(ConfigError::Validation(
+                    "script requires exactly one of path or source".to_string(),
+                ));
+            }
+            if self.script.max_operations == 0 {
+                return Err(ConfigError::Validation(
+                    "script.max_operations must be at least 1".to_string(),
+                ));
+            }
+        }
+
+        // Validate cluster discovery configuration
+        for cluster in self.cluster.iter().filter(|c| c.enabled) {
+            let discovery = &cluster.discovery;
+            match discovery.method {
+                DiscoveryMethod::Dns | DiscoveryMethod::DnsSrv if discovery.name.is_none() => {
+                    return Err(ConfigError::Validation(
+                        "cluster.discovery.name is required for DNS discovery".to_string(),
+                    ));
+                }
+                DiscoveryMethod::Kubernetes if discovery.service.is_none() => {
+                    return Err(ConfigError::Validation(
+                        "cluster.discovery.service is required for Kubernetes discovery"
+                            .to_string(),
+                    ));
+                }
+                _ => {}
+            }
+            if let Some(feature) = discovery.method.missing_feature() {
+                return Err(ConfigError::Validation(format!(
+                    "cluster.discovery.method requires building with the {} feature",
+                    feature
+                )));
+            }
+            if discovery.refresh_interval.is_zero() {
+                return Err(ConfigError::Validation(
+                    "cluster.discovery.refresh_interval must be greater than 0".to_string(),
+                ));
+            }
+            if cluster.consistency == Consistency::Strong {
+                if cluster.raft.members.is_empty() {
+                    return Err(ConfigError::Validation(
+                        "cluster.raft.members is required for strong consistency".to_string(),
+                    ));
+                }
+                if cluster.raft.election_timeout <= cluster.raft.heartbeat_interval {
+                    return Err(ConfigError::Validation(
+                        "cluster.raft.election_timeout must be greater than heartbeat_interval"
+                            .to_string(),
+                    ));
+                }
+                if cluster.raft.tls.is_none() {
+                    return Err(ConfigError::Validation(
+                        "cluster.raft.tls is required for strong consistency".to_string(),
+                    ));
+                }
+            }
+            if cluster.batch_size == 0 {
+                return Err(ConfigError::Validation(
+                    "cluster.batch_size must be greater than 0".to_string(),
+                ));
+            }
+            if !cluster.compression.is_available() {
+                return Err(ConfigError::Validation(
+                    "cluster.compression \"zstd\" requires building with the zstd feature"
+                        .to_string(),
+                ));
+            }
+            if cluster.expected_nodes == 0
+                && (cluster.witness.is_some() || cluster.partition_mode != PartitionMode::Continue)
+            {
+                return Err(ConfigError::Validation(
+                    "cluster.expected_nodes is required for partition detection".to_string(),
+                ));
+            }
+        }
+
         // Validate TLS configuration
         if self.server.tls_bind.is_some() {
             match &self.server.tls {
@@ -629,6 +1470,127 @@ impl Config {
             }
         }
 
+        // Exported events need a bridge that forwards them
+        for event in &self.export.events {
+            let topic = event.topic();
+            let forwarded = self.bridge.iter().filter(|b| b.enabled).any(|b| {
+                b.forwards.iter().any(|rule| {
+                    rule.is_outbound()
+                        && crate::topic::topic_matches_filter(&topic, &rule.local_topic)
+                })
+            });
+            if !forwarded {
+                return Err(ConfigError::Validation(format!(
+                    "export.events '{}' is not forwarded by any bridge; add a forward rule for {}/#",
+                    event.as_str(),
+                    EXPORT_TOPIC_PREFIX
+                )));
+            }
+        }
+
+        // Validate bridge configuration
+        for bridge in self.bridge.iter().filter(|b| b.enabled) {
+            if bridge.queue_size == 0 {
+                return Err(ConfigError::Validation(format!(
+                    "bridge '{}': queue_size must be greater than 0",
+                    bridge.name
+                )));
+            }
+            if !(bridge.backoff_multiplier >= 1.0 && bridge.backoff_multiplier.is_finite()) {
+                return Err(ConfigError::Validation(format!(
+                    "bridge '{}': backoff_multiplier must be at least 1.0",
+                    bridge.name
+                )));
+            }
+            if !(0.0..=1.0).contains(&bridge.reconnect_jitter) {
+                return Err(ConfigError::Validation(format!(
+                    "bridge '{}': reconnect_jitter must be between 0.0 and 1.0",
+                    bridge.name
+                )));
+            }
+            if bridge.max_hops == 0 {
+                return Err(ConfigError::Validation(format!(
+                    "bridge '{}': max_hops must be at least 1",
+                    bridge.name
+                )));
+            }
+            if !bridge.protocol.is_available() {
+                return Err(ConfigError::Validation(format!(
+                    "bridge '{}': protocol \"{}\" requires building with the {} feature",
+                    bridge.name, bridge.protocol, bridge.protocol
+                )));
+            }
+            if bridge.protocol == BridgeProtocol::Kafka {
+                // Kafka topic names are not MQTT filters
+                if let Some(rule) = bridge
+                    .forwards
+                    .iter()
+                    .find(|r| r.remote_topic.contains(['+', '#']))
+                {
+                    return Err(ConfigError::Validation(format!(
+                        "bridge '{}': remote_topic '{}' must be a Kafka topic name, not a filter",
+                        bridge.name, rule.remote_topic
+                    )));
+                }
+            }
+            if bridge.protocol.uses_http() {
+                // Webhooks only receive messages
+                if bridge
+                    .forwards
+                    .iter()
+                    .any(|r| r.direction != ForwardDirection::Out)
+                {
+                    return Err(ConfigError::Validation(format!(
+                        "bridge '{}': http bridges only support direction \"out\"",
+                        bridge.name
+                    )));
+                }
+                if !bridge.http.path.starts_with('/') {
+                    return Err(ConfigError::Validation(format!(
+                        "bridge '{}': http.path must start with '/'",
+                        bridge.name
+                    )));
+                }
+                if bridge.http.batch_size == 0 {
+                    return Err(ConfigError::Validation(format!(
+                        "bridge '{}': http.batch_size must be at least 1",
+                        bridge.name
+                    )));
+                }
+                if hyper::Method::from_bytes(bridge.http.method.as_bytes()).is_err() {
+                    return Err(ConfigError::Validation(format!(
+                        "bridge '{}': invalid http.method '{}'",
+                        bridge.name, bridge.http.method
+                    )));
+                }
+            }
+            for rule in &bridge.forwards {
+                let Some(ref pattern) = rule.topic_regex else {
+                    continue;
+                };
+                if rule.direction == ForwardDirection::Both {
+                    return Err(ConfigError::Validation(format!(
+                        "bridge '{}': topic_regex requires direction \"out\" or \"in\"",
+                        bridge.name
+                    )));
+                }
+                if let Err(e) = Regex::new(pattern) {
+                    return Err(ConfigError::Validation(format!(
+                        "bridge '{}': invalid topic_regex '{}': {}",
+                        bridge.name, pattern, e
+                    )));
+                }
+            }
+            if let Some(ref tls) = bridge.tls {
+                if tls.client_cert.is_some() != tls.client_key.is_some() {
+                    return Err(ConfigError::Validation(format!(
+                        "bridge '{}': tls.client_cert and tls.client_key must be set together",
+                        bridge.name
+                    )));
+                }
+            }
+        }
+
         Ok(())
     }
 
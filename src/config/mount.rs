@@ -0,0 +1,31 @@
+//! Topic mount point configuration.
+//!
+//! Places every topic a client publishes or subscribes to below a prefix,
+//! chosen by the client's user name or the listener it connected through.
+//! Clients see their topics without the prefix, so tenants can share a
+//! broker without changing their topic names.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+/// Mount point configuration
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MountPointConfig {
+    /// Listener name ("tcp", "tls", "ws") -> mount point
+    pub listeners: HashMap<String, String>,
+
+    /// User name -> mount point, taking precedence over the listener's
+    pub users: HashMap<String, String>,
+}
+
+impl MountPointConfig {
+    /// Resolve the mount point for a client, if it has one
+    pub fn mount_point_for(&self, listener: &str, username: Option<&str>) -> Option<&str> {
+        username
+            .and_then(|username| self.users.get(username))
+            .or_else(|| self.listeners.get(listener))
+            .map(String::as_str)
+    }
+}
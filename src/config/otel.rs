@@ -0,0 +1,29 @@
+//! OpenTelemetry trace export configuration
+
+use serde::Deserialize;
+
+/// OpenTelemetry trace export configuration (requires the `otel` feature)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OtelConfig {
+    /// Export spans over OTLP
+    pub enabled: bool,
+    /// OTLP gRPC collector endpoint
+    pub endpoint: String,
+    /// Service name reported with the spans
+    pub service_name: String,
+    /// Fraction of traces started by the broker that are sampled (0.0 to
+    /// 1.0); traces continued from a publisher follow its sampling decision
+    pub sample_ratio: f64,
+}
+
+impl Default for OtelConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: "http://localhost:4317".to_string(),
+            service_name: "vibemq".to_string(),
+            sample_ratio: 1.0,
+        }
+    }
+}
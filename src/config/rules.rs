@@ -0,0 +1,107 @@
+//! Rule engine configuration.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_method() -> String {
+    "POST".to_string()
+}
+
+fn default_webhook_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+/// A rule: a SQL-like statement selecting and filtering published
+/// messages, and the actions run for each message it passes
+///
+/// ```toml
+/// [[rules]]
+/// id = "overheat"
+/// sql = "SELECT payload.temp AS t FROM \"sensors/+/temp\" WHERE t > 90"
+/// actions = [{ type = "republish", topic = "alerts/{{t}}" }]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct RuleConfig {
+    /// Unique name of the rule
+    pub id: String,
+    /// Whether the rule is evaluated
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Free-form description
+    #[serde(default)]
+    pub description: String,
+    /// `SELECT <fields> FROM "<filter>"[, ...] [WHERE <condition>]`
+    pub sql: String,
+    /// Actions run, in order, for each message the rule passes
+    #[serde(default)]
+    pub actions: Vec<RuleActionConfig>,
+}
+
+/// What a rule does with a message it passes
+///
+/// Templates substitute `{{name}}` with a field of the rule's output, or
+/// else a column of the message (`topic`, `clientid`, `payload.x`, ...).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RuleActionConfig {
+    /// Publish the output to another topic
+    Republish {
+        /// Topic template
+        topic: String,
+        /// Payload template (default: the output as JSON)
+        #[serde(default)]
+        payload: Option<String>,
+        #[serde(default)]
+        qos: u8,
+        #[serde(default)]
+        retain: bool,
+    },
+    /// Send the output to an HTTP endpoint
+    Webhook {
+        /// http:// or https:// URL of the endpoint
+        url: String,
+        #[serde(default = "default_method")]
+        method: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        /// Body template (default: the output as JSON)
+        #[serde(default)]
+        body: Option<String>,
+        /// Deadline of the request (e.g., "5s")
+        #[serde(default = "default_webhook_timeout", with = "humantime_serde")]
+        timeout: Duration,
+    },
+    /// Hand the message to a bridge, as if published on `topic`
+    Bridge {
+        /// Name of the bridge
+        bridge: String,
+        /// Topic template (default: the message's topic); must match one
+        /// of the bridge's outbound forwards
+        #[serde(default)]
+        topic: Option<String>,
+        /// Payload template (default: the message's payload)
+        #[serde(default)]
+        payload: Option<String>,
+    },
+    /// Do not route the message to subscribers; the publisher is still
+    /// acknowledged
+    Drop,
+}
+
+impl RuleActionConfig {
+    /// Name of the action type, as configured
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RuleActionConfig::Republish { .. } => "republish",
+            RuleActionConfig::Webhook { .. } => "webhook",
+            RuleActionConfig::Bridge { .. } => "bridge",
+            RuleActionConfig::Drop => "drop",
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! Payload schema configuration.
+
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+fn default_quarantine_prefix() -> String {
+    "$quarantine/".to_string()
+}
+
+/// Schema that payloads published to matching topics must follow
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SchemaRule {
+    /// Topic filter the rule applies to (wildcards allowed)
+    pub topic: String,
+    /// Format of the schema
+    pub format: SchemaFormat,
+    /// JSON Schema document, or a serialized protobuf FileDescriptorSet
+    /// (`protoc --include_imports --descriptor_set_out`)
+    pub path: PathBuf,
+    /// Fully qualified protobuf message type (e.g., "telemetry.v1.Reading")
+    #[serde(default)]
+    pub message: Option<String>,
+    /// What happens to publishes whose payload does not follow the schema
+    #[serde(default)]
+    pub on_invalid: InvalidPayloadAction,
+    /// Prefix of the topic quarantined messages are routed to, before
+    /// their original topic
+    #[serde(default = "default_quarantine_prefix")]
+    pub quarantine_prefix: String,
+}
+
+/// Format of a payload schema
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaFormat {
+    /// JSON payloads validated against a JSON Schema
+    JsonSchema,
+    /// Payloads decoded as a protobuf message
+    Protobuf,
+}
+
+/// What happens to a publish whose payload does not follow its schema
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InvalidPayloadAction {
+    /// Refuse it with Payload Format Invalid
+    #[default]
+    Reject,
+    /// Route it to `<quarantine_prefix><topic>` instead, unretained
+    Quarantine,
+}
+
+impl InvalidPayloadAction {
+    /// Name of the action, as configured
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InvalidPayloadAction::Reject => "reject",
+            InvalidPayloadAction::Quarantine => "quarantine",
+        }
+    }
+}
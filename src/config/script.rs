@@ -0,0 +1,47 @@
+//! Scripting hook configuration.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+fn default_reload_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+/// Scripting hook configuration (requires the `scripting` feature)
+///
+/// A Rhai script defining any of `authenticate`, `authorize_publish`,
+/// `authorize_subscribe` and `transform_publish`; the broker calls those
+/// it defines.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ScriptConfig {
+    /// Call the script's hooks
+    pub enabled: bool,
+    /// Script file, reloaded when it changes
+    pub path: Option<PathBuf>,
+    /// Inline script, instead of a file
+    pub source: Option<String>,
+    /// How often the file is checked for changes (e.g., "5s"; 0 = never)
+    #[serde(default = "default_reload_interval", with = "humantime_serde")]
+    pub reload_interval: Duration,
+    /// Operations a call may run before it is aborted
+    pub max_operations: u64,
+    /// Order among the broker's hooks, highest first; the built-in
+    /// authentication and ACL have priority 0
+    pub priority: i32,
+}
+
+impl Default for ScriptConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            source: None,
+            reload_interval: default_reload_interval(),
+            max_operations: 100_000,
+            priority: 0,
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! Tenancy configuration.
+//!
+//! Assigns each client to a tenant so persisted data can be partitioned,
+//! exported and purged per tenant.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+/// How a client's tenant is determined
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TenantSource {
+    /// Username prefix before `separator`, e.g. "acme:alice" -> "acme"
+    #[default]
+    UsernamePrefix,
+    /// Fixed tenant per listener ("tcp", "tls", "ws"), from `listeners`
+    Listener,
+}
+
+/// Tenancy configuration
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TenancyConfig {
+    /// Enable per-tenant partitioning of persisted data
+    pub enabled: bool,
+
+    /// Where the tenant is taken from
+    pub source: TenantSource,
+
+    /// Separator between tenant and user name (for username_prefix)
+    pub separator: String,
+
+    /// Listener name -> tenant (for listener)
+    pub listeners: HashMap<String, String>,
+}
+
+impl Default for TenancyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            source: TenantSource::UsernamePrefix,
+            separator: ":".to_string(),
+            listeners: HashMap::new(),
+        }
+    }
+}
+
+impl TenancyConfig {
+    /// Listener names accepted in `listeners`
+    pub const LISTENERS: [&'static str; 3] = ["tcp", "tls", "ws"];
+
+    /// Resolve the tenant for a client
+    ///
+    /// Returns `None` when tenancy is disabled or no tenant can be derived;
+    /// such clients' data is stored outside any tenant.
+    pub fn tenant_for(&self, listener: &str, username: Option<&str>) -> Option<String> {
+        if !self.enabled {
+            return None;
+        }
+
+        match self.source {
+            TenantSource::UsernamePrefix => {
+                let (tenant, _) = username?.split_once(self.separator.as_str())?;
+                (!tenant.is_empty()).then(|| tenant.to_string())
+            }
+            TenantSource::Listener => self.listeners.get(listener).cloned(),
+        }
+    }
+}
@@ -0,0 +1,610 @@
+//! External gRPC Hook Provider
+//!
+//! Forwards hook callbacks to a service implementing EMQX's ExHook
+//! `emqx.exhook.v2.HookProvider`, so sidecar policy services written for
+//! EMQX work with VibeMQ. The provider is loaded once at startup
+//! (`OnProviderLoaded`) and answers with the hooks it wants; the others are
+//! never called.
+//!
+//! Authentication, authorization and `message.publish` wait for the
+//! provider's answer. A `ValuedResponse` of type `IGNORE` keeps the
+//! broker's own result; a result of type `STOP_AND_RETURN` settles the
+//! check, one of type `CONTINUE` leaves an allowed client to the broker's
+//! other hooks. A `message.publish` answer carrying the header
+//! `allow_publish = "false"` refuses the message. The remaining hooks are
+//! notifications, sent on a task of their own.
+//!
+//! Calls go round-robin over a pool of connections, each under the
+//! configured deadline. When a call fails or times out, the hook's
+//! failed action decides: `deny` fails closed, `ignore` carries on as if
+//! the provider had no opinion.
+
+mod proto;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::transport::{Channel, Endpoint};
+use tracing::{debug, warn};
+
+use super::{
+    ClientContext, HookDecision, HookError, HookResult, Hooks, PublishTransform, SubscribeDecision,
+};
+use crate::broker::{DisconnectReason, DropReason as MessageDropReason};
+use crate::config::{ExHookConfig, FailedAction};
+use crate::protocol::{Publish, QoS, ReasonCode};
+use crate::topic::topic_matches_filter;
+use proto::client_authorize_request::AuthorizeReqType;
+use proto::valued_response::{ResponsedType, Value};
+
+/// Header of a `message.publish` answer that refuses the message
+const ALLOW_PUBLISH: &str = "allow_publish";
+
+/// Hook provider backed by an external gRPC service
+pub struct ExHookProvider {
+    config: ExHookConfig,
+    channels: Vec<Channel>,
+    next: AtomicUsize,
+    /// Hooks the provider registered, with their topic filters
+    hooks: HashMap<String, Vec<String>>,
+    meta: proto::RequestMeta,
+}
+
+impl ExHookProvider {
+    /// Open the connection pool and load the provider
+    ///
+    /// # Arguments
+    /// * `config` - Provider configuration
+    /// * `node` - Name of this broker, sent with every request
+    pub async fn connect(config: &ExHookConfig, node: &str) -> HookResult<Self> {
+        let endpoint = Endpoint::from_shared(config.url.clone())
+            .map_err(|e| HookError::Internal(format!("invalid exhook url {}: {}", config.url, e)))?
+            .connect_timeout(config.request_timeout)
+            .timeout(config.request_timeout);
+        let channels = (0..config.pool_size)
+            .map(|_| endpoint.connect_lazy())
+            .collect();
+
+        let meta = proto::RequestMeta {
+            node: node.to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            sysdescr: "VibeMQ".to_string(),
+            cluster_name: String::new(),
+        };
+        let mut provider = Self {
+            config: config.clone(),
+            channels,
+            next: AtomicUsize::new(0),
+            hooks: HashMap::new(),
+            meta,
+        };
+
+        let request = proto::ProviderLoadedRequest {
+            broker: Some(proto::BrokerInfo {
+                version: provider.meta.version.clone(),
+                sysdescr: provider.meta.sysdescr.clone(),
+                uptime: 0,
+                datetime: humantime_serde::re::humantime::format_rfc3339_seconds(SystemTime::now())
+                    .to_string(),
+            }),
+            meta: Some(provider.meta.clone()),
+        };
+        let loaded: proto::LoadedResponse = unary(
+            provider.channel(),
+            "/emqx.exhook.v2.HookProvider/OnProviderLoaded",
+            request,
+        )
+        .await
+        .map_err(|e| {
+            HookError::Internal(format!(
+                "exhook provider at {} failed to load: {}",
+                config.url,
+                e.message()
+            ))
+        })?;
+        provider.hooks = loaded
+            .hooks
+            .into_iter()
+            .map(|spec| (spec.name, spec.topics))
+            .collect();
+        Ok(provider)
+    }
+
+    /// Names of the hooks the provider registered
+    pub fn registered_hooks(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.hooks.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    fn wants(&self, hook: &str) -> bool {
+        self.hooks.contains_key(hook)
+    }
+
+    /// Whether the provider wants `hook` for messages on `topic`
+    fn wants_topic(&self, hook: &str, topic: &str) -> bool {
+        match self.hooks.get(hook) {
+            Some(filters) => {
+                filters.is_empty() || filters.iter().any(|f| topic_matches_filter(topic, f))
+            }
+            None => false,
+        }
+    }
+
+    /// Next connection of the pool
+    fn channel(&self) -> Channel {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.channels.len();
+        self.channels[index].clone()
+    }
+
+    fn client_info(&self, client: &ClientContext) -> proto::ClientInfo {
+        proto::ClientInfo {
+            node: self.meta.node.clone(),
+            clientid: client.client_id.to_string(),
+            username: client.username().unwrap_or_default().to_string(),
+            peerhost: client.peer_addr.ip().to_string(),
+            peerport: client.peer_addr.port() as u32,
+            protocol: "mqtt".to_string(),
+            anonymous: client.username.is_none(),
+            cn: client.cert_cn.clone().unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    /// Client info of a client known only by its ID
+    fn client_id_info(&self, client_id: &str) -> proto::ClientInfo {
+        proto::ClientInfo {
+            node: self.meta.node.clone(),
+            clientid: client_id.to_string(),
+            anonymous: true,
+            ..Default::default()
+        }
+    }
+
+    fn message(&self, client_id: &str, topic: &str, payload: &[u8], qos: QoS) -> proto::Message {
+        proto::Message {
+            node: self.meta.node.clone(),
+            qos: qos as u32,
+            from: client_id.to_string(),
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            ..Default::default()
+        }
+    }
+
+    /// Call a hook the broker waits on; `Err` carries the hook's failed
+    /// action
+    async fn call<Req>(
+        &self,
+        hook: &str,
+        path: &'static str,
+        request: Req,
+    ) -> Result<proto::ValuedResponse, FailedAction>
+    where
+        Req: prost::Message + Send + Sync + 'static,
+    {
+        unary(self.channel(), path, request).await.map_err(|e| {
+            let action = self.config.failed_action(hook);
+            warn!("ExHook {} failed ({:?}): {}", hook, action, e.message());
+            action
+        })
+    }
+
+    /// Send a notification hook on a task of its own
+    fn notify<Req>(&self, hook: &'static str, path: &'static str, request: Req)
+    where
+        Req: prost::Message + Send + Sync + 'static,
+    {
+        let channel = self.channel();
+        tokio::spawn(async move {
+            if let Err(e) = unary::<Req, proto::EmptySuccess>(channel, path, request).await {
+                debug!("ExHook {} failed: {}", hook, e.message());
+            }
+        });
+    }
+
+    async fn authorize(
+        &self,
+        client: &ClientContext,
+        kind: AuthorizeReqType,
+        topic: &str,
+    ) -> HookResult<HookDecision> {
+        const HOOK: &str = "client.authorize";
+        if !self.wants(HOOK) {
+            return Ok(HookDecision::Continue);
+        }
+        let request = proto::ClientAuthorizeRequest {
+            clientinfo: Some(self.client_info(client)),
+            r#type: kind as i32,
+            topic: topic.to_string(),
+            result: true,
+            meta: Some(self.meta.clone()),
+        };
+        match self
+            .call(
+                HOOK,
+                "/emqx.exhook.v2.HookProvider/OnClientAuthorize",
+                request,
+            )
+            .await
+        {
+            Ok(response) => Ok(decision(&response)),
+            Err(action) => failed(action, HookDecision::Continue),
+        }
+    }
+}
+
+/// Outcome of a failed call under `action`
+fn failed<T>(action: FailedAction, default: T) -> HookResult<T> {
+    match action {
+        FailedAction::Deny => Err(HookError::Internal(
+            "exhook provider unavailable".to_string(),
+        )),
+        FailedAction::Ignore => Ok(default),
+    }
+}
+
+/// The verdict of an answer: a result the provider stopped with settles
+/// the check, one it continued with can only deny
+fn decision(response: &proto::ValuedResponse) -> HookDecision {
+    if response.r#type == ResponsedType::Ignore as i32 {
+        return HookDecision::Continue;
+    }
+    match response.value {
+        Some(Value::BoolResult(false)) => HookDecision::Deny,
+        Some(Value::BoolResult(true)) if response.r#type == ResponsedType::StopAndReturn as i32 => {
+            HookDecision::Allow
+        }
+        _ => HookDecision::Continue,
+    }
+}
+
+/// What a `message.publish` answer does to `publish`
+fn publish_transform(publish: &Publish, response: &proto::ValuedResponse) -> PublishTransform {
+    if response.r#type == ResponsedType::Ignore as i32 {
+        return PublishTransform::Unchanged;
+    }
+    let Some(Value::Message(message)) = &response.value else {
+        return PublishTransform::Unchanged;
+    };
+    if message.headers.get(ALLOW_PUBLISH).map(String::as_str) == Some("false") {
+        return PublishTransform::Reject(ReasonCode::NotAuthorized);
+    }
+    if publish.topic == message.topic && message.payload[..] == publish.payload[..] {
+        return PublishTransform::Unchanged;
+    }
+    PublishTransform::Modified(Box::new(Publish {
+        topic: message.topic.as_str().into(),
+        payload: message.payload.clone().into(),
+        ..publish.clone()
+    }))
+}
+
+/// Make a unary call of the HookProvider service
+async fn unary<Req, Resp>(
+    channel: Channel,
+    path: &'static str,
+    request: Req,
+) -> Result<Resp, tonic::Status>
+where
+    Req: prost::Message + Send + Sync + 'static,
+    Resp: prost::Message + Default + Send + Sync + 'static,
+{
+    let mut grpc = tonic::client::Grpc::new(channel);
+    grpc.ready()
+        .await
+        .map_err(|e| tonic::Status::unavailable(e.to_string()))?;
+    let codec = tonic::codec::ProstCodec::<Req, Resp>::default();
+    let response = grpc
+        .unary(
+            tonic::Request::new(request),
+            PathAndQuery::from_static(path),
+            codec,
+        )
+        .await?;
+    Ok(response.into_inner())
+}
+
+#[async_trait]
+impl Hooks for ExHookProvider {
+    async fn on_authenticate(
+        &self,
+        client: &ClientContext,
+        password: Option<&[u8]>,
+    ) -> HookResult<HookDecision> {
+        const HOOK: &str = "client.authenticate";
+        if !self.wants(HOOK) {
+            return Ok(HookDecision::Continue);
+        }
+        let mut clientinfo = self.client_info(client);
+        clientinfo.password = password
+            .map(|p| String::from_utf8_lossy(p).into_owned())
+            .unwrap_or_default();
+        let request = proto::ClientAuthenticateRequest {
+            clientinfo: Some(clientinfo),
+            result: true,
+            meta: Some(self.meta.clone()),
+        };
+        match self
+            .call(
+                HOOK,
+                "/emqx.exhook.v2.HookProvider/OnClientAuthenticate",
+                request,
+            )
+            .await
+        {
+            Ok(response) => Ok(decision(&response)),
+            Err(action) => failed(action, HookDecision::Continue),
+        }
+    }
+
+    async fn on_publish_check(
+        &self,
+        client: &ClientContext,
+        topic: &str,
+        _qos: QoS,
+        _retain: bool,
+    ) -> HookResult<HookDecision> {
+        self.authorize(client, AuthorizeReqType::Publish, topic)
+            .await
+    }
+
+    async fn on_publish_transform(
+        &self,
+        client: &ClientContext,
+        publish: &Publish,
+    ) -> HookResult<PublishTransform> {
+        const HOOK: &str = "message.publish";
+        if !self.wants_topic(HOOK, &publish.topic) {
+            return Ok(PublishTransform::Unchanged);
+        }
+        let message = self.message(
+            &client.client_id,
+            &publish.topic,
+            &publish.payload,
+            publish.qos,
+        );
+        let request = proto::MessagePublishRequest {
+            message: Some(message),
+            meta: Some(self.meta.clone()),
+        };
+        match self
+            .call(
+                HOOK,
+                "/emqx.exhook.v2.HookProvider/OnMessagePublish",
+                request,
+            )
+            .await
+        {
+            Ok(response) => Ok(publish_transform(publish, &response)),
+            Err(action) => failed(action, PublishTransform::Unchanged),
+        }
+    }
+
+    async fn on_subscribe_check(
+        &self,
+        client: &ClientContext,
+        filter: &str,
+        _qos: QoS,
+    ) -> HookResult<SubscribeDecision> {
+        self.authorize(client, AuthorizeReqType::Subscribe, filter)
+            .await
+            .map(SubscribeDecision::from)
+    }
+
+    async fn on_unsubscribe(&self, client: &ClientContext, filter: &str) {
+        const HOOK: &str = "session.unsubscribed";
+        if self.wants(HOOK) {
+            let request = proto::SessionUnsubscribedRequest {
+                clientinfo: Some(self.client_info(client)),
+                topic: filter.to_string(),
+                meta: Some(self.meta.clone()),
+            };
+            self.notify(
+                HOOK,
+                "/emqx.exhook.v2.HookProvider/OnSessionUnsubscribed",
+                request,
+            );
+        }
+    }
+
+    async fn on_client_connected(&self, client: &ClientContext) {
+        const HOOK: &str = "client.connected";
+        if self.wants(HOOK) {
+            let request = proto::ClientConnectedRequest {
+                clientinfo: Some(self.client_info(client)),
+                meta: Some(self.meta.clone()),
+            };
+            self.notify(
+                HOOK,
+                "/emqx.exhook.v2.HookProvider/OnClientConnected",
+                request,
+            );
+        }
+    }
+
+    async fn on_client_disconnected(&self, client: &ClientContext, reason: DisconnectReason) {
+        const HOOK: &str = "client.disconnected";
+        if self.wants(HOOK) {
+            let request = proto::ClientDisconnectedRequest {
+                clientinfo: Some(self.client_info(client)),
+                reason: reason.as_str().to_string(),
+                meta: Some(self.meta.clone()),
+            };
+            self.notify(
+                HOOK,
+                "/emqx.exhook.v2.HookProvider/OnClientDisconnected",
+                request,
+            );
+        }
+    }
+
+    async fn on_session_expired(&self, client_id: &str) {
+        const HOOK: &str = "session.terminated";
+        if self.wants(HOOK) {
+            let request = proto::SessionTerminatedRequest {
+                clientinfo: Some(self.client_id_info(client_id)),
+                reason: "expired".to_string(),
+                meta: Some(self.meta.clone()),
+            };
+            self.notify(
+                HOOK,
+                "/emqx.exhook.v2.HookProvider/OnSessionTerminated",
+                request,
+            );
+        }
+    }
+
+    async fn on_message_delivered(&self, client: &ClientContext, topic: &str, qos: QoS) {
+        const HOOK: &str = "message.delivered";
+        if self.wants_topic(HOOK, topic) {
+            let request = proto::MessageDeliveredRequest {
+                clientinfo: Some(self.client_info(client)),
+                message: Some(self.message(&client.client_id, topic, &[], qos)),
+                meta: Some(self.meta.clone()),
+            };
+            self.notify(
+                HOOK,
+                "/emqx.exhook.v2.HookProvider/OnMessageDelivered",
+                request,
+            );
+        }
+    }
+
+    async fn on_message_dropped(&self, client_id: &str, topic: &str, reason: MessageDropReason) {
+        const HOOK: &str = "message.dropped";
+        if self.wants_topic(HOOK, topic) {
+            let request = proto::MessageDroppedRequest {
+                message: Some(self.message(client_id, topic, &[], QoS::AtMostOnce)),
+                reason: reason.as_str().to_string(),
+                meta: Some(self.meta.clone()),
+            };
+            self.notify(
+                HOOK,
+                "/emqx.exhook.v2.HookProvider/OnMessageDropped",
+                request,
+            );
+        }
+    }
+
+    fn observes_messages(&self) -> bool {
+        self.wants("message.delivered") || self.wants("message.dropped")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Properties;
+
+    fn response(kind: ResponsedType, value: Option<Value>) -> proto::ValuedResponse {
+        proto::ValuedResponse {
+            r#type: kind as i32,
+            value,
+        }
+    }
+
+    fn publish() -> Publish {
+        Publish {
+            topic: "sensors/1".into(),
+            payload: bytes::Bytes::from_static(b"21.5"),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            dup: false,
+            packet_id: Some(1),
+            properties: Properties::default(),
+        }
+    }
+
+    #[test]
+    fn test_decision() {
+        let deny = Some(Value::BoolResult(false));
+        let allow = Some(Value::BoolResult(true));
+        assert_eq!(
+            decision(&response(ResponsedType::StopAndReturn, deny.clone())),
+            HookDecision::Deny
+        );
+        assert_eq!(
+            decision(&response(ResponsedType::Continue, deny.clone())),
+            HookDecision::Deny
+        );
+        assert_eq!(
+            decision(&response(ResponsedType::StopAndReturn, allow.clone())),
+            HookDecision::Allow
+        );
+        assert_eq!(
+            decision(&response(ResponsedType::Continue, allow)),
+            HookDecision::Continue
+        );
+        assert_eq!(
+            decision(&response(ResponsedType::Ignore, deny)),
+            HookDecision::Continue
+        );
+        assert_eq!(
+            decision(&response(ResponsedType::Continue, None)),
+            HookDecision::Continue
+        );
+    }
+
+    #[test]
+    fn test_publish_transform() {
+        let publish = publish();
+        let mut message = proto::Message {
+            topic: publish.topic.to_string(),
+            payload: publish.payload.to_vec(),
+            ..Default::default()
+        };
+        let answer = |message: &proto::Message| {
+            response(
+                ResponsedType::StopAndReturn,
+                Some(Value::Message(message.clone())),
+            )
+        };
+
+        assert!(matches!(
+            publish_transform(&publish, &answer(&message)),
+            PublishTransform::Unchanged
+        ));
+
+        message.payload = b"21.5C".to_vec();
+        match publish_transform(&publish, &answer(&message)) {
+            PublishTransform::Modified(modified) => {
+                assert_eq!(&modified.payload[..], b"21.5C");
+                assert_eq!(modified.topic, "sensors/1");
+                assert_eq!(modified.packet_id, Some(1));
+            }
+            other => panic!("expected a modified publish, got {:?}", other),
+        }
+
+        message
+            .headers
+            .insert(ALLOW_PUBLISH.to_string(), "false".to_string());
+        assert!(matches!(
+            publish_transform(&publish, &answer(&message)),
+            PublishTransform::Reject(ReasonCode::NotAuthorized)
+        ));
+        assert!(matches!(
+            publish_transform(
+                &publish,
+                &response(ResponsedType::Ignore, Some(Value::Message(message)))
+            ),
+            PublishTransform::Unchanged
+        ));
+    }
+
+    #[test]
+    fn test_failed_action() {
+        assert!(failed(FailedAction::Deny, HookDecision::Continue).is_err());
+        assert_eq!(
+            failed(FailedAction::Ignore, HookDecision::Continue).unwrap(),
+            HookDecision::Continue
+        );
+    }
+}
@@ -0,0 +1,236 @@
+//! Messages of the `emqx.exhook.v2.HookProvider` service
+//!
+//! Written out by hand from EMQX's `exhook.proto`, keeping its field
+//! numbers, so no protobuf compiler is needed to build. Only the messages
+//! of the hooks the broker calls are included.
+
+use std::collections::HashMap;
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ProviderLoadedRequest {
+    #[prost(message, optional, tag = "1")]
+    pub broker: Option<BrokerInfo>,
+    #[prost(message, optional, tag = "2")]
+    pub meta: Option<RequestMeta>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BrokerInfo {
+    #[prost(string, tag = "1")]
+    pub version: String,
+    #[prost(string, tag = "2")]
+    pub sysdescr: String,
+    #[prost(int64, tag = "3")]
+    pub uptime: i64,
+    #[prost(string, tag = "4")]
+    pub datetime: String,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LoadedResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub hooks: Vec<HookSpec>,
+}
+
+/// A hook the provider wants called, for messages on `topics` (all
+/// topics if empty)
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HookSpec {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, repeated, tag = "2")]
+    pub topics: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ValuedResponse {
+    #[prost(enumeration = "valued_response::ResponsedType", tag = "1")]
+    pub r#type: i32,
+    #[prost(oneof = "valued_response::Value", tags = "3, 4")]
+    pub value: Option<valued_response::Value>,
+}
+
+pub mod valued_response {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum ResponsedType {
+        /// Use the value, and let later hooks of the chain run
+        Continue = 0,
+        /// Keep the broker's own result
+        Ignore = 1,
+        /// Use the value, and stop the chain
+        StopAndReturn = 2,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Value {
+        #[prost(bool, tag = "3")]
+        BoolResult(bool),
+        #[prost(message, tag = "4")]
+        Message(super::Message),
+    }
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct EmptySuccess {}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ClientInfo {
+    #[prost(string, tag = "1")]
+    pub node: String,
+    #[prost(string, tag = "2")]
+    pub clientid: String,
+    #[prost(string, tag = "3")]
+    pub username: String,
+    #[prost(string, tag = "4")]
+    pub password: String,
+    #[prost(string, tag = "5")]
+    pub peerhost: String,
+    #[prost(uint32, tag = "6")]
+    pub sockport: u32,
+    #[prost(string, tag = "7")]
+    pub protocol: String,
+    #[prost(string, tag = "8")]
+    pub mountpoint: String,
+    #[prost(bool, tag = "9")]
+    pub is_superuser: bool,
+    #[prost(bool, tag = "10")]
+    pub anonymous: bool,
+    #[prost(string, tag = "11")]
+    pub cn: String,
+    #[prost(string, tag = "12")]
+    pub dn: String,
+    #[prost(uint32, tag = "13")]
+    pub peerport: u32,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Message {
+    #[prost(string, tag = "1")]
+    pub node: String,
+    #[prost(string, tag = "2")]
+    pub id: String,
+    #[prost(uint32, tag = "3")]
+    pub qos: u32,
+    #[prost(string, tag = "4")]
+    pub from: String,
+    #[prost(string, tag = "5")]
+    pub topic: String,
+    #[prost(bytes = "vec", tag = "6")]
+    pub payload: Vec<u8>,
+    #[prost(uint64, tag = "7")]
+    pub timestamp: u64,
+    #[prost(map = "string, string", tag = "8")]
+    pub headers: HashMap<String, String>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RequestMeta {
+    #[prost(string, tag = "1")]
+    pub node: String,
+    #[prost(string, tag = "2")]
+    pub version: String,
+    #[prost(string, tag = "3")]
+    pub sysdescr: String,
+    #[prost(string, tag = "4")]
+    pub cluster_name: String,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ClientAuthenticateRequest {
+    #[prost(message, optional, tag = "1")]
+    pub clientinfo: Option<ClientInfo>,
+    #[prost(bool, tag = "2")]
+    pub result: bool,
+    #[prost(message, optional, tag = "3")]
+    pub meta: Option<RequestMeta>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ClientAuthorizeRequest {
+    #[prost(message, optional, tag = "1")]
+    pub clientinfo: Option<ClientInfo>,
+    #[prost(enumeration = "client_authorize_request::AuthorizeReqType", tag = "2")]
+    pub r#type: i32,
+    #[prost(string, tag = "3")]
+    pub topic: String,
+    #[prost(bool, tag = "4")]
+    pub result: bool,
+    #[prost(message, optional, tag = "5")]
+    pub meta: Option<RequestMeta>,
+}
+
+pub mod client_authorize_request {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum AuthorizeReqType {
+        Publish = 0,
+        Subscribe = 1,
+    }
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ClientConnectedRequest {
+    #[prost(message, optional, tag = "1")]
+    pub clientinfo: Option<ClientInfo>,
+    #[prost(message, optional, tag = "2")]
+    pub meta: Option<RequestMeta>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ClientDisconnectedRequest {
+    #[prost(message, optional, tag = "1")]
+    pub clientinfo: Option<ClientInfo>,
+    #[prost(string, tag = "2")]
+    pub reason: String,
+    #[prost(message, optional, tag = "3")]
+    pub meta: Option<RequestMeta>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SessionUnsubscribedRequest {
+    #[prost(message, optional, tag = "1")]
+    pub clientinfo: Option<ClientInfo>,
+    #[prost(string, tag = "2")]
+    pub topic: String,
+    #[prost(message, optional, tag = "3")]
+    pub meta: Option<RequestMeta>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SessionTerminatedRequest {
+    #[prost(message, optional, tag = "1")]
+    pub clientinfo: Option<ClientInfo>,
+    #[prost(string, tag = "2")]
+    pub reason: String,
+    #[prost(message, optional, tag = "3")]
+    pub meta: Option<RequestMeta>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MessagePublishRequest {
+    #[prost(message, optional, tag = "1")]
+    pub message: Option<Message>,
+    #[prost(message, optional, tag = "2")]
+    pub meta: Option<RequestMeta>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MessageDeliveredRequest {
+    #[prost(message, optional, tag = "1")]
+    pub clientinfo: Option<ClientInfo>,
+    #[prost(message, optional, tag = "2")]
+    pub message: Option<Message>,
+    #[prost(message, optional, tag = "3")]
+    pub meta: Option<RequestMeta>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MessageDroppedRequest {
+    #[prost(message, optional, tag = "1")]
+    pub message: Option<Message>,
+    #[prost(string, tag = "2")]
+    pub reason: String,
+    #[prost(message, optional, tag = "3")]
+    pub meta: Option<RequestMeta>,
+}
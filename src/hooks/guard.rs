@@ -0,0 +1,224 @@
+//! Hook Isolation
+//!
+//! [`GuardedHooks`] runs every call of the hooks it wraps on a task of its
+//! own, under a deadline. A call that times out is aborted, and a call
+//! that panics is caught as the task's `JoinError`; either way the failed
+//! action decides the outcome, so one faulty hook cannot hang or take down
+//! the connection tasks calling it.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tracing::warn;
+
+use super::{
+    ClientContext, HookDecision, HookError, HookResult, Hooks, PublishTransform, SubscribeDecision,
+};
+use crate::broker::{DisconnectReason, DropReason as MessageDropReason};
+use crate::config::{FailedAction, HooksConfig, QueueOverflowPolicy};
+use crate::persistence::{DropReason, PersistenceOp};
+use crate::protocol::{Publish, QoS};
+
+/// Hooks whose calls run isolated, under a deadline
+pub struct GuardedHooks {
+    inner: Arc<dyn Hooks>,
+    timeout: Duration,
+    failed_action: FailedAction,
+}
+
+impl GuardedHooks {
+    /// Wrap `hooks`, failing calls that take longer than `timeout` or
+    /// panic as `failed_action` decides
+    pub fn new<H: Hooks + 'static>(
+        hooks: H,
+        timeout: Duration,
+        failed_action: FailedAction,
+    ) -> Self {
+        Self {
+            inner: Arc::new(hooks),
+            timeout,
+            failed_action,
+        }
+    }
+
+    /// Wrap `hooks` as configured
+    pub fn from_config<H: Hooks + 'static>(hooks: H, config: &HooksConfig) -> Self {
+        Self::new(hooks, config.timeout, config.failed_action)
+    }
+
+    /// Run a hook call on its own task, `None` if it timed out or panicked
+    async fn run<T, F>(&self, hook: &'static str, call: F) -> Option<T>
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let mut task = tokio::spawn(call);
+        match tokio::time::timeout(self.timeout, &mut task).await {
+            Ok(Ok(value)) => Some(value),
+            Ok(Err(e)) => {
+                warn!("Hook {} failed: {}", hook, e);
+                None
+            }
+            Err(_) => {
+                task.abort();
+                warn!("Hook {} timed out after {:?}", hook, self.timeout);
+                None
+            }
+        }
+    }
+
+    /// Outcome of a failed call of `hook`, `default` if failures are ignored
+    fn failed<T>(&self, hook: &'static str, default: T) -> HookResult<T> {
+        match self.failed_action {
+            FailedAction::Deny => Err(HookError::Internal(format!("hook {} failed", hook))),
+            FailedAction::Ignore => Ok(default),
+        }
+    }
+}
+
+#[async_trait]
+impl Hooks for GuardedHooks {
+    async fn on_authenticate(
+        &self,
+        client: &ClientContext,
+        password: Option<&[u8]>,
+    ) -> HookResult<HookDecision> {
+        const HOOK: &str = "on_authenticate";
+        let (inner, client) = (self.inner.clone(), client.clone());
+        let password = password.map(<[u8]>::to_vec);
+        self.run(HOOK, async move {
+            inner.on_authenticate(&client, password.as_deref()).await
+        })
+        .await
+        .unwrap_or_else(|| self.failed(HOOK, HookDecision::Continue))
+    }
+
+    async fn on_publish_check(
+        &self,
+        client: &ClientContext,
+        topic: &str,
+        qos: QoS,
+        retain: bool,
+    ) -> HookResult<HookDecision> {
+        const HOOK: &str = "on_publish_check";
+        let (inner, client, topic) = (self.inner.clone(), client.clone(), topic.to_string());
+        self.run(HOOK, async move {
+            inner.on_publish_check(&client, &topic, qos, retain).await
+        })
+        .await
+        .unwrap_or_else(|| self.failed(HOOK, HookDecision::Continue))
+    }
+
+    async fn on_publish_transform(
+        &self,
+        client: &ClientContext,
+        publish: &Publish,
+    ) -> HookResult<PublishTransform> {
+        const HOOK: &str = "on_publish_transform";
+        let (inner, client, publish) = (self.inner.clone(), client.clone(), publish.clone());
+        self.run(HOOK, async move {
+            inner.on_publish_transform(&client, &publish).await
+        })
+        .await
+        .unwrap_or_else(|| self.failed(HOOK, PublishTransform::Unchanged))
+    }
+
+    async fn on_subscribe_check(
+        &self,
+        client: &ClientContext,
+        filter: &str,
+        qos: QoS,
+    ) -> HookResult<SubscribeDecision> {
+        const HOOK: &str = "on_subscribe_check";
+        let (inner, client, filter) = (self.inner.clone(), client.clone(), filter.to_string());
+        self.run(HOOK, async move {
+            inner.on_subscribe_check(&client, &filter, qos).await
+        })
+        .await
+        .unwrap_or_else(|| self.failed(HOOK, SubscribeDecision::Continue))
+    }
+
+    fn grants_sys_subscribe(&self, client: &ClientContext, filter: &str) -> bool {
+        self.inner.grants_sys_subscribe(client, filter)
+    }
+
+    async fn on_unsubscribe(&self, client: &ClientContext, filter: &str) {
+        let (inner, client, filter) = (self.inner.clone(), client.clone(), filter.to_string());
+        self.run("on_unsubscribe", async move {
+            inner.on_unsubscribe(&client, &filter).await
+        })
+        .await;
+    }
+
+    async fn queue_overflow_policy(&self, client: &ClientContext) -> Option<QueueOverflowPolicy> {
+        let (inner, client) = (self.inner.clone(), client.clone());
+        self.run("queue_overflow_policy", async move {
+            inner.queue_overflow_policy(&client).await
+        })
+        .await
+        .flatten()
+    }
+
+    async fn on_client_connected(&self, client: &ClientContext) {
+        let (inner, client) = (self.inner.clone(), client.clone());
+        self.run("on_client_connected", async move {
+            inner.on_client_connected(&client).await
+        })
+        .await;
+    }
+
+    async fn on_client_disconnected(&self, client: &ClientContext, reason: DisconnectReason) {
+        let (inner, client) = (self.inner.clone(), client.clone());
+        self.run("on_client_disconnected", async move {
+            inner.on_client_disconnected(&client, reason).await
+        })
+        .await;
+    }
+
+    async fn on_session_expired(&self, client_id: &str) {
+        let (inner, client_id) = (self.inner.clone(), client_id.to_string());
+        self.run("on_session_expired", async move {
+            inner.on_session_expired(&client_id).await
+        })
+        .await;
+    }
+
+    async fn on_message_published(&self, topic: &str, payload: &[u8], qos: QoS) {
+        let (inner, topic, payload) = (self.inner.clone(), topic.to_string(), payload.to_vec());
+        self.run("on_message_published", async move {
+            inner.on_message_published(&topic, &payload, qos).await
+        })
+        .await;
+    }
+
+    async fn on_message_delivered(&self, client: &ClientContext, topic: &str, qos: QoS) {
+        let (inner, client, topic) = (self.inner.clone(), client.clone(), topic.to_string());
+        self.run("on_message_delivered", async move {
+            inner.on_message_delivered(&client, &topic, qos).await
+        })
+        .await;
+    }
+
+    async fn on_message_dropped(&self, client_id: &str, topic: &str, reason: MessageDropReason) {
+        let (inner, client_id, topic) =
+            (self.inner.clone(), client_id.to_string(), topic.to_string());
+        self.run("on_message_dropped", async move {
+            inner.on_message_dropped(&client_id, &topic, reason).await
+        })
+        .await;
+    }
+
+    fn observes_messages(&self) -> bool {
+        self.inner.observes_messages()
+    }
+
+    async fn on_persistence_op_dropped(&self, op: &PersistenceOp, reason: DropReason) {
+        let (inner, op) = (self.inner.clone(), op.clone());
+        self.run("on_persistence_op_dropped", async move {
+            inner.on_persistence_op_dropped(&op, reason).await
+        })
+        .await;
+    }
+}
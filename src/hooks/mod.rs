@@ -4,14 +4,26 @@
 //! and custom event handling in VibeMQ.
 
 use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
 
 use async_trait::async_trait;
 
-use crate::protocol::QoS;
+use crate::broker::{DisconnectReason, DropReason as MessageDropReason, Listener};
+use crate::config::QueueOverflowPolicy;
+use crate::persistence::{DropReason, PersistenceOp};
+use crate::protocol::{Properties, ProtocolVersion, Publish, QoS, ReasonCode};
 
+#[cfg(feature = "exhook")]
+pub mod exhook;
+mod guard;
+#[cfg(feature = "scripting")]
+pub mod script;
 #[cfg(test)]
 mod tests;
 
+pub use guard::GuardedHooks;
+
 /// Hook error types
 #[derive(Debug)]
 pub enum HookError {
@@ -38,92 +50,280 @@ impl std::error::Error for HookError {}
 /// Hook result type
 pub type HookResult<T> = Result<T, HookError>;
 
+/// Outcome of [`Hooks::on_authenticate`] and [`Hooks::on_publish_check`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookDecision {
+    /// Allow, without consulting later hooks
+    Allow,
+    /// Deny, without consulting later hooks
+    Deny,
+    /// Leave the decision to later hooks; allowed if no hook decides
+    Continue,
+}
+
+impl HookDecision {
+    /// Whether the broker lets the client go ahead
+    pub fn is_allowed(self) -> bool {
+        self != HookDecision::Deny
+    }
+}
+
+impl From<bool> for HookDecision {
+    /// Allow or deny
+    fn from(allowed: bool) -> Self {
+        if allowed {
+            HookDecision::Allow
+        } else {
+            HookDecision::Deny
+        }
+    }
+}
+
+/// Outcome of [`Hooks::on_subscribe_check`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscribeDecision {
+    /// Grant the requested QoS, up to the broker's maximum
+    Allow,
+    /// Grant at most this QoS
+    Grant(QoS),
+    /// Refuse the subscription with this SUBACK reason code
+    Deny(ReasonCode),
+    /// Leave the decision to later hooks; allowed if no hook decides
+    Continue,
+}
+
+impl From<HookDecision> for SubscribeDecision {
+    /// Allow, deny as Not Authorized, or continue
+    fn from(decision: HookDecision) -> Self {
+        match decision {
+            HookDecision::Allow => SubscribeDecision::Allow,
+            HookDecision::Deny => SubscribeDecision::Deny(ReasonCode::NotAuthorized),
+            HookDecision::Continue => SubscribeDecision::Continue,
+        }
+    }
+}
+
+impl From<bool> for SubscribeDecision {
+    /// Allow, or deny as Not Authorized
+    fn from(allowed: bool) -> Self {
+        if allowed {
+            SubscribeDecision::Allow
+        } else {
+            SubscribeDecision::Deny(ReasonCode::NotAuthorized)
+        }
+    }
+}
+
+/// Outcome of [`Hooks::on_publish_transform`]
+#[derive(Debug, Clone)]
+pub enum PublishTransform {
+    /// Route the message as published
+    Unchanged,
+    /// Route this message instead; only its topic, payload and properties
+    /// are used, the QoS, retain flag and packet ID stay the client's
+    Modified(Box<Publish>),
+    /// Refuse the message, acknowledging QoS 1 and 2 with this reason code
+    Reject(ReasonCode),
+}
+
+/// The connection a hook is called for
+///
+/// Built from the client's CONNECT packet and the connection it arrived
+/// on, so authorization can consider network and TLS identity as well as
+/// the client ID and username.
+#[derive(Debug, Clone)]
+pub struct ClientContext {
+    /// The client identifier (assigned by the broker if the client sent
+    /// none)
+    pub client_id: Arc<str>,
+    /// Username from the CONNECT packet
+    pub username: Option<String>,
+    /// Address of the client; the original address from a PROXY protocol
+    /// header when the connection came through a proxy
+    pub peer_addr: SocketAddr,
+    /// Common Name of the client certificate, verified by the TLS listener
+    /// or by a TLS terminating proxy that passed it in a PROXY v2 header
+    pub cert_cn: Option<String>,
+    /// Listener the connection was accepted on
+    pub listener: Listener,
+    /// MQTT version the client connected with
+    pub protocol_version: ProtocolVersion,
+    /// Properties of the CONNECT packet (empty for MQTT v3.1.1)
+    pub connect_properties: Properties,
+}
+
+impl ClientContext {
+    /// Context of a client on the TCP listener, before its CONNECT packet
+    /// is known
+    pub fn new(client_id: impl Into<Arc<str>>, peer_addr: SocketAddr) -> Self {
+        Self {
+            client_id: client_id.into(),
+            username: None,
+            peer_addr,
+            cert_cn: None,
+            listener: Listener::default(),
+            protocol_version: ProtocolVersion::V311,
+            connect_properties: Properties::default(),
+        }
+    }
+
+    /// Set the username
+    pub fn with_username(mut self, username: Option<&str>) -> Self {
+        self.username = username.map(str::to_string);
+        self
+    }
+
+    /// The username, if the client sent one
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+}
+
 /// Broker hooks trait
 ///
 /// Implement this trait to customize authentication, authorization,
 /// and event handling behavior. All methods have default implementations
 /// that allow everything.
+///
+/// Hooks called for something a client does get its [`ClientContext`];
+/// those called for events not tied to a connection (session expiry,
+/// dropped messages, persistence) get the client ID at most.
+///
+/// Authentication and authorization hooks answer with a decision that
+/// either settles the check or continues to the next hook of a
+/// [`CompositeHooks`]; a check no hook settles is allowed.
 #[async_trait]
 pub trait Hooks: Send + Sync {
     /// Called when a client attempts to authenticate
     ///
     /// # Arguments
-    /// * `client_id` - The client identifier
-    /// * `username` - Optional username from CONNECT packet
+    /// * `client` - The connecting client, with the username from its
+    ///   CONNECT packet
     /// * `password` - Optional password from CONNECT packet
     ///
     /// # Returns
-    /// * `Ok(true)` - Authentication successful
-    /// * `Ok(false)` - Authentication failed (will send CONNACK with error)
+    /// * `Ok(HookDecision::Allow)` - Authentication successful
+    /// * `Ok(HookDecision::Deny)` - Authentication failed (will send CONNACK
+    ///   with error)
+    /// * `Ok(HookDecision::Continue)` - Up to later hooks
     /// * `Err(_)` - Internal error occurred
     async fn on_authenticate(
         &self,
-        _client_id: &str,
-        _username: Option<&str>,
+        _client: &ClientContext,
         _password: Option<&[u8]>,
-    ) -> HookResult<bool> {
-        Ok(true) // Default: allow all
+    ) -> HookResult<HookDecision> {
+        Ok(HookDecision::Continue) // Default: no opinion
     }
 
     /// Called when a client attempts to publish a message
     ///
     /// # Arguments
-    /// * `client_id` - The client identifier
-    /// * `username` - The username used for authentication (if any)
+    /// * `client` - The publishing client
     /// * `topic` - The topic being published to
     /// * `qos` - The QoS level of the publish
     /// * `retain` - Whether this is a retained message
     ///
     /// # Returns
-    /// * `Ok(true)` - Publish allowed
-    /// * `Ok(false)` - Publish denied
+    /// * `Ok(HookDecision::Allow)` - Publish allowed
+    /// * `Ok(HookDecision::Deny)` - Publish denied
+    /// * `Ok(HookDecision::Continue)` - Up to later hooks
     /// * `Err(_)` - Internal error occurred
     async fn on_publish_check(
         &self,
-        _client_id: &str,
-        _username: Option<&str>,
+        _client: &ClientContext,
         _topic: &str,
         _qos: QoS,
         _retain: bool,
-    ) -> HookResult<bool> {
-        Ok(true) // Default: allow all
+    ) -> HookResult<HookDecision> {
+        Ok(HookDecision::Continue) // Default: no opinion
+    }
+
+    /// Called after a publish passed the ACL check, to rewrite or refuse it
+    /// before it is validated, retained and routed
+    ///
+    /// # Arguments
+    /// * `client` - The publishing client
+    /// * `publish` - The message, with its topic below the client's mount
+    ///   point
+    ///
+    /// # Returns
+    /// * `Ok(PublishTransform::Unchanged)` - Route the message as is
+    /// * `Ok(PublishTransform::Modified(publish))` - Route the rewritten message
+    /// * `Ok(PublishTransform::Reject(reason))` - Refuse the message
+    /// * `Err(_)` - Internal error occurred (the message is refused)
+    async fn on_publish_transform(
+        &self,
+        _client: &ClientContext,
+        _publish: &Publish,
+    ) -> HookResult<PublishTransform> {
+        Ok(PublishTransform::Unchanged) // Default: route as published
     }
 
     /// Called when a client attempts to subscribe to a topic filter
     ///
     /// # Arguments
-    /// * `client_id` - The client identifier
-    /// * `username` - The username used for authentication (if any)
+    /// * `client` - The subscribing client
     /// * `filter` - The topic filter being subscribed to
     /// * `qos` - The requested QoS level
     ///
     /// # Returns
-    /// * `Ok(true)` - Subscribe allowed
-    /// * `Ok(false)` - Subscribe denied
+    /// * `Ok(SubscribeDecision::Allow)` - Subscribe allowed
+    /// * `Ok(SubscribeDecision::Grant(qos))` - Subscribe allowed with at most `qos`
+    /// * `Ok(SubscribeDecision::Deny(reason))` - Subscribe denied with `reason`
+    /// * `Ok(SubscribeDecision::Continue)` - Up to later hooks
     /// * `Err(_)` - Internal error occurred
     async fn on_subscribe_check(
         &self,
-        _client_id: &str,
-        _username: Option<&str>,
+        _client: &ClientContext,
         _filter: &str,
         _qos: QoS,
-    ) -> HookResult<bool> {
-        Ok(true) // Default: allow all
+    ) -> HookResult<SubscribeDecision> {
+        Ok(SubscribeDecision::Continue) // Default: no opinion
+    }
+
+    /// Whether this provider explicitly grants `client` a `$SYS` filter
+    ///
+    /// The broker refuses `$SYS` subscriptions that no hook allowed in
+    /// `on_subscribe_check` unless one grants them here. Unlike
+    /// `SubscribeDecision::Allow`, a grant does not skip later hooks.
+    fn grants_sys_subscribe(&self, _client: &ClientContext, _filter: &str) -> bool {
+        false // Default: no grant
+    }
+
+    /// Called after a client removed one of its subscriptions
+    async fn on_unsubscribe(&self, _client: &ClientContext, _filter: &str) {
+        // Default: no-op
+    }
+
+    /// Called when a client connects, to choose what happens when its
+    /// message queue is full
+    ///
+    /// # Returns
+    /// * `Some(policy)` - Policy for this client
+    /// * `None` - Use the broker's `queue_overflow` setting
+    async fn queue_overflow_policy(&self, _client: &ClientContext) -> Option<QueueOverflowPolicy> {
+        None // Default: no override
     }
 
     /// Called after a client successfully connects
     ///
     /// This is called after authentication succeeds and CONNACK is sent.
-    async fn on_client_connected(&self, _client_id: &str, _username: Option<&str>) {
+    async fn on_client_connected(&self, _client: &ClientContext) {
         // Default: no-op
     }
 
     /// Called after a client disconnects
     ///
     /// # Arguments
-    /// * `client_id` - The client identifier
-    /// * `graceful` - Whether the disconnect was graceful (DISCONNECT packet received)
-    async fn on_client_disconnected(&self, _client_id: &str, _graceful: bool) {
+    /// * `client` - The client whose connection ended
+    /// * `reason` - Why the connection ended; [`DisconnectReason::is_graceful`]
+    ///   tells whether the client sent DISCONNECT
+    async fn on_client_disconnected(&self, _client: &ClientContext, _reason: DisconnectReason) {
+        // Default: no-op
+    }
+
+    /// Called after a disconnected client's session expired and was removed
+    async fn on_session_expired(&self, _client_id: &str) {
         // Default: no-op
     }
 
@@ -133,6 +333,50 @@ pub trait Hooks: Send + Sync {
     async fn on_message_published(&self, _topic: &str, _payload: &[u8], _qos: QoS) {
         // Default: no-op
     }
+
+    /// Called when a message reached a subscriber
+    ///
+    /// A QoS 0 message is delivered once written to the client's
+    /// connection, a QoS 1 or 2 message once the client acknowledged it
+    /// (PUBACK or PUBCOMP). This is called on the client's connection task
+    /// and should return quickly.
+    ///
+    /// # Arguments
+    /// * `client` - The subscriber
+    /// * `topic` - The topic of the message
+    /// * `qos` - The QoS the message was delivered with
+    async fn on_message_delivered(&self, _client: &ClientContext, _topic: &str, _qos: QoS) {
+        // Default: no-op
+    }
+
+    /// Called when a message for a subscriber is dropped instead of
+    /// delivered
+    ///
+    /// # Arguments
+    /// * `client_id` - The subscriber
+    /// * `topic` - The topic of the dropped message
+    /// * `reason` - Why it was dropped
+    async fn on_message_dropped(&self, _client_id: &str, _topic: &str, _reason: MessageDropReason) {
+        // Default: no-op
+    }
+
+    /// Whether `on_message_delivered` and `on_message_dropped` do anything
+    ///
+    /// The broker skips both calls, and the work of preparing them, when
+    /// this is false. Providers that only authenticate or authorize should
+    /// return false to keep them off the delivery path.
+    fn observes_messages(&self) -> bool {
+        true
+    }
+
+    /// Called when a persistence operation is dropped under backpressure
+    ///
+    /// # Arguments
+    /// * `op` - The operation that will not be persisted
+    /// * `reason` - Why it was dropped
+    async fn on_persistence_op_dropped(&self, _op: &PersistenceOp, _reason: DropReason) {
+        // Default: no-op
+    }
 }
 
 /// Default hooks implementation that allows everything
@@ -140,7 +384,11 @@ pub struct DefaultHooks;
 
 #[async_trait]
 impl Hooks for DefaultHooks {
-    // All methods use default implementations (allow all, no-op)
+    // All other methods use default implementations (allow all, no-op)
+
+    fn observes_messages(&self) -> bool {
+        false
+    }
 }
 
 impl Default for DefaultHooks {
@@ -155,60 +403,99 @@ impl Default for DefaultHooks {
 impl<T: Hooks + ?Sized> Hooks for std::sync::Arc<T> {
     async fn on_authenticate(
         &self,
-        client_id: &str,
-        username: Option<&str>,
+        client: &ClientContext,
         password: Option<&[u8]>,
-    ) -> HookResult<bool> {
-        (**self)
-            .on_authenticate(client_id, username, password)
-            .await
+    ) -> HookResult<HookDecision> {
+        (**self).on_authenticate(client, password).await
     }
 
     async fn on_publish_check(
         &self,
-        client_id: &str,
-        username: Option<&str>,
+        client: &ClientContext,
         topic: &str,
         qos: QoS,
         retain: bool,
-    ) -> HookResult<bool> {
-        (**self)
-            .on_publish_check(client_id, username, topic, qos, retain)
-            .await
+    ) -> HookResult<HookDecision> {
+        (**self).on_publish_check(client, topic, qos, retain).await
+    }
+
+    async fn on_publish_transform(
+        &self,
+        client: &ClientContext,
+        publish: &Publish,
+    ) -> HookResult<PublishTransform> {
+        (**self).on_publish_transform(client, publish).await
     }
 
     async fn on_subscribe_check(
         &self,
-        client_id: &str,
-        username: Option<&str>,
+        client: &ClientContext,
         filter: &str,
         qos: QoS,
-    ) -> HookResult<bool> {
-        (**self)
-            .on_subscribe_check(client_id, username, filter, qos)
-            .await
+    ) -> HookResult<SubscribeDecision> {
+        (**self).on_subscribe_check(client, filter, qos).await
+    }
+
+    fn grants_sys_subscribe(&self, client: &ClientContext, filter: &str) -> bool {
+        (**self).grants_sys_subscribe(client, filter)
     }
 
-    async fn on_client_connected(&self, client_id: &str, username: Option<&str>) {
-        (**self).on_client_connected(client_id, username).await;
+    async fn on_unsubscribe(&self, client: &ClientContext, filter: &str) {
+        (**self).on_unsubscribe(client, filter).await;
     }
 
-    async fn on_client_disconnected(&self, client_id: &str, graceful: bool) {
-        (**self).on_client_disconnected(client_id, graceful).await;
+    async fn queue_overflow_policy(&self, client: &ClientContext) -> Option<QueueOverflowPolicy> {
+        (**self).queue_overflow_policy(client).await
+    }
+
+    async fn on_client_connected(&self, client: &ClientContext) {
+        (**self).on_client_connected(client).await;
+    }
+
+    async fn on_client_disconnected(&self, client: &ClientContext, reason: DisconnectReason) {
+        (**self).on_client_disconnected(client, reason).await;
+    }
+
+    async fn on_session_expired(&self, client_id: &str) {
+        (**self).on_session_expired(client_id).await;
     }
 
     async fn on_message_published(&self, topic: &str, payload: &[u8], qos: QoS) {
         (**self).on_message_published(topic, payload, qos).await;
     }
+
+    async fn on_message_delivered(&self, client: &ClientContext, topic: &str, qos: QoS) {
+        (**self).on_message_delivered(client, topic, qos).await;
+    }
+
+    async fn on_message_dropped(&self, client_id: &str, topic: &str, reason: MessageDropReason) {
+        (**self).on_message_dropped(client_id, topic, reason).await;
+    }
+
+    fn observes_messages(&self) -> bool {
+        (**self).observes_messages()
+    }
+
+    async fn on_persistence_op_dropped(&self, op: &PersistenceOp, reason: DropReason) {
+        (**self).on_persistence_op_dropped(op, reason).await;
+    }
 }
 
 /// Composite hooks that chains multiple hook implementations
 ///
-/// For authentication: all hooks must return `Ok(true)` for success
-/// For authorization: all hooks must return `Ok(true)` for permission
+/// Hooks are called in order of priority, highest first; hooks of equal
+/// priority in the order they were added.
+///
+/// For authentication and authorization: the first hook to allow or deny
+/// settles the check and later hooks are not consulted; if every hook
+/// continues, the composite continues too
+/// For publish transforms: each hook sees the previous one's output; the
+/// first rejection wins
+/// For queue overflow policies: the first hook with an override wins
 /// For events: all hooks are called in order
 pub struct CompositeHooks {
-    hooks: Vec<Box<dyn Hooks>>,
+    /// Hooks with their priority, highest priority first
+    hooks: Vec<(i32, Box<dyn Hooks>)>,
 }
 
 impl CompositeHooks {
@@ -217,16 +504,35 @@ impl CompositeHooks {
         Self { hooks: Vec::new() }
     }
 
-    /// Add a hooks implementation
+    /// Add a hooks implementation with priority 0
     pub fn add<H: Hooks + 'static>(&mut self, hooks: H) {
-        self.hooks.push(Box::new(hooks));
+        self.add_with_priority(hooks, 0);
+    }
+
+    /// Add a hooks implementation, called before those of lower priority
+    pub fn add_with_priority<H: Hooks + 'static>(&mut self, hooks: H, priority: i32) {
+        let index = self.hooks.partition_point(|(p, _)| *p >= priority);
+        self.hooks.insert(index, (priority, Box::new(hooks)));
     }
 
-    /// Add a hooks implementation and return self for chaining
+    /// Add a hooks implementation with priority 0 and return self for
+    /// chaining
     pub fn with<H: Hooks + 'static>(mut self, hooks: H) -> Self {
         self.add(hooks);
         self
     }
+
+    /// Add a hooks implementation with a priority and return self for
+    /// chaining
+    pub fn with_priority<H: Hooks + 'static>(mut self, hooks: H, priority: i32) -> Self {
+        self.add_with_priority(hooks, priority);
+        self
+    }
+
+    /// The hooks, in the order they are called
+    fn iter(&self) -> impl Iterator<Item = &dyn Hooks> {
+        self.hooks.iter().map(|(_, hooks)| hooks.as_ref())
+    }
 }
 
 impl Default for CompositeHooks {
@@ -239,70 +545,129 @@ impl Default for CompositeHooks {
 impl Hooks for CompositeHooks {
     async fn on_authenticate(
         &self,
-        client_id: &str,
-        username: Option<&str>,
+        client: &ClientContext,
         password: Option<&[u8]>,
-    ) -> HookResult<bool> {
-        for hooks in &self.hooks {
-            if !hooks.on_authenticate(client_id, username, password).await? {
-                return Ok(false);
+    ) -> HookResult<HookDecision> {
+        for hooks in self.iter() {
+            match hooks.on_authenticate(client, password).await? {
+                HookDecision::Continue => {}
+                decision => return Ok(decision),
             }
         }
-        Ok(true)
+        Ok(HookDecision::Continue)
     }
 
     async fn on_publish_check(
         &self,
-        client_id: &str,
-        username: Option<&str>,
+        client: &ClientContext,
         topic: &str,
         qos: QoS,
         retain: bool,
-    ) -> HookResult<bool> {
-        for hooks in &self.hooks {
-            if !hooks
-                .on_publish_check(client_id, username, topic, qos, retain)
-                .await?
-            {
-                return Ok(false);
+    ) -> HookResult<HookDecision> {
+        for hooks in self.iter() {
+            match hooks.on_publish_check(client, topic, qos, retain).await? {
+                HookDecision::Continue => {}
+                decision => return Ok(decision),
             }
         }
-        Ok(true)
+        Ok(HookDecision::Continue)
+    }
+
+    async fn on_publish_transform(
+        &self,
+        client: &ClientContext,
+        publish: &Publish,
+    ) -> HookResult<PublishTransform> {
+        let mut modified: Option<Box<Publish>> = None;
+        for hooks in self.iter() {
+            let current = modified.as_deref().unwrap_or(publish);
+            match hooks.on_publish_transform(client, current).await? {
+                PublishTransform::Unchanged => {}
+                PublishTransform::Modified(publish) => modified = Some(publish),
+                reject @ PublishTransform::Reject(_) => return Ok(reject),
+            }
+        }
+        Ok(modified.map_or(PublishTransform::Unchanged, PublishTransform::Modified))
     }
 
     async fn on_subscribe_check(
         &self,
-        client_id: &str,
-        username: Option<&str>,
+        client: &ClientContext,
         filter: &str,
         qos: QoS,
-    ) -> HookResult<bool> {
-        for hooks in &self.hooks {
-            if !hooks
-                .on_subscribe_check(client_id, username, filter, qos)
-                .await?
-            {
-                return Ok(false);
+    ) -> HookResult<SubscribeDecision> {
+        for hooks in self.iter() {
+            match hooks.on_subscribe_check(client, filter, qos).await? {
+                SubscribeDecision::Continue => {}
+                decision => return Ok(decision),
             }
         }
-        Ok(true)
+        Ok(SubscribeDecision::Continue)
+    }
+
+    fn grants_sys_subscribe(&self, client: &ClientContext, filter: &str) -> bool {
+        self.iter()
+            .any(|hooks| hooks.grants_sys_subscribe(client, filter))
+    }
+
+    async fn on_unsubscribe(&self, client: &ClientContext, filter: &str) {
+        for hooks in self.iter() {
+            hooks.on_unsubscribe(client, filter).await;
+        }
     }
 
-    async fn on_client_connected(&self, client_id: &str, username: Option<&str>) {
-        for hooks in &self.hooks {
-            hooks.on_client_connected(client_id, username).await;
+    async fn queue_overflow_policy(&self, client: &ClientContext) -> Option<QueueOverflowPolicy> {
+        for hooks in self.iter() {
+            if let Some(policy) = hooks.queue_overflow_policy(client).await {
+                return Some(policy);
+            }
         }
+        None
     }
 
-    async fn on_client_disconnected(&self, client_id: &str, graceful: bool) {
-        for hooks in &self.hooks {
-            hooks.on_client_disconnected(client_id, graceful).await;
+    async fn on_client_connected(&self, client: &ClientContext) {
+        for hooks in self.iter() {
+            hooks.on_client_connected(client).await;
+        }
+    }
+
+    async fn on_client_disconnected(&self, client: &ClientContext, reason: DisconnectReason) {
+        for hooks in self.iter() {
+            hooks.on_client_disconnected(client, reason).await;
+        }
+    }
+
+    async fn on_session_expired(&self, client_id: &str) {
+        for hooks in self.iter() {
+            hooks.on_session_expired(client_id).await;
         }
     }
 
     async fn on_message_published(&self, topic: &str, payload: &[u8], qos: QoS) {
-        for hooks in &self.hooks {
+        for hooks in self.iter() {
             hooks.on_message_published(topic, payload, qos).await;
         }
     }
+
+    async fn on_message_delivered(&self, client: &ClientContext, topic: &str, qos: QoS) {
+        for hooks in self.iter() {
+            hooks.on_message_delivered(client, topic, qos).await;
+        }
+    }
+
+    async fn on_message_dropped(&self, client_id: &str, topic: &str, reason: MessageDropReason) {
+        for hooks in self.iter() {
+            hooks.on_message_dropped(client_id, topic, reason).await;
+        }
+    }
+
+    fn observes_messages(&self) -> bool {
+        self.iter().any(|hooks| hooks.observes_messages())
+    }
+
+    async fn on_persistence_op_dropped(&self, op: &PersistenceOp, reason: DropReason) {
+        for hooks in self.iter() {
+            hooks.on_persistence_op_dropped(op, reason).await;
+        }
+    }
 }
@@ -0,0 +1,493 @@
+//! Scripting Hook
+//!
+//! Runs authentication, authorization and publish transform hooks written
+//! in [Rhai](https://rhai.rs), for policies too involved for the TOML ACL
+//! but not worth a Rust plugin. The script defines any of:
+//!
+//! ```rhai
+//! fn authenticate(client, password) { ... }
+//! fn authorize_publish(client, topic, qos, retain) { ... }
+//! fn authorize_subscribe(client, filter, qos) { ... }
+//! fn transform_publish(client, message) { ... }
+//! ```
+//!
+//! `client` is a map of `client_id`, `username`, `peer_host`, `peer_port`,
+//! `cert_cn`, `listener` and `protocol_version`; `message` one of `topic`,
+//! `payload` (a string, or a blob if not UTF-8), `qos` and `retain`.
+//!
+//! Checks return `true` or `"allow"` to allow, `false` or `"deny"` to deny,
+//! and `()` or `"continue"` to leave the decision to the other hooks;
+//! `authorize_subscribe` may also return a QoS to grant at most. A
+//! transform returns the (modified) message map, `()` to keep the message,
+//! or `false` to refuse it.
+//!
+//! A script file is checked for changes periodically and recompiled; a
+//! script that fails to compile keeps the previous one running.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use parking_lot::{Mutex, RwLock};
+use rhai::{Blob, Dynamic, Engine, Map, Scope, AST};
+use tracing::{info, warn};
+
+use super::{
+    ClientContext, HookDecision, HookError, HookResult, Hooks, PublishTransform, SubscribeDecision,
+};
+use crate::config::ScriptConfig;
+use crate::protocol::{Publish, QoS, ReasonCode};
+
+/// Hook provider running a Rhai script
+pub struct ScriptHooks {
+    engine: Engine,
+    ast: RwLock<Arc<AST>>,
+    /// Script file, if not inline
+    path: Option<PathBuf>,
+    /// Modification time of the loaded file
+    modified: Mutex<Option<SystemTime>>,
+}
+
+impl ScriptHooks {
+    /// Compile the configured script
+    pub fn load(config: &ScriptConfig) -> HookResult<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(config.max_operations);
+
+        let (source, modified) = match &config.path {
+            Some(path) => read(path)?,
+            None => (config.source.clone().unwrap_or_default(), None),
+        };
+        let ast = compile(&engine, &source)?;
+        Ok(Self {
+            engine,
+            ast: RwLock::new(Arc::new(ast)),
+            path: config.path.clone(),
+            modified: Mutex::new(modified),
+        })
+    }
+
+    /// Hook functions the script defines
+    pub fn functions(&self) -> Vec<String> {
+        self.ast
+            .read()
+            .iter_functions()
+            .map(|f| f.name.to_string())
+            .collect()
+    }
+
+    /// Recompile the script file if it changed since it was loaded
+    ///
+    /// # Returns
+    /// * `Ok(true)` - The script was reloaded
+    /// * `Ok(false)` - The script is inline or unchanged
+    /// * `Err(_)` - The file could not be read or compiled; the previous
+    ///   script stays loaded
+    pub fn reload(&self) -> HookResult<bool> {
+        let Some(path) = &self.path else {
+            return Ok(false);
+        };
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        if modified.is_some() && modified == *self.modified.lock() {
+            return Ok(false);
+        }
+        let (source, modified) = read(path)?;
+        let ast = compile(&self.engine, &source)?;
+        *self.ast.write() = Arc::new(ast);
+        *self.modified.lock() = modified;
+        Ok(true)
+    }
+
+    /// Reload the script file every `interval` while the provider is in use
+    pub fn spawn_reloader(self: &Arc<Self>, interval: std::time::Duration) {
+        if self.path.is_none() || interval.is_zero() {
+            return;
+        }
+        let hooks = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(hooks) = hooks.upgrade() else {
+                    return;
+                };
+                match hooks.reload() {
+                    Ok(true) => info!("Script {:?} reloaded", hooks.path),
+                    Ok(false) => {}
+                    Err(e) => warn!("Script {:?} not reloaded: {}", hooks.path, e),
+                }
+            }
+        });
+    }
+
+    /// Call a script function, `None` if the script does not define it
+    fn call(&self, name: &str, args: impl rhai::FuncArgs) -> HookResult<Option<Dynamic>> {
+        let ast = self.ast.read().clone();
+        let mut args_vec = Vec::new();
+        args.parse(&mut args_vec);
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == name && f.params.len() == args_vec.len())
+        {
+            return Ok(None);
+        }
+        self.engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &ast, name, args_vec)
+            .map(Some)
+            .map_err(|e| HookError::Internal(format!("script {}: {}", name, e)))
+    }
+}
+
+/// Read a script file with its modification time
+fn read(path: &Path) -> HookResult<(String, Option<SystemTime>)> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| HookError::Internal(format!("cannot read script {:?}: {}", path, e)))?;
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    Ok((source, modified))
+}
+
+fn compile(engine: &Engine, source: &str) -> HookResult<AST> {
+    engine
+        .compile(source)
+        .map_err(|e| HookError::Internal(format!("invalid script: {}", e)))
+}
+
+/// The `client` argument of script functions
+fn client_map(client: &ClientContext) -> Map {
+    let optional = |value: &Option<String>| value.clone().map_or(Dynamic::UNIT, Dynamic::from);
+    let mut map = Map::new();
+    let client_id = client.client_id.to_string();
+    map.insert("client_id".into(), Dynamic::from(client_id));
+    map.insert("username".into(), optional(&client.username));
+    let peer_host = client.peer_addr.ip().to_string();
+    map.insert("peer_host".into(), Dynamic::from(peer_host));
+    map.insert(
+        "peer_port".into(),
+        Dynamic::from(client.peer_addr.port() as i64),
+    );
+    map.insert("cert_cn".into(), optional(&client.cert_cn));
+    let listener = client.listener.as_str().to_string();
+    map.insert("listener".into(), Dynamic::from(listener));
+    let protocol_version = client.protocol_version as i64;
+    map.insert("protocol_version".into(), Dynamic::from(protocol_version));
+    map
+}
+
+/// The `message` argument of `transform_publish`
+fn message_map(publish: &Publish) -> Map {
+    let payload = match std::str::from_utf8(&publish.payload) {
+        Ok(text) => Dynamic::from(text.to_string()),
+        Err(_) => Dynamic::from_blob(publish.payload.to_vec()),
+    };
+    let mut map = Map::new();
+    map.insert("topic".into(), Dynamic::from(publish.topic.clone()));
+    map.insert("payload".into(), payload);
+    map.insert("qos".into(), Dynamic::from(publish.qos as i64));
+    map.insert("retain".into(), Dynamic::from(publish.retain));
+    map
+}
+
+/// The decision a check returned
+fn decision(name: &str, value: Dynamic) -> HookResult<HookDecision> {
+    if value.is_unit() {
+        return Ok(HookDecision::Continue);
+    }
+    if let Some(allowed) = value.clone().try_cast::<bool>() {
+        return Ok(allowed.into());
+    }
+    match value.into_string().as_deref() {
+        Ok("allow") => Ok(HookDecision::Allow),
+        Ok("deny") => Ok(HookDecision::Deny),
+        Ok("continue") => Ok(HookDecision::Continue),
+        _ => Err(HookError::Internal(format!(
+            "script {} must return a bool, \"allow\", \"deny\" or \"continue\"",
+            name
+        ))),
+    }
+}
+
+/// What `transform_publish` did to `publish`
+fn transform(publish: &Publish, value: Dynamic) -> HookResult<PublishTransform> {
+    if value.is_unit() || value.clone().try_cast::<bool>() == Some(true) {
+        return Ok(PublishTransform::Unchanged);
+    }
+    if value.clone().try_cast::<bool>() == Some(false) {
+        return Ok(PublishTransform::Reject(ReasonCode::NotAuthorized));
+    }
+    let invalid = || {
+        HookError::Internal("script transform_publish must return a message, () or false".into())
+    };
+    let message = value.try_cast::<Map>().ok_or_else(invalid)?;
+    let topic = match message.get("topic") {
+        Some(topic) => topic.clone().into_string().map_err(|_| invalid())?.into(),
+        None => publish.topic.clone(),
+    };
+    let payload = match message.get("payload") {
+        Some(payload) if payload.is_blob() => payload.clone().cast::<Blob>(),
+        Some(payload) => payload
+            .clone()
+            .into_string()
+            .map_err(|_| invalid())?
+            .into_bytes(),
+        None => publish.payload.to_vec(),
+    };
+    if topic == publish.topic && payload[..] == publish.payload[..] {
+        return Ok(PublishTransform::Unchanged);
+    }
+    Ok(PublishTransform::Modified(Box::new(Publish {
+        topic,
+        payload: payload.into(),
+        ..publish.clone()
+    })))
+}
+
+#[async_trait]
+impl Hooks for ScriptHooks {
+    async fn on_authenticate(
+        &self,
+        client: &ClientContext,
+        password: Option<&[u8]>,
+    ) -> HookResult<HookDecision> {
+        const NAME: &str = "authenticate";
+        let password = password.map_or(Dynamic::UNIT, |p| {
+            Dynamic::from(String::from_utf8_lossy(p).into_owned())
+        });
+        match self.call(NAME, (client_map(client), password))? {
+            Some(value) => decision(NAME, value),
+            None => Ok(HookDecision::Continue),
+        }
+    }
+
+    async fn on_publish_check(
+        &self,
+        client: &ClientContext,
+        topic: &str,
+        qos: QoS,
+        retain: bool,
+    ) -> HookResult<HookDecision> {
+        const NAME: &str = "authorize_publish";
+        let args = (client_map(client), topic.to_string(), qos as i64, retain);
+        match self.call(NAME, args)? {
+            Some(value) => decision(NAME, value),
+            None => Ok(HookDecision::Continue),
+        }
+    }
+
+    async fn on_publish_transform(
+        &self,
+        client: &ClientContext,
+        publish: &Publish,
+    ) -> HookResult<PublishTransform> {
+        match self.call(
+            "transform_publish",
+            (client_map(client), message_map(publish)),
+        )? {
+            Some(value) => transform(publish, value),
+            None => Ok(PublishTransform::Unchanged),
+        }
+    }
+
+    async fn on_subscribe_check(
+        &self,
+        client: &ClientContext,
+        filter: &str,
+        qos: QoS,
+    ) -> HookResult<SubscribeDecision> {
+        const NAME: &str = "authorize_subscribe";
+        let args = (client_map(client), filter.to_string(), qos as i64);
+        let Some(value) = self.call(NAME, args)? else {
+            return Ok(SubscribeDecision::Continue);
+        };
+        if let Some(granted) = value.clone().try_cast::<i64>() {
+            return u8::try_from(granted)
+                .ok()
+                .and_then(QoS::from_u8)
+                .map(SubscribeDecision::Grant)
+                .ok_or_else(|| {
+                    HookError::Internal(format!("script {} granted QoS {}", NAME, granted))
+                });
+        }
+        decision(NAME, value).map(SubscribeDecision::from)
+    }
+
+    fn observes_messages(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Properties;
+
+    fn hooks(source: &str) -> ScriptHooks {
+        ScriptHooks::load(&ScriptConfig {
+            enabled: true,
+            source: Some(source.to_string()),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn client(username: Option<&str>) -> ClientContext {
+        ClientContext::new("dev-1", "10.0.0.1:50000".parse().unwrap()).with_username(username)
+    }
+
+    fn publish(topic: &str, payload: &'static [u8]) -> Publish {
+        Publish {
+            topic: topic.into(),
+            payload: bytes::Bytes::from_static(payload),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            dup: false,
+            packet_id: Some(1),
+            properties: Properties::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_checks() {
+        let hooks = hooks(
+            r#"
+            fn authenticate(client, password) {
+                if client.username == "root" { password == "secret" } else { () }
+            }
+            fn authorize_publish(client, topic, qos, retain) {
+                let own = "devices/" + client.client_id + "/";
+                if topic.starts_with(own) { "continue" } else { "deny" }
+            }
+            fn authorize_subscribe(client, filter, qos) {
+                if client.peer_host.starts_with("10.") { 1 } else { false }
+            }
+            "#,
+        );
+        assert_eq!(hooks.functions().len(), 3);
+
+        let root = client(Some("root"));
+        let result = hooks.on_authenticate(&root, Some(b"secret")).await.unwrap();
+        assert_eq!(result, HookDecision::Allow);
+        let result = hooks.on_authenticate(&root, Some(b"wrong")).await.unwrap();
+        assert_eq!(result, HookDecision::Deny);
+        let result = hooks.on_authenticate(&client(None), None).await.unwrap();
+        assert_eq!(result, HookDecision::Continue);
+
+        let device = client(None);
+        let result = hooks
+            .on_publish_check(&device, "devices/dev-1/temp", QoS::AtMostOnce, false)
+            .await
+            .unwrap();
+        assert_eq!(result, HookDecision::Continue);
+        let result = hooks
+            .on_publish_check(&device, "devices/dev-2/temp", QoS::AtMostOnce, false)
+            .await
+            .unwrap();
+        assert_eq!(result, HookDecision::Deny);
+
+        let result = hooks
+            .on_subscribe_check(&device, "devices/#", QoS::ExactlyOnce)
+            .await
+            .unwrap();
+        assert_eq!(result, SubscribeDecision::Grant(QoS::AtLeastOnce));
+    }
+
+    #[tokio::test]
+    async fn test_transform() {
+        let hooks = hooks(
+            r#"
+            fn transform_publish(client, message) {
+                if message.topic.starts_with("blocked/") { return false; }
+                if message.topic.starts_with("raw/") { return (); }
+                message.topic = "tagged/" + message.topic;
+                message.payload += "|" + client.client_id;
+                message
+            }
+            "#,
+        );
+        let device = client(None);
+
+        match hooks
+            .on_publish_transform(&device, &publish("a/b", b"21.5"))
+            .await
+            .unwrap()
+        {
+            PublishTransform::Modified(modified) => {
+                assert_eq!(modified.topic, "tagged/a/b");
+                assert_eq!(&modified.payload[..], b"21.5|dev-1");
+                assert_eq!(modified.packet_id, Some(1));
+            }
+            other => panic!("expected a modified publish, got {:?}", other),
+        }
+        assert!(matches!(
+            hooks
+                .on_publish_transform(&device, &publish("raw/a", b"x"))
+                .await
+                .unwrap(),
+            PublishTransform::Unchanged
+        ));
+        assert!(matches!(
+            hooks
+                .on_publish_transform(&device, &publish("blocked/a", b"x"))
+                .await
+                .unwrap(),
+            PublishTransform::Reject(ReasonCode::NotAuthorized)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_errors_and_limits() {
+        let config = ScriptConfig {
+            enabled: true,
+            source: Some("fn authenticate(client, password) {".to_string()),
+            ..Default::default()
+        };
+        assert!(ScriptHooks::load(&config).is_err());
+
+        // Runaway scripts are stopped
+        let config = ScriptConfig {
+            enabled: true,
+            source: Some("fn authenticate(client, password) { loop {} }".to_string()),
+            max_operations: 1_000,
+            ..Default::default()
+        };
+        let runaway = ScriptHooks::load(&config).unwrap();
+        assert!(runaway.on_authenticate(&client(None), None).await.is_err());
+
+        // Results of the wrong type are errors
+        let hooks = hooks("fn authorize_publish(client, topic, qos, retain) { 42 }");
+        assert!(hooks
+            .on_publish_check(&client(None), "a", QoS::AtMostOnce, false)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_reload() {
+        let path = std::env::temp_dir().join(format!("vibemq-script-{}.rhai", std::process::id()));
+        std::fs::write(&path, "fn authenticate(client, password) { true }").unwrap();
+        let hooks = ScriptHooks::load(&ScriptConfig {
+            enabled: true,
+            path: Some(path.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(!hooks.reload().unwrap(), "Unchanged script is kept");
+
+        // A broken script keeps the previous one
+        std::fs::write(&path, "fn authenticate(client, password) {").unwrap();
+        *hooks.modified.lock() = None;
+        assert!(hooks.reload().is_err());
+        assert_eq!(hooks.functions(), vec!["authenticate".to_string()]);
+
+        std::fs::write(
+            &path,
+            "fn authorize_publish(client, topic, qos, retain) { false }",
+        )
+        .unwrap();
+        *hooks.modified.lock() = None;
+        assert!(hooks.reload().unwrap());
+        assert_eq!(hooks.functions(), vec!["authorize_publish".to_string()]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
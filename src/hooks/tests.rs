@@ -2,36 +2,47 @@
 
 use super::*;
 
+fn client(client_id: &str, username: Option<&str>) -> ClientContext {
+    ClientContext::new(client_id, "127.0.0.1:50000".parse().unwrap()).with_username(username)
+}
+
 #[tokio::test]
 async fn test_default_hooks_allow_all() {
     let hooks = DefaultHooks;
 
     // Test authentication
     let result = hooks
-        .on_authenticate("client1", Some("user"), Some(b"pass"))
+        .on_authenticate(&client("client1", Some("user")), Some(b"pass"))
         .await
         .unwrap();
-    assert!(result, "DefaultHooks should allow authentication");
+    assert_eq!(result, HookDecision::Continue);
+    assert!(
+        result.is_allowed(),
+        "DefaultHooks should allow authentication"
+    );
 
     // Test publish
     let result = hooks
         .on_publish_check(
-            "client1",
-            Some("user"),
+            &client("client1", Some("user")),
             "test/topic",
             QoS::AtMostOnce,
             false,
         )
         .await
         .unwrap();
-    assert!(result, "DefaultHooks should allow publish");
+    assert!(result.is_allowed(), "DefaultHooks should allow publish");
 
     // Test subscribe
     let result = hooks
-        .on_subscribe_check("client1", Some("user"), "test/#", QoS::AtLeastOnce)
+        .on_subscribe_check(&client("client1", Some("user")), "test/#", QoS::AtLeastOnce)
         .await
         .unwrap();
-    assert!(result, "DefaultHooks should allow subscribe");
+    assert_eq!(
+        result,
+        SubscribeDecision::Continue,
+        "DefaultHooks should leave subscribe to other hooks"
+    );
 }
 
 struct AllowHooks;
@@ -41,32 +52,29 @@ struct DenyHooks;
 impl Hooks for AllowHooks {
     async fn on_authenticate(
         &self,
-        _client_id: &str,
-        _username: Option<&str>,
+        _client: &ClientContext,
         _password: Option<&[u8]>,
-    ) -> HookResult<bool> {
-        Ok(true)
+    ) -> HookResult<HookDecision> {
+        Ok(HookDecision::Allow)
     }
 
     async fn on_publish_check(
         &self,
-        _client_id: &str,
-        _username: Option<&str>,
+        _client: &ClientContext,
         _topic: &str,
         _qos: QoS,
         _retain: bool,
-    ) -> HookResult<bool> {
-        Ok(true)
+    ) -> HookResult<HookDecision> {
+        Ok(HookDecision::Allow)
     }
 
     async fn on_subscribe_check(
         &self,
-        _client_id: &str,
-        _username: Option<&str>,
+        _client: &ClientContext,
         _filter: &str,
         _qos: QoS,
-    ) -> HookResult<bool> {
-        Ok(true)
+    ) -> HookResult<SubscribeDecision> {
+        Ok(SubscribeDecision::Allow)
     }
 }
 
@@ -74,83 +82,410 @@ impl Hooks for AllowHooks {
 impl Hooks for DenyHooks {
     async fn on_authenticate(
         &self,
-        _client_id: &str,
-        _username: Option<&str>,
+        _client: &ClientContext,
         _password: Option<&[u8]>,
-    ) -> HookResult<bool> {
-        Ok(false)
+    ) -> HookResult<HookDecision> {
+        Ok(HookDecision::Deny)
     }
 
     async fn on_publish_check(
         &self,
-        _client_id: &str,
-        _username: Option<&str>,
+        _client: &ClientContext,
         _topic: &str,
         _qos: QoS,
         _retain: bool,
-    ) -> HookResult<bool> {
-        Ok(false)
+    ) -> HookResult<HookDecision> {
+        Ok(HookDecision::Deny)
     }
 
     async fn on_subscribe_check(
         &self,
-        _client_id: &str,
-        _username: Option<&str>,
+        _client: &ClientContext,
         _filter: &str,
         _qos: QoS,
-    ) -> HookResult<bool> {
-        Ok(false)
+    ) -> HookResult<SubscribeDecision> {
+        Ok(SubscribeDecision::Deny(ReasonCode::NotAuthorized))
     }
 }
 
 #[tokio::test]
-async fn test_composite_hooks_all_must_allow() {
-    let hooks = CompositeHooks::new().with(AllowHooks).with(AllowHooks);
+async fn test_composite_hooks_first_decision_wins() {
+    let hooks = CompositeHooks::new().with(AllowHooks).with(DenyHooks);
+    let result = hooks
+        .on_authenticate(&client("client1", Some("user")), Some(b"pass"))
+        .await
+        .unwrap();
+    assert_eq!(result, HookDecision::Allow, "First hook allows");
 
+    let hooks = CompositeHooks::new().with(DenyHooks).with(AllowHooks);
     let result = hooks
-        .on_authenticate("client1", Some("user"), Some(b"pass"))
+        .on_authenticate(&client("client1", Some("user")), Some(b"pass"))
         .await
         .unwrap();
-    assert!(result, "Both hooks allow, should be allowed");
+    assert_eq!(result, HookDecision::Deny, "First hook denies");
 }
 
 #[tokio::test]
-async fn test_composite_hooks_one_deny_fails() {
-    let hooks = CompositeHooks::new().with(AllowHooks).with(DenyHooks);
+async fn test_composite_hooks_continue() {
+    let hooks = CompositeHooks::new().with(DefaultHooks).with(DenyHooks);
+    let result = hooks
+        .on_authenticate(&client("client1", Some("user")), Some(b"pass"))
+        .await
+        .unwrap();
+    assert_eq!(result, HookDecision::Deny, "Later hook denies");
 
+    let hooks = CompositeHooks::new().with(DefaultHooks).with(DefaultHooks);
     let result = hooks
-        .on_authenticate("client1", Some("user"), Some(b"pass"))
+        .on_authenticate(&client("client1", Some("user")), Some(b"pass"))
         .await
         .unwrap();
-    assert!(!result, "One hook denies, should be denied");
+    assert_eq!(result, HookDecision::Continue, "No hook decides");
+    assert!(result.is_allowed());
+}
+
+#[tokio::test]
+async fn test_composite_hooks_priority() {
+    // A superuser hook added last still runs before the deny-all hook
+    let hooks = CompositeHooks::new()
+        .with(DenyHooks)
+        .with_priority(AllowHooks, 10);
+    let result = hooks
+        .on_authenticate(&client("client1", Some("admin")), Some(b"pass"))
+        .await
+        .unwrap();
+    assert_eq!(result, HookDecision::Allow);
+
+    // Negative priorities run after the default, equal ones in order added
+    let mut hooks = CompositeHooks::new();
+    hooks.add_with_priority(AllowHooks, -1);
+    hooks.add(DenyHooks);
+    hooks.add(AllowHooks);
+    let result = hooks
+        .on_authenticate(&client("client1", None), None)
+        .await
+        .unwrap();
+    assert_eq!(result, HookDecision::Deny);
 }
 
 #[tokio::test]
 async fn test_composite_hooks_publish_check() {
-    let hooks = CompositeHooks::new().with(AllowHooks).with(DenyHooks);
+    let hooks = CompositeHooks::new().with(DefaultHooks).with(DenyHooks);
 
     let result = hooks
         .on_publish_check(
-            "client1",
-            Some("user"),
+            &client("client1", Some("user")),
             "test/topic",
             QoS::AtMostOnce,
             false,
         )
         .await
         .unwrap();
-    assert!(!result, "One hook denies publish, should be denied");
+    assert_eq!(
+        result,
+        HookDecision::Deny,
+        "One hook denies publish, should be denied"
+    );
 }
 
 #[tokio::test]
 async fn test_composite_hooks_subscribe_check() {
-    let hooks = CompositeHooks::new().with(AllowHooks).with(DenyHooks);
+    let hooks = CompositeHooks::new().with(DefaultHooks).with(DenyHooks);
 
     let result = hooks
-        .on_subscribe_check("client1", Some("user"), "test/#", QoS::AtLeastOnce)
+        .on_subscribe_check(&client("client1", Some("user")), "test/#", QoS::AtLeastOnce)
+        .await
+        .unwrap();
+    assert_eq!(
+        result,
+        SubscribeDecision::Deny(ReasonCode::NotAuthorized),
+        "One hook denies subscribe, should be denied"
+    );
+}
+
+/// Grants at most a fixed QoS
+struct GrantHooks(QoS);
+
+#[async_trait]
+impl Hooks for GrantHooks {
+    async fn on_subscribe_check(
+        &self,
+        _client: &ClientContext,
+        _filter: &str,
+        _qos: QoS,
+    ) -> HookResult<SubscribeDecision> {
+        Ok(SubscribeDecision::Grant(self.0))
+    }
+}
+
+#[tokio::test]
+async fn test_composite_hooks_grant() {
+    let hooks = CompositeHooks::new()
+        .with(DefaultHooks)
+        .with(GrantHooks(QoS::AtLeastOnce))
+        .with(GrantHooks(QoS::AtMostOnce));
+    let result = hooks
+        .on_subscribe_check(&client("client1", None), "test/#", QoS::ExactlyOnce)
+        .await
+        .unwrap();
+    assert_eq!(result, SubscribeDecision::Grant(QoS::AtLeastOnce));
+
+    let hooks = CompositeHooks::new()
+        .with(GrantHooks(QoS::AtLeastOnce))
+        .with_priority(DenyHooks, 1);
+    let result = hooks
+        .on_subscribe_check(&client("client1", None), "test/#", QoS::ExactlyOnce)
+        .await
+        .unwrap();
+    assert_eq!(result, SubscribeDecision::Deny(ReasonCode::NotAuthorized));
+
+    assert_eq!(
+        SubscribeDecision::from(false),
+        SubscribeDecision::Deny(ReasonCode::NotAuthorized)
+    );
+    assert_eq!(
+        SubscribeDecision::from(HookDecision::Continue),
+        SubscribeDecision::Continue
+    );
+}
+
+/// Records the delivery outcomes it is told about
+#[derive(Default)]
+struct OutcomeHooks {
+    outcomes: std::sync::Mutex<Vec<String>>,
+}
+
+#[async_trait]
+impl Hooks for OutcomeHooks {
+    async fn on_message_delivered(&self, client: &ClientContext, topic: &str, qos: QoS) {
+        self.outcomes.lock().unwrap().push(format!(
+            "delivered {} {} {:?}",
+            client.client_id, topic, qos
+        ));
+    }
+
+    async fn on_message_dropped(&self, client_id: &str, topic: &str, reason: MessageDropReason) {
+        self.outcomes.lock().unwrap().push(format!(
+            "dropped {} {} {}",
+            client_id,
+            topic,
+            reason.as_str()
+        ));
+    }
+}
+
+#[tokio::test]
+async fn test_composite_hooks_delivery_outcomes() {
+    let first = std::sync::Arc::new(OutcomeHooks::default());
+    let second = std::sync::Arc::new(OutcomeHooks::default());
+    let hooks = CompositeHooks::new()
+        .with(first.clone())
+        .with(second.clone());
+
+    hooks
+        .on_message_delivered(&client("client1", None), "a/b", QoS::AtLeastOnce)
+        .await;
+    hooks
+        .on_message_dropped("client1", "a/c", MessageDropReason::QueueFull)
+        .await;
+
+    let expected = vec![
+        "delivered client1 a/b AtLeastOnce".to_string(),
+        "dropped client1 a/c queue_full".to_string(),
+    ];
+    assert_eq!(*first.outcomes.lock().unwrap(), expected);
+    assert_eq!(*second.outcomes.lock().unwrap(), expected);
+}
+
+#[test]
+fn test_observes_messages() {
+    assert!(!DefaultHooks.observes_messages());
+    let hooks = CompositeHooks::new().with(DefaultHooks).with(DenyHooks);
+    assert!(hooks.observes_messages());
+    let hooks = CompositeHooks::new().with(DefaultHooks);
+    assert!(!hooks.observes_messages());
+    let hooks = CompositeHooks::new()
+        .with(DefaultHooks)
+        .with(OutcomeHooks::default());
+    assert!(hooks.observes_messages());
+}
+
+/// Appends a suffix to the payload, or rejects publishes to `blocked/#`
+struct SuffixHooks(&'static str);
+
+#[async_trait]
+impl Hooks for SuffixHooks {
+    async fn on_publish_transform(
+        &self,
+        _client: &ClientContext,
+        publish: &Publish,
+    ) -> HookResult<PublishTransform> {
+        if publish.topic.starts_with("blocked/") {
+            return Ok(PublishTransform::Reject(ReasonCode::NotAuthorized));
+        }
+        let mut payload = publish.payload.to_vec();
+        payload.extend_from_slice(self.0.as_bytes());
+        Ok(PublishTransform::Modified(Box::new(Publish {
+            payload: payload.into(),
+            ..publish.clone()
+        })))
+    }
+}
+
+#[tokio::test]
+async fn test_composite_hooks_publish_transform() {
+    let publish = |topic: &str| Publish {
+        topic: topic.into(),
+        payload: bytes::Bytes::from_static(b"x"),
+        qos: QoS::AtMostOnce,
+        retain: false,
+        dup: false,
+        packet_id: None,
+        properties: crate::protocol::Properties::default(),
+    };
+
+    let unchanged = CompositeHooks::new()
+        .with(AllowHooks)
+        .on_publish_transform(&client("client1", None), &publish("a/b"))
         .await
         .unwrap();
-    assert!(!result, "One hook denies subscribe, should be denied");
+    assert!(matches!(unchanged, PublishTransform::Unchanged));
+
+    // Each hook transforms the previous one's output
+    let hooks = CompositeHooks::new()
+        .with(SuffixHooks("1"))
+        .with(AllowHooks)
+        .with(SuffixHooks("2"));
+    match hooks
+        .on_publish_transform(&client("client1", None), &publish("a/b"))
+        .await
+        .unwrap()
+    {
+        PublishTransform::Modified(modified) => assert_eq!(&modified.payload[..], b"x12"),
+        other => panic!("expected a modified publish, got {:?}", other),
+    }
+
+    let rejected = hooks
+        .on_publish_transform(&client("client1", None), &publish("blocked/a"))
+        .await
+        .unwrap();
+    assert!(matches!(
+        rejected,
+        PublishTransform::Reject(ReasonCode::NotAuthorized)
+    ));
+}
+
+/// Allows publishing only to `devices/<certificate CN>/#`
+struct CertTopicHooks;
+
+#[async_trait]
+impl Hooks for CertTopicHooks {
+    async fn on_publish_check(
+        &self,
+        client: &ClientContext,
+        topic: &str,
+        _qos: QoS,
+        _retain: bool,
+    ) -> HookResult<HookDecision> {
+        Ok(client
+            .cert_cn
+            .as_deref()
+            .is_some_and(|cn| topic.starts_with(&format!("devices/{}/", cn)))
+            .into())
+    }
+}
+
+#[tokio::test]
+async fn test_hooks_see_client_context() {
+    let hooks = CompositeHooks::new()
+        .with(DefaultHooks)
+        .with(CertTopicHooks);
+    let mut device = client("client1", Some("user"));
+    assert_eq!(device.username(), Some("user"));
+    assert_eq!(device.peer_addr.port(), 50000);
+
+    let allowed = hooks
+        .on_publish_check(&device, "devices/dev-1/temp", QoS::AtMostOnce, false)
+        .await
+        .unwrap();
+    assert_eq!(
+        allowed,
+        HookDecision::Deny,
+        "No certificate, should be denied"
+    );
+
+    device.cert_cn = Some("dev-1".to_string());
+    for (topic, expected) in [("devices/dev-1/temp", true), ("devices/dev-2/temp", false)] {
+        let allowed = hooks
+            .on_publish_check(&device, topic, QoS::AtMostOnce, false)
+            .await
+            .unwrap();
+        assert_eq!(allowed.is_allowed(), expected, "{}", topic);
+    }
+}
+
+/// Hangs or panics on publish checks; allows authentication
+struct FaultyHooks {
+    panic: bool,
+}
+
+#[async_trait]
+impl Hooks for FaultyHooks {
+    async fn on_authenticate(
+        &self,
+        _client: &ClientContext,
+        _password: Option<&[u8]>,
+    ) -> HookResult<HookDecision> {
+        Ok(HookDecision::Allow)
+    }
+
+    async fn on_publish_check(
+        &self,
+        _client: &ClientContext,
+        _topic: &str,
+        _qos: QoS,
+        _retain: bool,
+    ) -> HookResult<HookDecision> {
+        if self.panic {
+            panic!("faulty hook");
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+        Ok(HookDecision::Allow)
+    }
+}
+
+#[tokio::test]
+async fn test_guarded_hooks() {
+    use crate::config::FailedAction;
+    use std::time::Duration;
+
+    let device = client("client1", None);
+    for panic in [false, true] {
+        let hooks = GuardedHooks::new(
+            FaultyHooks { panic },
+            Duration::from_millis(50),
+            FailedAction::Deny,
+        );
+        let result = hooks.on_authenticate(&device, None).await.unwrap();
+        assert_eq!(result, HookDecision::Allow, "Healthy calls pass through");
+        assert!(
+            hooks
+                .on_publish_check(&device, "a/b", QoS::AtMostOnce, false)
+                .await
+                .is_err(),
+            "Failed call should fail closed"
+        );
+
+        let hooks = GuardedHooks::new(
+            FaultyHooks { panic },
+            Duration::from_millis(50),
+            FailedAction::Ignore,
+        );
+        let result = hooks
+            .on_publish_check(&device, "a/b", QoS::AtMostOnce, false)
+            .await
+            .unwrap();
+        assert_eq!(result, HookDecision::Continue, "Failed call is ignored");
+    }
 }
 
 #[tokio::test]
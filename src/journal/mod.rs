@@ -0,0 +1,393 @@
+//! Message journal.
+//!
+//! An optional append-only log of published messages whose topics match the
+//! configured filters. Where retained messages keep only the last value per
+//! topic, the journal keeps history that can be replayed or tailed, e.g. for
+//! debugging or to hand new subscribers the last N messages.
+//!
+//! The journal is a directory of segment files named by sequence number. Each
+//! record is a little-endian `u32` length followed by a bincode-encoded
+//! [`JournalEntry`]. The active segment is rolled over at `segment_size`, and
+//! whole segments are removed once the journal exceeds `max_bytes` or their
+//! newest entry is older than `max_age`.
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bincode::{Decode, Encode};
+use parking_lot::Mutex;
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+
+use crate::config::JournalConfig;
+use crate::protocol::QoS;
+use crate::topic::topic_matches_filter;
+
+#[cfg(test)]
+mod tests;
+
+/// Segment file extension
+const SEGMENT_EXTENSION: &str = "journal";
+
+/// Size of the length prefix in front of each record
+const LEN_PREFIX: usize = 4;
+
+/// Records claiming to be larger than this are treated as corruption
+const MAX_RECORD_SIZE: usize = 512 * 1024 * 1024;
+
+/// Capacity of the live tail channel
+const TAIL_CHANNEL_CAPACITY: usize = 1024;
+
+/// A journaled message
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct JournalEntry {
+    /// Unix timestamp in milliseconds when the message was journaled
+    pub timestamp_ms: u64,
+    pub topic: String,
+    pub qos: u8,
+    pub retain: bool,
+    pub payload: Vec<u8>,
+}
+
+impl JournalEntry {
+    /// The entry as JSON; UTF-8 payloads are strings, anything else a byte
+    /// array
+    pub fn to_json(&self) -> serde_json::Value {
+        let payload = match std::str::from_utf8(&self.payload) {
+            Ok(text) => serde_json::json!(text),
+            Err(_) => serde_json::json!(self.payload),
+        };
+        serde_json::json!({
+            "timestamp_ms": self.timestamp_ms,
+            "topic": self.topic,
+            "qos": self.qos,
+            "retain": self.retain,
+            "payload": payload,
+        })
+    }
+}
+
+/// The segment currently being appended to
+struct ActiveSegment {
+    id: u64,
+    writer: BufWriter<File>,
+    len: u64,
+    created: SystemTime,
+}
+
+impl ActiveSegment {
+    fn create(dir: &Path, id: u64) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(segment_path(dir, id))?;
+        Ok(Self {
+            id,
+            writer: BufWriter::new(file),
+            len: 0,
+            created: SystemTime::now(),
+        })
+    }
+}
+
+/// Append-only journal of published messages
+pub struct Journal {
+    dir: PathBuf,
+    filters: Vec<String>,
+    max_bytes: u64,
+    max_age: Duration,
+    segment_size: u64,
+    active: Mutex<ActiveSegment>,
+    tail_tx: broadcast::Sender<Arc<JournalEntry>>,
+}
+
+impl Journal {
+    /// Open the journal directory, starting a new segment
+    ///
+    /// Existing segments are kept for replay; a segment left behind by a
+    /// crash is never appended to, so a torn final record stays isolated.
+    pub fn open(config: &JournalConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.path)?;
+
+        // Reuse the last segment only if nothing was ever written to it
+        let id = match list_segments(&config.path)?.last() {
+            Some(&last) if fs::metadata(segment_path(&config.path, last))?.len() == 0 => last,
+            Some(&last) => last + 1,
+            None => 1,
+        };
+        let active = ActiveSegment::create(&config.path, id)?;
+        let (tail_tx, _) = broadcast::channel(TAIL_CHANNEL_CAPACITY);
+
+        let journal = Self {
+            dir: config.path.clone(),
+            filters: config.topics.clone(),
+            max_bytes: config.max_bytes,
+            max_age: config.max_age,
+            segment_size: config.segment_size,
+            active: Mutex::new(active),
+            tail_tx,
+        };
+        journal.maintain()?;
+        Ok(journal)
+    }
+
+    /// Journal directory
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Whether messages on `topic` are journaled
+    pub fn matches(&self, topic: &str) -> bool {
+        self.filters
+            .iter()
+            .any(|filter| topic_matches_filter(topic, filter))
+    }
+
+    /// Append a message if its topic matches a journaled filter
+    ///
+    /// Writes are buffered; call [`Journal::flush`] to make them visible to
+    /// readers. Returns whether the message was journaled.
+    pub fn append(&self, topic: &str, payload: &[u8], qos: QoS, retain: bool) -> io::Result<bool> {
+        if !self.matches(topic) {
+            return Ok(false);
+        }
+
+        let entry = JournalEntry {
+            timestamp_ms: now_ms(),
+            topic: topic.to_string(),
+            qos: qos as u8,
+            retain,
+            payload: payload.to_vec(),
+        };
+        let body = bincode::encode_to_vec(&entry, bincode::config::standard())
+            .map_err(io::Error::other)?;
+
+        {
+            let mut active = self.active.lock();
+            let record_len = (LEN_PREFIX + body.len()) as u64;
+            if active.len > 0 && active.len + record_len > self.segment_size {
+                self.roll(&mut active)?;
+            }
+            active
+                .writer
+                .write_all(&(body.len() as u32).to_le_bytes())?;
+            active.writer.write_all(&body)?;
+            active.len += record_len;
+        }
+
+        // No live tailers is not an error
+        let _ = self.tail_tx.send(Arc::new(entry));
+        Ok(true)
+    }
+
+    /// Write buffered entries to the active segment
+    pub fn flush(&self) -> io::Result<()> {
+        self.active.lock().writer.flush()
+    }
+
+    /// Enforce the age and size limits
+    ///
+    /// Rolls the active segment once it is older than `max_age`, then removes
+    /// the oldest segments until the journal is within its limits.
+    pub fn maintain(&self) -> io::Result<()> {
+        let mut active = self.active.lock();
+        let expired =
+            !self.max_age.is_zero() && active.created.elapsed().unwrap_or_default() > self.max_age;
+        if expired && active.len > 0 {
+            self.roll(&mut active)?;
+        } else {
+            active.writer.flush()?;
+            self.prune(&active)?;
+        }
+        Ok(())
+    }
+
+    /// The last `limit` journaled messages matching `filter`, oldest first
+    ///
+    /// Scans the whole journal, so this is meant for occasional use rather
+    /// than per-message paths.
+    pub fn history(&self, filter: &str, limit: usize) -> io::Result<Vec<JournalEntry>> {
+        self.flush()?;
+
+        let mut entries = VecDeque::new();
+        JournalReader::new(&self.dir).read_new(|entry| {
+            if limit > 0 && topic_matches_filter(&entry.topic, filter) {
+                if entries.len() == limit {
+                    entries.pop_front();
+                }
+                entries.push_back(entry);
+            }
+        })?;
+        Ok(entries.into())
+    }
+
+    /// Receive messages as they are journaled
+    pub fn tail(&self) -> broadcast::Receiver<Arc<JournalEntry>> {
+        self.tail_tx.subscribe()
+    }
+
+    /// Close the active segment and start the next one
+    fn roll(&self, active: &mut ActiveSegment) -> io::Result<()> {
+        active.writer.flush()?;
+        *active = ActiveSegment::create(&self.dir, active.id + 1)?;
+        debug!("Rolled message journal to segment {}", active.id);
+        self.prune(active)
+    }
+
+    /// Remove the oldest closed segments beyond the size and age limits
+    fn prune(&self, active: &ActiveSegment) -> io::Result<()> {
+        let mut segments = Vec::new();
+        for id in list_segments(&self.dir)? {
+            if id == active.id {
+                continue;
+            }
+            let meta = fs::metadata(segment_path(&self.dir, id))?;
+            segments.push((id, meta.len(), meta.modified()?));
+        }
+
+        let mut total = active.len + segments.iter().map(|(_, len, _)| len).sum::<u64>();
+        let cutoff = if self.max_age.is_zero() {
+            None
+        } else {
+            SystemTime::now().checked_sub(self.max_age)
+        };
+
+        // Segments are ordered oldest first
+        for (id, len, modified) in segments {
+            let too_big = self.max_bytes > 0 && total > self.max_bytes;
+            let too_old = cutoff.is_some_and(|cutoff| modified < cutoff);
+            if !too_big && !too_old {
+                break;
+            }
+            fs::remove_file(segment_path(&self.dir, id))?;
+            total -= len;
+            debug!("Removed message journal segment {}", id);
+        }
+        Ok(())
+    }
+}
+
+/// Incremental reader over a journal directory
+///
+/// Each call to [`JournalReader::read_new`] returns the entries appended since
+/// the previous call, so the same reader serves both replay and tailing. It
+/// works on the files alone and can run alongside a live broker.
+pub struct JournalReader {
+    dir: PathBuf,
+    segment: Option<u64>,
+    offset: u64,
+}
+
+impl JournalReader {
+    /// Create a reader positioned at the start of the oldest segment
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            segment: None,
+            offset: 0,
+        }
+    }
+
+    /// Pass entries appended since the last call to `f`, oldest first
+    ///
+    /// A record that is still being written is left for the next call.
+    /// Undecodable records are skipped with a warning. Returns the number of
+    /// entries read.
+    pub fn read_new(&mut self, mut f: impl FnMut(JournalEntry)) -> io::Result<usize> {
+        let mut count = 0;
+
+        for id in list_segments(&self.dir)? {
+            if self.segment.is_some_and(|current| id < current) {
+                continue;
+            }
+            if self.segment != Some(id) {
+                self.segment = Some(id);
+                self.offset = 0;
+            }
+
+            let path = segment_path(&self.dir, id);
+            let mut file = match File::open(&path) {
+                Ok(file) => file,
+                // Pruned since the directory was listed
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            file.seek(SeekFrom::Start(self.offset))?;
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+
+            let mut pos = 0;
+            while data.len() - pos >= LEN_PREFIX {
+                let len =
+                    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+                        as usize;
+                if len > MAX_RECORD_SIZE {
+                    warn!(
+                        "Corrupt record in {} at offset {}, skipping rest of segment",
+                        path.display(),
+                        self.offset + pos as u64
+                    );
+                    pos = data.len();
+                    break;
+                }
+                if data.len() - pos - LEN_PREFIX < len {
+                    break;
+                }
+
+                let body = &data[pos + LEN_PREFIX..pos + LEN_PREFIX + len];
+                match bincode::decode_from_slice(body, bincode::config::standard()) {
+                    Ok((entry, _)) => {
+                        f(entry);
+                        count += 1;
+                    }
+                    Err(e) => warn!(
+                        "Skipping undecodable record in {} at offset {}: {}",
+                        path.display(),
+                        self.offset + pos as u64,
+                        e
+                    ),
+                }
+                pos += LEN_PREFIX + len;
+            }
+            self.offset += pos as u64;
+        }
+
+        Ok(count)
+    }
+}
+
+fn segment_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", id, SEGMENT_EXTENSION))
+}
+
+/// Segment ids in the directory, oldest first
+fn list_segments(dir: &Path) -> io::Result<Vec<u64>> {
+    let mut ids = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(SEGMENT_EXTENSION) {
+            continue;
+        }
+        if let Some(id) = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse().ok())
+        {
+            ids.push(id);
+        }
+    }
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
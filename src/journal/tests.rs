@@ -0,0 +1,154 @@
+//! Message journal tests
+
+use super::*;
+
+fn config(dir: &Path) -> JournalConfig {
+    JournalConfig {
+        enabled: true,
+        path: dir.to_path_buf(),
+        topics: vec!["sensors/#".to_string()],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_append_and_history() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let journal = Journal::open(&config(temp_dir.path())).unwrap();
+
+    for i in 0..5u8 {
+        assert!(journal
+            .append("sensors/temp", &[i], QoS::AtLeastOnce, false)
+            .unwrap());
+    }
+    assert!(journal
+        .append("sensors/humidity", b"h", QoS::AtMostOnce, true)
+        .unwrap());
+    // Not covered by the journal filters
+    assert!(!journal
+        .append("commands/reboot", b"now", QoS::AtMostOnce, false)
+        .unwrap());
+
+    let history = journal.history("sensors/temp", 3).unwrap();
+    let payloads: Vec<_> = history.iter().map(|e| e.payload.clone()).collect();
+    assert_eq!(payloads, vec![vec![2], vec![3], vec![4]]);
+    assert_eq!(history[0].qos, 1);
+
+    assert_eq!(journal.history("#", 100).unwrap().len(), 6);
+}
+
+#[test]
+fn test_history_survives_reopen() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    {
+        let journal = Journal::open(&config(temp_dir.path())).unwrap();
+        journal
+            .append("sensors/temp", b"21", QoS::AtMostOnce, false)
+            .unwrap();
+        journal.flush().unwrap();
+    }
+
+    let journal = Journal::open(&config(temp_dir.path())).unwrap();
+    journal
+        .append("sensors/temp", b"22", QoS::AtMostOnce, false)
+        .unwrap();
+
+    let history = journal.history("sensors/temp", 10).unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].payload, b"21");
+}
+
+#[test]
+fn test_segments_roll_and_prune_by_size() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let journal = Journal::open(&JournalConfig {
+        segment_size: 256,
+        max_bytes: 1024,
+        ..config(temp_dir.path())
+    })
+    .unwrap();
+
+    for _ in 0..100 {
+        journal
+            .append("sensors/temp", &[0; 64], QoS::AtMostOnce, false)
+            .unwrap();
+    }
+    journal.maintain().unwrap();
+
+    let segments = list_segments(temp_dir.path()).unwrap();
+    assert!(segments.len() > 1);
+    let total: u64 = segments
+        .iter()
+        .map(|&id| {
+            fs::metadata(segment_path(temp_dir.path(), id))
+                .unwrap()
+                .len()
+        })
+        .sum();
+    assert!(total <= 1024);
+
+    // The newest messages are kept
+    let history = journal.history("#", 1000).unwrap();
+    assert!(!history.is_empty() && history.len() < 100);
+}
+
+#[test]
+fn test_reader_tails_and_skips_torn_record() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let journal = Journal::open(&config(temp_dir.path())).unwrap();
+    let mut reader = JournalReader::new(temp_dir.path());
+
+    journal
+        .append("sensors/a", b"1", QoS::AtMostOnce, false)
+        .unwrap();
+    journal.flush().unwrap();
+
+    let mut seen = Vec::new();
+    assert_eq!(reader.read_new(|e| seen.push(e.topic)).unwrap(), 1);
+
+    // Simulate a record cut off mid-write
+    let active_id = *list_segments(temp_dir.path()).unwrap().last().unwrap();
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(segment_path(temp_dir.path(), active_id))
+        .unwrap();
+    file.write_all(&100u32.to_le_bytes()).unwrap();
+    file.write_all(b"partial").unwrap();
+    drop(file);
+    assert_eq!(reader.read_new(|e| seen.push(e.topic)).unwrap(), 0);
+
+    // After a restart, new entries go to a fresh segment
+    drop(journal);
+    let journal = Journal::open(&config(temp_dir.path())).unwrap();
+    journal
+        .append("sensors/b", b"2", QoS::AtMostOnce, false)
+        .unwrap();
+    journal.flush().unwrap();
+
+    assert_eq!(reader.read_new(|e| seen.push(e.topic)).unwrap(), 1);
+    assert_eq!(seen, vec!["sensors/a", "sensors/b"]);
+}
+
+#[test]
+fn test_entry_to_json() {
+    let mut entry = JournalEntry {
+        timestamp_ms: 1000,
+        topic: "sensors/temp".to_string(),
+        qos: 1,
+        retain: true,
+        payload: b"21.5".to_vec(),
+    };
+    assert_eq!(
+        entry.to_json(),
+        serde_json::json!({
+            "timestamp_ms": 1000,
+            "topic": "sensors/temp",
+            "qos": 1,
+            "retain": true,
+            "payload": "21.5",
+        })
+    );
+
+    entry.payload = vec![0xff, 0];
+    assert_eq!(entry.to_json()["payload"], serde_json::json!([255, 0]));
+}
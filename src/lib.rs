@@ -13,14 +13,20 @@ pub mod codec;
 pub mod config;
 pub mod flapping;
 pub mod hooks;
+pub mod journal;
+pub mod loadgen;
 pub mod metrics;
+pub mod otel;
 pub mod persistence;
 #[cfg(feature = "pprof")]
 pub mod profiling;
 pub mod protocol;
 pub mod proxy;
 pub mod remote;
+pub mod rules;
+pub mod schema;
 pub mod session;
+pub mod sharded;
 pub mod topic;
 pub mod transport;
 
@@ -31,8 +37,16 @@ pub use broker::Broker;
 pub use cluster::{ClusterConfig, ClusterManager};
 pub use config::Config;
 pub use flapping::{ConnectionLimitConfig, FlappingConfig, FlappingDetector};
-pub use hooks::{CompositeHooks, DefaultHooks, Hooks};
+pub use hooks::{
+    ClientContext, CompositeHooks, DefaultHooks, GuardedHooks, HookDecision, Hooks,
+    PublishTransform, SubscribeDecision,
+};
+pub use journal::{Journal, JournalEntry, JournalReader};
 pub use metrics::{Metrics, MetricsServer};
-pub use persistence::{FjallBackend, PersistenceManager, StorageBackend};
+#[cfg(feature = "postgres")]
+pub use persistence::PostgresBackend;
+pub use persistence::{FjallBackend, MemoryBackend, PersistenceManager, StorageBackend};
 pub use protocol::{ProtocolVersion, QoS};
 pub use remote::{RemoteError, RemotePeer, RemotePeerStatus};
+pub use rules::RuleEngine;
+pub use schema::SchemaRegistry;
@@ -0,0 +1,428 @@
+//! Loopback Load Generator
+//!
+//! Drives a broker over TCP to measure the publish pipeline end to end: one
+//! publisher sends messages to a topic that a number of subscribers receive.
+//! The report gives the publish and delivery rates and, at QoS 1, the
+//! publisher's round-trip latencies from PUBLISH to PUBACK. Without a target
+//! address, a broker is started in-process on a free loopback port, so runs
+//! measure the broker rather than the network.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::{Bytes, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Barrier;
+
+use crate::broker::{Broker, BrokerConfig};
+use crate::codec::{Decoder, Encoder};
+use crate::protocol::{
+    Connect, Packet, Properties, ProtocolVersion, PubAck, Publish, QoS, Subscribe, Subscription,
+    SubscriptionOptions,
+};
+
+/// Topic the messages are published to
+const TOPIC: &str = "bench/load";
+
+/// QoS 1 publishes the publisher keeps unacknowledged at most
+const PUBLISH_WINDOW: usize = 64;
+
+/// How long a subscriber waits for the next message before giving up
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for an in-process broker to accept connections
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Load to generate
+#[derive(Debug, Clone)]
+pub struct LoadConfig {
+    /// Broker to drive; `None` starts one in-process
+    pub addr: Option<SocketAddr>,
+    /// Subscribers of the topic
+    pub subscribers: usize,
+    /// Messages published
+    pub messages: usize,
+    /// Payload size of each message, in bytes
+    pub payload_size: usize,
+    /// QoS of the messages and subscriptions, 0 or 1
+    pub qos: QoS,
+    pub protocol_version: ProtocolVersion,
+}
+
+impl Default for LoadConfig {
+    fn default() -> Self {
+        Self {
+            addr: None,
+            subscribers: 100,
+            messages: 10_000,
+            payload_size: 64,
+            qos: QoS::AtMostOnce,
+            protocol_version: ProtocolVersion::V5,
+        }
+    }
+}
+
+/// Outcome of a load run
+#[derive(Debug, Clone)]
+pub struct LoadReport {
+    /// Messages published
+    pub messages: usize,
+    /// Messages received, across all subscribers
+    pub deliveries: usize,
+    /// Deliveries expected, one per message and subscriber
+    pub expected: usize,
+    /// Time from the first publish to the last delivery
+    pub elapsed: Duration,
+    /// Publisher round-trip latencies at QoS 1, sorted
+    pub round_trips: Vec<Duration>,
+}
+
+impl LoadReport {
+    /// Messages published per second
+    pub fn publish_rate(&self) -> f64 {
+        self.messages as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Messages delivered per second, across all subscribers
+    pub fn delivery_rate(&self) -> f64 {
+        self.deliveries as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// The `p`th percentile of the round-trip latencies, `p` from 0 to 100
+    pub fn round_trip_percentile(&self, p: f64) -> Option<Duration> {
+        let last = self.round_trips.len().checked_sub(1)?;
+        let index = ((p / 100.0) * last as f64).round() as usize;
+        self.round_trips.get(index.min(last)).copied()
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Published {} messages in {:.3}s ({:.0} msg/s)",
+            self.messages,
+            self.elapsed.as_secs_f64(),
+            self.publish_rate()
+        )?;
+        write!(
+            f,
+            "Delivered {} of {} messages ({:.0} msg/s)",
+            self.deliveries,
+            self.expected,
+            self.delivery_rate()
+        )?;
+        if let (Some(p50), Some(p99), Some(max)) = (
+            self.round_trip_percentile(50.0),
+            self.round_trip_percentile(99.0),
+            self.round_trips.last(),
+        ) {
+            write!(
+                f,
+                "\nQoS 1 round trip: p50 {:?}, p99 {:?}, max {:?}",
+                p50, p99, max
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Generate the configured load, returning what was measured
+pub async fn run(config: &LoadConfig) -> io::Result<LoadReport> {
+    if config.qos == QoS::ExactlyOnce {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "load can only be generated at QoS 0 or 1",
+        ));
+    }
+    let (addr, broker) = match config.addr {
+        Some(addr) => (addr, None),
+        None => {
+            let (addr, broker) = start_broker(config.subscribers + 1).await?;
+            (addr, Some(broker))
+        }
+    };
+    let result = generate(addr, config).await;
+    if let Some(broker) = broker {
+        broker.shutdown();
+    }
+    result
+}
+
+/// Start a broker on a free loopback port
+async fn start_broker(max_connections: usize) -> io::Result<(SocketAddr, Arc<Broker>)> {
+    let addr = std::net::TcpListener::bind(("127.0.0.1", 0))?.local_addr()?;
+    let broker = Arc::new(Broker::new(BrokerConfig {
+        bind_addr: addr,
+        max_connections,
+        sys_topics_enabled: false,
+        ..BrokerConfig::default()
+    }));
+    let running = broker.clone();
+    tokio::spawn(async move { running.run().await });
+
+    let started = Instant::now();
+    while !broker.listener_status().is_accepting() {
+        if started.elapsed() > STARTUP_TIMEOUT {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "broker did not start",
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    Ok((addr, broker))
+}
+
+async fn generate(addr: SocketAddr, config: &LoadConfig) -> io::Result<LoadReport> {
+    // Subscribers and the publisher start together once all subscribed
+    let ready = Arc::new(Barrier::new(config.subscribers + 1));
+    let mut subscribers = Vec::with_capacity(config.subscribers);
+    for i in 0..config.subscribers {
+        let mut client =
+            Client::connect(addr, &format!("bench-sub-{}", i), config.protocol_version).await?;
+        client.subscribe(TOPIC, config.qos).await?;
+        let (ready, messages) = (ready.clone(), config.messages);
+        subscribers.push(tokio::spawn(async move {
+            ready.wait().await;
+            client.receive(messages).await
+        }));
+    }
+
+    let mut publisher = Client::connect(addr, "bench-pub", config.protocol_version).await?;
+    ready.wait().await;
+    let start = Instant::now();
+    let round_trips = publisher
+        .publish(
+            TOPIC,
+            Bytes::from(vec![0u8; config.payload_size]),
+            config.qos,
+            config.messages,
+        )
+        .await?;
+
+    let mut deliveries = 0;
+    for subscriber in subscribers {
+        deliveries += subscriber.await.map_err(io::Error::other)??;
+    }
+    let elapsed = start.elapsed();
+    let _ = publisher
+        .send(&Packet::Disconnect(Default::default()))
+        .await;
+
+    Ok(LoadReport {
+        messages: config.messages,
+        deliveries,
+        expected: config.messages * config.subscribers,
+        elapsed,
+        round_trips,
+    })
+}
+
+/// A minimal MQTT client over TCP
+struct Client {
+    stream: TcpStream,
+    encoder: Encoder,
+    decoder: Decoder,
+    read_buf: BytesMut,
+    write_buf: BytesMut,
+}
+
+impl Client {
+    async fn connect(
+        addr: SocketAddr,
+        client_id: &str,
+        protocol_version: ProtocolVersion,
+    ) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        let mut decoder = Decoder::new();
+        decoder.set_protocol_version(protocol_version);
+        let mut client = Self {
+            stream,
+            encoder: Encoder::new(protocol_version),
+            decoder,
+            read_buf: BytesMut::with_capacity(64 * 1024),
+            write_buf: BytesMut::with_capacity(64 * 1024),
+        };
+        client
+            .send(&Packet::Connect(Box::new(Connect {
+                protocol_version,
+                client_id: client_id.to_string(),
+                ..Default::default()
+            })))
+            .await?;
+        match client.recv().await? {
+            Packet::ConnAck(connack) if connack.reason_code.is_success() => Ok(client),
+            other => Err(unexpected("CONNACK", &other)),
+        }
+    }
+
+    async fn subscribe(&mut self, filter: &str, qos: QoS) -> io::Result<()> {
+        self.send(&Packet::Subscribe(Subscribe {
+            packet_id: 1,
+            subscriptions: vec![Subscription {
+                filter: filter.to_string(),
+                options: SubscriptionOptions {
+                    qos,
+                    ..Default::default()
+                },
+            }],
+            properties: Properties::default(),
+        }))
+        .await?;
+        match self.recv().await? {
+            Packet::SubAck(_) => Ok(()),
+            other => Err(unexpected("SUBACK", &other)),
+        }
+    }
+
+    /// Publish `count` messages, returning the round-trip latency of each
+    /// at QoS 1
+    async fn publish(
+        &mut self,
+        topic: &str,
+        payload: Bytes,
+        qos: QoS,
+        count: usize,
+    ) -> io::Result<Vec<Duration>> {
+        let mut publish = Publish {
+            dup: false,
+            qos,
+            retain: false,
+            topic: topic.into(),
+            packet_id: None,
+            payload,
+            properties: Properties::default(),
+        };
+        if qos == QoS::AtMostOnce {
+            for _ in 0..count {
+                self.encoder
+                    .encode(&Packet::Publish(publish.clone()), &mut self.write_buf)
+                    .map_err(io::Error::other)?;
+                if self.write_buf.len() >= 64 * 1024 {
+                    self.flush().await?;
+                }
+            }
+            self.flush().await?;
+            return Ok(Vec::new());
+        }
+
+        let mut round_trips = Vec::with_capacity(count);
+        let mut inflight: HashMap<u16, Instant> = HashMap::with_capacity(PUBLISH_WINDOW);
+        let mut sent = 0;
+        while round_trips.len() < count {
+            while sent < count && inflight.len() < PUBLISH_WINDOW {
+                let packet_id = (sent % 65535 + 1) as u16;
+                publish.packet_id = Some(packet_id);
+                self.encoder
+                    .encode(&Packet::Publish(publish.clone()), &mut self.write_buf)
+                    .map_err(io::Error::other)?;
+                inflight.insert(packet_id, Instant::now());
+                sent += 1;
+            }
+            self.flush().await?;
+            match self.recv().await? {
+                Packet::PubAck(puback) => {
+                    if let Some(sent_at) = inflight.remove(&puback.packet_id) {
+                        round_trips.push(sent_at.elapsed());
+                    }
+                }
+                other => return Err(unexpected("PUBACK", &other)),
+            }
+        }
+        round_trips.sort_unstable();
+        Ok(round_trips)
+    }
+
+    /// Receive up to `count` messages, acknowledging QoS 1 ones; stops
+    /// early once none arrives for a while
+    async fn receive(&mut self, count: usize) -> io::Result<usize> {
+        let mut received = 0;
+        while received < count {
+            let packet = match tokio::time::timeout(RECEIVE_TIMEOUT, self.recv()).await {
+                Ok(packet) => packet?,
+                Err(_) => break,
+            };
+            if let Packet::Publish(publish) = packet {
+                received += 1;
+                if let Some(packet_id) = publish.packet_id {
+                    self.encoder
+                        .encode(&Packet::PubAck(PubAck::new(packet_id)), &mut self.write_buf)
+                        .map_err(io::Error::other)?;
+                    // Acknowledgements go out with the next read
+                    if self.read_buf.is_empty() || self.write_buf.len() >= 16 * 1024 {
+                        self.flush().await?;
+                    }
+                }
+            }
+        }
+        self.flush().await?;
+        Ok(received)
+    }
+
+    async fn send(&mut self, packet: &Packet) -> io::Result<()> {
+        self.encoder
+            .encode(packet, &mut self.write_buf)
+            .map_err(io::Error::other)?;
+        self.flush().await
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        if !self.write_buf.is_empty() {
+            self.stream.write_all(&self.write_buf).await?;
+            self.write_buf.clear();
+        }
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> io::Result<Packet> {
+        loop {
+            if let Some((packet, consumed)) = self
+                .decoder
+                .decode(&self.read_buf)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?
+            {
+                let _ = self.read_buf.split_to(consumed);
+                return Ok(packet);
+            }
+            if self.stream.read_buf(&mut self.read_buf).await? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+}
+
+fn unexpected(expected: &str, packet: &Packet) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("expected {}, got {:?}", expected, packet),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_loopback_qos1() {
+        let report = run(&LoadConfig {
+            subscribers: 3,
+            messages: 200,
+            qos: QoS::AtLeastOnce,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(report.deliveries, report.expected);
+        assert_eq!(report.expected, 600);
+        assert_eq!(report.round_trips.len(), 200);
+        assert!(report.round_trip_percentile(50.0) <= report.round_trip_percentile(99.0));
+    }
+}
@@ -6,11 +6,18 @@
 //! Options:
 //!   -c, --config <FILE>    Configuration file path
 //!   -b, --bind <ADDR>      Bind address (default: 0.0.0.0:1883)
-//!   -w, --workers <N>      Number of worker threads (default: CPU count)
+//!   -w, --workers <N>      Number of worker runtimes (default: CPU count)
 //!   --max-connections <N>  Maximum connections (default: 100000)
 //!   --max-packet-size <N>  Maximum packet size (default: 1MB)
 //!   -l, --log-level        Log level (error, warn, info, debug, trace)
+//!   --export-tenant <T>    Export a tenant's persisted data as JSON and exit
+//!   --delete-tenant <T>    Delete a tenant's persisted data and exit
+//!   --journal-replay <F>   Print journaled messages matching a filter and exit
+//!   --check-store          Verify the persistence store and exit (--repair to fix)
 //!   -h, --help             Print help
+//!
+//! Commands:
+//!   bench                  Generate publish load over loopback and report throughput
 
 // Use jemalloc for heap profiling when pprof feature is enabled
 #[cfg(feature = "pprof")]
@@ -23,22 +30,40 @@ static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 #[export_name = "_rjem_malloc_conf"]
 pub static malloc_conf: &[u8] = b"prof:true,prof_active:true,lg_prof_sample:19\0";
 
+use std::collections::{HashSet, VecDeque};
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use clap::{Parser, ValueEnum};
-use tracing::{info, Level};
-use tracing_subscriber::FmtSubscriber;
+use clap::{Parser, Subcommand, ValueEnum};
+use tracing::{info, warn, Level};
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Layer;
 
 use vibemq::acl::AclProvider;
 use vibemq::auth::AuthProvider;
-use vibemq::broker::{Broker, BrokerConfig, RetainedMessage, TlsConfig};
-use vibemq::config::Config;
-use vibemq::hooks::CompositeHooks;
-use vibemq::persistence::{FjallBackend, PersistenceManager};
+use vibemq::bridge::EventExport;
+use vibemq::broker::{
+    Broker, BrokerConfig, RetainOutcome, RetainedMessage, TlsConfig, WorkerRuntimes,
+};
+use vibemq::config::{
+    BackendType, Config, CorruptionPolicy, DiscoveryMethod, HooksConfig, JournalConfig, LogFormat,
+    PersistenceConfig,
+};
+use vibemq::hooks::{CompositeHooks, GuardedHooks, Hooks};
+use vibemq::journal::{Journal, JournalEntry, JournalReader};
+use vibemq::loadgen::{self, LoadConfig};
+#[cfg(feature = "postgres")]
+use vibemq::persistence::PostgresBackend;
+use vibemq::persistence::{
+    split_tenant_key, tenant_key, FjallBackend, MemoryBackend, PersistenceError,
+    PersistenceManager, PersistenceOp, StorageBackend,
+};
 use vibemq::protocol::{Properties, QoS};
+use vibemq::session::SPILL_KEY_PREFIX;
+use vibemq::topic::{topic_matches_filter, Topic};
 
 /// Log level for CLI
 #[derive(Debug, Clone, Copy, ValueEnum, Default)]
@@ -87,7 +112,7 @@ struct Args {
     #[arg(long)]
     ws_bind: Option<SocketAddr>,
 
-    /// Number of worker threads (0 = auto)
+    /// Number of worker runtimes connections run on (0 = auto)
     #[arg(short, long)]
     workers: Option<usize>,
 
@@ -127,17 +152,288 @@ struct Args {
     #[arg(short, long, value_enum)]
     log_level: Option<LogLevel>,
 
+    /// Export all persisted data of a tenant as JSON and exit
+    /// (the broker must not be running against the same store)
+    #[arg(long, value_name = "TENANT")]
+    export_tenant: Option<String>,
+
+    /// File to write the tenant export to (default: stdout)
+    #[arg(long, value_name = "FILE", requires = "export_tenant")]
+    export_file: Option<PathBuf>,
+
+    /// Delete all persisted data of a tenant and exit
+    /// (the broker must not be running against the same store)
+    #[arg(long, value_name = "TENANT", conflicts_with = "export_tenant")]
+    delete_tenant: Option<String>,
+
+    /// Print journaled messages matching a topic filter as JSON lines and exit
+    #[arg(long, value_name = "FILTER")]
+    journal_replay: Option<String>,
+
+    /// Only print the last N matching messages
+    #[arg(long, value_name = "N", requires = "journal_replay")]
+    journal_last: Option<usize>,
+
+    /// Keep printing new messages as they are journaled
+    #[arg(long, requires = "journal_replay")]
+    journal_follow: bool,
+
+    /// Verify the persistence store, report corrupt entries and exit
+    /// (the broker must not be running against the same store)
+    #[arg(long)]
+    check_store: bool,
+
+    /// With --check-store, remove or rewrite corrupt entries
+    #[arg(long, requires = "check_store")]
+    repair: bool,
+
     /// Output directory for profiling data (enables profiling for entire run)
     /// CPU and heap profiles will be written as text files on shutdown
     #[cfg(feature = "pprof")]
     #[arg(long)]
     profile_output: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Publish messages to subscribers of one topic over loopback and
+    /// report the throughput and QoS 1 round-trip latencies
+    Bench(BenchArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct BenchArgs {
+    /// Broker to drive (default: start one in-process on a loopback port)
+    #[arg(long)]
+    addr: Option<SocketAddr>,
+
+    /// Number of subscribers
+    #[arg(long, default_value_t = 100)]
+    subscribers: usize,
+
+    /// Number of messages to publish
+    #[arg(long, default_value_t = 10_000)]
+    messages: usize,
+
+    /// Payload size in bytes
+    #[arg(long, default_value_t = 64)]
+    payload_size: usize,
+
+    /// QoS of the messages (0 or 1)
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=1))]
+    qos: u8,
+}
+
+/// A hook provider, isolated from the connections calling it if configured
+fn isolated(hooks: Arc<dyn Hooks>, config: &HooksConfig) -> Arc<dyn Hooks> {
+    if config.isolate {
+        Arc::new(GuardedHooks::from_config(hooks, config))
+    } else {
+        hooks
+    }
+}
+
+/// Open the configured storage backend
+async fn open_backend(
+    config: &PersistenceConfig,
+) -> Result<Arc<dyn StorageBackend>, PersistenceError> {
+    match config.backend {
+        BackendType::Fjall => {
+            info!("  Persistence: enabled (fjall, {:?})", config.path);
+            let backend = FjallBackend::open_with_compaction_workers(
+                &config.path,
+                config.compaction_workers,
+            )?;
+            Ok(Arc::new(backend))
+        }
+        BackendType::Memory => {
+            info!(
+                "  Persistence: enabled (memory, snapshots in {:?} every {:?})",
+                config.path, config.snapshot_interval
+            );
+            let backend = match MemoryBackend::open(&config.path, config.snapshot_interval) {
+                Err(e @ (PersistenceError::Corruption(_) | PersistenceError::Deserialize(_)))
+                    if config.on_corruption != CorruptionPolicy::Abort =>
+                {
+                    // Keep the damaged snapshot for inspection and start empty
+                    let moved = MemoryBackend::quarantine_snapshot(&config.path)?;
+                    warn!(
+                        "Persistence snapshot is corrupt ({}), moved to {:?}; starting empty",
+                        e, moved
+                    );
+                    MemoryBackend::open(&config.path, config.snapshot_interval)?
+                }
+                result => result?,
+            };
+            Ok(Arc::new(backend))
+        }
+        #[cfg(feature = "postgres")]
+        BackendType::Postgres => {
+            info!("  Persistence: enabled (postgres)");
+            // Presence of the URL is checked by Config::validate
+            let url = config.url.as_deref().unwrap_or_default();
+            let backend = PostgresBackend::connect(url, config.max_connections).await?;
+            Ok(Arc::new(backend))
+        }
+        #[cfg(not(feature = "postgres"))]
+        BackendType::Postgres => Err(PersistenceError::Storage(
+            "persistence backend \"postgres\" requires building with --features postgres"
+                .to_string(),
+        )),
+    }
+}
+
+/// Run `--export-tenant` / `--delete-tenant` against the configured store
+async fn run_tenant_command(
+    args: &Args,
+    config: &PersistenceConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let backend = open_backend(config).await?;
+
+    let result: Result<(), Box<dyn std::error::Error>> = async {
+        if let Some(ref tenant) = args.export_tenant {
+            let data = backend.export_tenant(tenant).await?;
+            match args.export_file {
+                Some(ref path) => {
+                    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+                    serde_json::to_writer_pretty(file, &data)?;
+                    eprintln!("Exported tenant {} to {:?}", tenant, path);
+                }
+                None => {
+                    serde_json::to_writer_pretty(std::io::stdout().lock(), &data)?;
+                    println!();
+                }
+            }
+        }
+        if let Some(ref tenant) = args.delete_tenant {
+            let count = backend.delete_tenant(tenant).await?;
+            eprintln!("Deleted {} entries of tenant {}", count, tenant);
+        }
+        Ok(())
+    }
+    .await;
+
+    backend.close().await?;
+    result
+}
+
+/// Run `--check-store` against the configured store
+///
+/// Returns whether the store is usable: no corruption found, or all of it repaired.
+async fn run_check_command(
+    args: &Args,
+    config: &PersistenceConfig,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut config = config.clone();
+    config.on_corruption = if args.repair {
+        CorruptionPolicy::Repair
+    } else {
+        CorruptionPolicy::Abort
+    };
+
+    let backend = open_backend(&config).await?;
+    let report = backend.check(args.repair).await;
+    backend.close().await?;
+    let report = report?;
+
+    println!(
+        "Checked {} entries, {} corrupt",
+        report.checked,
+        report.corrupt.len()
+    );
+    for entry in &report.corrupt {
+        println!("  {}", entry);
+    }
+    if report.repaired {
+        println!("Repaired {} entries", report.corrupt.len());
+    } else if !report.is_clean() {
+        println!("Run with --repair to remove corrupt entries");
+    }
+    Ok(report.is_clean() || report.repaired)
+}
+
+/// Print a journal entry as a JSON line
+fn print_journal_entry(entry: &JournalEntry) {
+    println!("{}", entry.to_json());
+}
+
+/// Run `--journal-replay` against the configured journal directory
+async fn run_journal_command(
+    args: &Args,
+    config: &JournalConfig,
+    filter: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let limit = args.journal_last.unwrap_or(usize::MAX);
+    let mut reader = JournalReader::new(&config.path);
+
+    let mut entries = VecDeque::new();
+    reader.read_new(|entry| {
+        if limit > 0 && topic_matches_filter(&entry.topic, filter) {
+            if entries.len() == limit {
+                entries.pop_front();
+            }
+            entries.push_back(entry);
+        }
+    })?;
+    entries.iter().for_each(print_journal_entry);
+
+    if args.journal_follow {
+        // The broker flushes the journal every second
+        let mut interval = tokio::time::interval(Duration::from_millis(500));
+        loop {
+            interval.tick().await;
+            reader.read_new(|entry| {
+                if topic_matches_filter(&entry.topic, filter) {
+                    print_journal_entry(&entry);
+                }
+            })?;
+        }
+    }
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// Run `bench`, printing the report
+async fn run_bench_command(args: &BenchArgs) -> std::io::Result<()> {
+    let config = LoadConfig {
+        addr: args.addr,
+        subscribers: args.subscribers,
+        messages: args.messages,
+        payload_size: args.payload_size,
+        qos: QoS::from_u8(args.qos).expect("clap limits --qos to 0 or 1"),
+        ..Default::default()
+    };
+    println!(
+        "Publishing {} messages of {} bytes at QoS {} to {} subscribers",
+        config.messages, config.payload_size, args.qos, config.subscribers
+    );
+    let report = loadgen::run(&config).await?;
+    println!("{}", report);
+    Ok(())
+}
+
+/// Threads of the runtime accepting connections and running broker-wide
+/// tasks; connections themselves run on the worker runtimes
+const ACCEPT_RUNTIME_THREADS: usize = 2;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
+    // Before any TLS client or server, including those of dependencies
+    vibemq::broker::install_crypto_provider();
 
+    // The load generator drives its clients from a runtime on every core
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if args.command.is_none() {
+        runtime
+            .worker_threads(ACCEPT_RUNTIME_THREADS)
+            .thread_name("vibemq-accept");
+    }
+    runtime.enable_all().build()?.block_on(run(args))
+}
+
+async fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
     // Load configuration file if specified, otherwise use env vars + defaults
     let file_config = if let Some(config_path) = &args.config {
         match Config::load(config_path) {
@@ -164,23 +460,101 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
 
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(log_level.to_tracing_level())
-        .with_target(false)
-        .with_thread_ids(true)
-        .with_file(false)
-        .with_line_number(false)
-        .compact()
-        .finish();
+    // Per-module levels override the log level; the broker's trace spans
+    // are kept out of the logs
+    let log_filter = Targets::new()
+        .with_default(log_level.to_tracing_level())
+        .with_targets(file_config.log.targets()?)
+        .with_target(vibemq::otel::SPAN_TARGET, LevelFilter::OFF);
+    let fmt_layer = match file_config.log.format {
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .with_target(false)
+            .with_thread_ids(true)
+            .with_file(false)
+            .with_line_number(false)
+            .compact()
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_thread_ids(true)
+            .boxed(),
+    }
+    .with_filter(log_filter);
+
+    #[cfg(feature = "otel")]
+    let (otel_layer, _otel_guard) = if file_config.otel.enabled {
+        let (tracer, guard) = vibemq::otel::init(&file_config.otel)?;
+        let layer = tracing_opentelemetry::layer()
+            .with_tracer(tracer)
+            .with_filter(Targets::new().with_target(vibemq::otel::SPAN_TARGET, Level::INFO));
+        (Some(layer), Some(guard))
+    } else {
+        (None, None)
+    };
+    #[cfg(not(feature = "otel"))]
+    let otel_layer: Option<tracing_subscriber::layer::Identity> = None;
 
-    tracing::subscriber::set_global_default(subscriber)?;
+    tracing::subscriber::set_global_default(
+        tracing_subscriber::registry()
+            .with(fmt_layer)
+            .with(otel_layer),
+    )?;
 
-    if args.config.is_some() {
+    #[cfg(feature = "otel")]
+    if file_config.otel.enabled {
         info!(
-            "Loaded configuration from {:?}",
-            args.config.as_ref().unwrap()
+            "OpenTelemetry: exporting spans to {}",
+            file_config.otel.endpoint
         );
     }
+    #[cfg(not(feature = "otel"))]
+    if file_config.otel.enabled {
+        warn!("[otel] is enabled but vibemq was built without the otel feature");
+    }
+
+    if let Some(config) = &args.config {
+        info!("Loaded configuration from {:?}", config);
+    }
+
+    // Offline tenant data management
+    if args.export_tenant.is_some() || args.delete_tenant.is_some() {
+        if let Err(e) = run_tenant_command(&args, &file_config.persistence).await {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // Offline store verification
+    if args.check_store {
+        match run_check_command(&args, &file_config.persistence).await {
+            Ok(true) => return Ok(()),
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Offline journal replay
+    if let Some(ref filter) = args.journal_replay {
+        if let Err(e) = run_journal_command(&args, &file_config.journal, filter).await {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // Load generation
+    if let Some(Command::Bench(ref bench)) = args.command {
+        if let Err(e) = run_bench_command(bench).await {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
     // CLI args override file config
     let bind_addr = args.bind.unwrap_or(file_config.server.bind);
@@ -246,6 +620,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         workers
     };
 
+    // Compile payload schemas
+    let schemas = match vibemq::SchemaRegistry::load(&file_config.mqtt.schemas) {
+        Ok(schemas) => Arc::new(schemas),
+        Err(e) => {
+            eprintln!("Error loading payload schemas: {}", e);
+            std::process::exit(1);
+        }
+    };
+
     // Build broker configuration
     let broker_config = BrokerConfig {
         bind_addr,
@@ -257,7 +640,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         max_packet_size,
         default_keep_alive: keep_alive,
         max_keep_alive,
+        server_keep_alive: file_config.mqtt.server_keep_alive,
         session_expiry_check_interval: file_config.session.expiry_check_interval,
+        parked_timeout: file_config.session.parked_timeout,
         receive_maximum,
         max_qos,
         retain_available,
@@ -268,6 +653,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         num_workers,
         sys_topics_enabled: file_config.mqtt.sys_topics,
         sys_topics_interval: file_config.mqtt.sys_interval,
+        sys_filter_topics: file_config.mqtt.sys_filter_topics,
+        sys_session_topics: file_config.mqtt.sys_session_topics,
+        sys_client_topics: file_config.mqtt.sys_client_topics,
+        sys_client_interval: file_config.mqtt.sys_client_interval,
+        presence_topics: file_config.mqtt.presence_topics,
         // 0 = unbounded for all limits
         max_inflight: if file_config.limits.max_inflight == 0 {
             u16::MAX
@@ -279,22 +669,48 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         } else {
             file_config.limits.max_queued_messages
         },
+        queue_overflow: file_config.limits.queue_overflow,
+        spill_threshold: file_config.limits.spill_threshold,
         max_awaiting_rel: if file_config.limits.max_awaiting_rel == 0 {
             usize::MAX
         } else {
             file_config.limits.max_awaiting_rel
         },
         retry_interval: file_config.limits.retry_interval,
+        retry_max_interval: file_config.limits.retry_max_interval,
+        max_retries: file_config.limits.max_retries,
+        retry_exhausted: file_config.limits.retry_exhausted,
         outbound_channel_capacity: if file_config.limits.outbound_channel_capacity == 0 {
             // tokio mpsc channel max is ~2^61, use a large but safe value
             1_000_000
         } else {
             file_config.limits.outbound_channel_capacity
         },
+        write_coalesce_bytes: file_config.limits.write_coalesce_bytes,
+        write_coalesce_delay: file_config.limits.write_coalesce_delay,
+        fanout_threshold: file_config.limits.fanout_threshold,
+        slow_consumer: file_config.limits.slow_consumer.clone(),
         max_topic_levels: file_config.limits.max_topic_levels,
+        max_subscriptions_per_client: file_config.limits.max_subscriptions_per_client,
         proxy_protocol: file_config.server.proxy_protocol.clone(),
         tls_proxy_protocol: file_config.server.tls_proxy_protocol.clone(),
         ws_proxy_protocol: file_config.server.ws_proxy_protocol.clone(),
+        max_retained_messages: file_config.mqtt.max_retained_messages,
+        max_retained_bytes: file_config.mqtt.max_retained_bytes,
+        retained_policy: file_config.mqtt.retained_policy,
+        tenancy: file_config.tenancy.clone(),
+        mount_points: file_config.mount_points.clone(),
+        redirect: file_config.mqtt.redirect.clone(),
+        validate_payload_format: file_config.mqtt.validate_payload_format,
+        content_types: file_config.mqtt.content_types.clone(),
+        payload_limits: file_config.mqtt.payload_limits.clone(),
+        schemas,
+        strict: file_config.mqtt.strict,
+        ordered_delivery: file_config.mqtt.ordered_delivery,
+        topic_priorities: file_config.mqtt.topic_priorities.clone(),
+        shared_subscription_strategy: file_config.mqtt.shared_subscription_strategy,
+        shared_subscription_groups: file_config.mqtt.shared_subscription_groups.clone(),
+        queue_subscriptions: file_config.mqtt.queue_subscriptions,
     };
 
     info!("Starting VibeMQ MQTT Broker");
@@ -305,7 +721,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     if let Some(ws_addr) = &broker_config.ws_bind_addr {
         info!("  WebSocket address: {}", ws_addr);
     }
-    info!("  Workers: {}", broker_config.num_workers);
+    info!(
+        "  Workers: {}{}",
+        broker_config.num_workers,
+        if file_config.server.pin_workers {
+            " (pinned)"
+        } else {
+            ""
+        }
+    );
     info!("  Max connections: {}", broker_config.max_connections);
     info!("  Max packet size: {} bytes", broker_config.max_packet_size);
     info!("  Max inflight: {}", broker_config.max_inflight);
@@ -318,6 +742,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         broker_config.outbound_channel_capacity
     );
     info!("  Max QoS: {:?}", broker_config.max_qos);
+    if !broker_config.schemas.is_empty() {
+        info!("  Payload schemas: {}", broker_config.schemas.len());
+    }
 
     // Log auth/ACL status
     if file_config.auth.enabled {
@@ -373,43 +800,163 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let auth_provider = Arc::new(AuthProvider::new(&file_config.auth));
     let acl_provider = Arc::new(AclProvider::new(&file_config.acl, auth_provider.clone()));
 
-    // Compose hooks: auth first, then ACL
-    let hooks = Arc::new(CompositeHooks::new().with(auth_provider).with(acl_provider));
+    // Compose hooks: auth first, then ACL, then the external provider and
+    // the script (before both if their priority is higher)
+    #[allow(unused_mut)]
+    let mut composite = CompositeHooks::new()
+        .with(isolated(auth_provider.clone(), &file_config.hooks))
+        .with(isolated(acl_provider.clone(), &file_config.hooks));
+    #[cfg(feature = "exhook")]
+    if file_config.exhook.enabled {
+        let node = file_config
+            .cluster
+            .iter()
+            .find(|c| c.enabled)
+            .cloned()
+            .unwrap_or_default()
+            .get_node_id();
+        match vibemq::hooks::exhook::ExHookProvider::connect(&file_config.exhook, &node).await {
+            Ok(provider) => {
+                info!(
+                    "  ExHook: {} ({})",
+                    file_config.exhook.url,
+                    provider.registered_hooks().join(", ")
+                );
+                composite = composite.with_priority(
+                    isolated(Arc::new(provider), &file_config.hooks),
+                    file_config.exhook.priority,
+                );
+            }
+            Err(e) => {
+                eprintln!("Error loading exhook provider: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    #[cfg(not(feature = "exhook"))]
+    if file_config.exhook.enabled {
+        eprintln!("Error: [exhook] requires building with --features exhook");
+        std::process::exit(1);
+    }
+    #[cfg(feature = "scripting")]
+    if file_config.script.enabled {
+        match vibemq::hooks::script::ScriptHooks::load(&file_config.script) {
+            Ok(provider) => {
+                let provider = Arc::new(provider);
+                info!(
+                    "  Script: {} ({})",
+                    file_config
+                        .script
+                        .path
+                        .as_ref()
+                        .map_or("inline".to_string(), |p| p.display().to_string()),
+                    provider.functions().join(", ")
+                );
+                provider.spawn_reloader(file_config.script.reload_interval);
+                composite = composite.with_priority(
+                    isolated(provider, &file_config.hooks),
+                    file_config.script.priority,
+                );
+            }
+            Err(e) => {
+                eprintln!("Error loading script: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    #[cfg(not(feature = "scripting"))]
+    if file_config.script.enabled {
+        eprintln!("Error: [script] requires building with --features scripting");
+        std::process::exit(1);
+    }
+    if file_config.hooks.isolate {
+        info!(
+            "  Hooks: isolated ({:?} deadline, {:?} on failure)",
+            file_config.hooks.timeout, file_config.hooks.failed_action
+        );
+    }
+    let hooks = Arc::new(composite);
 
     // Create broker with hooks
     let mut broker = Broker::with_hooks(broker_config, hooks);
+    broker.set_access_control(auth_provider, acl_provider);
+
+    // Run connections on per-core worker runtimes
+    match WorkerRuntimes::start(num_workers, file_config.server.pin_workers) {
+        Ok(workers) => broker.set_worker_runtimes(workers),
+        Err(e) => {
+            eprintln!("Error starting worker runtimes: {}", e);
+            std::process::exit(1);
+        }
+    }
 
     // Initialize persistence if enabled
     let persistence_manager = if file_config.persistence.enabled {
-        info!(
-            "  Persistence: enabled ({:?})",
-            file_config.persistence.path
-        );
-
-        // Open the fjall backend
-        let backend = match FjallBackend::open(&file_config.persistence.path) {
-            Ok(b) => Arc::new(b),
+        // Open the configured backend
+        let backend = match open_backend(&file_config.persistence).await {
+            Ok(b) => b,
             Err(e) => {
                 eprintln!("Error opening persistence backend: {}", e);
                 std::process::exit(1);
             }
         };
 
-        // Create the persistence manager
-        let manager = Arc::new(PersistenceManager::new(
-            backend,
-            file_config.persistence.flush_interval,
-            file_config.persistence.max_batch_size,
-        ));
-
-        // Load existing data
-        let loaded = match manager.load_all().await {
-            Ok(data) => data,
+        // Load existing data, verifying it as it is decoded
+        let on_corruption = file_config.persistence.on_corruption;
+        let (loaded, report) = match backend
+            .load_checked(on_corruption == CorruptionPolicy::Repair)
+            .await
+        {
+            Ok(loaded) => loaded,
             Err(e) => {
                 eprintln!("Error loading persistence data: {}", e);
                 std::process::exit(1);
             }
         };
+        if !report.is_clean() {
+            for entry in &report.corrupt {
+                warn!("Corrupt persistence entry {}", entry);
+            }
+            match on_corruption {
+                CorruptionPolicy::Abort => {
+                    eprintln!(
+                        "Error: persistence store has {} corrupt entries; run with --check-store --repair",
+                        report.corrupt.len()
+                    );
+                    std::process::exit(1);
+                }
+                CorruptionPolicy::Skip => {
+                    warn!(
+                        "Skipping {} corrupt persistence entries",
+                        report.corrupt.len()
+                    )
+                }
+                CorruptionPolicy::Repair => {
+                    warn!(
+                        "Repaired {} corrupt persistence entries",
+                        report.corrupt.len()
+                    )
+                }
+            }
+        }
+
+        // Create the persistence manager
+        let manager = Arc::new(
+            PersistenceManager::new(
+                backend,
+                file_config.persistence.flush_interval,
+                file_config.persistence.max_batch_size,
+                file_config.persistence.sync_mode,
+            )
+            .with_overflow_policy(
+                file_config.persistence.overflow_policy,
+                file_config.persistence.overflow_timeout,
+            ),
+        );
+
+        if !file_config.persistence.compaction_interval.is_zero() {
+            manager.spawn_compaction(file_config.persistence.compaction_interval);
+        }
 
         info!(
             "  Loaded: {} retained messages, {} sessions",
@@ -417,16 +964,53 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             loaded.sessions.len()
         );
 
+        // Spilled queue segments stay with the stored sessions that refer to
+        // them; the rest belonged to sessions that are gone
+        let referenced: HashSet<&str> = loaded
+            .sessions
+            .iter()
+            .flat_map(|(_, session)| session.spill_keys())
+            .collect();
+        match manager.list_spills(SPILL_KEY_PREFIX).await {
+            Ok(keys) => {
+                let mut removed = 0;
+                for key in keys {
+                    if !referenced.contains(key.as_str()) {
+                        manager.write(PersistenceOp::DeleteSpill { key });
+                        removed += 1;
+                    }
+                }
+                if removed > 0 {
+                    info!("  Removed {} unreferenced spilled queue segments", removed);
+                }
+            }
+            Err(e) => warn!("Failed to list spilled queue segments: {}", e),
+        }
+
         // Restore retained messages
-        for (topic, stored) in loaded.retained {
+        for (key, stored) in loaded.retained {
+            let (tenant, topic) = split_tenant_key(&key);
             let msg = RetainedMessage {
-                topic: topic.clone(),
+                topic: Topic::from(topic),
                 payload: bytes::Bytes::from(stored.payload),
                 qos: QoS::from_u8(stored.qos).unwrap_or_default(),
                 properties: Properties::from(stored.properties),
                 timestamp: Instant::now(), // Approximate - original timestamp lost
+                tenant: tenant.map(Arc::from),
             };
-            broker.retained().insert(topic, msg);
+            // Drop stored messages that no longer fit the configured limits
+            match broker.retained().insert(msg) {
+                RetainOutcome::Stored { evicted } => {
+                    for m in evicted {
+                        manager.write(PersistenceOp::DeleteRetained {
+                            topic: tenant_key(m.tenant.as_deref(), &m.topic),
+                        });
+                    }
+                }
+                RetainOutcome::Rejected => {
+                    manager.write(PersistenceOp::DeleteRetained { topic: key });
+                }
+            }
         }
 
         // TODO: Restore sessions when session store supports it
@@ -441,6 +1025,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         None
     };
 
+    // Setup message journal if enabled
+    if file_config.journal.enabled {
+        info!(
+            "  Journal: enabled ({:?}, topics={})",
+            file_config.journal.path,
+            file_config.journal.topics.join(", ")
+        );
+        match Journal::open(&file_config.journal) {
+            Ok(journal) => broker.set_journal(Arc::new(journal)),
+            Err(e) => {
+                eprintln!("Error opening message journal: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        info!("  Journal: disabled");
+    }
+
     // Setup flapping detection if enabled
     if file_config.limits.flapping_detect.enabled
         || file_config.limits.connection_limit.max_connections_per_ip > 0
@@ -468,7 +1070,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         enabled_bridges
     );
     if !file_config.bridge.is_empty() {
-        for bridge_cfg in &file_config.bridge {
+        // Clustered nodes bridge through the remote broker in their own zone
+        let mut bridges = file_config.bridge;
+        let zone = file_config
+            .cluster
+            .iter()
+            .find(|c| c.enabled)
+            .and_then(|c| c.zone.as_deref());
+        if let Some(zone) = zone {
+            for bridge_cfg in &mut bridges {
+                bridge_cfg.localize(zone);
+            }
+        }
+        for bridge_cfg in &bridges {
             let status = if bridge_cfg.enabled {
                 "enabled"
             } else {
@@ -491,10 +1105,37 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 );
             }
         }
-        let bridge_manager = broker.create_bridge_manager(file_config.bridge);
+        let bridge_manager = broker.create_bridge_manager(bridges);
+        let export = EventExport::new(&file_config.export);
+        if export.is_enabled() {
+            let events: Vec<&str> = file_config
+                .export
+                .events
+                .iter()
+                .map(|e| e.as_str())
+                .collect();
+            info!("  Event export: {}", events.join(", "));
+        }
+        bridge_manager.set_event_export(export);
         broker.set_bridge_manager(bridge_manager);
     }
 
+    // Load rules
+    if !file_config.rules.is_empty() {
+        let enabled_rules = file_config.rules.iter().filter(|r| r.enabled).count();
+        info!(
+            "  Rules: {} configured ({} enabled)",
+            file_config.rules.len(),
+            enabled_rules
+        );
+        for rule in &file_config.rules {
+            if let Err(e) = broker.rules().add(rule.clone()) {
+                eprintln!("Error loading rule '{}': {}", rule.id, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
     // Setup clustering if configured
     let enabled_clusters = file_config.cluster.iter().filter(|c| c.enabled).count();
     if enabled_clusters > 0 {
@@ -506,6 +1147,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         if !cluster_cfg.seeds.is_empty() {
             info!("    Seeds: {}", cluster_cfg.seeds.join(", "));
         }
+        if cluster_cfg.discovery.method != DiscoveryMethod::Static {
+            info!(
+                "    Discovery: {:?} (refresh every {:?})",
+                cluster_cfg.discovery.method, cluster_cfg.discovery.refresh_interval
+            );
+        }
 
         match broker.create_cluster_manager(cluster_cfg.clone()).await {
             Ok(cluster_manager) => {
@@ -522,12 +1169,53 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Setup metrics if configured
     if file_config.metrics.enabled {
-        let metrics = Arc::new(vibemq::Metrics::new());
+        let metrics = Arc::new(
+            vibemq::Metrics::new().with_topic_patterns(file_config.metrics.topic_patterns.clone()),
+        );
         broker.set_metrics(metrics.clone());
+        if let Some(ref persistence) = persistence_manager {
+            persistence.set_metrics(metrics.clone());
+        }
         info!("  Metrics: enabled (http://{})", file_config.metrics.bind);
+        if let Some(ref push) = file_config.metrics.push {
+            info!(
+                "  Metrics push: {:?} to {} every {:?}",
+                push.protocol, push.address, push.interval
+            );
+            tokio::spawn(vibemq::metrics::MetricsPusher::new(metrics.clone(), push.clone()).run());
+        }
 
         // Spawn metrics server
-        let metrics_server = vibemq::MetricsServer::new(metrics, file_config.metrics.bind);
+        let metrics_server = vibemq::MetricsServer::new(metrics, file_config.metrics.bind)
+            .with_cluster(broker.cluster_manager())
+            .with_bridges(broker.bridge_manager())
+            .with_sessions(broker.sessions().clone())
+            .with_subscriptions(broker.subscriptions().clone())
+            .with_rules(broker.rules().clone(), file_config.metrics.rules_api)
+            .with_users_api(
+                broker
+                    .cluster_manager()
+                    .filter(|_| file_config.metrics.users_api),
+            )
+            .with_journal(
+                broker
+                    .journal()
+                    .filter(|_| file_config.metrics.journal_api)
+                    .cloned(),
+            )
+            .with_redirect_api(
+                file_config
+                    .metrics
+                    .redirect_api
+                    .then(|| broker.redirect_control()),
+            )
+            .with_health(
+                vibemq::metrics::Health::new(file_config.metrics.health.clone())
+                    .with_listeners(broker.listener_status())
+                    .with_persistence(broker.persistence().cloned())
+                    .with_cluster(broker.cluster_manager())
+                    .with_bridges(broker.bridge_manager()),
+            );
         tokio::spawn(async move {
             if let Err(e) = metrics_server.run().await {
                 tracing::error!("Metrics server error: {}", e);
@@ -569,6 +1257,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Shutdown persistence (flush pending writes)
     if let Some(persistence) = persistence_manager {
+        if let Some(bridges) = broker.bridge_manager() {
+            bridges.flush_spools().await;
+        }
         info!("Flushing persistence...");
         if let Err(e) = persistence.shutdown().await {
             tracing::error!("Error during persistence shutdown: {}", e);
@@ -0,0 +1,86 @@
+//! Connection Gauge Tracking
+//!
+//! A connection counts itself in the protocol and listener gauges once its
+//! client is connected and holds a [`ConnectionGauge`] until it ends, so the
+//! gauges follow the connections themselves rather than events that a
+//! lagging listener could miss. A client ID briefly having two connections
+//! during a session takeover needs no special handling: each connection
+//! decrements the labels it incremented.
+
+use std::sync::Arc;
+
+use super::Metrics;
+
+/// Gauge labels of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionLabels {
+    pub protocol: &'static str,
+    pub listener: &'static str,
+}
+
+/// A connection counted in the connection gauges, until dropped
+pub struct ConnectionGauge {
+    metrics: Arc<Metrics>,
+    labels: ConnectionLabels,
+}
+
+impl ConnectionGauge {
+    /// Count a newly connected client
+    pub fn connected(metrics: Arc<Metrics>, labels: ConnectionLabels) -> Self {
+        metrics.client_connected(labels);
+        Self { metrics, labels }
+    }
+}
+
+impl Drop for ConnectionGauge {
+    fn drop(&mut self) {
+        self.metrics.client_disconnected(self.labels);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V5_TCP: ConnectionLabels = ConnectionLabels {
+        protocol: "v5.0",
+        listener: "tcp",
+    };
+    const V311_WS: ConnectionLabels = ConnectionLabels {
+        protocol: "v3.1.1",
+        listener: "ws",
+    };
+
+    fn gauges(metrics: &Metrics, labels: ConnectionLabels) -> (i64, i64) {
+        (
+            metrics
+                .connections_by_protocol
+                .with_label_values(&[labels.protocol])
+                .get(),
+            metrics
+                .connections_by_listener
+                .with_label_values(&[labels.listener])
+                .get(),
+        )
+    }
+
+    #[test]
+    fn test_gauge_counts_connection_until_dropped() {
+        let metrics = Arc::new(Metrics::new());
+        let tcp = ConnectionGauge::connected(metrics.clone(), V5_TCP);
+        // A takeover: the new connection is counted before the old one ends
+        let ws = ConnectionGauge::connected(metrics.clone(), V311_WS);
+        let tcp_again = ConnectionGauge::connected(metrics.clone(), V5_TCP);
+        assert_eq!(gauges(&metrics, V5_TCP), (2, 2));
+        assert_eq!(metrics.connections_current.get(), 3);
+
+        drop(tcp);
+        assert_eq!(gauges(&metrics, V5_TCP), (1, 1));
+        drop(ws);
+        drop(tcp_again);
+        assert_eq!(gauges(&metrics, V5_TCP), (0, 0));
+        assert_eq!(gauges(&metrics, V311_WS), (0, 0));
+        assert_eq!(metrics.connections_current.get(), 0);
+        assert_eq!(metrics.connections_maximum.get(), 3);
+    }
+}
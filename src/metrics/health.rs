@@ -0,0 +1,312 @@
+//! Health and Readiness
+//!
+//! Backs `/readyz` with checks of the listeners, the persistence backend,
+//! cluster quorum and bridge connectivity. Each check is reported with
+//! whether it passed and whether it is required; the configured criteria
+//! decide which ones are. `/healthz` only reports that the process answers.
+
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::bridge::BridgeManager;
+use crate::broker::ListenerStatus;
+use crate::cluster::ClusterManager;
+use crate::config::{BridgeReadiness, HealthConfig};
+use crate::persistence::PersistenceManager;
+use crate::remote::RemotePeerStatus;
+
+/// Outcome of one check
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthCheck {
+    pub name: &'static str,
+    pub healthy: bool,
+    /// Whether a failure makes the broker unready
+    pub required: bool,
+    pub detail: String,
+}
+
+/// Outcome of all checks
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    /// Every required check passed
+    pub ok: bool,
+    pub checks: Vec<HealthCheck>,
+}
+
+/// State the checks are made against
+#[derive(Debug, Default)]
+struct Observed {
+    /// Accepting connections, and the bound listeners as `name@addr`
+    listeners: Option<(bool, Vec<String>)>,
+    persistence_healthy: Option<bool>,
+    /// Partitioned, and the reachable and expected nodes
+    cluster: Option<(bool, usize, usize)>,
+    /// Name of each bridge and whether it is connected
+    bridges: Vec<(String, bool)>,
+}
+
+/// Broker components checked for health and readiness
+#[derive(Default)]
+pub struct Health {
+    config: HealthConfig,
+    listeners: Option<Arc<ListenerStatus>>,
+    persistence: Option<Arc<PersistenceManager>>,
+    cluster: Option<Arc<ClusterManager>>,
+    bridges: Option<Arc<BridgeManager>>,
+}
+
+impl Health {
+    pub fn new(config: HealthConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_listeners(mut self, listeners: Arc<ListenerStatus>) -> Self {
+        self.listeners = Some(listeners);
+        self
+    }
+
+    pub fn with_persistence(mut self, persistence: Option<Arc<PersistenceManager>>) -> Self {
+        self.persistence = persistence;
+        self
+    }
+
+    pub fn with_cluster(mut self, cluster: Option<Arc<ClusterManager>>) -> Self {
+        self.cluster = cluster;
+        self
+    }
+
+    pub fn with_bridges(mut self, bridges: Option<Arc<BridgeManager>>) -> Self {
+        self.bridges = bridges;
+        self
+    }
+
+    /// Liveness: the process answers, whatever its dependencies do; a
+    /// restart would not bring back a database or a peer, so their checks
+    /// are left to readiness
+    pub fn liveness(&self) -> HealthReport {
+        HealthReport {
+            ok: true,
+            checks: vec![HealthCheck {
+                name: "process",
+                healthy: true,
+                required: true,
+                detail: format!("pid {}", std::process::id()),
+            }],
+        }
+    }
+
+    /// Readiness: fails while any required check does
+    pub fn readiness(&self) -> HealthReport {
+        assess(&self.config, self.observe())
+    }
+
+    fn observe(&self) -> Observed {
+        Observed {
+            listeners: self.listeners.as_ref().map(|listeners| {
+                (
+                    listeners.is_accepting(),
+                    listeners
+                        .listeners()
+                        .iter()
+                        .map(|bound| format!("{}@{}", bound.listener, bound.addr))
+                        .collect(),
+                )
+            }),
+            persistence_healthy: self.persistence.as_ref().map(|p| p.is_healthy()),
+            cluster: self.cluster.as_ref().map(|cluster| {
+                let status = cluster.status();
+                (
+                    status.partitioned,
+                    status.reachable_nodes,
+                    status.expected_nodes,
+                )
+            }),
+            bridges: self
+                .bridges
+                .as_ref()
+                .map(|bridges| {
+                    bridges
+                        .health()
+                        .into_iter()
+                        .map(|b| (b.name, b.status == RemotePeerStatus::Connected))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+fn assess(config: &HealthConfig, observed: Observed) -> HealthReport {
+    let mut checks = Vec::new();
+
+    checks.push(match observed.listeners {
+        Some((accepting, bound)) => HealthCheck {
+            name: "listeners",
+            healthy: accepting,
+            required: true,
+            detail: if accepting {
+                format!("accepting on {}", bound.join(", "))
+            } else {
+                "not accepting connections".to_string()
+            },
+        },
+        None => HealthCheck {
+            name: "listeners",
+            healthy: false,
+            required: true,
+            detail: "broker not started".to_string(),
+        },
+    });
+
+    if let Some(healthy) = observed.persistence_healthy {
+        checks.push(HealthCheck {
+            name: "persistence",
+            healthy,
+            required: config.persistence,
+            detail: if healthy {
+                "committing writes".to_string()
+            } else {
+                "last write to the backend failed".to_string()
+            },
+        });
+    }
+
+    if let Some((partitioned, reachable, expected)) = observed.cluster {
+        checks.push(HealthCheck {
+            name: "cluster",
+            healthy: !partitioned,
+            required: config.cluster_quorum,
+            detail: format!(
+                "{} of {} nodes reachable{}",
+                reachable,
+                expected,
+                if partitioned { ", quorum lost" } else { "" }
+            ),
+        });
+    }
+
+    if !observed.bridges.is_empty() {
+        let connected = observed.bridges.iter().filter(|(_, up)| *up).count();
+        let total = observed.bridges.len();
+        let healthy = match config.bridges {
+            BridgeReadiness::None | BridgeReadiness::Any => connected > 0,
+            BridgeReadiness::All => connected == total,
+        };
+        let down: Vec<&str> = observed
+            .bridges
+            .iter()
+            .filter(|(_, up)| !*up)
+            .map(|(name, _)| name.as_str())
+            .collect();
+        checks.push(HealthCheck {
+            name: "bridges",
+            healthy,
+            required: config.bridges != BridgeReadiness::None,
+            detail: if down.is_empty() {
+                format!("{} of {} connected", connected, total)
+            } else {
+                format!(
+                    "{} of {} connected, down: {}",
+                    connected,
+                    total,
+                    down.join(", ")
+                )
+            },
+        });
+    }
+
+    HealthReport {
+        ok: checks.iter().all(|check| check.healthy || !check.required),
+        checks,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn started() -> Observed {
+        Observed {
+            listeners: Some((true, vec!["tcp@0.0.0.0:1883".to_string()])),
+            ..Default::default()
+        }
+    }
+
+    fn check<'a>(report: &'a HealthReport, name: &str) -> &'a HealthCheck {
+        report.checks.iter().find(|c| c.name == name).unwrap()
+    }
+
+    #[test]
+    fn test_listeners_gate_readiness() {
+        let config = HealthConfig::default();
+        assert!(assess(&config, started()).ok);
+        assert!(!assess(&config, Observed::default()).ok);
+
+        let draining = Observed {
+            listeners: Some((false, Vec::new())),
+            ..Default::default()
+        };
+        assert!(!assess(&config, draining).ok);
+    }
+
+    #[test]
+    fn test_optional_checks() {
+        let observed = || Observed {
+            persistence_healthy: Some(false),
+            cluster: Some((true, 1, 3)),
+            ..started()
+        };
+        let report = assess(&HealthConfig::default(), observed());
+        assert!(!report.ok);
+        assert_eq!(
+            check(&report, "cluster").detail,
+            "1 of 3 nodes reachable, quorum lost"
+        );
+
+        let lenient = HealthConfig {
+            persistence: false,
+            cluster_quorum: false,
+            ..Default::default()
+        };
+        let report = assess(&lenient, observed());
+        assert!(report.ok);
+        assert!(!check(&report, "persistence").healthy);
+    }
+
+    #[test]
+    fn test_liveness_ignores_dependencies() {
+        let health = Health::new(HealthConfig::default());
+        assert!(!health.readiness().ok);
+        let report = health.liveness();
+        assert!(report.ok);
+        assert_eq!(
+            check(&report, "process").detail,
+            format!("pid {}", std::process::id())
+        );
+    }
+
+    #[test]
+    fn test_bridge_readiness() {
+        let observed = || Observed {
+            bridges: vec![("cloud".to_string(), true), ("edge".to_string(), false)],
+            ..started()
+        };
+        let criteria = |bridges| HealthConfig {
+            bridges,
+            ..Default::default()
+        };
+
+        assert!(assess(&criteria(BridgeReadiness::None), observed()).ok);
+        assert!(assess(&criteria(BridgeReadiness::Any), observed()).ok);
+        let report = assess(&criteria(BridgeReadiness::All), observed());
+        assert!(!report.ok);
+        assert_eq!(
+            check(&report, "bridges").detail,
+            "1 of 2 connected, down: edge"
+        );
+    }
+}
@@ -3,12 +3,29 @@
 //! Exposes metrics at /metrics endpoint for monitoring and observability.
 //! Useful for Grafana dashboards, alerts, and capacity planning.
 
+use std::sync::Arc;
+
 use prometheus::{
-    Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    Gauge, GaugeVec, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry,
 };
 
+use crate::bridge::{BridgeStatus, SubscriptionState};
+use crate::broker::ResourceStats;
+use crate::buffer_pool::SizeClassStats;
+use crate::cluster::ClusterStatus;
+use crate::protocol::QoS;
+use crate::remote::RemotePeerStatus;
+use crate::topic::{topic_matches_filter, SubscriptionStore};
+
+mod connections;
+mod health;
+mod push;
 mod server;
 
+pub use connections::{ConnectionGauge, ConnectionLabels};
+pub use health::{Health, HealthCheck, HealthReport};
+pub use push::MetricsPusher;
 pub use server::MetricsServer;
 
 /// All VibeMQ metrics in one place
@@ -21,6 +38,8 @@ pub struct Metrics {
     pub connections_current: IntGauge,
     pub connections_maximum: IntGauge,
     pub connections_by_protocol: IntGaugeVec,
+    pub connections_by_listener: IntGaugeVec,
+    pub slow_consumers: IntGauge,
 
     // Session metrics
     pub sessions_expired_total: IntCounter,
@@ -39,11 +58,21 @@ pub struct Metrics {
     pub publish_messages_received: IntCounter,
     pub publish_messages_sent: IntCounter,
     pub publish_messages_dropped: IntCounter,
+    pub packets_too_large_total: IntCounterVec,
+    pub schema_validation_failures_total: IntCounterVec,
+
+    // Per-pattern topic metrics
+    pub topic_messages_total: IntCounterVec,
+    pub topic_bytes_total: IntCounterVec,
+    pub topic_subscribers: IntGaugeVec,
+    /// Topic filters the per-pattern metrics are labeled by
+    topic_patterns: Arc<[String]>,
 
     // Subscription metrics
     pub subscriptions_current: IntGauge,
     pub subscriptions_total: IntCounter,
     pub unsubscriptions_total: IntCounter,
+    pub subscriptions_quota_exceeded_total: IntCounter,
 
     // Retained messages
     pub retained_messages_current: IntGauge,
@@ -53,20 +82,58 @@ pub struct Metrics {
     pub inflight_messages: IntGaugeVec,
     pub qos1_retransmits: IntCounter,
     pub qos2_retransmits: IntCounter,
+    pub retries_exhausted_total: IntCounterVec,
 
     // Cluster metrics
     pub cluster_peers_current: IntGauge,
     pub cluster_messages_forwarded: IntCounter,
     pub cluster_messages_received: IntCounter,
+    pub cluster_peer_queue_depth: IntGaugeVec,
+    pub cluster_peer_queue_dropped_total: IntCounterVec,
+    pub cluster_partitioned: IntGauge,
+    pub cluster_partition_events_total: IntCounter,
+    pub cluster_reachable_nodes: IntGauge,
+
+    // Bridge metrics
+    pub bridge_queue_depth: IntGaugeVec,
+    pub bridge_spool_depth: IntGaugeVec,
+    pub bridge_queue_dropped_total: IntCounterVec,
+    pub bridge_connected: IntGaugeVec,
+    pub bridge_reconnects_total: IntCounterVec,
+    pub bridge_forwarded_total: IntCounterVec,
+    pub bridge_rtt_seconds: GaugeVec,
+    pub bridge_subscriptions: IntGaugeVec,
 
     // Performance metrics
     pub publish_latency: Histogram,
+    pub delivery_latency: Histogram,
+    pub outbound_queue_depth: Histogram,
     pub connect_duration: Histogram,
 
     // DoS protection metrics
     pub connections_rejected_total: IntCounterVec,
     pub ips_banned_current: IntGauge,
     pub ips_tracked_current: IntGauge,
+
+    // Persistence metrics
+    pub persistence_queue_depth: IntGauge,
+    pub persistence_ops_dropped_total: IntCounterVec,
+    pub persistence_batch_size: Histogram,
+    pub persistence_flush_latency: Histogram,
+    pub persistence_write_latency: Histogram,
+    pub persistence_disk_bytes: IntGaugeVec,
+    pub persistence_compactions_total: IntCounter,
+
+    // Process and runtime metrics
+    pub process_resident_memory_bytes: IntGauge,
+    pub process_open_fds: IntGauge,
+    pub runtime_workers: IntGauge,
+    pub runtime_alive_tasks: IntGauge,
+    pub runtime_global_queue_depth: IntGauge,
+    pub runtime_worker_utilization: Gauge,
+    pub runtime_blocking_queue_depth: IntGauge,
+    pub buffer_pool_buffers: IntGaugeVec,
+    pub buffer_pool_bytes: IntGaugeVec,
 }
 
 impl Metrics {
@@ -95,12 +162,27 @@ impl Metrics {
         )
         .unwrap();
 
+        let connections_by_listener = IntGaugeVec::new(
+            Opts::new(
+                "vibemq_connections_by_listener",
+                "Current connections by listener",
+            ),
+            &["listener"],
+        )
+        .unwrap();
+
         let connections_maximum = IntGauge::with_opts(Opts::new(
             "vibemq_connections_maximum",
             "Maximum concurrent connections since startup",
         ))
         .unwrap();
 
+        let slow_consumers = IntGauge::with_opts(Opts::new(
+            "vibemq_slow_consumers",
+            "Clients whose outbound channel stayed over the slow consumer threshold",
+        ))
+        .unwrap();
+
         // Session metrics
         let sessions_expired_total = IntCounter::with_opts(Opts::new(
             "vibemq_sessions_expired_total",
@@ -140,6 +222,52 @@ impl Metrics {
         ))
         .unwrap();
 
+        let packets_too_large_total = IntCounterVec::new(
+            Opts::new(
+                "vibemq_packets_too_large_total",
+                "Total packets exceeding the Maximum Packet Size, across all clients (inbound: connection closed, outbound: discarded)",
+            ),
+            &["direction"],
+        )
+        .unwrap();
+
+        let schema_validation_failures_total = IntCounterVec::new(
+            Opts::new(
+                "vibemq_schema_validation_failures_total",
+                "Publishes whose payload did not follow the schema of their topic",
+            ),
+            &["topic", "action"],
+        )
+        .unwrap();
+
+        // Per-pattern topic metrics
+        let topic_messages_total = IntCounterVec::new(
+            Opts::new(
+                "vibemq_topic_messages_total",
+                "Messages published to topics matching a configured pattern",
+            ),
+            &["pattern"],
+        )
+        .unwrap();
+
+        let topic_bytes_total = IntCounterVec::new(
+            Opts::new(
+                "vibemq_topic_bytes_total",
+                "Payload bytes published to topics matching a configured pattern",
+            ),
+            &["pattern"],
+        )
+        .unwrap();
+
+        let topic_subscribers = IntGaugeVec::new(
+            Opts::new(
+                "vibemq_topic_subscribers",
+                "Subscriptions to filters within a configured pattern",
+            ),
+            &["pattern"],
+        )
+        .unwrap();
+
         // Message metrics (by type, for Prometheus labels)
         let messages_received_total = IntCounterVec::new(
             Opts::new(
@@ -187,6 +315,12 @@ impl Metrics {
         ))
         .unwrap();
 
+        let subscriptions_quota_exceeded_total = IntCounter::with_opts(Opts::new(
+            "vibemq_subscriptions_quota_exceeded_total",
+            "Total subscriptions refused for exceeding the per-client limit",
+        ))
+        .unwrap();
+
         // Retained messages
         let retained_messages_current = IntGauge::with_opts(Opts::new(
             "vibemq_retained_messages_current",
@@ -222,6 +356,15 @@ impl Metrics {
         ))
         .unwrap();
 
+        let retries_exhausted_total = IntCounterVec::new(
+            Opts::new(
+                "vibemq_retries_exhausted_total",
+                "Unacked messages that ran out of retransmissions by action taken",
+            ),
+            &["action"],
+        )
+        .unwrap();
+
         // Cluster metrics
         let cluster_peers_current = IntGauge::with_opts(Opts::new(
             "vibemq_cluster_peers_current",
@@ -241,6 +384,115 @@ impl Metrics {
         ))
         .unwrap();
 
+        // Bridge metrics
+        let bridge_queue_depth = IntGaugeVec::new(
+            Opts::new(
+                "vibemq_bridge_queue_depth",
+                "Messages buffered in memory for a bridge's remote broker",
+            ),
+            &["bridge"],
+        )
+        .unwrap();
+
+        let bridge_spool_depth = IntGaugeVec::new(
+            Opts::new(
+                "vibemq_bridge_spool_depth",
+                "Messages spooled to disk for a bridge's remote broker",
+            ),
+            &["bridge"],
+        )
+        .unwrap();
+
+        let bridge_queue_dropped_total = IntCounterVec::new(
+            Opts::new(
+                "vibemq_bridge_queue_dropped_total",
+                "Messages dropped because a bridge's queue and spool were full",
+            ),
+            &["bridge"],
+        )
+        .unwrap();
+
+        let bridge_connected = IntGaugeVec::new(
+            Opts::new(
+                "vibemq_bridge_connected",
+                "Whether a bridge is connected to its remote broker (1 = connected)",
+            ),
+            &["bridge"],
+        )
+        .unwrap();
+
+        let bridge_reconnects_total = IntCounterVec::new(
+            Opts::new(
+                "vibemq_bridge_reconnects_total",
+                "Reconnect attempts after a bridge connection failed or was lost",
+            ),
+            &["bridge"],
+        )
+        .unwrap();
+
+        let bridge_forwarded_total = IntCounterVec::new(
+            Opts::new(
+                "vibemq_bridge_forwarded_total",
+                "Messages forwarded by a bridge (direction: out = to remote, in = from remote)",
+            ),
+            &["bridge", "direction"],
+        )
+        .unwrap();
+
+        let bridge_rtt_seconds = GaugeVec::new(
+            Opts::new(
+                "vibemq_bridge_rtt_seconds",
+                "Last keep-alive round trip to a bridge's remote broker",
+            ),
+            &["bridge"],
+        )
+        .unwrap();
+
+        let bridge_subscriptions = IntGaugeVec::new(
+            Opts::new(
+                "vibemq_bridge_subscriptions",
+                "Subscriptions of a bridge on its remote broker (state: active, pending, rejected)",
+            ),
+            &["bridge", "state"],
+        )
+        .unwrap();
+
+        let cluster_peer_queue_depth = IntGaugeVec::new(
+            Opts::new(
+                "vibemq_cluster_peer_queue_depth",
+                "Messages waiting to be forwarded to a cluster peer",
+            ),
+            &["peer"],
+        )
+        .unwrap();
+
+        let cluster_peer_queue_dropped_total = IntCounterVec::new(
+            Opts::new(
+                "vibemq_cluster_peer_queue_dropped_total",
+                "Total messages dropped because a cluster peer queue was full",
+            ),
+            &["peer"],
+        )
+        .unwrap();
+
+        let cluster_partitioned = IntGauge::with_opts(Opts::new(
+            "vibemq_cluster_partitioned",
+            "Whether this node has lost cluster quorum (1 = partitioned)",
+        ))
+        .unwrap();
+
+        let cluster_partition_events_total = IntCounter::with_opts(Opts::new(
+            "vibemq_cluster_partition_events_total",
+            "Total times this node lost cluster quorum",
+        ))
+        .unwrap();
+
+        let cluster_reachable_nodes = IntGauge::with_opts(Opts::new(
+            "vibemq_cluster_reachable_nodes",
+            "Cluster nodes reachable from this node, including itself",
+        ))
+        .unwrap();
+
         // Performance metrics
         let publish_latency = Histogram::with_opts(
             HistogramOpts::new(
@@ -253,6 +505,28 @@ impl Metrics {
         )
         .unwrap();
 
+        let delivery_latency = Histogram::with_opts(
+            HistogramOpts::new(
+                "vibemq_delivery_latency_seconds",
+                "Time from queueing a message for a subscriber to writing it to the socket (sampled)",
+            )
+            .buckets(vec![
+                0.0001, 0.0005, 0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0,
+            ]),
+        )
+        .unwrap();
+
+        let outbound_queue_depth = Histogram::with_opts(
+            HistogramOpts::new(
+                "vibemq_outbound_queue_depth",
+                "Packets queued in the outbound channel of each connected client (sampled)",
+            )
+            .buckets(vec![
+                0.0, 1.0, 10.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0,
+            ]),
+        )
+        .unwrap();
+
         let connect_duration = Histogram::with_opts(
             HistogramOpts::new(
                 "vibemq_connect_duration_seconds",
@@ -286,6 +560,131 @@ impl Metrics {
         ))
         .unwrap();
 
+        // Persistence metrics
+        let persistence_queue_depth = IntGauge::with_opts(Opts::new(
+            "vibemq_persistence_queue_depth",
+            "Operations waiting in the persistence writer channel",
+        ))
+        .unwrap();
+
+        let persistence_ops_dropped_total = IntCounterVec::new(
+            Opts::new(
+                "vibemq_persistence_ops_dropped_total",
+                "Total persistence operations dropped under backpressure",
+            ),
+            &["reason"],
+        )
+        .unwrap();
+
+        let persistence_batch_size = Histogram::with_opts(
+            HistogramOpts::new(
+                "vibemq_persistence_batch_size",
+                "Number of operations per committed persistence batch",
+            )
+            .buckets(vec![
+                1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 5000.0,
+            ]),
+        )
+        .unwrap();
+
+        let persistence_flush_latency = Histogram::with_opts(
+            HistogramOpts::new(
+                "vibemq_persistence_flush_latency_seconds",
+                "Time to commit a persistence batch or sync the backend",
+            )
+            .buckets(vec![
+                0.0001, 0.0005, 0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+            ]),
+        )
+        .unwrap();
+
+        let persistence_write_latency = Histogram::with_opts(
+            HistogramOpts::new(
+                "vibemq_persistence_write_latency_seconds",
+                "Time from queueing the oldest operation of a batch to committing the batch",
+            )
+            .buckets(vec![
+                0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+            ]),
+        )
+        .unwrap();
+
+        let persistence_disk_bytes = IntGaugeVec::new(
+            Opts::new(
+                "vibemq_persistence_disk_bytes",
+                "On-disk size of persisted data by partition",
+            ),
+            &["partition"],
+        )
+        .unwrap();
+
+        let persistence_compactions_total = IntCounter::with_opts(Opts::new(
+            "vibemq_persistence_compactions_total",
+            "Total manual persistence compactions run",
+        ))
+        .unwrap();
+
+        // Process and runtime metrics
+        let process_resident_memory_bytes = IntGauge::with_opts(Opts::new(
+            "vibemq_process_resident_memory_bytes",
+            "Resident memory of the broker process",
+        ))
+        .unwrap();
+
+        let process_open_fds = IntGauge::with_opts(Opts::new(
+            "vibemq_process_open_fds",
+            "Open file descriptors of the broker process, sockets included",
+        ))
+        .unwrap();
+
+        let runtime_workers = IntGauge::with_opts(Opts::new(
+            "vibemq_runtime_workers",
+            "Worker threads of the async runtime",
+        ))
+        .unwrap();
+
+        let runtime_alive_tasks = IntGauge::with_opts(Opts::new(
+            "vibemq_runtime_alive_tasks",
+            "Tasks alive in the async runtime",
+        ))
+        .unwrap();
+
+        let runtime_global_queue_depth = IntGauge::with_opts(Opts::new(
+            "vibemq_runtime_global_queue_depth",
+            "Tasks waiting in the global queue of the async runtime",
+        ))
+        .unwrap();
+
+        let runtime_worker_utilization = Gauge::with_opts(Opts::new(
+            "vibemq_runtime_worker_utilization",
+            "Share of time the runtime workers were busy over the last sample interval",
+        ))
+        .unwrap();
+
+        let runtime_blocking_queue_depth = IntGauge::with_opts(Opts::new(
+            "vibemq_runtime_blocking_queue_depth",
+            "Tasks waiting for a blocking pool thread (builds with tokio_unstable only)",
+        ))
+        .unwrap();
+
+        let buffer_pool_buffers = IntGaugeVec::new(
+            Opts::new(
+                "vibemq_buffer_pool_buffers",
+                "Buffers held in the buffer pool by size class",
+            ),
+            &["size_class"],
+        )
+        .unwrap();
+
+        let buffer_pool_bytes = IntGaugeVec::new(
+            Opts::new(
+                "vibemq_buffer_pool_bytes",
+                "Bytes held in the buffer pool by size class",
+            ),
+            &["size_class"],
+        )
+        .unwrap();
+
         // Register all metrics
         registry
             .register(Box::new(connections_total.clone()))
@@ -299,6 +698,10 @@ impl Metrics {
         registry
             .register(Box::new(connections_by_protocol.clone()))
             .unwrap();
+        registry
+            .register(Box::new(connections_by_listener.clone()))
+            .unwrap();
+        registry.register(Box::new(slow_consumers.clone())).unwrap();
         registry
             .register(Box::new(sessions_expired_total.clone()))
             .unwrap();
@@ -317,6 +720,21 @@ impl Metrics {
         registry
             .register(Box::new(publish_messages_dropped.clone()))
             .unwrap();
+        registry
+            .register(Box::new(packets_too_large_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(schema_validation_failures_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(topic_messages_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(topic_bytes_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(topic_subscribers.clone()))
+            .unwrap();
         registry
             .register(Box::new(messages_received_total.clone()))
             .unwrap();
@@ -338,6 +756,9 @@ impl Metrics {
         registry
             .register(Box::new(unsubscriptions_total.clone()))
             .unwrap();
+        registry
+            .register(Box::new(subscriptions_quota_exceeded_total.clone()))
+            .unwrap();
         registry
             .register(Box::new(retained_messages_current.clone()))
             .unwrap();
@@ -353,9 +774,51 @@ impl Metrics {
         registry
             .register(Box::new(qos2_retransmits.clone()))
             .unwrap();
+        registry
+            .register(Box::new(retries_exhausted_total.clone()))
+            .unwrap();
         registry
             .register(Box::new(cluster_peers_current.clone()))
             .unwrap();
+        registry
+            .register(Box::new(cluster_peer_queue_depth.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(bridge_queue_depth.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(bridge_spool_depth.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(bridge_queue_dropped_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(bridge_connected.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(bridge_reconnects_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(bridge_forwarded_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(bridge_rtt_seconds.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(bridge_subscriptions.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(cluster_peer_queue_dropped_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(cluster_partitioned.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(cluster_partition_events_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(cluster_reachable_nodes.clone()))
+            .unwrap();
         registry
             .register(Box::new(cluster_messages_forwarded.clone()))
             .unwrap();
@@ -365,6 +828,12 @@ impl Metrics {
         registry
             .register(Box::new(publish_latency.clone()))
             .unwrap();
+        registry
+            .register(Box::new(delivery_latency.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(outbound_queue_depth.clone()))
+            .unwrap();
         registry
             .register(Box::new(connect_duration.clone()))
             .unwrap();
@@ -377,6 +846,54 @@ impl Metrics {
         registry
             .register(Box::new(ips_tracked_current.clone()))
             .unwrap();
+        registry
+            .register(Box::new(persistence_queue_depth.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(persistence_ops_dropped_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(persistence_batch_size.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(persistence_flush_latency.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(persistence_write_latency.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(persistence_disk_bytes.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(persistence_compactions_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(process_resident_memory_bytes.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(process_open_fds.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(runtime_workers.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(runtime_alive_tasks.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(runtime_global_queue_depth.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(runtime_worker_utilization.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(runtime_blocking_queue_depth.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(buffer_pool_buffers.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(buffer_pool_bytes.clone()))
+            .unwrap();
 
         Metrics {
             registry,
@@ -384,6 +901,8 @@ impl Metrics {
             connections_current,
             connections_maximum,
             connections_by_protocol,
+            connections_by_listener,
+            slow_consumers,
             sessions_expired_total,
             messages_total_received,
             messages_total_sent,
@@ -394,32 +913,80 @@ impl Metrics {
             publish_messages_received,
             publish_messages_sent,
             publish_messages_dropped,
+            packets_too_large_total,
+            schema_validation_failures_total,
+            topic_messages_total,
+            topic_bytes_total,
+            topic_subscribers,
+            topic_patterns: Arc::from(Vec::new()),
             subscriptions_current,
             subscriptions_total,
             unsubscriptions_total,
+            subscriptions_quota_exceeded_total,
             retained_messages_current,
             retained_bytes_current,
             inflight_messages,
             qos1_retransmits,
             qos2_retransmits,
+            retries_exhausted_total,
             cluster_peers_current,
             cluster_messages_forwarded,
             cluster_messages_received,
+            cluster_peer_queue_depth,
+            cluster_peer_queue_dropped_total,
+            cluster_partitioned,
+            cluster_partition_events_total,
+            cluster_reachable_nodes,
+            bridge_queue_depth,
+            bridge_spool_depth,
+            bridge_queue_dropped_total,
+            bridge_connected,
+            bridge_reconnects_total,
+            bridge_forwarded_total,
+            bridge_rtt_seconds,
+            bridge_subscriptions,
             publish_latency,
+            delivery_latency,
+            outbound_queue_depth,
             connect_duration,
             connections_rejected_total,
             ips_banned_current,
             ips_tracked_current,
+            persistence_queue_depth,
+            persistence_ops_dropped_total,
+            persistence_batch_size,
+            persistence_flush_latency,
+            persistence_write_latency,
+            persistence_disk_bytes,
+            persistence_compactions_total,
+            process_resident_memory_bytes,
+            process_open_fds,
+            runtime_workers,
+            runtime_alive_tasks,
+            runtime_global_queue_depth,
+            runtime_worker_utilization,
+            runtime_blocking_queue_depth,
+            buffer_pool_buffers,
+            buffer_pool_bytes,
         }
     }
 
+    /// Export per-pattern topic metrics for `patterns`
+    pub fn with_topic_patterns(mut self, patterns: Vec<String>) -> Self {
+        self.topic_patterns = patterns.into();
+        self
+    }
+
     // Helper methods for common operations
 
-    pub fn client_connected(&self, protocol: &str) {
+    pub fn client_connected(&self, labels: ConnectionLabels) {
         self.connections_total.inc();
         self.connections_current.inc();
         self.connections_by_protocol
-            .with_label_values(&[protocol])
+            .with_label_values(&[labels.protocol])
+            .inc();
+        self.connections_by_listener
+            .with_label_values(&[labels.listener])
             .inc();
         // Update maximum if current exceeds it
         let current = self.connections_current.get();
@@ -429,10 +996,13 @@ impl Metrics {
         }
     }
 
-    pub fn client_disconnected(&self, protocol: &str) {
+    pub fn client_disconnected(&self, labels: ConnectionLabels) {
         self.connections_current.dec();
         self.connections_by_protocol
-            .with_label_values(&[protocol])
+            .with_label_values(&[labels.protocol])
+            .dec();
+        self.connections_by_listener
+            .with_label_values(&[labels.listener])
             .dec();
     }
 
@@ -460,6 +1030,10 @@ impl Metrics {
         self.unsubscriptions_total.inc();
     }
 
+    pub fn subscription_quota_exceeded(&self) {
+        self.subscriptions_quota_exceeded_total.inc();
+    }
+
     pub fn retained_message_stored(&self, bytes: usize) {
         self.retained_messages_current.inc();
         self.retained_bytes_current.add(bytes as i64);
@@ -486,6 +1060,20 @@ impl Metrics {
         self.cluster_messages_received.inc();
     }
 
+    /// Bring the forwarded/received counters up to the peer links' totals
+    pub fn cluster_traffic(&self, messages_sent: u64, messages_received: u64) {
+        let forwarded = self.cluster_messages_forwarded.get();
+        if messages_sent > forwarded {
+            self.cluster_messages_forwarded
+                .inc_by(messages_sent - forwarded);
+        }
+        let received = self.cluster_messages_received.get();
+        if messages_received > received {
+            self.cluster_messages_received
+                .inc_by(messages_received - received);
+        }
+    }
+
     // Publish-specific helpers
 
     pub fn publish_received(&self, bytes: usize) {
@@ -504,6 +1092,69 @@ impl Metrics {
         self.publish_messages_dropped.inc();
     }
 
+    /// Count the retransmission of an unacked QoS 1 or 2 message
+    pub fn retransmit(&self, qos: QoS) {
+        match qos {
+            QoS::AtMostOnce => {}
+            QoS::AtLeastOnce => self.qos1_retransmits.inc(),
+            QoS::ExactlyOnce => self.qos2_retransmits.inc(),
+        }
+    }
+
+    /// Count an unacked message that ran out of retransmissions ("drop" or
+    /// "disconnect")
+    pub fn retries_exhausted(&self, action: &str) {
+        self.retries_exhausted_total
+            .with_label_values(&[action])
+            .inc();
+    }
+
+    /// Count a publish toward the first topic pattern it matches; topics
+    /// outside all patterns are not counted, bounding label cardinality
+    pub fn topic_published(&self, topic: &str, bytes: usize) {
+        let Some(pattern) = self
+            .topic_patterns
+            .iter()
+            .find(|pattern| topic_matches_filter(topic, pattern))
+        else {
+            return;
+        };
+        self.topic_messages_total
+            .with_label_values(&[pattern.as_str()])
+            .inc();
+        self.topic_bytes_total
+            .with_label_values(&[pattern.as_str()])
+            .inc_by(bytes as u64);
+    }
+
+    /// Refresh the subscriber count of each topic pattern
+    pub fn update_topic_subscribers(&self, subscriptions: &SubscriptionStore) {
+        if self.topic_patterns.is_empty() {
+            return;
+        }
+        let counts = subscriptions.subscriptions_within(&self.topic_patterns);
+        for (pattern, count) in self.topic_patterns.iter().zip(counts) {
+            self.topic_subscribers
+                .with_label_values(&[pattern.as_str()])
+                .set(count as i64);
+        }
+    }
+
+    /// Count a packet over the Maximum Packet Size ("inbound" or "outbound")
+    pub fn packet_too_large(&self, direction: &str) {
+        self.packets_too_large_total
+            .with_label_values(&[direction])
+            .inc();
+    }
+
+    /// Count a payload failing the schema of topic filter `topic`, and the
+    /// action taken ("reject" or "quarantine")
+    pub fn schema_validation_failed(&self, topic: &str, action: &str) {
+        self.schema_validation_failures_total
+            .with_label_values(&[topic, action])
+            .inc();
+    }
+
     // Packet helpers (for total message counts)
 
     pub fn packet_received(&self) {
@@ -532,6 +1183,143 @@ impl Metrics {
         self.ips_banned_current.set(banned_ips as i64);
         self.ips_tracked_current.set(tracked_ips as i64);
     }
+
+    // Cluster helpers
+
+    pub fn cluster_peer_queued(&self, peer: &str, depth: usize, dropped: u64) {
+        self.cluster_peer_queue_depth
+            .with_label_values(&[peer])
+            .set(depth as i64);
+        if dropped > 0 {
+            self.cluster_peer_queue_dropped_total
+                .with_label_values(&[peer])
+                .inc_by(dropped);
+        }
+    }
+
+    pub fn cluster_partition_status(&self, status: &ClusterStatus) {
+        self.cluster_partitioned.set(i64::from(status.partitioned));
+        self.cluster_reachable_nodes
+            .set(status.reachable_nodes as i64);
+        let recorded = self.cluster_partition_events_total.get();
+        if status.partition_events > recorded {
+            self.cluster_partition_events_total
+                .inc_by(status.partition_events - recorded);
+        }
+    }
+
+    // Bridge helpers
+
+    pub fn bridge_status(&self, status: &BridgeStatus) {
+        let bridge = status.name.as_str();
+        self.bridge_queue_depth
+            .with_label_values(&[bridge])
+            .set(status.queued as i64);
+        self.bridge_spool_depth
+            .with_label_values(&[bridge])
+            .set(status.spooled as i64);
+        self.bridge_connected
+            .with_label_values(&[bridge])
+            .set(i64::from(status.status == RemotePeerStatus::Connected));
+        if let Some(rtt_ms) = status.rtt_ms {
+            self.bridge_rtt_seconds
+                .with_label_values(&[bridge])
+                .set(rtt_ms / 1000.0);
+        }
+        for state in [
+            SubscriptionState::Active,
+            SubscriptionState::Pending,
+            SubscriptionState::Rejected,
+        ] {
+            let count = status
+                .subscriptions
+                .iter()
+                .filter(|s| s.state == state)
+                .count();
+            self.bridge_subscriptions
+                .with_label_values(&[bridge, state.as_str()])
+                .set(count as i64);
+        }
+
+        // The bridge keeps running totals; catch the counters up
+        let totals = [
+            (
+                self.bridge_queue_dropped_total.with_label_values(&[bridge]),
+                status.dropped,
+            ),
+            (
+                self.bridge_reconnects_total.with_label_values(&[bridge]),
+                status.reconnects,
+            ),
+            (
+                self.bridge_forwarded_total
+                    .with_label_values(&[bridge, "out"]),
+                status.forwarded_out,
+            ),
+            (
+                self.bridge_forwarded_total
+                    .with_label_values(&[bridge, "in"]),
+                status.forwarded_in,
+            ),
+        ];
+        for (counter, total) in totals {
+            let recorded = counter.get();
+            if total > recorded {
+                counter.inc_by(total - recorded);
+            }
+        }
+    }
+
+    // Persistence helpers
+
+    pub fn persistence_batch_committed(&self, ops: usize, seconds: f64) {
+        self.persistence_batch_size.observe(ops as f64);
+        self.persistence_flush_latency.observe(seconds);
+    }
+
+    pub fn persistence_op_dropped(&self, reason: &str) {
+        self.persistence_ops_dropped_total
+            .with_label_values(&[reason])
+            .inc();
+    }
+
+    pub fn update_persistence_disk_usage(&self, partition: &str, bytes: u64) {
+        self.persistence_disk_bytes
+            .with_label_values(&[partition])
+            .set(bytes as i64);
+    }
+
+    // Process and runtime helpers
+
+    pub fn update_resources(&self, stats: &ResourceStats) {
+        if let Some(rss) = stats.rss_bytes {
+            self.process_resident_memory_bytes.set(rss as i64);
+        }
+        if let Some(fds) = stats.open_fds {
+            self.process_open_fds.set(fds as i64);
+        }
+        self.runtime_workers.set(stats.workers as i64);
+        self.runtime_alive_tasks.set(stats.alive_tasks as i64);
+        self.runtime_global_queue_depth
+            .set(stats.global_queue_depth as i64);
+        self.runtime_worker_utilization
+            .set(stats.worker_utilization);
+        if let Some(depth) = stats.blocking_queue_depth {
+            self.runtime_blocking_queue_depth.set(depth as i64);
+        }
+    }
+
+    pub fn update_buffer_pool(&self, stats: &[SizeClassStats]) {
+        for class in stats {
+            let size_class = class.size.to_string();
+            self.buffer_pool_buffers
+                .with_label_values(&[&size_class])
+                .set(class.buffers as i64);
+            self.buffer_pool_bytes
+                .with_label_values(&[&size_class])
+                .set(class.bytes as i64);
+        }
+    }
 }
 
 impl Default for Metrics {
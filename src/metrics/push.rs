@@ -0,0 +1,366 @@
+//! Push Export
+//!
+//! Pushes the metrics of the Prometheus registry to a statsd server over
+//! UDP or a Graphite server over TCP (plaintext protocol), for deployments
+//! that do not scrape. Each sample becomes a dotted path of the prefix, the
+//! metric name and its label values:
+//! `vibemq.vibemq_connections_by_protocol.v5_0`.
+//!
+//! Histograms are pushed as their `_sum` and `_count`; buckets are left
+//! out. Graphite gets cumulative values. statsd gets gauges as `|g` and
+//! counters as `|c` deltas since the previous push, as statsd sums counters
+//! over its own flush interval.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use prometheus::{Encoder, TextEncoder};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tracing::{debug, warn};
+
+use super::Metrics;
+use crate::config::{MetricsPushConfig, PushProtocol};
+
+/// Largest statsd datagram, to stay under common path MTUs
+const MAX_DATAGRAM: usize = 1432;
+
+/// How a sample is pushed to statsd
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Gauge,
+}
+
+/// A sample of the text exposition, with its dotted path
+#[derive(Debug, PartialEq)]
+struct Sample {
+    path: String,
+    value: f64,
+    kind: Kind,
+}
+
+/// Periodic push of the metrics to statsd or Graphite
+pub struct MetricsPusher {
+    metrics: Arc<Metrics>,
+    config: MetricsPushConfig,
+    /// Counter values at the previous push, for statsd deltas
+    pushed: HashMap<String, f64>,
+    graphite: Option<TcpStream>,
+}
+
+impl MetricsPusher {
+    pub fn new(metrics: Arc<Metrics>, config: MetricsPushConfig) -> Self {
+        Self {
+            metrics,
+            config,
+            pushed: HashMap::new(),
+            graphite: None,
+        }
+    }
+
+    /// Push the metrics every interval, forever
+    pub async fn run(mut self) {
+        let mut ticker = tokio::time::interval(self.config.interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.push().await {
+                warn!(
+                    "Failed to push metrics to {} ({:?}): {}",
+                    self.config.address, self.config.protocol, e
+                );
+                self.graphite = None;
+            }
+        }
+    }
+
+    async fn push(&mut self) -> std::io::Result<()> {
+        let mut text = Vec::new();
+        TextEncoder::new()
+            .encode(&self.metrics.registry.gather(), &mut text)
+            .map_err(std::io::Error::other)?;
+        let samples = parse(&String::from_utf8_lossy(&text), &self.config.prefix);
+
+        match self.config.protocol {
+            PushProtocol::Statsd => {
+                let lines = statsd_lines(&samples, &mut self.pushed);
+                let addr = resolve(&self.config.address).await?;
+                let bind: SocketAddr = if addr.is_ipv4() {
+                    "0.0.0.0:0".parse().unwrap()
+                } else {
+                    "[::]:0".parse().unwrap()
+                };
+                let socket = UdpSocket::bind(bind).await?;
+                for datagram in datagrams(&lines) {
+                    socket.send_to(datagram.as_bytes(), addr).await?;
+                }
+                debug!("Pushed {} metrics to statsd at {}", lines.len(), addr);
+            }
+            PushProtocol::Graphite => {
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                let body = graphite_lines(&samples, timestamp);
+                if self.graphite.is_none() {
+                    let addr = resolve(&self.config.address).await?;
+                    self.graphite = Some(TcpStream::connect(addr).await?);
+                }
+                if let Some(stream) = self.graphite.as_mut() {
+                    stream.write_all(body.as_bytes()).await?;
+                }
+                debug!(
+                    "Pushed {} metrics to Graphite at {}",
+                    samples.len(),
+                    self.config.address
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn resolve(address: &str) -> std::io::Result<SocketAddr> {
+    tokio::net::lookup_host(address)
+        .await?
+        .next()
+        .ok_or_else(|| std::io::Error::other(format!("no address for {}", address)))
+}
+
+/// Samples of a Prometheus text exposition
+fn parse(text: &str, prefix: &str) -> Vec<Sample> {
+    let mut types: HashMap<&str, &str> = HashMap::new();
+    let mut samples = Vec::new();
+
+    for line in text.lines() {
+        if let Some(declaration) = line.strip_prefix("# TYPE ") {
+            if let Some((name, kind)) = declaration.split_once(' ') {
+                types.insert(name, kind.trim());
+            }
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (series, value) = match line.rsplit_once(' ') {
+            Some(split) => split,
+            None => continue,
+        };
+        let Ok(value) = value.parse::<f64>() else {
+            continue;
+        };
+        if !value.is_finite() {
+            continue;
+        }
+        let (name, labels) = match series.split_once('{') {
+            Some((name, labels)) => (name, label_values(labels.trim_end_matches('}'))),
+            None => (series, Vec::new()),
+        };
+
+        let kind = match types.get(name) {
+            Some(&"counter") => Kind::Counter,
+            Some(_) => Kind::Gauge,
+            None => {
+                let base = name
+                    .strip_suffix("_sum")
+                    .or_else(|| name.strip_suffix("_count"))
+                    .or_else(|| name.strip_suffix("_bucket"));
+                match base.and_then(|base| types.get(base)) {
+                    Some(_) if name.ends_with("_bucket") => continue,
+                    Some(&"histogram") | Some(&"summary") => Kind::Counter,
+                    _ => Kind::Gauge,
+                }
+            }
+        };
+
+        let mut path = format!("{}.{}", prefix, sanitize(name));
+        for value in labels {
+            path.push('.');
+            path.push_str(&sanitize(&value));
+        }
+        samples.push(Sample { path, value, kind });
+    }
+    samples
+}
+
+/// Values of the labels `a="x",b="y"`, unescaped
+fn label_values(labels: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut chars = labels.chars();
+    while let Some(c) = chars.next() {
+        if c != '"' {
+            continue;
+        }
+        let mut value = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some(escaped) => value.push(escaped),
+                    None => break,
+                },
+                '"' => break,
+                _ => value.push(c),
+            }
+        }
+        values.push(value);
+    }
+    values
+}
+
+/// A path component, with anything other than letters, digits, `-` and
+/// `_` replaced by `_`
+fn sanitize(component: &str) -> String {
+    component
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// statsd lines of the samples; counters are deltas since the values in
+/// `pushed`, which are updated
+fn statsd_lines(samples: &[Sample], pushed: &mut HashMap<String, f64>) -> Vec<String> {
+    let mut lines = Vec::with_capacity(samples.len());
+    for sample in samples {
+        match sample.kind {
+            Kind::Counter => {
+                let previous = pushed.insert(sample.path.clone(), sample.value);
+                // A counter below its previous value was reset
+                let delta = match previous {
+                    Some(previous) if sample.value >= previous => sample.value - previous,
+                    _ => sample.value,
+                };
+                if delta > 0.0 {
+                    lines.push(format!("{}:{}|c", sample.path, delta));
+                }
+            }
+            Kind::Gauge => {
+                // A signed gauge value adjusts the gauge rather than sets it
+                if sample.value < 0.0 {
+                    lines.push(format!("{}:0|g", sample.path));
+                }
+                lines.push(format!("{}:{}|g", sample.path, sample.value));
+            }
+        }
+    }
+    lines
+}
+
+/// Graphite plaintext lines of the samples
+fn graphite_lines(samples: &[Sample], timestamp: u64) -> String {
+    let mut body = String::new();
+    for sample in samples {
+        body.push_str(&format!("{} {} {}\n", sample.path, sample.value, timestamp));
+    }
+    body
+}
+
+/// Lines joined into datagrams of at most `MAX_DATAGRAM` bytes, where a
+/// line fits
+fn datagrams(lines: &[String]) -> Vec<String> {
+    let mut datagrams = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + 1 + line.len() > MAX_DATAGRAM {
+            datagrams.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        datagrams.push(current);
+    }
+    datagrams
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPOSITION: &str = r#"# HELP vibemq_connections_total Total connections
+# TYPE vibemq_connections_total counter
+vibemq_connections_total 7
+# TYPE vibemq_connections_by_protocol gauge
+vibemq_connections_by_protocol{protocol="v5.0"} 3
+# TYPE vibemq_publish_latency_seconds histogram
+vibemq_publish_latency_seconds_bucket{le="0.001"} 4
+vibemq_publish_latency_seconds_bucket{le="+Inf"} 5
+vibemq_publish_latency_seconds_sum 0.25
+vibemq_publish_latency_seconds_count 5
+"#;
+
+    #[test]
+    fn test_parse_exposition() {
+        let samples = parse(EXPOSITION, "broker1");
+        let paths: Vec<_> = samples
+            .iter()
+            .map(|s| (s.path.as_str(), s.value, s.kind))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                ("broker1.vibemq_connections_total", 7.0, Kind::Counter),
+                (
+                    "broker1.vibemq_connections_by_protocol.v5_0",
+                    3.0,
+                    Kind::Gauge
+                ),
+                (
+                    "broker1.vibemq_publish_latency_seconds_sum",
+                    0.25,
+                    Kind::Counter
+                ),
+                (
+                    "broker1.vibemq_publish_latency_seconds_count",
+                    5.0,
+                    Kind::Counter
+                ),
+            ]
+        );
+        assert_eq!(label_values(r#"a="x\"y",b="z""#), ["x\"y", "z"]);
+    }
+
+    #[test]
+    fn test_statsd_counter_deltas() {
+        let mut pushed = HashMap::new();
+        let samples = parse(EXPOSITION, "vibemq");
+        let lines = statsd_lines(&samples, &mut pushed);
+        assert!(lines.contains(&"vibemq.vibemq_connections_total:7|c".to_string()));
+        assert!(lines.contains(&"vibemq.vibemq_connections_by_protocol.v5_0:3|g".to_string()));
+
+        // Unchanged counters are left out, gauges are always sent
+        let lines = statsd_lines(&samples, &mut pushed);
+        assert_eq!(lines, ["vibemq.vibemq_connections_by_protocol.v5_0:3|g"]);
+
+        let negative = [Sample {
+            path: "g".to_string(),
+            value: -2.0,
+            kind: Kind::Gauge,
+        }];
+        assert_eq!(statsd_lines(&negative, &mut pushed), ["g:0|g", "g:-2|g"]);
+    }
+
+    #[test]
+    fn test_graphite_and_datagrams() {
+        let samples = parse(EXPOSITION, "vibemq");
+        let body = graphite_lines(&samples[..1], 1700000000);
+        assert_eq!(body, "vibemq.vibemq_connections_total 7 1700000000\n");
+
+        let lines: Vec<String> = (0..100).map(|i| format!("metric.{:04}:1|c", i)).collect();
+        let datagrams = datagrams(&lines);
+        assert!(datagrams.len() > 1);
+        assert!(datagrams.iter().all(|d| d.len() <= MAX_DATAGRAM));
+        assert_eq!(datagrams.join("\n").lines().count(), 100);
+    }
+}
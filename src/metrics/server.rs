@@ -1,28 +1,191 @@
 //! HTTP server for Prometheus metrics endpoint
+//!
+//! With clustering enabled, `/cluster` also serves the aggregated cluster
+//! view as JSON; with bridges configured, `/bridges` serves their health.
+//! `/sessions/<client_id>` serves a snapshot of a client's session state,
+//! `/clients` those of all connected clients, with their message and byte
+//! counters, and `/subscriptions` the subscriber counts per topic filter. `/healthz`
+//! and `/readyz` serve the health checks as JSON, with status 503 while
+//! they fail.
+//!
+//! With the message journal and `journal_api` enabled,
+//! `/journal?filter=<filter>&limit=<n>` serves the last `n` (default 100)
+//! journaled messages matching the filter (default `#`), oldest first.
+//!
+//! `/rules` lists the rules with their counters and `/rules/<id>` serves
+//! one. With `rules_api` enabled, `POST /rules` adds or replaces a rule
+//! from its JSON definition and `DELETE /rules/<id>` removes one.
+//!
+//! With `users_api` enabled on a strongly consistent cluster, `PUT
+//! /users/<username>` (`{"password": .., "role": ..}`) and `PUT
+//! /roles/<name>` (`{"publish": [..], "subscribe": [..]}`) create or
+//! replace a user or ACL role on every node, and `DELETE` removes one.
+//!
+//! With `redirect_api` enabled, `GET /redirect` serves the redirect of new
+//! connections, `PUT /redirect` (`{"server_reference": .., "permanent": ..,
+//! "connected": ..}`) sets it, also redirecting connected clients if
+//! `connected`, and `DELETE /redirect` lifts it. `POST
+//! /redirect/<client_id>` redirects one connected client.
 
-use super::Metrics;
-use http_body_util::Full;
+use super::{Health, HealthReport, Metrics};
+use crate::bridge::BridgeManager;
+use crate::broker::RedirectControl;
+use crate::cluster::{ClusterManager, MetadataCommand};
+use crate::config::{RedirectConfig, RuleConfig};
+use crate::journal::Journal;
+use crate::persistence::{StoredRole, StoredUser};
+use crate::rules::RuleEngine;
+use crate::session::SessionStore;
+use crate::topic::SubscriptionStore;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHasher};
+use http_body_util::{BodyExt, Full, Limited};
 use hyper::body::Bytes;
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
-use hyper::{Request, Response, StatusCode};
+use hyper::{Method, Request, Response, StatusCode};
 use hyper_util::rt::TokioIo;
 use prometheus::{Encoder, TextEncoder};
+use serde::Deserialize;
 use std::convert::Infallible;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tracing::{error, info};
 
+/// Largest rule definition accepted by `POST /rules`
+const MAX_RULE_BODY: usize = 64 * 1024;
+
+/// Largest user or role definition accepted by `PUT /users` and `PUT /roles`
+const MAX_ACCESS_BODY: usize = 16 * 1024;
+
+/// Largest redirect accepted by `PUT /redirect` and `POST /redirect/<client_id>`
+const MAX_REDIRECT_BODY: usize = 4 * 1024;
+
+/// Journaled messages served by `/journal` without a `limit`
+const DEFAULT_JOURNAL_LIMIT: usize = 100;
+
+/// The rule engine, and whether rules may be changed over HTTP
+#[derive(Clone)]
+struct RulesApi {
+    engine: Arc<RuleEngine>,
+    writable: bool,
+}
+
 /// HTTP server that exposes Prometheus metrics
 pub struct MetricsServer {
     metrics: Arc<Metrics>,
     addr: SocketAddr,
+    cluster: Option<Arc<ClusterManager>>,
+    bridges: Option<Arc<BridgeManager>>,
+    sessions: Option<Arc<SessionStore>>,
+    subscriptions: Option<Arc<SubscriptionStore>>,
+    health: Arc<Health>,
+    rules: Option<RulesApi>,
+    journal: Option<Arc<Journal>>,
+    /// Cluster that user and role changes are committed through
+    users_api: Option<Arc<ClusterManager>>,
+    redirect: Option<RedirectControl>,
+}
+
+/// Body of `PUT /users/<username>`
+#[derive(Deserialize)]
+struct UserBody {
+    password: Option<String>,
+    password_hash: Option<String>,
+    role: Option<String>,
+}
+
+/// Body of `PUT /redirect`
+#[derive(Deserialize)]
+struct RedirectBody {
+    #[serde(flatten)]
+    redirect: RedirectConfig,
+    /// Also redirect the clients connected now
+    #[serde(default)]
+    connected: bool,
+}
+
+/// Body of `PUT /roles/<name>`
+#[derive(Deserialize)]
+struct RoleBody {
+    #[serde(default)]
+    publish: Vec<String>,
+    #[serde(default)]
+    subscribe: Vec<String>,
 }
 
 impl MetricsServer {
     pub fn new(metrics: Arc<Metrics>, addr: SocketAddr) -> Self {
-        Self { metrics, addr }
+        Self {
+            metrics,
+            addr,
+            cluster: None,
+            bridges: None,
+            sessions: None,
+            subscriptions: None,
+            health: Arc::new(Health::default()),
+            rules: None,
+            journal: None,
+            users_api: None,
+            redirect: None,
+        }
+    }
+
+    /// Serve the cluster view at `/cluster`
+    pub fn with_cluster(mut self, cluster: Option<Arc<ClusterManager>>) -> Self {
+        self.cluster = cluster;
+        self
+    }
+
+    /// Serve bridge health at `/bridges`
+    pub fn with_bridges(mut self, bridges: Option<Arc<BridgeManager>>) -> Self {
+        self.bridges = bridges;
+        self
+    }
+
+    /// Serve session snapshots at `/sessions/<client_id>`
+    pub fn with_sessions(mut self, sessions: Arc<SessionStore>) -> Self {
+        self.sessions = Some(sessions);
+        self
+    }
+
+    /// Serve filter statistics at `/subscriptions`
+    pub fn with_subscriptions(mut self, subscriptions: Arc<SubscriptionStore>) -> Self {
+        self.subscriptions = Some(subscriptions);
+        self
+    }
+
+    /// Serve health checks at `/healthz` and `/readyz`
+    pub fn with_health(mut self, health: Health) -> Self {
+        self.health = Arc::new(health);
+        self
+    }
+
+    /// Serve the rules at `/rules`, accepting changes if `writable`
+    pub fn with_rules(mut self, engine: Arc<RuleEngine>, writable: bool) -> Self {
+        self.rules = Some(RulesApi { engine, writable });
+        self
+    }
+
+    /// Accept user and ACL role changes at `/users/<username>` and
+    /// `/roles/<name>`, committed through the cluster's raft metadata
+    pub fn with_users_api(mut self, cluster: Option<Arc<ClusterManager>>) -> Self {
+        self.users_api = cluster;
+        self
+    }
+
+    /// Accept redirects of connecting and connected clients at `/redirect`
+    pub fn with_redirect_api(mut self, redirect: Option<RedirectControl>) -> Self {
+        self.redirect = redirect;
+        self
+    }
+
+    /// Serve the message journal history at `/journal`
+    pub fn with_journal(mut self, journal: Option<Arc<Journal>>) -> Self {
+        self.journal = journal;
+        self
     }
 
     pub async fn run(self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
@@ -33,11 +196,44 @@ impl MetricsServer {
             let (stream, _) = listener.accept().await?;
             let io = TokioIo::new(stream);
             let metrics = self.metrics.clone();
+            let cluster = self.cluster.clone();
+            let bridges = self.bridges.clone();
+            let sessions = self.sessions.clone();
+            let subscriptions = self.subscriptions.clone();
+            let health = self.health.clone();
+            let rules = self.rules.clone();
+            let journal = self.journal.clone();
+            let users_api = self.users_api.clone();
+            let redirect = self.redirect.clone();
 
             tokio::spawn(async move {
                 let service = service_fn(move |req| {
                     let metrics = metrics.clone();
-                    async move { handle_request(req, metrics).await }
+                    let cluster = cluster.clone();
+                    let bridges = bridges.clone();
+                    let sessions = sessions.clone();
+                    let subscriptions = subscriptions.clone();
+                    let health = health.clone();
+                    let rules = rules.clone();
+                    let journal = journal.clone();
+                    let users_api = users_api.clone();
+                    let redirect = redirect.clone();
+                    async move {
+                        handle_request(
+                            req,
+                            metrics,
+                            cluster,
+                            bridges,
+                            sessions,
+                            subscriptions,
+                            health,
+                            rules,
+                            journal,
+                            users_api,
+                            redirect,
+                        )
+                        .await
+                    }
                 });
 
                 if let Err(err) = http1::Builder::new().serve_connection(io, service).await {
@@ -48,12 +244,42 @@ impl MetricsServer {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_request(
     req: Request<hyper::body::Incoming>,
     metrics: Arc<Metrics>,
+    cluster: Option<Arc<ClusterManager>>,
+    bridges: Option<Arc<BridgeManager>>,
+    sessions: Option<Arc<SessionStore>>,
+    subscriptions: Option<Arc<SubscriptionStore>>,
+    health: Arc<Health>,
+    rules: Option<RulesApi>,
+    journal: Option<Arc<Journal>>,
+    users_api: Option<Arc<ClusterManager>>,
+    redirect: Option<RedirectControl>,
 ) -> Result<Response<Full<Bytes>>, Infallible> {
-    let response = match req.uri().path() {
+    let path = req.uri().path();
+    if path == "/journal" {
+        return Ok(journal_response(journal, req.uri().query()).await);
+    }
+    if let Some(client_id) = path.strip_prefix("/sessions/") {
+        return Ok(session_response(sessions.as_deref(), client_id));
+    }
+    if path == "/rules" || path.starts_with("/rules/") {
+        return Ok(rules_response(req, rules.as_ref()).await);
+    }
+    if path.starts_with("/users/") || path.starts_with("/roles/") {
+        return Ok(access_response(req, users_api.as_deref()).await);
+    }
+    if path == "/redirect" || path.starts_with("/redirect/") {
+        return Ok(redirect_response(req, redirect.as_ref()).await);
+    }
+
+    let response = match path {
         "/metrics" => {
+            if let Some(ref subscriptions) = subscriptions {
+                metrics.update_topic_subscribers(subscriptions);
+            }
             let encoder = TextEncoder::new();
             let metric_families = metrics.registry.gather();
             let mut buffer = Vec::new();
@@ -73,14 +299,96 @@ async fn handle_request(
                 }
             }
         }
-        "/health" | "/healthz" => Response::builder()
-            .status(StatusCode::OK)
-            .body(Full::new(Bytes::from("OK")))
-            .unwrap(),
-        "/ready" | "/readyz" => Response::builder()
-            .status(StatusCode::OK)
-            .body(Full::new(Bytes::from("OK")))
-            .unwrap(),
+        "/cluster" => match cluster {
+            Some(cluster) => match serde_json::to_vec(&cluster.cluster_view()) {
+                Ok(body) => Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", "application/json")
+                    .body(Full::new(Bytes::from(body)))
+                    .unwrap(),
+                Err(e) => {
+                    error!("Failed to encode cluster view: {}", e);
+                    Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Full::new(Bytes::from("Failed to encode cluster view")))
+                        .unwrap()
+                }
+            },
+            None => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Full::new(Bytes::from("Clustering disabled")))
+                .unwrap(),
+        },
+        "/bridges" => match bridges {
+            Some(bridges) => match serde_json::to_vec(&bridges.health()) {
+                Ok(body) => Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", "application/json")
+                    .body(Full::new(Bytes::from(body)))
+                    .unwrap(),
+                Err(e) => {
+                    error!("Failed to encode bridge status: {}", e);
+                    Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Full::new(Bytes::from("Failed to encode bridge status")))
+                        .unwrap()
+                }
+            },
+            None => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Full::new(Bytes::from("No bridges configured")))
+                .unwrap(),
+        },
+        "/clients" => match sessions {
+            Some(sessions) => {
+                let mut clients: Vec<_> = sessions
+                    .all_stats()
+                    .into_iter()
+                    .filter(|stats| stats.connected)
+                    .collect();
+                clients.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+                match serde_json::to_vec(&clients) {
+                    Ok(body) => Response::builder()
+                        .status(StatusCode::OK)
+                        .header("Content-Type", "application/json")
+                        .body(Full::new(Bytes::from(body)))
+                        .unwrap(),
+                    Err(e) => {
+                        error!("Failed to encode client stats: {}", e);
+                        Response::builder()
+                            .status(StatusCode::INTERNAL_SERVER_ERROR)
+                            .body(Full::new(Bytes::from("Failed to encode client stats")))
+                            .unwrap()
+                    }
+                }
+            }
+            None => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Full::new(Bytes::from("Not Found")))
+                .unwrap(),
+        },
+        "/subscriptions" => match subscriptions {
+            Some(subscriptions) => match serde_json::to_vec(&subscriptions.filter_stats()) {
+                Ok(body) => Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", "application/json")
+                    .body(Full::new(Bytes::from(body)))
+                    .unwrap(),
+                Err(e) => {
+                    error!("Failed to encode filter stats: {}", e);
+                    Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Full::new(Bytes::from("Failed to encode filter stats")))
+                        .unwrap()
+                }
+            },
+            None => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Full::new(Bytes::from("Not Found")))
+                .unwrap(),
+        },
+        "/health" | "/healthz" => health_response(&health.liveness()),
+        "/ready" | "/readyz" => health_response(&health.readiness()),
         _ => Response::builder()
             .status(StatusCode::NOT_FOUND)
             .body(Full::new(Bytes::from("Not Found")))
@@ -89,3 +397,399 @@ async fn handle_request(
 
     Ok(response)
 }
+
+/// A health report as JSON, with status 503 if it failed
+fn health_response(report: &HealthReport) -> Response<Full<Bytes>> {
+    let status = if report.ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    match serde_json::to_vec(report) {
+        Ok(body) => Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(body)))
+            .unwrap(),
+        Err(e) => {
+            error!("Failed to encode health report: {}", e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Full::new(Bytes::from("Failed to encode health report")))
+                .unwrap()
+        }
+    }
+}
+
+/// Snapshot of the session of a percent-encoded client ID as JSON
+fn session_response(sessions: Option<&SessionStore>, client_id: &str) -> Response<Full<Bytes>> {
+    let stats = match (sessions, percent_decode(client_id)) {
+        (Some(sessions), Some(client_id)) => sessions.stats(&client_id),
+        _ => None,
+    };
+    match stats.map(|stats| serde_json::to_vec(&stats)) {
+        Some(Ok(body)) => Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(body)))
+            .unwrap(),
+        Some(Err(e)) => {
+            error!("Failed to encode session state: {}", e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Full::new(Bytes::from("Failed to encode session state")))
+                .unwrap()
+        }
+        None => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Full::new(Bytes::from("Session not found")))
+            .unwrap(),
+    }
+}
+
+/// The last journaled messages matching the `filter` query parameter
+async fn journal_response(
+    journal: Option<Arc<Journal>>,
+    query: Option<&str>,
+) -> Response<Full<Bytes>> {
+    let Some(journal) = journal else {
+        return text_response(StatusCode::NOT_FOUND, "Message journal disabled");
+    };
+    let mut filter = "#".to_string();
+    let mut limit = DEFAULT_JOURNAL_LIMIT;
+    for param in query.unwrap_or_default().split('&') {
+        let (name, value) = param.split_once('=').unwrap_or((param, ""));
+        let value = percent_decode(&value.replace('+', " "));
+        match (name, value) {
+            ("filter", Some(value)) => filter = value,
+            ("limit", Some(value)) => match value.parse() {
+                Ok(value) => limit = value,
+                Err(_) => return text_response(StatusCode::BAD_REQUEST, "Invalid limit"),
+            },
+            ("filter" | "limit", None) => {
+                return text_response(StatusCode::BAD_REQUEST, "Invalid query parameter")
+            }
+            _ => {}
+        }
+    }
+    if crate::topic::validate_topic_filter(&filter).is_err() {
+        return text_response(StatusCode::BAD_REQUEST, "Invalid topic filter");
+    }
+
+    // The journal is read from disk
+    match tokio::task::spawn_blocking(move || journal.history(&filter, limit)).await {
+        Ok(Ok(entries)) => {
+            let entries: Vec<_> = entries.iter().map(|entry| entry.to_json()).collect();
+            json_response(StatusCode::OK, &entries)
+        }
+        Ok(Err(e)) => {
+            error!("Failed to read message journal: {}", e);
+            text_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read message journal",
+            )
+        }
+        Err(e) => {
+            error!("Failed to read message journal: {}", e);
+            text_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read message journal",
+            )
+        }
+    }
+}
+
+/// A response with a plain text body
+fn text_response(status: StatusCode, body: impl Into<Bytes>) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .body(Full::new(body.into()))
+        .unwrap()
+}
+
+/// A response with a JSON body
+fn json_response(status: StatusCode, value: &impl serde::Serialize) -> Response<Full<Bytes>> {
+    match serde_json::to_vec(value) {
+        Ok(body) => Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(body)))
+            .unwrap(),
+        Err(e) => {
+            error!("Failed to encode rule status: {}", e);
+            text_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to encode rule status",
+            )
+        }
+    }
+}
+
+/// List, add, replace and remove rules
+async fn rules_response(
+    req: Request<hyper::body::Incoming>,
+    rules: Option<&RulesApi>,
+) -> Response<Full<Bytes>> {
+    let Some(rules) = rules else {
+        return text_response(StatusCode::NOT_FOUND, "Not Found");
+    };
+    let id = match req.uri().path().strip_prefix("/rules/") {
+        Some(id) => match percent_decode(id) {
+            Some(id) => Some(id),
+            None => return text_response(StatusCode::BAD_REQUEST, "Invalid rule id"),
+        },
+        None => None,
+    };
+    let method = req.method().clone();
+    if method != Method::GET && !rules.writable {
+        return text_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Rule changes are disabled (metrics.rules_api)",
+        );
+    }
+
+    match (method, id) {
+        (Method::GET, None) => json_response(StatusCode::OK, &rules.engine.status()),
+        (Method::GET, Some(id)) => match rules.engine.rule_status(&id) {
+            Some(status) => json_response(StatusCode::OK, &status),
+            None => text_response(StatusCode::NOT_FOUND, "Rule not found"),
+        },
+        (Method::POST, None) => {
+            let body = match Limited::new(req.into_body(), MAX_RULE_BODY).collect().await {
+                Ok(body) => body.to_bytes(),
+                Err(e) => {
+                    return text_response(StatusCode::BAD_REQUEST, format!("Invalid body: {}", e))
+                }
+            };
+            let config: RuleConfig = match serde_json::from_slice(&body) {
+                Ok(config) => config,
+                Err(e) => {
+                    return text_response(StatusCode::BAD_REQUEST, format!("Invalid rule: {}", e))
+                }
+            };
+            let id = config.id.clone();
+            match rules.engine.add(config) {
+                Ok(replaced) => {
+                    info!(
+                        "Rule '{}' {} over HTTP",
+                        id,
+                        if replaced { "replaced" } else { "added" }
+                    );
+                    let status = if replaced {
+                        StatusCode::OK
+                    } else {
+                        StatusCode::CREATED
+                    };
+                    match rules.engine.rule_status(&id) {
+                        Some(rule) => json_response(status, &rule),
+                        None => text_response(status, ""),
+                    }
+                }
+                Err(e) => text_response(StatusCode::BAD_REQUEST, e.to_string()),
+            }
+        }
+        (Method::DELETE, Some(id)) => {
+            if rules.engine.remove(&id) {
+                info!("Rule '{}' removed over HTTP", id);
+                text_response(StatusCode::NO_CONTENT, "")
+            } else {
+                text_response(StatusCode::NOT_FOUND, "Rule not found")
+            }
+        }
+        _ => text_response(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed"),
+    }
+}
+
+/// Set and lift the redirect of new connections, and redirect connected
+/// clients
+async fn redirect_response(
+    req: Request<hyper::body::Incoming>,
+    control: Option<&RedirectControl>,
+) -> Response<Full<Bytes>> {
+    let Some(control) = control else {
+        return text_response(StatusCode::NOT_FOUND, "Not Found");
+    };
+    let client_id = match req.uri().path().strip_prefix("/redirect/") {
+        Some(client_id) => match percent_decode(client_id) {
+            Some(client_id) if !client_id.is_empty() => Some(client_id),
+            _ => return text_response(StatusCode::BAD_REQUEST, "Invalid client id"),
+        },
+        None => None,
+    };
+
+    match (req.method().clone(), client_id) {
+        (Method::GET, None) => json_response(StatusCode::OK, &control.connecting().get()),
+        (Method::DELETE, None) => {
+            control.connecting().set(None);
+            info!("Redirect lifted over HTTP");
+            text_response(StatusCode::NO_CONTENT, "")
+        }
+        (Method::PUT, None) => {
+            let body: RedirectBody = match redirect_body(req).await {
+                Ok(body) => body,
+                Err(response) => return response,
+            };
+            let redirected = if body.connected {
+                control.redirect_all(&body.redirect)
+            } else {
+                0
+            };
+            info!(
+                "Redirecting to {} over HTTP ({} connected clients redirected)",
+                body.redirect.server_reference, redirected
+            );
+            control.connecting().set(Some(body.redirect));
+            json_response(
+                StatusCode::OK,
+                &serde_json::json!({ "redirected": redirected }),
+            )
+        }
+        (Method::POST, Some(client_id)) => {
+            let redirect: RedirectConfig = match redirect_body(req).await {
+                Ok(redirect) => redirect,
+                Err(response) => return response,
+            };
+            if control.redirect_client(&client_id, &redirect) {
+                info!(
+                    "Client '{}' redirected to {} over HTTP",
+                    client_id, redirect.server_reference
+                );
+                text_response(StatusCode::NO_CONTENT, "")
+            } else {
+                text_response(StatusCode::NOT_FOUND, "Client not connected")
+            }
+        }
+        _ => text_response(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed"),
+    }
+}
+
+/// Parse the JSON body of a redirect request
+async fn redirect_body<T: serde::de::DeserializeOwned>(
+    req: Request<hyper::body::Incoming>,
+) -> Result<T, Response<Full<Bytes>>> {
+    let body = match Limited::new(req.into_body(), MAX_REDIRECT_BODY)
+        .collect()
+        .await
+    {
+        Ok(body) => body.to_bytes(),
+        Err(e) => {
+            return Err(text_response(
+                StatusCode::BAD_REQUEST,
+                format!("Invalid body: {}", e),
+            ))
+        }
+    };
+    serde_json::from_slice(&body)
+        .map_err(|e| text_response(StatusCode::BAD_REQUEST, format!("Invalid redirect: {}", e)))
+}
+
+/// Create, replace and remove users and ACL roles cluster-wide
+async fn access_response(
+    req: Request<hyper::body::Incoming>,
+    cluster: Option<&ClusterManager>,
+) -> Response<Full<Bytes>> {
+    let Some(cluster) = cluster else {
+        return text_response(StatusCode::NOT_FOUND, "Not Found");
+    };
+    let path = req.uri().path();
+    let (is_user, name) = match path.strip_prefix("/users/") {
+        Some(name) => (true, name),
+        None => (false, path.trim_start_matches("/roles/")),
+    };
+    let name = match percent_decode(name) {
+        Some(name) if !name.is_empty() => name,
+        _ => return text_response(StatusCode::BAD_REQUEST, "Invalid name"),
+    };
+
+    let command = match (req.method().clone(), is_user) {
+        (Method::PUT, _) => {
+            let body = match Limited::new(req.into_body(), MAX_ACCESS_BODY)
+                .collect()
+                .await
+            {
+                Ok(body) => body.to_bytes(),
+                Err(e) => {
+                    return text_response(StatusCode::BAD_REQUEST, format!("Invalid body: {}", e))
+                }
+            };
+            let command = if is_user {
+                user_command(name.clone(), &body).await
+            } else {
+                serde_json::from_slice::<RoleBody>(&body)
+                    .map(|role| {
+                        MetadataCommand::SetRole(StoredRole {
+                            name: name.clone(),
+                            publish: role.publish,
+                            subscribe: role.subscribe,
+                        })
+                    })
+                    .map_err(|e| format!("Invalid role: {}", e))
+            };
+            match command {
+                Ok(command) => command,
+                Err(e) => return text_response(StatusCode::BAD_REQUEST, e),
+            }
+        }
+        (Method::DELETE, true) => MetadataCommand::DeleteUser {
+            username: name.clone(),
+        },
+        (Method::DELETE, false) => MetadataCommand::DeleteRole { name: name.clone() },
+        _ => return text_response(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed"),
+    };
+
+    let kind = if is_user { "User" } else { "Role" };
+    match cluster.write_metadata(command).await {
+        Ok(()) => {
+            info!("{} '{}' changed over HTTP", kind, name);
+            text_response(StatusCode::NO_CONTENT, "")
+        }
+        Err(e) => text_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("Failed to commit change: {}", e),
+        ),
+    }
+}
+
+/// `SetUser` for a `PUT /users/<username>` body, hashing a plaintext
+/// password with argon2
+async fn user_command(username: String, body: &[u8]) -> Result<MetadataCommand, String> {
+    let user: UserBody =
+        serde_json::from_slice(body).map_err(|e| format!("Invalid user: {}", e))?;
+    let password_hash = match (user.password, user.password_hash) {
+        (Some(password), None) => tokio::task::spawn_blocking(move || {
+            let salt = SaltString::generate(&mut OsRng);
+            Argon2::default()
+                .hash_password(password.as_bytes(), &salt)
+                .map(|hash| hash.to_string())
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Failed to hash password: {}", e))?,
+        (None, Some(hash)) if hash.starts_with("$argon2") => hash,
+        (None, Some(_)) => return Err("password_hash must be in argon2 PHC format".to_string()),
+        _ => return Err("Exactly one of password and password_hash is required".to_string()),
+    };
+    Ok(MetadataCommand::SetUser(StoredUser {
+        username,
+        password_hash,
+        role: user.role,
+    }))
+}
+
+/// Decode `%XX` escapes in a URL path segment
+fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
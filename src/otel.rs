@@ -0,0 +1,112 @@
+//! OpenTelemetry Tracing
+//!
+//! The broker opens spans for the connect handshake (`mqtt.connect`),
+//! publish routing (`mqtt.publish`), persistence writes (`persistence.write`)
+//! and forwarding to bridges and cluster peers (`bridge.forward`,
+//! `cluster.forward`), all under [`SPAN_TARGET`]. They stay out of the logs;
+//! with the `otel` feature they are exported over OTLP.
+//!
+//! Trace context crosses the broker in MQTT v5 User Properties, following
+//! W3C Trace Context: a publish carrying `traceparent` (and `tracestate`)
+//! continues that trace. The properties are forwarded with the message
+//! unchanged, so subscribers, bridged brokers and cluster peers join the
+//! same trace.
+
+use crate::protocol::Properties;
+
+/// Target of the broker's trace spans
+pub const SPAN_TARGET: &str = "vibemq::otel";
+
+/// User Property carrying the W3C trace context
+pub const TRACEPARENT: &str = "traceparent";
+
+/// Continue the trace a publish carries, if any, in `span`
+pub fn continue_trace(span: &tracing::Span, properties: &Properties) {
+    #[cfg(feature = "otel")]
+    exporter::continue_trace(span, properties);
+    #[cfg(not(feature = "otel"))]
+    let _ = (span, properties);
+}
+
+#[cfg(feature = "otel")]
+pub use exporter::{init, OtelGuard};
+
+#[cfg(feature = "otel")]
+mod exporter {
+    use opentelemetry::propagation::{Extractor, TextMapPropagator};
+    use opentelemetry::trace::{TraceError, TracerProvider as _};
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::{Sampler, Tracer, TracerProvider};
+    use opentelemetry_sdk::{runtime, Resource};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    use super::TRACEPARENT;
+    use crate::config::OtelConfig;
+    use crate::protocol::Properties;
+
+    /// Flushes the exported spans and stops the exporter when dropped
+    pub struct OtelGuard {
+        provider: TracerProvider,
+    }
+
+    impl Drop for OtelGuard {
+        fn drop(&mut self) {
+            if let Err(e) = self.provider.shutdown() {
+                tracing::warn!("Failed to flush OpenTelemetry spans: {}", e);
+            }
+        }
+    }
+
+    /// Start exporting spans to the configured collector; returns the tracer
+    /// for a `tracing_opentelemetry` layer
+    pub fn init(config: &OtelConfig) -> Result<(Tracer, OtelGuard), TraceError> {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(config.endpoint.clone())
+            .build()?;
+        // Traces continued from a publisher keep its sampling decision
+        let sampler =
+            Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio)));
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_sampler(sampler)
+            .with_resource(Resource::new([KeyValue::new(
+                "service.name",
+                config.service_name.clone(),
+            )]))
+            .build();
+        let tracer = provider.tracer("vibemq");
+        Ok((tracer, OtelGuard { provider }))
+    }
+
+    /// Reads the trace context from MQTT v5 User Properties
+    struct UserProperties<'a>(&'a [(String, String)]);
+
+    impl Extractor for UserProperties<'_> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| v.as_str())
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            self.0.iter().map(|(k, _)| k.as_str()).collect()
+        }
+    }
+
+    pub(super) fn continue_trace(span: &tracing::Span, properties: &Properties) {
+        if !properties
+            .user_properties
+            .iter()
+            .any(|(k, _)| k.eq_ignore_ascii_case(TRACEPARENT))
+        {
+            return;
+        }
+        let context =
+            TraceContextPropagator::new().extract(&UserProperties(&properties.user_properties));
+        span.set_parent(context);
+    }
+}
@@ -5,8 +5,13 @@
 
 use async_trait::async_trait;
 
+use super::check::CheckReport;
 use super::error::Result;
-use super::models::{LoadedData, StoredRetainedMessage, StoredRole, StoredSession, StoredUser};
+use super::models::{
+    LoadedData, StoredPendingMessage, StoredRetainedMessage, StoredRole, StoredSession,
+    StoredSpillSegment, StoredUser,
+};
+use super::tenant::{strip_tenant, tenant_key};
 
 /// Persistence operation for batch writes
 #[derive(Debug, Clone)]
@@ -25,6 +30,15 @@ pub enum PersistenceOp {
     },
     /// Delete a session
     DeleteSession { client_id: String },
+    /// Append a message to the queue of a stored session
+    ///
+    /// Saves rewriting the whole session for every message queued while the
+    /// client is offline. Appended messages are folded into the session when
+    /// it is read, and dropped when the session is set or deleted.
+    AppendQueued {
+        client_id: String,
+        message: StoredPendingMessage,
+    },
     /// Set a user
     SetUser { username: String, user: StoredUser },
     /// Delete a user
@@ -33,6 +47,43 @@ pub enum PersistenceOp {
     SetRole { name: String, role: StoredRole },
     /// Delete a role
     DeleteRole { name: String },
+    /// Set a segment of a spilled session queue or bridge spool
+    SetSpill {
+        key: String,
+        segment: StoredSpillSegment,
+    },
+    /// Delete a segment of a spilled session queue or bridge spool
+    DeleteSpill { key: String },
+}
+
+impl PersistenceOp {
+    /// Whether this op may be shed under backpressure
+    ///
+    /// Retained updates are low priority: losing one only affects what a
+    /// future subscriber sees after a restart, while session state carries
+    /// inflight QoS 1/2 messages.
+    pub fn is_low_priority(&self) -> bool {
+        matches!(self, Self::SetRetained { .. } | Self::DeleteRetained { .. })
+    }
+
+    /// Short description for logs (kind and key, without the payload)
+    pub fn describe(&self) -> String {
+        match self {
+            Self::SetRetained { topic, .. } => format!("set retained '{}'", topic),
+            Self::DeleteRetained { topic } => format!("delete retained '{}'", topic),
+            Self::SetSession { client_id, .. } => format!("set session '{}'", client_id),
+            Self::DeleteSession { client_id } => format!("delete session '{}'", client_id),
+            Self::AppendQueued { client_id, .. } => {
+                format!("append to queue of session '{}'", client_id)
+            }
+            Self::SetUser { username, .. } => format!("set user '{}'", username),
+            Self::DeleteUser { username } => format!("delete user '{}'", username),
+            Self::SetRole { name, .. } => format!("set role '{}'", name),
+            Self::DeleteRole { name } => format!("delete role '{}'", name),
+            Self::SetSpill { key, .. } => format!("set spill '{}'", key),
+            Self::DeleteSpill { key } => format!("delete spill '{}'", key),
+        }
+    }
 }
 
 /// Storage backend trait for persistence
@@ -102,6 +153,16 @@ pub trait StorageBackend: Send + Sync {
     /// List all roles
     async fn list_roles(&self) -> Result<Vec<(String, StoredRole)>>;
 
+    // ========================================================================
+    // Spilled session queues and bridge spools
+    // ========================================================================
+
+    /// Get a spilled queue segment by key
+    async fn get_spill(&self, key: &str) -> Result<Option<StoredSpillSegment>>;
+
+    /// Keys of the spilled queue segments starting with `prefix`, in order
+    async fn list_spills(&self, prefix: &str) -> Result<Vec<String>>;
+
     // ========================================================================
     // Batch operations
     // ========================================================================
@@ -119,6 +180,103 @@ pub trait StorageBackend: Send + Sync {
     /// Close the backend (flush and release resources)
     async fn close(&self) -> Result<()>;
 
+    // ========================================================================
+    // Maintenance
+    // ========================================================================
+
+    /// Compact stored data, reclaiming space held by overwritten and deleted entries
+    ///
+    /// Backends without manual compaction control do nothing.
+    async fn compact(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// On-disk size in bytes of each partition (retained, sessions, users, roles)
+    ///
+    /// Backends that cannot report their size return an empty list.
+    async fn disk_usage(&self) -> Result<Vec<(&'static str, u64)>> {
+        Ok(Vec::new())
+    }
+
+    /// Verify that every stored entry can be decoded
+    ///
+    /// With `repair`, corrupt entries are removed (or rewritten, where the
+    /// backend can). The default implementation decodes everything via
+    /// `load_all` and can only report corruption as an error.
+    async fn check(&self, _repair: bool) -> Result<CheckReport> {
+        let data = self.load_all().await?;
+        Ok(CheckReport {
+            checked: data.retained.len()
+                + data.sessions.len()
+                + data.users.len()
+                + data.roles.len(),
+            ..Default::default()
+        })
+    }
+
+    /// Load all data at startup, verifying it in the same pass
+    ///
+    /// With `repair`, corrupt entries are removed as by `check`. Corrupt
+    /// entries are left out of the loaded data.
+    async fn load_checked(&self, _repair: bool) -> Result<(LoadedData, CheckReport)> {
+        let data = self.load_all().await?;
+        let report = CheckReport {
+            checked: data.retained.len()
+                + data.sessions.len()
+                + data.users.len()
+                + data.roles.len(),
+            ..Default::default()
+        };
+        Ok((data, report))
+    }
+
+    // ========================================================================
+    // Tenants
+    // ========================================================================
+
+    /// Export all data stored for a tenant, keyed by id without the tenant prefix
+    async fn export_tenant(&self, tenant: &str) -> Result<LoadedData> {
+        let data = self.load_all().await?;
+        Ok(LoadedData {
+            retained: strip_tenant(data.retained, tenant),
+            sessions: strip_tenant(data.sessions, tenant),
+            users: strip_tenant(data.users, tenant),
+            roles: strip_tenant(data.roles, tenant),
+        })
+    }
+
+    /// Delete all data stored for a tenant, returning the number of entries removed
+    async fn delete_tenant(&self, tenant: &str) -> Result<usize> {
+        let data = self.export_tenant(tenant).await?;
+        let key = |id: String| tenant_key(Some(tenant), &id);
+
+        let mut ops = Vec::new();
+        ops.extend(
+            data.retained
+                .into_iter()
+                .map(|(id, _)| PersistenceOp::DeleteRetained { topic: key(id) }),
+        );
+        ops.extend(
+            data.sessions
+                .into_iter()
+                .map(|(id, _)| PersistenceOp::DeleteSession { client_id: key(id) }),
+        );
+        ops.extend(
+            data.users
+                .into_iter()
+                .map(|(id, _)| PersistenceOp::DeleteUser { username: key(id) }),
+        );
+        ops.extend(
+            data.roles
+                .into_iter()
+                .map(|(id, _)| PersistenceOp::DeleteRole { name: key(id) }),
+        );
+
+        let count = ops.len();
+        self.batch_write(ops).await?;
+        Ok(count)
+    }
+
     /// Load all data at startup
     async fn load_all(&self) -> Result<LoadedData> {
         let retained = self.list_retained().await?;
@@ -0,0 +1,38 @@
+//! Store integrity checking.
+
+use std::fmt;
+
+/// A stored entry that failed verification
+#[derive(Debug, Clone)]
+pub struct CorruptEntry {
+    /// Table holding the entry (retained, sessions, users, roles or snapshot)
+    pub table: &'static str,
+    /// Entry key, lossily decoded if it is not valid UTF-8
+    pub key: String,
+    /// What is wrong with the entry
+    pub reason: String,
+}
+
+impl fmt::Display for CorruptEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{:?}: {}", self.table, self.key, self.reason)
+    }
+}
+
+/// Result of a store integrity check
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    /// Number of entries verified
+    pub checked: usize,
+    /// Entries that failed verification
+    pub corrupt: Vec<CorruptEntry>,
+    /// Whether the corrupt entries were repaired
+    pub repaired: bool,
+}
+
+impl CheckReport {
+    /// Whether no corruption was found
+    pub fn is_clean(&self) -> bool {
+        self.corrupt.is_empty()
+    }
+}
@@ -50,6 +50,20 @@ impl From<fjall::Error> for PersistenceError {
     }
 }
 
+#[cfg(feature = "postgres")]
+impl From<sqlx::Error> for PersistenceError {
+    fn from(err: sqlx::Error) -> Self {
+        Self::Storage(err.to_string())
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<sqlx::migrate::MigrateError> for PersistenceError {
+    fn from(err: sqlx::migrate::MigrateError) -> Self {
+        Self::Storage(format!("migration failed: {}", err))
+    }
+}
+
 impl From<bincode::error::EncodeError> for PersistenceError {
     fn from(err: bincode::error::EncodeError) -> Self {
         Self::Serialize(err.to_string())
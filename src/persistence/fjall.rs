@@ -1,15 +1,26 @@
 //! Fjall-based storage backend implementation.
 //!
 //! Uses fjall (an LSM-tree based embedded database) for local persistence.
+//!
+//! Messages appended to a stored session's queue live in a partition of
+//! their own, keyed by client and sequence number, until the session is
+//! next written.
 
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 
+use ahash::AHashMap;
 use async_trait::async_trait;
-use fjall::{Config, Keyspace, PartitionCreateOptions, PartitionHandle, PersistMode};
+use fjall::{Batch, Config, Keyspace, PartitionCreateOptions, PartitionHandle, PersistMode};
+use tracing::warn;
 
 use super::backend::{PersistenceOp, StorageBackend};
+use super::check::{CheckReport, CorruptEntry};
 use super::error::{PersistenceError, Result};
-use super::models::{StoredRetainedMessage, StoredRole, StoredSession, StoredUser};
+use super::models::{
+    LoadedData, StoredPendingMessage, StoredRetainedMessage, StoredRole, StoredSession,
+    StoredSpillSegment, StoredUser,
+};
 
 /// Fjall-based storage backend
 pub struct FjallBackend {
@@ -18,17 +29,76 @@ pub struct FjallBackend {
     sessions: PartitionHandle,
     users: PartitionHandle,
     roles: PartitionHandle,
+    spills: PartitionHandle,
+    /// Messages appended to stored sessions' queues
+    queued: PartitionHandle,
+    /// Sequence number of the next appended message
+    next_queued: AtomicU64,
+}
+
+/// Key prefix of the messages appended to a session's queue
+///
+/// Length-prefixed, so no client ID's prefix is a prefix of another's.
+fn queued_prefix(client_id: &str) -> Vec<u8> {
+    let mut key = Vec::with_capacity(2 + client_id.len() + 8);
+    key.extend_from_slice(&(client_id.len() as u16).to_be_bytes());
+    key.extend_from_slice(client_id.as_bytes());
+    key
+}
+
+/// Key of a message appended to a session's queue
+fn queued_key(client_id: &str, seq: u64) -> Vec<u8> {
+    let mut key = queued_prefix(client_id);
+    key.extend_from_slice(&seq.to_be_bytes());
+    key
+}
+
+/// Split a queued message key into client ID and sequence number
+fn split_queued_key(key: &[u8]) -> Option<(&str, u64)> {
+    let (len, rest) = key.split_first_chunk::<2>()?;
+    let len = u16::from_be_bytes(*len) as usize;
+    if rest.len() != len + 8 {
+        return None;
+    }
+    let (client_id, seq) = rest.split_at(len);
+    let seq = u64::from_be_bytes(seq.try_into().ok()?);
+    Some((std::str::from_utf8(client_id).ok()?, seq))
 }
 
 impl FjallBackend {
     /// Open a fjall backend at the given path
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let keyspace = Config::new(path).open()?;
+        Self::open_with_compaction_workers(path, 0)
+    }
+
+    /// Open a fjall backend with a fixed number of background compaction workers
+    ///
+    /// `0` keeps fjall's default (one worker per CPU core, up to 4).
+    pub fn open_with_compaction_workers<P: AsRef<Path>>(
+        path: P,
+        compaction_workers: usize,
+    ) -> Result<Self> {
+        let mut config = Config::new(path);
+        if compaction_workers > 0 {
+            config = config.compaction_workers(compaction_workers);
+        }
+        let keyspace = config.open()?;
 
         let retained = keyspace.open_partition("retained", PartitionCreateOptions::default())?;
         let sessions = keyspace.open_partition("sessions", PartitionCreateOptions::default())?;
         let users = keyspace.open_partition("users", PartitionCreateOptions::default())?;
         let roles = keyspace.open_partition("roles", PartitionCreateOptions::default())?;
+        let spills = keyspace.open_partition("spills", PartitionCreateOptions::default())?;
+        let queued = keyspace.open_partition("queued", PartitionCreateOptions::default())?;
+
+        // Keys group by client, so the last key need not hold the highest
+        let mut next_queued = 0;
+        for item in queued.iter() {
+            let (key, _) = item?;
+            if let Some((_, seq)) = split_queued_key(&key) {
+                next_queued = next_queued.max(seq + 1);
+            }
+        }
 
         Ok(Self {
             keyspace,
@@ -36,6 +106,9 @@ impl FjallBackend {
             sessions,
             users,
             roles,
+            spills,
+            queued,
+            next_queued: AtomicU64::new(next_queued),
         })
     }
 
@@ -50,6 +123,147 @@ impl FjallBackend {
             .map(|(value, _)| value)
             .map_err(PersistenceError::from)
     }
+
+    /// Decode a stored entry, rejecting invalid keys and trailing bytes
+    fn decode_entry<T: bincode::Decode<()>>(
+        key: &[u8],
+        value: &[u8],
+    ) -> std::result::Result<(String, T), String> {
+        let key = std::str::from_utf8(key).map_err(|e| format!("invalid key: {}", e))?;
+        let (decoded, read) = bincode::decode_from_slice(value, bincode::config::standard())
+            .map_err(|e| e.to_string())?;
+        if read != value.len() {
+            return Err(format!("{} trailing bytes", value.len() - read));
+        }
+        Ok((key.to_string(), decoded))
+    }
+
+    /// List all entries of a partition, skipping corrupt ones
+    fn list<T: bincode::Decode<()>>(
+        partition: &PartitionHandle,
+        table: &str,
+    ) -> Result<Vec<(String, T)>> {
+        let mut result = Vec::new();
+        for item in partition.iter() {
+            let (key, value) = item?;
+            match Self::decode_entry(&key, &value) {
+                Ok(entry) => result.push(entry),
+                Err(e) => warn!(
+                    "Skipping corrupt {} entry {:?}: {}",
+                    table,
+                    String::from_utf8_lossy(&key),
+                    e
+                ),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Decode every entry of a partition, reporting corrupt ones and
+    /// removing them if `repair`
+    fn load_partition<T: bincode::Decode<()>>(
+        partition: &PartitionHandle,
+        table: &'static str,
+        repair: bool,
+        report: &mut CheckReport,
+    ) -> Result<Vec<(String, T)>> {
+        let mut entries = Vec::new();
+        let mut corrupt_keys = Vec::new();
+        for item in partition.iter() {
+            let (key, value) = item?;
+            report.checked += 1;
+            match Self::decode_entry::<T>(&key, &value) {
+                Ok(entry) => entries.push(entry),
+                Err(reason) => {
+                    report.corrupt.push(CorruptEntry {
+                        table,
+                        key: String::from_utf8_lossy(&key).to_string(),
+                        reason,
+                    });
+                    corrupt_keys.push(key);
+                }
+            }
+        }
+
+        if repair {
+            for key in corrupt_keys {
+                partition.remove(key)?;
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Fold the messages appended to a session's queue into it
+    fn fold_queued(&self, client_id: &str, session: &mut StoredSession) -> Result<()> {
+        for item in self.queued.prefix(queued_prefix(client_id)) {
+            let (_, value) = item?;
+            session.append_queued(Self::deserialize(&value)?);
+        }
+        Ok(())
+    }
+
+    /// Fold all appended messages into their sessions, reporting corrupt
+    /// ones and removing them if `repair`
+    ///
+    /// Messages of sessions that are not stored are left alone; they are
+    /// removed when a session is next written for the client.
+    fn fold_all_queued(
+        &self,
+        sessions: &mut [(String, StoredSession)],
+        repair: bool,
+        report: &mut CheckReport,
+    ) -> Result<()> {
+        let mut appended: AHashMap<String, Vec<StoredPendingMessage>> = AHashMap::new();
+        let mut corrupt_keys = Vec::new();
+        for item in self.queued.iter() {
+            let (key, value) = item?;
+            report.checked += 1;
+            let decoded = split_queued_key(&key)
+                .ok_or_else(|| "invalid key".to_string())
+                .and_then(|(client_id, _)| {
+                    Self::decode_entry::<StoredPendingMessage>(client_id.as_bytes(), &value)
+                });
+            match decoded {
+                Ok((client_id, message)) => appended.entry(client_id).or_default().push(message),
+                Err(reason) => {
+                    report.corrupt.push(CorruptEntry {
+                        table: "queued",
+                        key: String::from_utf8_lossy(&key).to_string(),
+                        reason,
+                    });
+                    corrupt_keys.push(key);
+                }
+            }
+        }
+
+        if repair {
+            for key in corrupt_keys {
+                self.queued.remove(key)?;
+            }
+        }
+        for (client_id, session) in sessions {
+            for message in appended.remove(client_id.as_str()).into_iter().flatten() {
+                session.append_queued(message);
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop the messages appended to a session's queue, stored or earlier
+    /// in the batch, as the session is written whole
+    fn clear_queued(
+        &self,
+        batch: &mut Batch,
+        appended: &mut AHashMap<String, Vec<Vec<u8>>>,
+        client_id: &str,
+    ) -> Result<()> {
+        appended.remove(client_id);
+        for item in self.queued.prefix(queued_prefix(client_id)) {
+            let (key, _) = item?;
+            batch.remove(&self.queued, key);
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -77,14 +291,7 @@ impl StorageBackend for FjallBackend {
     }
 
     async fn list_retained(&self) -> Result<Vec<(String, StoredRetainedMessage)>> {
-        let mut result = Vec::new();
-        for item in self.retained.iter() {
-            let (key, value) = item?;
-            let topic = String::from_utf8_lossy(&key).to_string();
-            let message: StoredRetainedMessage = Self::deserialize(&value)?;
-            result.push((topic, message));
-        }
-        Ok(result)
+        Self::list(&self.retained, "retained")
     }
 
     // ========================================================================
@@ -92,32 +299,33 @@ impl StorageBackend for FjallBackend {
     // ========================================================================
 
     async fn get_session(&self, client_id: &str) -> Result<Option<StoredSession>> {
-        match self.sessions.get(client_id)? {
-            Some(bytes) => Ok(Some(Self::deserialize(&bytes)?)),
-            None => Ok(None),
-        }
+        let Some(bytes) = self.sessions.get(client_id)? else {
+            return Ok(None);
+        };
+        let mut session = Self::deserialize(&bytes)?;
+        self.fold_queued(client_id, &mut session)?;
+        Ok(Some(session))
     }
 
     async fn set_session(&self, client_id: &str, session: &StoredSession) -> Result<()> {
-        let bytes = Self::serialize(session)?;
-        self.sessions.insert(client_id, bytes)?;
-        Ok(())
+        self.batch_write(vec![PersistenceOp::SetSession {
+            client_id: client_id.to_string(),
+            session: session.clone(),
+        }])
+        .await
     }
 
     async fn delete_session(&self, client_id: &str) -> Result<()> {
-        self.sessions.remove(client_id)?;
-        Ok(())
+        self.batch_write(vec![PersistenceOp::DeleteSession {
+            client_id: client_id.to_string(),
+        }])
+        .await
     }
 
     async fn list_sessions(&self) -> Result<Vec<(String, StoredSession)>> {
-        let mut result = Vec::new();
-        for item in self.sessions.iter() {
-            let (key, value) = item?;
-            let client_id = String::from_utf8_lossy(&key).to_string();
-            let session: StoredSession = Self::deserialize(&value)?;
-            result.push((client_id, session));
-        }
-        Ok(result)
+        let mut sessions = Self::list(&self.sessions, "sessions")?;
+        self.fold_all_queued(&mut sessions, false, &mut CheckReport::default())?;
+        Ok(sessions)
     }
 
     // ========================================================================
@@ -143,14 +351,7 @@ impl StorageBackend for FjallBackend {
     }
 
     async fn list_users(&self) -> Result<Vec<(String, StoredUser)>> {
-        let mut result = Vec::new();
-        for item in self.users.iter() {
-            let (key, value) = item?;
-            let username = String::from_utf8_lossy(&key).to_string();
-            let user: StoredUser = Self::deserialize(&value)?;
-            result.push((username, user));
-        }
-        Ok(result)
+        Self::list(&self.users, "users")
     }
 
     // ========================================================================
@@ -176,14 +377,25 @@ impl StorageBackend for FjallBackend {
     }
 
     async fn list_roles(&self) -> Result<Vec<(String, StoredRole)>> {
-        let mut result = Vec::new();
-        for item in self.roles.iter() {
-            let (key, value) = item?;
-            let name = String::from_utf8_lossy(&key).to_string();
-            let role: StoredRole = Self::deserialize(&value)?;
-            result.push((name, role));
+        Self::list(&self.roles, "roles")
+    }
+
+    // ========================================================================
+    // Spilled session queues and bridge spools
+    // ========================================================================
+
+    async fn get_spill(&self, key: &str) -> Result<Option<StoredSpillSegment>> {
+        match self.spills.get(key)? {
+            Some(bytes) => Ok(Some(Self::deserialize(&bytes)?)),
+            None => Ok(None),
         }
-        Ok(result)
+    }
+
+    async fn list_spills(&self, prefix: &str) -> Result<Vec<String>> {
+        self.spills
+            .prefix(prefix)
+            .map(|item| Ok(String::from_utf8_lossy(&item?.0).into_owned()))
+            .collect()
     }
 
     // ========================================================================
@@ -192,6 +404,9 @@ impl StorageBackend for FjallBackend {
 
     async fn batch_write(&self, ops: Vec<PersistenceOp>) -> Result<()> {
         let mut batch = self.keyspace.batch();
+        // Appended messages not superseded by a later write of their session
+        // in this batch
+        let mut appended: AHashMap<String, Vec<Vec<u8>>> = AHashMap::new();
 
         for op in ops {
             match op {
@@ -203,12 +418,18 @@ impl StorageBackend for FjallBackend {
                     batch.remove(&self.retained, topic);
                 }
                 PersistenceOp::SetSession { client_id, session } => {
+                    self.clear_queued(&mut batch, &mut appended, &client_id)?;
                     let bytes = Self::serialize(&session)?;
                     batch.insert(&self.sessions, client_id, bytes);
                 }
                 PersistenceOp::DeleteSession { client_id } => {
+                    self.clear_queued(&mut batch, &mut appended, &client_id)?;
                     batch.remove(&self.sessions, client_id);
                 }
+                PersistenceOp::AppendQueued { client_id, message } => {
+                    let bytes = Self::serialize(&message)?;
+                    appended.entry(client_id).or_default().push(bytes);
+                }
                 PersistenceOp::SetUser { username, user } => {
                     let bytes = Self::serialize(&user)?;
                     batch.insert(&self.users, username, bytes);
@@ -223,6 +444,20 @@ impl StorageBackend for FjallBackend {
                 PersistenceOp::DeleteRole { name } => {
                     batch.remove(&self.roles, name);
                 }
+                PersistenceOp::SetSpill { key, segment } => {
+                    let bytes = Self::serialize(&segment)?;
+                    batch.insert(&self.spills, key, bytes);
+                }
+                PersistenceOp::DeleteSpill { key } => {
+                    batch.remove(&self.spills, key);
+                }
+            }
+        }
+
+        for (client_id, messages) in appended {
+            for bytes in messages {
+                let seq = self.next_queued.fetch_add(1, Ordering::Relaxed);
+                batch.insert(&self.queued, queued_key(&client_id, seq), bytes);
             }
         }
 
@@ -245,4 +480,63 @@ impl StorageBackend for FjallBackend {
         // fjall handles cleanup on drop
         Ok(())
     }
+
+    // ========================================================================
+    // Maintenance
+    // ========================================================================
+
+    async fn compact(&self) -> Result<()> {
+        let partitions = [
+            self.retained.clone(),
+            self.sessions.clone(),
+            self.users.clone(),
+            self.roles.clone(),
+            self.spills.clone(),
+            self.queued.clone(),
+        ];
+
+        // Major compaction rewrites every segment, keep it off the async workers
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            for partition in &partitions {
+                partition.major_compact()?;
+            }
+            Ok(())
+        })
+        .await
+        .map_err(|e| PersistenceError::Storage(format!("compaction task failed: {}", e)))?
+    }
+
+    async fn disk_usage(&self) -> Result<Vec<(&'static str, u64)>> {
+        Ok(vec![
+            ("retained", self.retained.disk_space()),
+            ("sessions", self.sessions.disk_space()),
+            ("users", self.users.disk_space()),
+            ("roles", self.roles.disk_space()),
+            ("spills", self.spills.disk_space()),
+            ("queued", self.queued.disk_space()),
+        ])
+    }
+
+    async fn check(&self, repair: bool) -> Result<CheckReport> {
+        Ok(self.load_checked(repair).await?.1)
+    }
+
+    async fn load_checked(&self, repair: bool) -> Result<(LoadedData, CheckReport)> {
+        let mut report = CheckReport::default();
+        let mut sessions = Self::load_partition(&self.sessions, "sessions", repair, &mut report)?;
+        self.fold_all_queued(&mut sessions, repair, &mut report)?;
+        let data = LoadedData {
+            retained: Self::load_partition(&self.retained, "retained", repair, &mut report)?,
+            sessions,
+            users: Self::load_partition(&self.users, "users", repair, &mut report)?,
+            roles: Self::load_partition(&self.roles, "roles", repair, &mut report)?,
+        };
+        Self::load_partition::<StoredSpillSegment>(&self.spills, "spills", repair, &mut report)?;
+
+        if repair && !report.is_clean() {
+            self.flush().await?;
+            report.repaired = true;
+        }
+        Ok((data, report))
+    }
 }
@@ -0,0 +1,688 @@
+//! In-memory storage backend with periodic snapshots.
+//!
+//! Keeps all data in memory and periodically writes a compressed snapshot of
+//! everything to a single file, plus a final one on close. The snapshot is
+//! loaded on open, so state survives restarts up to the last snapshot.
+//!
+//! Snapshots are written to a temporary file, fsynced and renamed over the
+//! previous one, so a crash mid-write never leaves a torn snapshot behind.
+//! Entries are encoded one by one into checksummed, lz4-compressed blocks, so
+//! a damaged block or an entry that no longer decodes loses only itself.
+//!
+//! Bridge spools are kept lz4-compressed outside the snapshot, so they do
+//! not outlive a restart. Spilling session queues would save no memory with
+//! this backend, so `limits.spill_threshold` is rejected with it.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bincode::{Decode, Encode};
+use parking_lot::{Mutex, RwLock};
+use tracing::{debug, error, warn};
+
+use super::backend::{PersistenceOp, StorageBackend};
+use super::check::{CheckReport, CorruptEntry};
+use super::error::{PersistenceError, Result};
+use super::models::{
+    LoadedData, StoredRetainedMessage, StoredRole, StoredSession, StoredSpillSegment, StoredUser,
+};
+
+/// Snapshot file name inside the data directory
+const SNAPSHOT_FILE: &str = "vibemq.snapshot";
+
+/// Extension a corrupt snapshot is renamed to
+const CORRUPT_EXTENSION: &str = "snapshot.corrupt";
+
+/// File header: magic bytes followed by the format version
+const SNAPSHOT_MAGIC: &[u8; 4] = b"VMQS";
+const SNAPSHOT_VERSION: u8 = 2;
+
+/// Uncompressed size at which a snapshot block is cut
+const SNAPSHOT_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Table names, by their number in snapshot records
+const TABLES: [&str; 4] = ["retained", "sessions", "users", "roles"];
+
+/// One stored entry in a snapshot block, its value encoded on its own
+#[derive(Encode, Decode)]
+struct SnapshotRecord {
+    table: u8,
+    key: String,
+    value: Vec<u8>,
+}
+
+/// All stored data
+#[derive(Debug, Default)]
+struct Tables {
+    retained: BTreeMap<String, StoredRetainedMessage>,
+    sessions: BTreeMap<String, StoredSession>,
+    users: BTreeMap<String, StoredUser>,
+    roles: BTreeMap<String, StoredRole>,
+}
+
+impl Tables {
+    fn len(&self) -> usize {
+        self.retained.len() + self.sessions.len() + self.users.len() + self.roles.len()
+    }
+
+    /// Encode every entry into snapshot blocks
+    fn encode_blocks(&self) -> Result<BlockWriter> {
+        let mut writer = BlockWriter::default();
+        writer.push_table(0, &self.retained)?;
+        writer.push_table(1, &self.sessions)?;
+        writer.push_table(2, &self.users)?;
+        writer.push_table(3, &self.roles)?;
+        writer.finish_block();
+        Ok(writer)
+    }
+
+    /// Insert a decoded snapshot record
+    fn insert_record(&mut self, record: SnapshotRecord) -> std::result::Result<(), String> {
+        let SnapshotRecord { table, key, value } = record;
+        match table {
+            0 => self.retained.insert(key, decode_value(&value)?).map(drop),
+            1 => self.sessions.insert(key, decode_value(&value)?).map(drop),
+            2 => self.users.insert(key, decode_value(&value)?).map(drop),
+            3 => self.roles.insert(key, decode_value(&value)?).map(drop),
+            table => return Err(format!("unknown table {}", table)),
+        };
+        Ok(())
+    }
+
+    /// Load the records of a decompressed block, reporting those that do not
+    /// decode
+    fn read_block(&mut self, mut data: &[u8], corrupt: &mut Vec<CorruptEntry>) {
+        while !data.is_empty() {
+            let Some((len, rest)) = split_u32(data) else {
+                corrupt.push(snapshot_corruption("truncated record length"));
+                return;
+            };
+            if rest.len() < len as usize {
+                corrupt.push(snapshot_corruption("truncated record"));
+                return;
+            }
+            let (record, rest) = rest.split_at(len as usize);
+            data = rest;
+
+            let record: SnapshotRecord = match decode_value(record) {
+                Ok(record) => record,
+                Err(reason) => {
+                    corrupt.push(snapshot_corruption(&reason));
+                    continue;
+                }
+            };
+            let table = TABLES
+                .get(record.table as usize)
+                .copied()
+                .unwrap_or("snapshot");
+            let key = record.key.clone();
+            if let Err(reason) = self.insert_record(record) {
+                corrupt.push(CorruptEntry { table, key, reason });
+            }
+        }
+    }
+
+    fn apply(&mut self, op: PersistenceOp) {
+        match op {
+            PersistenceOp::SetRetained { topic, message } => {
+                self.retained.insert(topic, message);
+            }
+            PersistenceOp::DeleteRetained { topic } => {
+                self.retained.remove(&topic);
+            }
+            PersistenceOp::SetSession { client_id, session } => {
+                self.sessions.insert(client_id, session);
+            }
+            PersistenceOp::DeleteSession { client_id } => {
+                self.sessions.remove(&client_id);
+            }
+            PersistenceOp::AppendQueued { client_id, message } => {
+                if let Some(session) = self.sessions.get_mut(&client_id) {
+                    session.append_queued(message);
+                }
+            }
+            PersistenceOp::SetUser { username, user } => {
+                self.users.insert(username, user);
+            }
+            PersistenceOp::DeleteUser { username } => {
+                self.users.remove(&username);
+            }
+            PersistenceOp::SetRole { name, role } => {
+                self.roles.insert(name, role);
+            }
+            PersistenceOp::DeleteRole { name } => {
+                self.roles.remove(&name);
+            }
+            // Kept outside the snapshot, see `MemoryBackend::batch_write`
+            PersistenceOp::SetSpill { .. } | PersistenceOp::DeleteSpill { .. } => {}
+        }
+    }
+}
+
+/// Snapshot body being built, block by block
+#[derive(Default)]
+struct BlockWriter {
+    /// Finished blocks, each framed with its length and checksum
+    out: Vec<u8>,
+    /// Records of the block being filled
+    block: Vec<u8>,
+    /// Size of all records before compression
+    uncompressed: usize,
+}
+
+impl BlockWriter {
+    fn push_table<T: Encode>(&mut self, table: u8, entries: &BTreeMap<String, T>) -> Result<()> {
+        let config = bincode::config::standard();
+        for (key, value) in entries {
+            let record = SnapshotRecord {
+                table,
+                key: key.clone(),
+                value: bincode::encode_to_vec(value, config)?,
+            };
+            let encoded = bincode::encode_to_vec(&record, config)?;
+            self.block
+                .extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+            self.block.extend_from_slice(&encoded);
+            if self.block.len() >= SNAPSHOT_BLOCK_SIZE {
+                self.finish_block();
+            }
+        }
+        Ok(())
+    }
+
+    fn finish_block(&mut self) {
+        if self.block.is_empty() {
+            return;
+        }
+        let compressed = lz4_flex::compress_prepend_size(&self.block);
+        self.uncompressed += self.block.len();
+        self.block.clear();
+        self.out
+            .extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        self.out
+            .extend_from_slice(&crc32c::crc32c(&compressed).to_le_bytes());
+        self.out.extend_from_slice(&compressed);
+    }
+}
+
+/// Decode a value that must take up all of `data`
+fn decode_value<T: Decode<()>>(data: &[u8]) -> std::result::Result<T, String> {
+    let (value, read) =
+        bincode::decode_from_slice(data, bincode::config::standard()).map_err(|e| e.to_string())?;
+    if read != data.len() {
+        return Err(format!("{} trailing bytes", data.len() - read));
+    }
+    Ok(value)
+}
+
+/// Split a little-endian `u32` off the front of `data`
+fn split_u32(data: &[u8]) -> Option<(u32, &[u8])> {
+    let (bytes, rest) = data.split_first_chunk::<4>()?;
+    Some((u32::from_le_bytes(*bytes), rest))
+}
+
+fn snapshot_corruption(reason: &str) -> CorruptEntry {
+    CorruptEntry {
+        table: "snapshot",
+        key: SNAPSHOT_FILE.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+/// State shared with the background snapshot task
+struct Shared {
+    tables: RwLock<Tables>,
+    /// Whether data changed since the last snapshot
+    dirty: AtomicBool,
+    /// Serializes snapshot writers
+    write_lock: Mutex<()>,
+    path: PathBuf,
+    /// Compressed spilled queue segments, not part of the snapshot
+    spills: RwLock<BTreeMap<String, Vec<u8>>>,
+    /// Entries of the loaded snapshot that could not be decoded
+    corrupt: Mutex<Vec<CorruptEntry>>,
+}
+
+impl Shared {
+    /// Write a snapshot if anything changed since the last one
+    fn snapshot(&self) -> Result<()> {
+        let _guard = self.write_lock.lock();
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        let result = self.write_snapshot();
+        if result.is_err() {
+            // Retry on the next round
+            self.dirty.store(true, Ordering::Release);
+        }
+        result
+    }
+
+    fn write_snapshot(&self) -> Result<()> {
+        let blocks = self.tables.read().encode_blocks()?;
+
+        let tmp_path = self.path.with_extension("tmp");
+        {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(SNAPSHOT_MAGIC)?;
+            file.write_all(&[SNAPSHOT_VERSION])?;
+            file.write_all(&blocks.out)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+
+        // Make the rename itself durable
+        if let Some(dir) = self.path.parent() {
+            File::open(dir)?.sync_all()?;
+        }
+
+        debug!(
+            "Wrote persistence snapshot ({} bytes, {} uncompressed)",
+            blocks.out.len() + SNAPSHOT_MAGIC.len() + 1,
+            blocks.uncompressed
+        );
+        Ok(())
+    }
+}
+
+/// Read and decode a snapshot file, along with the entries that could not be
+/// decoded
+///
+/// Fails only if the file is not a snapshot at all.
+fn read_snapshot(path: &Path) -> Result<(Tables, Vec<CorruptEntry>)> {
+    let data = fs::read(path)?;
+    let header_len = SNAPSHOT_MAGIC.len() + 1;
+    if data.len() < header_len || &data[..SNAPSHOT_MAGIC.len()] != SNAPSHOT_MAGIC {
+        return Err(PersistenceError::Corruption(format!(
+            "{} is not a snapshot file",
+            path.display()
+        )));
+    }
+    let version = data[SNAPSHOT_MAGIC.len()];
+    match version {
+        SNAPSHOT_VERSION => Ok(read_blocks(&data[header_len..])),
+        version => Err(PersistenceError::Corruption(format!(
+            "unsupported snapshot version {}",
+            version
+        ))),
+    }
+}
+
+/// Decode the blocks of a snapshot, skipping those that are damaged
+fn read_blocks(mut data: &[u8]) -> (Tables, Vec<CorruptEntry>) {
+    let mut tables = Tables::default();
+    let mut corrupt = Vec::new();
+    while !data.is_empty() {
+        let header = split_u32(data).and_then(|(len, rest)| {
+            let (crc, rest) = split_u32(rest)?;
+            Some((len as usize, crc, rest))
+        });
+        let Some((len, crc, rest)) = header.filter(|(len, _, rest)| rest.len() >= *len) else {
+            // Nothing after a bad length can be found again
+            corrupt.push(snapshot_corruption("truncated block"));
+            break;
+        };
+        let (block, rest) = rest.split_at(len);
+        data = rest;
+
+        if crc32c::crc32c(block) != crc {
+            corrupt.push(snapshot_corruption("block checksum mismatch"));
+            continue;
+        }
+        match lz4_flex::decompress_size_prepended(block) {
+            Ok(records) => tables.read_block(&records, &mut corrupt),
+            Err(e) => corrupt.push(snapshot_corruption(&format!("block decompression: {}", e))),
+        }
+    }
+    (tables, corrupt)
+}
+
+/// In-memory storage backend with periodic snapshots
+pub struct MemoryBackend {
+    shared: Arc<Shared>,
+}
+
+impl MemoryBackend {
+    /// Open a memory backend in the given data directory
+    ///
+    /// Loads the existing snapshot, if any, and starts a background task that
+    /// writes a new snapshot every `snapshot_interval` when data changed.
+    /// Entries of the snapshot that cannot be decoded are left out and
+    /// reported by `check`; a copy of the damaged snapshot is kept for
+    /// inspection. Must be called from within a Tokio runtime.
+    pub fn open<P: AsRef<Path>>(dir: P, snapshot_interval: Duration) -> Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let path = dir.join(SNAPSHOT_FILE);
+
+        let (tables, corrupt) = if path.exists() {
+            read_snapshot(&path)?
+        } else {
+            Default::default()
+        };
+        if !corrupt.is_empty() {
+            let copy = path.with_extension(CORRUPT_EXTENSION);
+            fs::copy(&path, &copy)?;
+            warn!(
+                "Persistence snapshot has {} corrupt entries, copied to {:?}",
+                corrupt.len(),
+                copy
+            );
+        }
+
+        let shared = Arc::new(Shared {
+            tables: RwLock::new(tables),
+            dirty: AtomicBool::new(false),
+            write_lock: Mutex::new(()),
+            path,
+            spills: RwLock::new(BTreeMap::new()),
+            corrupt: Mutex::new(corrupt),
+        });
+
+        if !snapshot_interval.is_zero() {
+            tokio::spawn(snapshot_loop(Arc::downgrade(&shared), snapshot_interval));
+        }
+
+        Ok(Self { shared })
+    }
+
+    /// Move a snapshot that cannot be loaded out of the way
+    ///
+    /// Renames it so the next `open` starts empty instead of failing and the
+    /// next snapshot does not overwrite the damaged data. Returns the new path,
+    /// or `None` if there was no snapshot.
+    pub fn quarantine_snapshot<P: AsRef<Path>>(dir: P) -> Result<Option<PathBuf>> {
+        let path = dir.as_ref().join(SNAPSHOT_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let target = path.with_extension(CORRUPT_EXTENSION);
+        fs::rename(&path, &target)?;
+        Ok(Some(target))
+    }
+
+    /// Write a snapshot now if anything changed since the last one
+    pub async fn snapshot(&self) -> Result<()> {
+        let shared = self.shared.clone();
+        tokio::task::spawn_blocking(move || shared.snapshot())
+            .await
+            .map_err(|e| PersistenceError::Storage(format!("snapshot task failed: {}", e)))?
+    }
+
+    fn modify(&self, op: PersistenceOp) {
+        self.shared.tables.write().apply(op);
+        self.shared.dirty.store(true, Ordering::Release);
+    }
+}
+
+/// Periodically snapshot until the backend is dropped
+async fn snapshot_loop(shared: Weak<Shared>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes immediately; nothing to write at startup
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let Some(shared) = shared.upgrade() else {
+            break;
+        };
+        match tokio::task::spawn_blocking(move || shared.snapshot()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("Failed to write persistence snapshot: {}", e),
+            Err(e) => error!("Persistence snapshot task failed: {}", e),
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MemoryBackend {
+    // ========================================================================
+    // Retained messages
+    // ========================================================================
+
+    async fn get_retained(&self, topic: &str) -> Result<Option<StoredRetainedMessage>> {
+        Ok(self.shared.tables.read().retained.get(topic).cloned())
+    }
+
+    async fn set_retained(&self, topic: &str, message: &StoredRetainedMessage) -> Result<()> {
+        self.modify(PersistenceOp::SetRetained {
+            topic: topic.to_string(),
+            message: message.clone(),
+        });
+        Ok(())
+    }
+
+    async fn delete_retained(&self, topic: &str) -> Result<()> {
+        self.modify(PersistenceOp::DeleteRetained {
+            topic: topic.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn list_retained(&self) -> Result<Vec<(String, StoredRetainedMessage)>> {
+        let tables = self.shared.tables.read();
+        Ok(tables
+            .retained
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect())
+    }
+
+    // ========================================================================
+    // Sessions
+    // ========================================================================
+
+    async fn get_session(&self, client_id: &str) -> Result<Option<StoredSession>> {
+        Ok(self.shared.tables.read().sessions.get(client_id).cloned())
+    }
+
+    async fn set_session(&self, client_id: &str, session: &StoredSession) -> Result<()> {
+        self.modify(PersistenceOp::SetSession {
+            client_id: client_id.to_string(),
+            session: session.clone(),
+        });
+        Ok(())
+    }
+
+    async fn delete_session(&self, client_id: &str) -> Result<()> {
+        self.modify(PersistenceOp::DeleteSession {
+            client_id: client_id.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<(String, StoredSession)>> {
+        let tables = self.shared.tables.read();
+        Ok(tables
+            .sessions
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect())
+    }
+
+    // ========================================================================
+    // Users
+    // ========================================================================
+
+    async fn get_user(&self, username: &str) -> Result<Option<StoredUser>> {
+        Ok(self.shared.tables.read().users.get(username).cloned())
+    }
+
+    async fn set_user(&self, username: &str, user: &StoredUser) -> Result<()> {
+        self.modify(PersistenceOp::SetUser {
+            username: username.to_string(),
+            user: user.clone(),
+        });
+        Ok(())
+    }
+
+    async fn delete_user(&self, username: &str) -> Result<()> {
+        self.modify(PersistenceOp::DeleteUser {
+            username: username.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn list_users(&self) -> Result<Vec<(String, StoredUser)>> {
+        let tables = self.shared.tables.read();
+        Ok(tables
+            .users
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect())
+    }
+
+    // ========================================================================
+    // Roles
+    // ========================================================================
+
+    async fn get_role(&self, name: &str) -> Result<Option<StoredRole>> {
+        Ok(self.shared.tables.read().roles.get(name).cloned())
+    }
+
+    async fn set_role(&self, name: &str, role: &StoredRole) -> Result<()> {
+        self.modify(PersistenceOp::SetRole {
+            name: name.to_string(),
+            role: role.clone(),
+        });
+        Ok(())
+    }
+
+    async fn delete_role(&self, name: &str) -> Result<()> {
+        self.modify(PersistenceOp::DeleteRole {
+            name: name.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn list_roles(&self) -> Result<Vec<(String, StoredRole)>> {
+        let tables = self.shared.tables.read();
+        Ok(tables
+            .roles
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect())
+    }
+
+    // ========================================================================
+    // Spilled session queues and bridge spools
+    // ========================================================================
+
+    async fn get_spill(&self, key: &str) -> Result<Option<StoredSpillSegment>> {
+        let Some(compressed) = self.shared.spills.read().get(key).cloned() else {
+            return Ok(None);
+        };
+        let encoded = lz4_flex::decompress_size_prepended(&compressed)
+            .map_err(|e| PersistenceError::Corruption(format!("spill decompression: {}", e)))?;
+        let (segment, _) = bincode::decode_from_slice(&encoded, bincode::config::standard())?;
+        Ok(Some(segment))
+    }
+
+    async fn list_spills(&self, prefix: &str) -> Result<Vec<String>> {
+        let spills = self.shared.spills.read();
+        Ok(spills
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, _)| key.clone())
+            .collect())
+    }
+
+    // ========================================================================
+    // Batch operations
+    // ========================================================================
+
+    async fn batch_write(&self, ops: Vec<PersistenceOp>) -> Result<()> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+
+        // Encode spilled segments before taking any lock
+        let mut spills = Vec::new();
+        let mut table_ops = Vec::with_capacity(ops.len());
+        for op in ops {
+            match op {
+                PersistenceOp::SetSpill { key, segment } => {
+                    let encoded = bincode::encode_to_vec(&segment, bincode::config::standard())?;
+                    spills.push((key, Some(lz4_flex::compress_prepend_size(&encoded))));
+                }
+                PersistenceOp::DeleteSpill { key } => spills.push((key, None)),
+                op => table_ops.push(op),
+            }
+        }
+
+        if !spills.is_empty() {
+            let mut stored = self.shared.spills.write();
+            for (key, data) in spills {
+                match data {
+                    Some(data) => stored.insert(key, data),
+                    None => stored.remove(&key),
+                };
+            }
+        }
+
+        if !table_ops.is_empty() {
+            let mut tables = self.shared.tables.write();
+            for op in table_ops {
+                tables.apply(op);
+            }
+            self.shared.dirty.store(true, Ordering::Release);
+        }
+        Ok(())
+    }
+
+    // ========================================================================
+    // Lifecycle
+    // ========================================================================
+
+    async fn flush(&self) -> Result<()> {
+        // Durability is bounded by the snapshot interval; writing a full
+        // snapshot on every flush would defeat the purpose of this backend
+        Ok(())
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.snapshot().await
+    }
+
+    // ========================================================================
+    // Maintenance
+    // ========================================================================
+
+    async fn disk_usage(&self) -> Result<Vec<(&'static str, u64)>> {
+        match fs::metadata(&self.shared.path) {
+            Ok(meta) => Ok(vec![("snapshot", meta.len())]),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Report the snapshot entries that could not be loaded; repairing
+    /// rewrites the snapshot without them
+    async fn check(&self, repair: bool) -> Result<CheckReport> {
+        let corrupt = self.shared.corrupt.lock().clone();
+        let mut report = CheckReport {
+            checked: self.shared.tables.read().len() + corrupt.len(),
+            corrupt,
+            repaired: false,
+        };
+
+        if repair && !report.is_clean() {
+            self.shared.dirty.store(true, Ordering::Release);
+            self.snapshot().await?;
+            self.shared.corrupt.lock().clear();
+            report.repaired = true;
+        }
+        Ok(report)
+    }
+
+    async fn load_checked(&self, repair: bool) -> Result<(LoadedData, CheckReport)> {
+        let report = self.check(repair).await?;
+        Ok((self.load_all().await?, report))
+    }
+}
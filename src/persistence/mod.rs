@@ -7,71 +7,379 @@
 //!
 //! Uses a trait-based design allowing different backends:
 //! - `FjallBackend` (default) - Local LSM-tree storage
-//! - Future: Redis, PostgreSQL, etc.
+//! - `MemoryBackend` - In-memory with periodic compressed snapshots
+//! - `PostgresBackend` (`postgres` feature) - PostgreSQL via sqlx
 
 mod backend;
+mod check;
 mod error;
 mod fjall;
+mod memory;
 mod models;
+#[cfg(feature = "postgres")]
+mod postgres;
+mod tenant;
 
 pub use backend::{PersistenceOp, StorageBackend};
+pub use check::{CheckReport, CorruptEntry};
 pub use error::{PersistenceError, Result};
 pub use fjall::FjallBackend;
+pub use memory::MemoryBackend;
 pub use models::{
     LoadedData, StoredInflightMessage, StoredPendingMessage, StoredProperties, StoredPublish,
-    StoredRetainedMessage, StoredRole, StoredSession, StoredSubscription, StoredUser,
-    StoredWillMessage,
+    StoredRetainedMessage, StoredRole, StoredSession, StoredSpill, StoredSpillSegment,
+    StoredSubscription, StoredUser, StoredWillMessage,
 };
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresBackend;
+pub use tenant::{split_tenant_key, tenant_key, TENANT_KEY_SEPARATOR};
 
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 
-use tokio::sync::mpsc;
-use tracing::{debug, error, info, warn};
+use tokio::sync::mpsc::error::{SendTimeoutError, TrySendError};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+use crate::config::{OverflowPolicy, SyncMode};
+use crate::hooks::Hooks;
+use crate::metrics::Metrics;
+
+/// Capacity of the writer channel
+const WRITER_CHANNEL_CAPACITY: usize = 10_000;
+
+/// Queue depth above which low-priority ops are shed (`OverflowPolicy::Shed`)
+const SHED_WATERMARK: usize = WRITER_CHANNEL_CAPACITY * 8 / 10;
+
+/// How often disk usage is sampled for metrics
+const DISK_USAGE_SAMPLE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Command for the background writer task
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+enum WriterCommand {
+    /// Queue an operation for the next batch
+    Op(PersistenceOp),
+    /// Commit everything queued so far, fsync, then acknowledge
+    Sync(oneshot::Sender<Result<()>>),
+}
+
+impl WriterCommand {
+    fn into_op(self) -> Option<PersistenceOp> {
+        match self {
+            Self::Op(op) => Some(op),
+            Self::Sync(_) => None,
+        }
+    }
+}
+
+/// Why a persistence operation was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// The writer queue was full
+    QueueFull,
+    /// Low-priority op shed to keep room for session state
+    Shed,
+    /// No queue capacity became available before the deadline
+    Timeout,
+}
+
+impl DropReason {
+    /// Label used in metrics and logs
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::QueueFull => "queue_full",
+            Self::Shed => "shed",
+            Self::Timeout => "timeout",
+        }
+    }
+}
+
+/// Metrics handle shared with background tasks (set after construction)
+type SharedMetrics = Arc<OnceLock<Arc<Metrics>>>;
 
 /// Persistence manager that handles background writes
 pub struct PersistenceManager {
     backend: Arc<dyn StorageBackend>,
-    tx: mpsc::Sender<PersistenceOp>,
+    tx: mpsc::Sender<WriterCommand>,
     shutdown_tx: mpsc::Sender<()>,
+    sync_mode: SyncMode,
+    overflow_policy: OverflowPolicy,
+    overflow_timeout: Duration,
+    metrics: SharedMetrics,
+    hooks: OnceLock<Arc<dyn Hooks>>,
+    /// Whether the writer's last commit or fsync failed
+    failing: Arc<AtomicBool>,
+    /// Whether retained updates were dropped, so the stored retained
+    /// messages may no longer match the broker's
+    retained_stale: AtomicBool,
 }
 
 impl PersistenceManager {
     /// Create a new persistence manager with the given backend
     ///
     /// This spawns a background task that batches and commits writes.
+    /// `sync_mode` controls when committed batches are fsynced.
     pub fn new(
         backend: Arc<dyn StorageBackend>,
         flush_interval: Duration,
         max_batch_size: usize,
+        sync_mode: SyncMode,
     ) -> Self {
-        let (tx, rx) = mpsc::channel(10_000);
+        let (tx, rx) = mpsc::channel(WRITER_CHANNEL_CAPACITY);
         let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
+        let metrics = SharedMetrics::default();
+        let failing = Arc::new(AtomicBool::new(false));
 
         // Spawn background writer task
-        let backend_clone = backend.clone();
-        tokio::spawn(Self::writer_loop(
-            backend_clone,
-            rx,
-            shutdown_rx,
-            flush_interval,
-            max_batch_size,
-        ));
+        let writer = Writer {
+            backend: backend.clone(),
+            metrics: metrics.clone(),
+            sync_mode,
+            dirty: false,
+            oldest: None,
+            failing: failing.clone(),
+        };
+        tokio::spawn(writer.run(rx, shutdown_rx, flush_interval, max_batch_size));
 
         Self {
             backend,
             tx,
             shutdown_tx,
+            sync_mode,
+            overflow_policy: OverflowPolicy::default(),
+            overflow_timeout: Duration::ZERO,
+            metrics,
+            hooks: OnceLock::new(),
+            failing,
+            retained_stale: AtomicBool::new(false),
         }
     }
 
+    /// Set what happens when the writer queue is full
+    ///
+    /// `timeout` bounds how long `write_async` callers wait for capacity
+    /// under the `block` and `shed` policies.
+    pub fn with_overflow_policy(mut self, policy: OverflowPolicy, timeout: Duration) -> Self {
+        self.overflow_policy = policy;
+        self.overflow_timeout = timeout;
+        self
+    }
+
+    /// Attach hooks notified when operations are dropped
+    ///
+    /// Only the first call has an effect.
+    pub fn set_hooks(&self, hooks: Arc<dyn Hooks>) {
+        let _ = self.hooks.set(hooks);
+    }
+
+    /// Attach metrics and start sampling persistence disk usage
+    ///
+    /// Only the first call has an effect.
+    pub fn set_metrics(&self, metrics: Arc<Metrics>) {
+        if self.metrics.set(metrics.clone()).is_err() {
+            return;
+        }
+
+        let backend = self.backend.clone();
+        let tx = self.tx.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(DISK_USAGE_SAMPLE_INTERVAL);
+            // Stop once the writer has exited
+            while !tx.is_closed() {
+                interval.tick().await;
+                match backend.disk_usage().await {
+                    Ok(usage) => {
+                        for (partition, bytes) in usage {
+                            metrics.update_persistence_disk_usage(partition, bytes);
+                        }
+                    }
+                    Err(e) => debug!("Failed to read persistence disk usage: {}", e),
+                }
+            }
+        });
+    }
+
+    /// Run a backend compaction every `interval` in the background
+    pub fn spawn_compaction(&self, interval: Duration) {
+        let backend = self.backend.clone();
+        let metrics = self.metrics.clone();
+        let tx = self.tx.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately; don't compact at startup
+            ticker.tick().await;
+            while !tx.is_closed() {
+                ticker.tick().await;
+                let start = Instant::now();
+                match backend.compact().await {
+                    Ok(()) => {
+                        debug!("Persistence compaction finished in {:?}", start.elapsed());
+                        if let Some(metrics) = metrics.get() {
+                            metrics.persistence_compactions_total.inc();
+                        }
+                    }
+                    Err(e) => error!("Persistence compaction failed: {}", e),
+                }
+            }
+        });
+    }
+
+    /// Whether the backend is committing writes; false while its last
+    /// commit or fsync failed
+    pub fn is_healthy(&self) -> bool {
+        !self.failing.load(Ordering::Relaxed)
+    }
+
+    /// Get the configured sync mode
+    pub fn sync_mode(&self) -> SyncMode {
+        self.sync_mode
+    }
+
+    /// Number of operations waiting in the writer channel
+    pub fn queue_depth(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
+
     /// Fire-and-forget write operation (non-blocking for hot path)
     ///
-    /// If the channel is full, the operation is dropped (backpressure).
+    /// If the channel is full, the operation is dropped. Under the `shed`
+    /// policy, low-priority operations are also dropped once the queue
+    /// passes its high-water mark.
     pub fn write(&self, op: PersistenceOp) {
-        if let Err(e) = self.tx.try_send(op) {
-            warn!("Persistence channel full, dropping operation: {:?}", e);
+        if self.should_shed(&op) {
+            self.dropped(op, DropReason::Shed);
+            return;
+        }
+
+        match self.tx.try_send(WriterCommand::Op(op)) {
+            Ok(()) => self.record_queue_depth(),
+            Err(TrySendError::Full(cmd)) => {
+                if let Some(op) = cmd.into_op() {
+                    self.dropped(op, DropReason::QueueFull);
+                }
+            }
+            Err(TrySendError::Closed(_)) => {
+                warn!("Persistence writer stopped, dropping operation");
+            }
+        }
+    }
+
+    /// Write operation that applies backpressure to the caller
+    ///
+    /// Under the `block` policy, waits up to the overflow timeout for queue
+    /// capacity before dropping. Under `shed`, only session, user and role
+    /// operations wait; retained updates are shed as in `write`. Under
+    /// `drop`, behaves like `write`.
+    pub async fn write_async(&self, op: PersistenceOp) {
+        let wait = match self.overflow_policy {
+            OverflowPolicy::Drop => false,
+            OverflowPolicy::Block => true,
+            OverflowPolicy::Shed => !op.is_low_priority(),
+        };
+        if !wait {
+            self.write(op);
+            return;
         }
+
+        match self
+            .tx
+            .send_timeout(WriterCommand::Op(op), self.overflow_timeout)
+            .await
+        {
+            Ok(()) => self.record_queue_depth(),
+            Err(SendTimeoutError::Timeout(cmd)) => {
+                if let Some(op) = cmd.into_op() {
+                    self.dropped(op, DropReason::Timeout);
+                }
+            }
+            Err(SendTimeoutError::Closed(_)) => {
+                warn!("Persistence writer stopped, dropping operation");
+            }
+        }
+    }
+
+    /// Publish the writer queue depth as it changes
+    fn record_queue_depth(&self) {
+        if let Some(metrics) = self.metrics.get() {
+            metrics
+                .persistence_queue_depth
+                .set(self.queue_depth() as i64);
+        }
+    }
+
+    fn should_shed(&self, op: &PersistenceOp) -> bool {
+        self.overflow_policy == OverflowPolicy::Shed
+            && op.is_low_priority()
+            && self.queue_depth() >= SHED_WATERMARK
+    }
+
+    /// Record a dropped operation in metrics and notify hooks
+    fn dropped(&self, op: PersistenceOp, reason: DropReason) {
+        warn!(
+            "Dropping persistence operation ({}): {}",
+            reason.as_str(),
+            op.describe()
+        );
+        if let Some(metrics) = self.metrics.get() {
+            metrics.persistence_op_dropped(reason.as_str());
+        }
+        if matches!(
+            op,
+            PersistenceOp::SetRetained { .. } | PersistenceOp::DeleteRetained { .. }
+        ) {
+            self.mark_retained_stale();
+        }
+        if let Some(hooks) = self.hooks.get() {
+            let hooks = hooks.clone();
+            tokio::spawn(async move {
+                hooks.on_persistence_op_dropped(&op, reason).await;
+            });
+        }
+    }
+
+    /// Durable write: queue `ops` and wait until they, and everything queued
+    /// before them, are committed and fsynced
+    ///
+    /// Unlike `write`, this waits for channel capacity instead of dropping.
+    pub async fn write_sync(&self, ops: Vec<PersistenceOp>) -> Result<()> {
+        let closed = || PersistenceError::Storage("persistence writer stopped".to_string());
+
+        for op in ops {
+            self.tx
+                .send(WriterCommand::Op(op))
+                .await
+                .map_err(|_| closed())?;
+        }
+
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.tx
+            .send(WriterCommand::Sync(ack_tx))
+            .await
+            .map_err(|_| closed())?;
+        self.record_queue_depth();
+        ack_rx.await.map_err(|_| closed())?
+    }
+
+    /// Mark the stored retained messages as out of date with the broker's
+    pub fn mark_retained_stale(&self) {
+        self.retained_stale.store(true, Ordering::Release);
+    }
+
+    /// Whether retained updates were dropped or marked stale since the last
+    /// call, so the stored retained messages need rewriting
+    pub fn take_retained_stale(&self) -> bool {
+        self.retained_stale.swap(false, Ordering::AcqRel)
+    }
+
+    /// Keys of the stored retained messages
+    ///
+    /// Operations queued before the call are committed first.
+    pub async fn retained_keys(&self) -> Result<Vec<String>> {
+        self.write_sync(Vec::new()).await?;
+        let retained = self.backend.list_retained().await?;
+        Ok(retained.into_iter().map(|(key, _)| key).collect())
     }
 
     /// Load all data at startup
@@ -79,6 +387,35 @@ impl PersistenceManager {
         self.backend.load_all().await
     }
 
+    /// Read a spilled queue segment
+    pub async fn get_spill(&self, key: &str) -> Result<Option<StoredSpillSegment>> {
+        self.backend.get_spill(key).await
+    }
+
+    /// Keys of the spilled queue segments starting with `prefix`, in order
+    pub async fn list_spills(&self, prefix: &str) -> Result<Vec<String>> {
+        self.backend.list_spills(prefix).await
+    }
+
+    /// Export all persisted data of a tenant
+    ///
+    /// Operations queued before the call are committed first.
+    pub async fn export_tenant(&self, tenant: &str) -> Result<LoadedData> {
+        self.write_sync(Vec::new()).await?;
+        self.backend.export_tenant(tenant).await
+    }
+
+    /// Delete all persisted data of a tenant, returning the number of entries removed
+    ///
+    /// Operations queued before the call are committed first, so they cannot
+    /// recreate the tenant's data afterwards.
+    pub async fn delete_tenant(&self, tenant: &str) -> Result<usize> {
+        self.write_sync(Vec::new()).await?;
+        let count = self.backend.delete_tenant(tenant).await?;
+        self.backend.flush().await?;
+        Ok(count)
+    }
+
     /// Gracefully shutdown the persistence manager
     ///
     /// This flushes all pending writes and closes the backend.
@@ -98,11 +435,26 @@ impl PersistenceManager {
         info!("Persistence manager shutdown complete");
         Ok(())
     }
+}
 
+/// Background writer state
+struct Writer {
+    backend: Arc<dyn StorageBackend>,
+    metrics: SharedMetrics,
+    sync_mode: SyncMode,
+    /// Whether batches were committed since the last fsync
+    dirty: bool,
+    /// When the oldest operation of the pending batch was queued
+    oldest: Option<Instant>,
+    /// Set while the backend fails to commit or fsync
+    failing: Arc<AtomicBool>,
+}
+
+impl Writer {
     /// Background writer loop that batches and commits writes
-    async fn writer_loop(
-        backend: Arc<dyn StorageBackend>,
-        mut rx: mpsc::Receiver<PersistenceOp>,
+    async fn run(
+        mut self,
+        mut rx: mpsc::Receiver<WriterCommand>,
         mut shutdown_rx: mpsc::Receiver<()>,
         flush_interval: Duration,
         max_batch_size: usize,
@@ -113,26 +465,38 @@ impl PersistenceManager {
         loop {
             tokio::select! {
                 // Receive operations
-                op = rx.recv() => {
-                    match op {
-                        Some(op) => {
+                cmd = rx.recv() => {
+                    self.record_queue_depth(&rx);
+                    match cmd {
+                        Some(WriterCommand::Op(op)) => {
+                            if batch.is_empty() {
+                                self.oldest = Some(Instant::now());
+                            }
                             batch.push(op);
 
                             // Flush immediately if batch is large
                             if batch.len() >= max_batch_size {
-                                if let Err(e) = backend.batch_write(std::mem::take(&mut batch)).await {
+                                let count = batch.len();
+                                if let Err(e) = self.commit(&mut batch).await {
                                     error!("Failed to write batch: {}", e);
                                 } else {
-                                    debug!("Flushed {} operations (max batch)", batch.capacity());
+                                    debug!("Flushed {} operations (max batch)", count);
                                 }
+                                self.record_queue_depth(&rx);
                             }
                         }
+                        Some(WriterCommand::Sync(ack)) => {
+                            let mut result = self.commit(&mut batch).await;
+                            // Nothing committed since the last fsync needs another
+                            if result.is_ok() && self.dirty {
+                                result = self.sync().await;
+                            }
+                            let _ = ack.send(result);
+                        }
                         None => {
                             // Channel closed, flush remaining and exit
-                            if !batch.is_empty() {
-                                if let Err(e) = backend.batch_write(std::mem::take(&mut batch)).await {
-                                    error!("Failed to write final batch: {}", e);
-                                }
+                            if let Err(e) = self.commit(&mut batch).await {
+                                error!("Failed to write final batch: {}", e);
                             }
                             break;
                         }
@@ -143,12 +507,20 @@ impl PersistenceManager {
                 _ = interval.tick() => {
                     if !batch.is_empty() {
                         let count = batch.len();
-                        if let Err(e) = backend.batch_write(std::mem::take(&mut batch)).await {
+                        if let Err(e) = self.commit(&mut batch).await {
                             error!("Failed to write batch: {}", e);
                         } else {
                             debug!("Flushed {} operations (interval)", count);
                         }
                     }
+
+                    // "never" leaves durability to the backend and the OS
+                    if self.dirty && self.sync_mode != SyncMode::Never {
+                        if let Err(e) = self.sync().await {
+                            error!("Failed to sync persistence backend: {}", e);
+                        }
+                    }
+                    self.record_queue_depth(&rx);
                 }
 
                 // Shutdown signal
@@ -157,7 +529,7 @@ impl PersistenceManager {
                     // Flush remaining operations
                     if !batch.is_empty() {
                         let count = batch.len();
-                        if let Err(e) = backend.batch_write(std::mem::take(&mut batch)).await {
+                        if let Err(e) = self.commit(&mut batch).await {
                             error!("Failed to write final batch on shutdown: {}", e);
                         } else {
                             info!("Flushed {} operations on shutdown", count);
@@ -170,6 +542,61 @@ impl PersistenceManager {
 
         info!("Persistence writer loop exited");
     }
+
+    /// Commit the pending batch (no-op when empty)
+    async fn commit(&mut self, batch: &mut Vec<PersistenceOp>) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let count = batch.len();
+        let oldest = self.oldest.take();
+        let start = Instant::now();
+        let result = self
+            .backend
+            .batch_write(std::mem::take(batch))
+            .instrument(info_span!(
+                target: crate::otel::SPAN_TARGET,
+                "persistence.write",
+                ops = count,
+            ))
+            .await;
+        self.failing.store(result.is_err(), Ordering::Relaxed);
+        result?;
+        self.dirty = true;
+
+        if let Some(metrics) = self.metrics.get() {
+            metrics.persistence_batch_committed(count, start.elapsed().as_secs_f64());
+            if let Some(oldest) = oldest {
+                metrics
+                    .persistence_write_latency
+                    .observe(oldest.elapsed().as_secs_f64());
+            }
+        }
+        Ok(())
+    }
+
+    /// Fsync committed batches
+    async fn sync(&mut self) -> Result<()> {
+        let start = Instant::now();
+        let result = self.backend.flush().await;
+        self.failing.store(result.is_err(), Ordering::Relaxed);
+        result?;
+        self.dirty = false;
+
+        if let Some(metrics) = self.metrics.get() {
+            metrics
+                .persistence_flush_latency
+                .observe(start.elapsed().as_secs_f64());
+        }
+        Ok(())
+    }
+
+    fn record_queue_depth(&self, rx: &mpsc::Receiver<WriterCommand>) {
+        if let Some(metrics) = self.metrics.get() {
+            metrics.persistence_queue_depth.set(rx.len() as i64);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -244,4 +671,376 @@ mod tests {
         let retained = backend.list_retained().await.unwrap();
         assert_eq!(retained.len(), 2);
     }
+
+    #[tokio::test]
+    async fn test_write_sync_commits_queued_ops() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let backend = Arc::new(FjallBackend::open(temp_dir.path()).unwrap());
+
+        // Long interval so only the sync can commit the queued op
+        let manager = PersistenceManager::new(
+            backend.clone(),
+            Duration::from_secs(3600),
+            100,
+            SyncMode::Always,
+        );
+
+        let message = StoredRetainedMessage {
+            topic: "queued".to_string(),
+            payload: vec![1],
+            qos: 1,
+            properties: StoredProperties::default(),
+            timestamp_secs: 0,
+        };
+        manager.write(PersistenceOp::SetRetained {
+            topic: "queued".to_string(),
+            message: message.clone(),
+        });
+        manager
+            .write_sync(vec![PersistenceOp::SetRetained {
+                topic: "synced".to_string(),
+                message,
+            }])
+            .await
+            .unwrap();
+
+        assert!(backend.get_retained("queued").await.unwrap().is_some());
+        assert!(backend.get_retained("synced").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_queue_depth_gauge_follows_writes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let backend = Arc::new(FjallBackend::open(temp_dir.path()).unwrap());
+        let manager =
+            PersistenceManager::new(backend, Duration::from_secs(3600), 100, SyncMode::Never);
+        let metrics = Arc::new(Metrics::new());
+        manager.set_metrics(metrics.clone());
+
+        // The writer has not run yet on this single-threaded runtime
+        for topic in ["a", "b"] {
+            manager.write(PersistenceOp::DeleteRetained {
+                topic: topic.to_string(),
+            });
+        }
+        assert_eq!(metrics.persistence_queue_depth.get(), 2);
+
+        manager.write_sync(Vec::new()).await.unwrap();
+        assert_eq!(metrics.persistence_queue_depth.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_memory_backend_snapshot_roundtrip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let backend = MemoryBackend::open(temp_dir.path(), Duration::ZERO).unwrap();
+
+        let message = StoredRetainedMessage {
+            topic: "kept".to_string(),
+            payload: vec![1, 2, 3],
+            qos: 1,
+            properties: StoredProperties::default(),
+            timestamp_secs: 0,
+        };
+        backend
+            .batch_write(vec![
+                PersistenceOp::SetRetained {
+                    topic: "kept".to_string(),
+                    message: message.clone(),
+                },
+                PersistenceOp::SetRetained {
+                    topic: "deleted".to_string(),
+                    message,
+                },
+                PersistenceOp::DeleteRetained {
+                    topic: "deleted".to_string(),
+                },
+            ])
+            .await
+            .unwrap();
+        backend.close().await.unwrap();
+        drop(backend);
+
+        let reopened = MemoryBackend::open(temp_dir.path(), Duration::ZERO).unwrap();
+        let loaded = reopened.load_all().await.unwrap();
+        assert_eq!(loaded.retained.len(), 1);
+        assert_eq!(loaded.retained[0].0, "kept");
+        assert_eq!(loaded.retained[0].1.payload, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_export_and_delete_tenant() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let backend = FjallBackend::open(temp_dir.path()).unwrap();
+
+        let message = StoredRetainedMessage {
+            topic: "sensors/temp".to_string(),
+            payload: vec![1],
+            qos: 0,
+            properties: StoredProperties::default(),
+            timestamp_secs: 0,
+        };
+        let ops = [Some("acme"), Some("other"), None]
+            .into_iter()
+            .map(|tenant| PersistenceOp::SetRetained {
+                topic: tenant_key(tenant, "sensors/temp"),
+                message: message.clone(),
+            })
+            .collect();
+        backend.batch_write(ops).await.unwrap();
+
+        let exported = backend.export_tenant("acme").await.unwrap();
+        assert_eq!(exported.retained.len(), 1);
+        assert_eq!(exported.retained[0].0, "sensors/temp");
+
+        assert_eq!(backend.delete_tenant("acme").await.unwrap(), 1);
+        assert!(backend
+            .export_tenant("acme")
+            .await
+            .unwrap()
+            .retained
+            .is_empty());
+
+        // Other tenants and untenanted data are untouched
+        let loaded = backend.load_all().await.unwrap();
+        assert_eq!(loaded.retained.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fjall_check_reports_and_repairs_corruption() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        {
+            let keyspace = ::fjall::Config::new(temp_dir.path()).open().unwrap();
+            let retained = keyspace
+                .open_partition("retained", ::fjall::PartitionCreateOptions::default())
+                .unwrap();
+            let valid = StoredRetainedMessage {
+                topic: "ok".to_string(),
+                payload: vec![1],
+                qos: 0,
+                properties: StoredProperties::default(),
+                timestamp_secs: 0,
+            };
+            let bytes = bincode::encode_to_vec(&valid, bincode::config::standard()).unwrap();
+            retained.insert("ok", &bytes).unwrap();
+            // Truncated value
+            retained.insert("torn", &bytes[..bytes.len() / 2]).unwrap();
+            keyspace.persist(::fjall::PersistMode::SyncAll).unwrap();
+        }
+
+        let backend = FjallBackend::open(temp_dir.path()).unwrap();
+
+        // Corrupt entries are skipped rather than failing the load
+        assert_eq!(backend.list_retained().await.unwrap().len(), 1);
+
+        let report = backend.check(false).await.unwrap();
+        assert_eq!(report.checked, 2);
+        assert_eq!(report.corrupt.len(), 1);
+        assert_eq!(report.corrupt[0].key, "torn");
+        assert!(!report.repaired);
+
+        let report = backend.check(true).await.unwrap();
+        assert!(report.repaired);
+        assert!(backend.check(false).await.unwrap().is_clean());
+        assert!(backend.get_retained("ok").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_memory_backend_quarantines_corrupt_snapshot() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::write(temp_dir.path().join("vibemq.snapshot"), b"VMQS\x01garbage").unwrap();
+
+        assert!(MemoryBackend::open(temp_dir.path(), Duration::ZERO).is_err());
+
+        let moved = MemoryBackend::quarantine_snapshot(temp_dir.path())
+            .unwrap()
+            .unwrap();
+        assert!(moved.exists());
+
+        let backend = MemoryBackend::open(temp_dir.path(), Duration::ZERO).unwrap();
+        assert!(backend.check(false).await.unwrap().is_clean());
+    }
+
+    #[tokio::test]
+    async fn test_memory_backend_skips_corrupt_snapshot_block() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let backend = MemoryBackend::open(temp_dir.path(), Duration::ZERO).unwrap();
+
+        // Incompressible payloads, so the snapshot spans several blocks
+        let mut seed = 1u32;
+        let ops = (0..40)
+            .map(|i| PersistenceOp::SetRetained {
+                topic: format!("t/{}", i),
+                message: StoredRetainedMessage {
+                    topic: format!("t/{}", i),
+                    payload: (0..4096)
+                        .map(|_| {
+                            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                            (seed >> 16) as u8
+                        })
+                        .collect(),
+                    qos: 0,
+                    properties: StoredProperties::default(),
+                    timestamp_secs: 0,
+                },
+            })
+            .collect();
+        backend.batch_write(ops).await.unwrap();
+        backend.close().await.unwrap();
+        drop(backend);
+
+        let path = temp_dir.path().join("vibemq.snapshot");
+        let mut data = std::fs::read(&path).unwrap();
+        let middle = data.len() / 2;
+        data[middle] ^= 0xff;
+        std::fs::write(&path, data).unwrap();
+
+        let reopened = MemoryBackend::open(temp_dir.path(), Duration::ZERO).unwrap();
+        let (loaded, report) = reopened.load_checked(false).await.unwrap();
+        assert!(!loaded.retained.is_empty());
+        assert!(loaded.retained.len() < 40);
+        assert_eq!(report.corrupt.len(), 1);
+        assert!(path.with_extension("snapshot.corrupt").exists());
+
+        let report = reopened.check(true).await.unwrap();
+        assert!(report.repaired);
+        reopened.close().await.unwrap();
+        drop(reopened);
+
+        let repaired = MemoryBackend::open(temp_dir.path(), Duration::ZERO).unwrap();
+        let (reloaded, report) = repaired.load_checked(false).await.unwrap();
+        assert!(report.is_clean());
+        assert_eq!(reloaded.retained.len(), loaded.retained.len());
+    }
+
+    #[tokio::test]
+    async fn test_fjall_appended_messages_fold_into_session() {
+        use crate::protocol::{ProtocolVersion, Publish, QoS};
+        use crate::session::{Session, SessionLimits};
+
+        let append_to = |client_id: &str, payload: &'static [u8]| PersistenceOp::AppendQueued {
+            client_id: tenant_key(Some("acme"), client_id),
+            message: StoredPendingMessage::queued_now(&Publish {
+                dup: false,
+                qos: QoS::AtLeastOnce,
+                retain: false,
+                topic: "sensors/temp".into(),
+                packet_id: None,
+                payload: bytes::Bytes::from_static(payload),
+                properties: Default::default(),
+            }),
+        };
+        let append = |payload| append_to("client", payload);
+        let payloads = |session: &StoredSession| -> Vec<Vec<u8>> {
+            session
+                .pending_messages
+                .iter()
+                .map(|pm| pm.publish.payload.clone())
+                .collect()
+        };
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let session = Session::new(
+            "client".into(),
+            ProtocolVersion::V5,
+            SessionLimits::default(),
+        );
+        let stored = StoredSession::from_session(&session);
+        {
+            let backend = FjallBackend::open(temp_dir.path()).unwrap();
+            backend
+                .batch_write(vec![
+                    PersistenceOp::SetSession {
+                        client_id: tenant_key(Some("acme"), "client"),
+                        session: stored.clone(),
+                    },
+                    append(b"1"),
+                ])
+                .await
+                .unwrap();
+            backend.batch_write(vec![append(b"2")]).await.unwrap();
+            // A client whose ID extends this one's keeps its own messages
+            backend
+                .batch_write(vec![append_to("client2", b"other")])
+                .await
+                .unwrap();
+            backend.close().await.unwrap();
+        }
+
+        // Appends after a restart stay behind the earlier ones
+        let backend = FjallBackend::open(temp_dir.path()).unwrap();
+        backend.batch_write(vec![append(b"3")]).await.unwrap();
+        let key = tenant_key(Some("acme"), "client");
+        let session = backend.get_session(&key).await.unwrap().unwrap();
+        assert_eq!(payloads(&session), [b"1", b"2", b"3"]);
+        let loaded = backend.load_all().await.unwrap();
+        assert_eq!(loaded.sessions.len(), 1);
+        assert_eq!(payloads(&loaded.sessions[0].1), [b"1", b"2", b"3"]);
+
+        // Writing the session whole drops its appended messages, except those
+        // appended after it
+        backend
+            .batch_write(vec![
+                PersistenceOp::SetSession {
+                    client_id: key.clone(),
+                    session: stored,
+                },
+                append(b"4"),
+            ])
+            .await
+            .unwrap();
+        let session = backend.get_session(&key).await.unwrap().unwrap();
+        assert_eq!(payloads(&session), [b"4"]);
+
+        backend
+            .batch_write(vec![PersistenceOp::DeleteSession {
+                client_id: key.clone(),
+            }])
+            .await
+            .unwrap();
+        backend.batch_write(vec![]).await.unwrap();
+        let report = backend.check(false).await.unwrap();
+        // Only the other client's message is left
+        assert_eq!(report.checked, 1);
+    }
+
+    #[test]
+    fn test_stored_session_into_session() {
+        use crate::protocol::{ProtocolVersion, Publish, QoS, SubscriptionOptions};
+        use crate::session::{Session, SessionLimits, SessionState};
+
+        let mut session = Session::new(
+            "client".into(),
+            ProtocolVersion::V5,
+            SessionLimits::default(),
+        );
+        session.session_expiry_interval = 3600;
+        session.add_subscription(
+            "sensors/#".to_string(),
+            SubscriptionOptions {
+                qos: QoS::AtLeastOnce,
+                ..Default::default()
+            },
+            Some(7),
+        );
+        session.queue_message(Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            topic: "sensors/temp".into(),
+            packet_id: None,
+            payload: bytes::Bytes::from_static(b"21"),
+            properties: Default::default(),
+        });
+
+        let restored = StoredSession::from_session(&session).into_session(SessionLimits::default());
+        assert_eq!(&*restored.client_id, "client");
+        assert_eq!(restored.state, SessionState::Disconnected);
+        assert!(!restored.clean_start);
+        assert_eq!(restored.session_expiry_interval, 3600);
+        let sub = &restored.subscriptions["sensors/#"];
+        assert_eq!(sub.options.qos, QoS::AtLeastOnce);
+        assert_eq!(sub.subscription_id, Some(7));
+        assert_eq!(restored.pending_messages.len(), 1);
+        assert_eq!(restored.pending_messages[0].publish.topic, "sensors/temp");
+    }
 }
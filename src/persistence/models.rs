@@ -6,14 +6,20 @@
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
 
-use crate::protocol::{Properties, Publish, QoS, RetainHandling, SubscriptionOptions};
+use super::error::{PersistenceError, Result};
+use crate::config::MessagePriority;
+use crate::protocol::{
+    Properties, ProtocolVersion, Publish, QoS, RetainHandling, SubscriptionOptions,
+};
 use crate::session::{
-    InflightMessage, PendingMessage, Qos2State, Session, SessionSubscription, WillMessage,
+    InflightMessage, PendingMessage, Qos2State, Session, SessionLimits, SessionState,
+    SessionSubscription, WillMessage,
 };
 
 /// Stored retained message
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, Encode, Decode, Serialize)]
 pub struct StoredRetainedMessage {
     pub topic: String,
     pub payload: Vec<u8>,
@@ -24,7 +30,7 @@ pub struct StoredRetainedMessage {
 }
 
 /// Stored session
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, Encode, Decode, Serialize)]
 pub struct StoredSession {
     pub client_id: String,
     pub protocol_version: u8,
@@ -32,6 +38,8 @@ pub struct StoredSession {
     pub keep_alive: u16,
     pub subscriptions: Vec<StoredSubscription>,
     pub pending_messages: Vec<StoredPendingMessage>,
+    /// Queued messages behind `pending_messages`, in queue order
+    pub spilled: Vec<StoredSpill>,
     pub inflight_outgoing: Vec<StoredInflightMessage>,
     pub inflight_incoming: Vec<StoredInflightMessage>,
     pub will: Option<StoredWillMessage>,
@@ -42,7 +50,7 @@ pub struct StoredSession {
 }
 
 /// Stored subscription
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, Encode, Decode, Serialize)]
 pub struct StoredSubscription {
     pub filter: String,
     pub qos: u8,
@@ -53,15 +61,30 @@ pub struct StoredSubscription {
 }
 
 /// Stored pending message
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, Encode, Decode, Serialize)]
 pub struct StoredPendingMessage {
     pub publish: StoredPublish,
     /// Unix timestamp when queued
     pub queued_at_secs: u64,
 }
 
+/// Pending messages of one session moved out of memory, in queue order
+#[derive(Debug, Clone, Encode, Decode, Serialize)]
+pub struct StoredSpillSegment {
+    pub messages: Vec<StoredPendingMessage>,
+}
+
+/// Part of a stored session's queue beyond the messages held in memory
+#[derive(Debug, Clone, Encode, Decode, Serialize)]
+pub enum StoredSpill {
+    /// A segment in the spill store
+    Segment { key: String, count: u32 },
+    /// Messages not yet written to the spill store
+    Messages(Vec<StoredPendingMessage>),
+}
+
 /// Stored inflight message
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, Encode, Decode, Serialize)]
 pub struct StoredInflightMessage {
     pub packet_id: u16,
     pub publish: StoredPublish,
@@ -73,7 +96,7 @@ pub struct StoredInflightMessage {
 }
 
 /// Stored publish message
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, Encode, Decode, Serialize)]
 pub struct StoredPublish {
     pub topic: String,
     pub payload: Vec<u8>,
@@ -85,7 +108,7 @@ pub struct StoredPublish {
 }
 
 /// Stored will message
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, Encode, Decode, Serialize)]
 pub struct StoredWillMessage {
     pub topic: String,
     pub payload: Vec<u8>,
@@ -95,7 +118,7 @@ pub struct StoredWillMessage {
 }
 
 /// Stored MQTT v5 properties (subset relevant for persistence)
-#[derive(Debug, Clone, Default, Encode, Decode)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Encode, Decode, Serialize)]
 pub struct StoredProperties {
     pub payload_format_indicator: Option<u8>,
     pub message_expiry_interval: Option<u32>,
@@ -106,7 +129,7 @@ pub struct StoredProperties {
 }
 
 /// Stored user for auth
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
 pub struct StoredUser {
     pub username: String,
     /// Always stored as argon2 hash
@@ -115,7 +138,7 @@ pub struct StoredUser {
 }
 
 /// Stored ACL role
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
 pub struct StoredRole {
     pub name: String,
     pub publish: Vec<String>,
@@ -126,6 +149,10 @@ pub struct StoredRole {
 // Conversion implementations
 // ============================================================================
 
+/// Header of an exported session: magic bytes followed by the format version
+const EXPORT_MAGIC: &[u8; 4] = b"VMQE";
+const EXPORT_VERSION: u8 = 2;
+
 fn now_unix_secs() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -166,6 +193,18 @@ fn unix_secs_to_instant(secs: u64) -> Instant {
     }
 }
 
+impl StoredProperties {
+    /// Whether no property is set
+    pub fn is_empty(&self) -> bool {
+        self.payload_format_indicator.is_none()
+            && self.message_expiry_interval.is_none()
+            && self.content_type.is_none()
+            && self.response_topic.is_none()
+            && self.correlation_data.is_none()
+            && self.user_properties.is_empty()
+    }
+}
+
 impl From<&Properties> for StoredProperties {
     fn from(props: &Properties) -> Self {
         Self {
@@ -196,7 +235,7 @@ impl From<StoredProperties> for Properties {
 impl From<&Publish> for StoredPublish {
     fn from(publish: &Publish) -> Self {
         Self {
-            topic: publish.topic.clone(),
+            topic: publish.topic.to_string(),
             payload: publish.payload.to_vec(),
             qos: publish.qos as u8,
             retain: publish.retain,
@@ -210,7 +249,7 @@ impl From<&Publish> for StoredPublish {
 impl From<StoredPublish> for Publish {
     fn from(stored: StoredPublish) -> Self {
         Self {
-            topic: stored.topic,
+            topic: stored.topic.into(),
             payload: bytes::Bytes::from(stored.payload),
             qos: QoS::from_u8(stored.qos).unwrap_or_default(),
             retain: stored.retain,
@@ -274,6 +313,16 @@ impl From<StoredSubscription> for SessionSubscription {
     }
 }
 
+impl StoredPendingMessage {
+    /// A message queued now
+    pub fn queued_now(publish: &Publish) -> Self {
+        Self {
+            publish: StoredPublish::from(publish),
+            queued_at_secs: now_unix_secs(),
+        }
+    }
+}
+
 impl From<&PendingMessage> for StoredPendingMessage {
     fn from(pm: &PendingMessage) -> Self {
         Self {
@@ -288,6 +337,8 @@ impl From<StoredPendingMessage> for PendingMessage {
         Self {
             publish: Publish::from(stored.publish),
             queued_at: unix_secs_to_instant(stored.queued_at_secs),
+            // Reclassified by the session it is restored into
+            priority: MessagePriority::Normal,
         }
     }
 }
@@ -346,6 +397,7 @@ impl StoredSession {
                 .iter()
                 .map(StoredPendingMessage::from)
                 .collect(),
+            spilled: session.stored_spilled(),
             inflight_outgoing: session
                 .inflight_outgoing
                 .values()
@@ -367,12 +419,102 @@ impl StoredSession {
             next_packet_id: 1, // Will be recalculated on restore
         }
     }
+
+    /// Rebuild a disconnected runtime Session from its stored form
+    pub fn into_session(self, limits: SessionLimits) -> Session {
+        let protocol_version =
+            ProtocolVersion::from_u8(self.protocol_version).unwrap_or(ProtocolVersion::V5);
+        let mut session = Session::new(self.client_id.into(), protocol_version, limits);
+
+        session.state = SessionState::Disconnected;
+        session.clean_start = false;
+        session.session_expiry_interval = self.session_expiry_interval;
+        session.keep_alive = self.keep_alive;
+        for sub in self.subscriptions {
+            let sub = SessionSubscription::from(sub);
+            session.subscriptions.insert(sub.filter.clone().into(), sub);
+        }
+        session.restore_pending(self.pending_messages.into_iter().map(PendingMessage::from));
+        session.restore_spilled(self.spilled);
+        session.inflight_outgoing = self
+            .inflight_outgoing
+            .into_iter()
+            .map(|im| (im.packet_id, InflightMessage::from(im)))
+            .collect();
+        session.inflight_incoming = self
+            .inflight_incoming
+            .into_iter()
+            .map(|im| (im.packet_id, Publish::from(im.publish)))
+            .collect();
+        session.will = self.will.map(WillMessage::from);
+        session.disconnected_at = Some(
+            self.disconnected_at_secs
+                .map(unix_secs_to_instant)
+                .unwrap_or_else(Instant::now),
+        );
+        session
+    }
+
+    /// Add a message queued after the session was stored, behind everything
+    /// queued before
+    pub fn append_queued(&mut self, message: StoredPendingMessage) {
+        match self.spilled.last_mut() {
+            None => self.pending_messages.push(message),
+            Some(StoredSpill::Messages(messages)) => messages.push(message),
+            Some(StoredSpill::Segment { .. }) => {
+                self.spilled.push(StoredSpill::Messages(vec![message]));
+            }
+        }
+    }
+
+    /// Keys of the spilled queue segments the session refers to
+    pub fn spill_keys(&self) -> impl Iterator<Item = &str> {
+        self.spilled.iter().filter_map(|part| match part {
+            StoredSpill::Segment { key, .. } => Some(key.as_str()),
+            StoredSpill::Messages(_) => None,
+        })
+    }
+
+    /// Encode the session for moving it to another broker
+    pub fn export(&self) -> Result<Vec<u8>> {
+        let mut bytes = EXPORT_MAGIC.to_vec();
+        bytes.push(EXPORT_VERSION);
+        bytes.extend(bincode::encode_to_vec(self, bincode::config::standard())?);
+        Ok(bytes)
+    }
+
+    /// Decode a session encoded by `export`
+    pub fn import(bytes: &[u8]) -> Result<Self> {
+        let header_len = EXPORT_MAGIC.len() + 1;
+        if bytes.len() < header_len || &bytes[..EXPORT_MAGIC.len()] != EXPORT_MAGIC {
+            return Err(PersistenceError::Deserialize(
+                "not an exported session".to_string(),
+            ));
+        }
+        let version = bytes[EXPORT_MAGIC.len()];
+        if version != EXPORT_VERSION {
+            return Err(PersistenceError::Deserialize(format!(
+                "unsupported session export version {}",
+                version
+            )));
+        }
+
+        let encoded = &bytes[header_len..];
+        let (session, read) = bincode::decode_from_slice(encoded, bincode::config::standard())?;
+        if read != encoded.len() {
+            return Err(PersistenceError::Deserialize(format!(
+                "{} trailing bytes after exported session",
+                encoded.len() - read
+            )));
+        }
+        Ok(session)
+    }
 }
 
 impl From<&crate::broker::RetainedMessage> for StoredRetainedMessage {
     fn from(rm: &crate::broker::RetainedMessage) -> Self {
         Self {
-            topic: rm.topic.clone(),
+            topic: rm.topic.to_string(),
             payload: rm.payload.to_vec(),
             qos: rm.qos as u8,
             properties: StoredProperties::from(&rm.properties),
@@ -381,8 +523,8 @@ impl From<&crate::broker::RetainedMessage> for StoredRetainedMessage {
     }
 }
 
-/// Data loaded from persistence at startup
-#[derive(Debug, Default)]
+/// Data loaded from persistence at startup (also used for tenant exports)
+#[derive(Debug, Default, Serialize)]
 pub struct LoadedData {
     pub retained: Vec<(String, StoredRetainedMessage)>,
     pub sessions: Vec<(String, StoredSession)>,
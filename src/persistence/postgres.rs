@@ -0,0 +1,492 @@
+//! PostgreSQL-based storage backend implementation.
+//!
+//! Stores broker state in a PostgreSQL database via sqlx. Each data kind lives
+//! in its own table keyed by a text id, with the value encoded using the same
+//! bincode format as the fjall backend. The schema is managed by the embedded
+//! migrations in `migrations/postgres`.
+//!
+//! Messages appended to a stored session's queue are rows of their own until
+//! the session is next written.
+
+use ahash::{AHashMap, AHashSet};
+use async_trait::async_trait;
+use futures_util::TryStreamExt;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::{Executor, Postgres, Transaction};
+
+use super::backend::{PersistenceOp, StorageBackend};
+use super::error::{PersistenceError, Result};
+use super::models::{
+    LoadedData, StoredPendingMessage, StoredRetainedMessage, StoredRole, StoredSession,
+    StoredSpillSegment, StoredUser,
+};
+
+const RETAINED_TABLE: &str = "vibemq_retained";
+const SESSIONS_TABLE: &str = "vibemq_sessions";
+const USERS_TABLE: &str = "vibemq_users";
+const ROLES_TABLE: &str = "vibemq_roles";
+const SPILLS_TABLE: &str = "vibemq_spills";
+const QUEUED_TABLE: &str = "vibemq_queued";
+
+/// PostgreSQL-based storage backend
+pub struct PostgresBackend {
+    pool: PgPool,
+}
+
+/// Pending changes for a single table, collapsed to the last write per key
+///
+/// A multi-row upsert cannot touch the same row twice, so a batch that sets
+/// and then deletes the same key must be reduced before it is sent.
+#[derive(Default)]
+struct TableChanges {
+    /// Key -> new value (`None` means delete)
+    changes: AHashMap<String, Option<Vec<u8>>>,
+}
+
+impl TableChanges {
+    fn set(&mut self, id: String, data: Vec<u8>) {
+        self.changes.insert(id, Some(data));
+    }
+
+    fn delete(&mut self, id: String) {
+        self.changes.insert(id, None);
+    }
+
+    /// Apply the changes inside the given transaction
+    async fn apply(self, tx: &mut Transaction<'static, Postgres>, table: &str) -> Result<()> {
+        if self.changes.is_empty() {
+            return Ok(());
+        }
+
+        let mut upsert_ids = Vec::new();
+        let mut upsert_data = Vec::new();
+        let mut delete_ids = Vec::new();
+        for (id, data) in self.changes {
+            match data {
+                Some(data) => {
+                    upsert_ids.push(id);
+                    upsert_data.push(data);
+                }
+                None => delete_ids.push(id),
+            }
+        }
+
+        if !upsert_ids.is_empty() {
+            let sql = format!(
+                "INSERT INTO {} (id, data) SELECT * FROM UNNEST($1::text[], $2::bytea[]) \
+                 ON CONFLICT (id) DO UPDATE SET data = EXCLUDED.data, updated_at = now()",
+                table
+            );
+            sqlx::query(&sql)
+                .bind(upsert_ids)
+                .bind(upsert_data)
+                .execute(&mut **tx)
+                .await?;
+        }
+
+        if !delete_ids.is_empty() {
+            let sql = format!("DELETE FROM {} WHERE id = ANY($1)", table);
+            sqlx::query(&sql)
+                .bind(delete_ids)
+                .execute(&mut **tx)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Pending changes to the messages appended to session queues
+#[derive(Default)]
+struct QueuedChanges {
+    /// Clients whose stored appended messages are deleted
+    cleared: AHashSet<String>,
+    /// Client ID and message of each new appended message, in order
+    appended: Vec<(String, Vec<u8>)>,
+}
+
+impl QueuedChanges {
+    /// Drop the appended messages of a session written whole
+    fn clear(&mut self, client_id: &str) {
+        self.appended.retain(|(id, _)| id != client_id);
+        self.cleared.insert(client_id.to_string());
+    }
+
+    fn append(&mut self, client_id: String, data: Vec<u8>) {
+        self.appended.push((client_id, data));
+    }
+
+    /// Apply the changes inside the given transaction
+    async fn apply(self, tx: &mut Transaction<'static, Postgres>) -> Result<()> {
+        if !self.cleared.is_empty() {
+            let sql = format!("DELETE FROM {} WHERE client_id = ANY($1)", QUEUED_TABLE);
+            sqlx::query(&sql)
+                .bind(self.cleared.into_iter().collect::<Vec<_>>())
+                .execute(&mut **tx)
+                .await?;
+        }
+
+        if !self.appended.is_empty() {
+            let (client_ids, data): (Vec<_>, Vec<_>) = self.appended.into_iter().unzip();
+            let sql = format!(
+                "INSERT INTO {} (client_id, data) SELECT client_id, data \
+                 FROM UNNEST($1::text[], $2::bytea[]) WITH ORDINALITY AS t(client_id, data, ord) \
+                 ORDER BY ord",
+                QUEUED_TABLE
+            );
+            sqlx::query(&sql)
+                .bind(client_ids)
+                .bind(data)
+                .execute(&mut **tx)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl PostgresBackend {
+    /// Connect to PostgreSQL and run pending schema migrations
+    pub async fn connect(url: &str, max_connections: u32) -> Result<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .connect(url)
+            .await?;
+
+        sqlx::migrate!("./migrations/postgres").run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    /// Serialize a value using bincode
+    fn serialize<T: bincode::Encode>(value: &T) -> Result<Vec<u8>> {
+        bincode::encode_to_vec(value, bincode::config::standard()).map_err(PersistenceError::from)
+    }
+
+    /// Deserialize a value using bincode
+    fn deserialize<T: bincode::Decode<()>>(bytes: &[u8]) -> Result<T> {
+        bincode::decode_from_slice(bytes, bincode::config::standard())
+            .map(|(value, _)| value)
+            .map_err(PersistenceError::from)
+    }
+
+    async fn get_value<T: bincode::Decode<()>>(&self, table: &str, id: &str) -> Result<Option<T>> {
+        let sql = format!("SELECT data FROM {} WHERE id = $1", table);
+        let row: Option<(Vec<u8>,)> = sqlx::query_as(&sql)
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        row.map(|(data,)| Self::deserialize(&data)).transpose()
+    }
+
+    async fn set_value<T: bincode::Encode>(&self, table: &str, id: &str, value: &T) -> Result<()> {
+        let data = Self::serialize(value)?;
+        let sql = format!(
+            "INSERT INTO {} (id, data) VALUES ($1, $2) \
+             ON CONFLICT (id) DO UPDATE SET data = EXCLUDED.data, updated_at = now()",
+            table
+        );
+        sqlx::query(&sql)
+            .bind(id)
+            .bind(data)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_value(&self, table: &str, id: &str) -> Result<()> {
+        let sql = format!("DELETE FROM {} WHERE id = $1", table);
+        sqlx::query(&sql).bind(id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    /// Stream all rows of a table, decoding each row as it arrives
+    async fn stream_table<'c, E, T>(executor: E, table: &str) -> Result<Vec<(String, T)>>
+    where
+        E: Executor<'c, Database = Postgres>,
+        T: bincode::Decode<()> + Send,
+    {
+        let sql = format!("SELECT id, data FROM {}", table);
+        let mut rows = sqlx::query_as::<_, (String, Vec<u8>)>(&sql).fetch(executor);
+
+        let mut result = Vec::new();
+        while let Some((id, data)) = rows.try_next().await? {
+            result.push((id, Self::deserialize(&data)?));
+        }
+        Ok(result)
+    }
+
+    /// Fold the messages appended to the sessions' queues into them
+    async fn fold_queued<'c, E>(executor: E, sessions: &mut [(String, StoredSession)]) -> Result<()>
+    where
+        E: Executor<'c, Database = Postgres>,
+    {
+        let sql = format!("SELECT client_id, data FROM {} ORDER BY seq", QUEUED_TABLE);
+        let mut rows = sqlx::query_as::<_, (String, Vec<u8>)>(&sql).fetch(executor);
+
+        let mut appended: AHashMap<String, Vec<StoredPendingMessage>> = AHashMap::new();
+        while let Some((client_id, data)) = rows.try_next().await? {
+            appended
+                .entry(client_id)
+                .or_default()
+                .push(Self::deserialize(&data)?);
+        }
+        for (client_id, session) in sessions {
+            for message in appended.remove(client_id.as_str()).into_iter().flatten() {
+                session.append_queued(message);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StorageBackend for PostgresBackend {
+    // ========================================================================
+    // Retained messages
+    // ========================================================================
+
+    async fn get_retained(&self, topic: &str) -> Result<Option<StoredRetainedMessage>> {
+        self.get_value(RETAINED_TABLE, topic).await
+    }
+
+    async fn set_retained(&self, topic: &str, message: &StoredRetainedMessage) -> Result<()> {
+        self.set_value(RETAINED_TABLE, topic, message).await
+    }
+
+    async fn delete_retained(&self, topic: &str) -> Result<()> {
+        self.delete_value(RETAINED_TABLE, topic).await
+    }
+
+    async fn list_retained(&self) -> Result<Vec<(String, StoredRetainedMessage)>> {
+        Self::stream_table(&self.pool, RETAINED_TABLE).await
+    }
+
+    // ========================================================================
+    // Sessions
+    // ========================================================================
+
+    async fn get_session(&self, client_id: &str) -> Result<Option<StoredSession>> {
+        let Some(mut session) = self
+            .get_value::<StoredSession>(SESSIONS_TABLE, client_id)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let sql = format!(
+            "SELECT data FROM {} WHERE client_id = $1 ORDER BY seq",
+            QUEUED_TABLE
+        );
+        let rows: Vec<(Vec<u8>,)> = sqlx::query_as(&sql)
+            .bind(client_id)
+            .fetch_all(&self.pool)
+            .await?;
+        for (data,) in rows {
+            session.append_queued(Self::deserialize(&data)?);
+        }
+        Ok(Some(session))
+    }
+
+    async fn set_session(&self, client_id: &str, session: &StoredSession) -> Result<()> {
+        self.batch_write(vec![PersistenceOp::SetSession {
+            client_id: client_id.to_string(),
+            session: session.clone(),
+        }])
+        .await
+    }
+
+    async fn delete_session(&self, client_id: &str) -> Result<()> {
+        self.batch_write(vec![PersistenceOp::DeleteSession {
+            client_id: client_id.to_string(),
+        }])
+        .await
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<(String, StoredSession)>> {
+        let mut tx = self.pool.begin().await?;
+        let mut sessions = Self::stream_table(&mut *tx, SESSIONS_TABLE).await?;
+        Self::fold_queued(&mut *tx, &mut sessions).await?;
+        tx.commit().await?;
+        Ok(sessions)
+    }
+
+    // ========================================================================
+    // Users
+    // ========================================================================
+
+    async fn get_user(&self, username: &str) -> Result<Option<StoredUser>> {
+        self.get_value(USERS_TABLE, username).await
+    }
+
+    async fn set_user(&self, username: &str, user: &StoredUser) -> Result<()> {
+        self.set_value(USERS_TABLE, username, user).await
+    }
+
+    async fn delete_user(&self, username: &str) -> Result<()> {
+        self.delete_value(USERS_TABLE, username).await
+    }
+
+    async fn list_users(&self) -> Result<Vec<(String, StoredUser)>> {
+        Self::stream_table(&self.pool, USERS_TABLE).await
+    }
+
+    // ========================================================================
+    // Roles
+    // ========================================================================
+
+    async fn get_role(&self, name: &str) -> Result<Option<StoredRole>> {
+        self.get_value(ROLES_TABLE, name).await
+    }
+
+    async fn set_role(&self, name: &str, role: &StoredRole) -> Result<()> {
+        self.set_value(ROLES_TABLE, name, role).await
+    }
+
+    async fn delete_role(&self, name: &str) -> Result<()> {
+        self.delete_value(ROLES_TABLE, name).await
+    }
+
+    async fn list_roles(&self) -> Result<Vec<(String, StoredRole)>> {
+        Self::stream_table(&self.pool, ROLES_TABLE).await
+    }
+
+    // ========================================================================
+    // Spilled session queues and bridge spools
+    // ========================================================================
+
+    async fn get_spill(&self, key: &str) -> Result<Option<StoredSpillSegment>> {
+        self.get_value(SPILLS_TABLE, key).await
+    }
+
+    async fn list_spills(&self, prefix: &str) -> Result<Vec<String>> {
+        let sql = format!(
+            "SELECT id FROM {} WHERE left(id, char_length($1)) = $1 ORDER BY id",
+            SPILLS_TABLE
+        );
+        let rows: Vec<(String,)> = sqlx::query_as(&sql)
+            .bind(prefix)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(|(id,)| id).collect())
+    }
+
+    // ========================================================================
+    // Batch operations
+    // ========================================================================
+
+    async fn batch_write(&self, ops: Vec<PersistenceOp>) -> Result<()> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+
+        let mut retained = TableChanges::default();
+        let mut sessions = TableChanges::default();
+        let mut users = TableChanges::default();
+        let mut roles = TableChanges::default();
+        let mut spills = TableChanges::default();
+        let mut queued = QueuedChanges::default();
+
+        for op in ops {
+            match op {
+                PersistenceOp::SetRetained { topic, message } => {
+                    retained.set(topic, Self::serialize(&message)?);
+                }
+                PersistenceOp::DeleteRetained { topic } => retained.delete(topic),
+                PersistenceOp::SetSession { client_id, session } => {
+                    queued.clear(&client_id);
+                    sessions.set(client_id, Self::serialize(&session)?);
+                }
+                PersistenceOp::DeleteSession { client_id } => {
+                    queued.clear(&client_id);
+                    sessions.delete(client_id);
+                }
+                PersistenceOp::AppendQueued { client_id, message } => {
+                    queued.append(client_id, Self::serialize(&message)?);
+                }
+                PersistenceOp::SetUser { username, user } => {
+                    users.set(username, Self::serialize(&user)?);
+                }
+                PersistenceOp::DeleteUser { username } => users.delete(username),
+                PersistenceOp::SetRole { name, role } => {
+                    roles.set(name, Self::serialize(&role)?);
+                }
+                PersistenceOp::DeleteRole { name } => roles.delete(name),
+                PersistenceOp::SetSpill { key, segment } => {
+                    spills.set(key, Self::serialize(&segment)?);
+                }
+                PersistenceOp::DeleteSpill { key } => spills.delete(key),
+            }
+        }
+
+        let mut tx = self.pool.begin().await?;
+        retained.apply(&mut tx, RETAINED_TABLE).await?;
+        sessions.apply(&mut tx, SESSIONS_TABLE).await?;
+        users.apply(&mut tx, USERS_TABLE).await?;
+        roles.apply(&mut tx, ROLES_TABLE).await?;
+        spills.apply(&mut tx, SPILLS_TABLE).await?;
+        queued.apply(&mut tx).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    // ========================================================================
+    // Lifecycle
+    // ========================================================================
+
+    async fn flush(&self) -> Result<()> {
+        // Every write is committed before returning; nothing is buffered here
+        Ok(())
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.pool.close().await;
+        Ok(())
+    }
+
+    // ========================================================================
+    // Maintenance
+    // ========================================================================
+
+    async fn disk_usage(&self) -> Result<Vec<(&'static str, u64)>> {
+        let mut usage = Vec::with_capacity(6);
+        for (partition, table) in [
+            ("retained", RETAINED_TABLE),
+            ("sessions", SESSIONS_TABLE),
+            ("users", USERS_TABLE),
+            ("roles", ROLES_TABLE),
+            ("spills", SPILLS_TABLE),
+            ("queued", QUEUED_TABLE),
+        ] {
+            let (bytes,): (i64,) = sqlx::query_as("SELECT pg_total_relation_size($1::regclass)")
+                .bind(table)
+                .fetch_one(&self.pool)
+                .await?;
+            usage.push((partition, bytes.max(0) as u64));
+        }
+        Ok(usage)
+    }
+
+    /// Load all data from a single consistent snapshot
+    async fn load_all(&self) -> Result<LoadedData> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+            .execute(&mut *tx)
+            .await?;
+
+        let retained = Self::stream_table(&mut *tx, RETAINED_TABLE).await?;
+        let mut sessions = Self::stream_table(&mut *tx, SESSIONS_TABLE).await?;
+        Self::fold_queued(&mut *tx, &mut sessions).await?;
+        let users = Self::stream_table(&mut *tx, USERS_TABLE).await?;
+        let roles = Self::stream_table(&mut *tx, ROLES_TABLE).await?;
+
+        tx.commit().await?;
+
+        Ok(LoadedData {
+            retained,
+            sessions,
+            users,
+            roles,
+        })
+    }
+}
@@ -0,0 +1,63 @@
+//! Tenant-scoped storage keys.
+//!
+//! Data belonging to a tenant is stored under `"{tenant}\0{id}"`. NUL cannot
+//! appear in MQTT topics, client IDs or usernames, so the prefix never
+//! collides with an untenanted key.
+
+/// Separator between the tenant and the id in a storage key
+pub const TENANT_KEY_SEPARATOR: char = '\0';
+
+/// Build the storage key for an id, optionally scoped to a tenant
+pub fn tenant_key(tenant: Option<&str>, id: &str) -> String {
+    match tenant {
+        Some(tenant) => {
+            let mut key = String::with_capacity(tenant.len() + 1 + id.len());
+            key.push_str(tenant);
+            key.push(TENANT_KEY_SEPARATOR);
+            key.push_str(id);
+            key
+        }
+        None => id.to_string(),
+    }
+}
+
+/// Split a storage key into its tenant (if any) and id
+pub fn split_tenant_key(key: &str) -> (Option<&str>, &str) {
+    match key.split_once(TENANT_KEY_SEPARATOR) {
+        Some((tenant, id)) => (Some(tenant), id),
+        None => (None, key),
+    }
+}
+
+/// Keep the entries of one tenant, with the tenant prefix removed from keys
+pub(crate) fn strip_tenant<T>(items: Vec<(String, T)>, tenant: &str) -> Vec<(String, T)> {
+    items
+        .into_iter()
+        .filter_map(|(key, value)| match split_tenant_key(&key) {
+            (Some(t), id) if t == tenant => Some((id.to_string(), value)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenant_key_roundtrip() {
+        let key = tenant_key(Some("acme"), "sensors/temp");
+        assert_eq!(split_tenant_key(&key), (Some("acme"), "sensors/temp"));
+        assert_eq!(split_tenant_key("sensors/temp"), (None, "sensors/temp"));
+    }
+
+    #[test]
+    fn test_strip_tenant() {
+        let items = vec![
+            (tenant_key(Some("acme"), "a"), 1),
+            (tenant_key(Some("other"), "b"), 2),
+            ("c".to_string(), 3),
+        ];
+        assert_eq!(strip_tenant(items, "acme"), vec![("a".to_string(), 1)]);
+    }
+}
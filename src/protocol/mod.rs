@@ -87,6 +87,16 @@ impl RetainHandling {
             _ => None,
         }
     }
+
+    /// Whether retained messages are sent for a subscription, given whether
+    /// it replaced an existing one [MQTT-3.3.1-9] [MQTT-3.3.1-10] [MQTT-3.3.1-11]
+    pub fn sends_retained(self, subscription_existed: bool) -> bool {
+        match self {
+            RetainHandling::SendAtSubscribe => true,
+            RetainHandling::SendAtSubscribeIfNew => !subscription_existed,
+            RetainHandling::DoNotSend => false,
+        }
+    }
 }
 
 /// Subscription options for MQTT v5.0
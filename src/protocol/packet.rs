@@ -2,9 +2,14 @@
 //!
 //! Unified packet types supporting both MQTT v3.1.1 and v5.0
 
-use bytes::Bytes;
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
 
-use super::{Properties, ProtocolVersion, QoS, ReasonCode, SubscriptionOptions};
+use bytes::{Bytes, BytesMut};
+
+use super::{EncodeError, Properties, ProtocolVersion, QoS, ReasonCode, SubscriptionOptions};
+use crate::codec::{read_variable_int, Encoder, RawPublish};
+use crate::topic::Topic;
 
 /// MQTT Packet - unified representation for v3.1.1 and v5.0
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -13,6 +18,8 @@ pub enum Packet {
     Connect(Box<Connect>),
     ConnAck(ConnAck),
     Publish(Publish),
+    /// A PUBLISH fanned out alike to many subscribers (never decoded)
+    SharedPublish(Arc<SharedPublish>),
     PubAck(PubAck),
     PubRec(PubRec),
     PubRel(PubRel),
@@ -33,7 +40,7 @@ impl Packet {
         match self {
             Packet::Connect(_) => 1,
             Packet::ConnAck(_) => 2,
-            Packet::Publish(_) => 3,
+            Packet::Publish(_) | Packet::SharedPublish(_) => 3,
             Packet::PubAck(_) => 4,
             Packet::PubRec(_) => 5,
             Packet::PubRel(_) => 6,
@@ -131,8 +138,8 @@ pub struct Publish {
     pub qos: QoS,
     /// Retain flag
     pub retain: bool,
-    /// Topic name
-    pub topic: String,
+    /// Topic name, shared by the copies queued for each subscriber
+    pub topic: Topic,
     /// Packet identifier (present only for QoS > 0)
     pub packet_id: Option<u16>,
     /// Payload
@@ -141,13 +148,144 @@ pub struct Publish {
     pub properties: Properties,
 }
 
+/// A PUBLISH sent alike to many subscribers, encoded once per protocol
+/// version and shared by their connections
+///
+/// The encoding carries the full topic, so it only serves connections that
+/// neither alias nor mount the topic; the fixed header flags and the packet
+/// identifier are patched in per connection.
+#[derive(Debug)]
+pub struct SharedPublish {
+    publish: Publish,
+    /// Encoding and packet identifier offset, for v3.1.1 and v5.0
+    encoded: [OnceLock<(Bytes, usize)>; 2],
+    /// When the message was fanned out
+    created: Instant,
+}
+
+impl SharedPublish {
+    pub fn new(publish: Publish) -> Self {
+        Self {
+            publish,
+            encoded: [OnceLock::new(), OnceLock::new()],
+            created: Instant::now(),
+        }
+    }
+
+    /// Share `publish`, taking the wire bytes it was received with as its
+    /// encoding for their protocol version if they still encode it
+    pub fn with_raw(publish: Publish, raw: Option<&RawPublish>) -> Self {
+        let shared = Self::new(publish);
+        if let Some(raw) = raw.filter(|raw| raw.encodes(&shared.publish)) {
+            let slot = &shared.encoded[usize::from(raw.version() == ProtocolVersion::V5)];
+            let _ = slot.set((raw.bytes().clone(), raw.packet_id_offset()));
+        }
+        shared
+    }
+
+    /// The message, without a packet identifier
+    pub fn publish(&self) -> &Publish {
+        &self.publish
+    }
+
+    /// When the message was fanned out, and so queued for its subscribers
+    pub fn created(&self) -> Instant {
+        self.created
+    }
+
+    fn encoding(&self, version: ProtocolVersion) -> Result<&(Bytes, usize), EncodeError> {
+        let slot = &self.encoded[usize::from(version == ProtocolVersion::V5)];
+        if let Some(encoded) = slot.get() {
+            return Ok(encoded);
+        }
+
+        let mut template = self.publish.clone();
+        if template.qos != QoS::AtMostOnce {
+            template.packet_id = Some(0);
+        }
+        let mut buf = BytesMut::new();
+        Encoder::new(version).encode(&Packet::Publish(template), &mut buf)?;
+        // Fixed header, then the topic, then the packet identifier
+        let (_, length_bytes) =
+            read_variable_int(&buf[1..]).map_err(|_| EncodeError::PacketTooLarge)?;
+        let packet_id_offset = 1 + length_bytes + 2 + self.publish.topic.len();
+
+        // Another connection may have got there first; both encodings match
+        let _ = slot.set((buf.freeze(), packet_id_offset));
+        Ok(slot.get().expect("encoding was just set"))
+    }
+
+    /// Append the encoding for `version` to `buf`, with `packet_id` for
+    /// QoS 1 and 2
+    pub fn encode(
+        &self,
+        version: ProtocolVersion,
+        packet_id: Option<u16>,
+        buf: &mut BytesMut,
+    ) -> Result<(), EncodeError> {
+        let (encoded, packet_id_offset) = self.encoding(version)?;
+        let start = buf.len();
+        buf.extend_from_slice(encoded);
+        buf[start] = self.publish.fixed_header();
+        if let (Some(packet_id), QoS::AtLeastOnce | QoS::ExactlyOnce) =
+            (packet_id, self.publish.qos)
+        {
+            let offset = start + packet_id_offset;
+            buf[offset..offset + 2].copy_from_slice(&packet_id.to_be_bytes());
+        }
+        Ok(())
+    }
+
+    /// The QoS 0 encoding for `version` without a copy: the first byte of
+    /// the fixed header, then the rest of the packet, to write vectored
+    pub fn frames(&self, version: ProtocolVersion) -> Result<[Bytes; 2], EncodeError> {
+        debug_assert_eq!(self.publish.qos, QoS::AtMostOnce);
+        let (encoded, _) = self.encoding(version)?;
+        // QoS 0 PUBLISH headers only differ in the DUP and RETAIN flags
+        let header = usize::from(self.publish.fixed_header() & 0x0F);
+        Ok([
+            Bytes::from_static(&PUBLISH_HEADERS[header..header + 1]),
+            encoded.slice(1..),
+        ])
+    }
+}
+
+/// Every first byte of a PUBLISH fixed header, by flags
+static PUBLISH_HEADERS: [u8; 16] = [
+    0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F,
+];
+
+impl PartialEq for SharedPublish {
+    fn eq(&self, other: &Self) -> bool {
+        self.publish == other.publish
+    }
+}
+
+impl Eq for SharedPublish {}
+
+impl Publish {
+    /// First byte of the fixed header: the packet type and the DUP, QoS and
+    /// RETAIN flags
+    pub(crate) fn fixed_header(&self) -> u8 {
+        let mut first_byte: u8 = 0x30; // PUBLISH type (0011)
+        if self.dup {
+            first_byte |= 0x08;
+        }
+        first_byte |= (self.qos as u8) << 1;
+        if self.retain {
+            first_byte |= 0x01;
+        }
+        first_byte
+    }
+}
+
 impl Default for Publish {
     fn default() -> Self {
         Self {
             dup: false,
             qos: QoS::AtMostOnce,
             retain: false,
-            topic: String::new(),
+            topic: Topic::default(),
             packet_id: None,
             payload: Bytes::new(),
             properties: Properties::default(),
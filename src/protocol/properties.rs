@@ -2,11 +2,13 @@
 //!
 //! Based on spec/v5.0/2.2_variable-header.md
 
+use std::borrow::Cow;
+
 use bytes::{BufMut, Bytes, BytesMut};
 
 use crate::codec::{
-    read_binary, read_string, read_variable_int, variable_int_len, write_binary, write_string,
-    write_variable_int,
+    read_binary, read_string, read_string_lossy, read_variable_int, variable_int_len, write_binary,
+    write_string, write_variable_int,
 };
 use crate::protocol::{DecodeError, EncodeError};
 
@@ -338,6 +340,12 @@ impl Properties {
 
     /// Decode properties from buffer
     pub fn decode(buf: &[u8]) -> Result<(Self, usize), DecodeError> {
+        Self::decode_with(buf, true)
+    }
+
+    /// Decode properties from buffer; unless `strict`, invalid UTF-8 in User
+    /// Properties is replaced rather than rejected
+    pub fn decode_with(buf: &[u8], strict: bool) -> Result<(Self, usize), DecodeError> {
         if buf.is_empty() {
             return Err(DecodeError::InsufficientData);
         }
@@ -587,13 +595,21 @@ impl Properties {
                     pos += 1;
                 }
                 PropertyId::UserProperty => {
-                    let (key, key_len) = read_string(&buf[pos..])?;
+                    let (key, key_len) = if strict {
+                        read_string(&buf[pos..]).map(|(s, len)| (Cow::Borrowed(s), len))?
+                    } else {
+                        read_string_lossy(&buf[pos..])?
+                    };
                     pos += key_len;
-                    let (val, val_len) = read_string(&buf[pos..])?;
+                    let (val, val_len) = if strict {
+                        read_string(&buf[pos..]).map(|(s, len)| (Cow::Borrowed(s), len))?
+                    } else {
+                        read_string_lossy(&buf[pos..])?
+                    };
                     pos += val_len;
                     props
                         .user_properties
-                        .push((key.to_string(), val.to_string()));
+                        .push((key.into_owned(), val.into_owned()));
                 }
                 PropertyId::MaximumPacketSize => {
                     if props.maximum_packet_size.is_some() {
@@ -167,7 +167,10 @@ impl ReasonCode {
             ReasonCode::Success => 0x00,
             ReasonCode::UnsupportedProtocolVersion => 0x01,
             ReasonCode::ClientIdNotValid => 0x02,
-            ReasonCode::ServerUnavailable => 0x03,
+            // v3.1.1 has no redirect; the client has to try elsewhere
+            ReasonCode::ServerUnavailable
+            | ReasonCode::UseAnotherServer
+            | ReasonCode::ServerMoved => 0x03,
             ReasonCode::BadUserNameOrPassword => 0x04,
             ReasonCode::NotAuthorized => 0x05,
             _ => 0x05, // Default to "not authorized" for other errors
@@ -8,6 +8,7 @@ use std::fmt;
 
 use async_trait::async_trait;
 use bytes::Bytes;
+use serde::Serialize;
 
 use crate::protocol::QoS;
 
@@ -44,7 +45,8 @@ impl fmt::Display for RemoteError {
 impl std::error::Error for RemoteError {}
 
 /// Status of a remote peer connection
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum RemotePeerStatus {
     /// Not connected, will attempt to connect
     Disconnected,
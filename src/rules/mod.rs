@@ -0,0 +1,795 @@
+//! Rule Engine
+//!
+//! Rules select, filter and reshape messages published by clients with a
+//! SQL-like statement (see [`sql`]) and run actions on the messages they
+//! pass: republish to another topic, send to a webhook, hand to a bridge,
+//! or drop the message.
+//!
+//! Rules are evaluated on the publishing connection's task, after the ACL
+//! check and publish transform hooks, so they see the message as it will
+//! be routed. Only the `drop` action takes effect there; the others are
+//! queued and run on a task of the broker, so a slow webhook never holds
+//! up publishers. Webhook requests go through the client of the HTTP
+//! bridges, over TLS for `https://` URLs, and reuse idle connections to
+//! their endpoint; at most [`MAX_WEBHOOK_REQUESTS`] are in flight at once
+//! and actions beyond that fail. A failed webhook is counted, not retried;
+//! the `bridge` action with an `http` bridge queues and retries instead.
+//! Republished messages are not evaluated by the rules again.
+
+pub mod sql;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::header::{HeaderName, HeaderValue, CONTENT_TYPE, HOST};
+use hyper::{Method, Request, Uri};
+use parking_lot::{Mutex, RwLock};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tokio::sync::{mpsc, Semaphore};
+use tracing::{debug, warn};
+
+use crate::bridge::{BridgeManager, HttpEndpoint, HttpSender};
+use crate::broker::Broker;
+use crate::config::{RuleActionConfig, RuleConfig};
+use crate::protocol::{Publish, QoS};
+use crate::topic::{topic_matches_filter, validate_topic_name};
+use sql::Statement;
+
+/// Outputs waiting for their actions; more are counted as failed actions
+const ACTION_QUEUE_SIZE: usize = 4096;
+
+/// Webhook requests in flight at once; more are counted as failed actions
+pub const MAX_WEBHOOK_REQUESTS: usize = 64;
+
+/// Idle connections kept open per webhook endpoint
+const MAX_IDLE_WEBHOOK_CONNECTIONS: usize = 8;
+
+/// Error in a rule definition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleError(pub String);
+
+impl std::fmt::Display for RuleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for RuleError {}
+
+/// Counters of a rule
+#[derive(Debug, Default)]
+struct RuleStats {
+    /// Messages on a topic of the FROM clause
+    matched: AtomicU64,
+    /// Messages that passed the WHERE clause
+    passed: AtomicU64,
+    actions_succeeded: AtomicU64,
+    actions_failed: AtomicU64,
+}
+
+/// A rule, ready to evaluate
+struct Rule {
+    config: RuleConfig,
+    statement: Statement,
+    /// Whether an action drops the messages
+    drops: bool,
+    stats: RuleStats,
+}
+
+/// State of a rule, as served by the admin API
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleStatus {
+    pub id: String,
+    pub enabled: bool,
+    pub description: String,
+    pub sql: String,
+    /// Types of the actions, in order
+    pub actions: Vec<&'static str>,
+    pub matched: u64,
+    pub passed: u64,
+    pub actions_succeeded: u64,
+    pub actions_failed: u64,
+}
+
+/// A message a rule passed, with the rule's output, waiting for the
+/// rule's actions
+pub struct RuleOutput {
+    rule: Arc<Rule>,
+    output: Map<String, Value>,
+    columns: Arc<Map<String, Value>>,
+    payload: Bytes,
+}
+
+/// Connections to webhook endpoints, kept for reuse, and the bound on
+/// requests in flight
+struct WebhookClient {
+    permits: Arc<Semaphore>,
+    /// Idle connections by scheme, host and port
+    idle: Mutex<HashMap<String, Vec<HttpSender>>>,
+}
+
+/// The configured rules and their evaluation
+pub struct RuleEngine {
+    rules: RwLock<Vec<Arc<Rule>>>,
+    outputs: mpsc::Sender<RuleOutput>,
+    receiver: Mutex<Option<mpsc::Receiver<RuleOutput>>>,
+    webhooks: Arc<WebhookClient>,
+}
+
+impl Default for RuleEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RuleEngine {
+    pub fn new() -> Self {
+        let (outputs, receiver) = mpsc::channel(ACTION_QUEUE_SIZE);
+        Self {
+            rules: RwLock::new(Vec::new()),
+            outputs,
+            receiver: Mutex::new(Some(receiver)),
+            webhooks: Arc::new(WebhookClient {
+                permits: Arc::new(Semaphore::new(MAX_WEBHOOK_REQUESTS)),
+                idle: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Add a rule, replacing the rule with the same ID; returns true if a
+    /// rule was replaced
+    pub fn add(&self, config: RuleConfig) -> Result<bool, RuleError> {
+        let statement = check(&config)?;
+        let rule = Arc::new(Rule {
+            drops: config
+                .actions
+                .iter()
+                .any(|action| matches!(action, RuleActionConfig::Drop)),
+            config,
+            statement,
+            stats: RuleStats::default(),
+        });
+        let mut rules = self.rules.write();
+        match rules.iter_mut().find(|r| r.config.id == rule.config.id) {
+            Some(existing) => {
+                *existing = rule;
+                Ok(true)
+            }
+            None => {
+                rules.push(rule);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Remove a rule; returns false if there is no rule with this ID
+    pub fn remove(&self, id: &str) -> bool {
+        let mut rules = self.rules.write();
+        let before = rules.len();
+        rules.retain(|rule| rule.config.id != id);
+        rules.len() != before
+    }
+
+    /// State of all rules, in evaluation order
+    pub fn status(&self) -> Vec<RuleStatus> {
+        self.rules.read().iter().map(|rule| rule.status()).collect()
+    }
+
+    /// State of one rule
+    pub fn rule_status(&self, id: &str) -> Option<RuleStatus> {
+        self.rules
+            .read()
+            .iter()
+            .find(|rule| rule.config.id == id)
+            .map(|rule| rule.status())
+    }
+
+    /// Evaluate the rules on a message published by a client, queueing the
+    /// actions of the rules it passes; returns true if a rule drops it
+    pub fn evaluate(&self, client_id: &str, username: Option<&str>, publish: &Publish) -> bool {
+        let rules = self.rules.read();
+        let mut columns: Option<Arc<Map<String, Value>>> = None;
+        let mut dropped = false;
+
+        for rule in rules.iter().filter(|rule| rule.config.enabled) {
+            if !rule
+                .statement
+                .from
+                .iter()
+                .any(|filter| topic_matches_filter(&publish.topic, filter))
+            {
+                continue;
+            }
+            rule.stats.matched.fetch_add(1, Ordering::Relaxed);
+
+            let columns = columns
+                .get_or_insert_with(|| Arc::new(message_columns(client_id, username, publish)));
+            let Some(output) = rule.statement.execute(columns) else {
+                continue;
+            };
+            rule.stats.passed.fetch_add(1, Ordering::Relaxed);
+            dropped |= rule.drops;
+
+            if rule
+                .config
+                .actions
+                .iter()
+                .all(|a| matches!(a, RuleActionConfig::Drop))
+            {
+                continue;
+            }
+            let queued = self.outputs.try_send(RuleOutput {
+                rule: rule.clone(),
+                output,
+                columns: columns.clone(),
+                payload: publish.payload.clone(),
+            });
+            if queued.is_err() {
+                debug!(
+                    "Rule '{}': action queue full, skipping actions",
+                    rule.config.id
+                );
+                rule.stats.actions_failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        dropped
+    }
+
+    /// Take the queue of outputs waiting for their actions; `None` after
+    /// the first call
+    pub fn take_outputs(&self) -> Option<mpsc::Receiver<RuleOutput>> {
+        self.receiver.lock().take()
+    }
+
+    /// Run the actions of an output, other than drop
+    pub fn run_actions(
+        &self,
+        output: RuleOutput,
+        broker: &Broker,
+        bridges: Option<&BridgeManager>,
+    ) {
+        let rule = &output.rule;
+        for action in &rule.config.actions {
+            let result = match action {
+                RuleActionConfig::Republish {
+                    topic,
+                    payload,
+                    qos,
+                    retain,
+                } => republish(&output, broker, topic, payload.as_deref(), *qos, *retain),
+                RuleActionConfig::Webhook {
+                    url,
+                    method,
+                    headers,
+                    body,
+                    timeout,
+                } => {
+                    let timeout = *timeout;
+                    let (endpoint, request) = match webhook_request(
+                        &output,
+                        url,
+                        method,
+                        headers,
+                        body.as_deref(),
+                        timeout,
+                    ) {
+                        Ok(request) => request,
+                        Err(e) => {
+                            rule.record(Err(e), "webhook");
+                            continue;
+                        }
+                    };
+                    let Ok(permit) = self.webhooks.permits.clone().try_acquire_owned() else {
+                        rule.record(Err("too many requests in flight".to_string()), "webhook");
+                        continue;
+                    };
+                    // Webhooks run on their own so they do not hold up other rules
+                    let webhooks = self.webhooks.clone();
+                    let rule = rule.clone();
+                    tokio::spawn(async move {
+                        let result = webhooks.send(&endpoint, request, timeout).await;
+                        rule.record(result, "webhook");
+                        drop(permit);
+                    });
+                    continue;
+                }
+                RuleActionConfig::Bridge {
+                    bridge,
+                    topic,
+                    payload,
+                } => forward_to_bridge(
+                    &output,
+                    bridges,
+                    bridge,
+                    topic.as_deref(),
+                    payload.as_deref(),
+                ),
+                RuleActionConfig::Drop => continue,
+            };
+            rule.record(result, action.kind());
+        }
+    }
+}
+
+impl Rule {
+    fn status(&self) -> RuleStatus {
+        RuleStatus {
+            id: self.config.id.clone(),
+            enabled: self.config.enabled,
+            description: self.config.description.clone(),
+            sql: self.config.sql.clone(),
+            actions: self.config.actions.iter().map(|a| a.kind()).collect(),
+            matched: self.stats.matched.load(Ordering::Relaxed),
+            passed: self.stats.passed.load(Ordering::Relaxed),
+            actions_succeeded: self.stats.actions_succeeded.load(Ordering::Relaxed),
+            actions_failed: self.stats.actions_failed.load(Ordering::Relaxed),
+        }
+    }
+
+    fn record(&self, result: Result<(), String>, action: &str) {
+        match result {
+            Ok(()) => {
+                self.stats.actions_succeeded.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                warn!("Rule '{}': {} action failed: {}", self.config.id, action, e);
+                self.stats.actions_failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Check a rule definition, returning its parsed statement
+pub fn check(config: &RuleConfig) -> Result<Statement, RuleError> {
+    if config.id.is_empty() {
+        return Err(RuleError("rule id must not be empty".to_string()));
+    }
+    let statement = Statement::parse(&config.sql)
+        .map_err(|e| RuleError(format!("rule '{}': invalid sql: {}", config.id, e)))?;
+    for action in &config.actions {
+        match action {
+            RuleActionConfig::Republish { topic, qos, .. } => {
+                if topic.is_empty() {
+                    return Err(RuleError(format!(
+                        "rule '{}': republish topic must not be empty",
+                        config.id
+                    )));
+                }
+                if *qos > 2 {
+                    return Err(RuleError(format!(
+                        "rule '{}': republish qos must be 0, 1 or 2",
+                        config.id
+                    )));
+                }
+            }
+            RuleActionConfig::Webhook { url, method, .. } => {
+                let scheme = url
+                    .parse::<Uri>()
+                    .ok()
+                    .and_then(|uri| uri.scheme().cloned());
+                if !matches!(scheme.as_ref().map(|s| s.as_str()), Some("http" | "https")) {
+                    return Err(RuleError(format!(
+                        "rule '{}': webhook url '{}' must be an http:// or https:// URL",
+                        config.id, url
+                    )));
+                }
+                if Method::from_bytes(method.as_bytes()).is_err() {
+                    return Err(RuleError(format!(
+                        "rule '{}': invalid webhook method '{}'",
+                        config.id, method
+                    )));
+                }
+            }
+            RuleActionConfig::Bridge { bridge, .. } => {
+                if bridge.is_empty() {
+                    return Err(RuleError(format!(
+                        "rule '{}': bridge action needs a bridge name",
+                        config.id
+                    )));
+                }
+            }
+            RuleActionConfig::Drop => {}
+        }
+    }
+    Ok(statement)
+}
+
+/// Columns of a published message, as rules see them
+fn message_columns(
+    client_id: &str,
+    username: Option<&str>,
+    publish: &Publish,
+) -> Map<String, Value> {
+    // A JSON payload is selected into; any other is a string
+    let payload = serde_json::from_slice(&publish.payload)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&publish.payload).into_owned()));
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+
+    let mut columns = Map::new();
+    columns.insert("topic".to_string(), Value::from(publish.topic.as_str()));
+    columns.insert("payload".to_string(), payload);
+    columns.insert("qos".to_string(), Value::from(publish.qos as u8));
+    columns.insert("retain".to_string(), Value::from(publish.retain));
+    columns.insert("clientid".to_string(), Value::from(client_id));
+    columns.insert(
+        "username".to_string(),
+        username.map_or(Value::Null, Value::from),
+    );
+    columns.insert("timestamp".to_string(), Value::from(timestamp));
+    columns
+}
+
+/// Substitute `{{path}}` in a template with fields of the output, or else
+/// columns of the message; strings are inserted as is, other values as
+/// JSON, and missing values as nothing
+///
+/// Double braces keep templates clear of `${VAR}` environment substitution
+/// in config files and of the braces of JSON payload templates.
+pub fn render(template: &str, output: &Map<String, Value>, columns: &Map<String, Value>) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        rendered.push_str(&rest[..start]);
+        let path: Vec<&str> = rest[start + 2..start + end].trim().split('.').collect();
+        match sql::lookup(&path, output, columns) {
+            Value::Null => {}
+            Value::String(s) => rendered.push_str(&s),
+            value => rendered.push_str(&value.to_string()),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Payload of an action: its template rendered, or the output as JSON
+fn action_payload(output: &RuleOutput, template: Option<&str>) -> Bytes {
+    match template {
+        Some(template) => Bytes::from(render(template, &output.output, &output.columns)),
+        None => Bytes::from(Value::Object(output.output.clone()).to_string()),
+    }
+}
+
+fn republish(
+    output: &RuleOutput,
+    broker: &Broker,
+    topic: &str,
+    payload: Option<&str>,
+    qos: u8,
+    retain: bool,
+) -> Result<(), String> {
+    let topic = render(topic, &output.output, &output.columns);
+    validate_topic_name(&topic).map_err(|e| format!("invalid topic '{}': {}", topic, e))?;
+    let qos = QoS::from_u8(qos).unwrap_or(QoS::AtMostOnce);
+    broker.publish(topic, action_payload(output, payload), qos, retain);
+    Ok(())
+}
+
+fn forward_to_bridge(
+    output: &RuleOutput,
+    bridges: Option<&BridgeManager>,
+    bridge: &str,
+    topic: Option<&str>,
+    payload: Option<&str>,
+) -> Result<(), String> {
+    let bridges = bridges.ok_or_else(|| "no bridges configured".to_string())?;
+    let topic = match topic {
+        Some(template) => render(template, &output.output, &output.columns),
+        None => lookup_str(&output.columns, "topic"),
+    };
+    let payload = match payload {
+        Some(template) => Bytes::from(render(template, &output.output, &output.columns)),
+        None => output.payload.clone(),
+    };
+    let qos = output
+        .columns
+        .get("qos")
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    let qos = QoS::from_u8(qos as u8).unwrap_or(QoS::AtMostOnce);
+    if bridges.forward_to(bridge, &topic, payload, qos, false) {
+        Ok(())
+    } else {
+        Err(format!("no bridge named '{}'", bridge))
+    }
+}
+
+fn lookup_str(map: &Map<String, Value>, key: &str) -> String {
+    map.get(key)
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Build the request of a webhook action, with the endpoint to send it to
+fn webhook_request(
+    output: &RuleOutput,
+    url: &str,
+    method: &str,
+    headers: &HashMap<String, String>,
+    body: Option<&str>,
+    timeout: Duration,
+) -> Result<(HttpEndpoint, Request<Full<Bytes>>), String> {
+    let uri: Uri = url.parse().map_err(|e| format!("invalid url: {}", e))?;
+    let (Some(host), Some(authority)) = (uri.host(), uri.authority()) else {
+        return Err("url has no host".to_string());
+    };
+    let https = uri.scheme_str() == Some("https");
+    let endpoint = HttpEndpoint {
+        host: host.to_string(),
+        port: uri.port_u16().unwrap_or(if https { 443 } else { 80 }),
+        tls: https.then(Default::default),
+        connect_timeout: timeout,
+    };
+    let method = Method::from_bytes(method.as_bytes()).map_err(|e| e.to_string())?;
+    let path = uri
+        .path_and_query()
+        .map_or("/", |path| path.as_str())
+        .to_string();
+
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(path)
+        .header(HOST, authority.as_str())
+        .header(CONTENT_TYPE, "application/json");
+    let request_headers = builder.headers_mut().expect("request builder is valid");
+    for (name, value) in headers {
+        let value = render(value, &output.output, &output.columns);
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| format!("invalid header name '{}'", name))?;
+        let value = HeaderValue::from_str(&value)
+            .map_err(|_| format!("invalid value for header '{}'", name))?;
+        request_headers.insert(name, value);
+    }
+    let request = builder
+        .body(Full::new(action_payload(output, body)))
+        .map_err(|e| e.to_string())?;
+    Ok((endpoint, request))
+}
+
+impl WebhookClient {
+    /// Send a webhook request on an idle connection to its endpoint, or a
+    /// new one; a 2xx response succeeds
+    async fn send(
+        &self,
+        endpoint: &HttpEndpoint,
+        request: Request<Full<Bytes>>,
+        timeout: Duration,
+    ) -> Result<(), String> {
+        let key = format!(
+            "{}://{}:{}",
+            if endpoint.tls.is_some() {
+                "https"
+            } else {
+                "http"
+            },
+            endpoint.host,
+            endpoint.port
+        );
+        let mut sender = match self.take_idle(&key) {
+            Some(sender) => sender,
+            None => endpoint.connect().await.map_err(|e| e.to_string())?,
+        };
+        let status = endpoint
+            .send(&mut sender, request, timeout)
+            .await
+            .map_err(|e| e.to_string())?;
+        self.put_idle(key, sender);
+
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(format!("endpoint answered {}", status))
+        }
+    }
+
+    /// An idle connection to the endpoint `key` that can take a request
+    fn take_idle(&self, key: &str) -> Option<HttpSender> {
+        let mut idle = self.idle.lock();
+        let senders = idle.get_mut(key)?;
+        std::iter::from_fn(|| senders.pop()).find(|sender| sender.is_ready())
+    }
+
+    /// Keep a connection for the next request to the endpoint `key`
+    fn put_idle(&self, key: String, sender: HttpSender) {
+        let mut idle = self.idle.lock();
+        let senders = idle.entry(key).or_default();
+        if senders.len() < MAX_IDLE_WEBHOOK_CONNECTIONS {
+            senders.push(sender);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Properties;
+    use serde_json::json;
+
+    fn rule(id: &str, sql: &str, actions: Vec<RuleActionConfig>) -> RuleConfig {
+        RuleConfig {
+            id: id.to_string(),
+            enabled: true,
+            description: String::new(),
+            sql: sql.to_string(),
+            actions,
+        }
+    }
+
+    fn publish(topic: &str, payload: &'static [u8]) -> Publish {
+        Publish {
+            topic: topic.into(),
+            payload: Bytes::from_static(payload),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            dup: false,
+            packet_id: Some(1),
+            properties: Properties::default(),
+        }
+    }
+
+    fn republish_to(topic: &str) -> RuleActionConfig {
+        RuleActionConfig::Republish {
+            topic: topic.to_string(),
+            payload: None,
+            qos: 0,
+            retain: false,
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let engine = RuleEngine::new();
+        let mut outputs = engine.take_outputs().unwrap();
+        assert!(engine.take_outputs().is_none());
+
+        let sql = r#"SELECT payload.temp AS t FROM "sensors/+/temp" WHERE t > 90"#;
+        assert!(!engine
+            .add(rule("hot", sql, vec![republish_to("alerts/{{clientid}}")]))
+            .unwrap());
+        engine
+            .add(rule(
+                "mute",
+                r#"SELECT * FROM "debug/#""#,
+                vec![RuleActionConfig::Drop],
+            ))
+            .unwrap();
+
+        assert!(!engine.evaluate("s1", None, &publish("sensors/1/temp", br#"{"temp": 95}"#)));
+        assert!(!engine.evaluate("s1", None, &publish("sensors/1/temp", br#"{"temp": 20}"#)));
+        assert!(!engine.evaluate("s1", None, &publish("other", br#"{"temp": 95}"#)));
+        assert!(engine.evaluate("s1", None, &publish("debug/x", b"")));
+
+        let output = outputs.try_recv().unwrap();
+        assert_eq!(Value::Object(output.output.clone()), json!({"t": 95}));
+        assert_eq!(
+            render("alerts/{{clientid}}/{{t}}", &output.output, &output.columns),
+            "alerts/s1/95"
+        );
+        // Drop-only rules queue nothing
+        assert!(outputs.try_recv().is_err());
+
+        let status = engine.rule_status("hot").unwrap();
+        assert_eq!((status.matched, status.passed), (2, 1));
+        assert_eq!(status.actions, ["republish"]);
+
+        assert!(engine.remove("mute"));
+        assert!(!engine.remove("mute"));
+        assert!(!engine.evaluate("s1", None, &publish("debug/x", b"")));
+        assert_eq!(engine.status().len(), 1);
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(check(&rule("", r#"SELECT * FROM "a""#, vec![])).is_err());
+        assert!(check(&rule("a", "SELECT * FROM", vec![])).is_err());
+        let webhook = |url: &str| RuleActionConfig::Webhook {
+            url: url.to_string(),
+            method: "POST".to_string(),
+            headers: HashMap::new(),
+            body: None,
+            timeout: std::time::Duration::from_secs(1),
+        };
+        assert!(check(&rule(
+            "a",
+            r#"SELECT * FROM "a""#,
+            vec![webhook("http://hook:8080/x")]
+        ))
+        .is_ok());
+        assert!(check(&rule(
+            "a",
+            r#"SELECT * FROM "a""#,
+            vec![webhook("https://hooks.example.com/x")]
+        ))
+        .is_ok());
+        assert!(check(&rule(
+            "a",
+            r#"SELECT * FROM "a""#,
+            vec![webhook("ftp://hook/x")]
+        ))
+        .is_err());
+        assert!(check(&rule(
+            "a",
+            r#"SELECT * FROM "a""#,
+            vec![webhook("hook:8080")]
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        let output = match json!({"t": 95, "name": "probe", "nested": {"x": [1]}}) {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        };
+        let columns = Map::new();
+        assert_eq!(render("{{name}}: {{ t }}", &output, &columns), "probe: 95");
+        assert_eq!(
+            render("{{nested.x}}|{{missing}}|{{", &output, &columns),
+            "[1]||{{"
+        );
+        // JSON payload templates keep their single braces
+        assert_eq!(
+            render(r#"{"temp": {{t}}}"#, &output, &columns),
+            r#"{"temp": 95}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_webhook_reuses_connections() {
+        use hyper::service::service_fn;
+        use hyper_util::rt::TokioIo;
+        use std::convert::Infallible;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let listener_port = listener.local_addr().unwrap().port();
+        let accepted = Arc::new(AtomicU64::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::Relaxed);
+                let service = service_fn(|_req| async {
+                    Ok::<_, Infallible>(hyper::Response::new(Full::new(Bytes::from_static(b"ok"))))
+                });
+                tokio::spawn(
+                    hyper::server::conn::http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service),
+                );
+            }
+        });
+
+        let engine = RuleEngine::new();
+        let endpoint = HttpEndpoint {
+            host: "127.0.0.1".to_string(),
+            port: listener_port,
+            tls: None,
+            connect_timeout: Duration::from_secs(5),
+        };
+        for _ in 0..3 {
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri("/hook")
+                .header(HOST, address.as_str())
+                .body(Full::new(Bytes::from_static(b"{}")))
+                .unwrap();
+            engine
+                .webhooks
+                .send(&endpoint, request, Duration::from_secs(5))
+                .await
+                .unwrap();
+        }
+        assert_eq!(accepted.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,637 @@
+//! Rule Statements
+//!
+//! Parses and evaluates the SQL-like statements of rules:
+//!
+//! ```text
+//! SELECT payload.temp AS t, clientid FROM "sensors/+/temp", "legacy/#" WHERE t > 90
+//! ```
+//!
+//! `SELECT *` selects every column of the message. A field is a path into
+//! the columns (`payload.temp`) or an expression with an alias. The
+//! condition may use the aliases of the fields. Expressions support
+//! numbers, strings in single or double quotes, `true`, `false`, `null`,
+//! `+ - * /`, the comparisons `= != <> < <= > >=`, and `AND`, `OR`, `NOT`.
+//!
+//! Evaluation never fails: a path that does not resolve is `null`, and an
+//! operation on values it does not apply to yields `null`. Comparisons with
+//! `null` are false, and only `true` passes the condition.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use serde_json::{Map, Number, Value};
+
+use crate::topic::validate_topic_filter;
+
+/// A parsed rule statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub fields: Vec<Field>,
+    /// Topic filters the rule applies to
+    pub from: Vec<String>,
+    pub condition: Option<Expr>,
+}
+
+/// A field of the SELECT clause
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    /// `*`: every column
+    All,
+    /// An expression, output under `name`
+    Expr { expr: Expr, name: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    /// A column or alias, then keys into it
+    Path(Vec<String>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// Error parsing a statement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError(pub String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Statement {
+    /// Parse a statement
+    pub fn parse(sql: &str) -> Result<Self, ParseError> {
+        let tokens = tokenize(sql)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let statement = parser.statement()?;
+        if let Some(token) = parser.peek() {
+            return Err(ParseError(format!("unexpected {} at end", token)));
+        }
+        Ok(statement)
+    }
+
+    /// The output of the statement for a message's columns, or `None` if
+    /// the condition does not pass
+    pub fn execute(&self, columns: &Map<String, Value>) -> Option<Map<String, Value>> {
+        let mut output = Map::new();
+        for field in &self.fields {
+            match field {
+                Field::All => output.extend(columns.iter().map(|(k, v)| (k.clone(), v.clone()))),
+                Field::Expr { expr, name } => {
+                    let value = eval(expr, &output, columns);
+                    output.insert(name.clone(), value);
+                }
+            }
+        }
+        match &self.condition {
+            Some(condition) if eval(condition, &output, columns) != Value::Bool(true) => None,
+            _ => Some(output),
+        }
+    }
+}
+
+/// Value of a dotted path, looked up in `output` first, then `columns`
+pub fn lookup(
+    path: &[impl AsRef<str>],
+    output: &Map<String, Value>,
+    columns: &Map<String, Value>,
+) -> Value {
+    let Some((first, rest)) = path.split_first() else {
+        return Value::Null;
+    };
+    let mut value = match output
+        .get(first.as_ref())
+        .or_else(|| columns.get(first.as_ref()))
+    {
+        Some(value) => value,
+        None => return Value::Null,
+    };
+    for key in rest {
+        value = match value {
+            Value::Object(map) => match map.get(key.as_ref()) {
+                Some(value) => value,
+                None => return Value::Null,
+            },
+            Value::Array(items) => match key
+                .as_ref()
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| items.get(i))
+            {
+                Some(value) => value,
+                None => return Value::Null,
+            },
+            _ => return Value::Null,
+        };
+    }
+    value.clone()
+}
+
+fn eval(expr: &Expr, output: &Map<String, Value>, columns: &Map<String, Value>) -> Value {
+    match expr {
+        Expr::Literal(value) => value.clone(),
+        Expr::Path(path) => lookup(path, output, columns),
+        Expr::Not(inner) => match eval(inner, output, columns) {
+            Value::Bool(b) => Value::Bool(!b),
+            _ => Value::Null,
+        },
+        Expr::Neg(inner) => match number(&eval(inner, output, columns)) {
+            Some(n) => to_value(-n),
+            None => Value::Null,
+        },
+        Expr::Binary(left, op, right) => {
+            let left = eval(left, output, columns);
+            // AND and OR short-circuit
+            match (op, &left) {
+                (BinaryOp::And, Value::Bool(false)) => return Value::Bool(false),
+                (BinaryOp::Or, Value::Bool(true)) => return Value::Bool(true),
+                _ => {}
+            }
+            binary(*op, &left, &eval(right, output, columns))
+        }
+    }
+}
+
+fn binary(op: BinaryOp, left: &Value, right: &Value) -> Value {
+    match op {
+        BinaryOp::And | BinaryOp::Or => match (left, right) {
+            (Value::Bool(_), Value::Bool(b)) => Value::Bool(*b),
+            _ => Value::Null,
+        },
+        BinaryOp::Eq => Value::Bool(!left.is_null() && equal(left, right)),
+        BinaryOp::Ne => Value::Bool(!left.is_null() && !right.is_null() && !equal(left, right)),
+        BinaryOp::Lt => Value::Bool(compare(left, right) == Some(Ordering::Less)),
+        BinaryOp::Le => Value::Bool(matches!(
+            compare(left, right),
+            Some(Ordering::Less | Ordering::Equal)
+        )),
+        BinaryOp::Gt => Value::Bool(compare(left, right) == Some(Ordering::Greater)),
+        BinaryOp::Ge => Value::Bool(matches!(
+            compare(left, right),
+            Some(Ordering::Greater | Ordering::Equal)
+        )),
+        BinaryOp::Add => match (left, right) {
+            (Value::String(a), Value::String(b)) => Value::String(format!("{}{}", a, b)),
+            _ => arithmetic(left, right, |a, b| a + b),
+        },
+        BinaryOp::Sub => arithmetic(left, right, |a, b| a - b),
+        BinaryOp::Mul => arithmetic(left, right, |a, b| a * b),
+        BinaryOp::Div => arithmetic(left, right, |a, b| a / b),
+    }
+}
+
+fn arithmetic(left: &Value, right: &Value, f: impl Fn(f64, f64) -> f64) -> Value {
+    match (number(left), number(right)) {
+        (Some(a), Some(b)) => to_value(f(a, b)),
+        _ => Value::Null,
+    }
+}
+
+/// A value as a number; numeric strings count
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// A number as a JSON value, integral where it is whole
+fn to_value(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() < i64::MAX as f64 {
+        Value::Number(Number::from(n as i64))
+    } else {
+        Number::from_f64(n).map_or(Value::Null, Value::Number)
+    }
+}
+
+fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Null, _) | (_, Value::Null) => None,
+        _ => number(left)?.partial_cmp(&number(right)?),
+    }
+}
+
+fn equal(left: &Value, right: &Value) -> bool {
+    match compare(left, right) {
+        Some(ordering) => ordering == Ordering::Equal,
+        None => left == right,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    Str(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "'{}'", name),
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Str(s) => write!(f, "\"{}\"", s),
+            Token::Symbol(s) => write!(f, "'{}'", s),
+        }
+    }
+}
+
+const SYMBOLS: &[&str] = &[
+    "!=", "<>", "<=", ">=", "=", "<", ">", "+", "-", "*", "/", ",", ".", "(", ")",
+];
+
+fn tokenize(sql: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = sql.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '\'' || c == '"' {
+            // Quotes are escaped by doubling them
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    Some(&q) if q == c && chars.get(i + 1) == Some(&c) => {
+                        value.push(c);
+                        i += 2;
+                    }
+                    Some(&q) if q == c => {
+                        i += 1;
+                        break;
+                    }
+                    Some(&other) => {
+                        value.push(other);
+                        i += 1;
+                    }
+                    None => return Err(ParseError("unterminated string".to_string())),
+                }
+            }
+            tokens.push(Token::Str(value));
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let n = text
+                .parse()
+                .map_err(|_| ParseError(format!("invalid number {}", text)))?;
+            tokens.push(Token::Number(n));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let symbol = SYMBOLS
+                .iter()
+                .find(|s| rest.starts_with(**s))
+                .ok_or_else(|| ParseError(format!("unexpected character '{}'", c)))?;
+            tokens.push(Token::Symbol(symbol));
+            i += symbol.len();
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(name)) if name.eq_ignore_ascii_case(keyword))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.is_keyword(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.unexpected(keyword))
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> ParseError {
+        match self.peek() {
+            Some(token) => ParseError(format!("expected {}, found {}", expected, token)),
+            None => ParseError(format!("expected {}, found end of statement", expected)),
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, ParseError> {
+        self.expect_keyword("SELECT")?;
+        let mut fields = vec![self.field()?];
+        while self.eat_symbol(",") {
+            fields.push(self.field()?);
+        }
+
+        self.expect_keyword("FROM")?;
+        let mut from = vec![self.filter()?];
+        while self.eat_symbol(",") {
+            from.push(self.filter()?);
+        }
+
+        let condition = if self.eat_keyword("WHERE") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+        Ok(Statement {
+            fields,
+            from,
+            condition,
+        })
+    }
+
+    fn field(&mut self) -> Result<Field, ParseError> {
+        if self.eat_symbol("*") {
+            return Ok(Field::All);
+        }
+        let expr = self.expr()?;
+        if self.eat_keyword("AS") {
+            return match self.next() {
+                Some(Token::Ident(name)) => Ok(Field::Expr { expr, name }),
+                _ => Err(ParseError("expected a name after AS".to_string())),
+            };
+        }
+        match &expr {
+            Expr::Path(path) => Ok(Field::Expr {
+                name: path.join("."),
+                expr,
+            }),
+            _ => Err(ParseError(
+                "a field that is not a path needs a name (AS <name>)".to_string(),
+            )),
+        }
+    }
+
+    fn filter(&mut self) -> Result<String, ParseError> {
+        match self.next() {
+            Some(Token::Str(filter)) => {
+                validate_topic_filter(&filter)
+                    .map_err(|e| ParseError(format!("invalid topic filter '{}': {}", filter, e)))?;
+                Ok(filter)
+            }
+            _ => {
+                self.pos -= 1;
+                Err(self.unexpected("a quoted topic filter"))
+            }
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.and()?;
+        while self.eat_keyword("OR") {
+            left = Expr::Binary(Box::new(left), BinaryOp::Or, Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.not()?;
+        while self.eat_keyword("AND") {
+            left = Expr::Binary(Box::new(left), BinaryOp::And, Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr, ParseError> {
+        if self.eat_keyword("NOT") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let left = self.sum()?;
+        let op = match self.peek() {
+            Some(Token::Symbol("=")) => BinaryOp::Eq,
+            Some(Token::Symbol("!=" | "<>")) => BinaryOp::Ne,
+            Some(Token::Symbol("<")) => BinaryOp::Lt,
+            Some(Token::Symbol("<=")) => BinaryOp::Le,
+            Some(Token::Symbol(">")) => BinaryOp::Gt,
+            Some(Token::Symbol(">=")) => BinaryOp::Ge,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        Ok(Expr::Binary(Box::new(left), op, Box::new(self.sum()?)))
+    }
+
+    fn sum(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.product()?;
+        loop {
+            let op = if self.eat_symbol("+") {
+                BinaryOp::Add
+            } else if self.eat_symbol("-") {
+                BinaryOp::Sub
+            } else {
+                return Ok(left);
+            };
+            left = Expr::Binary(Box::new(left), op, Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.unary()?;
+        loop {
+            let op = if self.eat_symbol("*") {
+                BinaryOp::Mul
+            } else if self.eat_symbol("/") {
+                BinaryOp::Div
+            } else {
+                return Ok(left);
+            };
+            left = Expr::Binary(Box::new(left), op, Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if self.eat_symbol("-") {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        if self.eat_symbol("(") {
+            let expr = self.expr()?;
+            if !self.eat_symbol(")") {
+                return Err(self.unexpected("')'"));
+            }
+            return Ok(expr);
+        }
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Literal(to_value(n))),
+            Some(Token::Str(s)) => Ok(Expr::Literal(Value::String(s))),
+            Some(Token::Ident(name)) => {
+                match name.to_ascii_lowercase().as_str() {
+                    "true" => return Ok(Expr::Literal(Value::Bool(true))),
+                    "false" => return Ok(Expr::Literal(Value::Bool(false))),
+                    "null" => return Ok(Expr::Literal(Value::Null)),
+                    _ => {}
+                }
+                let mut path = vec![name];
+                while self.eat_symbol(".") {
+                    match self.next() {
+                        Some(Token::Ident(key)) => path.push(key),
+                        Some(Token::Number(n)) if n.fract() == 0.0 => path.push(n.to_string()),
+                        _ => return Err(ParseError("expected a key after '.'".to_string())),
+                    }
+                }
+                Ok(Expr::Path(path))
+            }
+            _ => {
+                self.pos -= 1;
+                Err(self.unexpected("an expression"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn columns(payload: Value) -> Map<String, Value> {
+        match json!({
+            "topic": "sensors/1/temp",
+            "clientid": "sensor-1",
+            "qos": 1,
+            "payload": payload,
+        }) {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let statement = Statement::parse(
+            r#"select payload.temp as t, clientid FROM "sensors/+/temp", 'legacy/#' where t > 90"#,
+        )
+        .unwrap();
+        assert_eq!(statement.from, ["sensors/+/temp", "legacy/#"]);
+        assert_eq!(
+            statement.fields[0],
+            Field::Expr {
+                expr: Expr::Path(vec!["payload".to_string(), "temp".to_string()]),
+                name: "t".to_string(),
+            }
+        );
+        assert_eq!(
+            statement.condition,
+            Some(Expr::Binary(
+                Box::new(Expr::Path(vec!["t".to_string()])),
+                BinaryOp::Gt,
+                Box::new(Expr::Literal(json!(90))),
+            ))
+        );
+
+        assert!(Statement::parse("SELECT * FROM \"a/#\"").is_ok());
+        assert!(Statement::parse("SELECT * FROM \"a/#/b\"").is_err());
+        assert!(Statement::parse("SELECT * FROM a").is_err());
+        assert!(Statement::parse("SELECT 1 + 1 FROM \"a\"").is_err());
+        assert!(Statement::parse("SELECT * FROM \"a\" WHERE (x = 1").is_err());
+        assert!(Statement::parse("SELECT * FROM \"a\" LIMIT 1").is_err());
+    }
+
+    #[test]
+    fn test_execute() {
+        let statement =
+            Statement::parse(r#"SELECT payload.temp AS t, clientid FROM "sensors/#" WHERE t > 90"#)
+                .unwrap();
+        let output = statement.execute(&columns(json!({"temp": 95.5}))).unwrap();
+        assert_eq!(
+            Value::Object(output),
+            json!({"t": 95.5, "clientid": "sensor-1"})
+        );
+        assert!(statement.execute(&columns(json!({"temp": 20}))).is_none());
+        // Missing fields are null, and null comparisons fail
+        assert!(statement.execute(&columns(json!("not json"))).is_none());
+
+        let all =
+            Statement::parse(r##"SELECT * FROM "#" WHERE qos = 1 AND NOT topic = 'x'"##).unwrap();
+        assert_eq!(all.execute(&columns(json!(1))).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_expressions() {
+        let eval_str = |sql: &str| {
+            let statement = Statement::parse(&format!("SELECT {} AS v FROM \"#\"", sql)).unwrap();
+            statement
+                .execute(&columns(json!({"a": 4, "s": "12", "list": [1, 2]})))
+                .unwrap()
+                .remove("v")
+                .unwrap()
+        };
+        assert_eq!(eval_str("payload.a * 2 + 1"), json!(9));
+        assert_eq!(eval_str("payload.a / 8"), json!(0.5));
+        assert_eq!(eval_str("-payload.a"), json!(-4));
+        assert_eq!(eval_str("payload.s > 9"), json!(true));
+        assert_eq!(eval_str("payload.list.1"), json!(2));
+        assert_eq!(eval_str("'a' + clientid"), json!("asensor-1"));
+        assert_eq!(eval_str("payload.missing = null"), json!(false));
+        assert_eq!(eval_str("payload.missing != 1"), json!(false));
+        assert_eq!(
+            eval_str("1 < 2 AND (2 < 1 OR 'it''s' = \"it's\")"),
+            json!(true)
+        );
+    }
+}
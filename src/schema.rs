@@ -0,0 +1,237 @@
+//! Payload Schema Validation
+//!
+//! Publishes to topics matching a `[[mqtt.schemas]]` rule must carry a
+//! payload following its schema: a JSON document valid against a JSON
+//! Schema, or a protobuf message of a type from a FileDescriptorSet.
+//! Invalid payloads are rejected, or quarantined below a separate topic
+//! prefix so they can be inspected without reaching regular subscribers.
+//!
+//! Schemas are compiled with the `schema` feature; without it, configuring
+//! any is an error.
+
+use std::fmt;
+
+use crate::config::SchemaRule;
+use crate::topic::topic_matches_filter;
+
+/// Error loading a schema
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaError(pub String);
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+/// A payload that does not follow the schema of its topic
+#[derive(Debug)]
+pub struct SchemaViolation<'a> {
+    /// The rule whose schema the payload violates
+    pub rule: &'a SchemaRule,
+    /// Why the payload is invalid
+    pub error: String,
+}
+
+/// A compiled schema
+enum Validator {
+    #[cfg(feature = "schema")]
+    Json(Box<jsonschema::Validator>),
+    #[cfg(feature = "schema")]
+    Protobuf(prost_reflect::MessageDescriptor),
+}
+
+impl Validator {
+    /// Load and compile the schema of a rule
+    fn load(rule: &SchemaRule) -> Result<Self, SchemaError> {
+        #[cfg(feature = "schema")]
+        {
+            use crate::config::SchemaFormat;
+
+            let contents = std::fs::read(&rule.path)
+                .map_err(|e| SchemaError(format!("cannot read schema {:?}: {}", rule.path, e)))?;
+            match rule.format {
+                SchemaFormat::JsonSchema => {
+                    let schema: serde_json::Value =
+                        serde_json::from_slice(&contents).map_err(|e| {
+                            SchemaError(format!("schema {:?} is not JSON: {}", rule.path, e))
+                        })?;
+                    let validator = jsonschema::validator_for(&schema).map_err(|e| {
+                        SchemaError(format!("invalid JSON Schema {:?}: {}", rule.path, e))
+                    })?;
+                    Ok(Validator::Json(Box::new(validator)))
+                }
+                SchemaFormat::Protobuf => {
+                    let pool = prost_reflect::DescriptorPool::decode(contents.as_slice()).map_err(
+                        |e| SchemaError(format!("invalid descriptor set {:?}: {}", rule.path, e)),
+                    )?;
+                    let name = rule.message.as_deref().unwrap_or_default();
+                    let descriptor = pool.get_message_by_name(name).ok_or_else(|| {
+                        SchemaError(format!(
+                            "descriptor set {:?} has no message type '{}'",
+                            rule.path, name
+                        ))
+                    })?;
+                    Ok(Validator::Protobuf(descriptor))
+                }
+            }
+        }
+        #[cfg(not(feature = "schema"))]
+        {
+            Err(SchemaError(format!(
+                "schema for '{}' requires building with --features schema",
+                rule.topic
+            )))
+        }
+    }
+
+    /// Check a payload, returning why it is invalid
+    fn check(&self, payload: &[u8]) -> Result<(), String> {
+        #[cfg(not(feature = "schema"))]
+        let _ = payload;
+        match *self {
+            #[cfg(feature = "schema")]
+            Validator::Json(ref validator) => {
+                let document: serde_json::Value = serde_json::from_slice(payload)
+                    .map_err(|e| format!("payload is not JSON: {}", e))?;
+                let error = validator
+                    .iter_errors(&document)
+                    .next()
+                    .map(|e| format!("{} at '{}'", e, e.instance_path));
+                error.map_or(Ok(()), Err)
+            }
+            #[cfg(feature = "schema")]
+            Validator::Protobuf(ref descriptor) => {
+                prost_reflect::DynamicMessage::decode(descriptor.clone(), payload)
+                    .map(|_| ())
+                    .map_err(|e| format!("payload is not a {}: {}", descriptor.full_name(), e))
+            }
+        }
+    }
+}
+
+/// A rule with its compiled schema
+struct Schema {
+    rule: SchemaRule,
+    validator: Validator,
+}
+
+/// The configured payload schemas
+#[derive(Default)]
+pub struct SchemaRegistry {
+    schemas: Vec<Schema>,
+}
+
+impl fmt::Debug for SchemaRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.schemas.iter().map(|schema| &schema.rule.topic))
+            .finish()
+    }
+}
+
+impl SchemaRegistry {
+    /// Load the schemas of the rules, in order
+    pub fn load(rules: &[SchemaRule]) -> Result<Self, SchemaError> {
+        let schemas = rules
+            .iter()
+            .map(|rule| {
+                Ok(Schema {
+                    validator: Validator::load(rule)?,
+                    rule: rule.clone(),
+                })
+            })
+            .collect::<Result<_, SchemaError>>()?;
+        Ok(Self { schemas })
+    }
+
+    /// Number of schemas
+    pub fn len(&self) -> usize {
+        self.schemas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.schemas.is_empty()
+    }
+
+    /// Check a payload against the schema of the first rule matching its
+    /// topic; topics without a rule accept any payload
+    pub fn validate(&self, topic: &str, payload: &[u8]) -> Result<(), SchemaViolation<'_>> {
+        let Some(schema) = self
+            .schemas
+            .iter()
+            .find(|schema| topic_matches_filter(topic, &schema.rule.topic))
+        else {
+            return Ok(());
+        };
+        schema
+            .validator
+            .check(payload)
+            .map_err(|error| SchemaViolation {
+                rule: &schema.rule,
+                error,
+            })
+    }
+}
+
+#[cfg(all(test, feature = "schema"))]
+mod tests {
+    use super::*;
+    use crate::config::{InvalidPayloadAction, SchemaFormat};
+
+    fn json_rule(topic: &str, schema: &str) -> SchemaRule {
+        let path = std::env::temp_dir().join(format!(
+            "vibemq-schema-{}-{}.json",
+            std::process::id(),
+            topic.replace(['/', '+', '#'], "_")
+        ));
+        std::fs::write(&path, schema).unwrap();
+        SchemaRule {
+            topic: topic.to_string(),
+            format: SchemaFormat::JsonSchema,
+            path,
+            message: None,
+            on_invalid: InvalidPayloadAction::Reject,
+            quarantine_prefix: "$quarantine/".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_json_schema() {
+        let schema = r#"{
+            "type": "object",
+            "properties": { "temp": { "type": "number" } },
+            "required": ["temp"]
+        }"#;
+        let registry = SchemaRegistry::load(&[json_rule("sensors/+/temp", schema)]).unwrap();
+        assert_eq!(registry.len(), 1);
+
+        assert!(registry
+            .validate("sensors/a/temp", br#"{"temp": 21.5}"#)
+            .is_ok());
+        let violation = registry
+            .validate("sensors/a/temp", br#"{"temp": "hot"}"#)
+            .unwrap_err();
+        assert_eq!(violation.rule.topic, "sensors/+/temp");
+        assert!(registry.validate("sensors/a/temp", b"{}").is_err());
+        assert!(registry.validate("sensors/a/temp", b"not json").is_err());
+
+        // Other topics accept anything
+        assert!(registry.validate("sensors/a/humidity", b"not json").is_ok());
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let mut rule = json_rule("bad/schema", "{ not json");
+        assert!(SchemaRegistry::load(&[rule.clone()]).is_err());
+
+        rule.path = std::env::temp_dir().join("vibemq-schema-missing.json");
+        assert!(SchemaRegistry::load(&[rule.clone()]).is_err());
+
+        rule.format = SchemaFormat::Protobuf;
+        rule.message = Some("telemetry.v1.Reading".to_string());
+        assert!(SchemaRegistry::load(&[rule]).is_err());
+    }
+}
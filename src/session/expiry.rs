@@ -0,0 +1,138 @@
+//! Message Expiry Timers
+//!
+//! Queued messages with a Message Expiry Interval are removed by a hashed
+//! timer wheel of one-second slots, like delayed wills, so a tick only
+//! visits the sessions with a message due rather than every queued message.
+//! Each session keeps a timer for its earliest expiry only. Timers are not
+//! cancelled: one whose session has since drained its queue or scheduled an
+//! earlier expiry is dropped by the session store when it comes up.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+/// Granularity of message expiry; the broker sweeps due messages this often
+pub const EXPIRY_TIMER_RESOLUTION: Duration = Duration::from_secs(1);
+
+/// Slots in the wheel; expiries beyond this many seconds take several turns
+const SLOTS: u64 = 256;
+
+/// A session's earliest message expiry
+pub(crate) struct ExpiryTimer {
+    pub(crate) client_id: Arc<str>,
+    pub(crate) deadline: Instant,
+    tick: u64,
+}
+
+struct Wheel {
+    slots: Vec<Vec<ExpiryTimer>>,
+    /// First tick not yet expired
+    next_tick: u64,
+}
+
+/// Pending message expiries of a session store
+pub(crate) struct ExpiryTimers {
+    start: Instant,
+    wheel: Mutex<Wheel>,
+}
+
+impl ExpiryTimers {
+    pub(crate) fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            wheel: Mutex::new(Wheel {
+                slots: (0..SLOTS).map(|_| Vec::new()).collect(),
+                next_tick: 0,
+            }),
+        }
+    }
+
+    /// Schedule a sweep of a client's queue at `deadline`
+    pub(crate) fn schedule(&self, client_id: Arc<str>, deadline: Instant) {
+        // Round up so a message is never removed early
+        let due = deadline.saturating_duration_since(self.start);
+        let tick = due
+            .as_millis()
+            .div_ceil(EXPIRY_TIMER_RESOLUTION.as_millis()) as u64;
+
+        let mut wheel = self.wheel.lock();
+        let tick = tick.max(wheel.next_tick);
+        wheel.slots[(tick % SLOTS) as usize].push(ExpiryTimer {
+            client_id,
+            deadline,
+            tick,
+        });
+    }
+
+    /// Number of scheduled timers, including ones that are no longer live
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.wheel.lock().slots.iter().map(Vec::len).sum()
+    }
+
+    /// Remove and return the timers due by `now`
+    pub(crate) fn expire(&self, now: Instant) -> Vec<ExpiryTimer> {
+        let now_tick = (now.saturating_duration_since(self.start).as_millis()
+            / EXPIRY_TIMER_RESOLUTION.as_millis()) as u64;
+
+        let mut wheel = self.wheel.lock();
+        if now_tick < wheel.next_tick {
+            return Vec::new();
+        }
+        // After a stall of a full turn or more, every slot is visited once
+        let first = wheel.next_tick.max((now_tick + 1).saturating_sub(SLOTS));
+        wheel.next_tick = now_tick + 1;
+
+        let slots = &mut wheel.slots;
+        let mut due = Vec::new();
+        for tick in first..=now_tick {
+            let slot = &mut slots[(tick % SLOTS) as usize];
+            let mut i = 0;
+            while i < slot.len() {
+                if slot[i].tick > now_tick {
+                    i += 1; // Due in a later turn
+                    continue;
+                }
+                due.push(slot.swap_remove(i));
+            }
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expired(timers: &ExpiryTimers, after: Duration) -> Vec<String> {
+        timers
+            .expire(timers.start + after)
+            .into_iter()
+            .map(|t| t.client_id.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_timers_expire_at_their_deadline() {
+        let timers = ExpiryTimers::new();
+        timers.schedule("a".into(), timers.start + Duration::from_millis(1500));
+        timers.schedule("b".into(), timers.start + Duration::from_secs(600));
+        assert_eq!(timers.len(), 2);
+
+        assert!(expired(&timers, Duration::from_millis(1999)).is_empty());
+        assert_eq!(expired(&timers, Duration::from_secs(2)), vec!["a"]);
+        // Past a full turn of the wheel, but not yet due
+        assert!(expired(&timers, Duration::from_secs(300)).is_empty());
+        assert_eq!(expired(&timers, Duration::from_secs(1000)), vec!["b"]);
+        assert_eq!(timers.len(), 0);
+    }
+
+    #[test]
+    fn test_past_deadline_expires_on_next_tick() {
+        let timers = ExpiryTimers::new();
+        assert!(expired(&timers, Duration::from_secs(5)).is_empty());
+        timers.schedule("a".into(), timers.start + Duration::from_secs(1));
+        assert_eq!(expired(&timers, Duration::from_secs(6)), vec!["a"]);
+    }
+}
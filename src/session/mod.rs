@@ -12,15 +12,33 @@
 //! - Implements message expiry per MQTT v5.0 spec [MQTT-3.3.2-5]
 
 use std::collections::VecDeque;
-use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use ahash::AHashMap;
 use bytes::Bytes;
-use dashmap::DashMap;
 use parking_lot::RwLock;
+use serde::Serialize;
 
+use crate::config::{MessagePriority, QueueOverflowPolicy, TopicPriorityRule};
+use crate::persistence::{
+    PersistenceError, StoredPendingMessage, StoredSession, StoredSpill, StoredSpillSegment,
+};
 use crate::protocol::{Properties, ProtocolVersion, Publish, QoS, SubscriptionOptions};
+use crate::sharded::ShardedMap;
+use crate::topic::{topic_matches_filter, Topic};
+
+mod expiry;
+mod spill;
+mod wills;
+
+use expiry::ExpiryTimers;
+pub use expiry::EXPIRY_TIMER_RESOLUTION;
+pub use spill::{QueueSpill, SPILL_INTERVAL, SPILL_KEY_PREFIX};
+use spill::{SegmentState, SpillSegment};
+use wills::WillTimers;
+pub use wills::{DueWill, WILL_TIMER_RESOLUTION};
 
 /// A pending message with timestamp for expiry tracking
 #[derive(Debug, Clone)]
@@ -29,6 +47,27 @@ pub struct PendingMessage {
     pub publish: Publish,
     /// When this message was queued
     pub queued_at: Instant,
+    /// Priority class of the message's topic
+    pub priority: MessagePriority,
+}
+
+impl PendingMessage {
+    /// When the message expires, if it has a Message Expiry Interval
+    pub fn deadline(&self) -> Option<Instant> {
+        self.publish
+            .properties
+            .message_expiry_interval
+            .map(|expiry| self.queued_at + Duration::from_secs(u64::from(expiry)))
+    }
+}
+
+/// Priority of a topic under the configured rules; the first matching rule
+/// applies and unmatched topics are normal priority
+pub fn topic_priority(rules: &[TopicPriorityRule], topic: &str) -> MessagePriority {
+    rules
+        .iter()
+        .find(|rule| topic_matches_filter(topic, &rule.topic))
+        .map_or(MessagePriority::Normal, |rule| rule.priority)
 }
 
 /// Session state
@@ -93,6 +132,19 @@ pub struct Session {
     pub keep_alive: u16,
     /// Last activity timestamp
     pub last_activity: Instant,
+    /// When the current connection was established
+    pub connected_at: Instant,
+    /// Packets received on the current connection
+    pub packets_received: u64,
+    /// PUBLISH packets received on the current connection
+    pub messages_received: u64,
+    /// Payload bytes of the PUBLISH packets received on the current connection
+    pub bytes_received: u64,
+    /// PUBLISH packets written on the current connection; atomic so the
+    /// connection can count them under the read lock
+    pub messages_sent: AtomicU64,
+    /// Bytes of the PUBLISH packets written on the current connection
+    pub bytes_sent: AtomicU64,
     /// Subscriptions (uses Arc<str> keys for memory efficiency)
     pub subscriptions: AHashMap<Arc<str>, SessionSubscription>,
     /// Inflight outgoing messages (QoS 1/2) - uses AHashMap for faster lookup
@@ -105,6 +157,23 @@ pub struct Session {
     pub pending_messages: VecDeque<PendingMessage>,
     /// Maximum pending messages
     pub max_pending_messages: usize,
+    /// What to do when the pending queue is full
+    pub queue_overflow: QueueOverflowPolicy,
+    /// Priority classes of queued messages by topic
+    pub topic_priorities: Arc<[TopicPriorityRule]>,
+    /// Earliest expiry among the queued messages with an expiry timer
+    next_expiry: Option<Instant>,
+    /// Expiry timers of the session store holding this session
+    expiry_timers: Option<Arc<ExpiryTimers>>,
+    /// Queued messages behind `pending_messages` that spill, or have
+    /// spilled, to persistence
+    spilled: VecDeque<SpillSegment>,
+    /// Queue spilling of the session store holding this session
+    spill: Option<Arc<QueueSpill>>,
+    /// Messages dropped because the pending queue was full
+    pub dropped_messages: u64,
+    /// Retransmissions of unacked outgoing messages
+    pub retransmits: u64,
     /// Maximum in-flight outgoing messages (QoS 1/2)
     pub max_inflight: u16,
     /// Maximum pending PUBREL (QoS 2 incoming)
@@ -116,11 +185,11 @@ pub struct Session {
     /// Maximum packet size
     pub max_packet_size: u32,
     /// Topic aliases (client -> server) - uses AHashMap for faster lookup
-    pub client_topic_aliases: AHashMap<u16, String>,
+    pub client_topic_aliases: AHashMap<u16, Topic>,
     /// Topic aliases (server -> client) - uses AHashMap for faster lookup
     pub server_topic_aliases: AHashMap<String, u16>,
-    /// Next server topic alias
-    next_server_alias: u16,
+    /// Next server topic alias (past `u16::MAX` once all are used)
+    next_server_alias: u32,
     /// Maximum topic alias
     pub topic_alias_maximum: u16,
     /// Will message
@@ -129,6 +198,8 @@ pub struct Session {
     pub will_delay_interval: u32,
     /// Disconnect timestamp
     pub disconnected_at: Option<Instant>,
+    /// Tenant of the client, partitioning its persisted state
+    pub tenant: Option<Arc<str>>,
 }
 
 /// Will message
@@ -141,19 +212,180 @@ pub struct WillMessage {
     pub properties: Properties,
 }
 
+impl WillMessage {
+    /// The PUBLISH sent for this will, `elapsed` after the client
+    /// disconnected
+    ///
+    /// Every will property but the Will Delay Interval is re-emitted
+    /// [MQTT-3.1.3-9]. The Message Expiry Interval counts from the
+    /// disconnect, so a will that expired while delayed is not published.
+    pub fn to_publish(&self, elapsed: Duration) -> Option<Publish> {
+        let message_expiry_interval = match self.properties.message_expiry_interval {
+            Some(expiry) => {
+                let elapsed = elapsed.as_secs().min(u32::MAX as u64) as u32;
+                if elapsed >= expiry {
+                    return None;
+                }
+                Some(expiry - elapsed)
+            }
+            None => None,
+        };
+        Some(Publish {
+            dup: false,
+            qos: self.qos,
+            retain: self.retain,
+            topic: Topic::from(self.topic.as_str()),
+            packet_id: None,
+            payload: self.payload.clone(),
+            properties: Properties {
+                payload_format_indicator: self.properties.payload_format_indicator,
+                message_expiry_interval,
+                content_type: self.properties.content_type.clone(),
+                response_topic: self.properties.response_topic.clone(),
+                correlation_data: self.properties.correlation_data.clone(),
+                user_properties: self.properties.user_properties.clone(),
+                ..Default::default()
+            },
+        })
+    }
+}
+
 /// Result of queueing a message
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+///
+/// Each overflow outcome carries the topic of the message it dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum QueueResult {
     /// Message was queued successfully
     Queued,
     /// Message was queued but an older message was dropped due to queue overflow
-    DroppedOldest,
+    DroppedOldest(Topic),
+    /// Message was dropped due to queue overflow
+    DroppedNewest(Topic),
+    /// Message was dropped and the client must be disconnected, under the
+    /// disconnect overflow policy
+    Overflow(Topic),
+}
+
+impl QueueResult {
+    /// Whether a message was lost to queue overflow
+    pub fn is_dropped(&self) -> bool {
+        *self != QueueResult::Queued
+    }
+
+    /// Topic of the message lost to queue overflow
+    pub fn dropped_topic(&self) -> Option<&str> {
+        match self {
+            QueueResult::Queued => None,
+            QueueResult::DroppedOldest(topic)
+            | QueueResult::DroppedNewest(topic)
+            | QueueResult::Overflow(topic) => Some(topic),
+        }
+    }
+}
+
+/// Snapshot of a session's state, for debugging stuck QoS flows
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionStats {
+    pub client_id: String,
+    pub connected: bool,
+    pub protocol_version: u8,
+    /// Seconds since a packet was last received
+    pub idle_secs: u64,
+    /// Packets received on the current connection
+    pub packets_received: u64,
+    /// Average packets per second received on the current connection
+    pub packet_rate: f64,
+    /// When the current connection was established (Unix seconds), if
+    /// the client is connected
+    pub connected_at: Option<u64>,
+    /// PUBLISH packets received on the current connection
+    pub messages_received: u64,
+    /// PUBLISH packets sent on the current connection
+    pub messages_sent: u64,
+    /// Payload bytes received on the current connection
+    pub bytes_received: u64,
+    /// Bytes of the PUBLISH packets sent on the current connection
+    pub bytes_sent: u64,
+    /// Messages waiting in the pending queue
+    pub queued_messages: usize,
+    /// Queued messages spilled, or about to spill, to persistence
+    pub spilled_messages: usize,
+    /// Payload bytes of the queued messages held in memory
+    pub queued_bytes: usize,
+    /// Messages dropped because the pending queue was full
+    pub dropped_messages: u64,
+    /// Retransmissions of unacked outgoing messages
+    pub retransmits: u64,
+    /// Outgoing QoS 1 and 2 messages waiting for PUBACK or PUBREC
+    pub inflight_awaiting_ack: usize,
+    /// Outgoing QoS 2 messages waiting for PUBCOMP
+    pub inflight_awaiting_comp: usize,
+    /// Incoming QoS 2 messages waiting for PUBREL
+    pub incoming_awaiting_rel: usize,
+    pub subscriptions: Vec<SessionSubscriptionStats>,
+    /// Messages the client may still receive before acknowledging any
+    pub send_quota: u16,
+    pub receive_maximum: u16,
+    pub max_inflight: u16,
+    pub max_queued_messages: usize,
+    pub max_awaiting_rel: usize,
+    pub session_expiry_interval: u32,
+    /// Seconds until a disconnected session expires, if it ever does
+    pub expires_in_secs: Option<u64>,
+}
+
+/// Subscription entry of a session snapshot
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSubscriptionStats {
+    pub filter: String,
+    pub qos: u8,
+    pub subscription_id: Option<u32>,
+}
+
+/// Packets received from a client since the session was last updated
+///
+/// Clients pipelining small messages deliver hundreds of packets in one
+/// read; counting them here and recording them with
+/// [`Session::record_activity`] once per read takes the session lock once
+/// rather than once per packet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InboundActivity {
+    pub packets: u64,
+    pub messages: u64,
+    pub bytes: u64,
+}
+
+impl InboundActivity {
+    /// Count a packet received from the client
+    pub fn add_packet(&mut self) {
+        self.packets += 1;
+    }
+
+    /// Count a PUBLISH received from the client
+    pub fn add_publish(&mut self, payload_len: usize) {
+        self.messages += 1;
+        self.bytes += payload_len as u64;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets == 0
+    }
+
+    /// Record the packets counted so far on `session` and start over,
+    /// locking the session only if there are any
+    pub fn flush(&mut self, session: &RwLock<Session>) {
+        if !self.is_empty() {
+            session.write().record_activity(std::mem::take(self));
+        }
+    }
 }
 
 /// Session limits configuration
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct SessionLimits {
     pub max_pending_messages: usize,
+    pub queue_overflow: QueueOverflowPolicy,
+    pub topic_priorities: Arc<[TopicPriorityRule]>,
     pub max_inflight: u16,
     pub max_awaiting_rel: usize,
 }
@@ -162,6 +394,8 @@ impl Default for SessionLimits {
     fn default() -> Self {
         Self {
             max_pending_messages: 1000,
+            queue_overflow: QueueOverflowPolicy::default(),
+            topic_priorities: Arc::from([]),
             max_inflight: 32,
             max_awaiting_rel: 100,
         }
@@ -182,6 +416,12 @@ impl Session {
             session_expiry_interval: 0,
             keep_alive: 60,
             last_activity: Instant::now(),
+            connected_at: Instant::now(),
+            packets_received: 0,
+            messages_received: 0,
+            bytes_received: 0,
+            messages_sent: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
             // Start empty, grow on demand
             subscriptions: AHashMap::new(),
             inflight_outgoing: AHashMap::new(),
@@ -189,6 +429,14 @@ impl Session {
             next_packet_id: 1,
             pending_messages: VecDeque::new(),
             max_pending_messages: limits.max_pending_messages,
+            queue_overflow: limits.queue_overflow,
+            topic_priorities: limits.topic_priorities,
+            next_expiry: None,
+            expiry_timers: None,
+            spilled: VecDeque::new(),
+            spill: None,
+            dropped_messages: 0,
+            retransmits: 0,
             max_inflight: limits.max_inflight,
             max_awaiting_rel: limits.max_awaiting_rel,
             receive_maximum: 65535,
@@ -201,6 +449,7 @@ impl Session {
             will: None,
             will_delay_interval: 0,
             disconnected_at: None,
+            tenant: None,
         }
     }
 
@@ -222,11 +471,69 @@ impl Session {
         }
     }
 
+    /// Packet identifiers of inflight outgoing messages in the order they
+    /// were sent, for re-sending them in their original order [MQTT-4.6.0-1]
+    pub fn inflight_outgoing_in_order(&self) -> Vec<u16> {
+        let mut ids: Vec<u16> = self.inflight_outgoing.keys().copied().collect();
+        ids.sort_unstable();
+        // Identifiers are assigned in increasing order, wrapping past
+        // u16::MAX to 1, so the oldest follows the widest gap between them
+        if let (Some(&first), Some(&last)) = (ids.first(), ids.last()) {
+            let mut widest = u32::from(first) + u32::from(u16::MAX) - u32::from(last);
+            let mut oldest = 0;
+            for (i, pair) in ids.windows(2).enumerate() {
+                let gap = u32::from(pair[1] - pair[0]);
+                if gap > widest {
+                    widest = gap;
+                    oldest = i + 1;
+                }
+            }
+            ids.rotate_left(oldest);
+        }
+        ids
+    }
+
     /// Update last activity timestamp
     pub fn touch(&mut self) {
         self.last_activity = Instant::now();
     }
 
+    /// Record a packet received from the client
+    pub fn record_packet(&mut self) {
+        self.touch();
+        self.packets_received += 1;
+    }
+
+    /// Record a PUBLISH received from the client
+    pub fn record_publish_received(&mut self, payload_len: usize) {
+        self.messages_received += 1;
+        self.bytes_received += payload_len as u64;
+    }
+
+    /// Record packets received from the client, counted outside the lock
+    pub fn record_activity(&mut self, activity: InboundActivity) {
+        self.touch();
+        self.packets_received += activity.packets;
+        self.messages_received += activity.messages;
+        self.bytes_received += activity.bytes;
+    }
+
+    /// Record a PUBLISH of `bytes` written to the client
+    pub fn record_publish_sent(&self, bytes: usize) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Average packets per second received on the current connection
+    pub fn packet_rate(&self) -> f64 {
+        let secs = self.connected_at.elapsed().as_secs_f64();
+        if secs > 0.0 {
+            self.packets_received as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
     /// Check if session has expired
     pub fn is_expired(&self) -> bool {
         if self.state != SessionState::Disconnected {
@@ -261,20 +568,350 @@ impl Session {
     }
 
     /// Queue a message for later delivery
-    /// Returns QueueResult::DroppedOldest if the queue was full and an older message was dropped
+    ///
+    /// Messages are kept in priority order, oldest first within a priority
+    /// class. When the queue is full, a queued message of a lower class than
+    /// the new one is dropped first; otherwise the session's overflow policy
+    /// decides which message of the lowest class is dropped, or whether the
+    /// client must be disconnected. Spilled messages count towards the limit
+    /// but are never dropped for a new one.
     pub fn queue_message(&mut self, publish: Publish) -> QueueResult {
-        let result = if self.pending_messages.len() >= self.max_pending_messages {
-            // Drop oldest message
-            self.pending_messages.pop_front();
-            QueueResult::DroppedOldest
-        } else {
-            QueueResult::Queued
+        self.queue(publish, true)
+    }
+
+    /// Put back a message taken from the head of the queue, ahead of any
+    /// spilled messages
+    pub fn requeue_message(&mut self, publish: Publish) -> QueueResult {
+        self.queue(publish, false)
+    }
+
+    fn queue(&mut self, publish: Publish, behind_spilled: bool) -> QueueResult {
+        let priority = topic_priority(&self.topic_priorities, &publish.topic);
+        if self.queued_len() < self.max_pending_messages {
+            self.enqueue(publish, priority, behind_spilled);
+            return QueueResult::Queued;
+        }
+
+        self.dropped_messages += 1;
+        let lowest = self.pending_messages.iter().map(|pm| pm.priority).min();
+        if let Some(lowest) = lowest.filter(|&lowest| lowest < priority) {
+            if let Some(dropped) = self.remove_oldest(|pm| pm.priority == lowest) {
+                self.enqueue(publish, priority, behind_spilled);
+                return QueueResult::DroppedOldest(dropped);
+            }
+        }
+
+        let removed = match self.queue_overflow {
+            QueueOverflowPolicy::DropOldest => self.remove_oldest(|pm| pm.priority == priority),
+            QueueOverflowPolicy::DropNewest => None,
+            QueueOverflowPolicy::Disconnect => {
+                if self.state == SessionState::Connected {
+                    return QueueResult::Overflow(publish.topic);
+                }
+                // Nothing to disconnect while offline
+                None
+            }
+            QueueOverflowPolicy::DropQos0First => self
+                .remove_oldest(|pm| pm.priority == priority && pm.publish.qos == QoS::AtMostOnce)
+                .or_else(|| {
+                    (publish.qos != QoS::AtMostOnce)
+                        .then(|| self.remove_oldest(|pm| pm.priority == priority))
+                        .flatten()
+                }),
         };
-        self.pending_messages.push_back(PendingMessage {
+        match removed {
+            Some(dropped) => {
+                self.enqueue(publish, priority, behind_spilled);
+                QueueResult::DroppedOldest(dropped)
+            }
+            None => QueueResult::DroppedNewest(publish.topic),
+        }
+    }
+
+    /// Queue a message in memory, or behind the spilled messages if there
+    /// are any and `behind_spilled` is set
+    fn enqueue(&mut self, publish: Publish, priority: MessagePriority, behind_spilled: bool) {
+        let message = PendingMessage {
             publish,
             queued_at: Instant::now(),
-        });
-        result
+            priority,
+        };
+        if behind_spilled && !self.spilled.is_empty() {
+            if let Some(SpillSegment {
+                count,
+                state: SegmentState::Open(messages),
+                ..
+            }) = self.spilled.back_mut()
+            {
+                *count += 1;
+                messages.push(message);
+                return;
+            }
+            if self.spill.is_some() {
+                self.spilled.push_back(SpillSegment {
+                    key: None,
+                    count: 1,
+                    state: SegmentState::Open(vec![message]),
+                });
+                return;
+            }
+        }
+        self.push_pending(message);
+    }
+
+    /// Queue a message in memory behind all queued messages of the same or
+    /// a higher priority
+    fn push_pending(&mut self, message: PendingMessage) {
+        let index = self
+            .pending_messages
+            .iter()
+            .rposition(|pm| pm.priority >= message.priority)
+            .map_or(0, |i| i + 1);
+        if let Some(deadline) = message.deadline() {
+            self.schedule_expiry(deadline);
+        }
+        self.pending_messages.insert(index, message);
+    }
+
+    /// Messages queued in memory and spilled
+    pub fn queued_len(&self) -> usize {
+        self.pending_messages.len() + self.spilled_len()
+    }
+
+    /// Messages queued behind the in-memory queue, whether or not they have
+    /// been written to persistence yet
+    pub fn spilled_len(&self) -> usize {
+        self.spilled.iter().map(|segment| segment.count).sum()
+    }
+
+    /// Whether any message is queued, in memory or spilled
+    pub fn has_queued(&self) -> bool {
+        !self.pending_messages.is_empty() || !self.spilled.is_empty()
+    }
+
+    /// Whether some queued messages are only in spilled segments
+    pub fn has_spilled(&self) -> bool {
+        !self.spilled.is_empty()
+    }
+
+    /// Queued messages held in memory
+    fn in_memory_len(&self) -> usize {
+        self.pending_messages.len()
+            + self
+                .spilled
+                .iter()
+                .map(SpillSegment::in_memory)
+                .sum::<usize>()
+    }
+
+    /// Whether the queue holds more messages in memory than a persistent
+    /// session may keep before spilling
+    fn needs_spill(&self, threshold: usize) -> bool {
+        self.session_expiry_interval > 0 && self.in_memory_len() > threshold
+    }
+
+    /// Move the queue beyond the spill threshold into segments and hand out
+    /// the segments still to be written
+    fn take_spill_segments(&mut self, spill: &QueueSpill) -> Vec<(String, StoredSpillSegment)> {
+        if !self.needs_spill(spill.threshold()) {
+            return Vec::new();
+        }
+        if self.spilled.is_empty() {
+            // Keep the head of the queue in memory
+            let tail: Vec<PendingMessage> =
+                self.pending_messages.split_off(spill.threshold()).into();
+            self.spilled.push_back(SpillSegment {
+                key: None,
+                count: tail.len(),
+                state: SegmentState::Open(tail),
+            });
+            self.reschedule_expiry();
+        }
+
+        let mut segments = Vec::new();
+        for segment in &mut self.spilled {
+            if let SegmentState::Open(ref mut messages) = segment.state {
+                let messages = std::mem::take(messages);
+                let stored = StoredSpillSegment {
+                    messages: messages.iter().map(StoredPendingMessage::from).collect(),
+                };
+                segment.state = SegmentState::Writing(messages);
+                let key = segment.key.get_or_insert_with(|| spill.next_key());
+                segments.push((key.clone(), stored));
+            }
+        }
+        segments
+    }
+
+    /// Drop the in-memory copies of segments now written to persistence,
+    /// returning those delivered in the meantime, which can be deleted
+    fn spill_written(&mut self, keys: &[String]) -> Vec<String> {
+        let mut delivered = Vec::new();
+        for key in keys {
+            match self.spilled.iter_mut().find(|segment| segment.is(key)) {
+                Some(segment) => segment.state = SegmentState::Stored,
+                None => delivered.push(key.clone()),
+            }
+        }
+        delivered
+    }
+
+    /// Keep segments that failed to be written in memory, to retry later
+    fn spill_failed(&mut self, keys: &[String]) {
+        for segment in &mut self.spilled {
+            if !keys.iter().any(|key| segment.is(key)) {
+                continue;
+            }
+            if let SegmentState::Writing(ref mut messages) = segment.state {
+                segment.state = SegmentState::Open(std::mem::take(messages));
+            }
+        }
+    }
+
+    /// Move spilled messages still in memory back into the queue until it
+    /// holds `threshold` messages, returning the next segment to read from
+    /// persistence if one is needed
+    fn next_spilled(&mut self, threshold: usize) -> Option<String> {
+        while self.pending_messages.len() < threshold {
+            let segment = self.spilled.front_mut()?;
+            match segment.state {
+                SegmentState::Stored => {
+                    segment.state = SegmentState::Loading;
+                    return segment.key.clone();
+                }
+                SegmentState::Loading => return None,
+                SegmentState::Open(ref mut messages) | SegmentState::Writing(ref mut messages) => {
+                    // A segment still being written is deleted once it lands
+                    let messages = std::mem::take(messages);
+                    self.spilled.pop_front();
+                    for message in messages {
+                        self.push_pending(message);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Put the messages read back from a spilled segment into the queue
+    fn finish_load(&mut self, key: &str, messages: impl IntoIterator<Item = PendingMessage>) {
+        let Some(index) = self.spilled.iter().position(|segment| segment.is(key)) else {
+            return;
+        };
+        let Some(segment) = self.spilled.remove(index) else {
+            return;
+        };
+
+        let mut loaded = 0;
+        for mut message in messages {
+            message.priority = topic_priority(&self.topic_priorities, &message.publish.topic);
+            self.push_pending(message);
+            loaded += 1;
+        }
+        // Lost with a segment that could not be read
+        self.dropped_messages += segment.count.saturating_sub(loaded) as u64;
+    }
+
+    /// Make sure a sweep of the queue is scheduled by `deadline`
+    fn schedule_expiry(&mut self, deadline: Instant) {
+        if self.next_expiry.is_some_and(|next| next <= deadline) {
+            return;
+        }
+        self.next_expiry = Some(deadline);
+        if let Some(ref timers) = self.expiry_timers {
+            timers.schedule(self.client_id.clone(), deadline);
+        }
+    }
+
+    /// Schedule a sweep for the earliest expiry in the queue
+    fn reschedule_expiry(&mut self) {
+        self.next_expiry = None;
+        if let Some(deadline) = self
+            .pending_messages
+            .iter()
+            .filter_map(PendingMessage::deadline)
+            .min()
+        {
+            self.schedule_expiry(deadline);
+        }
+    }
+
+    /// Remove the oldest queued message matching `pred`, returning its
+    /// topic if one was found
+    fn remove_oldest(&mut self, pred: impl Fn(&PendingMessage) -> bool) -> Option<Topic> {
+        let index = self.pending_messages.iter().position(pred)?;
+        self.pending_messages
+            .remove(index)
+            .map(|pm| pm.publish.topic)
+    }
+
+    /// Replace the pending queue with restored messages, classifying them
+    /// under this session's topic priorities
+    pub fn restore_pending(&mut self, messages: impl IntoIterator<Item = PendingMessage>) {
+        let mut messages: Vec<PendingMessage> = messages
+            .into_iter()
+            .map(|mut pm| {
+                pm.priority = topic_priority(&self.topic_priorities, &pm.publish.topic);
+                pm
+            })
+            .collect();
+        // Stable, so each class keeps its queued order
+        messages.sort_by_key(|pm| std::cmp::Reverse(pm.priority));
+        self.pending_messages = messages.into();
+        self.reschedule_expiry();
+    }
+
+    /// The spilled part of the queue in stored form: written segments by
+    /// key, and the messages of the others
+    pub(crate) fn stored_spilled(&self) -> Vec<StoredSpill> {
+        self.spilled
+            .iter()
+            .filter_map(|segment| match segment.state {
+                SegmentState::Open(ref messages) | SegmentState::Writing(ref messages) => {
+                    Some(StoredSpill::Messages(
+                        messages.iter().map(StoredPendingMessage::from).collect(),
+                    ))
+                }
+                SegmentState::Stored | SegmentState::Loading => {
+                    segment.key.clone().map(|key| StoredSpill::Segment {
+                        key,
+                        count: segment.count as u32,
+                    })
+                }
+            })
+            .collect()
+    }
+
+    /// Put the spilled part of a restored queue behind the pending queue;
+    /// its segments are read back from the spill store as it is worked
+    /// through
+    pub(crate) fn restore_spilled(&mut self, spilled: Vec<StoredSpill>) {
+        for part in spilled {
+            let segment = match part {
+                StoredSpill::Segment { key, count } => SpillSegment {
+                    key: Some(key),
+                    count: count as usize,
+                    state: SegmentState::Stored,
+                },
+                StoredSpill::Messages(messages) if !messages.is_empty() => SpillSegment {
+                    key: None,
+                    count: messages.len(),
+                    state: SegmentState::Open(
+                        messages
+                            .into_iter()
+                            .map(|message| {
+                                let mut pm = PendingMessage::from(message);
+                                pm.priority =
+                                    topic_priority(&self.topic_priorities, &pm.publish.topic);
+                                pm
+                            })
+                            .collect(),
+                    ),
+                },
+                StoredSpill::Messages(_) => continue,
+            };
+            self.spilled.push_back(segment);
+        }
     }
 
     /// Get and remove pending messages, filtering expired ones per MQTT-3.3.2-5
@@ -282,6 +919,7 @@ impl Session {
     pub fn drain_pending_messages(&mut self) -> VecDeque<Publish> {
         let now = Instant::now();
         let pending = std::mem::take(&mut self.pending_messages);
+        self.next_expiry = None;
 
         pending
             .into_iter()
@@ -313,6 +951,7 @@ impl Session {
                 true // No expiry, keep the message
             }
         });
+        self.reschedule_expiry();
     }
 
     /// Add a subscription
@@ -339,33 +978,43 @@ impl Session {
         self.subscriptions.remove(filter).is_some()
     }
 
-    /// Get a topic alias for server->client
-    pub fn get_or_create_topic_alias(&mut self, topic: &str) -> Option<u16> {
+    /// Get a topic alias for server->client, and whether it was just
+    /// created, in which case the topic must be sent along to establish it
+    pub fn get_or_create_topic_alias(&mut self, topic: &str) -> Option<(u16, bool)> {
         if self.topic_alias_maximum == 0 {
             return None;
         }
 
         if let Some(&alias) = self.server_topic_aliases.get(topic) {
-            return Some(alias);
+            return Some((alias, false));
         }
 
-        if self.next_server_alias <= self.topic_alias_maximum {
-            let alias = self.next_server_alias;
+        if self.next_server_alias <= u32::from(self.topic_alias_maximum) {
+            let alias = self.next_server_alias as u16;
             self.next_server_alias += 1;
             self.server_topic_aliases.insert(topic.to_string(), alias);
-            Some(alias)
+            Some((alias, true))
         } else {
             None
         }
     }
 
+    /// Drop the topic aliases of the previous network connection, which do
+    /// not outlive it [MQTT-3.3.2-7], and set the client's Topic Alias Maximum
+    pub fn reset_topic_aliases(&mut self, topic_alias_maximum: u16) {
+        self.client_topic_aliases.clear();
+        self.server_topic_aliases.clear();
+        self.next_server_alias = 1;
+        self.topic_alias_maximum = topic_alias_maximum;
+    }
+
     /// Resolve a client topic alias
-    pub fn resolve_topic_alias(&self, alias: u16) -> Option<&String> {
+    pub fn resolve_topic_alias(&self, alias: u16) -> Option<&Topic> {
         self.client_topic_aliases.get(&alias)
     }
 
     /// Register a client topic alias
-    pub fn register_topic_alias(&mut self, alias: u16, topic: String) {
+    pub fn register_topic_alias(&mut self, alias: u16, topic: Topic) {
         self.client_topic_aliases.insert(alias, topic);
     }
 
@@ -379,23 +1028,174 @@ impl Session {
         }
     }
 
+    /// Reset the state that belongs to a network connection rather than to
+    /// the session, when a client connects or resumes the session
+    ///
+    /// Topic aliases start over [MQTT-3.3.2-7] and flow control follows the
+    /// new CONNECT, with `None` meaning the protocol default. Unacknowledged
+    /// messages are kept for re-sending and still count against the send
+    /// quota [MQTT-4.9.0-2].
+    pub fn reset_connection_state(
+        &mut self,
+        receive_maximum: Option<u16>,
+        max_packet_size: Option<u32>,
+        topic_alias_maximum: u16,
+    ) {
+        self.reset_topic_aliases(topic_alias_maximum);
+        self.connected_at = Instant::now();
+        self.packets_received = 0;
+        self.messages_received = 0;
+        self.bytes_received = 0;
+        self.messages_sent.store(0, Ordering::Relaxed);
+        self.bytes_sent.store(0, Ordering::Relaxed);
+        self.receive_maximum = receive_maximum.unwrap_or(65535);
+        self.max_packet_size = max_packet_size.unwrap_or(268_435_455);
+        let inflight = u16::try_from(self.inflight_outgoing.len()).unwrap_or(u16::MAX);
+        self.send_quota = self.receive_maximum.saturating_sub(inflight);
+    }
+
     /// Increment send quota (on ack received)
     pub fn increment_send_quota(&mut self) {
         if self.send_quota < self.receive_maximum {
             self.send_quota += 1;
         }
     }
+
+    /// Snapshot of the session's queues, inflight flows and quotas
+    pub fn stats(&self) -> SessionStats {
+        let awaiting_comp = self
+            .inflight_outgoing
+            .values()
+            .filter(|im| im.qos2_state == Some(Qos2State::WaitingPubComp))
+            .count();
+        let mut subscriptions: Vec<SessionSubscriptionStats> = self
+            .subscriptions
+            .values()
+            .map(|sub| SessionSubscriptionStats {
+                filter: sub.filter.clone(),
+                qos: sub.options.qos as u8,
+                subscription_id: sub.subscription_id,
+            })
+            .collect();
+        subscriptions.sort_by(|a, b| a.filter.cmp(&b.filter));
+
+        let expires_in_secs = match (
+            self.state == SessionState::Disconnected,
+            self.disconnected_at,
+        ) {
+            (true, Some(disconnected_at)) if self.session_expiry_interval != 0xFFFFFFFF => Some(
+                u64::from(self.session_expiry_interval)
+                    .saturating_sub(disconnected_at.elapsed().as_secs()),
+            ),
+            _ => None,
+        };
+
+        SessionStats {
+            client_id: self.client_id.to_string(),
+            connected: self.state == SessionState::Connected,
+            protocol_version: self.protocol_version as u8,
+            idle_secs: self.last_activity.elapsed().as_secs(),
+            packets_received: self.packets_received,
+            packet_rate: self.packet_rate(),
+            connected_at: (self.state == SessionState::Connected).then(|| {
+                SystemTime::now()
+                    .checked_sub(self.connected_at.elapsed())
+                    .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |at| at.as_secs())
+            }),
+            messages_received: self.messages_received,
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received,
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            queued_messages: self.queued_len(),
+            spilled_messages: self.spilled_len(),
+            queued_bytes: self
+                .pending_messages
+                .iter()
+                .map(|pm| pm.publish.payload.len())
+                .sum(),
+            dropped_messages: self.dropped_messages,
+            retransmits: self.retransmits,
+            inflight_awaiting_ack: self.inflight_outgoing.len() - awaiting_comp,
+            inflight_awaiting_comp: awaiting_comp,
+            incoming_awaiting_rel: self.inflight_incoming.len(),
+            subscriptions,
+            send_quota: self.send_quota,
+            receive_maximum: self.receive_maximum,
+            max_inflight: self.max_inflight,
+            max_queued_messages: self.max_pending_messages,
+            max_awaiting_rel: self.max_awaiting_rel,
+            session_expiry_interval: self.session_expiry_interval,
+            expires_in_secs,
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // Spilled messages are only reachable through their session
+        if let Some(ref spill) = self.spill {
+            spill.delete(
+                self.spilled
+                    .iter()
+                    .filter(|segment| !matches!(segment.state, SegmentState::Open(_)))
+                    .filter_map(|segment| segment.key.clone()),
+            );
+        }
+    }
 }
 
 /// Thread-safe session store
 pub struct SessionStore {
-    sessions: DashMap<Arc<str>, Arc<RwLock<Session>>>,
+    sessions: ShardedMap<Arc<RwLock<Session>>>,
+    /// Wills waiting out their delay after an ungraceful disconnect
+    wills: WillTimers,
+    /// Earliest message expiry of each session with expiring messages
+    expiries: Arc<ExpiryTimers>,
+    /// Spilling of large queues to persistence, if enabled
+    spill: OnceLock<Arc<QueueSpill>>,
 }
 
 impl SessionStore {
     pub fn new() -> Self {
+        Self::with_workers(crate::broker::num_cpus::get())
+    }
+
+    /// Create a store sharded for `workers` worker threads
+    pub fn with_workers(workers: usize) -> Self {
         Self {
-            sessions: DashMap::new(),
+            sessions: ShardedMap::new(workers),
+            wills: WillTimers::new(),
+            expiries: Arc::new(ExpiryTimers::new()),
+            spill: OnceLock::new(),
+        }
+    }
+
+    /// Spill queues of sessions created from now on beyond the threshold of
+    /// `spill`; has no effect if spilling is already enabled
+    pub fn enable_spill(&self, spill: QueueSpill) {
+        let _ = self.spill.set(Arc::new(spill));
+    }
+
+    /// Queue spilling, if enabled
+    pub fn spill(&self) -> Option<&Arc<QueueSpill>> {
+        self.spill.get()
+    }
+
+    /// Spill the queues of persistent sessions holding more messages in
+    /// memory than the spill threshold
+    pub async fn spill_queues(&self) {
+        let Some(spill) = self.spill.get() else {
+            return;
+        };
+        let mut sessions = Vec::new();
+        self.sessions.for_each(|_, session| {
+            if session.read().needs_spill(spill.threshold()) {
+                sessions.push(session.clone());
+            }
+        });
+        for session in sessions {
+            spill.spill(&session).await;
         }
     }
 
@@ -411,12 +1211,7 @@ impl SessionStore {
 
         if clean_start {
             // Create new session
-            let session = Arc::new(RwLock::new(Session::new(
-                client_id.clone(),
-                protocol_version,
-                limits,
-            )));
-            self.sessions.insert(client_id, session.clone());
+            let session = self.insert(Session::new(client_id, protocol_version, limits));
             (session, false)
         } else {
             // Try to resume existing session
@@ -427,24 +1222,69 @@ impl SessionStore {
                     s.protocol_version = protocol_version;
                     s.disconnected_at = None;
                     drop(s);
-                    return (session.clone(), true);
+                    return (session, true);
                 }
             }
 
             // Create new session
-            let session = Arc::new(RwLock::new(Session::new(
-                client_id.clone(),
-                protocol_version,
-                limits,
-            )));
-            self.sessions.insert(client_id, session.clone());
+            let session = self.insert(Session::new(client_id, protocol_version, limits));
             (session, false)
         }
     }
 
+    /// Insert a session, replacing any existing one for the same client ID
+    pub fn insert(&self, mut session: Session) -> Arc<RwLock<Session>> {
+        let client_id = session.client_id.clone();
+        session.expiry_timers = Some(self.expiries.clone());
+        session.spill = self.spill.get().cloned();
+        session.reschedule_expiry();
+        let session = Arc::new(RwLock::new(session));
+        self.sessions.insert(client_id, session.clone());
+        session
+    }
+
+    /// A session in its portable form, with any spilled messages read back
+    pub async fn export_stored(&self, session: &Arc<RwLock<Session>>) -> StoredSession {
+        if let Some(spill) = self.spill.get() {
+            spill.load_all(session).await;
+        }
+        StoredSession::from_session(&session.read())
+    }
+
+    /// Serialize a client's session (subscriptions, queued and inflight
+    /// messages, will) to migrate it to another broker
+    ///
+    /// The session stays in this store. Returns `None` if the client has no
+    /// session.
+    pub async fn export(&self, client_id: &str) -> Result<Option<Vec<u8>>, PersistenceError> {
+        let Some(session) = self.get(client_id) else {
+            return Ok(None);
+        };
+        self.export_stored(&session).await.export().map(Some)
+    }
+
+    /// Install a session from its portable form, disconnected and replacing
+    /// any session of the same client
+    pub fn import_stored(
+        &self,
+        stored: StoredSession,
+        limits: SessionLimits,
+    ) -> Arc<RwLock<Session>> {
+        self.insert(stored.into_session(limits))
+    }
+
+    /// Install a session serialized by `export` on this or another broker
+    pub fn import(
+        &self,
+        bytes: &[u8],
+        limits: SessionLimits,
+    ) -> Result<Arc<RwLock<Session>>, PersistenceError> {
+        Ok(self.import_stored(StoredSession::import(bytes)?, limits))
+    }
+
     /// Get a session by client ID
     pub fn get(&self, client_id: &str) -> Option<Arc<RwLock<Session>>> {
-        self.sessions.get(client_id).map(|r| r.clone())
+        self.sessions.get(client_id)
     }
 
     /// Remove a session
@@ -454,31 +1294,126 @@ impl SessionStore {
 
     /// Mark session as disconnected
     pub fn disconnect(&self, client_id: &str) {
-        let should_remove = if let Some(session) = self.sessions.get(client_id) {
+        let Some(session) = self.sessions.get(client_id) else {
+            return;
+        };
+        let should_remove = {
             let mut s = session.write();
             s.state = SessionState::Disconnected;
             s.disconnected_at = Some(Instant::now());
             s.session_expiry_interval == 0
-        } else {
-            false
         };
 
-        // Remove after releasing the DashMap read lock to avoid deadlock
+        // Only if no new session took its place meanwhile
         if should_remove {
-            self.sessions.remove(client_id);
+            self.sessions
+                .remove_if(client_id, |current| Arc::ptr_eq(current, &session));
         }
     }
 
-    /// Clean up expired sessions and expired messages within sessions
-    /// Per MQTT v5.0 spec [MQTT-3.3.2-5]: expired messages MUST be deleted
-    pub fn cleanup_expired(&self) {
-        self.sessions.retain(|_, session| {
+    /// Publish a disconnected session's will after `delay`, unless the
+    /// client reconnects first [MQTT-3.1.3-9]
+    pub fn schedule_will(
+        &self,
+        session: &Arc<RwLock<Session>>,
+        delay: Duration,
+        tenant: Option<Arc<str>>,
+    ) {
+        self.wills.schedule(session, delay, tenant, Instant::now());
+    }
+
+    /// Cancel a client's delayed will; returns true if one was pending
+    pub fn cancel_will(&self, client_id: &str) -> bool {
+        self.wills.cancel(client_id)
+    }
+
+    /// Number of delayed wills not yet due
+    pub fn pending_wills(&self) -> usize {
+        self.wills.len()
+    }
+
+    /// Take the wills whose delay has elapsed from their sessions
+    ///
+    /// A will is skipped if its session was replaced, reconnected or
+    /// disconnected again since it was scheduled.
+    pub fn take_due_wills(&self) -> Vec<DueWill> {
+        self.wills
+            .expire(Instant::now())
+            .into_iter()
+            .filter_map(|timer| {
+                let session = timer.session.upgrade()?;
+                let is_current = self
+                    .get(&timer.client_id)
+                    .is_some_and(|s| Arc::ptr_eq(&s, &session));
+                if !is_current {
+                    return None;
+                }
+                let mut s = session.write();
+                if s.state != SessionState::Disconnected
+                    || s.disconnected_at != timer.disconnected_at
+                {
+                    return None;
+                }
+                let will = s.will.take()?;
+                Some(DueWill {
+                    client_id: timer.client_id,
+                    will,
+                    disconnected_at: timer.disconnected_at,
+                    tenant: timer.tenant,
+                })
+            })
+            .collect()
+    }
+
+    /// Clean up expired sessions
+    ///
+    /// Returns the wills still waiting on their delay in the removed
+    /// sessions, which are due now that the session has ended
+    /// [MQTT-3.1.3-9].
+    pub fn cleanup_expired(&self) -> Vec<DueWill> {
+        self.cleanup_expired_with(|_| {})
+    }
+
+    /// Clean up expired sessions like [`Self::cleanup_expired`], passing
+    /// the client ID of each removed session to `expired`
+    pub fn cleanup_expired_with(&self, mut expired: impl FnMut(&Arc<str>)) -> Vec<DueWill> {
+        let mut due = Vec::new();
+        self.sessions.retain(|client_id, session| {
+            // Return false to remove session if it's expired; expired
+            // messages are removed by `expire_messages`
             let mut s = session.write();
-            // Clean up expired messages in this session
-            s.cleanup_expired_messages();
-            // Return false to remove session if it's expired
-            !s.is_expired()
+            if !s.is_expired() {
+                return true;
+            }
+            expired(client_id);
+            if let Some(timer) = self.wills.take(client_id) {
+                if let Some(will) = s.will.take() {
+                    due.push(DueWill {
+                        client_id: client_id.clone(),
+                        will,
+                        disconnected_at: s.disconnected_at,
+                        tenant: timer.tenant,
+                    });
+                }
+            }
+            false
         });
+        due
+    }
+
+    /// Remove queued messages whose Message Expiry Interval has passed
+    /// [MQTT-3.3.2-5], visiting only the sessions with a message due
+    pub fn expire_messages(&self) {
+        for timer in self.expiries.expire(Instant::now()) {
+            let Some(session) = self.get(&timer.client_id) else {
+                continue;
+            };
+            let mut s = session.write();
+            // A drained queue or an earlier expiry makes the timer stale
+            if s.next_expiry == Some(timer.deadline) {
+                s.cleanup_expired_messages();
+            }
+        }
     }
 
     /// Get session count
@@ -493,25 +1428,39 @@ impl SessionStore {
     /// Count disconnected sessions (not yet expired)
     /// For $SYS/broker/clients/inactive and clients/disconnected
     pub fn count_disconnected(&self) -> usize {
+        let mut count = 0;
+        self.sessions.for_each(|_, session| {
+            let session = session.read();
+            if session.state == SessionState::Disconnected && !session.is_expired() {
+                count += 1;
+            }
+        });
+        count
+    }
+
+    /// Snapshot of a client's session, or `None` if it has none
+    pub fn stats(&self, client_id: &str) -> Option<SessionStats> {
         self.sessions
-            .iter()
-            .filter(|entry| {
-                let session = entry.value().read();
-                session.state == SessionState::Disconnected && !session.is_expired()
-            })
-            .count()
+            .get(client_id)
+            .map(|session| session.read().stats())
+    }
+
+    /// Snapshots of all sessions
+    /// For $SYS/sessions/<client>/...
+    pub fn all_stats(&self) -> Vec<SessionStats> {
+        let mut stats = Vec::with_capacity(self.sessions.len());
+        self.sessions
+            .for_each(|_, session| stats.push(session.read().stats()));
+        stats
     }
 
     /// Count total queued messages across all sessions
     /// For $SYS/broker/messages/stored
     pub fn total_queued_messages(&self) -> usize {
+        let mut total = 0;
         self.sessions
-            .iter()
-            .map(|entry| {
-                let session = entry.value().read();
-                session.pending_messages.len()
-            })
-            .sum()
+            .for_each(|_, session| total += session.read().queued_len());
+        total
     }
 }
 
@@ -556,6 +1505,77 @@ mod tests {
         assert_eq!(session.send_quota, 0);
     }
 
+    /// Connection state resets on resume while inflight messages keep
+    /// counting against the send quota
+    #[test]
+    fn test_reset_connection_state() {
+        let mut session =
+            Session::new("test".into(), ProtocolVersion::V5, SessionLimits::default());
+        session.reset_connection_state(Some(10), Some(1024), 5);
+        assert_eq!(session.get_or_create_topic_alias("a"), Some((1, true)));
+        let publish = Publish {
+            topic: "a".into(),
+            payload: Bytes::from_static(b"x"),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            dup: false,
+            packet_id: Some(1),
+            properties: Properties::default(),
+        };
+        for packet_id in 1..=3 {
+            session.inflight_outgoing.insert(
+                packet_id,
+                InflightMessage {
+                    packet_id,
+                    publish: publish.clone(),
+                    qos2_state: None,
+                    sent_at: Instant::now(),
+                    retry_count: 0,
+                },
+            );
+        }
+
+        session.reset_connection_state(Some(2), None, 0);
+        assert!(session.server_topic_aliases.is_empty());
+        assert_eq!(session.get_or_create_topic_alias("a"), None);
+        assert_eq!(session.receive_maximum, 2);
+        assert_eq!(session.send_quota, 0);
+        assert_eq!(session.max_packet_size, 268_435_455);
+        assert_eq!(session.inflight_outgoing.len(), 3);
+
+        session.record_packet();
+        assert_eq!(session.packets_received, 1);
+        session.reset_connection_state(None, None, 0);
+        assert_eq!(session.packets_received, 0);
+        assert_eq!(session.receive_maximum, 65535);
+        assert_eq!(session.send_quota, 65532);
+    }
+
+    #[test]
+    fn test_inbound_activity_batch() {
+        let session = RwLock::new(Session::new(
+            Arc::from("client"),
+            ProtocolVersion::V5,
+            SessionLimits::default(),
+        ));
+
+        let mut activity = InboundActivity::default();
+        activity.flush(&session);
+        assert_eq!(session.read().packets_received, 0);
+
+        for _ in 0..100 {
+            activity.add_packet();
+            activity.add_publish(10);
+        }
+        activity.add_packet();
+        activity.flush(&session);
+        assert!(activity.is_empty());
+
+        let s = session.read();
+        assert_eq!(s.packets_received, 101);
+        assert_eq!((s.messages_received, s.bytes_received), (100, 1000));
+    }
+
     /// Test MQTT-3.3.2-5: Message expiry interval enforcement
     #[test]
     fn test_message_expiry_cleanup() {
@@ -564,7 +1584,7 @@ mod tests {
 
         // Create a message with 1 second expiry
         let mut publish1 = Publish {
-            topic: "test/topic".to_string(),
+            topic: "test/topic".into(),
             payload: bytes::Bytes::from("test1"),
             qos: QoS::AtLeastOnce,
             retain: false,
@@ -576,7 +1596,7 @@ mod tests {
 
         // Create a message with no expiry
         let publish2 = Publish {
-            topic: "test/topic".to_string(),
+            topic: "test/topic".into(),
             payload: bytes::Bytes::from("test2"),
             qos: QoS::AtLeastOnce,
             retain: false,
@@ -587,7 +1607,7 @@ mod tests {
 
         // Create a message with long expiry
         let mut publish3 = Publish {
-            topic: "test/topic".to_string(),
+            topic: "test/topic".into(),
             payload: bytes::Bytes::from("test3"),
             qos: QoS::AtLeastOnce,
             retain: false,
@@ -629,7 +1649,7 @@ mod tests {
             Session::new("test".into(), ProtocolVersion::V5, SessionLimits::default());
 
         let mut publish = Publish {
-            topic: "test/topic".to_string(),
+            topic: "test/topic".into(),
             payload: bytes::Bytes::from("test"),
             qos: QoS::AtLeastOnce,
             retain: false,
@@ -668,7 +1688,7 @@ mod tests {
             Session::new("test".into(), ProtocolVersion::V5, SessionLimits::default());
 
         let mut publish1 = Publish {
-            topic: "test/topic".to_string(),
+            topic: "test/topic".into(),
             payload: bytes::Bytes::from("expires"),
             qos: QoS::AtLeastOnce,
             retain: false,
@@ -679,7 +1699,7 @@ mod tests {
         publish1.properties.message_expiry_interval = Some(1);
 
         let publish2 = Publish {
-            topic: "test/topic".to_string(),
+            topic: "test/topic".into(),
             payload: bytes::Bytes::from("no_expiry"),
             qos: QoS::AtLeastOnce,
             retain: false,
@@ -705,4 +1725,679 @@ mod tests {
             "no_expiry"
         );
     }
+
+    #[test]
+    fn test_server_topic_aliases() {
+        let mut session =
+            Session::new("test".into(), ProtocolVersion::V5, SessionLimits::default());
+        assert_eq!(session.get_or_create_topic_alias("a"), None);
+
+        session.reset_topic_aliases(2);
+        assert_eq!(session.get_or_create_topic_alias("a"), Some((1, true)));
+        assert_eq!(session.get_or_create_topic_alias("a"), Some((1, false)));
+        assert_eq!(session.get_or_create_topic_alias("b"), Some((2, true)));
+        assert_eq!(session.get_or_create_topic_alias("c"), None);
+
+        // A new connection starts over
+        session.reset_topic_aliases(u16::MAX);
+        assert_eq!(session.get_or_create_topic_alias("b"), Some((1, true)));
+        session.next_server_alias = u32::from(u16::MAX);
+        assert_eq!(
+            session.get_or_create_topic_alias("c"),
+            Some((u16::MAX, true))
+        );
+        assert_eq!(session.get_or_create_topic_alias("d"), None);
+    }
+
+    #[test]
+    fn test_inflight_outgoing_in_order() {
+        let mut session =
+            Session::new("test".into(), ProtocolVersion::V5, SessionLimits::default());
+        let mut insert = |packet_id: u16| {
+            session.inflight_outgoing.insert(
+                packet_id,
+                InflightMessage {
+                    packet_id,
+                    publish: Publish {
+                        topic: "test/topic".into(),
+                        payload: bytes::Bytes::new(),
+                        qos: QoS::AtLeastOnce,
+                        retain: false,
+                        dup: false,
+                        packet_id: Some(packet_id),
+                        properties: Properties::default(),
+                    },
+                    qos2_state: None,
+                    sent_at: Instant::now(),
+                    retry_count: 0,
+                },
+            );
+        };
+
+        // Sent in the order 65534, 65535, 1, 2, with 3 already acknowledged
+        for packet_id in [4, 65534, 1, 65535, 2] {
+            insert(packet_id);
+        }
+        assert_eq!(
+            session.inflight_outgoing_in_order(),
+            vec![65534, 65535, 1, 2, 4]
+        );
+
+        session.inflight_outgoing.retain(|&id, _| id < 100);
+        assert_eq!(session.inflight_outgoing_in_order(), vec![1, 2, 4]);
+    }
+
+    #[test]
+    fn test_queue_overflow_policies() {
+        let publish = |payload: &'static str, qos: QoS| Publish {
+            topic: "test/topic".into(),
+            payload: Bytes::from_static(payload.as_bytes()),
+            qos,
+            retain: false,
+            dup: false,
+            packet_id: None,
+            properties: Properties::default(),
+        };
+        let queued = |session: &Session| -> Vec<Bytes> {
+            session
+                .pending_messages
+                .iter()
+                .map(|pm| pm.publish.payload.clone())
+                .collect()
+        };
+        let session = |queue_overflow| {
+            let limits = SessionLimits {
+                max_pending_messages: 2,
+                queue_overflow,
+                ..Default::default()
+            };
+            let mut session = Session::new("test".into(), ProtocolVersion::V5, limits);
+            session.queue_message(publish("a", QoS::AtLeastOnce));
+            session.queue_message(publish("b", QoS::AtMostOnce));
+            session
+        };
+
+        let mut s = session(QueueOverflowPolicy::DropOldest);
+        assert_eq!(
+            s.queue_message(publish("c", QoS::AtLeastOnce)),
+            QueueResult::DroppedOldest("test/topic".into())
+        );
+        assert_eq!(queued(&s), vec!["b", "c"]);
+
+        let mut s = session(QueueOverflowPolicy::DropNewest);
+        assert_eq!(
+            s.queue_message(publish("c", QoS::AtLeastOnce)),
+            QueueResult::DroppedNewest("test/topic".into())
+        );
+        assert_eq!(queued(&s), vec!["a", "b"]);
+
+        let mut s = session(QueueOverflowPolicy::Disconnect);
+        assert_eq!(
+            s.queue_message(publish("c", QoS::AtLeastOnce)),
+            QueueResult::Overflow("test/topic".into())
+        );
+        s.state = SessionState::Disconnected;
+        assert_eq!(
+            s.queue_message(publish("c", QoS::AtLeastOnce)),
+            QueueResult::DroppedNewest("test/topic".into())
+        );
+        assert_eq!(queued(&s), vec!["a", "b"]);
+
+        // QoS 0 messages go first, queued or new
+        let mut s = session(QueueOverflowPolicy::DropQos0First);
+        assert_eq!(
+            s.queue_message(publish("c", QoS::AtLeastOnce)),
+            QueueResult::DroppedOldest("test/topic".into())
+        );
+        assert_eq!(queued(&s), vec!["a", "c"]);
+        assert_eq!(
+            s.queue_message(publish("d", QoS::AtMostOnce)),
+            QueueResult::DroppedNewest("test/topic".into())
+        );
+        assert_eq!(
+            s.queue_message(publish("e", QoS::ExactlyOnce)),
+            QueueResult::DroppedOldest("test/topic".into())
+        );
+        assert_eq!(queued(&s), vec!["c", "e"]);
+        assert_eq!(s.dropped_messages, 3);
+    }
+
+    #[test]
+    fn test_dropped_topic() {
+        let publish = |topic: &str| Publish {
+            topic: topic.into(),
+            payload: Bytes::new(),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            dup: false,
+            packet_id: None,
+            properties: Properties::default(),
+        };
+        let session = |queue_overflow| {
+            let limits = SessionLimits {
+                max_pending_messages: 1,
+                queue_overflow,
+                ..Default::default()
+            };
+            let mut session = Session::new("test".into(), ProtocolVersion::V5, limits);
+            session.queue_message(publish("old"));
+            session
+        };
+
+        let mut s = session(QueueOverflowPolicy::DropOldest);
+        assert_eq!(s.queue_message(publish("new")).dropped_topic(), Some("old"));
+        assert_eq!(
+            s.queue_message(publish("newer")).dropped_topic(),
+            Some("new")
+        );
+
+        let mut s = session(QueueOverflowPolicy::DropNewest);
+        assert_eq!(s.queue_message(publish("new")).dropped_topic(), Some("new"));
+
+        assert_eq!(QueueResult::Queued.dropped_topic(), None);
+    }
+
+    #[test]
+    fn test_session_stats() {
+        let store = SessionStore::new();
+        let (session, _) = store.get_or_create(
+            "client",
+            ProtocolVersion::V5,
+            true,
+            SessionLimits::default(),
+        );
+        assert!(store.stats("other").is_none());
+
+        {
+            let mut s = session.write();
+            s.add_subscription(
+                "b/#".to_string(),
+                SubscriptionOptions {
+                    qos: QoS::AtLeastOnce,
+                    ..Default::default()
+                },
+                Some(7),
+            );
+            s.add_subscription("a".to_string(), SubscriptionOptions::default(), None);
+            let publish = Publish {
+                topic: "a".into(),
+                payload: Bytes::from_static(b"hello"),
+                qos: QoS::ExactlyOnce,
+                retain: false,
+                dup: false,
+                packet_id: Some(1),
+                properties: Properties::default(),
+            };
+            s.queue_message(publish.clone());
+            for (packet_id, qos2_state) in [
+                (1, Some(Qos2State::WaitingPubRec)),
+                (2, Some(Qos2State::WaitingPubComp)),
+            ] {
+                s.inflight_outgoing.insert(
+                    packet_id,
+                    InflightMessage {
+                        packet_id,
+                        publish: publish.clone(),
+                        qos2_state,
+                        sent_at: Instant::now(),
+                        retry_count: 0,
+                    },
+                );
+            }
+            s.inflight_incoming.insert(3, publish);
+            s.session_expiry_interval = 60;
+            s.record_publish_received(5);
+            s.record_publish_sent(12);
+            s.record_publish_sent(30);
+        }
+
+        let stats = store.stats("client").unwrap();
+        assert!(stats.connected);
+        assert!(stats.connected_at.is_some());
+        assert_eq!((stats.messages_received, stats.bytes_received), (1, 5));
+        assert_eq!((stats.messages_sent, stats.bytes_sent), (2, 42));
+        assert_eq!(stats.queued_messages, 1);
+        assert_eq!(stats.queued_bytes, 5);
+        assert_eq!(stats.inflight_awaiting_ack, 1);
+        assert_eq!(stats.inflight_awaiting_comp, 1);
+        assert_eq!(stats.incoming_awaiting_rel, 1);
+        let filters: Vec<&str> = stats
+            .subscriptions
+            .iter()
+            .map(|s| s.filter.as_str())
+            .collect();
+        assert_eq!(filters, vec!["a", "b/#"]);
+        assert_eq!(stats.subscriptions[1].qos, 1);
+        assert_eq!(stats.subscriptions[1].subscription_id, Some(7));
+        assert_eq!(stats.expires_in_secs, None);
+
+        store.disconnect("client");
+        let stats = store.stats("client").unwrap();
+        assert!(!stats.connected);
+        assert_eq!(stats.connected_at, None);
+        assert_eq!(stats.expires_in_secs, Some(60));
+        assert_eq!(store.all_stats().len(), 1);
+    }
+
+    /// Test MQTT-3.3.2-5: the store removes expired messages on their timer
+    #[test]
+    fn test_store_expire_messages() {
+        let store = SessionStore::new();
+        let publish = |expiry: Option<u32>| {
+            let mut publish = Publish {
+                topic: "test/topic".into(),
+                payload: Bytes::from_static(b"x"),
+                qos: QoS::AtLeastOnce,
+                retain: false,
+                dup: false,
+                packet_id: None,
+                properties: Properties::default(),
+            };
+            publish.properties.message_expiry_interval = expiry;
+            publish
+        };
+        let session = |client_id: &str| {
+            store
+                .get_or_create(
+                    client_id,
+                    ProtocolVersion::V5,
+                    true,
+                    SessionLimits::default(),
+                )
+                .0
+        };
+
+        let (a, b, c) = (session("a"), session("b"), session("c"));
+        a.write().queue_message(publish(Some(1)));
+        a.write().queue_message(publish(Some(100)));
+        a.write().queue_message(publish(None));
+        b.write().queue_message(publish(None));
+        c.write().queue_message(publish(Some(1)));
+        // Only the earliest expiry of a session has a timer
+        assert_eq!(store.expiries.len(), 2);
+        // A drained queue leaves its timer stale
+        assert_eq!(c.write().drain_pending_messages().len(), 1);
+
+        thread::sleep(Duration::from_secs(2));
+        store.expire_messages();
+        assert_eq!(a.read().pending_messages.len(), 2);
+        assert_eq!(b.read().pending_messages.len(), 1);
+        // The next expiry of the session is scheduled
+        assert_eq!(store.expiries.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_session_export_import() {
+        let source = SessionStore::new();
+        let (session, _) = source.get_or_create(
+            "client",
+            ProtocolVersion::V5,
+            false,
+            SessionLimits::default(),
+        );
+        let publish = |topic: &str, packet_id: Option<u16>| Publish {
+            topic: topic.into(),
+            payload: Bytes::from_static(b"x"),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            dup: false,
+            packet_id,
+            properties: Properties::default(),
+        };
+        {
+            let mut s = session.write();
+            s.session_expiry_interval = 3600;
+            s.add_subscription(
+                "sensors/#".to_string(),
+                SubscriptionOptions::default(),
+                Some(3),
+            );
+            s.queue_message(publish("sensors/queued", None));
+            s.inflight_outgoing.insert(
+                7,
+                InflightMessage {
+                    packet_id: 7,
+                    publish: publish("sensors/inflight", Some(7)),
+                    qos2_state: Some(Qos2State::WaitingPubComp),
+                    sent_at: Instant::now(),
+                    retry_count: 0,
+                },
+            );
+        }
+
+        let bytes = source.export("client").await.unwrap().unwrap();
+        assert!(source.export("unknown").await.unwrap().is_none());
+        // Exporting leaves the session in place
+        assert!(source.get("client").is_some());
+
+        let target = SessionStore::new();
+        let imported = target.import(&bytes, SessionLimits::default()).unwrap();
+        assert!(Arc::ptr_eq(&imported, &target.get("client").unwrap()));
+        let s = imported.read();
+        assert_eq!(s.state, SessionState::Disconnected);
+        assert_eq!(s.session_expiry_interval, 3600);
+        assert_eq!(s.subscriptions["sensors/#"].subscription_id, Some(3));
+        assert_eq!(s.pending_messages[0].publish.topic, "sensors/queued");
+        assert_eq!(
+            s.inflight_outgoing[&7].qos2_state,
+            Some(Qos2State::WaitingPubComp)
+        );
+
+        assert!(target
+            .import(b"not a session", SessionLimits::default())
+            .is_err());
+        assert!(target
+            .import(&bytes[..bytes.len() - 1], SessionLimits::default())
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_spill_queue() {
+        use crate::config::SyncMode;
+        use crate::persistence::{MemoryBackend, PersistenceManager};
+
+        let dir = tempfile::tempdir().unwrap();
+        let backend = MemoryBackend::open(dir.path(), Duration::ZERO).unwrap();
+        let persistence = Arc::new(PersistenceManager::new(
+            Arc::new(backend),
+            Duration::from_millis(10),
+            100,
+            SyncMode::Never,
+        ));
+        let store = SessionStore::new();
+        store.enable_spill(QueueSpill::new(persistence, 2));
+        let spill = store.spill().unwrap().clone();
+
+        let (session, _) = store.get_or_create(
+            "client",
+            ProtocolVersion::V5,
+            false,
+            SessionLimits::default(),
+        );
+        session.write().session_expiry_interval = 60;
+        let publish = |i: usize| Publish {
+            topic: format!("test/{}", i).into(),
+            payload: Bytes::from_static(b"x"),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            dup: false,
+            packet_id: None,
+            properties: Properties::default(),
+        };
+        let drain = |session: &Arc<RwLock<Session>>| -> Vec<String> {
+            session
+                .write()
+                .drain_pending_messages()
+                .into_iter()
+                .map(|p| p.topic.to_string())
+                .collect()
+        };
+
+        for i in 0..5 {
+            session.write().queue_message(publish(i));
+        }
+        store.spill_queues().await;
+        {
+            let s = session.read();
+            assert_eq!(s.pending_messages.len(), 2);
+            assert_eq!(s.spilled_len(), 3);
+            assert_eq!(s.in_memory_len(), 2);
+        }
+
+        // Newer messages queue behind the spilled ones, and spill in turn
+        for i in 5..7 {
+            assert_eq!(
+                session.write().queue_message(publish(i)),
+                QueueResult::Queued
+            );
+        }
+        assert_eq!(session.read().pending_messages.len(), 2);
+        store.spill_queues().await;
+        assert_eq!(session.read().in_memory_len(), 2);
+        assert_eq!(session.read().stats().queued_messages, 7);
+
+        // A message put back stays ahead of the spilled ones
+        assert_eq!(drain(&session), vec!["test/0", "test/1"]);
+        session.write().requeue_message(publish(1));
+        assert_eq!(drain(&session), vec!["test/1"]);
+
+        // Spilled messages come back in order, a segment at a time
+        spill.load(&session).await;
+        assert_eq!(drain(&session), vec!["test/2", "test/3", "test/4"]);
+        spill.load(&session).await;
+        assert_eq!(drain(&session), vec!["test/5", "test/6"]);
+        assert!(!session.read().has_queued());
+    }
+
+    #[tokio::test]
+    async fn test_spilled_queue_survives_restore() {
+        use crate::config::SyncMode;
+        use crate::persistence::{MemoryBackend, PersistenceManager};
+
+        let dir = tempfile::tempdir().unwrap();
+        let backend = MemoryBackend::open(dir.path(), Duration::ZERO).unwrap();
+        let persistence = Arc::new(PersistenceManager::new(
+            Arc::new(backend),
+            Duration::from_millis(10),
+            100,
+            SyncMode::Never,
+        ));
+        let publish = |i: usize| Publish {
+            topic: format!("test/{}", i).into(),
+            payload: Bytes::from_static(b"x"),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            dup: false,
+            packet_id: None,
+            properties: Properties::default(),
+        };
+
+        let store = SessionStore::new();
+        store.enable_spill(QueueSpill::new(persistence.clone(), 2));
+        let (session, _) = store.get_or_create(
+            "client",
+            ProtocolVersion::V5,
+            false,
+            SessionLimits::default(),
+        );
+        session.write().session_expiry_interval = 60;
+        for i in 0..5 {
+            session.write().queue_message(publish(i));
+        }
+        store.spill_queues().await;
+        // Queued behind the written segment, not yet written itself
+        session.write().queue_message(publish(5));
+
+        let stored = StoredSession::from_session(&session.read());
+        assert_eq!(stored.pending_messages.len(), 2);
+        assert_eq!(stored.spill_keys().count(), 1);
+        // The process stops without dropping the session and its segments
+        std::mem::forget(session);
+        std::mem::forget(store);
+
+        // Another process picks the session up with its segments
+        let store = SessionStore::new();
+        store.enable_spill(QueueSpill::new(persistence, 2));
+        let session = store.import_stored(stored, SessionLimits::default());
+        assert_eq!(session.read().queued_len(), 6);
+        let mut topics = Vec::new();
+        while session.read().has_queued() {
+            store.spill().unwrap().load(&session).await;
+            topics.extend(
+                session
+                    .write()
+                    .drain_pending_messages()
+                    .into_iter()
+                    .map(|p| p.topic),
+            );
+        }
+        assert_eq!(
+            topics,
+            vec!["test/0", "test/1", "test/2", "test/3", "test/4", "test/5"]
+        );
+    }
+
+    #[test]
+    fn test_queue_topic_priorities() {
+        let publish = |topic: &str, payload: &'static str| Publish {
+            topic: topic.into(),
+            payload: Bytes::from_static(payload.as_bytes()),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            dup: false,
+            packet_id: None,
+            properties: Properties::default(),
+        };
+        let queued = |session: &Session| -> Vec<Bytes> {
+            session
+                .pending_messages
+                .iter()
+                .map(|pm| pm.publish.payload.clone())
+                .collect()
+        };
+        let rule = |topic: &str, priority| TopicPriorityRule {
+            topic: topic.to_string(),
+            priority,
+        };
+        let limits = SessionLimits {
+            max_pending_messages: 3,
+            topic_priorities: vec![
+                rule("alarms/#", MessagePriority::High),
+                rule("bulk/#", MessagePriority::Low),
+            ]
+            .into(),
+            ..Default::default()
+        };
+        let mut s = Session::new("test".into(), ProtocolVersion::V5, limits);
+
+        // High priority messages are queued ahead of the others
+        s.queue_message(publish("bulk/a", "b1"));
+        s.queue_message(publish("data/a", "n1"));
+        s.queue_message(publish("alarms/a", "h1"));
+        assert_eq!(queued(&s), vec!["h1", "n1", "b1"]);
+
+        // Low priority messages are shed first
+        assert_eq!(
+            s.queue_message(publish("alarms/b", "h2")),
+            QueueResult::DroppedOldest("bulk/a".into())
+        );
+        assert_eq!(queued(&s), vec!["h1", "h2", "n1"]);
+        assert_eq!(
+            s.queue_message(publish("bulk/b", "b2")),
+            QueueResult::DroppedNewest("bulk/b".into())
+        );
+
+        // Within the lowest class the overflow policy applies
+        assert_eq!(
+            s.queue_message(publish("data/b", "n2")),
+            QueueResult::DroppedOldest("data/a".into())
+        );
+        assert_eq!(queued(&s), vec!["h1", "h2", "n2"]);
+        assert_eq!(s.dropped_messages, 3);
+
+        // Restored messages are reclassified
+        let restored: Vec<PendingMessage> = s
+            .pending_messages
+            .iter()
+            .rev()
+            .cloned()
+            .map(|mut pm| {
+                pm.priority = MessagePriority::Normal;
+                pm
+            })
+            .collect();
+        s.restore_pending(restored);
+        assert_eq!(queued(&s), vec!["h2", "h1", "n2"]);
+    }
+
+    #[test]
+    fn test_will_to_publish_keeps_properties() {
+        let will = WillMessage {
+            topic: "clients/c1/status".to_string(),
+            payload: Bytes::from_static(b"offline"),
+            qos: QoS::AtLeastOnce,
+            retain: true,
+            properties: Properties {
+                payload_format_indicator: Some(1),
+                message_expiry_interval: Some(10),
+                content_type: Some("text/plain".to_string()),
+                response_topic: Some("clients/c1/reply".to_string()),
+                correlation_data: Some(Bytes::from_static(b"corr")),
+                user_properties: vec![("k".to_string(), "v".to_string())],
+                will_delay_interval: Some(5),
+                ..Default::default()
+            },
+        };
+
+        let publish = will.to_publish(Duration::from_secs(4)).unwrap();
+        assert_eq!(publish.payload, will.payload);
+        assert!(publish.retain);
+        assert_eq!(publish.properties.payload_format_indicator, Some(1));
+        assert_eq!(publish.properties.message_expiry_interval, Some(6));
+        assert_eq!(
+            publish.properties.content_type.as_deref(),
+            Some("text/plain")
+        );
+        assert_eq!(
+            publish.properties.response_topic.as_deref(),
+            Some("clients/c1/reply")
+        );
+        assert_eq!(
+            publish.properties.correlation_data,
+            will.properties.correlation_data
+        );
+        assert_eq!(
+            publish.properties.user_properties,
+            will.properties.user_properties
+        );
+        // The Will Delay Interval is not a PUBLISH property
+        assert_eq!(publish.properties.will_delay_interval, None);
+
+        // Expired during the delay
+        assert!(will.to_publish(Duration::from_secs(10)).is_none());
+    }
+
+    #[test]
+    fn test_session_expiry_publishes_delayed_will() {
+        let store = SessionStore::new();
+        let will = WillMessage {
+            topic: "clients/c1/status".to_string(),
+            payload: Bytes::from_static(b"offline"),
+            qos: QoS::AtMostOnce,
+            retain: false,
+            properties: Properties::default(),
+        };
+        for client_id in ["waiting", "published"] {
+            let (session, _) = store.get_or_create(
+                client_id,
+                ProtocolVersion::V5,
+                false,
+                SessionLimits::default(),
+            );
+            let mut s = session.write();
+            s.session_expiry_interval = 1;
+            s.will = Some(will.clone());
+        }
+        store.disconnect("waiting");
+        store.disconnect("published");
+        // Only one will is still waiting on its delay
+        let waiting = store.get("waiting").unwrap();
+        store.schedule_will(&waiting, Duration::from_secs(60), Some("acme".into()));
+        for client_id in ["waiting", "published"] {
+            let session = store.get(client_id).unwrap();
+            session.write().disconnected_at = Some(Instant::now() - Duration::from_secs(2));
+        }
+
+        let mut expired = Vec::new();
+        let due = store.cleanup_expired_with(|client_id| expired.push(client_id.clone()));
+        expired.sort();
+        assert_eq!(expired, [Arc::from("published"), Arc::from("waiting")]);
+        assert_eq!(due.len(), 1);
+        assert_eq!(&*due[0].client_id, "waiting");
+        assert_eq!(due[0].will.topic, "clients/c1/status");
+        assert_eq!(due[0].tenant.as_deref(), Some("acme"));
+        assert!(store.is_empty());
+        assert_eq!(store.pending_wills(), 0);
+        assert!(waiting.read().will.is_none());
+    }
 }
@@ -0,0 +1,170 @@
+//! Queue Spilling
+//!
+//! A persistent session whose queue grows past the spill threshold keeps
+//! only the head of its queue in memory. The rest moves into segments that a
+//! background task writes to the persistence backend, and that are read back
+//! one at a time as the client works through its queue. Messages queued
+//! while segments exist join a segment at the tail, so the queue keeps its
+//! order across memory and storage; priority classes only reorder the part
+//! held in memory.
+//!
+//! A stored session refers to its written segments by key and carries the
+//! messages of the others, so the spilled part of its queue is kept across
+//! restarts. At startup, segments no stored session refers to are removed.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::RwLock;
+use tracing::{debug, warn};
+
+use super::{PendingMessage, Session};
+use crate::persistence::{PersistenceManager, PersistenceOp};
+
+/// How often queues over the threshold are spilled
+pub const SPILL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Key prefix of spilled session queue segments
+pub const SPILL_KEY_PREFIX: &str = "session/";
+
+/// Where the messages of a segment currently are
+pub(crate) enum SegmentState {
+    /// In memory, taking newly queued messages if it is the last segment
+    Open(Vec<PendingMessage>),
+    /// In memory until the write to the backend is confirmed
+    Writing(Vec<PendingMessage>),
+    /// In the backend only
+    Stored,
+    /// Being read back from the backend
+    Loading,
+}
+
+/// A run of queued messages behind the in-memory queue
+pub(crate) struct SpillSegment {
+    /// Key in the backend, given when the segment is first written
+    pub(crate) key: Option<String>,
+    pub(crate) count: usize,
+    pub(crate) state: SegmentState,
+}
+
+impl SpillSegment {
+    /// Whether the segment was written under `key`
+    pub(crate) fn is(&self, key: &str) -> bool {
+        self.key.as_deref() == Some(key)
+    }
+
+    /// Messages of the segment still held in memory
+    pub(crate) fn in_memory(&self) -> usize {
+        match self.state {
+            SegmentState::Open(ref messages) | SegmentState::Writing(ref messages) => {
+                messages.len()
+            }
+            SegmentState::Stored | SegmentState::Loading => 0,
+        }
+    }
+}
+
+/// Spills session queues to a persistence backend
+pub struct QueueSpill {
+    persistence: Arc<PersistenceManager>,
+    threshold: usize,
+    /// Keeps segment keys apart from those of other broker processes
+    instance: u64,
+    next_seq: AtomicU64,
+}
+
+impl QueueSpill {
+    /// Keep up to `threshold` queued messages per session in memory
+    pub fn new(persistence: Arc<PersistenceManager>, threshold: usize) -> Self {
+        Self {
+            persistence,
+            threshold,
+            instance: crate::broker::rand_id(),
+            next_seq: AtomicU64::new(0),
+        }
+    }
+
+    /// Queued messages per session kept in memory
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Key for a segment written for the first time
+    pub(crate) fn next_key(&self) -> String {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        format!("{}{:016x}{:016x}", SPILL_KEY_PREFIX, self.instance, seq)
+    }
+
+    /// Write the in-memory segments of a session's queue to the backend
+    pub async fn spill(&self, session: &Arc<RwLock<Session>>) {
+        let (client_id, segments) = {
+            let mut s = session.write();
+            (s.client_id.clone(), s.take_spill_segments(self))
+        };
+        if segments.is_empty() {
+            return;
+        }
+
+        let keys: Vec<String> = segments.iter().map(|(key, _)| key.clone()).collect();
+        let count: usize = segments.iter().map(|(_, s)| s.messages.len()).sum();
+        let ops = segments
+            .into_iter()
+            .map(|(key, segment)| PersistenceOp::SetSpill { key, segment })
+            .collect();
+
+        match self.persistence.write_sync(ops).await {
+            Ok(()) => {
+                debug!("Spilled {} queued messages of {}", count, client_id);
+                let delivered = session.write().spill_written(&keys);
+                self.delete(delivered);
+            }
+            Err(e) => {
+                warn!("Failed to spill queue of {}: {}", client_id, e);
+                session.write().spill_failed(&keys);
+            }
+        }
+    }
+
+    /// Read spilled segments back until the session holds `threshold`
+    /// queued messages in memory, or nothing is left in the backend
+    pub async fn load(&self, session: &Arc<RwLock<Session>>) {
+        self.load_until(session, self.threshold).await
+    }
+
+    /// Read every spilled segment of a session back into memory
+    pub async fn load_all(&self, session: &Arc<RwLock<Session>>) {
+        self.load_until(session, usize::MAX).await
+    }
+
+    async fn load_until(&self, session: &Arc<RwLock<Session>>, limit: usize) {
+        loop {
+            let Some(key) = session.write().next_spilled(limit) else {
+                return;
+            };
+
+            let messages = match self.persistence.get_spill(&key).await {
+                Ok(Some(segment)) => segment.messages,
+                Ok(None) => {
+                    warn!("Spilled queue segment {} is missing", key);
+                    Vec::new()
+                }
+                Err(e) => {
+                    warn!("Failed to read spilled queue segment {}: {}", key, e);
+                    Vec::new()
+                }
+            };
+            session
+                .write()
+                .finish_load(&key, messages.into_iter().map(PendingMessage::from));
+            self.delete([key]);
+        }
+    }
+
+    /// Delete segments from the backend
+    pub(crate) fn delete(&self, keys: impl IntoIterator<Item = String>) {
+        for key in keys {
+            self.persistence.write(PersistenceOp::DeleteSpill { key });
+        }
+    }
+}
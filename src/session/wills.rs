@@ -0,0 +1,254 @@
+//! Delayed Will Timers
+//!
+//! Wills with a delay wait in a hashed timer wheel of one-second slots
+//! until they are due, so a tick only looks at the slots that elapsed
+//! rather than at every disconnected session. Cancelling is lazy: a
+//! client's live timer is tracked by sequence number, and timers that no
+//! longer match are discarded when their slot comes up.
+//!
+//! A will is due when its delay elapses or its session ends, whichever
+//! comes first [MQTT-3.1.3-9]. The delay is capped by the session expiry
+//! interval when scheduled, but a session expiry sweep may still get to a
+//! session before its timer fires; it then takes the will from the wheel
+//! and publishes it itself.
+
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use ahash::AHashMap;
+use parking_lot::{Mutex, RwLock};
+
+use super::{Session, WillMessage};
+
+/// Granularity of will delays; the broker checks for due wills this often
+pub const WILL_TIMER_RESOLUTION: Duration = Duration::from_secs(1);
+
+/// Slots in the wheel; delays beyond this many seconds take several turns
+const SLOTS: u64 = 64;
+
+/// A will whose delay has elapsed, taken from its session
+#[derive(Debug, Clone)]
+pub struct DueWill {
+    pub client_id: Arc<str>,
+    pub will: WillMessage,
+    /// When the client disconnected, which the will's expiry counts from
+    pub disconnected_at: Option<Instant>,
+    /// Tenant of the client, for the retained store
+    pub tenant: Option<Arc<str>>,
+}
+
+/// A scheduled will
+pub(crate) struct WillTimer {
+    pub(crate) client_id: Arc<str>,
+    pub(crate) session: Weak<RwLock<Session>>,
+    /// Disconnect the timer belongs to; a later disconnect schedules anew
+    pub(crate) disconnected_at: Option<Instant>,
+    pub(crate) tenant: Option<Arc<str>>,
+    seq: u64,
+    tick: u64,
+}
+
+/// The timer of a client that fires
+pub(crate) struct LiveTimer {
+    seq: u64,
+    pub(crate) tenant: Option<Arc<str>>,
+}
+
+struct Wheel {
+    slots: Vec<Vec<WillTimer>>,
+    /// First tick not yet expired
+    next_tick: u64,
+    /// Each client's live timer
+    live: AHashMap<Arc<str>, LiveTimer>,
+    seq: u64,
+}
+
+/// Pending delayed wills of a session store
+pub(crate) struct WillTimers {
+    start: Instant,
+    wheel: Mutex<Wheel>,
+}
+
+impl WillTimers {
+    pub(crate) fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            wheel: Mutex::new(Wheel {
+                slots: (0..SLOTS).map(|_| Vec::new()).collect(),
+                next_tick: 0,
+                live: AHashMap::new(),
+                seq: 0,
+            }),
+        }
+    }
+
+    /// Schedule the will of a session that disconnected, replacing any
+    /// timer the client already has
+    pub(crate) fn schedule(
+        &self,
+        session: &Arc<RwLock<Session>>,
+        delay: Duration,
+        tenant: Option<Arc<str>>,
+        now: Instant,
+    ) {
+        let (client_id, disconnected_at) = {
+            let s = session.read();
+            (s.client_id.clone(), s.disconnected_at)
+        };
+        // Round up so a will is never published early
+        let due = now.saturating_duration_since(self.start) + delay;
+        let tick = due.as_millis().div_ceil(WILL_TIMER_RESOLUTION.as_millis()) as u64;
+
+        let mut wheel = self.wheel.lock();
+        let tick = tick.max(wheel.next_tick);
+        wheel.seq += 1;
+        let seq = wheel.seq;
+        wheel.live.insert(
+            client_id.clone(),
+            LiveTimer {
+                seq,
+                tenant: tenant.clone(),
+            },
+        );
+        wheel.slots[(tick % SLOTS) as usize].push(WillTimer {
+            client_id,
+            session: Arc::downgrade(session),
+            disconnected_at,
+            tenant,
+            seq,
+            tick,
+        });
+    }
+
+    /// Cancel a client's pending will; returns true if there was one
+    pub(crate) fn cancel(&self, client_id: &str) -> bool {
+        self.take(client_id).is_some()
+    }
+
+    /// Cancel a client's pending will and return its timer, so the caller
+    /// can publish the will early
+    pub(crate) fn take(&self, client_id: &str) -> Option<LiveTimer> {
+        self.wheel.lock().live.remove(client_id)
+    }
+
+    /// Number of pending wills
+    pub(crate) fn len(&self) -> usize {
+        self.wheel.lock().live.len()
+    }
+
+    /// Remove and return the live timers due by `now`
+    pub(crate) fn expire(&self, now: Instant) -> Vec<WillTimer> {
+        let now_tick = (now.saturating_duration_since(self.start).as_millis()
+            / WILL_TIMER_RESOLUTION.as_millis()) as u64;
+
+        let mut wheel = self.wheel.lock();
+        if now_tick < wheel.next_tick {
+            return Vec::new();
+        }
+        // After a stall of a full turn or more, every slot is visited once
+        let first = wheel.next_tick.max((now_tick + 1).saturating_sub(SLOTS));
+        wheel.next_tick = now_tick + 1;
+
+        let Wheel { slots, live, .. } = &mut *wheel;
+        let mut due = Vec::new();
+        for tick in first..=now_tick {
+            let slot = &mut slots[(tick % SLOTS) as usize];
+            let mut i = 0;
+            while i < slot.len() {
+                if slot[i].tick > now_tick {
+                    i += 1; // Due in a later turn
+                    continue;
+                }
+                let timer = slot.swap_remove(i);
+                if live.get(&timer.client_id).map(|l| l.seq) == Some(timer.seq) {
+                    live.remove(&timer.client_id);
+                    due.push(timer);
+                }
+            }
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ProtocolVersion;
+    use crate::session::SessionLimits;
+
+    fn session(client_id: &str) -> Arc<RwLock<Session>> {
+        let mut session = Session::new(
+            client_id.into(),
+            ProtocolVersion::V5,
+            SessionLimits::default(),
+        );
+        session.disconnected_at = Some(Instant::now());
+        Arc::new(RwLock::new(session))
+    }
+
+    fn expired(timers: &WillTimers, after: Duration) -> Vec<String> {
+        timers
+            .expire(timers.start + after)
+            .into_iter()
+            .map(|t| t.client_id.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_wills_expire_after_their_delay() {
+        let timers = WillTimers::new();
+        let (a, b) = (session("a"), session("b"));
+        timers.schedule(&a, Duration::from_secs(2), None, timers.start);
+        timers.schedule(&b, Duration::from_secs(100), None, timers.start);
+        assert_eq!(timers.len(), 2);
+
+        assert!(expired(&timers, Duration::from_millis(1999)).is_empty());
+        assert_eq!(expired(&timers, Duration::from_secs(2)), vec!["a"]);
+        // Past a full turn of the wheel, but not yet due
+        assert!(expired(&timers, Duration::from_secs(70)).is_empty());
+        assert_eq!(expired(&timers, Duration::from_secs(300)), vec!["b"]);
+        assert_eq!(timers.len(), 0);
+    }
+
+    #[test]
+    fn test_cancel_and_reschedule() {
+        let timers = WillTimers::new();
+        let a = session("a");
+        timers.schedule(&a, Duration::from_secs(1), None, timers.start);
+        assert!(timers.cancel("a"));
+        assert!(!timers.cancel("a"));
+        assert!(expired(&timers, Duration::from_secs(5)).is_empty());
+
+        // Only the latest timer of a client fires
+        timers.schedule(
+            &a,
+            Duration::from_secs(1),
+            None,
+            timers.start + Duration::from_secs(5),
+        );
+        timers.schedule(
+            &a,
+            Duration::from_secs(3),
+            None,
+            timers.start + Duration::from_secs(5),
+        );
+        assert!(expired(&timers, Duration::from_secs(6)).is_empty());
+        assert_eq!(expired(&timers, Duration::from_secs(8)), vec!["a"]);
+    }
+
+    #[test]
+    fn test_take_live_timer() {
+        let timers = WillTimers::new();
+        let a = session("a");
+        timers.schedule(
+            &a,
+            Duration::from_secs(30),
+            Some("acme".into()),
+            timers.start,
+        );
+        let live = timers.take("a").unwrap();
+        assert_eq!(live.tenant.as_deref(), Some("acme"));
+        assert!(timers.take("a").is_none());
+        assert!(expired(&timers, Duration::from_secs(60)).is_empty());
+    }
+}
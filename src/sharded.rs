@@ -0,0 +1,182 @@
+//! Client-ID sharded maps
+//!
+//! Sessions and connections are looked up by client ID on every connect,
+//! disconnect and routed message, from every worker. [`ShardedMap`]
+//! spreads them over lock-free skip lists sized to the worker threads,
+//! each on its own cache line, so lookups, inserts and removals on
+//! different workers never wait for each other, and iteration never holds
+//! up either.
+//!
+//! Lookups clone the value out (values are `Arc`s or channel senders), so
+//! callers never hold on to an entry while they work with the value.
+
+use std::sync::Arc;
+
+use ahash::RandomState;
+use crossbeam_skiplist::SkipMap;
+
+/// Shards per worker thread, so concurrent workers rarely share one
+const SHARDS_PER_WORKER: usize = 4;
+
+/// A shard, aligned to keep neighbouring shards off its cache line
+#[repr(align(128))]
+struct Shard<V>(SkipMap<Arc<str>, V>);
+
+/// A map keyed by client ID, sharded by the key's hash
+pub struct ShardedMap<V> {
+    shards: Box<[Shard<V>]>,
+    /// `shards.len() - 1`; the shard count is a power of two
+    mask: usize,
+    hasher: RandomState,
+}
+
+impl<V: Clone + Send + 'static> ShardedMap<V> {
+    /// Create a map with shards for `workers` worker threads
+    pub fn new(workers: usize) -> Self {
+        Self::with_shards(workers.max(1) * SHARDS_PER_WORKER)
+    }
+
+    /// Create a map with `shards` shards, rounded up to a power of two
+    pub fn with_shards(shards: usize) -> Self {
+        let shards = shards.max(1).next_power_of_two();
+        Self {
+            shards: (0..shards).map(|_| Shard(SkipMap::new())).collect(),
+            mask: shards - 1,
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Number of shards
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// The shard holding `key`
+    fn shard(&self, key: &str) -> &SkipMap<Arc<str>, V> {
+        &self.shards[self.hasher.hash_one(key) as usize & self.mask].0
+    }
+
+    /// The value of `key`
+    pub fn get(&self, key: &str) -> Option<V> {
+        self.shard(key).get(key).map(|entry| entry.value().clone())
+    }
+
+    /// Whether `key` has a value
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.shard(key).contains_key(key)
+    }
+
+    /// Set the value of `key`, replacing any value it had
+    pub fn insert(&self, key: Arc<str>, value: V) {
+        self.shard(&key).insert(key, value);
+    }
+
+    /// Remove the value of `key`
+    pub fn remove(&self, key: &str) -> Option<V> {
+        self.remove_if(key, |_| true)
+    }
+
+    /// Remove the value of `key` if `predicate` holds for it; a value
+    /// inserted in its place meanwhile is left alone
+    pub fn remove_if(&self, key: &str, predicate: impl FnOnce(&V) -> bool) -> Option<V> {
+        let entry = self.shard(key).get(key)?;
+        (predicate(entry.value()) && entry.remove()).then(|| entry.value().clone())
+    }
+
+    /// Keep only the entries for which `keep` returns true
+    pub fn retain(&self, mut keep: impl FnMut(&Arc<str>, &V) -> bool) {
+        for shard in self.shards.iter() {
+            for entry in shard.0.iter() {
+                if !keep(entry.key(), entry.value()) {
+                    entry.remove();
+                }
+            }
+        }
+    }
+
+    /// Call `f` for every entry
+    pub fn for_each(&self, mut f: impl FnMut(&Arc<str>, &V)) {
+        for shard in self.shards.iter() {
+            for entry in shard.0.iter() {
+                f(entry.key(), entry.value());
+            }
+        }
+    }
+
+    /// Number of entries
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.0.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.0.is_empty())
+    }
+}
+
+impl<V: Clone + Send + 'static> Default for ShardedMap<V> {
+    /// A map with shards for each CPU
+    fn default() -> Self {
+        Self::new(crate::broker::num_cpus::get())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operations() {
+        let map = ShardedMap::with_shards(3);
+        assert_eq!(map.shard_count(), 4);
+        assert!(map.is_empty());
+
+        for i in 0..100 {
+            map.insert(Arc::from(format!("client-{}", i)), i);
+        }
+        assert_eq!(map.len(), 100);
+        map.insert(Arc::from("client-7"), 700);
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.get("client-7"), Some(700));
+        assert!(map.contains_key("client-99"));
+        assert!(!map.contains_key("client-100"));
+
+        assert_eq!(map.remove_if("client-7", |v| *v == 7), None);
+        assert_eq!(map.remove_if("client-7", |v| *v == 700), Some(700));
+        assert_eq!(map.remove("client-7"), None);
+        assert_eq!(map.len(), 99);
+
+        map.retain(|_, v| *v % 2 == 0);
+        assert_eq!(map.len(), 50);
+        let mut values = Vec::new();
+        map.for_each(|_, v| values.push(*v));
+        assert_eq!(values.len(), 50);
+        assert!(values.iter().all(|v| v % 2 == 0));
+    }
+
+    #[test]
+    fn test_concurrent_connect_storm() {
+        let map = Arc::new(ShardedMap::new(4));
+        let threads: Vec<_> = (0..8)
+            .map(|t| {
+                let map = map.clone();
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        let key: Arc<str> = Arc::from(format!("{}-{}", t, i));
+                        map.insert(key.clone(), i);
+                        assert_eq!(map.get(&key), Some(i));
+                        if i % 2 == 0 {
+                            map.remove(&key);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(map.len(), 8 * 500);
+        let mut counted = 0;
+        map.for_each(|_, _| counted += 1);
+        assert_eq!(counted, map.len());
+    }
+}
@@ -8,24 +8,40 @@
 //! - Uses callback-based matching to avoid intermediate allocations
 //! - Uses SmallVec for typical workloads (few matching subscriptions per topic)
 //! - Pre-allocates result vectors with reasonable capacity
+//! - Matches against a lock-free snapshot of the trie (see `SubscriptionStore`)
+//! - Caches matches per topic; a subscription change only invalidates the
+//!   entries of the topics that may match its filter, without scanning the
+//!   cache, so connect storms keep the cache warm
 
+mod name;
+mod persistent;
+mod shared;
 mod trie;
 pub mod validation;
 
+pub use name::Topic;
+pub use shared::InflightProbe;
 pub use trie::TopicTrie;
 pub use validation::{
-    topic_matches_filter, validate_topic_filter, validate_topic_filter_with_max_levels,
-    validate_topic_name, validate_topic_name_with_max_levels, TopicLevel,
+    is_reserved_topic, is_sys_topic, topic_matches_filter, validate_topic_filter,
+    validate_topic_filter_with_max_levels, validate_topic_name,
+    validate_topic_name_with_max_levels, TopicLevel,
 };
 
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
+use arc_swap::ArcSwap;
 use dashmap::DashMap;
-use parking_lot::RwLock;
-use smallvec::SmallVec;
-use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
-use std::sync::Arc;
+use parking_lot::Mutex;
+use serde::Serialize;
+use smallvec::{smallvec, SmallVec};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
 
+use crate::config::{SharedDeliveryStrategy, SharedGroupRule};
 use crate::protocol::QoS;
+use persistent::PersistentMap;
+use shared::SharedDelivery;
 
 /// Maximum number of entries in the topic cache
 const TOPIC_CACHE_MAX_SIZE: usize = 1024;
@@ -47,9 +63,50 @@ pub struct Subscription {
     pub share_group: Option<Arc<str>>,
 }
 
+/// Local members of a shared subscription group
+#[derive(Debug, Clone, Default)]
+pub struct ShareGroupMembers {
+    /// Topic filters subscribed to in the group
+    pub filters: HashSet<String>,
+    /// Clients subscribed in the group
+    pub clients: Vec<Arc<str>>,
+}
+
+/// Subscribers of a topic filter
+#[derive(Debug, Clone, Serialize)]
+pub struct FilterCount {
+    pub filter: String,
+    /// Subscriptions to the filter, shared or not
+    pub subscribers: usize,
+    /// Subscriptions to the filter through a share group
+    pub shared: usize,
+}
+
+/// Subscriber counts per topic filter, for finding hot filters
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FilterStats {
+    /// Filters with at least one subscriber, most subscribed first
+    pub filters: Vec<FilterCount>,
+    /// Subscriptions across all filters
+    pub subscriptions: usize,
+    /// Nodes in the subscription trie
+    pub trie_nodes: usize,
+}
+
+/// Share group formed by all subscribers of a `$queue/{filter}` subscription
+pub const QUEUE_GROUP: &str = "$queue";
+
 /// Parse a shared subscription filter
-/// Returns (share_group, actual_filter) if it's a shared subscription, or None
-pub fn parse_shared_subscription(filter: &str) -> Option<(&str, &str)> {
+/// Returns (share_group, actual_filter) if it's a shared subscription, or None.
+/// With `queue_subscriptions`, `$queue/{filter}` is shared in the implicit
+/// `$queue` group; otherwise it is a plain filter.
+pub fn parse_shared_subscription(filter: &str, queue_subscriptions: bool) -> Option<(&str, &str)> {
+    if let Some(actual_filter) = filter
+        .strip_prefix("$queue/")
+        .filter(|_| queue_subscriptions)
+    {
+        return (!actual_filter.is_empty()).then_some((QUEUE_GROUP, actual_filter));
+    }
     if let Some(rest) = filter.strip_prefix("$share/") {
         // Format: $share/{group}/{filter}
         // Skip "$share/"
@@ -64,146 +121,423 @@ pub fn parse_shared_subscription(filter: &str) -> Option<(&str, &str)> {
     None
 }
 
+/// Place a topic filter below a mount point, keeping the `$share/{group}/`
+/// or, with `queue_subscriptions`, `$queue/` prefix of a shared subscription
+/// in front
+pub fn mount_filter(mount_point: &str, filter: &str, queue_subscriptions: bool) -> String {
+    if let Some(actual) = filter
+        .strip_prefix("$queue/")
+        .filter(|_| queue_subscriptions)
+    {
+        return format!("$queue/{}{}", mount_point, actual);
+    }
+    match parse_shared_subscription(filter, queue_subscriptions) {
+        Some((group, actual)) => format!("$share/{}/{}{}", group, mount_point, actual),
+        None => format!("{}{}", mount_point, filter),
+    }
+}
+
+/// Number of epochs invalidating cached matches, bucketed by the first
+/// level of the topic
+const CACHE_EPOCHS: usize = 64;
+
 /// Cached topic match result
 struct CachedMatch {
     subscriptions: SmallVec<[Subscription; 16]>,
-    generation: u64,
+    /// Wildcard epochs the match was computed at; the entry is stale once
+    /// either has moved on
+    epochs: (u64, u64),
+}
+
+/// Epoch bucket of a topic or filter by its first level
+fn epoch_bucket(filter: &str) -> usize {
+    let first = filter.split('/').next().unwrap_or_default();
+    first
+        .bytes()
+        .fold(0usize, |h, b| h.wrapping_mul(31).wrapping_add(b as usize))
+        % CACHE_EPOCHS
+}
+
+/// The filter a subscription is stored under, without any share prefix
+fn actual_filter(filter: &str, queue_subscriptions: bool) -> &str {
+    parse_shared_subscription(filter, queue_subscriptions).map_or(filter, |(_, actual)| actual)
+}
+
+/// Subscribers of a filter, keyed by client and share group
+type Subscribers = PersistentMap<(Arc<str>, Option<Arc<str>>), Subscription>;
+
+/// Each client's filters as given on SUBSCRIBE
+type ClientFilters = AHashMap<Arc<str>, AHashSet<String>>;
+
+/// A subscription change waiting for the next update of the trie
+enum Change {
+    Subscribe(String, Subscription),
+    Unsubscribe(String, Arc<str>),
+    UnsubscribeAll(Arc<str>),
+}
+
+/// Changes queued by one caller, and where the filters they removed are
+/// handed back once the update carrying them is published
+struct PendingChanges {
+    changes: SmallVec<[Change; 1]>,
+    removed: Arc<OnceLock<Vec<String>>>,
 }
 
 /// Thread-safe subscription store using topic trie
+///
+/// Matching reads the current snapshot of the trie without taking a lock, so
+/// subscription churn never blocks message routing. Writers queue their
+/// changes; whichever takes the writer lock applies everything queued so far
+/// to a copy-on-write clone of the snapshot and publishes it in one swap, so
+/// a connect storm costs one snapshot per batch rather than per SUBSCRIBE.
 pub struct SubscriptionStore {
-    trie: RwLock<TopicTrie<Vec<Subscription>>>,
-    /// Round-robin counters for shared subscriptions, keyed by share group
-    share_counters: DashMap<Arc<str>, AtomicUsize>,
-    /// Cache of topic -> matching subscriptions (invalidated on subscription changes)
+    trie: ArcSwap<TopicTrie<Subscribers>>,
+    /// Changes not yet applied to the trie
+    pending: Mutex<Vec<PendingChanges>>,
+    /// Serializes writers, and indexes each client's filters so removing
+    /// a client does not walk the trie
+    writer: Mutex<ClientFilters>,
+    /// Member selection for shared subscriptions
+    shared: SharedDelivery,
+    /// Whether `$queue/{filter}` subscribes to the `$queue` share group
+    queue_subscriptions: bool,
+    /// Cache of topic -> matching subscriptions; a change to a filter
+    /// without wildcards drops that topic's entry, other changes move an
+    /// epoch on
     topic_cache: DashMap<String, CachedMatch>,
-    /// Generation counter - incremented on any subscription change
+    /// Moved on by changes to filters starting with a wildcard, which may
+    /// match any topic
+    wildcard_epoch: AtomicU64,
+    /// Moved on by changes to other wildcard filters, in the bucket of their
+    /// first level
+    level_epochs: [AtomicU64; CACHE_EPOCHS],
+    /// Generation counter - incremented on any subscription change, so a
+    /// match computed across a change is not cached
     generation: AtomicU64,
 }
 
 impl SubscriptionStore {
     pub fn new() -> Self {
+        Self::with_shared_delivery(SharedDeliveryStrategy::default(), &[])
+    }
+
+    /// Store delivering to shared subscription groups by `strategy`, or by
+    /// the strategy of a matching group rule
+    pub fn with_shared_delivery(
+        strategy: SharedDeliveryStrategy,
+        rules: &[SharedGroupRule],
+    ) -> Self {
         Self {
-            trie: RwLock::new(TopicTrie::new()),
-            share_counters: DashMap::new(),
+            trie: ArcSwap::from_pointee(TopicTrie::new()),
+            pending: Mutex::new(Vec::new()),
+            writer: Mutex::new(ClientFilters::new()),
+            shared: SharedDelivery::new(strategy, rules),
+            queue_subscriptions: false,
             topic_cache: DashMap::new(),
+            wildcard_epoch: AtomicU64::new(0),
+            level_epochs: std::array::from_fn(|_| AtomicU64::new(0)),
             generation: AtomicU64::new(0),
         }
     }
 
-    /// Invalidate cache by incrementing generation
-    #[inline]
-    fn invalidate_cache(&self) {
-        self.generation.fetch_add(1, Ordering::Release);
-        // Optionally clear cache if it's too large
-        if self.topic_cache.len() > TOPIC_CACHE_MAX_SIZE * 2 {
-            self.topic_cache.clear();
+    /// Share `$queue/{filter}` subscriptions as the `$queue` group, rather
+    /// than storing them as plain filters
+    pub fn with_queue_subscriptions(mut self, enabled: bool) -> Self {
+        self.queue_subscriptions = enabled;
+        self
+    }
+
+    /// Delivery strategy of a shared subscription group
+    pub fn shared_strategy(&self, group: &str) -> SharedDeliveryStrategy {
+        self.shared.strategy(group)
+    }
+
+    /// Count a client's unacknowledged messages for `least_inflight` groups
+    pub fn set_inflight_probe(&self, probe: InflightProbe) {
+        self.shared.set_inflight_probe(probe);
+    }
+
+    /// Let the topics that stick to a disconnected client move on to other
+    /// members of their groups
+    pub fn release_sticky(&self, client_id: &str) {
+        self.shared.release(client_id);
+    }
+
+    /// Invalidate the cached matches of the topics `filters` may match,
+    /// which may have gained or lost subscribers; the rest of the cache
+    /// stays valid
+    fn invalidate_cache<'a>(&self, filters: impl IntoIterator<Item = &'a str>) {
+        // Must follow the snapshot swap, see `matches`
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        for filter in filters {
+            if !filter.contains(['+', '#']) {
+                self.topic_cache.remove(filter);
+            } else if filter.starts_with(['+', '#']) {
+                self.wildcard_epoch.fetch_add(1, Ordering::SeqCst);
+            } else {
+                self.level_epochs[epoch_bucket(filter)].fetch_add(1, Ordering::SeqCst);
+            }
         }
     }
 
-    /// Add a subscription
-    pub fn subscribe(&self, filter: &str, mut subscription: Subscription) {
+    /// Wildcard epochs a match for `topic` is computed at
+    fn cache_epochs(&self, topic: &str) -> (u64, u64) {
+        (
+            self.wildcard_epoch.load(Ordering::SeqCst),
+            self.level_epochs[epoch_bucket(topic)].load(Ordering::SeqCst),
+        )
+    }
+
+    /// Cached matches of a topic, unless a change has invalidated them
+    fn cached(&self, topic: &str) -> Option<SmallVec<[Subscription; 16]>> {
+        let cached = self.topic_cache.get(topic)?;
+        (cached.epochs == self.cache_epochs(topic)).then(|| cached.subscriptions.clone())
+    }
+
+    /// Queue changes and wait until a published snapshot carries them,
+    /// returning the filters they removed as given on SUBSCRIBE
+    fn commit(&self, changes: SmallVec<[Change; 1]>) -> Vec<String> {
+        let removed = Arc::new(OnceLock::new());
+        self.pending.lock().push(PendingChanges {
+            changes,
+            removed: removed.clone(),
+        });
+
+        let mut clients = self.writer.lock();
+        // The previous writer may have applied these changes already
+        if removed.get().is_none() {
+            self.apply_pending(&mut clients);
+        }
+        drop(clients);
+
+        Arc::into_inner(removed)
+            .and_then(OnceLock::into_inner)
+            .expect("changes are applied under the writer lock")
+    }
+
+    /// Apply every queued change to a copy of the trie and publish it as
+    /// the new snapshot
+    fn apply_pending(&self, clients: &mut ClientFilters) {
+        let batch = std::mem::take(&mut *self.pending.lock());
+        let mut trie = TopicTrie::clone(&self.trie.load());
+        let mut changed: Vec<String> = Vec::new();
+        let mut results = Vec::with_capacity(batch.len());
+        let queue = self.queue_subscriptions;
+
+        for pending in batch {
+            let mut removed = Vec::new();
+            for change in pending.changes {
+                match change {
+                    Change::Subscribe(filter, subscription) => {
+                        changed.push(actual_filter(&filter, queue).to_string());
+                        Self::insert(&mut trie, clients, filter, subscription, queue);
+                    }
+                    Change::Unsubscribe(filter, client_id) => {
+                        if Self::remove(&mut trie, clients, &filter, &client_id, queue) {
+                            changed.push(actual_filter(&filter, queue).to_string());
+                            removed.push(filter);
+                        }
+                    }
+                    Change::UnsubscribeAll(client_id) => {
+                        for filter in clients.remove(&client_id).unwrap_or_default() {
+                            Self::remove_subscriber(&mut trie, &filter, &client_id, queue);
+                            changed.push(actual_filter(&filter, queue).to_string());
+                            removed.push(filter);
+                        }
+                    }
+                }
+            }
+            results.push((pending.removed, removed));
+        }
+
+        self.trie.store(Arc::new(trie));
+        self.invalidate_cache(changed.iter().map(String::as_str));
+        for (slot, removed) in results {
+            let _ = slot.set(removed);
+        }
+    }
+
+    /// The filter a subscription is indexed under: as given on SUBSCRIBE,
+    /// with `$queue/` spelled as its share group
+    fn given_filter(actual_filter: &str, share_group: Option<&str>) -> String {
+        match share_group {
+            Some(group) => format!("$share/{}/{}", group, actual_filter),
+            None => actual_filter.to_string(),
+        }
+    }
+
+    fn insert(
+        trie: &mut TopicTrie<Subscribers>,
+        clients: &mut ClientFilters,
+        filter: String,
+        mut subscription: Subscription,
+        queue_subscriptions: bool,
+    ) {
         // Check if this is a shared subscription
-        let actual_filter = if let Some((group, actual)) = parse_shared_subscription(filter) {
+        let actual_filter = if let Some((group, actual)) =
+            parse_shared_subscription(&filter, queue_subscriptions)
+        {
             subscription.share_group = Some(group.into());
-            // Ensure we have a counter for this share group
-            self.share_counters
-                .entry(group.into())
-                .or_insert_with(|| AtomicUsize::new(0));
             actual
         } else {
-            filter
+            &filter
         };
 
-        let mut trie = self.trie.write();
+        clients
+            .entry(subscription.client_id.clone())
+            .or_default()
+            .insert(Self::given_filter(
+                actual_filter,
+                subscription.share_group.as_deref(),
+            ));
+
+        // For shared subscriptions, the key also includes the share group
+        let key = (
+            subscription.client_id.clone(),
+            subscription.share_group.clone(),
+        );
         if let Some(subs) = trie.get_mut(actual_filter) {
-            // For shared subscriptions, also match on share_group
-            subs.retain(|s| {
-                !(s.client_id == subscription.client_id
-                    && s.share_group == subscription.share_group)
-            });
-            subs.push(subscription);
+            subs.insert(key, subscription);
         } else {
-            trie.insert(actual_filter, vec![subscription]);
+            let mut subs = Subscribers::new();
+            subs.insert(key, subscription);
+            trie.insert(actual_filter, subs);
         }
-        drop(trie);
-        self.invalidate_cache();
     }
 
-    /// Remove a subscription
-    pub fn unsubscribe(&self, filter: &str, client_id: &str) -> bool {
-        // Check if this is a shared subscription
+    /// Remove a client's subscription to a filter as given on SUBSCRIBE
+    fn remove(
+        trie: &mut TopicTrie<Subscribers>,
+        clients: &mut ClientFilters,
+        filter: &str,
+        client_id: &Arc<str>,
+        queue_subscriptions: bool,
+    ) -> bool {
         let (actual_filter, share_group) =
-            if let Some((group, actual)) = parse_shared_subscription(filter) {
-                (actual, Some(group))
-            } else {
-                (filter, None)
+            match parse_shared_subscription(filter, queue_subscriptions) {
+                Some((group, actual)) => (actual, Some(group)),
+                None => (filter, None),
             };
+        let Some(filters) = clients.get_mut(client_id) else {
+            return false;
+        };
+        let given = Self::given_filter(actual_filter, share_group);
+        if !filters.remove(&given) {
+            return false;
+        }
+        if filters.is_empty() {
+            clients.remove(client_id);
+        }
+        Self::remove_subscriber(trie, &given, client_id, queue_subscriptions);
+        true
+    }
 
-        let mut trie = self.trie.write();
-        let removed = if let Some(subs) = trie.get_mut(actual_filter) {
-            let len_before = subs.len();
-            subs.retain(|s| {
-                if s.client_id.as_ref() != client_id {
-                    return true;
-                }
-                // Match share group if this is a shared subscription
-                match (&s.share_group, share_group) {
-                    (Some(sg), Some(fg)) => sg.as_ref() != fg,
-                    (None, None) => false, // Remove non-shared sub
-                    _ => true,             // Mismatch, keep
-                }
-            });
-            let removed = subs.len() != len_before;
-            if subs.is_empty() {
-                trie.remove(actual_filter);
-            }
-            removed
-        } else {
-            false
+    /// Remove a client from the subscribers of an indexed filter
+    fn remove_subscriber(
+        trie: &mut TopicTrie<Subscribers>,
+        filter: &str,
+        client_id: &Arc<str>,
+        queue_subscriptions: bool,
+    ) {
+        let (actual_filter, share_group) =
+            match parse_shared_subscription(filter, queue_subscriptions) {
+                Some((group, actual)) => (actual, Some(group.into())),
+                None => (filter, None),
+            };
+        let Some(subs) = trie.get_mut(actual_filter) else {
+            return;
         };
-        drop(trie);
-        if removed {
-            self.invalidate_cache();
+        subs.remove(&(client_id.clone(), share_group));
+        if subs.is_empty() {
+            trie.remove(actual_filter);
+        }
+    }
+
+    /// Add a subscription
+    pub fn subscribe(&self, filter: &str, subscription: Subscription) {
+        self.commit(smallvec![Change::Subscribe(
+            filter.to_string(),
+            subscription
+        )]);
+    }
+
+    /// Add several subscriptions, such as those of a restored session, in
+    /// a single update
+    pub fn subscribe_many<'a>(
+        &self,
+        subscriptions: impl IntoIterator<Item = (&'a str, Subscription)>,
+    ) {
+        let changes: SmallVec<[Change; 1]> = subscriptions
+            .into_iter()
+            .map(|(filter, subscription)| Change::Subscribe(filter.to_string(), subscription))
+            .collect();
+        if !changes.is_empty() {
+            self.commit(changes);
         }
-        removed
     }
 
-    /// Remove all subscriptions for a client
-    pub fn unsubscribe_all(&self, client_id: &str) {
-        let mut trie = self.trie.write();
-        trie.remove_by_predicate(|subs| {
-            subs.retain(|s| s.client_id.as_ref() != client_id);
-            subs.is_empty()
+    /// Remove a subscription
+    pub fn unsubscribe(&self, filter: &str, client_id: &str) -> bool {
+        // Check if this is a shared subscription
+        let (actual_filter, share_group) = if let Some((group, actual)) =
+            parse_shared_subscription(filter, self.queue_subscriptions)
+        {
+            (actual, Some(group))
+        } else {
+            (filter, None)
+        };
+
+        // Leave the snapshot alone if the client has no such subscription
+        let subscribed = self.trie.load().get(actual_filter).is_some_and(|subs| {
+            subs.values().any(|s| {
+                s.client_id.as_ref() == client_id && s.share_group.as_deref() == share_group
+            })
         });
-        drop(trie);
-        self.invalidate_cache();
+        if !subscribed {
+            return false;
+        }
+
+        !self
+            .commit(smallvec![Change::Unsubscribe(
+                filter.to_string(),
+                client_id.into()
+            )])
+            .is_empty()
+    }
+
+    /// Remove all subscriptions for a client, returning the filters it was
+    /// subscribed to as given on SUBSCRIBE
+    pub fn unsubscribe_all(&self, client_id: &str) -> Vec<String> {
+        self.commit(smallvec![Change::UnsubscribeAll(client_id.into())])
     }
 
     /// Find all matching subscriptions for a topic
-    /// For shared subscriptions, only one subscriber per share group is returned (round-robin)
+    /// For shared subscriptions, only one subscriber per share group is returned,
+    /// picked by the group's delivery strategy
     ///
     /// Performance: Uses topic cache for frequently-published topics (O(1) lookup)
-    /// Cache is invalidated when subscriptions change.
+    /// A subscription change invalidates the entries of the topics it affects.
     pub fn matches(&self, topic: &str) -> SmallVec<[Subscription; 16]> {
-        let current_gen = self.generation.load(Ordering::Acquire);
+        // Read before the snapshot, so a change published after this point
+        // either invalidates the entry cached below or is noticed before
+        // returning
+        let generation = self.generation.load(Ordering::SeqCst);
+        let epochs = self.cache_epochs(topic);
 
         // Check cache first (only for non-shared subscriptions)
-        if let Some(cached) = self.topic_cache.get(topic) {
-            if cached.generation == current_gen {
-                return cached.subscriptions.clone();
-            }
+        if let Some(cached) = self.cached(topic) {
+            return cached;
         }
 
-        // Cache miss or stale - compute matches
-        let trie = self.trie.read();
+        // Cache miss - compute matches
+        let trie = self.trie.load();
         let mut result: SmallVec<[Subscription; 16]> = SmallVec::new();
         let mut share_groups: AHashMap<Arc<str>, SmallVec<[Subscription; 4]>> =
             AHashMap::with_capacity(4);
         let mut has_shared = false;
 
         trie.matches(topic, |subs| {
-            for sub in subs {
+            for sub in subs.values() {
                 if let Some(ref group) = sub.share_group {
                     has_shared = true;
                     share_groups
@@ -217,53 +551,53 @@ impl SubscriptionStore {
         });
         drop(trie);
 
-        // For each share group, pick one subscriber using round-robin
+        // For each share group, pick one subscriber
         for (group, subs) in share_groups {
             if subs.is_empty() {
                 continue;
             }
-            let counter = self
-                .share_counters
-                .entry(group)
-                .or_insert_with(|| AtomicUsize::new(0));
-            let idx = counter.fetch_add(1, Ordering::Relaxed) % subs.len();
+            let idx = self.shared.choose(&group, topic, &subs);
             result.push(subs[idx].clone());
         }
 
-        // Cache result only if no shared subscriptions (round-robin makes them uncacheable)
+        // Cache result only if no shared subscriptions (member selection makes them uncacheable)
         // and cache isn't too large
         if !has_shared && self.topic_cache.len() < TOPIC_CACHE_MAX_SIZE {
             self.topic_cache.insert(
                 topic.to_string(),
                 CachedMatch {
                     subscriptions: result.clone(),
-                    generation: current_gen,
+                    epochs,
                 },
             );
+            // The invalidation of a concurrent change may have missed it
+            if self.generation.load(Ordering::SeqCst) != generation {
+                self.topic_cache.remove(topic);
+            }
         }
 
         result
     }
 
     /// Find all matching subscriptions using a callback to avoid allocation
-    /// For shared subscriptions, only one subscriber per share group is called (round-robin)
+    /// For shared subscriptions, only one subscriber per share group is called
     ///
     /// Note: For shared subscriptions, this still needs to clone subscriptions temporarily
-    /// to handle the round-robin selection. For non-shared subscriptions, the callback
+    /// to handle the member selection. For non-shared subscriptions, the callback
     /// is invoked immediately without cloning.
     pub fn matches_with_callback<F>(&self, topic: &str, mut callback: F)
     where
         F: FnMut(&Subscription),
     {
-        let trie = self.trie.read();
+        let trie = self.trie.load();
         // Temporary storage for share group selection (must clone due to callback lifetime)
         let mut share_groups: AHashMap<Arc<str>, SmallVec<[Subscription; 4]>> =
             AHashMap::with_capacity(4);
 
         trie.matches(topic, |subs| {
-            for sub in subs {
+            for sub in subs.values() {
                 if let Some(ref group) = sub.share_group {
-                    // Collect shared subscriptions by group (clone needed for round-robin selection)
+                    // Collect shared subscriptions by group (clone needed for member selection)
                     share_groups
                         .entry(group.clone())
                         .or_default()
@@ -274,28 +608,118 @@ impl SubscriptionStore {
                 }
             }
         });
+        drop(trie);
 
-        // For each share group, pick one subscriber using round-robin
+        // For each share group, pick one subscriber
         for (group, subs) in share_groups {
             if subs.is_empty() {
                 continue;
             }
-            let counter = self
-                .share_counters
-                .entry(group)
-                .or_insert_with(|| AtomicUsize::new(0));
-            let idx = counter.fetch_add(1, Ordering::Relaxed) % subs.len();
+            let idx = self.shared.choose(&group, topic, &subs);
             callback(&subs[idx]);
         }
     }
 
+    /// All topic filters with at least one non-shared subscriber
+    pub fn filters(&self) -> HashSet<String> {
+        let trie = self.trie.load();
+        let mut filters = HashSet::new();
+        trie.for_each_with_filter(|filter, subs| {
+            if subs.values().any(|s| s.share_group.is_none()) {
+                filters.insert(filter.to_string());
+            }
+        });
+        filters
+    }
+
+    /// Whether a topic filter has a non-shared subscriber
+    pub fn has_filter(&self, filter: &str) -> bool {
+        self.trie
+            .load()
+            .get(filter)
+            .is_some_and(|subs| subs.values().any(|s| s.share_group.is_none()))
+    }
+
+    /// Shared subscription groups with their filters and members
+    pub fn share_groups(&self) -> HashMap<Arc<str>, ShareGroupMembers> {
+        let trie = self.trie.load();
+        let mut groups: HashMap<Arc<str>, ShareGroupMembers> = HashMap::new();
+        trie.for_each_with_filter(|filter, subs| {
+            for sub in subs.values() {
+                if let Some(ref group) = sub.share_group {
+                    let members = groups.entry(group.clone()).or_default();
+                    if !members.filters.contains(filter) {
+                        members.filters.insert(filter.to_string());
+                    }
+                    if !members.clients.contains(&sub.client_id) {
+                        members.clients.push(sub.client_id.clone());
+                    }
+                }
+            }
+        });
+        groups
+    }
+
+    /// Subscriber counts per filter and the size of the subscription trie
+    pub fn filter_stats(&self) -> FilterStats {
+        let trie = self.trie.load();
+        let mut stats = FilterStats {
+            trie_nodes: trie.node_count(),
+            ..Default::default()
+        };
+        trie.for_each_with_filter(|filter, subs| {
+            if subs.is_empty() {
+                return;
+            }
+            stats.subscriptions += subs.len();
+            stats.filters.push(FilterCount {
+                filter: filter.to_string(),
+                subscribers: subs.len(),
+                shared: subs.values().filter(|s| s.share_group.is_some()).count(),
+            });
+        });
+        drop(trie);
+
+        stats.filters.sort_unstable_by(|a, b| {
+            b.subscribers
+                .cmp(&a.subscribers)
+                .then_with(|| a.filter.cmp(&b.filter))
+        });
+        stats
+    }
+
+    /// Count the subscriptions to filters within each of `patterns`, such
+    /// as `sensors/1/temp` and `sensors/+/temp` within `sensors/#`; each
+    /// filter counts toward the first pattern it is within
+    pub fn subscriptions_within(&self, patterns: &[String]) -> Vec<usize> {
+        let trie = self.trie.load();
+        let mut counts = vec![0; patterns.len()];
+        trie.for_each_with_filter(|filter, subs| {
+            if let Some(i) = patterns
+                .iter()
+                .position(|pattern| topic_matches_filter(filter, pattern))
+            {
+                counts[i] += subs.len();
+            }
+        });
+        counts
+    }
+
+    /// Count all subscriptions, shared or not
+    pub fn subscription_count(&self) -> usize {
+        let trie = self.trie.load();
+        let mut count = 0;
+        trie.for_each(|subs| count += subs.len());
+        count
+    }
+
     /// Count the number of shared subscriptions
     /// For $SYS/broker/shared_subscriptions/count
     pub fn shared_subscription_count(&self) -> usize {
-        let trie = self.trie.read();
+        let trie = self.trie.load();
         let mut count = 0;
         trie.for_each(|subs| {
-            count += subs.iter().filter(|s| s.share_group.is_some()).count();
+            count += subs.values().filter(|s| s.share_group.is_some()).count();
         });
         count
     }
@@ -306,3 +730,232 @@ impl Default for SubscriptionStore {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sub(client_id: &str) -> Subscription {
+        Subscription {
+            client_id: client_id.into(),
+            qos: QoS::AtMostOnce,
+            no_local: false,
+            retain_as_published: false,
+            subscription_id: None,
+            share_group: None,
+        }
+    }
+
+    #[test]
+    fn test_mount_filter() {
+        assert_eq!(
+            mount_filter("tenant-x/", "sensors/#", false),
+            "tenant-x/sensors/#"
+        );
+        assert_eq!(mount_filter("tenant-x/", "#", false), "tenant-x/#");
+        assert_eq!(
+            mount_filter("tenant-x/", "$share/g/sensors/+", false),
+            "$share/g/tenant-x/sensors/+"
+        );
+        assert_eq!(
+            mount_filter("tenant-x/", "$queue/jobs", true),
+            "$queue/tenant-x/jobs"
+        );
+        assert_eq!(
+            mount_filter("tenant-x/", "$queue/jobs", false),
+            "tenant-x/$queue/jobs"
+        );
+    }
+
+    #[test]
+    fn test_queue_subscription() {
+        assert_eq!(
+            parse_shared_subscription("$queue/jobs/+", true),
+            Some((QUEUE_GROUP, "jobs/+"))
+        );
+        assert_eq!(parse_shared_subscription("$queue/", true), None);
+
+        // Every $queue subscriber joins the same group
+        let store = SubscriptionStore::new().with_queue_subscriptions(true);
+        store.subscribe("$queue/jobs", sub("a"));
+        store.subscribe("$queue/jobs", sub("b"));
+        let mut receivers = Vec::new();
+        for _ in 0..4 {
+            let matches = store.matches("jobs");
+            assert_eq!(matches.len(), 1);
+            receivers.push(matches[0].client_id.to_string());
+        }
+        // Members take turns, in the store's order
+        receivers[..2].sort();
+        assert_eq!(receivers[..2], ["a", "b"]);
+        assert_ne!(receivers[2], receivers[3]);
+
+        assert!(store.unsubscribe("$queue/jobs", "a"));
+        assert_eq!(store.matches("jobs")[0].client_id.as_ref(), "b");
+    }
+
+    #[test]
+    fn test_queue_subscriptions_disabled() {
+        assert_eq!(parse_shared_subscription("$queue/jobs/+", false), None);
+        assert_eq!(
+            parse_shared_subscription("$share/g/jobs/+", false),
+            Some(("g", "jobs/+"))
+        );
+
+        // Without queue subscriptions, $queue/jobs is a filter of its own
+        let store = SubscriptionStore::new();
+        store.subscribe("$queue/jobs", sub("a"));
+        store.subscribe("$queue/jobs", sub("b"));
+        assert!(store.matches("jobs").is_empty());
+        assert_eq!(store.matches("$queue/jobs").len(), 2);
+        assert!(store.has_filter("$queue/jobs"));
+
+        assert!(store.unsubscribe("$queue/jobs", "a"));
+        assert_eq!(store.unsubscribe_all("b"), ["$queue/jobs"]);
+        assert_eq!(store.subscription_count(), 0);
+    }
+
+    #[test]
+    fn test_subscribe_many_and_unsubscribe_all() {
+        let store = SubscriptionStore::new();
+        store.subscribe_many([("a/+", sub("c1")), ("b/#", sub("c1")), ("a/+", sub("c2"))]);
+        assert_eq!(store.subscription_count(), 3);
+        assert_eq!(store.matches("a/x").len(), 2);
+
+        store.unsubscribe_all("c1");
+        assert_eq!(store.subscription_count(), 1);
+        assert_eq!(store.matches("a/x")[0].client_id.as_ref(), "c2");
+        assert!(store.matches("b/x").is_empty());
+        assert!(!store.unsubscribe("b/#", "c1"));
+    }
+
+    #[test]
+    fn test_unsubscribe_all_returns_given_filters() {
+        let store = SubscriptionStore::new().with_queue_subscriptions(true);
+        store.subscribe("a/+", sub("c1"));
+        store.subscribe("$share/g/a/+", sub("c1"));
+        store.subscribe("$queue/jobs", sub("c1"));
+        assert!(store.unsubscribe("$share/g/a/+", "c1"));
+        store.subscribe("$share/h/a/+", sub("c1"));
+
+        let mut removed = store.unsubscribe_all("c1");
+        removed.sort();
+        assert_eq!(removed, ["$share/$queue/jobs", "$share/h/a/+", "a/+"]);
+        assert_eq!(store.subscription_count(), 0);
+        assert!(store.unsubscribe_all("c1").is_empty());
+    }
+
+    #[test]
+    fn test_concurrent_changes_are_all_applied() {
+        let store = Arc::new(SubscriptionStore::new());
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    for i in 0..200 {
+                        let client = format!("c{}-{}", t, i);
+                        store.subscribe("devices/+", sub(&client));
+                        store.subscribe(&format!("devices/{}", client), sub(&client));
+                        if i % 2 == 0 {
+                            assert_eq!(store.unsubscribe_all(&client).len(), 2);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(store.subscription_count(), 8 * 100 * 2);
+        assert_eq!(store.matches("devices/x").len(), 8 * 100);
+        assert_eq!(store.matches("devices/c3-7").len(), 8 * 100 + 1);
+    }
+
+    #[test]
+    fn test_has_filter() {
+        let store = SubscriptionStore::new();
+        store.subscribe("a/+", sub("c1"));
+        store.subscribe("$share/g/b/#", sub("c2"));
+        assert!(store.has_filter("a/+"));
+        // Shared subscribers do not count
+        assert!(!store.has_filter("b/#"));
+
+        store.unsubscribe("a/+", "c1");
+        assert!(!store.has_filter("a/+"));
+    }
+
+    #[test]
+    fn test_cache_invalidation_is_selective() {
+        let store = SubscriptionStore::new();
+        store.subscribe("sensors/+/temp", sub("a"));
+        store.subscribe("alerts/fire", sub("b"));
+        assert_eq!(store.matches("sensors/1/temp").len(), 1);
+        assert_eq!(store.matches("alerts/fire").len(), 1);
+        assert!(store.matches("other").is_empty());
+        assert_eq!(store.topic_cache.len(), 3);
+
+        // A new filter invalidates only the topics it may match
+        store.subscribe("sensors/#", sub("c"));
+        assert!(store.cached("sensors/1/temp").is_none());
+        assert!(store.cached("alerts/fire").is_some());
+        assert!(store.cached("other").is_some());
+        assert_eq!(store.matches("sensors/1/temp").len(), 2);
+
+        // So does a removed one, with or without wildcards
+        store.unsubscribe("alerts/fire", "b");
+        assert!(store.cached("alerts/fire").is_none());
+        assert!(store.cached("sensors/1/temp").is_some());
+        assert!(store.matches("alerts/fire").is_empty());
+
+        store.subscribe("$share/g/other", sub("d"));
+        assert!(store.cached("other").is_none());
+        store.unsubscribe_all("c");
+        assert!(store.cached("sensors/1/temp").is_none());
+        assert_eq!(store.matches("sensors/1/temp").len(), 1);
+
+        // A filter starting with a wildcard may match any topic
+        assert!(store.matches("alerts/fire").is_empty());
+        store.subscribe("+/fire", sub("e"));
+        assert!(store.cached("sensors/1/temp").is_none());
+        assert_eq!(store.matches("alerts/fire").len(), 1);
+    }
+
+    #[test]
+    fn test_subscriptions_within() {
+        let store = SubscriptionStore::new();
+        store.subscribe("sensors/1/temp", sub("a"));
+        store.subscribe("sensors/+/temp", sub("b"));
+        store.subscribe("$share/g/sensors/+/temp", sub("c"));
+        store.subscribe("sensors/2/humidity", sub("a"));
+        store.subscribe("#", sub("d"));
+
+        let patterns = ["sensors/+/temp".to_string(), "sensors/#".to_string()];
+        assert_eq!(store.subscriptions_within(&patterns), vec![3, 1]);
+    }
+
+    #[test]
+    fn test_filter_stats() {
+        let store = SubscriptionStore::new();
+        store.subscribe("sensors/+/temp", sub("a"));
+        store.subscribe("sensors/+/temp", sub("b"));
+        store.subscribe("$share/g/sensors/+/temp", sub("c"));
+        store.subscribe("alerts/#", sub("a"));
+
+        let stats = store.filter_stats();
+        assert_eq!(stats.subscriptions, 4);
+        // sensors, sensors/+, sensors/+/temp, alerts
+        assert_eq!(stats.trie_nodes, 4);
+        assert_eq!(stats.filters.len(), 2);
+        assert_eq!(stats.filters[0].filter, "sensors/+/temp");
+        assert_eq!(stats.filters[0].subscribers, 3);
+        assert_eq!(stats.filters[0].shared, 1);
+        assert_eq!(stats.filters[1].filter, "alerts/#");
+        assert_eq!(stats.filters[1].subscribers, 1);
+
+        store.unsubscribe("alerts/#", "a");
+        let stats = store.filter_stats();
+        assert_eq!(stats.filters.len(), 1);
+        assert_eq!(stats.trie_nodes, 3);
+    }
+}
@@ -0,0 +1,106 @@
+//! Shared topic names
+//!
+//! A [`Topic`] is a reference-counted topic name. A PUBLISH carries one
+//! from the decoder on, so the copies queued for each subscriber, handed to
+//! event listeners and kept as the retained message share one allocation
+//! instead of each cloning a `String`.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, OnceLock};
+
+use serde::{Serialize, Serializer};
+
+/// A topic name, cheap to clone
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Topic(Arc<str>);
+
+impl Topic {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The empty topic, as sent with a Topic Alias, shared so clearing a topic
+/// does not allocate
+impl Default for Topic {
+    fn default() -> Self {
+        static EMPTY: OnceLock<Arc<str>> = OnceLock::new();
+        Self(EMPTY.get_or_init(|| Arc::from("")).clone())
+    }
+}
+
+impl Deref for Topic {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Topic {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Topic {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Topic {
+    fn from(topic: &str) -> Self {
+        Self(Arc::from(topic))
+    }
+}
+
+impl From<String> for Topic {
+    fn from(topic: String) -> Self {
+        Self(Arc::from(topic))
+    }
+}
+
+impl From<Arc<str>> for Topic {
+    fn from(topic: Arc<str>) -> Self {
+        Self(topic)
+    }
+}
+
+impl PartialEq<str> for Topic {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Topic {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl PartialEq<String> for Topic {
+    fn eq(&self, other: &String) -> bool {
+        *self.0 == **other
+    }
+}
+
+impl fmt::Debug for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Serialize for Topic {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
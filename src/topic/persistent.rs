@@ -0,0 +1,529 @@
+//! Persistent hash map for the topic trie
+//!
+//! A hash array mapped trie: each node holds up to 32 entries selected by
+//! five bits of the key's hash, so lookups and updates touch O(log32 n)
+//! nodes. Nodes are shared through `Arc`, so cloning a map is O(1) and
+//! changing a clone copies only the nodes on the path to the changed key,
+//! however many entries the map holds.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::{Arc, LazyLock};
+
+/// Hash bits consumed per level
+const BITS: u32 = 5;
+const MASK: u64 = (1 << BITS) - 1;
+
+/// Process-wide hasher, randomly seeded so peers cannot force collisions
+static HASHER: LazyLock<ahash::RandomState> = LazyLock::new(ahash::RandomState::new);
+
+fn hash_of<Q: Hash + ?Sized>(key: &Q) -> u64 {
+    HASHER.hash_one(key)
+}
+
+/// Position of a hash in the bitmap of the node at `shift`
+fn bit(hash: u64, shift: u32) -> u32 {
+    1 << ((hash >> shift) & MASK)
+}
+
+#[derive(Clone)]
+enum Entry<K, V> {
+    Leaf {
+        hash: u64,
+        key: K,
+        value: V,
+    },
+    /// Keys whose full hashes are equal
+    Collision {
+        hash: u64,
+        pairs: Vec<(K, V)>,
+    },
+    Node(Arc<Node<K, V>>),
+}
+
+#[derive(Clone)]
+struct Node<K, V> {
+    /// Which of the 32 slots are occupied
+    bitmap: u32,
+    /// Occupied slots, in slot order
+    entries: Vec<Entry<K, V>>,
+}
+
+impl<K, V> Node<K, V> {
+    fn index(&self, bit: u32) -> usize {
+        (self.bitmap & (bit - 1)).count_ones() as usize
+    }
+
+    /// Node holding a single entry whose hash is `hash`
+    fn with_entry(hash: u64, shift: u32, entry: Entry<K, V>) -> Self {
+        Self {
+            bitmap: bit(hash, shift),
+            entries: vec![entry],
+        }
+    }
+}
+
+impl<K, V> Entry<K, V> {
+    fn hash(&self) -> Option<u64> {
+        match self {
+            Entry::Leaf { hash, .. } | Entry::Collision { hash, .. } => Some(*hash),
+            Entry::Node(_) => None,
+        }
+    }
+}
+
+/// Persistent hash map with O(1) clones and path-copying updates
+pub struct PersistentMap<K, V> {
+    /// None while empty, so an empty map does not allocate
+    root: Option<Arc<Node<K, V>>>,
+    len: usize,
+}
+
+impl<K, V> PersistentMap<K, V> {
+    pub fn new() -> Self {
+        Self { root: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterate over the entries in hash order
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            stack: self.root.iter().map(|root| root.entries.iter()).collect(),
+            pairs: [].iter(),
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, v)| v)
+    }
+}
+
+impl<K: Hash + Eq, V> PersistentMap<K, V> {
+    /// Get a reference to the value of a key
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = hash_of(key);
+        let mut node = self.root.as_deref()?;
+        let mut shift = 0;
+        loop {
+            let bit = bit(hash, shift);
+            if node.bitmap & bit == 0 {
+                return None;
+            }
+            match &node.entries[node.index(bit)] {
+                Entry::Leaf { key: k, value, .. } => {
+                    return (k.borrow() == key).then_some(value);
+                }
+                Entry::Collision { pairs, .. } => {
+                    return pairs
+                        .iter()
+                        .find(|(k, _)| k.borrow() == key)
+                        .map(|(_, v)| v);
+                }
+                Entry::Node(child) => {
+                    node = child;
+                    shift += BITS;
+                }
+            }
+        }
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> PersistentMap<K, V> {
+    /// Get a mutable reference to the value of a key, copying the nodes on
+    /// its path that are shared with another map
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        // Leave shared nodes alone when the key is absent
+        if !self.contains_key(key) {
+            return None;
+        }
+        let hash = hash_of(key);
+        let mut node = Arc::make_mut(self.root.as_mut()?);
+        let mut shift = 0;
+        loop {
+            let index = node.index(bit(hash, shift));
+            match &mut node.entries[index] {
+                Entry::Leaf { value, .. } => return Some(value),
+                Entry::Collision { pairs, .. } => {
+                    return pairs
+                        .iter_mut()
+                        .find(|(k, _)| k.borrow() == key)
+                        .map(|(_, v)| v);
+                }
+                Entry::Node(child) => {
+                    node = Arc::make_mut(child);
+                    shift += BITS;
+                }
+            }
+        }
+    }
+
+    /// Get a mutable reference to the value of a key, inserting `default()`
+    /// first if the key is absent; the key is only copied to insert it
+    pub fn get_or_insert_with<Q>(&mut self, key: &Q, default: impl FnOnce() -> V) -> &mut V
+    where
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if !self.contains_key(key) {
+            self.insert(K::from(key), default());
+        }
+        self.get_mut(key).expect("key was just inserted")
+    }
+
+    /// Insert a value, returning the value it replaced
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = hash_of(&key);
+        let root = self.root.get_or_insert_with(|| {
+            Arc::new(Node {
+                bitmap: 0,
+                entries: Vec::new(),
+            })
+        });
+        let replaced = Self::insert_into(Arc::make_mut(root), hash, 0, key, value);
+        if replaced.is_none() {
+            self.len += 1;
+        }
+        replaced
+    }
+
+    fn insert_into(node: &mut Node<K, V>, hash: u64, shift: u32, key: K, value: V) -> Option<V> {
+        let bit = bit(hash, shift);
+        let index = node.index(bit);
+        if node.bitmap & bit == 0 {
+            node.bitmap |= bit;
+            node.entries.insert(index, Entry::Leaf { hash, key, value });
+            return None;
+        }
+
+        let entry = &mut node.entries[index];
+        match entry {
+            Entry::Node(child) => {
+                return Self::insert_into(Arc::make_mut(child), hash, shift + BITS, key, value);
+            }
+            Entry::Leaf {
+                key: k, value: v, ..
+            } if *k == key => {
+                return Some(std::mem::replace(v, value));
+            }
+            Entry::Collision { hash: h, pairs } if *h == hash => {
+                if let Some((_, v)) = pairs.iter_mut().find(|(k, _)| *k == key) {
+                    return Some(std::mem::replace(v, value));
+                }
+                pairs.push((key, value));
+                return None;
+            }
+            _ => {}
+        }
+
+        // Another key occupies the slot
+        let Some(existing_hash) = entry.hash() else {
+            unreachable!("nodes are handled above");
+        };
+        let existing = std::mem::replace(
+            entry,
+            Entry::Node(Arc::new(Node {
+                bitmap: 0,
+                entries: Vec::new(),
+            })),
+        );
+        if existing_hash == hash {
+            // Equal hashes cannot be told apart at any depth
+            let Entry::Leaf {
+                key: k, value: v, ..
+            } = existing
+            else {
+                unreachable!("equal-hash collisions are handled above");
+            };
+            *entry = Entry::Collision {
+                hash,
+                pairs: vec![(k, v), (key, value)],
+            };
+            return None;
+        }
+        // Move the existing entry one level down, next to the new key
+        let mut child = Node::with_entry(existing_hash, shift + BITS, existing);
+        Self::insert_into(&mut child, hash, shift + BITS, key, value);
+        *entry = Entry::Node(Arc::new(child));
+        None
+    }
+
+    /// Remove a key, returning its value
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        // Leave shared nodes alone when the key is absent
+        if !self.contains_key(key) {
+            return None;
+        }
+        let root = Arc::make_mut(self.root.as_mut()?);
+        let removed = Self::remove_from(root, hash_of(key), 0, key);
+        if removed.is_some() {
+            self.len -= 1;
+            if self.len == 0 {
+                self.root = None;
+            }
+        }
+        removed
+    }
+
+    fn remove_from<Q>(node: &mut Node<K, V>, hash: u64, shift: u32, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let bit = bit(hash, shift);
+        if node.bitmap & bit == 0 {
+            return None;
+        }
+        let index = node.index(bit);
+        let removed = match &mut node.entries[index] {
+            Entry::Leaf { key: k, .. } => {
+                if (*k).borrow() != key {
+                    return None;
+                }
+                node.bitmap &= !bit;
+                let Entry::Leaf { value, .. } = node.entries.remove(index) else {
+                    unreachable!();
+                };
+                return Some(value);
+            }
+            Entry::Collision { pairs, .. } => {
+                let position = pairs.iter().position(|(k, _)| k.borrow() == key)?;
+                pairs.swap_remove(position).1
+            }
+            Entry::Node(child) => {
+                let child = Arc::make_mut(child);
+                let removed = Self::remove_from(child, hash, shift + BITS, key)?;
+                // Pull a lone leaf or collision back up to keep paths short
+                if child.entries.len() == 1 && !matches!(child.entries[0], Entry::Node(_)) {
+                    let lone = child.entries.pop().expect("one entry");
+                    node.entries[index] = lone;
+                }
+                removed
+            }
+        };
+
+        // A collision left with one key becomes a leaf again
+        if let Entry::Collision { hash, pairs } = &mut node.entries[index] {
+            if pairs.len() == 1 {
+                let hash = *hash;
+                let (key, value) = pairs.pop().expect("one pair");
+                node.entries[index] = Entry::Leaf { hash, key, value };
+            }
+        }
+        Some(removed)
+    }
+
+    /// Visit every entry mutably, copying all nodes shared with another map
+    pub fn for_each_mut(&mut self, mut f: impl FnMut(&K, &mut V)) {
+        if let Some(root) = self.root.as_mut() {
+            Self::for_each_mut_in(Arc::make_mut(root), &mut f);
+        }
+    }
+
+    fn for_each_mut_in(node: &mut Node<K, V>, f: &mut impl FnMut(&K, &mut V)) {
+        for entry in &mut node.entries {
+            match entry {
+                Entry::Leaf { key, value, .. } => f(key, value),
+                Entry::Collision { pairs, .. } => {
+                    for (key, value) in pairs {
+                        f(key, value);
+                    }
+                }
+                Entry::Node(child) => Self::for_each_mut_in(Arc::make_mut(child), f),
+            }
+        }
+    }
+}
+
+impl<K, V> Clone for PersistentMap<K, V> {
+    /// Snapshot the map; nodes are shared until either copy changes them
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl<K, V> Default for PersistentMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for PersistentMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a PersistentMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over the entries of a [`PersistentMap`]
+pub struct Iter<'a, K, V> {
+    /// Entries left to visit in each node on the current path
+    stack: Vec<std::slice::Iter<'a, Entry<K, V>>>,
+    /// Pairs left to visit in the current collision
+    pairs: std::slice::Iter<'a, (K, V)>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((k, v)) = self.pairs.next() {
+                return Some((k, v));
+            }
+            let entries = self.stack.last_mut()?;
+            match entries.next() {
+                Some(Entry::Leaf { key, value, .. }) => return Some((key, value)),
+                Some(Entry::Collision { pairs, .. }) => self.pairs = pairs.iter(),
+                Some(Entry::Node(child)) => self.stack.push(child.entries.iter()),
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_get_remove() {
+        let mut map = PersistentMap::new();
+        for i in 0..1000 {
+            assert_eq!(map.insert(i, i * 2), None);
+        }
+        assert_eq!(map.len(), 1000);
+        assert_eq!(map.insert(7, 0), Some(14));
+        assert_eq!(map.get(&7), Some(&0));
+        assert_eq!(map.get(&1000), None);
+
+        *map.get_mut(&8).unwrap() += 1;
+        assert_eq!(map.get(&8), Some(&17));
+
+        for i in (0..1000).step_by(2) {
+            assert!(map.remove(&i).is_some());
+        }
+        assert_eq!(map.remove(&0), None);
+        assert_eq!(map.len(), 500);
+        assert!((1..1000).step_by(2).all(|i| map.contains_key(&i)));
+
+        let mut keys: Vec<_> = map.keys().copied().collect();
+        keys.sort();
+        assert_eq!(keys, (1..1000).step_by(2).collect::<Vec<_>>());
+
+        for i in (1..1000).step_by(2) {
+            map.remove(&i);
+        }
+        assert!(map.is_empty());
+        assert!(map.root.is_none());
+    }
+
+    #[test]
+    fn test_borrowed_lookup() {
+        let mut map: PersistentMap<Arc<str>, u32> = PersistentMap::new();
+        map.insert("sensors".into(), 1);
+        assert_eq!(map.get("sensors"), Some(&1));
+        *map.get_or_insert_with("devices", || 0) += 2;
+        assert_eq!(map.get("devices"), Some(&2));
+        assert_eq!(map.remove("sensors"), Some(1));
+    }
+
+    #[test]
+    fn test_colliding_hashes() {
+        let mut node: Node<u32, u32> = Node {
+            bitmap: 0,
+            entries: Vec::new(),
+        };
+        // Same full hash, then one that differs only in the last level
+        PersistentMap::insert_into(&mut node, 42, 0, 1, 10);
+        PersistentMap::insert_into(&mut node, 42, 0, 2, 20);
+        PersistentMap::insert_into(&mut node, 42 | 1 << 60, 0, 3, 30);
+        assert!(matches!(node.entries[0], Entry::Node(_)));
+
+        assert_eq!(PersistentMap::remove_from(&mut node, 42, 0, &1), Some(10));
+        assert_eq!(PersistentMap::remove_from(&mut node, 42, 0, &1), None);
+        assert_eq!(
+            PersistentMap::remove_from(&mut node, 42 | 1 << 60, 0, &3),
+            Some(30)
+        );
+        // The remaining key is pulled back up as a plain leaf
+        assert!(matches!(node.entries[0], Entry::Leaf { key: 2, .. }));
+    }
+
+    #[test]
+    fn test_clone_copies_only_changed_path() {
+        let mut map = PersistentMap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        let snapshot = map.clone();
+        map.insert(5, 0);
+        map.remove(&6);
+
+        assert_eq!(snapshot.get(&5), Some(&5));
+        assert_eq!(snapshot.get(&6), Some(&6));
+        assert_eq!(snapshot.len(), 100);
+        assert_eq!(map.get(&5), Some(&0));
+        assert_eq!(map.len(), 99);
+
+        // Only the subtree holding the replaced key is copied
+        let mut changed = snapshot.clone();
+        changed.insert(5, 1);
+        let (root, old_root) = (
+            changed.root.as_ref().unwrap(),
+            snapshot.root.as_ref().unwrap(),
+        );
+        assert!(!Arc::ptr_eq(root, old_root));
+        let copied = root
+            .entries
+            .iter()
+            .zip(&old_root.entries)
+            .filter(|pair| matches!(pair, (Entry::Node(x), Entry::Node(y)) if !Arc::ptr_eq(x, y)))
+            .count();
+        assert!(copied <= 1);
+    }
+}
@@ -0,0 +1,221 @@
+//! Shared Subscription Delivery
+//!
+//! Picks the member of a `$share/<group>/...` group that receives a
+//! message. Each group follows the configured strategy, or an override
+//! for that group:
+//! - `round_robin` rotates through the members
+//! - `sticky` keeps sending a topic to the member that got it first, until
+//!   that member disconnects or leaves the group
+//! - `random` picks any member
+//! - `least_inflight` picks the member with the fewest unacknowledged
+//!   messages, rotating among ties
+//! - `local_only` rotates like `round_robin` and is never balanced across
+//!   cluster nodes
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use ahash::{AHashMap, AHashSet};
+use dashmap::DashMap;
+use smallvec::SmallVec;
+
+use super::Subscription;
+use crate::broker::rand_id;
+use crate::config::{SharedDeliveryStrategy, SharedGroupRule};
+
+/// Unacknowledged outgoing messages of a client
+pub type InflightProbe = Arc<dyn Fn(&str) -> usize + Send + Sync>;
+
+/// Topics that stick to a member, by share group
+type StickyTopics = AHashMap<Arc<str>, AHashSet<Box<str>>>;
+
+/// Member selection state of the shared subscription groups
+pub(crate) struct SharedDelivery {
+    strategy: SharedDeliveryStrategy,
+    overrides: AHashMap<Arc<str>, SharedDeliveryStrategy>,
+    /// Rotation counters, keyed by share group
+    counters: DashMap<Arc<str>, AtomicUsize>,
+    /// Topics that stick to each member, by share group; dropped with the
+    /// member's connection
+    sticky: DashMap<Arc<str>, StickyTopics>,
+    inflight: OnceLock<InflightProbe>,
+}
+
+impl SharedDelivery {
+    pub(crate) fn new(strategy: SharedDeliveryStrategy, rules: &[SharedGroupRule]) -> Self {
+        Self {
+            strategy,
+            overrides: rules
+                .iter()
+                .map(|rule| (rule.group.as_str().into(), rule.strategy))
+                .collect(),
+            counters: DashMap::new(),
+            sticky: DashMap::new(),
+            inflight: OnceLock::new(),
+        }
+    }
+
+    /// Strategy of a share group
+    pub(crate) fn strategy(&self, group: &str) -> SharedDeliveryStrategy {
+        self.overrides.get(group).copied().unwrap_or(self.strategy)
+    }
+
+    pub(crate) fn set_inflight_probe(&self, probe: InflightProbe) {
+        let _ = self.inflight.set(probe);
+    }
+
+    fn next(&self, group: &Arc<str>) -> usize {
+        if let Some(counter) = self.counters.get(group) {
+            return counter.fetch_add(1, Ordering::Relaxed);
+        }
+        self.counters
+            .entry(group.clone())
+            .or_insert_with(|| AtomicUsize::new(0))
+            .fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Pick the member of `group` that receives a message on `topic`;
+    /// returns an index into `members`, which must not be empty
+    pub(crate) fn choose(&self, group: &Arc<str>, topic: &str, members: &[Subscription]) -> usize {
+        debug_assert!(!members.is_empty());
+        match self.strategy(group) {
+            SharedDeliveryStrategy::RoundRobin | SharedDeliveryStrategy::LocalOnly => {
+                self.next(group) % members.len()
+            }
+            SharedDeliveryStrategy::Random => rand_id() as usize % members.len(),
+            SharedDeliveryStrategy::Sticky => {
+                let stuck = members.iter().position(|m| {
+                    self.sticky.get(&m.client_id).is_some_and(|groups| {
+                        groups
+                            .get(group)
+                            .is_some_and(|topics| topics.contains(topic))
+                    })
+                });
+                if let Some(i) = stuck {
+                    return i;
+                }
+                let i = self.next(group) % members.len();
+                self.sticky
+                    .entry(members[i].client_id.clone())
+                    .or_default()
+                    .entry(group.clone())
+                    .or_default()
+                    .insert(Box::from(topic));
+                i
+            }
+            SharedDeliveryStrategy::LeastInflight => {
+                let Some(probe) = self.inflight.get() else {
+                    return self.next(group) % members.len();
+                };
+                let inflight: SmallVec<[usize; 4]> =
+                    members.iter().map(|m| probe(&m.client_id)).collect();
+                let least = inflight.iter().copied().min().unwrap_or(0);
+                let tied: SmallVec<[usize; 4]> = inflight
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, n)| **n == least)
+                    .map(|(i, _)| i)
+                    .collect();
+                tied[self.next(group) % tied.len()]
+            }
+        }
+    }
+
+    /// Forget the topics that stick to a client, so they move on to other
+    /// members
+    pub(crate) fn release(&self, client_id: &str) {
+        self.sticky.remove(client_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::QoS;
+
+    fn members(ids: &[&str]) -> Vec<Subscription> {
+        ids.iter()
+            .map(|id| Subscription {
+                client_id: (*id).into(),
+                qos: QoS::AtMostOnce,
+                no_local: false,
+                retain_as_published: false,
+                subscription_id: None,
+                share_group: Some("g".into()),
+            })
+            .collect()
+    }
+
+    fn rule(group: &str, strategy: SharedDeliveryStrategy) -> SharedGroupRule {
+        SharedGroupRule {
+            group: group.to_string(),
+            strategy,
+        }
+    }
+
+    #[test]
+    fn test_group_override() {
+        let delivery = SharedDelivery::new(
+            SharedDeliveryStrategy::RoundRobin,
+            &[rule("pinned", SharedDeliveryStrategy::Sticky)],
+        );
+        assert_eq!(delivery.strategy("pinned"), SharedDeliveryStrategy::Sticky);
+        assert_eq!(
+            delivery.strategy("other"),
+            SharedDeliveryStrategy::RoundRobin
+        );
+    }
+
+    #[test]
+    fn test_sticky_until_released() {
+        let delivery = SharedDelivery::new(SharedDeliveryStrategy::Sticky, &[]);
+        let group: Arc<str> = "g".into();
+        let subs = members(&["a", "b", "c"]);
+
+        let first = delivery.choose(&group, "sensors/1", &subs);
+        for _ in 0..5 {
+            assert_eq!(delivery.choose(&group, "sensors/1", &subs), first);
+        }
+        // Another topic moves on to the next member
+        assert_ne!(delivery.choose(&group, "sensors/2", &subs), first);
+
+        delivery.release(&subs[first].client_id);
+        let next = delivery.choose(&group, "sensors/1", &subs);
+        assert_ne!(next, first);
+        assert_eq!(delivery.choose(&group, "sensors/1", &subs), next);
+
+        // A member that left the group loses its topics
+        let remaining: Vec<_> = subs
+            .iter()
+            .filter(|s| s.client_id != subs[next].client_id)
+            .cloned()
+            .collect();
+        let moved = delivery.choose(&group, "sensors/1", &remaining);
+        assert_ne!(remaining[moved].client_id, subs[next].client_id);
+    }
+
+    #[test]
+    fn test_least_inflight() {
+        let delivery = SharedDelivery::new(SharedDeliveryStrategy::LeastInflight, &[]);
+        delivery.set_inflight_probe(Arc::new(|client_id: &str| match client_id {
+            "a" => 3,
+            "b" => 0,
+            _ => 1,
+        }));
+        let group: Arc<str> = "g".into();
+        let subs = members(&["a", "b", "c"]);
+        for _ in 0..3 {
+            assert_eq!(delivery.choose(&group, "t", &subs), 1);
+        }
+    }
+
+    #[test]
+    fn test_random_stays_in_range() {
+        let delivery = SharedDelivery::new(SharedDeliveryStrategy::Random, &[]);
+        let group: Arc<str> = "g".into();
+        let subs = members(&["a", "b"]);
+        for _ in 0..20 {
+            assert!(delivery.choose(&group, "t", &subs) < 2);
+        }
+    }
+}
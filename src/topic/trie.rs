@@ -1,26 +1,37 @@
 //! Topic Trie for efficient subscription matching
 //!
 //! A trie (prefix tree) data structure optimized for MQTT topic matching.
-//! Supports wildcards (+ and #) for subscription filters.
+//! Supports wildcards (+ and #) for subscription filters. A trie keyed by
+//! topic names instead, such as the retained message index, is searched
+//! with a filter through `matches_filter`.
+//!
+//! Nodes are shared through `Arc`, so cloning a trie is O(1) and mutating a
+//! clone copies only the nodes along the changed path (copy-on-write). This
+//! lets readers keep matching against a snapshot while a writer prepares the
+//! next one.
+//!
+//! Children are kept in a persistent map, so the copy of a node holding
+//! millions of children shares all but the changed path of that map.
 //!
 //! Performance optimizations:
 //! - Uses iterator-based traversal to avoid Vec allocations on every operation
-//! - Uses compact_str for memory-efficient topic level storage
-//! - Pre-allocates children HashMap capacity for common workloads
+//! - Topic levels are `Arc<str>`, so copying a path only bumps reference counts
+
+use std::sync::Arc;
 
-use ahash::AHashMap;
-use compact_str::CompactString;
 use smallvec::SmallVec;
 
+use super::persistent::PersistentMap;
+
 /// Node in the topic trie
 #[derive(Debug)]
 struct TrieNode<V> {
     /// Value stored at this node (subscription data)
     value: Option<V>,
-    /// Children indexed by topic level (CompactString avoids heap allocation for short strings)
-    children: AHashMap<CompactString, TrieNode<V>>,
+    /// Children indexed by topic level
+    children: PersistentMap<Arc<str>, Arc<TrieNode<V>>>,
     /// Single-level wildcard (+) child
-    single_wildcard: Option<Box<TrieNode<V>>>,
+    single_wildcard: Option<Arc<TrieNode<V>>>,
     /// Multi-level wildcard (#) value
     multi_wildcard: Option<V>,
 }
@@ -29,37 +40,59 @@ impl<V> TrieNode<V> {
     fn new() -> Self {
         Self {
             value: None,
-            // Most nodes have few children, but some may have many
-            children: AHashMap::with_capacity(4),
+            children: PersistentMap::new(),
             single_wildcard: None,
             multi_wildcard: None,
         }
     }
 }
 
+impl<V> TrieNode<V> {
+    fn is_empty(&self) -> bool {
+        self.value.is_none()
+            && self.multi_wildcard.is_none()
+            && self.single_wildcard.is_none()
+            && self.children.is_empty()
+    }
+}
+
 impl<V> Default for TrieNode<V> {
     fn default() -> Self {
         Self::new()
     }
 }
 
+impl<V: Clone> Clone for TrieNode<V> {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            children: self.children.clone(),
+            single_wildcard: self.single_wildcard.clone(),
+            multi_wildcard: self.multi_wildcard.clone(),
+        }
+    }
+}
+
 /// Topic Trie for efficient subscription matching
 #[derive(Debug)]
 pub struct TopicTrie<V> {
-    root: TrieNode<V>,
+    root: Arc<TrieNode<V>>,
 }
 
-impl<V> TopicTrie<V> {
-    pub fn new() -> Self {
+impl<V> Clone for TopicTrie<V> {
+    /// Snapshot the trie; nodes are shared until either copy changes them
+    fn clone(&self) -> Self {
         Self {
-            root: TrieNode::new(),
+            root: self.root.clone(),
         }
     }
+}
 
+impl<V: Clone> TopicTrie<V> {
     /// Insert a topic filter with associated value
     /// Uses iterator-based traversal to avoid Vec allocation
     pub fn insert(&mut self, filter: &str, value: V) {
-        let mut node = &mut self.root;
+        let mut node = Arc::make_mut(&mut self.root);
         let mut levels = filter.split('/').peekable();
 
         while let Some(level) = levels.next() {
@@ -71,13 +104,10 @@ impl<V> TopicTrie<V> {
                 return;
             } else if level == "+" {
                 // Single-level wildcard
-                if node.single_wildcard.is_none() {
-                    node.single_wildcard = Some(Box::new(TrieNode::new()));
-                }
-                node = node.single_wildcard.as_mut().unwrap();
+                node = Arc::make_mut(node.single_wildcard.get_or_insert_with(Default::default));
             } else {
-                // Normal level - use CompactString for efficient storage
-                node = node.children.entry(CompactString::new(level)).or_default();
+                // Normal level - the level is only copied for a new child
+                node = Arc::make_mut(node.children.get_or_insert_with(level, Default::default));
             }
 
             // If this is the last level, store the value
@@ -91,7 +121,7 @@ impl<V> TopicTrie<V> {
     /// Get a mutable reference to the value at a filter
     /// Uses iterator-based traversal to avoid Vec allocation
     pub fn get_mut(&mut self, filter: &str) -> Option<&mut V> {
-        let mut node = &mut self.root;
+        let mut node = Arc::make_mut(&mut self.root);
         let mut levels = filter.split('/').peekable();
 
         while let Some(level) = levels.next() {
@@ -100,9 +130,9 @@ impl<V> TopicTrie<V> {
             if level == "#" {
                 return node.multi_wildcard.as_mut();
             } else if level == "+" {
-                node = node.single_wildcard.as_mut()?;
+                node = Arc::make_mut(node.single_wildcard.as_mut()?);
             } else {
-                node = node.children.get_mut(level)?;
+                node = Arc::make_mut(node.children.get_mut(level)?);
             }
 
             if is_last {
@@ -113,11 +143,13 @@ impl<V> TopicTrie<V> {
         None
     }
 
-    /// Remove a filter from the trie
+    /// Remove a filter from the trie, pruning nodes left empty
     /// Uses SmallVec to avoid heap allocation for typical topic depths (up to 8 levels)
     pub fn remove(&mut self, filter: &str) -> Option<V> {
+        // Leave shared nodes alone when there is nothing to remove
+        self.get(filter)?;
         let levels: SmallVec<[&str; 8]> = filter.split('/').collect();
-        Self::remove_recursive(&mut self.root, &levels, 0)
+        Self::remove_recursive(Arc::make_mut(&mut self.root), &levels, 0)
     }
 
     fn remove_recursive(node: &mut TrieNode<V>, levels: &[&str], index: usize) -> Option<V> {
@@ -130,36 +162,32 @@ impl<V> TopicTrie<V> {
         match level {
             "#" => node.multi_wildcard.take(),
             "+" => {
-                if let Some(ref mut child) = node.single_wildcard {
-                    if index + 1 >= levels.len() {
-                        child.value.take()
-                    } else {
-                        Self::remove_recursive(child, levels, index + 1)
-                    }
-                } else {
-                    None
+                let child = Arc::make_mut(node.single_wildcard.as_mut()?);
+                let removed = Self::remove_recursive(child, levels, index + 1);
+                if child.is_empty() {
+                    node.single_wildcard = None;
                 }
+                removed
             }
             _ => {
-                if let Some(child) = node.children.get_mut(level) {
-                    if index + 1 >= levels.len() {
-                        child.value.take()
-                    } else {
-                        Self::remove_recursive(child, levels, index + 1)
-                    }
-                } else {
-                    None
+                let child = Arc::make_mut(node.children.get_mut(level)?);
+                let removed = Self::remove_recursive(child, levels, index + 1);
+                if child.is_empty() {
+                    node.children.remove(level);
                 }
+                removed
             }
         }
     }
 
     /// Remove entries by predicate (returns true if entry should be removed)
+    ///
+    /// Visits every node, so this copies all nodes shared with a snapshot.
     pub fn remove_by_predicate<F>(&mut self, mut pred: F)
     where
         F: FnMut(&mut V) -> bool,
     {
-        Self::remove_by_predicate_recursive(&mut self.root, &mut pred);
+        Self::remove_by_predicate_recursive(Arc::make_mut(&mut self.root), &mut pred);
     }
 
     fn remove_by_predicate_recursive<F>(node: &mut TrieNode<V>, pred: &mut F)
@@ -179,12 +207,44 @@ impl<V> TopicTrie<V> {
         }
 
         if let Some(ref mut child) = node.single_wildcard {
-            Self::remove_by_predicate_recursive(child, pred);
+            Self::remove_by_predicate_recursive(Arc::make_mut(child), pred);
+        }
+
+        node.children.for_each_mut(|_, child| {
+            Self::remove_by_predicate_recursive(Arc::make_mut(child), pred);
+        });
+    }
+}
+
+impl<V> TopicTrie<V> {
+    pub fn new() -> Self {
+        Self {
+            root: Arc::new(TrieNode::new()),
         }
+    }
+
+    /// Get a reference to the value at a filter
+    pub fn get(&self, filter: &str) -> Option<&V> {
+        let mut node = &self.root;
+        let mut levels = filter.split('/').peekable();
+
+        while let Some(level) = levels.next() {
+            let is_last = levels.peek().is_none();
 
-        for child in node.children.values_mut() {
-            Self::remove_by_predicate_recursive(child, pred);
+            if level == "#" {
+                return node.multi_wildcard.as_ref();
+            } else if level == "+" {
+                node = node.single_wildcard.as_ref()?;
+            } else {
+                node = node.children.get(level)?;
+            }
+
+            if is_last {
+                return node.value.as_ref();
+            }
         }
+
+        None
     }
 
     /// Find all matching subscriptions for a topic name
@@ -240,6 +300,79 @@ impl<V> TopicTrie<V> {
         }
     }
 
+    /// Find all values stored at topic names matching a filter
+    ///
+    /// The inverse of `matches`, for a trie keyed by topic names rather
+    /// than filters. Wildcards at the first level do not match topic names
+    /// starting with '$' [MQTT-4.7.2-1].
+    pub fn matches_filter<F>(&self, filter: &str, mut callback: F)
+    where
+        F: FnMut(&V),
+    {
+        let levels: SmallVec<[&str; 8]> = filter.split('/').collect();
+        Self::matches_filter_recursive(&self.root, &levels, 0, &mut callback);
+    }
+
+    fn matches_filter_recursive<F>(
+        node: &TrieNode<V>,
+        levels: &[&str],
+        index: usize,
+        callback: &mut F,
+    ) where
+        F: FnMut(&V),
+    {
+        if index >= levels.len() {
+            if let Some(ref v) = node.value {
+                callback(v);
+            }
+            return;
+        }
+
+        match levels[index] {
+            "#" => {
+                // "a/#" also matches "a" itself
+                if let Some(ref v) = node.value {
+                    callback(v);
+                }
+                for (level, child) in &node.children {
+                    if index == 0 && level.starts_with('$') {
+                        continue;
+                    }
+                    Self::for_each_recursive(child, callback);
+                }
+            }
+            "+" => {
+                for (level, child) in &node.children {
+                    if index == 0 && level.starts_with('$') {
+                        continue;
+                    }
+                    Self::matches_filter_recursive(child, levels, index + 1, callback);
+                }
+            }
+            level => {
+                if let Some(child) = node.children.get(level) {
+                    Self::matches_filter_recursive(child, levels, index + 1, callback);
+                }
+            }
+        }
+    }
+
+    /// Number of nodes in the trie, not counting the root
+    pub fn node_count(&self) -> usize {
+        Self::node_count_recursive(&self.root) - 1
+    }
+
+    fn node_count_recursive(node: &TrieNode<V>) -> usize {
+        let mut count = 1;
+        if let Some(ref child) = node.single_wildcard {
+            count += Self::node_count_recursive(child);
+        }
+        for child in node.children.values() {
+            count += Self::node_count_recursive(child);
+        }
+        count
+    }
+
     /// Iterate over all values in the trie
     pub fn for_each<F>(&self, mut callback: F)
     where
@@ -268,6 +401,56 @@ impl<V> TopicTrie<V> {
             Self::for_each_recursive(child, callback);
         }
     }
+
+    /// Iterate over all values in the trie along with their topic filter
+    pub fn for_each_with_filter<F>(&self, mut callback: F)
+    where
+        F: FnMut(&str, &V),
+    {
+        let mut filter = String::new();
+        Self::for_each_with_filter_recursive(&self.root, &mut filter, 0, &mut callback);
+    }
+
+    fn for_each_with_filter_recursive<F>(
+        node: &TrieNode<V>,
+        filter: &mut String,
+        depth: usize,
+        callback: &mut F,
+    ) where
+        F: FnMut(&str, &V),
+    {
+        if depth > 0 {
+            if let Some(ref v) = node.value {
+                callback(filter, v);
+            }
+        }
+
+        let len = filter.len();
+        let push_level = |filter: &mut String, level: &str| {
+            filter.truncate(len);
+            if depth > 0 {
+                filter.push('/');
+            }
+            filter.push_str(level);
+        };
+
+        if let Some(ref v) = node.multi_wildcard {
+            push_level(filter, "#");
+            callback(filter, v);
+        }
+
+        if let Some(ref child) = node.single_wildcard {
+            push_level(filter, "+");
+            Self::for_each_with_filter_recursive(child, filter, depth + 1, callback);
+        }
+
+        for (level, child) in &node.children {
+            push_level(filter, level);
+            Self::for_each_with_filter_recursive(child, filter, depth + 1, callback);
+        }
+
+        filter.truncate(len);
+    }
 }
 
 impl<V> Default for TopicTrie<V> {
@@ -344,4 +527,132 @@ mod tests {
         trie.matches("test/topic", |v| matches.push(*v));
         assert!(matches.is_empty());
     }
+
+    #[test]
+    fn test_remove_prunes_empty_nodes() {
+        let mut trie = TopicTrie::new();
+        trie.insert("a/b/c", 1);
+        trie.insert("a", 2);
+
+        assert_eq!(trie.remove("a/b/c"), Some(1));
+        assert!(trie.root.children.get("a").unwrap().children.is_empty());
+        assert_eq!(trie.get("a"), Some(&2));
+
+        assert_eq!(trie.remove("a"), Some(2));
+        assert!(trie.root.is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_copy_on_write() {
+        let mut trie = TopicTrie::new();
+        trie.insert("a/b", 1);
+        trie.insert("c/d", 2);
+
+        let snapshot = trie.clone();
+        trie.insert("a/b", 3);
+        trie.remove("c/d");
+        trie.insert("a/+", 4);
+
+        // The snapshot keeps its values
+        assert_eq!(snapshot.get("a/b"), Some(&1));
+        assert_eq!(snapshot.get("c/d"), Some(&2));
+        assert_eq!(snapshot.get("a/+"), None);
+        assert_eq!(trie.get("a/b"), Some(&3));
+        assert_eq!(trie.get("c/d"), None);
+
+        // Untouched subtrees stay shared
+        let mut trie = snapshot.clone();
+        trie.insert("a/x", 5);
+        assert!(Arc::ptr_eq(
+            trie.root.children.get("c").unwrap(),
+            snapshot.root.children.get("c").unwrap()
+        ));
+        assert!(!Arc::ptr_eq(
+            trie.root.children.get("a").unwrap(),
+            snapshot.root.children.get("a").unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_node_count() {
+        let mut trie = TopicTrie::new();
+        assert_eq!(trie.node_count(), 0);
+
+        trie.insert("a/b/c", 1);
+        trie.insert("a/+/c", 2);
+        trie.insert("a/#", 3);
+        // a, a/b, a/b/c, a/+, a/+/c; "#" is stored on its parent
+        assert_eq!(trie.node_count(), 5);
+
+        trie.remove("a/b/c");
+        assert_eq!(trie.node_count(), 3);
+    }
+
+    #[test]
+    fn test_matches_filter() {
+        let mut trie = TopicTrie::new();
+        for topic in [
+            "sensors",
+            "sensors/1/temp",
+            "sensors/2/temp",
+            "sensors/2/hum",
+            "$SYS/uptime",
+            "/x",
+        ] {
+            trie.insert(topic, topic);
+        }
+        let lookup = |filter: &str| {
+            let mut found = Vec::new();
+            trie.matches_filter(filter, |v| found.push(*v));
+            found.sort();
+            found
+        };
+
+        assert_eq!(lookup("sensors/1/temp"), vec!["sensors/1/temp"]);
+        assert_eq!(
+            lookup("sensors/+/temp"),
+            vec!["sensors/1/temp", "sensors/2/temp"]
+        );
+        assert_eq!(
+            lookup("sensors/#"),
+            vec![
+                "sensors",
+                "sensors/1/temp",
+                "sensors/2/hum",
+                "sensors/2/temp"
+            ]
+        );
+        assert_eq!(lookup("sensors/+"), Vec::<&str>::new());
+        assert_eq!(lookup("+/x"), vec!["/x"]);
+        // Wildcards at the first level skip $-topics
+        assert!(!lookup("#").contains(&"$SYS/uptime"));
+        assert!(lookup("+/uptime").is_empty());
+        assert_eq!(lookup("$SYS/#"), vec!["$SYS/uptime"]);
+    }
+
+    #[test]
+    fn test_for_each_with_filter() {
+        let mut trie = TopicTrie::new();
+        trie.insert("a/b", 1);
+        trie.insert("a/+", 2);
+        trie.insert("a/#", 3);
+        trie.insert("#", 4);
+        trie.insert("/x", 5);
+        trie.insert("+/c/+", 6);
+
+        let mut filters = Vec::new();
+        trie.for_each_with_filter(|filter, v| filters.push((filter.to_string(), *v)));
+        filters.sort();
+        assert_eq!(
+            filters,
+            vec![
+                ("#".to_string(), 4),
+                ("+/c/+".to_string(), 6),
+                ("/x".to_string(), 5),
+                ("a/#".to_string(), 3),
+                ("a/+".to_string(), 2),
+                ("a/b".to_string(), 1),
+            ]
+        );
+    }
 }
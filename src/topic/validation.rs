@@ -94,7 +94,7 @@ pub fn validate_topic_name_with_max_levels(
 /// - Shared subscriptions ($share/{group}/{filter}) are also supported
 /// - Must not exceed max_topic_levels if set (0 = unlimited)
 pub fn validate_topic_filter(filter: &str) -> Result<(), &'static str> {
-    validate_topic_filter_with_max_levels(filter, 0)
+    validate_topic_filter_with_max_levels(filter, 0, false)
 }
 
 /// Validate a topic filter with configurable max levels
@@ -108,11 +108,13 @@ pub fn validate_topic_filter(filter: &str) -> Result<(), &'static str> {
 ///   - Preceded by a level separator (/)
 ///   - The last character
 /// - Single-level wildcard (+) must occupy an entire level
-/// - Shared subscriptions ($share/{group}/{filter}) are also supported
+/// - Shared subscriptions ($share/{group}/{filter}, and $queue/{filter} with
+///   queue_subscriptions) are also supported
 /// - Must not exceed max_topic_levels if set (0 = unlimited)
 pub fn validate_topic_filter_with_max_levels(
     filter: &str,
     max_topic_levels: usize,
+    queue_subscriptions: bool,
 ) -> Result<(), &'static str> {
     if filter.is_empty() {
         return Err("topic filter cannot be empty");
@@ -145,6 +147,14 @@ pub fn validate_topic_filter_with_max_levels(
         } else {
             return Err("invalid shared subscription format");
         }
+    } else if let Some(actual) = filter
+        .strip_prefix("$queue/")
+        .filter(|_| queue_subscriptions)
+    {
+        if actual.is_empty() {
+            return Err("queue subscription filter cannot be empty");
+        }
+        actual
     } else {
         filter
     };
@@ -178,6 +188,17 @@ pub fn validate_topic_filter_with_max_levels(
     Ok(())
 }
 
+/// Check if a topic is in a namespace reserved for the server, such as
+/// `$SYS`; clients may not publish there
+pub fn is_reserved_topic(topic: &str) -> bool {
+    topic.starts_with('$')
+}
+
+/// Check if a topic or filter is below `$SYS`
+pub fn is_sys_topic(topic: &str) -> bool {
+    topic == "$SYS" || topic.starts_with("$SYS/")
+}
+
 /// Check if a topic filter matches a topic name
 ///
 /// Matching rules:
@@ -297,6 +318,18 @@ mod tests {
         assert!(topic_matches_filter("$SYS/test", "$SYS/#"));
     }
 
+    #[test]
+    fn test_reserved_topics() {
+        assert!(is_reserved_topic("$SYS/broker/uptime"));
+        assert!(is_reserved_topic("$internal/x"));
+        assert!(!is_reserved_topic("sensors/$x"));
+
+        assert!(is_sys_topic("$SYS"));
+        assert!(is_sys_topic("$SYS/#"));
+        assert!(!is_sys_topic("$SYSTEM/x"));
+        assert!(!is_sys_topic("$share/g/$SYS/#"));
+    }
+
     #[test]
     fn test_validate_topic_name_max_levels() {
         // 0 = unlimited (no limit enforced)
@@ -323,28 +356,36 @@ mod tests {
     #[test]
     fn test_validate_topic_filter_max_levels() {
         // 0 = unlimited (no limit enforced)
-        assert!(validate_topic_filter_with_max_levels("a/b/c/d/e", 0).is_ok());
-        assert!(validate_topic_filter_with_max_levels("a/+/c/#", 0).is_ok());
+        assert!(validate_topic_filter_with_max_levels("a/b/c/d/e", 0, false).is_ok());
+        assert!(validate_topic_filter_with_max_levels("a/+/c/#", 0, false).is_ok());
 
         // Within limit
-        assert!(validate_topic_filter_with_max_levels("a", 3).is_ok());
-        assert!(validate_topic_filter_with_max_levels("a/b", 3).is_ok());
-        assert!(validate_topic_filter_with_max_levels("a/b/c", 3).is_ok());
-        assert!(validate_topic_filter_with_max_levels("a/+/c", 3).is_ok());
-        assert!(validate_topic_filter_with_max_levels("a/b/#", 3).is_ok());
+        assert!(validate_topic_filter_with_max_levels("a", 3, false).is_ok());
+        assert!(validate_topic_filter_with_max_levels("a/b", 3, false).is_ok());
+        assert!(validate_topic_filter_with_max_levels("a/b/c", 3, false).is_ok());
+        assert!(validate_topic_filter_with_max_levels("a/+/c", 3, false).is_ok());
+        assert!(validate_topic_filter_with_max_levels("a/b/#", 3, false).is_ok());
 
         // Exceeds limit
-        assert!(validate_topic_filter_with_max_levels("a/b/c/d", 3).is_err());
-        assert!(validate_topic_filter_with_max_levels("a/+/c/d", 3).is_err());
-        assert!(validate_topic_filter_with_max_levels("a/b/c/#", 3).is_err()); // # counts as a level
+        assert!(validate_topic_filter_with_max_levels("a/b/c/d", 3, false).is_err());
+        assert!(validate_topic_filter_with_max_levels("a/+/c/d", 3, false).is_err());
+        assert!(validate_topic_filter_with_max_levels("a/b/c/#", 3, false).is_err()); // # counts as a level
 
         // Edge cases
-        assert!(validate_topic_filter_with_max_levels("#", 1).is_ok());
-        assert!(validate_topic_filter_with_max_levels("+", 1).is_ok());
-        assert!(validate_topic_filter_with_max_levels("a/#", 1).is_err());
+        assert!(validate_topic_filter_with_max_levels("#", 1, false).is_ok());
+        assert!(validate_topic_filter_with_max_levels("+", 1, false).is_ok());
+        assert!(validate_topic_filter_with_max_levels("a/#", 1, false).is_err());
 
         // Shared subscriptions - only the actual filter part counts
-        assert!(validate_topic_filter_with_max_levels("$share/group/a/b/c", 3).is_ok());
-        assert!(validate_topic_filter_with_max_levels("$share/group/a/b/c/d", 3).is_err());
+        assert!(validate_topic_filter_with_max_levels("$share/group/a/b/c", 3, false).is_ok());
+        assert!(validate_topic_filter_with_max_levels("$share/group/a/b/c/d", 3, false).is_err());
+        assert!(validate_topic_filter_with_max_levels("$queue/a/b/c", 3, true).is_ok());
+        assert!(validate_topic_filter_with_max_levels("$queue/a/b/c/d", 3, true).is_err());
+        assert!(validate_topic_filter_with_max_levels("$queue/", 0, true).is_err());
+
+        // Without queue subscriptions, $queue is just the first level
+        assert!(validate_topic_filter_with_max_levels("$queue/a/b", 3, false).is_ok());
+        assert!(validate_topic_filter_with_max_levels("$queue/a/b/c", 3, false).is_err());
+        assert!(validate_topic_filter("$queue/").is_ok());
     }
 }
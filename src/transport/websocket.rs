@@ -1,7 +1,9 @@
 //! WebSocket Transport
 //!
 //! Provides a wrapper around tokio-tungstenite WebSocket that implements
-//! AsyncRead and AsyncWrite for use with MQTT over WebSocket.
+//! AsyncRead and AsyncWrite for use with MQTT over WebSocket. The server
+//! side accepts connections on the WebSocket listener; the client side is
+//! used by bridges to reach brokers behind HTTP(S) load balancers.
 
 use std::collections::VecDeque;
 use std::io::{self};
@@ -13,6 +15,7 @@ use futures_util::stream::{SplitSink, SplitStream};
 use futures_util::{Sink, Stream, StreamExt};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
 use tokio_tungstenite::tungstenite::protocol::Message;
 use tokio_tungstenite::WebSocketStream;
 
@@ -21,11 +24,11 @@ use tokio_tungstenite::WebSocketStream;
 /// MQTT over WebSocket uses binary frames to transport MQTT packets.
 /// This wrapper buffers incoming binary messages and presents them
 /// as a continuous byte stream.
-pub struct WsStream {
+pub struct WsStream<S = TcpStream> {
     /// Split sink for writing
-    sink: SplitSink<WebSocketStream<TcpStream>, Message>,
+    sink: SplitSink<WebSocketStream<S>, Message>,
     /// Split stream for reading
-    stream: SplitStream<WebSocketStream<TcpStream>>,
+    stream: SplitStream<WebSocketStream<S>>,
     /// Read buffer for incomplete reads
     read_buffer: BytesMut,
     /// Write buffer for batching small writes
@@ -36,9 +39,9 @@ pub struct WsStream {
     closed: bool,
 }
 
-impl WsStream {
+impl<S: AsyncRead + AsyncWrite + Unpin> WsStream<S> {
     /// Create a new WebSocket stream wrapper
-    pub fn new(ws: WebSocketStream<TcpStream>) -> Self {
+    pub fn new(ws: WebSocketStream<S>) -> Self {
         let (sink, stream) = ws.split();
         Self {
             sink,
@@ -50,12 +53,33 @@ impl WsStream {
         }
     }
 
+    /// Open a client WebSocket connection requesting the MQTT subprotocol
+    ///
+    /// `stream` is already connected (and TLS-wrapped for `wss` URLs); the
+    /// URL supplies the Host header and request path.
+    pub async fn connect(stream: S, url: &str) -> Result<Self, io::Error> {
+        let mut request = url.into_client_request().map_err(io::Error::other)?;
+        request
+            .headers_mut()
+            .insert("sec-websocket-protocol", "mqtt".parse().unwrap());
+
+        let (ws, _response) = tokio_tungstenite::client_async(request, stream)
+            .await
+            .map_err(io::Error::other)?;
+
+        Ok(Self::new(ws))
+    }
+}
+
+impl WsStream {
     /// Accept a WebSocket connection with MQTT subprotocol
     pub async fn accept(stream: TcpStream) -> Result<Self, io::Error> {
         Self::accept_with_path(stream, "/mqtt").await
     }
 
     /// Accept a WebSocket connection with MQTT subprotocol and path validation
+    // The handshake callback's error type is fixed by tungstenite
+    #[allow(clippy::result_large_err)]
     pub async fn accept_with_path(
         stream: TcpStream,
         expected_path: &str,
@@ -96,7 +120,7 @@ impl WsStream {
     }
 }
 
-impl AsyncRead for WsStream {
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WsStream<S> {
     fn poll_read(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
@@ -164,12 +188,20 @@ impl AsyncRead for WsStream {
     }
 }
 
-impl AsyncWrite for WsStream {
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WsStream<S> {
     fn poll_write(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<io::Result<usize>> {
+        // The sink holds the last message until flushed, so push out the
+        // previous one before taking another
+        match Pin::new(&mut self.sink).poll_flush(cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(io::Error::other(e))),
+            Poll::Pending => return Poll::Pending,
+        }
+
         // Buffer the write
         self.write_buffer.extend_from_slice(buf);
 
@@ -179,7 +211,12 @@ impl AsyncWrite for WsStream {
 
         match Pin::new(&mut self.sink).poll_ready(cx) {
             Poll::Ready(Ok(())) => match Pin::new(&mut self.sink).start_send(message) {
-                Ok(()) => Poll::Ready(Ok(buf.len())),
+                Ok(()) => {
+                    // Send it now rather than with the next write; a flush
+                    // that cannot complete yet finishes on the next call
+                    let _ = Pin::new(&mut self.sink).poll_flush(cx);
+                    Poll::Ready(Ok(buf.len()))
+                }
                 Err(e) => Poll::Ready(Err(io::Error::other(e))),
             },
             Poll::Ready(Err(e)) => Poll::Ready(Err(io::Error::other(e))),
@@ -11,10 +11,15 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::time::timeout;
 
-use vibemq::bridge::{BridgeConfig, ForwardDirection, ForwardRule, LoopPrevention};
+use vibemq::bridge::{
+    BridgeConfig, BridgeProtocol, ForwardDirection, ForwardRule, LoopPrevention, QosMode,
+};
 use vibemq::broker::{Broker, BrokerConfig};
 use vibemq::codec::{Decoder, Encoder};
-use vibemq::config::ProxyProtocolConfig;
+use vibemq::config::{
+    MountPointConfig, ProxyProtocolConfig, QueueOverflowPolicy, RetainedPolicy,
+    RetryExhaustedPolicy, SharedDeliveryStrategy, SlowConsumerConfig, TenancyConfig,
+};
 use vibemq::protocol::{
     Connect, Packet, Properties, ProtocolVersion, Publish, QoS, ReasonCode, Subscribe,
     Subscription, SubscriptionOptions,
@@ -39,7 +44,9 @@ fn test_broker_config(port: u16) -> BrokerConfig {
         max_packet_size: 1024 * 1024,
         default_keep_alive: 60,
         max_keep_alive: 300,
+        server_keep_alive: None,
         session_expiry_check_interval: Duration::from_secs(60),
+        parked_timeout: Duration::ZERO,
         receive_maximum: 65535,
         max_qos: QoS::ExactlyOnce,
         retain_available: true,
@@ -50,15 +57,46 @@ fn test_broker_config(port: u16) -> BrokerConfig {
         num_workers: 2,
         sys_topics_enabled: false,
         sys_topics_interval: Duration::from_secs(10),
+        sys_filter_topics: false,
+        sys_session_topics: false,
+        sys_client_topics: false,
+        sys_client_interval: Duration::from_secs(60),
+        presence_topics: false,
         max_inflight: 32,
         max_queued_messages: 1000,
+        queue_overflow: QueueOverflowPolicy::default(),
+        spill_threshold: 0,
         max_awaiting_rel: 100,
         retry_interval: Duration::from_secs(30),
+        retry_max_interval: Duration::ZERO,
+        max_retries: 0,
+        retry_exhausted: RetryExhaustedPolicy::Drop,
         outbound_channel_capacity: 1024,
+        write_coalesce_bytes: 64 * 1024,
+        write_coalesce_delay: Duration::ZERO,
+        fanout_threshold: 128,
+        slow_consumer: SlowConsumerConfig::default(),
         max_topic_levels: 0,
+        max_subscriptions_per_client: 0,
         proxy_protocol: ProxyProtocolConfig::default(),
         tls_proxy_protocol: ProxyProtocolConfig::default(),
         ws_proxy_protocol: ProxyProtocolConfig::default(),
+        max_retained_messages: 0,
+        max_retained_bytes: 0,
+        retained_policy: RetainedPolicy::default(),
+        tenancy: TenancyConfig::default(),
+        mount_points: MountPointConfig::default(),
+        redirect: None,
+        validate_payload_format: false,
+        content_types: Vec::new(),
+        payload_limits: Vec::new(),
+        schemas: Default::default(),
+        strict: true,
+        ordered_delivery: false,
+        topic_priorities: Vec::new(),
+        shared_subscription_strategy: SharedDeliveryStrategy::RoundRobin,
+        shared_subscription_groups: Vec::new(),
+        queue_subscriptions: false,
     }
 }
 
@@ -169,7 +207,7 @@ impl TestClient {
             dup: false,
             qos,
             retain,
-            topic: topic.to_string(),
+            topic: topic.into(),
             packet_id,
             payload: Bytes::copy_from_slice(payload),
             properties: Properties::default(),
@@ -204,7 +242,9 @@ async fn test_bridge_manager_creation() {
             remote_topic: "test/#".to_string(),
             direction: ForwardDirection::Out,
             qos: 1,
+            qos_mode: QosMode::Cap,
             retain: true,
+            topic_regex: None,
         }],
     )];
 
@@ -253,6 +293,7 @@ async fn test_broker_events_include_payload() {
                     payload,
                     qos,
                     retain,
+                    ..
                 })) => {
                     assert_eq!(topic, "test/topic");
                     assert_eq!(&payload[..], b"hello bridge");
@@ -267,6 +308,7 @@ async fn test_broker_events_include_payload() {
             payload,
             qos,
             retain,
+            ..
         })) => {
             assert_eq!(topic, "test/topic");
             assert_eq!(&payload[..], b"hello bridge");
@@ -295,7 +337,9 @@ async fn test_inbound_callback_routing() {
             remote_topic: "remote/#".to_string(),
             direction: ForwardDirection::In,
             qos: 1,
+            qos_mode: QosMode::Cap,
             retain: true,
+            topic_regex: None,
         }],
     )];
 
@@ -353,7 +397,9 @@ async fn test_two_broker_bridge_outbound() {
             remote_topic: "sensors/#".to_string(),
             direction: ForwardDirection::Out,
             qos: 1,
+            qos_mode: QosMode::Cap,
             retain: true,
+            topic_regex: None,
         }],
     )];
 
@@ -398,6 +444,150 @@ async fn test_two_broker_bridge_outbound() {
     broker2_handle.abort();
 }
 
+/// Bridge to a broker that is only reachable over WebSocket
+#[tokio::test]
+async fn test_bridge_over_websocket() {
+    let remote_port = next_port();
+    let remote_ws_port = next_port();
+
+    let mut remote_config = test_broker_config(remote_port);
+    remote_config.ws_bind_addr = Some(SocketAddr::from(([127, 0, 0, 1], remote_ws_port)));
+    let remote = Broker::new(remote_config);
+    let remote_handle = tokio::spawn(async move {
+        let _ = remote.run().await;
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], remote_port));
+    let mut subscriber = TestClient::connect(addr, ProtocolVersion::V5).await;
+    subscriber.mqtt_connect("ws-remote-subscriber").await;
+    subscriber.subscribe(1, "edge/#", QoS::AtLeastOnce).await;
+
+    let local = Broker::new(test_broker_config(next_port()));
+    let mut bridge_config = test_bridge_config(
+        "ws-bridge",
+        remote_ws_port,
+        vec![ForwardRule {
+            local_topic: "sensors/#".to_string(),
+            remote_topic: "edge/#".to_string(),
+            direction: ForwardDirection::Out,
+            qos: 1,
+            qos_mode: QosMode::Cap,
+            retain: false,
+            topic_regex: None,
+        }],
+    );
+    bridge_config.protocol = BridgeProtocol::Ws;
+    let bridge_manager = local.create_bridge_manager(vec![bridge_config]);
+    bridge_manager.start_all().await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(bridge_manager.connected_count(), 1);
+
+    bridge_manager
+        .forward_publish(
+            "sensors/temp",
+            Bytes::from_static(b"21.5"),
+            QoS::AtLeastOnce,
+            false,
+        )
+        .await;
+
+    match timeout(Duration::from_secs(2), subscriber.recv()).await {
+        Ok(Some(Packet::Publish(publish))) => {
+            assert_eq!(publish.topic, "edge/temp");
+            assert_eq!(&publish.payload[..], b"21.5");
+        }
+        other => panic!(
+            "Expected PUBLISH over the WebSocket bridge, got {:?}",
+            other
+        ),
+    }
+
+    let health = bridge_manager.health();
+    assert_eq!(health[0].forwarded_out, 1);
+    let address = format!("127.0.0.1:{}", remote_ws_port);
+    assert_eq!(health[0].address.as_deref(), Some(address.as_str()));
+
+    bridge_manager.stop_all().await;
+    remote_handle.abort();
+}
+
+/// Test that an http bridge posts forwarded messages to a webhook
+#[tokio::test]
+async fn test_bridge_to_webhook() {
+    let webhook_port = next_port();
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", webhook_port))
+        .await
+        .unwrap();
+    // Minimal HTTP server: capture one request and answer 204
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.ends_with(b"21.5") {
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(n > 0, "connection closed before the request body");
+            request.extend_from_slice(&buf[..n]);
+        }
+        stream
+            .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+            .await
+            .unwrap();
+        String::from_utf8(request).unwrap()
+    });
+
+    let local = Broker::new(test_broker_config(next_port()));
+    let mut bridge_config = test_bridge_config(
+        "webhook",
+        webhook_port,
+        vec![ForwardRule {
+            local_topic: "sensors/#".to_string(),
+            remote_topic: "sensors/#".to_string(),
+            direction: ForwardDirection::Out,
+            qos: 1,
+            qos_mode: QosMode::Cap,
+            retain: false,
+            topic_regex: None,
+        }],
+    );
+    bridge_config.protocol = BridgeProtocol::Http;
+    bridge_config.http.path = "/ingest/{1}".to_string();
+    bridge_config
+        .http
+        .headers
+        .insert("X-Topic".to_string(), "{topic}".to_string());
+    let bridge_manager = local.create_bridge_manager(vec![bridge_config]);
+    bridge_manager.start_all().await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    bridge_manager
+        .forward_publish(
+            "sensors/dev42/temp",
+            Bytes::from_static(b"21.5"),
+            QoS::AtLeastOnce,
+            false,
+        )
+        .await;
+
+    let request = timeout(Duration::from_secs(2), server)
+        .await
+        .expect("webhook request")
+        .unwrap();
+    assert!(
+        request.starts_with("POST /ingest/dev42 HTTP/1.1\r\n"),
+        "{}",
+        request
+    );
+    assert!(request
+        .to_ascii_lowercase()
+        .contains("x-topic: sensors/dev42/temp\r\n"));
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(bridge_manager.health()[0].forwarded_out, 1);
+
+    bridge_manager.stop_all().await;
+}
+
 // =============================================================================
 // Loop Prevention Tests
 // =============================================================================
@@ -485,7 +675,9 @@ async fn test_bridge_manager_status() {
                 remote_topic: "test1/#".to_string(),
                 direction: ForwardDirection::Out,
                 qos: 1,
+                qos_mode: QosMode::Cap,
                 retain: true,
+                topic_regex: None,
             }],
         ),
         test_bridge_config(
@@ -496,7 +688,9 @@ async fn test_bridge_manager_status() {
                 remote_topic: "test2/#".to_string(),
                 direction: ForwardDirection::Out,
                 qos: 1,
+                qos_mode: QosMode::Cap,
                 retain: true,
+                topic_regex: None,
             }],
         ),
     ];
@@ -5,16 +5,22 @@
 
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
-use bytes::{Bytes, BytesMut};
+use bytes::{Buf, Bytes, BytesMut};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::time::timeout;
 
-use vibemq::broker::{Broker, BrokerConfig};
+use vibemq::broker::{Broker, BrokerConfig, BrokerEvent, DisconnectReason, DropReason};
 use vibemq::codec::{Decoder, Encoder};
-use vibemq::config::ProxyProtocolConfig;
+use vibemq::config::{
+    MountPointConfig, ProxyProtocolConfig, QueueOverflowPolicy, RetainedPolicy,
+    RetryExhaustedPolicy, SharedDeliveryStrategy, SlowConsumerConfig, SyncMode, TenancyConfig,
+};
+use vibemq::hooks::{ClientContext, HookDecision, HookResult, Hooks, PublishTransform};
+use vibemq::persistence::{FjallBackend, PersistenceManager, StorageBackend};
 use vibemq::protocol::{
     ConnAck, Connect, Disconnect, Packet, Properties, ProtocolVersion, PubRel, Publish, QoS,
     ReasonCode, RetainHandling, SubAck, Subscribe, Subscription, SubscriptionOptions, Unsubscribe,
@@ -40,7 +46,9 @@ fn test_config(port: u16) -> BrokerConfig {
         max_packet_size: 1024 * 1024,
         default_keep_alive: 60,
         max_keep_alive: 300,
+        server_keep_alive: None,
         session_expiry_check_interval: Duration::from_secs(60),
+        parked_timeout: Duration::ZERO,
         receive_maximum: 65535,
         max_qos: QoS::ExactlyOnce,
         retain_available: true,
@@ -51,15 +59,46 @@ fn test_config(port: u16) -> BrokerConfig {
         num_workers: 2,
         sys_topics_enabled: false, // Disable in tests
         sys_topics_interval: Duration::from_secs(10),
+        sys_filter_topics: false,
+        sys_session_topics: false,
+        sys_client_topics: false,
+        sys_client_interval: Duration::from_secs(60),
+        presence_topics: false,
         max_inflight: 32,
         max_queued_messages: 1000,
+        queue_overflow: QueueOverflowPolicy::default(),
+        spill_threshold: 0,
         max_awaiting_rel: 100,
         retry_interval: Duration::from_secs(30),
+        retry_max_interval: Duration::ZERO,
+        max_retries: 0,
+        retry_exhausted: RetryExhaustedPolicy::Drop,
         outbound_channel_capacity: 1024,
+        write_coalesce_bytes: 64 * 1024,
+        write_coalesce_delay: Duration::ZERO,
+        fanout_threshold: 128,
+        slow_consumer: SlowConsumerConfig::default(),
         max_topic_levels: 0,
+        max_subscriptions_per_client: 0,
         proxy_protocol: ProxyProtocolConfig::default(),
         tls_proxy_protocol: ProxyProtocolConfig::default(),
         ws_proxy_protocol: ProxyProtocolConfig::default(),
+        max_retained_messages: 0,
+        max_retained_bytes: 0,
+        retained_policy: RetainedPolicy::default(),
+        tenancy: TenancyConfig::default(),
+        mount_points: MountPointConfig::default(),
+        redirect: None,
+        validate_payload_format: false,
+        content_types: Vec::new(),
+        payload_limits: Vec::new(),
+        schemas: Default::default(),
+        strict: true,
+        ordered_delivery: false,
+        topic_priorities: Vec::new(),
+        shared_subscription_strategy: SharedDeliveryStrategy::RoundRobin,
+        shared_subscription_groups: Vec::new(),
+        queue_subscriptions: false,
     }
 }
 
@@ -161,7 +200,7 @@ impl TestClient {
             dup: false,
             qos,
             retain,
-            topic: topic.to_string(),
+            topic: topic.into(),
             packet_id,
             payload: Bytes::copy_from_slice(payload),
             properties: Properties::default(),
@@ -338,7 +377,7 @@ async fn test_publish_qos1_flow() {
         dup: false,
         qos: QoS::AtLeastOnce,
         retain: false,
-        topic: "qos1/test".to_string(),
+        topic: "qos1/test".into(),
         packet_id: Some(100),
         payload: Bytes::from_static(b"qos1 message"),
         properties: Properties::default(),
@@ -377,7 +416,7 @@ async fn test_publish_qos2_flow() {
         dup: false,
         qos: QoS::ExactlyOnce,
         retain: false,
-        topic: "qos2/test".to_string(),
+        topic: "qos2/test".into(),
         packet_id: Some(200),
         payload: Bytes::from_static(b"qos2 message"),
         properties: Properties::default(),
@@ -536,6 +575,49 @@ async fn test_retained_message() {
     broker_handle.abort();
 }
 
+/// Test that a QoS 0 retained message the full store rejects is still delivered
+#[tokio::test]
+async fn test_retained_store_full_still_routes_qos0() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.max_retained_messages = 1;
+    let broker = Broker::new(config);
+
+    let broker_handle = tokio::spawn(async move {
+        let _ = broker.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    let mut subscriber = TestClient::connect(addr, ProtocolVersion::V311).await;
+    subscriber.mqtt_connect("full-retain-sub", true).await;
+    subscriber.subscribe(1, "full/+", QoS::AtMostOnce).await;
+
+    let mut publisher = TestClient::connect(addr, ProtocolVersion::V311).await;
+    publisher.mqtt_connect("full-retain-pub", true).await;
+    for (topic, payload) in [("full/1", b"kept"), ("full/2", b"sent")] {
+        publisher
+            .publish(topic, payload, QoS::AtMostOnce, true)
+            .await;
+        match subscriber.recv().await {
+            Some(Packet::Publish(msg)) => {
+                assert_eq!(msg.topic, topic);
+                assert_eq!(&msg.payload[..], payload);
+            }
+            other => panic!("Expected PUBLISH, got {:?}", other),
+        }
+    }
+
+    // Only the first was retained
+    let mut late = TestClient::connect(addr, ProtocolVersion::V311).await;
+    late.mqtt_connect("full-retain-late", true).await;
+    late.subscribe(1, "full/2", QoS::AtMostOnce).await;
+    assert!(late.recv().await.is_none());
+
+    broker_handle.abort();
+}
+
 // ============================================================================
 // Will Message Tests (MQTT-3.1.2.5)
 // ============================================================================
@@ -595,6 +677,115 @@ async fn test_will_message_on_disconnect() {
     broker_handle.abort();
 }
 
+/// CONNECT with a v5 will delayed by `will_delay` seconds
+fn connect_with_delayed_will(client_id: &str, clean_start: bool, will_delay: u32) -> Packet {
+    Packet::Connect(Box::new(Connect {
+        protocol_version: ProtocolVersion::V5,
+        client_id: client_id.to_string(),
+        clean_start,
+        keep_alive: 60,
+        username: None,
+        password: None,
+        will: Some(Will {
+            topic: "client/status".to_string(),
+            payload: Bytes::from_static(b"offline"),
+            qos: QoS::AtMostOnce,
+            retain: false,
+            properties: Properties {
+                will_delay_interval: Some(will_delay),
+                ..Default::default()
+            },
+        }),
+        properties: Properties {
+            session_expiry_interval: Some(60),
+            ..Default::default()
+        },
+    }))
+}
+
+#[tokio::test]
+async fn test_will_delay_interval() {
+    let port = next_port();
+    let broker = Broker::new(test_config(port));
+    let broker_handle = tokio::spawn(async move {
+        let _ = broker.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    let mut subscriber = TestClient::connect(addr, ProtocolVersion::V5).await;
+    subscriber.mqtt_connect("delay-sub", true).await;
+    subscriber
+        .subscribe(1, "client/status", QoS::AtMostOnce)
+        .await;
+
+    let mut will_client = TestClient::connect(addr, ProtocolVersion::V5).await;
+    will_client
+        .send(&connect_with_delayed_will("delay-client", true, 2))
+        .await;
+    let _ = will_client.recv().await; // CONNACK
+    drop(will_client);
+
+    // Not before the delay has passed
+    assert!(
+        timeout(Duration::from_millis(1500), subscriber.recv())
+            .await
+            .is_err(),
+        "Will published before its delay"
+    );
+
+    match subscriber.recv().await {
+        Some(Packet::Publish(msg)) => {
+            assert_eq!(msg.topic, "client/status");
+            assert_eq!(&msg.payload[..], b"offline");
+        }
+        other => panic!("Expected delayed will, got {:?}", other),
+    }
+
+    broker_handle.abort();
+}
+
+#[tokio::test]
+async fn test_will_delay_cancelled_by_reconnect() {
+    let port = next_port();
+    let broker = Broker::new(test_config(port));
+    let broker_handle = tokio::spawn(async move {
+        let _ = broker.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    let mut subscriber = TestClient::connect(addr, ProtocolVersion::V5).await;
+    subscriber.mqtt_connect("cancel-sub", true).await;
+    subscriber
+        .subscribe(1, "client/status", QoS::AtMostOnce)
+        .await;
+
+    let mut will_client = TestClient::connect(addr, ProtocolVersion::V5).await;
+    will_client
+        .send(&connect_with_delayed_will("cancel-client", false, 2))
+        .await;
+    let _ = will_client.recv().await; // CONNACK
+    drop(will_client);
+
+    // Resume the session within the delay [MQTT-3.1.3-9]
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let mut will_client = TestClient::connect(addr, ProtocolVersion::V5).await;
+    let connack = will_client.mqtt_connect("cancel-client", false).await;
+    assert!(connack.session_present);
+
+    assert!(
+        timeout(Duration::from_secs(3), subscriber.recv())
+            .await
+            .is_err(),
+        "Will published although the client reconnected"
+    );
+
+    broker_handle.abort();
+}
+
 // ============================================================================
 // UNSUBSCRIBE Tests (MQTT-3.10, MQTT-3.11)
 // ============================================================================
@@ -701,6 +892,70 @@ async fn test_graceful_disconnect() {
     broker_handle.abort();
 }
 
+/// Wait for the next ClientDisconnected event and return its reason
+async fn next_disconnect(
+    events: &mut tokio::sync::broadcast::Receiver<BrokerEvent>,
+) -> (String, DisconnectReason) {
+    loop {
+        let event = timeout(Duration::from_secs(2), events.recv())
+            .await
+            .expect("Should receive an event")
+            .expect("Event channel should be open");
+        if let BrokerEvent::ClientDisconnected { client_id, reason } = event {
+            return (client_id.to_string(), reason);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_disconnect_reasons_in_events() {
+    let port = next_port();
+    let broker = Broker::new(test_config(port));
+    let mut events = broker.subscribe_events();
+
+    let broker_handle = tokio::spawn(async move {
+        let _ = broker.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    // DISCONNECT with Success is a normal disconnect
+    let mut client = TestClient::connect(addr, ProtocolVersion::V5).await;
+    client.mqtt_connect("reason-normal", true).await;
+    client
+        .send(&Packet::Disconnect(Disconnect {
+            reason_code: ReasonCode::Success,
+            properties: Properties::default(),
+        }))
+        .await;
+    let (client_id, reason) = next_disconnect(&mut events).await;
+    assert_eq!(client_id, "reason-normal");
+    assert_eq!(reason, DisconnectReason::Normal);
+    assert!(reason.is_graceful());
+
+    // Dropping the socket is a lost connection
+    let mut client = TestClient::connect(addr, ProtocolVersion::V5).await;
+    client.mqtt_connect("reason-lost", true).await;
+    drop(client);
+    let (client_id, reason) = next_disconnect(&mut events).await;
+    assert_eq!(client_id, "reason-lost");
+    assert_eq!(reason, DisconnectReason::ConnectionLost);
+    assert!(reason.publishes_will());
+
+    // A second connection with the same client ID takes the session over
+    let mut first = TestClient::connect(addr, ProtocolVersion::V5).await;
+    first.mqtt_connect("reason-takeover", true).await;
+    let mut second = TestClient::connect(addr, ProtocolVersion::V5).await;
+    second.mqtt_connect("reason-takeover", true).await;
+    let (client_id, reason) = next_disconnect(&mut events).await;
+    assert_eq!(client_id, "reason-takeover");
+    assert_eq!(reason, DisconnectReason::Takeover);
+    assert!(!reason.publishes_will());
+
+    broker_handle.abort();
+}
+
 // ============================================================================
 // Session State Tests
 // ============================================================================
@@ -746,6 +1001,61 @@ async fn test_session_persistence_v5() {
     broker_handle.abort();
 }
 
+/// With sync_mode = "always", a QoS 1 message is durable in the queue of an
+/// offline persistent subscriber by the time the publisher gets its PUBACK
+#[tokio::test]
+async fn test_sync_always_persists_queued_message_before_puback() {
+    let port = next_port();
+    let temp_dir = tempfile::tempdir().unwrap();
+    let backend = Arc::new(FjallBackend::open(temp_dir.path()).unwrap());
+    // Long interval so only the sync before PUBACK commits anything
+    let persistence = Arc::new(PersistenceManager::new(
+        backend.clone(),
+        Duration::from_secs(3600),
+        100,
+        SyncMode::Always,
+    ));
+    let mut broker = Broker::new(test_config(port));
+    broker.set_persistence(persistence);
+
+    let broker_handle = tokio::spawn(async move {
+        let _ = broker.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    {
+        let mut client = TestClient::connect(addr, ProtocolVersion::V311).await;
+        client.mqtt_connect("durable-sub", false).await;
+        client.subscribe(1, "durable/topic", QoS::AtLeastOnce).await;
+        client
+            .send(&Packet::Disconnect(Disconnect {
+                reason_code: ReasonCode::Success,
+                properties: Properties::default(),
+            }))
+            .await;
+    }
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut publisher = TestClient::connect(addr, ProtocolVersion::V311).await;
+    publisher.mqtt_connect("durable-pub", true).await;
+    publisher
+        .publish("durable/topic", b"kept", QoS::AtLeastOnce, false)
+        .await;
+    match publisher.recv().await {
+        Some(Packet::PubAck(_)) => {}
+        other => panic!("Expected PUBACK, got {:?}", other),
+    }
+
+    let stored = backend.get_session("durable-sub").await.unwrap().unwrap();
+    assert_eq!(stored.pending_messages.len(), 1);
+    assert_eq!(stored.pending_messages[0].publish.payload, b"kept");
+
+    broker_handle.abort();
+}
+
 // ============================================================================
 // Multiple Subscribers Test
 // ============================================================================
@@ -856,7 +1166,7 @@ async fn test_max_awaiting_rel_limit() {
             dup: false,
             qos: QoS::ExactlyOnce,
             retain: false,
-            topic: "test/qos2".to_string(),
+            topic: "test/qos2".into(),
             packet_id: Some(i),
             payload: Bytes::from(format!("msg{}", i)),
             properties: Properties::default(),
@@ -879,7 +1189,7 @@ async fn test_max_awaiting_rel_limit() {
         dup: false,
         qos: QoS::ExactlyOnce,
         retain: false,
-        topic: "test/qos2".to_string(),
+        topic: "test/qos2".into(),
         packet_id: Some(3),
         payload: Bytes::from("msg3"),
         properties: Properties::default(),
@@ -897,12 +1207,12 @@ async fn test_max_awaiting_rel_limit() {
     broker_handle.abort();
 }
 
-/// Test max_inflight config is applied to sessions
+/// Test limits.max_subscriptions_per_client
 #[tokio::test]
-async fn test_max_inflight_limit() {
+async fn test_max_subscriptions_per_client() {
     let port = next_port();
     let mut config = test_config(port);
-    config.max_inflight = 16;
+    config.max_subscriptions_per_client = 2;
 
     let addr = config.bind_addr;
     let broker = Broker::new(config);
@@ -911,52 +1221,84 @@ async fn test_max_inflight_limit() {
     });
     tokio::time::sleep(Duration::from_millis(100)).await;
 
-    // Subscriber with QoS 1
-    let mut subscriber = TestClient::connect(addr, ProtocolVersion::V5).await;
-    subscriber.mqtt_connect("sub-inflight", true).await;
-    subscriber
-        .subscribe(1, "test/inflight", QoS::AtLeastOnce)
-        .await;
+    let mut client = TestClient::connect(addr, ProtocolVersion::V5).await;
+    client.mqtt_connect("sub-limit", true).await;
 
-    // Publisher
-    let mut publisher = TestClient::connect(addr, ProtocolVersion::V5).await;
-    publisher.mqtt_connect("pub-inflight", true).await;
+    let ack = client.subscribe(1, "limit/a", QoS::AtLeastOnce).await;
+    assert_eq!(ack.reason_codes, vec![ReasonCode::GrantedQoS1]);
+    let ack = client.subscribe(2, "limit/b", QoS::AtLeastOnce).await;
+    assert_eq!(ack.reason_codes, vec![ReasonCode::GrantedQoS1]);
 
-    // Publish a QoS 1 message
-    let publish = Packet::Publish(Publish {
-        dup: false,
-        qos: QoS::AtLeastOnce,
-        retain: false,
-        topic: "test/inflight".to_string(),
-        packet_id: Some(1),
-        payload: Bytes::from("test message"),
-        properties: Properties::default(),
-    });
-    publisher.send(&publish).await;
-    let _ = publisher.recv().await; // PUBACK from broker
+    // A third filter is refused
+    let ack = client.subscribe(3, "limit/c", QoS::AtLeastOnce).await;
+    assert_eq!(ack.reason_codes, vec![ReasonCode::QuotaExceeded]);
 
+    // Replacing an existing subscription is still allowed
+    let ack = client.subscribe(4, "limit/a", QoS::AtMostOnce).await;
+    assert_eq!(ack.reason_codes, vec![ReasonCode::Success]);
+
+    broker_handle.abort();
+}
+
+/// Test that a mounted client's topics are placed below its mount point
+#[tokio::test]
+async fn test_mount_point() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config
+        .mount_points
+        .users
+        .insert("alice".to_string(), "tenant-x/".to_string());
+
+    let addr = config.bind_addr;
+    let broker = Broker::new(config);
+    let broker_handle = tokio::spawn(async move {
+        broker.run().await.unwrap();
+    });
     tokio::time::sleep(Duration::from_millis(100)).await;
 
-    // Subscriber should receive the message
-    let msg = subscriber.recv().await;
-    assert!(msg.is_some(), "Should receive message");
+    // Unmounted client sees the full topics
+    let mut observer = TestClient::connect(addr, ProtocolVersion::V5).await;
+    observer.mqtt_connect("mount-observer", true).await;
+    observer.subscribe(1, "tenant-x/#", QoS::AtMostOnce).await;
+
+    let mut alice = TestClient::connect(addr, ProtocolVersion::V5).await;
+    alice
+        .send(&Packet::Connect(Box::new(Connect {
+            protocol_version: ProtocolVersion::V5,
+            client_id: "mount-alice".to_string(),
+            clean_start: true,
+            keep_alive: 60,
+            username: Some("alice".to_string()),
+            password: None,
+            will: None,
+            properties: Properties::default(),
+        })))
+        .await;
+    assert!(matches!(alice.recv().await, Some(Packet::ConnAck(_))));
+    alice.subscribe(1, "sensors/#", QoS::AtMostOnce).await;
 
-    if let Some(Packet::Publish(p)) = msg {
-        assert_eq!(p.payload.as_ref(), b"test message");
-        // ACK it
-        let puback = Packet::PubAck(vibemq::protocol::PubAck::new(p.packet_id.unwrap()));
-        subscriber.send(&puback).await;
+    alice
+        .publish("sensors/1", b"21.5", QoS::AtMostOnce, false)
+        .await;
+
+    match alice.recv().await {
+        Some(Packet::Publish(publish)) => assert_eq!(publish.topic, "sensors/1"),
+        other => panic!("Expected PUBLISH, got {:?}", other),
+    }
+    match observer.recv().await {
+        Some(Packet::Publish(publish)) => assert_eq!(publish.topic, "tenant-x/sensors/1"),
+        other => panic!("Expected PUBLISH, got {:?}", other),
     }
 
     broker_handle.abort();
 }
 
-/// Test that session takeover doesn't count against max_connections
+/// Test that clients cannot publish to $-prefixed topics
 #[tokio::test]
-async fn test_max_connections_allows_takeover() {
+async fn test_reserved_topic_publish_denied() {
     let port = next_port();
-    let mut config = test_config(port);
-    config.max_connections = 1; // Only 1 connection allowed
+    let config = test_config(port);
 
     let addr = config.bind_addr;
     let broker = Broker::new(config);
@@ -965,28 +1307,1051 @@ async fn test_max_connections_allows_takeover() {
     });
     tokio::time::sleep(Duration::from_millis(100)).await;
 
-    // Connect first client
-    let mut client1 = TestClient::connect(addr, ProtocolVersion::V5).await;
-    let connack1 = client1.mqtt_connect("same-client", true).await;
-    assert_eq!(connack1.reason_code, ReasonCode::Success);
+    let mut client = TestClient::connect(addr, ProtocolVersion::V5).await;
+    client.mqtt_connect("reserved-publisher", true).await;
 
-    // Connect second client with SAME client_id - should succeed (takeover)
-    let mut client2 = TestClient::connect(addr, ProtocolVersion::V5).await;
-    let connack2 = client2.mqtt_connect("same-client", true).await;
-    assert_eq!(
-        connack2.reason_code,
-        ReasonCode::Success,
-        "Session takeover should succeed even at max_connections"
-    );
+    client
+        .publish("$SYS/broker/uptime", b"0", QoS::AtLeastOnce, false)
+        .await;
+    match client.recv().await {
+        Some(Packet::PubAck(ack)) => assert_eq!(ack.reason_code, ReasonCode::NotAuthorized),
+        other => panic!("Expected PUBACK, got {:?}", other),
+    }
 
-    // Connect third client with DIFFERENT client_id - should fail
-    let mut client3 = TestClient::connect(addr, ProtocolVersion::V5).await;
-    let connack3 = client3.mqtt_connect("different-client", true).await;
-    assert_eq!(
-        connack3.reason_code,
-        ReasonCode::ServerUnavailable,
-        "New client_id should be rejected at max_connections"
-    );
+    // Regular topics are unaffected
+    client
+        .publish("sensors/$x", b"0", QoS::AtLeastOnce, false)
+        .await;
+    match client.recv().await {
+        Some(Packet::PubAck(ack)) => assert_eq!(ack.reason_code, ReasonCode::Success),
+        other => panic!("Expected PUBACK, got {:?}", other),
+    }
+
+    broker_handle.abort();
+}
+
+/// Rewrites `<mount>/to/<name>` to the topic given for `name`, and denies
+/// publishes to `tenant-x/denied`
+struct RedirectHooks;
+
+#[async_trait::async_trait]
+impl Hooks for RedirectHooks {
+    async fn on_publish_check(
+        &self,
+        _client: &ClientContext,
+        topic: &str,
+        _qos: QoS,
+        _retain: bool,
+    ) -> HookResult<HookDecision> {
+        Ok(match topic {
+            "tenant-x/denied" => HookDecision::Deny,
+            _ => HookDecision::Continue,
+        })
+    }
+
+    async fn on_publish_transform(
+        &self,
+        _client: &ClientContext,
+        publish: &Publish,
+    ) -> HookResult<PublishTransform> {
+        let topic = match publish.topic.rsplit('/').next() {
+            Some("escape") => "tenant-y/data",
+            Some("sys") => "tenant-x/$SYS/broker/uptime",
+            Some("denied") => "tenant-x/denied",
+            Some("ok") => "tenant-x/ok",
+            _ => return Ok(PublishTransform::Unchanged),
+        };
+        Ok(PublishTransform::Modified(Box::new(Publish {
+            topic: topic.into(),
+            ..publish.clone()
+        })))
+    }
+}
+
+/// Test that a topic rewritten by a transform hook stays below the mount
+/// point, out of reserved namespaces and subject to the ACL
+#[tokio::test]
+async fn test_transformed_topic_rechecked() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config
+        .mount_points
+        .users
+        .insert("alice".to_string(), "tenant-x/".to_string());
+
+    let addr = config.bind_addr;
+    let broker = Broker::with_hooks(config, Arc::new(RedirectHooks));
+    let broker_handle = tokio::spawn(async move {
+        broker.run().await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut alice = TestClient::connect(addr, ProtocolVersion::V5).await;
+    alice
+        .send(&Packet::Connect(Box::new(Connect {
+            protocol_version: ProtocolVersion::V5,
+            client_id: "transform-alice".to_string(),
+            clean_start: true,
+            keep_alive: 60,
+            username: Some("alice".to_string()),
+            password: None,
+            will: None,
+            properties: Properties::default(),
+        })))
+        .await;
+    assert!(matches!(alice.recv().await, Some(Packet::ConnAck(_))));
+
+    let cases = [
+        ("to/escape", ReasonCode::NotAuthorized),
+        ("to/sys", ReasonCode::NotAuthorized),
+        ("to/denied", ReasonCode::NotAuthorized),
+        ("to/ok", ReasonCode::Success),
+    ];
+    for (topic, expected) in cases {
+        alice.publish(topic, b"0", QoS::AtLeastOnce, false).await;
+        match alice.recv().await {
+            Some(Packet::PubAck(ack)) => assert_eq!(ack.reason_code, expected, "{}", topic),
+            other => panic!("Expected PUBACK, got {:?}", other),
+        }
+    }
 
     broker_handle.abort();
 }
+
+/// Test that $SYS subscriptions are refused unless the ACL grants them
+#[tokio::test]
+async fn test_sys_subscribe_denied_without_acl() {
+    let port = next_port();
+    let config = test_config(port);
+
+    let addr = config.bind_addr;
+    let broker = Broker::new(config);
+    let broker_handle = tokio::spawn(async move {
+        broker.run().await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = TestClient::connect(addr, ProtocolVersion::V5).await;
+    client.mqtt_connect("sys-subscriber", true).await;
+
+    let cases = [
+        ("$SYS/#", ReasonCode::NotAuthorized),
+        ("$share/g/$SYS/broker/+", ReasonCode::NotAuthorized),
+        ("sensors/#", ReasonCode::Success),
+    ];
+    for (packet_id, (filter, expected)) in (1..).zip(cases) {
+        let suback = client.subscribe(packet_id, filter, QoS::AtMostOnce).await;
+        assert_eq!(suback.reason_codes, vec![expected], "{}", filter);
+    }
+
+    broker_handle.abort();
+}
+
+/// Test that $queue subscribers share the messages of their topic
+#[tokio::test]
+async fn test_queue_subscriptions() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.queue_subscriptions = true;
+
+    let addr = config.bind_addr;
+    let broker = Broker::new(config);
+    let broker_handle = tokio::spawn(async move {
+        broker.run().await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut worker1 = TestClient::connect(addr, ProtocolVersion::V5).await;
+    worker1.mqtt_connect("queue-worker1", true).await;
+    let ack = worker1.subscribe(1, "$queue/jobs", QoS::AtMostOnce).await;
+    assert_eq!(ack.reason_codes, vec![ReasonCode::Success]);
+
+    let mut worker2 = TestClient::connect(addr, ProtocolVersion::V5).await;
+    worker2.mqtt_connect("queue-worker2", true).await;
+    worker2.subscribe(1, "$queue/jobs", QoS::AtMostOnce).await;
+
+    let mut publisher = TestClient::connect(addr, ProtocolVersion::V5).await;
+    publisher.mqtt_connect("queue-publisher", true).await;
+    for payload in [b"job1", b"job2"] {
+        publisher
+            .publish("jobs", payload, QoS::AtMostOnce, false)
+            .await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    // Each worker gets one of the two jobs
+    let mut payloads = Vec::new();
+    for worker in [&mut worker1, &mut worker2] {
+        match worker.recv().await {
+            Some(Packet::Publish(publish)) => {
+                assert_eq!(publish.topic, "jobs");
+                payloads.push(publish.payload);
+            }
+            other => panic!("Expected PUBLISH, got {:?}", other),
+        }
+    }
+    payloads.sort();
+    assert_eq!(payloads, [&b"job1"[..], &b"job2"[..]]);
+
+    broker_handle.abort();
+}
+
+/// Test that $queue/ is a plain topic filter with queue subscriptions off
+#[tokio::test]
+async fn test_queue_subscriptions_disabled() {
+    let port = next_port();
+    let config = test_config(port);
+
+    let addr = config.bind_addr;
+    let broker = Arc::new(Broker::new(config));
+    let runner = broker.clone();
+    let broker_handle = tokio::spawn(async move {
+        runner.run().await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut subscribers = Vec::new();
+    for client_id in ["plain-queue1", "plain-queue2"] {
+        let mut client = TestClient::connect(addr, ProtocolVersion::V5).await;
+        client.mqtt_connect(client_id, true).await;
+        let ack = client.subscribe(1, "$queue/jobs", QoS::AtMostOnce).await;
+        assert_eq!(ack.reason_codes, vec![ReasonCode::Success]);
+        subscribers.push(client);
+    }
+
+    let mut publisher = TestClient::connect(addr, ProtocolVersion::V5).await;
+    publisher.mqtt_connect("plain-queue-publisher", true).await;
+    publisher
+        .publish("jobs", b"job", QoS::AtMostOnce, false)
+        .await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    // Clients cannot publish to $ topics, but the server can
+    broker.publish(
+        "$queue/jobs".to_string(),
+        Bytes::from_static(b"queued"),
+        QoS::AtMostOnce,
+        false,
+    );
+
+    // Both subscribers get the message on $queue/jobs, and nothing on jobs
+    for subscriber in &mut subscribers {
+        match subscriber.recv().await {
+            Some(Packet::Publish(publish)) => {
+                assert_eq!(publish.topic, "$queue/jobs");
+                assert_eq!(&publish.payload[..], b"queued");
+            }
+            other => panic!("Expected PUBLISH, got {:?}", other),
+        }
+    }
+
+    broker_handle.abort();
+}
+
+/// Test that fanned out messages reach subscribers of both protocol
+/// versions at each QoS
+#[tokio::test]
+async fn test_fanout() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.fanout_threshold = 1;
+
+    let addr = config.bind_addr;
+    let broker = Broker::new(config);
+    let broker_handle = tokio::spawn(async move {
+        broker.run().await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut subscribers = Vec::new();
+    for (i, (version, qos)) in [
+        (ProtocolVersion::V311, QoS::AtMostOnce),
+        (ProtocolVersion::V311, QoS::AtLeastOnce),
+        (ProtocolVersion::V5, QoS::AtMostOnce),
+        (ProtocolVersion::V5, QoS::AtLeastOnce),
+        (ProtocolVersion::V5, QoS::AtLeastOnce),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let mut subscriber = TestClient::connect(addr, version).await;
+        subscriber
+            .mqtt_connect(&format!("fanout-sub{}", i), true)
+            .await;
+        subscriber.subscribe(1, "fanout/+", qos).await;
+        subscribers.push((subscriber, qos));
+    }
+
+    let mut publisher = TestClient::connect(addr, ProtocolVersion::V5).await;
+    publisher.mqtt_connect("fanout-publisher", true).await;
+    publisher
+        .publish("fanout/1", b"hello", QoS::AtLeastOnce, false)
+        .await;
+
+    for (subscriber, qos) in &mut subscribers {
+        match subscriber.recv().await {
+            Some(Packet::Publish(publish)) => {
+                assert_eq!(publish.topic, "fanout/1");
+                assert_eq!(&publish.payload[..], b"hello");
+                assert_eq!(publish.qos, *qos);
+                assert_eq!(publish.packet_id.is_some(), *qos == QoS::AtLeastOnce);
+            }
+            other => panic!("Expected PUBLISH, got {:?}", other),
+        }
+    }
+
+    broker_handle.abort();
+}
+
+/// Test that packets coalesced into vectored writes arrive whole and in order
+#[tokio::test]
+async fn test_write_coalescing() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.fanout_threshold = 1;
+    config.write_coalesce_bytes = 256;
+    config.write_coalesce_delay = Duration::from_millis(20);
+
+    let addr = config.bind_addr;
+    let broker = Broker::new(config);
+    let broker_handle = tokio::spawn(async move {
+        broker.run().await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut subscriber = TestClient::connect(addr, ProtocolVersion::V5).await;
+    subscriber.mqtt_connect("coalesce-sub", true).await;
+    subscriber.subscribe(1, "coalesce/+", QoS::AtMostOnce).await;
+
+    let mut publisher = TestClient::connect(addr, ProtocolVersion::V5).await;
+    publisher.mqtt_connect("coalesce-publisher", true).await;
+    for i in 0..50 {
+        publisher
+            .publish(
+                &format!("coalesce/{}", i % 2),
+                format!("message {}", i).as_bytes(),
+                QoS::AtMostOnce,
+                i % 5 == 0,
+            )
+            .await;
+    }
+
+    let mut payloads = Vec::new();
+    let mut buf = BytesMut::new();
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    subscriber.decoder.set_protocol_version(ProtocolVersion::V5);
+    while payloads.len() < 50 {
+        let read = tokio::time::timeout_at(deadline, subscriber.stream.read_buf(&mut buf))
+            .await
+            .expect("timed out waiting for messages")
+            .unwrap();
+        assert!(read > 0, "connection closed");
+        while let Some((packet, consumed)) = subscriber.decoder.decode(&buf).unwrap() {
+            buf.advance(consumed);
+            match packet {
+                Packet::Publish(publish) => payloads.push(publish.payload),
+                other => panic!("Expected PUBLISH, got {:?}", other),
+            }
+        }
+    }
+    for (i, payload) in payloads.iter().enumerate() {
+        assert_eq!(&payload[..], format!("message {}", i).as_bytes());
+    }
+
+    broker_handle.abort();
+}
+
+/// Test retained presence messages on connect and disconnect
+#[tokio::test]
+async fn test_presence_topics() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.presence_topics = true;
+
+    let addr = config.bind_addr;
+    let broker = Broker::new(config);
+    let broker_handle = tokio::spawn(async move {
+        broker.run().await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut device = TestClient::connect(addr, ProtocolVersion::V5).await;
+    device.mqtt_connect("presence-device", true).await;
+
+    // The retained connected message is there for late subscribers
+    let mut watcher = TestClient::connect(addr, ProtocolVersion::V5).await;
+    watcher.mqtt_connect("presence-watcher", true).await;
+    watcher
+        .subscribe(1, "$events/clients/presence-device/+", QoS::AtMostOnce)
+        .await;
+    match watcher.recv().await {
+        Some(Packet::Publish(publish)) => {
+            assert_eq!(publish.topic, "$events/clients/presence-device/connected");
+            assert!(publish.retain);
+            let json: serde_json::Value = serde_json::from_slice(&publish.payload).unwrap();
+            assert_eq!(json["ip"], "127.0.0.1");
+            assert_eq!(json["protocol_version"], 5);
+        }
+        other => panic!("Expected PUBLISH, got {:?}", other),
+    }
+
+    device
+        .send(&Packet::Disconnect(Disconnect {
+            reason_code: ReasonCode::Success,
+            properties: Properties::default(),
+        }))
+        .await;
+    match watcher.recv().await {
+        Some(Packet::Publish(publish)) => {
+            assert_eq!(
+                publish.topic,
+                "$events/clients/presence-device/disconnected"
+            );
+            let json: serde_json::Value = serde_json::from_slice(&publish.payload).unwrap();
+            assert_eq!(json["reason"], "normal");
+        }
+        other => panic!("Expected PUBLISH, got {:?}", other),
+    }
+
+    // A new subscriber sees only the latest state
+    let mut late = TestClient::connect(addr, ProtocolVersion::V5).await;
+    late.mqtt_connect("presence-late", true).await;
+    late.subscribe(1, "$events/clients/presence-device/+", QoS::AtMostOnce)
+        .await;
+    match late.recv().await {
+        Some(Packet::Publish(publish)) => {
+            assert_eq!(
+                publish.topic,
+                "$events/clients/presence-device/disconnected"
+            );
+        }
+        other => panic!("Expected PUBLISH, got {:?}", other),
+    }
+    assert!(
+        timeout(Duration::from_millis(200), late.recv())
+            .await
+            .is_err(),
+        "only the latest state is retained"
+    );
+
+    broker_handle.abort();
+}
+
+/// Test the disconnect queue overflow policy
+#[tokio::test]
+async fn test_queue_overflow_disconnect() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.max_inflight = 1;
+    config.max_queued_messages = 2;
+    config.queue_overflow = QueueOverflowPolicy::Disconnect;
+
+    let addr = config.bind_addr;
+    let broker = Broker::new(config);
+    let broker_handle = tokio::spawn(async move {
+        broker.run().await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // A subscriber that never acknowledges holds one message inflight
+    let mut subscriber = TestClient::connect(addr, ProtocolVersion::V5).await;
+    subscriber.mqtt_connect("slow-consumer", true).await;
+    subscriber
+        .subscribe(1, "test/overflow", QoS::AtLeastOnce)
+        .await;
+
+    let mut publisher = TestClient::connect(addr, ProtocolVersion::V5).await;
+    publisher.mqtt_connect("overflow-publisher", true).await;
+    for i in 0..4 {
+        publisher
+            .publish(
+                "test/overflow",
+                format!("msg{}", i).as_bytes(),
+                QoS::AtLeastOnce,
+                false,
+            )
+            .await;
+        let _ = publisher.recv().await; // PUBACK
+    }
+
+    // One inflight and two queued; the fourth overflows the queue
+    let mut buf = BytesMut::new();
+    let mut reason_code = None;
+    while reason_code.is_none() {
+        let mut chunk = vec![0u8; 4096];
+        match timeout(Duration::from_secs(2), subscriber.stream.read(&mut chunk)).await {
+            Ok(Ok(n)) if n > 0 => buf.extend_from_slice(&chunk[..n]),
+            _ => break,
+        }
+        subscriber.decoder.set_protocol_version(ProtocolVersion::V5);
+        while let Ok(Some((packet, consumed))) = subscriber.decoder.decode(&buf) {
+            let _ = buf.split_to(consumed);
+            if let Packet::Disconnect(disconnect) = packet {
+                reason_code = Some(disconnect.reason_code);
+            }
+        }
+    }
+    assert_eq!(reason_code, Some(ReasonCode::QuotaExceeded));
+
+    broker_handle.abort();
+}
+
+/// Test that the disconnect overflow policy applies to messages the
+/// broker routes itself, as it does to those of cluster peers and bridges
+#[tokio::test]
+async fn test_queue_overflow_disconnect_routed() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.max_inflight = 1;
+    config.max_queued_messages = 2;
+    config.queue_overflow = QueueOverflowPolicy::Disconnect;
+
+    let addr = config.bind_addr;
+    let broker = Arc::new(Broker::new(config));
+    let runner = broker.clone();
+    let broker_handle = tokio::spawn(async move {
+        runner.run().await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut subscriber = TestClient::connect(addr, ProtocolVersion::V5).await;
+    subscriber.mqtt_connect("routed-consumer", true).await;
+    subscriber
+        .subscribe(1, "test/routed", QoS::AtLeastOnce)
+        .await;
+
+    for i in 0..4 {
+        broker.publish(
+            "test/routed".to_string(),
+            Bytes::from(format!("msg{}", i)),
+            QoS::AtLeastOnce,
+            false,
+        );
+    }
+
+    let mut buf = BytesMut::new();
+    let mut reason_code = None;
+    while reason_code.is_none() {
+        let mut chunk = vec![0u8; 4096];
+        match timeout(Duration::from_secs(2), subscriber.stream.read(&mut chunk)).await {
+            Ok(Ok(n)) if n > 0 => buf.extend_from_slice(&chunk[..n]),
+            _ => break,
+        }
+        subscriber.decoder.set_protocol_version(ProtocolVersion::V5);
+        while let Ok(Some((packet, consumed))) = subscriber.decoder.decode(&buf) {
+            let _ = buf.split_to(consumed);
+            if let Packet::Disconnect(disconnect) = packet {
+                reason_code = Some(disconnect.reason_code);
+            }
+        }
+    }
+    assert_eq!(reason_code, Some(ReasonCode::QuotaExceeded));
+
+    broker_handle.abort();
+}
+
+/// Records the messages dropped for subscribers
+#[derive(Default)]
+struct DropRecorder {
+    dropped: std::sync::Mutex<Vec<(String, String, DropReason)>>,
+}
+
+#[async_trait::async_trait]
+impl Hooks for DropRecorder {
+    async fn on_message_dropped(&self, client_id: &str, topic: &str, reason: DropReason) {
+        self.dropped
+            .lock()
+            .unwrap()
+            .push((client_id.to_string(), topic.to_string(), reason));
+    }
+}
+
+/// Test that messages lost to queue overflow reach the dropped hook with
+/// their topic
+#[tokio::test]
+async fn test_queue_overflow_reports_dropped_topic() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.max_inflight = 1;
+    config.max_queued_messages = 1;
+    config.queue_overflow = QueueOverflowPolicy::DropOldest;
+
+    let addr = config.bind_addr;
+    let hooks = Arc::new(DropRecorder::default());
+    let broker = Broker::with_hooks(config, hooks.clone());
+    let broker_handle = tokio::spawn(async move {
+        broker.run().await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // A subscriber that never acknowledges holds one message inflight
+    let mut subscriber = TestClient::connect(addr, ProtocolVersion::V5).await;
+    subscriber.mqtt_connect("drop-consumer", true).await;
+    subscriber
+        .subscribe(1, "test/drop/+", QoS::AtLeastOnce)
+        .await;
+
+    let mut publisher = TestClient::connect(addr, ProtocolVersion::V5).await;
+    publisher.mqtt_connect("drop-publisher", true).await;
+    for topic in ["test/drop/a", "test/drop/b", "test/drop/c"] {
+        publisher
+            .publish(topic, b"payload", QoS::AtLeastOnce, false)
+            .await;
+        let _ = publisher.recv().await; // PUBACK
+    }
+
+    // One inflight and one queued; the third replaces the queued one
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(
+        *hooks.dropped.lock().unwrap(),
+        vec![(
+            "drop-consumer".to_string(),
+            "test/drop/b".to_string(),
+            DropReason::QueueFull
+        )]
+    );
+
+    broker_handle.abort();
+}
+
+/// Test that messages the broker publishes itself report queue overflow of
+/// offline sessions to the dropped hook
+#[tokio::test]
+async fn test_offline_queue_overflow_reports_dropped() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.max_queued_messages = 1;
+    config.queue_overflow = QueueOverflowPolicy::DropOldest;
+
+    let addr = config.bind_addr;
+    let hooks = Arc::new(DropRecorder::default());
+    let broker = Arc::new(Broker::with_hooks(config, hooks.clone()));
+    let runner = broker.clone();
+    let broker_handle = tokio::spawn(async move {
+        runner.run().await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut subscriber = TestClient::connect(addr, ProtocolVersion::V311).await;
+    subscriber.mqtt_connect("offline-consumer", false).await;
+    subscriber
+        .subscribe(1, "test/offline/+", QoS::AtLeastOnce)
+        .await;
+    subscriber
+        .send(&Packet::Disconnect(Disconnect::default()))
+        .await;
+    drop(subscriber);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for topic in ["test/offline/a", "test/offline/b"] {
+        broker.publish(
+            topic.to_string(),
+            Bytes::from_static(b"x"),
+            QoS::AtLeastOnce,
+            false,
+        );
+    }
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(
+        *hooks.dropped.lock().unwrap(),
+        vec![(
+            "offline-consumer".to_string(),
+            "test/offline/a".to_string(),
+            DropReason::QueueFull
+        )]
+    );
+
+    broker_handle.abort();
+}
+
+/// Test that parked clients, which neither subscribe nor publish, are
+/// disconnected after the parked timeout
+#[tokio::test]
+async fn test_parked_timeout() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.parked_timeout = Duration::from_millis(500);
+
+    let addr = config.bind_addr;
+    let broker = Broker::new(config);
+    let broker_handle = tokio::spawn(async move {
+        broker.run().await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut parked = TestClient::connect(addr, ProtocolVersion::V5).await;
+    parked.mqtt_connect("parked-client", true).await;
+    let mut active = TestClient::connect(addr, ProtocolVersion::V5).await;
+    active.mqtt_connect("active-client", true).await;
+    active.subscribe(1, "test/parked", QoS::AtMostOnce).await;
+
+    match parked.recv().await {
+        Some(Packet::Disconnect(disconnect)) => {
+            assert_eq!(disconnect.reason_code, ReasonCode::AdministrativeAction);
+        }
+        other => panic!("Expected DISCONNECT, got {:?}", other),
+    }
+
+    // The subscribed client stays connected
+    active.send(&Packet::PingReq).await;
+    assert!(matches!(active.recv().await, Some(Packet::PingResp)));
+
+    broker_handle.abort();
+}
+
+/// Test that an unacked message is retransmitted until max_retries, then
+/// the client is disconnected under the disconnect policy
+#[tokio::test]
+async fn test_retries_exhausted_disconnect() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.retry_interval = Duration::from_millis(300);
+    config.max_retries = 1;
+    config.retry_exhausted = RetryExhaustedPolicy::Disconnect;
+
+    let addr = config.bind_addr;
+    let broker = Broker::new(config);
+    let broker_handle = tokio::spawn(async move {
+        broker.run().await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut subscriber = TestClient::connect(addr, ProtocolVersion::V5).await;
+    subscriber.mqtt_connect("sub-retries", true).await;
+    subscriber
+        .subscribe(1, "test/retries", QoS::AtLeastOnce)
+        .await;
+
+    let mut publisher = TestClient::connect(addr, ProtocolVersion::V5).await;
+    publisher.mqtt_connect("pub-retries", true).await;
+    publisher
+        .publish("test/retries", b"unacked", QoS::AtLeastOnce, false)
+        .await;
+
+    // Delivered, then retransmitted once, never acknowledged
+    match subscriber.recv().await {
+        Some(Packet::Publish(p)) => assert!(!p.dup),
+        other => panic!("Expected PUBLISH, got {:?}", other),
+    }
+    match subscriber.recv().await {
+        Some(Packet::Publish(p)) => assert!(p.dup),
+        other => panic!("Expected retransmitted PUBLISH, got {:?}", other),
+    }
+    match subscriber.recv().await {
+        Some(Packet::Disconnect(disconnect)) => {
+            assert_eq!(disconnect.reason_code, ReasonCode::UnspecifiedError);
+        }
+        other => panic!("Expected DISCONNECT, got {:?}", other),
+    }
+
+    broker_handle.abort();
+}
+
+/// Test max_inflight config is applied to sessions
+#[tokio::test]
+async fn test_max_inflight_limit() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.max_inflight = 16;
+
+    let addr = config.bind_addr;
+    let broker = Broker::new(config);
+    let broker_handle = tokio::spawn(async move {
+        broker.run().await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Subscriber with QoS 1
+    let mut subscriber = TestClient::connect(addr, ProtocolVersion::V5).await;
+    subscriber.mqtt_connect("sub-inflight", true).await;
+    subscriber
+        .subscribe(1, "test/inflight", QoS::AtLeastOnce)
+        .await;
+
+    // Publisher
+    let mut publisher = TestClient::connect(addr, ProtocolVersion::V5).await;
+    publisher.mqtt_connect("pub-inflight", true).await;
+
+    // Publish a QoS 1 message
+    let publish = Packet::Publish(Publish {
+        dup: false,
+        qos: QoS::AtLeastOnce,
+        retain: false,
+        topic: "test/inflight".into(),
+        packet_id: Some(1),
+        payload: Bytes::from("test message"),
+        properties: Properties::default(),
+    });
+    publisher.send(&publish).await;
+    let _ = publisher.recv().await; // PUBACK from broker
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Subscriber should receive the message
+    let msg = subscriber.recv().await;
+    assert!(msg.is_some(), "Should receive message");
+
+    if let Some(Packet::Publish(p)) = msg {
+        assert_eq!(p.payload.as_ref(), b"test message");
+        // ACK it
+        let puback = Packet::PubAck(vibemq::protocol::PubAck::new(p.packet_id.unwrap()));
+        subscriber.send(&puback).await;
+    }
+
+    broker_handle.abort();
+}
+
+/// Test that session takeover doesn't count against max_connections
+#[tokio::test]
+async fn test_max_connections_allows_takeover() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.max_connections = 1; // Only 1 connection allowed
+
+    let addr = config.bind_addr;
+    let broker = Broker::new(config);
+    let broker_handle = tokio::spawn(async move {
+        broker.run().await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Connect first client
+    let mut client1 = TestClient::connect(addr, ProtocolVersion::V5).await;
+    let connack1 = client1.mqtt_connect("same-client", true).await;
+    assert_eq!(connack1.reason_code, ReasonCode::Success);
+
+    // Connect second client with SAME client_id - should succeed (takeover)
+    let mut client2 = TestClient::connect(addr, ProtocolVersion::V5).await;
+    let connack2 = client2.mqtt_connect("same-client", true).await;
+    assert_eq!(
+        connack2.reason_code,
+        ReasonCode::Success,
+        "Session takeover should succeed even at max_connections"
+    );
+
+    // Connect third client with DIFFERENT client_id - should fail
+    let mut client3 = TestClient::connect(addr, ProtocolVersion::V5).await;
+    let connack3 = client3.mqtt_connect("different-client", true).await;
+    assert_eq!(
+        connack3.reason_code,
+        ReasonCode::ServerUnavailable,
+        "New client_id should be rejected at max_connections"
+    );
+
+    broker_handle.abort();
+}
+
+// ============================================================================
+// CLUSTER Tests
+// ============================================================================
+
+/// Start a broker clustered with the nodes at `seeds`, returning its MQTT address
+async fn start_cluster_node(
+    node_id: &str,
+    gossip_port: u16,
+    seeds: Vec<String>,
+) -> (
+    SocketAddr,
+    std::sync::Arc<vibemq::cluster::ClusterManager>,
+    tokio::sync::broadcast::Receiver<BrokerEvent>,
+    tokio::task::JoinHandle<()>,
+) {
+    let port = next_port();
+    let mut broker = Broker::new(test_config(port));
+    let cluster = vibemq::config::ClusterConfig {
+        enabled: true,
+        node_id: Some(node_id.to_string()),
+        gossip_addr: SocketAddr::from(([127, 0, 0, 1], gossip_port)),
+        peer_addr: SocketAddr::from(([127, 0, 0, 1], next_port())),
+        seeds,
+        gossip_interval: Duration::from_millis(100),
+        ..Default::default()
+    };
+    let manager = broker.create_cluster_manager(cluster).await.unwrap();
+    broker.set_cluster_manager(manager);
+    let manager = broker.cluster_manager().unwrap();
+    let events = broker.subscribe_events();
+
+    let handle = tokio::spawn(async move {
+        let _ = broker.run().await;
+    });
+    (
+        SocketAddr::from(([127, 0, 0, 1], port)),
+        manager,
+        events,
+        handle,
+    )
+}
+
+/// A client reconnecting to another node takes its session over from the
+/// node it was connected to
+#[tokio::test]
+async fn test_cluster_session_takeover() {
+    let gossip_a = next_port();
+    let gossip_b = next_port();
+    let (addr_a, manager_a, mut events_a, handle_a) =
+        start_cluster_node("node-a", gossip_a, vec![format!("127.0.0.1:{}", gossip_b)]).await;
+    let (addr_b, manager_b, _, handle_b) =
+        start_cluster_node("node-b", gossip_b, vec![format!("127.0.0.1:{}", gossip_a)]).await;
+
+    timeout(Duration::from_secs(10), async {
+        while manager_a.connected_peer_count() == 0 || manager_b.connected_peer_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("Nodes should connect to each other");
+
+    // A persistent session with a subscription on node A
+    let mut client_a = TestClient::connect(addr_a, ProtocolVersion::V311).await;
+    client_a.mqtt_connect("roaming", false).await;
+    client_a
+        .subscribe(1, "cluster/takeover", QoS::AtLeastOnce)
+        .await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    // Reconnecting to node B brings the session along
+    let mut client_b = TestClient::connect(addr_b, ProtocolVersion::V311).await;
+    let connack = client_b.mqtt_connect("roaming", false).await;
+    assert_eq!(connack.reason_code, ReasonCode::Success);
+    assert!(connack.session_present);
+
+    // Node A closes the old MQTT v3.1.1 connection without a DISCONNECT and
+    // announces the removed subscription
+    let mut buf = [0u8; 16];
+    let closed = timeout(Duration::from_secs(2), client_a.stream.read(&mut buf))
+        .await
+        .expect("Node A should close the old connection");
+    assert!(
+        matches!(closed, Ok(0) | Err(_)),
+        "Expected close, got {:?}",
+        closed
+    );
+    let removed = timeout(Duration::from_secs(2), async {
+        loop {
+            if let Ok(BrokerEvent::SubscriptionRemoved { filter, client_id }) =
+                events_a.recv().await
+            {
+                return (filter, client_id);
+            }
+        }
+    })
+    .await
+    .expect("Node A should announce the removed subscription");
+    assert_eq!(removed.0, "cluster/takeover");
+    assert_eq!(&*removed.1, "roaming");
+
+    // The subscription now lives on node B
+    let mut publisher = TestClient::connect(addr_b, ProtocolVersion::V311).await;
+    publisher.mqtt_connect("roaming-publisher", true).await;
+    publisher
+        .publish("cluster/takeover", b"moved", QoS::AtMostOnce, false)
+        .await;
+    match client_b.recv().await {
+        Some(Packet::Publish(publish)) => assert_eq!(&publish.payload[..], b"moved"),
+        other => panic!("Expected PUBLISH, got {:?}", other),
+    }
+
+    handle_a.abort();
+    handle_b.abort();
+}
+
+/// A node joining the cluster receives the retained messages published
+/// before it joined
+#[tokio::test]
+async fn test_cluster_retained_sync() {
+    let gossip_a = next_port();
+    let gossip_b = next_port();
+    let (addr_a, manager_a, _, handle_a) =
+        start_cluster_node("node-a", gossip_a, vec![format!("127.0.0.1:{}", gossip_b)]).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut publisher = TestClient::connect(addr_a, ProtocolVersion::V311).await;
+    publisher.mqtt_connect("retained-publisher", true).await;
+    publisher
+        .publish("cluster/retained", b"before", QoS::AtMostOnce, true)
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (addr_b, manager_b, _, handle_b) =
+        start_cluster_node("node-b", gossip_b, vec![format!("127.0.0.1:{}", gossip_a)]).await;
+    timeout(Duration::from_secs(10), async {
+        while manager_a.connected_peer_count() == 0 || manager_b.connected_peer_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("Nodes should connect to each other");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let mut subscriber = TestClient::connect(addr_b, ProtocolVersion::V311).await;
+    subscriber.mqtt_connect("retained-subscriber", true).await;
+    subscriber.subscribe(1, "cluster/+", QoS::AtMostOnce).await;
+    match subscriber.recv().await {
+        Some(Packet::Publish(publish)) => {
+            assert!(publish.retain);
+            assert_eq!(publish.topic, "cluster/retained");
+            assert_eq!(&publish.payload[..], b"before");
+        }
+        other => panic!("Expected retained PUBLISH, got {:?}", other),
+    }
+
+    handle_a.abort();
+    handle_b.abort();
+}
+
+/// A will published on one node reaches exactly one member of a shared
+/// group with members on several nodes
+#[tokio::test]
+async fn test_cluster_shared_will_delivered_once() {
+    let gossip_a = next_port();
+    let gossip_b = next_port();
+    let (addr_a, manager_a, _, handle_a) =
+        start_cluster_node("node-a", gossip_a, vec![format!("127.0.0.1:{}", gossip_b)]).await;
+    let (addr_b, manager_b, _, handle_b) =
+        start_cluster_node("node-b", gossip_b, vec![format!("127.0.0.1:{}", gossip_a)]).await;
+
+    timeout(Duration::from_secs(10), async {
+        while manager_a.connected_peer_count() == 0 || manager_b.connected_peer_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("Nodes should connect to each other");
+
+    // One group member on each node
+    let mut members = Vec::new();
+    for (i, addr) in [addr_a, addr_b].into_iter().enumerate() {
+        let mut member = TestClient::connect(addr, ProtocolVersion::V311).await;
+        member.mqtt_connect(&format!("worker-{}", i), true).await;
+        member
+            .subscribe(1, "$share/workers/client/status", QoS::AtMostOnce)
+            .await;
+        members.push(member);
+    }
+    // Let gossip advertise the groups
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    // A delayed will is published outside the client's connection
+    let mut will_client = TestClient::connect(addr_a, ProtocolVersion::V5).await;
+    will_client
+        .send(&connect_with_delayed_will("will-client", true, 1))
+        .await;
+    let _ = will_client.recv().await; // CONNACK
+    drop(will_client);
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let mut delivered = 0;
+    for member in &mut members {
+        if let Ok(Some(Packet::Publish(publish))) =
+            timeout(Duration::from_millis(500), member.recv()).await
+        {
+            assert_eq!(&publish.payload[..], b"offline");
+            delivered += 1;
+        }
+    }
+    assert_eq!(delivered, 1);
+
+    handle_a.abort();
+    handle_b.abort();
+}
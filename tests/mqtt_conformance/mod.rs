@@ -22,7 +22,10 @@ use tokio::net::TcpStream;
 use tokio::time::timeout;
 
 use vibemq::broker::{Broker, BrokerConfig};
-use vibemq::config::ProxyProtocolConfig;
+use vibemq::config::{
+    MountPointConfig, ProxyProtocolConfig, QueueOverflowPolicy, RetainedPolicy,
+    RetryExhaustedPolicy, SharedDeliveryStrategy, SlowConsumerConfig, TenancyConfig,
+};
 use vibemq::protocol::QoS;
 
 static PORT_COUNTER: AtomicU16 = AtomicU16::new(21000);
@@ -42,7 +45,9 @@ pub fn test_config(port: u16) -> BrokerConfig {
         max_packet_size: 1024, // Small size for testing limits
         default_keep_alive: 60,
         max_keep_alive: 300,
+        server_keep_alive: None,
         session_expiry_check_interval: Duration::from_secs(60),
+        parked_timeout: Duration::ZERO,
         receive_maximum: 65535,
         max_qos: QoS::ExactlyOnce,
         retain_available: true,
@@ -53,15 +58,46 @@ pub fn test_config(port: u16) -> BrokerConfig {
         num_workers: 2,
         sys_topics_enabled: false,
         sys_topics_interval: Duration::from_secs(10),
+        sys_filter_topics: false,
+        sys_session_topics: false,
+        sys_client_topics: false,
+        sys_client_interval: Duration::from_secs(60),
+        presence_topics: false,
         max_inflight: 32,
         max_queued_messages: 1000,
+        queue_overflow: QueueOverflowPolicy::default(),
+        spill_threshold: 0,
         max_awaiting_rel: 100,
         retry_interval: Duration::from_secs(30),
+        retry_max_interval: Duration::ZERO,
+        max_retries: 0,
+        retry_exhausted: RetryExhaustedPolicy::Drop,
         outbound_channel_capacity: 1024,
+        write_coalesce_bytes: 64 * 1024,
+        write_coalesce_delay: Duration::ZERO,
+        fanout_threshold: 128,
+        slow_consumer: SlowConsumerConfig::default(),
         max_topic_levels: 0,
+        max_subscriptions_per_client: 0,
         proxy_protocol: ProxyProtocolConfig::default(),
         tls_proxy_protocol: ProxyProtocolConfig::default(),
         ws_proxy_protocol: ProxyProtocolConfig::default(),
+        max_retained_messages: 0,
+        max_retained_bytes: 0,
+        retained_policy: RetainedPolicy::default(),
+        tenancy: TenancyConfig::default(),
+        mount_points: MountPointConfig::default(),
+        redirect: None,
+        validate_payload_format: false,
+        content_types: Vec::new(),
+        payload_limits: Vec::new(),
+        schemas: Default::default(),
+        strict: true,
+        ordered_delivery: false,
+        topic_priorities: Vec::new(),
+        shared_subscription_strategy: SharedDeliveryStrategy::RoundRobin,
+        shared_subscription_groups: Vec::new(),
+        queue_subscriptions: false,
     }
 }
 
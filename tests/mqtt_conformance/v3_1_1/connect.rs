@@ -6,6 +6,7 @@ use std::net::SocketAddr;
 use std::time::Duration;
 
 use tokio::io::AsyncWriteExt;
+use vibemq::broker::BrokerConfig;
 
 use crate::mqtt_conformance::{next_port, start_broker, test_config, RawClient, CONNECT_V311};
 
@@ -665,6 +666,48 @@ async fn test_mqtt_3_1_3_5_valid_client_id_alphanumeric() {
     broker_handle.abort();
 }
 
+#[tokio::test]
+async fn test_mqtt_3_1_3_5_long_client_id_strict() {
+    let long_id = "abcdefghijklmnopqrstuvwxyz";
+
+    // Strict mode rejects client IDs over 23 bytes with Identifier Rejected
+    let port = next_port();
+    let broker_handle = start_broker(test_config(port)).await;
+    let mut client = RawClient::connect(SocketAddr::from(([127, 0, 0, 1], port))).await;
+    client.connect_v311(long_id, true).await;
+    assert_eq!(client.recv_connack().await, Some((false, 0x02)));
+    assert!(client.expect_disconnect(1000).await);
+    broker_handle.abort();
+
+    // Without it they are accepted
+    let port = next_port();
+    let mut config = test_config(port);
+    config.strict = false;
+    let broker_handle = start_broker(config).await;
+    let mut client = RawClient::connect(SocketAddr::from(([127, 0, 0, 1], port))).await;
+    client.connect_v311(long_id, true).await;
+    assert_eq!(client.recv_connack().await, Some((false, 0x00)));
+    broker_handle.abort();
+}
+
+#[tokio::test]
+async fn test_mqtt_3_1_3_5_uuid_client_id_lenient() {
+    // A UUID client ID (36 bytes) connects once strict mode is off
+    let port = next_port();
+    let config = BrokerConfig {
+        bind_addr: SocketAddr::from(([127, 0, 0, 1], port)),
+        strict: false,
+        ..Default::default()
+    };
+    let broker_handle = start_broker(config).await;
+    let mut client = RawClient::connect(SocketAddr::from(([127, 0, 0, 1], port))).await;
+    client
+        .connect_v311("6f1c2a8e-4b7d-4e0a-9c3f-2d5b8a7e1f60", true)
+        .await;
+    assert_eq!(client.recv_connack().await, Some((false, 0x00)));
+    broker_handle.abort();
+}
+
 // ============================================================================
 // [MQTT-3.1.3-6] Zero-Length ClientId: Server MUST Assign Unique ID
 // [MQTT-3.1.3-7] Zero-Length ClientId MUST Have CleanSession=1
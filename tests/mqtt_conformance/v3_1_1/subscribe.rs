@@ -5,6 +5,8 @@
 use std::net::SocketAddr;
 use std::time::Duration;
 
+use vibemq::broker::BrokerConfig;
+
 use crate::mqtt_conformance::{next_port, start_broker, test_config, RawClient, CONNECT_V311};
 
 // ============================================================================
@@ -37,6 +39,34 @@ async fn test_mqtt_3_8_1_1_subscribe_invalid_flags_closes() {
     broker_handle.abort();
 }
 
+#[tokio::test]
+async fn test_mqtt_3_8_1_1_subscribe_invalid_flags_closes_default_config() {
+    // The stock config checks reserved flag bits too
+    let port = next_port();
+    let config = BrokerConfig {
+        bind_addr: SocketAddr::from(([127, 0, 0, 1], port)),
+        ..Default::default()
+    };
+    let broker_handle = start_broker(config).await;
+
+    let mut client = RawClient::connect(SocketAddr::from(([127, 0, 0, 1], port))).await;
+    client.send_raw(&CONNECT_V311).await;
+    let _ = client.recv_raw(1000).await;
+
+    let invalid_subscribe = [
+        0x80, 0x09, // Wrong flags (0x80 instead of 0x82)
+        0x00, 0x01, 0x00, 0x04, b't', b'e', b's', b't', 0x00,
+    ];
+    client.send_raw(&invalid_subscribe).await;
+
+    assert!(
+        client.expect_disconnect(1000).await,
+        "Server MUST close connection on invalid SUBSCRIBE flags [MQTT-3.8.1-1]"
+    );
+
+    broker_handle.abort();
+}
+
 // ============================================================================
 // [MQTT-3.8.3-3] SUBSCRIBE Must Have At Least One Topic Filter
 // ============================================================================
@@ -187,3 +187,114 @@ async fn test_mqtt_3_2_2_15_server_max_packet_size() {
 
     broker_handle.abort();
 }
+
+#[tokio::test]
+async fn test_mqtt_3_2_2_15_packet_too_large_disconnect() {
+    let port = next_port();
+    let config = test_config(port);
+    let broker_handle = start_broker(config).await;
+
+    let mut client = RawClient::connect(SocketAddr::from(([127, 0, 0, 1], port))).await;
+    connect_v5(&mut client).await;
+
+    // A PUBLISH of exactly 1024 bytes, fixed header included, is accepted
+    let publish = build_publish_v5("test", &[b'X'; 1012], 1, false, false, Some(1), &[]);
+    assert_eq!(publish.len(), 1024);
+    client.send_raw(&publish).await;
+    let puback = client.recv_raw(1000).await.expect("Should receive PUBACK");
+    assert_eq!(puback[0], 0x40, "Should receive PUBACK");
+
+    // One byte more gets DISCONNECT with Packet Too Large
+    let publish = build_publish_v5("test", &[b'X'; 1013], 1, false, false, Some(2), &[]);
+    client.send_raw(&publish).await;
+    let disconnect = client
+        .recv_raw(1000)
+        .await
+        .expect("Should receive DISCONNECT");
+    assert_eq!(disconnect[0], 0xE0, "Should receive DISCONNECT");
+    assert_eq!(
+        disconnect[2], 0x95,
+        "Reason code should be Packet Too Large"
+    );
+    assert!(client.expect_disconnect(1000).await);
+
+    broker_handle.abort();
+}
+
+// ============================================================================
+// [MQTT-3.2.2-21] Server Keep Alive
+// ============================================================================
+
+/// Server Keep Alive property of a CONNACK, if present
+fn server_keep_alive(connack: &[u8]) -> Option<u16> {
+    assert_eq!(connack[0], 0x20, "Should receive CONNACK");
+    let props_len = connack[4] as usize;
+    let props = &connack[5..5 + props_len];
+    let mut pos = 0;
+    while pos < props.len() {
+        let id = props[pos];
+        pos += 1;
+        let len = match id {
+            // Server Keep Alive
+            0x13 => return Some(u16::from_be_bytes([props[pos], props[pos + 1]])),
+            0x21 | 0x22 => 2,
+            0x24 | 0x25 | 0x28 | 0x29 | 0x2A => 1,
+            0x27 => 4,
+            // Assigned Client Identifier
+            0x12 => 2 + u16::from_be_bytes([props[pos], props[pos + 1]]) as usize,
+            _ => panic!("unexpected CONNACK property {:#x}", id),
+        };
+        pos += len;
+    }
+    None
+}
+
+#[tokio::test]
+async fn test_mqtt_3_2_2_21_server_keep_alive() {
+    let port = next_port();
+    let config = test_config(port); // max_keep_alive = 300
+    let broker_handle = start_broker(config).await;
+
+    // Keep alive 600 exceeds the maximum: the server assigns its own
+    let mut client = RawClient::connect(SocketAddr::from(([127, 0, 0, 1], port))).await;
+    let mut connect = build_connect_v5("ska-long", true, 600, &[]);
+    client.send_raw(&connect).await;
+    let connack = client.recv_raw(1000).await.expect("Should receive CONNACK");
+    assert_eq!(
+        server_keep_alive(&connack),
+        Some(300),
+        "Server MUST report the keep alive it assigns [MQTT-3.2.2-21]"
+    );
+
+    // An accepted keep alive is not echoed
+    let mut client = RawClient::connect(SocketAddr::from(([127, 0, 0, 1], port))).await;
+    connect = build_connect_v5("ska-short", true, 60, &[]);
+    client.send_raw(&connect).await;
+    let connack = client.recv_raw(1000).await.expect("Should receive CONNACK");
+    assert_eq!(server_keep_alive(&connack), None);
+
+    broker_handle.abort();
+}
+
+#[tokio::test]
+async fn test_mqtt_3_2_2_21_server_keep_alive_configured_and_enforced() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.max_keep_alive = 30;
+    config.server_keep_alive = Some(1);
+    let broker_handle = start_broker(config).await;
+
+    let mut client = RawClient::connect(SocketAddr::from(([127, 0, 0, 1], port))).await;
+    let connect = build_connect_v5("ska-enforced", true, 600, &[]);
+    client.send_raw(&connect).await;
+    let connack = client.recv_raw(1000).await.expect("Should receive CONNACK");
+    assert_eq!(server_keep_alive(&connack), Some(1));
+
+    // Silent for longer than 1.5x the assigned keep alive
+    assert!(
+        client.expect_disconnect(3000).await,
+        "Server MUST enforce the keep alive it assigned"
+    );
+
+    broker_handle.abort();
+}
@@ -5,15 +5,234 @@
 use std::net::SocketAddr;
 use std::time::Duration;
 
+use tokio::time::timeout;
+
 use crate::mqtt_conformance::v5::{
     build_connect_v5, build_publish_v5, build_subscribe_v5, connect_v5,
 };
 use crate::mqtt_conformance::{next_port, start_broker, test_config, RawClient};
 
+/// Session Expiry Interval = 3600
+const SESSION_EXPIRY: [u8; 5] = [0x11, 0x00, 0x00, 0x0E, 0x10];
+
+/// Longest a test waits for all its messages
+const RECV_DEADLINE: Duration = Duration::from_secs(10);
+
+/// Read until at least one complete packet arrives, keeping a trailing
+/// partial packet in `buf` for the next call
+async fn recv_packets(client: &mut RawClient, buf: &mut Vec<u8>) -> Vec<Vec<u8>> {
+    let mut packets = Vec::new();
+    while packets.is_empty() {
+        match client.recv_raw(1000).await {
+            Some(data) => buf.extend_from_slice(&data),
+            None => break,
+        }
+        while buf.len() >= 2 {
+            let mut len = 0usize;
+            let mut pos = 1;
+            while pos < buf.len() {
+                let byte = buf[pos];
+                len |= ((byte & 0x7F) as usize) << (7 * (pos - 1));
+                pos += 1;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+            if buf.len() < pos + len {
+                break;
+            }
+            packets.push(buf.drain(..pos + len).collect());
+        }
+    }
+    packets
+}
+
+/// Packet Identifier and payload of a PUBLISH
+fn publish_parts(packet: &[u8]) -> (Option<u16>, &[u8]) {
+    let mut pos = 1;
+    while packet[pos] & 0x80 != 0 {
+        pos += 1;
+    }
+    pos += 1;
+    let topic_len = u16::from_be_bytes([packet[pos], packet[pos + 1]]) as usize;
+    pos += 2 + topic_len;
+    let packet_id = if (packet[0] >> 1) & 0x03 > 0 {
+        pos += 2;
+        Some(u16::from_be_bytes([packet[pos - 2], packet[pos - 1]]))
+    } else {
+        None
+    };
+    let props_len = packet[pos] as usize;
+    (packet_id, &packet[pos + 1 + props_len..])
+}
+
 // ============================================================================
 // [MQTT-4.6.0-1] Re-sent PUBLISH Packets Must Be in Original Order
 // ============================================================================
-// Note: This is tested in session reconnection scenarios
+
+#[tokio::test]
+async fn test_mqtt_4_6_0_1_resend_in_original_order() {
+    let port = next_port();
+    let broker_handle = start_broker(test_config(port)).await;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    let mut subscriber = RawClient::connect(addr).await;
+    subscriber
+        .send_raw(&build_connect_v5("resendsub", true, 60, &SESSION_EXPIRY))
+        .await;
+    let _ = subscriber.recv_raw(1000).await; // CONNACK
+    subscriber
+        .send_raw(&build_subscribe_v5(1, "ordered/resend", 1, &[], 0))
+        .await;
+    let _ = subscriber.recv_raw(1000).await; // SUBACK
+
+    let mut publisher = RawClient::connect(addr).await;
+    connect_v5(&mut publisher).await;
+    for i in 1u8..=5 {
+        let publish = build_publish_v5(
+            "ordered/resend",
+            &[b'0' + i],
+            1,
+            false,
+            false,
+            Some(i as u16),
+            &[],
+        );
+        publisher.send_raw(&publish).await;
+        let _ = publisher.recv_raw(1000).await; // PUBACK
+    }
+
+    // Receive all five without acknowledging them, then drop the connection
+    let mut buf = Vec::new();
+    let mut received = 0;
+    timeout(RECV_DEADLINE, async {
+        while received < 5 {
+            let packets = recv_packets(&mut subscriber, &mut buf).await;
+            assert!(!packets.is_empty(), "Should receive the messages");
+            received += packets.len();
+        }
+    })
+    .await
+    .expect("Messages should arrive before the deadline");
+    drop(subscriber);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // On resume they are re-sent with DUP=1 in their original order
+    let mut subscriber = RawClient::connect(addr).await;
+    subscriber
+        .send_raw(&build_connect_v5("resendsub", false, 60, &SESSION_EXPIRY))
+        .await;
+    let mut buf = Vec::new();
+    let mut resent = Vec::new();
+    timeout(RECV_DEADLINE, async {
+        while resent.len() < 5 {
+            let packets = recv_packets(&mut subscriber, &mut buf).await;
+            assert!(!packets.is_empty(), "Should receive the re-sent messages");
+            for packet in packets.iter().filter(|p| p[0] & 0xF0 == 0x30) {
+                assert_eq!(packet[0] & 0x08, 0x08, "Re-sent PUBLISH should have DUP=1");
+                resent.push(publish_parts(packet).1[0]);
+            }
+        }
+    })
+    .await
+    .expect("Messages should arrive before the deadline");
+    assert_eq!(
+        resent,
+        b"12345".to_vec(),
+        "PUBLISH packets MUST be re-sent in their original order [MQTT-4.6.0-1]"
+    );
+
+    broker_handle.abort();
+}
+
+// ============================================================================
+// [MQTT-3.3.2-7] Topic Aliases Do Not Survive a Reconnect
+// ============================================================================
+
+#[tokio::test]
+async fn test_mqtt_3_3_2_7_resend_without_previous_aliases() {
+    let port = next_port();
+    let broker_handle = start_broker(test_config(port)).await;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    // Session Expiry Interval plus Topic Alias Maximum = 10
+    let mut properties = SESSION_EXPIRY.to_vec();
+    properties.extend_from_slice(&[0x22, 0x00, 0x0A]);
+
+    let mut subscriber = RawClient::connect(addr).await;
+    subscriber
+        .send_raw(&build_connect_v5("aliassub", true, 60, &properties))
+        .await;
+    let _ = subscriber.recv_raw(1000).await; // CONNACK
+    subscriber
+        .send_raw(&build_subscribe_v5(1, "ordered/alias", 1, &[], 0))
+        .await;
+    let _ = subscriber.recv_raw(1000).await; // SUBACK
+
+    let mut publisher = RawClient::connect(addr).await;
+    connect_v5(&mut publisher).await;
+    for i in 1u8..=2 {
+        let publish = build_publish_v5(
+            "ordered/alias",
+            &[b'0' + i],
+            1,
+            false,
+            false,
+            Some(i as u16),
+            &[],
+        );
+        publisher.send_raw(&publish).await;
+        let _ = publisher.recv_raw(1000).await; // PUBACK
+    }
+
+    // The second message is sent with only the alias
+    let mut buf = Vec::new();
+    let mut received = Vec::new();
+    timeout(RECV_DEADLINE, async {
+        while received.len() < 2 {
+            let packets = recv_packets(&mut subscriber, &mut buf).await;
+            assert!(!packets.is_empty(), "Should receive the messages");
+            received.extend(packets);
+        }
+    })
+    .await
+    .expect("Messages should arrive before the deadline");
+    assert_eq!(&received[1][2..4], &[0x00, 0x00], "Should use the alias");
+    drop(subscriber);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Re-sent messages carry their topic and no alias of the old connection
+    let mut subscriber = RawClient::connect(addr).await;
+    subscriber
+        .send_raw(&build_connect_v5("aliassub", false, 60, &properties))
+        .await;
+    let mut buf = Vec::new();
+    let mut resent = Vec::new();
+    timeout(RECV_DEADLINE, async {
+        while resent.len() < 2 {
+            let packets = recv_packets(&mut subscriber, &mut buf).await;
+            assert!(!packets.is_empty(), "Should receive the re-sent messages");
+            resent.extend(packets.into_iter().filter(|p| p[0] & 0xF0 == 0x30));
+        }
+    })
+    .await
+    .expect("Messages should arrive before the deadline");
+    for packet in &resent {
+        assert_eq!(packet[0] & 0x08, 0x08, "Re-sent PUBLISH should have DUP=1");
+        assert_eq!(
+            &packet[2..17],
+            b"\x00\x0dordered/alias",
+            "Re-sent PUBLISH MUST carry its topic [MQTT-3.3.2-7]"
+        );
+        let props_len = packet[19] as usize;
+        assert!(
+            !packet[20..20 + props_len].contains(&0x23),
+            "Re-sent PUBLISH must not use a Topic Alias of the old connection"
+        );
+    }
+
+    broker_handle.abort();
+}
 
 // ============================================================================
 // [MQTT-4.6.0-2] PUBACK Must Be Sent in Order of PUBLISH Received
@@ -190,3 +409,68 @@ async fn test_mqtt_4_6_0_6_ordered_qos1() {
 
     broker_handle.abort();
 }
+
+#[tokio::test]
+async fn test_mqtt_4_6_0_6_ordered_delivery_under_flow_control() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.ordered_delivery = true;
+    let broker_handle = start_broker(config).await;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    // Subscriber accepts one unacknowledged QoS 1 message at a time
+    let mut subscriber = RawClient::connect(addr).await;
+    subscriber
+        .send_raw(&build_connect_v5("flowsub", true, 60, &[0x21, 0x00, 0x01]))
+        .await;
+    let _ = subscriber.recv_raw(1000).await; // CONNACK
+    subscriber
+        .send_raw(&build_subscribe_v5(1, "ordered/flow", 1, &[], 0))
+        .await;
+    let _ = subscriber.recv_raw(1000).await; // SUBACK
+
+    // Three QoS 1 messages, then a QoS 0 one that must not overtake them
+    let mut publisher = RawClient::connect(addr).await;
+    connect_v5(&mut publisher).await;
+    for i in 1u8..=3 {
+        let publish = build_publish_v5(
+            "ordered/flow",
+            &[b'0' + i],
+            1,
+            false,
+            false,
+            Some(i as u16),
+            &[],
+        );
+        publisher.send_raw(&publish).await;
+        let _ = publisher.recv_raw(1000).await; // PUBACK
+    }
+    let publish = build_publish_v5("ordered/flow", b"4", 0, false, false, None, &[]);
+    publisher.send_raw(&publish).await;
+
+    let mut buf = Vec::new();
+    let mut received = Vec::new();
+    timeout(RECV_DEADLINE, async {
+        while received.len() < 4 {
+            let packets = recv_packets(&mut subscriber, &mut buf).await;
+            assert!(!packets.is_empty(), "Should receive all messages");
+            for packet in packets.iter().filter(|p| p[0] & 0xF0 == 0x30) {
+                let (packet_id, payload) = publish_parts(packet);
+                received.push(payload[0]);
+                if let Some(id) = packet_id {
+                    let [hi, lo] = id.to_be_bytes();
+                    subscriber.send_raw(&[0x40, 0x02, hi, lo]).await;
+                }
+            }
+        }
+    })
+    .await
+    .expect("Messages should arrive before the deadline");
+    assert_eq!(
+        received,
+        b"1234".to_vec(),
+        "Messages MUST be delivered in publish order [MQTT-4.6.0-6]"
+    );
+
+    broker_handle.abort();
+}
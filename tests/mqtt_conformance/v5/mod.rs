@@ -15,10 +15,13 @@ pub mod ping;
 pub mod properties;
 pub mod publish;
 pub mod pubrel;
+pub mod request_response;
+pub mod server_redirection;
 pub mod shared_subscriptions;
 pub mod subscribe;
 pub mod topics;
 pub mod unsubscribe;
+pub mod will_properties;
 
 use crate::mqtt_conformance::RawClient;
 
@@ -44,7 +47,8 @@ pub fn build_connect_v5(
 ) -> Vec<u8> {
     let flags = if clean_start { 0x02 } else { 0x00 };
     let client_id_bytes = client_id.as_bytes();
-    let remaining_len = 10 + properties.len() + 2 + client_id_bytes.len();
+    let properties_len_size = if properties.len() < 128 { 1 } else { 2 };
+    let remaining_len = 10 + properties_len_size + properties.len() + 2 + client_id_bytes.len();
 
     let mut packet = vec![0x10];
     // Encode remaining length (simplified for small packets)
@@ -9,6 +9,7 @@ use crate::mqtt_conformance::v5::{
     build_connect_v5, build_publish_v5, build_subscribe_v5, connect_v5,
 };
 use crate::mqtt_conformance::{next_port, start_broker, test_config, RawClient};
+use vibemq::config::{ContentTypeRule, PayloadLimitRule};
 
 // ============================================================================
 // [MQTT-3.3.1-1] DUP Must Be 1 on Re-delivery
@@ -374,6 +375,101 @@ async fn test_mqtt_3_3_2_5_topic_alias_zero_invalid() {
     broker_handle.abort();
 }
 
+// ============================================================================
+// [MQTT-3.3.2-8] Server Topic Aliases
+// ============================================================================
+
+#[tokio::test]
+async fn test_mqtt_3_3_2_8_server_assigns_topic_alias() {
+    let port = next_port();
+    let config = test_config(port);
+    let broker_handle = start_broker(config).await;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    // Subscriber accepts up to 10 Topic Aliases
+    let mut sub = RawClient::connect(addr).await;
+    sub.send_raw(&build_connect_v5("sub", true, 60, &[0x22, 0x00, 0x0A]))
+        .await;
+    let _ = sub.recv_raw(1000).await; // CONNACK
+    sub.send_raw(&build_subscribe_v5(1, "alias/topic", 0, &[], 0))
+        .await;
+    let _ = sub.recv_raw(1000).await; // SUBACK
+
+    // Subscriber without a Topic Alias Maximum
+    let mut plain = RawClient::connect(addr).await;
+    plain
+        .send_raw(&build_connect_v5("plain", true, 60, &[]))
+        .await;
+    let _ = plain.recv_raw(1000).await; // CONNACK
+    plain
+        .send_raw(&build_subscribe_v5(1, "alias/topic", 0, &[], 0))
+        .await;
+    let _ = plain.recv_raw(1000).await; // SUBACK
+
+    let mut publisher = RawClient::connect(addr).await;
+    connect_v5(&mut publisher).await;
+
+    // First delivery carries the topic and establishes alias 1
+    publisher
+        .send_raw(&build_publish_v5(
+            "alias/topic",
+            b"1",
+            0,
+            false,
+            false,
+            None,
+            &[],
+        ))
+        .await;
+    let first = sub.recv_raw(1000).await.expect("Should receive PUBLISH");
+    assert_eq!(
+        &first[2..19],
+        &[
+            0x00, 0x0B, b'a', b'l', b'i', b'a', b's', b'/', b't', b'o', b'p', b'i', b'c', 0x03,
+            0x23, 0x00, 0x01,
+        ],
+        "First PUBLISH should carry the topic and Topic Alias 1"
+    );
+
+    // Later deliveries send an empty topic and the alias [MQTT-3.3.2-8]
+    publisher
+        .send_raw(&build_publish_v5(
+            "alias/topic",
+            b"2",
+            0,
+            false,
+            false,
+            None,
+            &[],
+        ))
+        .await;
+    let second = sub.recv_raw(1000).await.expect("Should receive PUBLISH");
+    assert_eq!(
+        second,
+        vec![0x30, 0x07, 0x00, 0x00, 0x03, 0x23, 0x00, 0x01, b'2'],
+        "Repeated PUBLISH should use the Topic Alias"
+    );
+
+    // Without a Topic Alias Maximum the server MUST NOT send aliases [MQTT-3.3.2-9]
+    let mut expected = Vec::new();
+    for payload in [b'1', b'2'] {
+        expected.extend_from_slice(&[
+            0x30, 0x0F, 0x00, 0x0B, b'a', b'l', b'i', b'a', b's', b'/', b't', b'o', b'p', b'i',
+            b'c', 0x00, payload,
+        ]);
+    }
+    let mut received = Vec::new();
+    while received.len() < expected.len() {
+        match plain.recv_raw(1000).await {
+            Some(data) => received.extend_from_slice(&data),
+            None => break,
+        }
+    }
+    assert_eq!(received, expected, "Both PUBLISHes should carry the topic");
+
+    broker_handle.abort();
+}
+
 // ============================================================================
 // [MQTT-3.3.4-1] QoS 1 Receiver Must Respond with PUBACK
 // ============================================================================
@@ -510,6 +606,46 @@ async fn test_mqtt_3_3_4_7_receive_maximum() {
     broker_handle.abort();
 }
 
+#[tokio::test]
+async fn test_mqtt_3_3_4_7_server_receive_maximum_exceeded() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.receive_maximum = 2;
+    let broker_handle = start_broker(config).await;
+
+    let mut client = RawClient::connect(SocketAddr::from(([127, 0, 0, 1], port))).await;
+    connect_v5(&mut client).await;
+
+    // Two QoS 2 publishes awaiting PUBREL use up the server's Receive Maximum
+    for packet_id in 1..=2 {
+        let publish = build_publish_v5("rm/test", b"data", 2, false, false, Some(packet_id), &[]);
+        client.send_raw(&publish).await;
+        let pubrec = client.recv_raw(1000).await.expect("Should receive PUBREC");
+        assert_eq!(pubrec[0], 0x50, "Should receive PUBREC");
+    }
+
+    // Resending one of them is not a new publish
+    let resend = build_publish_v5("rm/test", b"data", 2, false, true, Some(2), &[]);
+    client.send_raw(&resend).await;
+    let pubrec = client.recv_raw(1000).await.expect("Should receive PUBREC");
+    assert_eq!(pubrec[0], 0x50, "Should receive PUBREC for the resend");
+
+    // A third one exceeds it: DISCONNECT with Receive Maximum exceeded (0x93)
+    let publish = build_publish_v5("rm/test", b"data", 1, false, false, Some(3), &[]);
+    client.send_raw(&publish).await;
+    let disconnect = client
+        .recv_raw(1000)
+        .await
+        .expect("Should receive DISCONNECT");
+    assert_eq!(disconnect[0], 0xE0, "Should receive DISCONNECT");
+    assert_eq!(
+        disconnect[2], 0x93,
+        "Reason should be Receive Maximum exceeded"
+    );
+
+    broker_handle.abort();
+}
+
 // ============================================================================
 // [MQTT-3.3.1-3] DUP Flag Set Independently for Outgoing PUBLISH
 // ============================================================================
@@ -559,3 +695,129 @@ async fn test_mqtt_3_3_2_3_topic_matches_filter() {
 
     broker_handle.abort();
 }
+
+// ============================================================================
+// [3.3.2.3.2] Payload Format Indicator and Content Type Validation
+// ============================================================================
+
+/// PUBACK reason code of a QoS 1 publish
+async fn puback_reason(client: &mut RawClient, topic: &str, payload: &[u8], props: &[u8]) -> u8 {
+    let publish = build_publish_v5(topic, payload, 1, false, false, Some(1), props);
+    client.send_raw(&publish).await;
+    let puback = client.recv_raw(1000).await.expect("Should receive PUBACK");
+    assert_eq!(puback[0], 0x40, "Should receive PUBACK");
+    // Success is sent without a reason code
+    puback.get(4).copied().unwrap_or(0x00)
+}
+
+#[tokio::test]
+async fn test_mqtt_3_3_2_3_2_payload_format_invalid() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.validate_payload_format = true;
+    let broker_handle = start_broker(config).await;
+
+    let mut client = RawClient::connect(SocketAddr::from(([127, 0, 0, 1], port))).await;
+    connect_v5(&mut client).await;
+
+    // Payload Format Indicator = 1 (UTF-8)
+    let utf8 = [0x01, 0x01];
+    assert_eq!(
+        puback_reason(&mut client, "pfi", b"caf\xC3\xA9", &utf8).await,
+        0x00
+    );
+    assert_eq!(
+        puback_reason(&mut client, "pfi", b"\xFF\xFE", &utf8).await,
+        0x99,
+        "Invalid UTF-8 should be rejected with Payload format invalid"
+    );
+    // Unspecified bytes are not checked
+    assert_eq!(
+        puback_reason(&mut client, "pfi", b"\xFF\xFE", &[]).await,
+        0x00
+    );
+
+    broker_handle.abort();
+}
+
+#[tokio::test]
+async fn test_mqtt_3_3_2_3_9_required_content_type() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.content_types = vec![ContentTypeRule {
+        topic: "telemetry/#".to_string(),
+        content_types: vec!["application/json".to_string()],
+    }];
+    let broker_handle = start_broker(config).await;
+
+    let mut client = RawClient::connect(SocketAddr::from(([127, 0, 0, 1], port))).await;
+    connect_v5(&mut client).await;
+
+    // Content Type (0x03) = "application/json"
+    let mut json = vec![0x03, 0x00, 0x10];
+    json.extend_from_slice(b"application/json");
+    let mut text = vec![0x03, 0x00, 0x0A];
+    text.extend_from_slice(b"text/plain");
+
+    assert_eq!(
+        puback_reason(&mut client, "telemetry/1", b"{}", &json).await,
+        0x00
+    );
+    assert_eq!(
+        puback_reason(&mut client, "telemetry/1", b"{}", &text).await,
+        0x99
+    );
+    assert_eq!(
+        puback_reason(&mut client, "telemetry/1", b"{}", &[]).await,
+        0x99
+    );
+    // Topics without a rule take any content type
+    assert_eq!(
+        puback_reason(&mut client, "other", b"{}", &text).await,
+        0x00
+    );
+
+    broker_handle.abort();
+}
+
+#[tokio::test]
+async fn test_topic_payload_limits() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.payload_limits = vec![
+        PayloadLimitRule {
+            topic: "telemetry/exempt".to_string(),
+            max_payload_size: 0,
+        },
+        PayloadLimitRule {
+            topic: "telemetry/#".to_string(),
+            max_payload_size: 4,
+        },
+    ];
+    let broker_handle = start_broker(config).await;
+
+    let mut client = RawClient::connect(SocketAddr::from(([127, 0, 0, 1], port))).await;
+    connect_v5(&mut client).await;
+
+    assert_eq!(
+        puback_reason(&mut client, "telemetry/1", b"1234", &[]).await,
+        0x00
+    );
+    assert_eq!(
+        puback_reason(&mut client, "telemetry/1", b"12345", &[]).await,
+        0x83,
+        "Payload over the topic's limit should be rejected"
+    );
+    // The first matching rule applies
+    assert_eq!(
+        puback_reason(&mut client, "telemetry/exempt", b"12345", &[]).await,
+        0x00
+    );
+    // Topics without a rule only have max_packet_size
+    assert_eq!(
+        puback_reason(&mut client, "other", b"12345", &[]).await,
+        0x00
+    );
+
+    broker_handle.abort();
+}
@@ -0,0 +1,190 @@
+//! Section 4.10 - Request / Response (MQTT 5.0)
+//!
+//! Tests that the Response Topic and Correlation Data of a request reach the
+//! responder unaltered, whether the message is delivered live, from the
+//! retained store or from the offline queue of a persistent session.
+
+use std::net::SocketAddr;
+
+use crate::mqtt_conformance::v5::{build_connect_v5, build_publish_v5, build_subscribe_v5};
+use crate::mqtt_conformance::{next_port, start_broker, test_config, RawClient, DISCONNECT};
+
+/// Response Topic "resp/a" and Correlation Data "req-1"
+const REQUEST_PROPERTIES: [u8; 17] = [
+    0x08, 0x00, 0x06, b'r', b'e', b's', b'p', b'/', b'a', // Response Topic
+    0x09, 0x00, 0x05, b'r', b'e', b'q', b'-', b'1', // Correlation Data
+];
+
+/// Session Expiry Interval = 3600
+const SESSION_EXPIRY: [u8; 5] = [0x11, 0x00, 0x00, 0x0E, 0x10];
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+/// Read until `needle` shows up in the received bytes, or the server goes quiet
+async fn recv_until(client: &mut RawClient, needle: &[u8]) -> Vec<u8> {
+    let mut received = Vec::new();
+    while !contains(&received, needle) {
+        match client.recv_raw(1000).await {
+            Some(data) => received.extend_from_slice(&data),
+            None => break,
+        }
+    }
+    received
+}
+
+async fn connect(addr: SocketAddr, client_id: &str, clean: bool, props: &[u8]) -> RawClient {
+    let mut client = RawClient::connect(addr).await;
+    client
+        .send_raw(&build_connect_v5(client_id, clean, 60, props))
+        .await;
+    let connack = client.recv_raw(1000).await.expect("Should receive CONNACK");
+    assert_eq!(connack[0], 0x20);
+    client
+}
+
+async fn subscribe(client: &mut RawClient, topic: &str, qos: u8) {
+    client
+        .send_raw(&build_subscribe_v5(1, topic, qos, &[], 0))
+        .await;
+    let suback = client.recv_raw(1000).await.expect("Should receive SUBACK");
+    assert_eq!(suback[0], 0x90);
+}
+
+// ============================================================================
+// [MQTT-4.10.0] Response Topic and Correlation Data reach the responder
+// ============================================================================
+
+#[tokio::test]
+async fn test_mqtt_4_10_0_1_request_response_round_trip() {
+    let port = next_port();
+    let broker_handle = start_broker(test_config(port)).await;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    let mut responder = connect(addr, "responder", true, &[]).await;
+    subscribe(&mut responder, "req", 1).await;
+    let mut requester = connect(addr, "requester", true, &[]).await;
+    subscribe(&mut requester, "resp/a", 1).await;
+
+    requester
+        .send_raw(&build_publish_v5(
+            "req",
+            b"ping",
+            1,
+            false,
+            false,
+            Some(1),
+            &REQUEST_PROPERTIES,
+        ))
+        .await;
+
+    // The responder sees both properties unaltered
+    let request = recv_until(&mut responder, &REQUEST_PROPERTIES).await;
+    assert!(
+        contains(&request, &REQUEST_PROPERTIES),
+        "Request should carry Response Topic and Correlation Data"
+    );
+
+    // The response echoes the Correlation Data back to the Response Topic
+    responder
+        .send_raw(&build_publish_v5(
+            "resp/a",
+            b"pong",
+            1,
+            false,
+            false,
+            Some(1),
+            &REQUEST_PROPERTIES[9..],
+        ))
+        .await;
+    let response = recv_until(&mut requester, b"pong").await;
+    assert!(
+        contains(&response, b"pong"),
+        "Requester should get the response"
+    );
+    assert!(
+        contains(&response, &REQUEST_PROPERTIES[9..]),
+        "Response should carry the Correlation Data"
+    );
+
+    broker_handle.abort();
+}
+
+#[tokio::test]
+async fn test_mqtt_4_10_0_2_retained_request_keeps_properties() {
+    let port = next_port();
+    let broker_handle = start_broker(test_config(port)).await;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    let mut requester = connect(addr, "requester", true, &[]).await;
+    requester
+        .send_raw(&build_publish_v5(
+            "req",
+            b"ping",
+            0,
+            true,
+            false,
+            None,
+            &REQUEST_PROPERTIES,
+        ))
+        .await;
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // A responder subscribing later gets the retained request
+    let mut responder = RawClient::connect(addr).await;
+    responder
+        .send_raw(&build_connect_v5("responder", true, 60, &[]))
+        .await;
+    let _ = responder.recv_raw(1000).await; // CONNACK
+    responder
+        .send_raw(&build_subscribe_v5(1, "req", 0, &[], 0))
+        .await;
+    let received = recv_until(&mut responder, &REQUEST_PROPERTIES).await;
+    assert!(
+        contains(&received, &REQUEST_PROPERTIES),
+        "Retained request should keep Response Topic and Correlation Data"
+    );
+
+    broker_handle.abort();
+}
+
+#[tokio::test]
+async fn test_mqtt_4_10_0_3_queued_request_keeps_properties() {
+    let port = next_port();
+    let broker_handle = start_broker(test_config(port)).await;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    let mut responder = connect(addr, "responder", false, &SESSION_EXPIRY).await;
+    subscribe(&mut responder, "req", 1).await;
+    responder.send_raw(&DISCONNECT).await;
+    drop(responder);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut requester = connect(addr, "requester", true, &[]).await;
+    requester
+        .send_raw(&build_publish_v5(
+            "req",
+            b"ping",
+            1,
+            false,
+            false,
+            Some(1),
+            &REQUEST_PROPERTIES,
+        ))
+        .await;
+    let _ = requester.recv_raw(1000).await; // PUBACK
+
+    // The request waits in the offline queue of the responder's session
+    let mut responder = RawClient::connect(addr).await;
+    responder
+        .send_raw(&build_connect_v5("responder", false, 60, &SESSION_EXPIRY))
+        .await;
+    let received = recv_until(&mut responder, &REQUEST_PROPERTIES).await;
+    assert!(
+        contains(&received, &REQUEST_PROPERTIES),
+        "Queued request should keep Response Topic and Correlation Data"
+    );
+
+    broker_handle.abort();
+}
@@ -0,0 +1,127 @@
+//! Section 4.11 - Server Redirection (MQTT 5.0)
+//!
+//! Tests that redirected clients get Use Another Server or Server Moved with
+//! a Server Reference, in CONNACK when connecting and in DISCONNECT when
+//! already connected.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
+use tokio::time::timeout;
+use vibemq::broker::Broker;
+use vibemq::config::RedirectConfig;
+
+use crate::mqtt_conformance::v5::{connect_v5, CONNECT_V5};
+use crate::mqtt_conformance::{next_port, start_broker, test_config, RawClient, CONNECT_V311};
+
+/// Server Reference property (0x1C) naming "mqtt2:1883"
+const SERVER_REFERENCE: [u8; 13] = [
+    0x1C, 0x00, 0x0A, b'm', b'q', b't', b't', b'2', b':', b'1', b'8', b'8', b'3',
+];
+
+fn redirect(permanent: bool) -> RedirectConfig {
+    RedirectConfig {
+        server_reference: "mqtt2:1883".to_string(),
+        permanent,
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+// ============================================================================
+// [MQTT-4.11.0] CONNACK with Use Another Server / Server Moved
+// ============================================================================
+
+#[tokio::test]
+async fn test_mqtt_4_11_0_1_configured_redirect_in_connack() {
+    let port = next_port();
+    let mut config = test_config(port);
+    config.redirect = Some(redirect(false));
+    let broker_handle = start_broker(config).await;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    let mut client = RawClient::connect(addr).await;
+    let connack = connect_v5(&mut client)
+        .await
+        .expect("Should receive CONNACK");
+    assert_eq!(connack[0], 0x20, "Should receive CONNACK");
+    assert_eq!(connack[3], 0x9C, "Reason code should be Use Another Server");
+    assert!(
+        contains(&connack, &SERVER_REFERENCE),
+        "CONNACK should carry the Server Reference"
+    );
+    assert!(client.expect_disconnect(1000).await);
+
+    // v3.1.1 has no redirect; the client is refused as Server Unavailable
+    let mut client = RawClient::connect(addr).await;
+    client.send_raw(&CONNECT_V311).await;
+    let connack = client.recv_raw(1000).await.expect("Should receive CONNACK");
+    assert_eq!(connack, vec![0x20, 0x02, 0x00, 0x03]);
+
+    broker_handle.abort();
+}
+
+#[tokio::test]
+async fn test_mqtt_4_11_0_2_admin_redirect() {
+    let port = next_port();
+    let broker = Arc::new(Broker::new(test_config(port)));
+    let running = broker.clone();
+    let broker_handle = tokio::spawn(async move {
+        let _ = running.run().await;
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    // A connected client is disconnected with Server Moved
+    let mut client = RawClient::connect(addr).await;
+    client.send_raw(&CONNECT_V5).await;
+    let _ = client.recv_raw(1000).await; // CONNACK
+    assert!(broker.redirect_client("a", &redirect(true)));
+    let disconnect = client
+        .recv_raw(1000)
+        .await
+        .expect("Should receive DISCONNECT");
+    assert_eq!(disconnect[0], 0xE0, "Should receive DISCONNECT");
+    assert_eq!(disconnect[2], 0x9D, "Reason code should be Server Moved");
+    assert!(
+        contains(&disconnect, &SERVER_REFERENCE),
+        "DISCONNECT should carry the Server Reference"
+    );
+    assert!(!broker.redirect_client("unknown", &redirect(true)));
+
+    // MQTT v3.1.1 has no server DISCONNECT; the connection is just closed
+    let mut client = RawClient::connect(addr).await;
+    client.connect_v311("legacy", true).await;
+    let _ = client.recv_raw(1000).await; // CONNACK
+    assert_eq!(broker.redirect_all(&redirect(true)), 1);
+    let mut buf = [0u8; 16];
+    let closed = timeout(Duration::from_secs(1), client.stream.read(&mut buf))
+        .await
+        .expect("Should close the connection");
+    assert!(
+        matches!(closed, Ok(0) | Err(_)),
+        "Should close without DISCONNECT, got {:?}",
+        closed
+    );
+
+    // New connections are redirected until the redirect is lifted
+    broker.redirect().set(Some(redirect(false)));
+    let mut client = RawClient::connect(addr).await;
+    let connack = connect_v5(&mut client)
+        .await
+        .expect("Should receive CONNACK");
+    assert_eq!(connack[3], 0x9C, "Reason code should be Use Another Server");
+
+    broker.redirect().set(None);
+    let mut client = RawClient::connect(addr).await;
+    let connack = connect_v5(&mut client)
+        .await
+        .expect("Should receive CONNACK");
+    assert_eq!(connack[3], 0x00, "Redirect should be lifted");
+
+    broker_handle.abort();
+}
@@ -387,3 +387,133 @@ async fn test_mqtt_3_8_4_6_reason_code_per_topic() {
 
     broker_handle.abort();
 }
+
+// ============================================================================
+// [MQTT-3.3.1-9] [MQTT-3.3.1-10] [MQTT-3.3.1-11] Retain Handling
+// ============================================================================
+
+/// Packet types received until the connection goes quiet
+async fn recv_packet_types(client: &mut RawClient) -> Vec<u8> {
+    let mut data = Vec::new();
+    while let Some(chunk) = client.recv_raw(300).await {
+        data.extend_from_slice(&chunk);
+    }
+
+    let mut types = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        types.push(data[pos] & 0xF0);
+        let mut len = 0usize;
+        let mut shift = 0;
+        pos += 1;
+        while pos < data.len() {
+            let byte = data[pos];
+            pos += 1;
+            len |= ((byte & 0x7F) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        pos += len;
+    }
+    types
+}
+
+async fn publish_retained(port: u16, topic: &str) {
+    let mut publisher = RawClient::connect(SocketAddr::from(([127, 0, 0, 1], port))).await;
+    connect_v5(&mut publisher).await;
+    let publish = build_publish_v5(topic, b"retained", 0, true, false, None, &[]);
+    publisher.send_raw(&publish).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+}
+
+#[tokio::test]
+async fn test_mqtt_3_3_1_10_retain_handling_send_if_new() {
+    let port = next_port();
+    let config = test_config(port);
+    let broker_handle = start_broker(config).await;
+
+    publish_retained(port, "rh1/test").await;
+
+    let mut client = RawClient::connect(SocketAddr::from(([127, 0, 0, 1], port))).await;
+    connect_v5(&mut client).await;
+
+    // Retain Handling = 1 (bits 4-5)
+    let subscribe = build_subscribe_v5(1, "rh1/test", 0, &[], 0x10);
+    client.send_raw(&subscribe).await;
+    assert_eq!(
+        recv_packet_types(&mut client).await,
+        vec![0x90, 0x30],
+        "New subscription with RH=1 MUST receive retained [MQTT-3.3.1-10]"
+    );
+
+    // Same filter again: the subscription exists, so nothing is sent
+    let subscribe = build_subscribe_v5(2, "rh1/test", 0, &[], 0x10);
+    client.send_raw(&subscribe).await;
+    assert_eq!(
+        recv_packet_types(&mut client).await,
+        vec![0x90],
+        "Existing subscription with RH=1 MUST NOT receive retained [MQTT-3.3.1-10]"
+    );
+
+    broker_handle.abort();
+}
+
+#[tokio::test]
+async fn test_mqtt_3_3_1_11_retain_handling_do_not_send() {
+    let port = next_port();
+    let config = test_config(port);
+    let broker_handle = start_broker(config).await;
+
+    publish_retained(port, "rh2/test").await;
+
+    let mut client = RawClient::connect(SocketAddr::from(([127, 0, 0, 1], port))).await;
+    connect_v5(&mut client).await;
+
+    // Retain Handling = 2
+    let subscribe = build_subscribe_v5(1, "rh2/test", 0, &[], 0x20);
+    client.send_raw(&subscribe).await;
+    assert_eq!(
+        recv_packet_types(&mut client).await,
+        vec![0x90],
+        "RH=2 MUST NOT send retained messages [MQTT-3.3.1-11]"
+    );
+
+    broker_handle.abort();
+}
+
+#[tokio::test]
+async fn test_mqtt_3_3_1_11_retain_handling_on_session_resume() {
+    let port = next_port();
+    let config = test_config(port);
+    let broker_handle = start_broker(config).await;
+
+    publish_retained(port, "rhresume/test").await;
+
+    // Session Expiry Interval = 3600 so the subscription survives
+    let session_expiry = [0x11, 0x00, 0x00, 0x0E, 0x10];
+
+    let mut client = RawClient::connect(SocketAddr::from(([127, 0, 0, 1], port))).await;
+    let connect = build_connect_v5("rhresume", true, 60, &session_expiry);
+    client.send_raw(&connect).await;
+    let _ = client.recv_raw(1000).await;
+
+    let subscribe = build_subscribe_v5(1, "rhresume/test", 0, &[], 0x20);
+    client.send_raw(&subscribe).await;
+    let _ = recv_packet_types(&mut client).await;
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Resuming the session is not a new subscription
+    let mut client = RawClient::connect(SocketAddr::from(([127, 0, 0, 1], port))).await;
+    let connect = build_connect_v5("rhresume", false, 60, &session_expiry);
+    client.send_raw(&connect).await;
+    assert_eq!(
+        recv_packet_types(&mut client).await,
+        vec![0x20],
+        "Resumed RH=2 subscription MUST NOT receive retained [MQTT-3.3.1-11]"
+    );
+
+    broker_handle.abort();
+}
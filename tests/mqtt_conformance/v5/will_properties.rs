@@ -0,0 +1,237 @@
+//! Section 3.1.3.2 - Will Properties (MQTT 5.0)
+//!
+//! Tests that the properties of a will message are re-emitted when it is
+//! published, whether right away, after a will delay or from the retained
+//! store, and that its Message Expiry Interval counts from the disconnect.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::mqtt_conformance::v5::{build_connect_v5, build_subscribe_v5};
+use crate::mqtt_conformance::{next_port, start_broker, test_config, RawClient};
+
+/// Payload Format Indicator, Content Type, Response Topic, Correlation Data
+/// and a User Property
+const WILL_PROPERTIES: [u8; 36] = [
+    0x01, 0x01, // Payload Format Indicator = UTF-8
+    0x03, 0x00, 0x0A, b't', b'e', b'x', b't', b'/', b'p', b'l', b'a', b'i',
+    b'n', // Content Type
+    0x08, 0x00, 0x06, b'w', b'i', b'l', b'l', b'/', b'r', // Response Topic
+    0x09, 0x00, 0x02, b'c', b'1', // Correlation Data
+    0x26, 0x00, 0x01, b'k', 0x00, 0x01, b'v', // User Property
+];
+
+/// Session Expiry Interval = 60
+const SESSION_EXPIRY: [u8; 5] = [0x11, 0x00, 0x00, 0x00, 0x3C];
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+fn push_varint(packet: &mut Vec<u8>, mut len: usize) {
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+}
+
+fn push_string(packet: &mut Vec<u8>, data: &[u8]) {
+    packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    packet.extend_from_slice(data);
+}
+
+/// Build a v5 CONNECT carrying a QoS 0 will on "will/topic"
+fn build_connect_with_will(
+    client_id: &str,
+    properties: &[u8],
+    will_properties: &[u8],
+    will_retain: bool,
+) -> Vec<u8> {
+    let flags = 0x02 | 0x04 | if will_retain { 0x20 } else { 0x00 };
+    let mut body = vec![0x00, 0x04, b'M', b'Q', b'T', b'T', 0x05, flags, 0x00, 0x3C];
+    push_varint(&mut body, properties.len());
+    body.extend_from_slice(properties);
+    push_string(&mut body, client_id.as_bytes());
+    push_varint(&mut body, will_properties.len());
+    body.extend_from_slice(will_properties);
+    push_string(&mut body, b"will/topic");
+    push_string(&mut body, b"offline");
+
+    let mut packet = vec![0x10];
+    push_varint(&mut packet, body.len());
+    packet.extend_from_slice(&body);
+    packet
+}
+
+/// Read until `needle` shows up in the received bytes, or the server goes quiet
+async fn recv_until(client: &mut RawClient, needle: &[u8], timeout_ms: u64) -> Vec<u8> {
+    let mut received = Vec::new();
+    while !contains(&received, needle) {
+        match client.recv_raw(timeout_ms).await {
+            Some(data) => received.extend_from_slice(&data),
+            None => break,
+        }
+    }
+    received
+}
+
+async fn subscriber(addr: SocketAddr, client_id: &str) -> RawClient {
+    let mut client = RawClient::connect(addr).await;
+    client
+        .send_raw(&build_connect_v5(client_id, true, 60, &[]))
+        .await;
+    let connack = client.recv_raw(1000).await.expect("Should receive CONNACK");
+    assert_eq!(connack[0], 0x20);
+    client
+        .send_raw(&build_subscribe_v5(1, "will/topic", 0, &[], 0))
+        .await;
+    let suback = client.recv_raw(1000).await.expect("Should receive SUBACK");
+    assert_eq!(suback[0], 0x90);
+    client
+}
+
+async fn connect_with_will(
+    addr: SocketAddr,
+    properties: &[u8],
+    will_properties: &[u8],
+    will_retain: bool,
+) -> RawClient {
+    let mut client = RawClient::connect(addr).await;
+    client
+        .send_raw(&build_connect_with_will(
+            "will-client",
+            properties,
+            will_properties,
+            will_retain,
+        ))
+        .await;
+    let connack = client.recv_raw(1000).await.expect("Should receive CONNACK");
+    assert_eq!(connack[0], 0x20);
+    assert_eq!(connack[3], 0x00, "CONNACK should be successful");
+    client
+}
+
+// ============================================================================
+// [MQTT-3.1.3-9] Will properties are sent with the will message
+// ============================================================================
+
+#[tokio::test]
+async fn test_mqtt_3_1_3_9_will_properties_reemitted() {
+    let port = next_port();
+    let broker_handle = start_broker(test_config(port)).await;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let properties = &WILL_PROPERTIES;
+
+    let mut sub = subscriber(addr, "will-sub").await;
+    let client = connect_with_will(addr, &[], properties, false).await;
+    drop(client);
+
+    let received = recv_until(&mut sub, properties, 1000).await;
+    assert_eq!(received.first(), Some(&0x30), "Should receive the will");
+    assert!(
+        contains(&received, properties),
+        "Will should carry all its properties [MQTT-3.1.3-9]"
+    );
+
+    broker_handle.abort();
+}
+
+#[tokio::test]
+async fn test_mqtt_3_1_3_9_retained_will_keeps_properties() {
+    let port = next_port();
+    let broker_handle = start_broker(test_config(port)).await;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let properties = &WILL_PROPERTIES;
+
+    let client = connect_with_will(addr, &[], properties, true).await;
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // A later subscriber gets the retained will with its properties
+    let mut sub = RawClient::connect(addr).await;
+    sub.send_raw(&build_connect_v5("will-sub", true, 60, &[]))
+        .await;
+    let _ = sub.recv_raw(1000).await; // CONNACK
+    sub.send_raw(&build_subscribe_v5(1, "will/topic", 0, &[], 0))
+        .await;
+    let received = recv_until(&mut sub, properties, 1000).await;
+    assert!(
+        contains(&received, properties),
+        "Retained will should keep its properties"
+    );
+
+    broker_handle.abort();
+}
+
+// ============================================================================
+// [MQTT-3.1.3-10] Will Delay Interval and Message Expiry Interval
+// ============================================================================
+
+#[tokio::test]
+async fn test_mqtt_3_1_3_10_delayed_will_expiry_counts_from_disconnect() {
+    let port = next_port();
+    let broker_handle = start_broker(test_config(port)).await;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    // Will Delay Interval = 2, Message Expiry Interval = 10
+    let will_properties = [
+        0x18, 0x00, 0x00, 0x00, 0x02, // Will Delay Interval
+        0x02, 0x00, 0x00, 0x00, 0x0A, // Message Expiry Interval
+        0x03, 0x00, 0x0A, b't', b'e', b'x', b't', b'/', b'p', b'l', b'a', b'i', b'n',
+    ];
+
+    let mut sub = subscriber(addr, "will-sub").await;
+    let client = connect_with_will(addr, &SESSION_EXPIRY, &will_properties, false).await;
+    drop(client);
+
+    let received = recv_until(&mut sub, b"offline", 4000).await;
+    assert_eq!(received.first(), Some(&0x30), "Should receive the will");
+    assert!(
+        contains(&received, &will_properties[10..]),
+        "Delayed will should keep its Content Type"
+    );
+    // The delay already used up part of the expiry
+    assert!(
+        contains(&received, &[0x02, 0x00, 0x00, 0x00, 0x08])
+            || contains(&received, &[0x02, 0x00, 0x00, 0x00, 0x07]),
+        "Message Expiry Interval should count from the disconnect"
+    );
+    assert!(
+        !contains(&received, &will_properties[..5]),
+        "Will Delay Interval is not a PUBLISH property"
+    );
+
+    broker_handle.abort();
+}
+
+#[tokio::test]
+async fn test_mqtt_3_1_3_10_will_expired_during_delay_is_dropped() {
+    let port = next_port();
+    let broker_handle = start_broker(test_config(port)).await;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    // Will Delay Interval = 2 outlasts Message Expiry Interval = 1
+    let will_properties = [
+        0x18, 0x00, 0x00, 0x00, 0x02, // Will Delay Interval
+        0x02, 0x00, 0x00, 0x00, 0x01, // Message Expiry Interval
+    ];
+
+    let mut sub = subscriber(addr, "will-sub").await;
+    let client = connect_with_will(addr, &SESSION_EXPIRY, &will_properties, false).await;
+    drop(client);
+
+    let received = recv_until(&mut sub, b"offline", 4000).await;
+    assert!(
+        !contains(&received, b"offline"),
+        "A will that expired during its delay should not be published"
+    );
+
+    broker_handle.abort();
+}